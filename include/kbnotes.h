@@ -0,0 +1,70 @@
+/*
+ * C interface to kbnotes, built with
+ * `cargo rustc --release --lib --features ffi --crate-type cdylib`
+ * (or `--crate-type staticlib`).
+ *
+ * Every function returns a kbnotes_status_t. On failure, kbnotes_last_error()
+ * describes the error on the calling thread. Records are exchanged as UTF-8
+ * JSON strings; strings returned through out parameters must be released
+ * with kbnotes_string_free().
+ *
+ * A vault handle may be shared between threads. It owns its own runtime, so
+ * no async runtime is needed on the calling side.
+ */
+#ifndef KBNOTES_H
+#define KBNOTES_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum kbnotes_status {
+    KBNOTES_OK = 0,
+    KBNOTES_INVALID_ARGUMENT = 1,
+    KBNOTES_NOT_FOUND = 2,
+    KBNOTES_CONFLICT = 3,
+    KBNOTES_IO = 4,
+    KBNOTES_FORMAT = 5,
+    KBNOTES_OTHER = 6,
+} kbnotes_status_t;
+
+typedef struct KbnotesVault kbnotes_vault_t;
+typedef struct KbnotesSubscription kbnotes_subscription_t;
+
+/* Receives {"type": "created"|"updated"|"deleted", "note_id", "updated_at",
+ * "external"}. event_json is only valid during the call. */
+typedef void (*kbnotes_event_callback_t)(const char *event_json, void *user_data);
+
+kbnotes_status_t kbnotes_vault_open(const char *notes_dir, const char *backup_dir,
+                                    kbnotes_vault_t **out_vault);
+kbnotes_status_t kbnotes_vault_close(kbnotes_vault_t *vault);
+
+/* JSON array of {"id", "title", "tags", "created_at", "updated_at"}.
+ * tag may be NULL to list every note. */
+kbnotes_status_t kbnotes_list_notes(const kbnotes_vault_t *vault, const char *tag,
+                                    char **out_json);
+kbnotes_status_t kbnotes_search_notes(const kbnotes_vault_t *vault, const char *query,
+                                      char **out_json);
+
+/* reference may be a note ID, title or alias */
+kbnotes_status_t kbnotes_get_note(const kbnotes_vault_t *vault, const char *reference,
+                                  char **out_json);
+
+/* note_json: {"id"?, "title", "content"?, "tags"?}; without "id" a note is created */
+kbnotes_status_t kbnotes_save_note(const kbnotes_vault_t *vault, const char *note_json,
+                                   char **out_json);
+kbnotes_status_t kbnotes_delete_note(const kbnotes_vault_t *vault, const char *id);
+
+kbnotes_status_t kbnotes_subscribe(const kbnotes_vault_t *vault,
+                                   kbnotes_event_callback_t callback, void *user_data,
+                                   kbnotes_subscription_t **out_subscription);
+kbnotes_status_t kbnotes_unsubscribe(kbnotes_subscription_t *subscription);
+
+const char *kbnotes_last_error(void);
+void kbnotes_string_free(char *value);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* KBNOTES_H */
@@ -0,0 +1,76 @@
+//! Files (PDFs, images, ...) attached to notes.
+//!
+//! Attached files are copied into `<notes_dir>/attachments/<note_id>/` and
+//! described by an [`Attachment`] entry in the note, so they travel with the
+//! vault, its backups and its restores.
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Directory (inside `notes_dir`) holding the attached files of every note
+pub const ATTACHMENTS_DIR: &str = "attachments";
+
+/// A file attached to a note
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    /// File name, unique among the note's attachments
+    pub filename: String,
+    /// Location of the stored copy, relative to the notes directory
+    pub path: String,
+    /// MIME type guessed from the file extension
+    pub mime_type: String,
+    /// Size of the file in bytes
+    pub size: u64,
+    /// When the file was attached
+    pub added_at: DateTime<Utc>,
+}
+
+/// Guesses the MIME type of a file from its extension
+///
+/// # Returns
+///
+/// The MIME type, or `application/octet-stream` for unknown extensions
+pub fn mime_type_for_path(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "bmp" => "image/bmp",
+        "txt" => "text/plain",
+        "md" | "markdown" => "text/markdown",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "zip" => "application/zip",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Picks a file name not yet used by any of `existing`, numbering the stem
+/// of `filename` (`scan.pdf`, `scan-2.pdf`, ...) when it is taken
+pub fn unique_attachment_name(existing: &[Attachment], filename: &str) -> String {
+    let taken = |name: &str| existing.iter().any(|a| a.filename == name);
+    if !taken(filename) {
+        return filename.to_string();
+    }
+
+    let (stem, extension) = match filename.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{}", ext)),
+        _ => (filename, String::new()),
+    };
+    (2..)
+        .map(|n| format!("{}-{}{}", stem, n, extension))
+        .find(|name| !taken(name))
+        .expect("an unused attachment name")
+}
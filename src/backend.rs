@@ -0,0 +1,151 @@
+//! Persistence of notes behind [`NoteStorage`](crate::NoteStorage).
+//!
+//! A [`StorageBackend`] stores whole notes: the cache, indexes, history,
+//! trash and attachments stay with `NoteStorage`. The `backend` setting picks
+//! [`FileBackend`] (one file per note, the default) or [`SqliteBackend`] (a
+//! single database at `notes_dir/notes.db`); `kbnotes migrate-backend`
+//! copies a vault from one to the other.
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Config, FileBackend, KbError, Note, NoteFilter, Result, SqliteBackend};
+
+/// File name of the SQLite database inside `notes_dir`
+pub const SQLITE_DB_FILE: &str = "notes.db";
+
+/// The `backend` configuration setting
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    /// One file per note below `notes_dir` (the default, so existing vaults
+    /// keep their files)
+    #[default]
+    Files,
+    /// A SQLite database at `notes_dir/notes.db`
+    Sqlite,
+}
+
+impl std::fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackendKind::Files => write!(f, "files"),
+            BackendKind::Sqlite => write!(f, "sqlite"),
+        }
+    }
+}
+
+impl std::str::FromStr for BackendKind {
+    type Err = KbError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "files" => Ok(BackendKind::Files),
+            "sqlite" => Ok(BackendKind::Sqlite),
+            other => Err(KbError::InvalidArgument {
+                message: format!("Unknown storage backend '{}': use files or sqlite", other),
+            }),
+        }
+    }
+}
+
+/// Everything a backend holds, as read by [`StorageBackend::load_all`]
+#[derive(Debug, Default)]
+pub struct BackendLoad {
+    /// The notes that could be read, one per ID
+    pub notes: Vec<Note>,
+    /// Where each unreadable note is stored and why it could not be read
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Stores and retrieves whole notes
+pub trait StorageBackend: Send + Sync {
+    /// Which backend this is
+    fn kind(&self) -> BackendKind;
+
+    /// Reads every stored note
+    fn load_all(&self) -> Result<BackendLoad>;
+
+    /// Reads one note, or `None` when it is not stored
+    fn load(&self, note_id: &str) -> Result<Option<Note>>;
+
+    /// Stores a note, replacing the stored version if there is one
+    fn save(&self, note: &Note) -> Result<()>;
+
+    /// Stores several notes, as [`StorageBackend::save`] would one by one
+    ///
+    /// # Returns
+    ///
+    /// The outcome of each note, in the order given
+    fn save_batch(&self, notes: &[&Note]) -> Vec<Result<()>> {
+        notes.iter().map(|note| self.save(note)).collect()
+    }
+
+    /// Removes a note
+    ///
+    /// # Returns
+    ///
+    /// Whether the note was stored
+    fn delete(&self, note_id: &str) -> Result<bool>;
+
+    /// Returns the IDs of every stored note
+    fn list_ids(&self) -> Result<Vec<String>>;
+
+    /// Finds the notes matching a tag and date filter whose title or content
+    /// contains `text` as written
+    fn query(&self, filter: &NoteFilter, text: Option<&str>) -> Result<Vec<Note>> {
+        Ok(self
+            .load_all()?
+            .notes
+            .into_iter()
+            .filter(|note| filter.matches(note))
+            .filter(|note| {
+                text.is_none_or(|text| note.title.contains(text) || note.content.contains(text))
+            })
+            .collect())
+    }
+
+    /// Whether [`StorageBackend::query`] is answered from an index rather
+    /// than by reading every note, making it worth calling instead of
+    /// scanning the notes cache
+    fn indexed_queries(&self) -> bool {
+        false
+    }
+
+    /// Writes a consistent copy of the backend's database to `target`
+    ///
+    /// # Returns
+    ///
+    /// Whether anything was written; backends without a database write nothing
+    fn snapshot(&self, _target: &Path) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Closes open handles, e.g. before the notes directory is moved; they
+    /// are reopened on next use
+    fn release(&self) {}
+}
+
+/// Opens the backend of the given kind for `config`
+///
+/// # Arguments
+///
+/// * `kind` - The backend to open
+/// * `config` - Where the vault lives and how durable writes must be
+/// * `files` - The file layout of the vault, reused by the file backend
+pub fn open_backend(
+    kind: BackendKind,
+    config: &Config,
+    files: &FileBackend,
+) -> Arc<dyn StorageBackend> {
+    match kind {
+        BackendKind::Files => Arc::new(files.clone()),
+        BackendKind::Sqlite => Arc::new(SqliteBackend::new(
+            config.notes_dir.join(SQLITE_DB_FILE),
+            config.fsync,
+        )),
+    }
+}
@@ -1,15 +1,20 @@
 // src/backup_scheduler.rs - Backup scheduler module
-use std::{path::PathBuf, sync::{Arc, Weak}};
+use std::{fs, io::Write, path::{Path, PathBuf}, sync::{Arc, RwLock, Weak}};
 
-use chrono::Utc;
-use log::{debug, error, info};
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
 use tokio::sync::{mpsc, Mutex};
 use tokio::task::JoinHandle;
 use tokio::time::{self, Duration};
 
-use crate::{Config, KbError, NoteStorage, Result};
+use crate::{
+    push_backup, BackupChainState, BackupPushResult, Config, KbError, NoteStorage, Result,
+    BACKUP_STATE_FILE,
+};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct BackupSchedulerStatus {
     /// Whether the scheduler is running
     pub is_running: bool,
@@ -17,6 +22,70 @@ pub struct BackupSchedulerStatus {
     pub last_backup_time: Option<chrono::DateTime<Utc>>,
     /// The path to the last backup file
     pub last_backup_path: Option<PathBuf>,
+    /// The time the last backup was attempted, successfully or not
+    pub last_run_time: Option<chrono::DateTime<Utc>>,
+    /// Why the last attempted backup failed, cleared by the next successful one
+    pub last_error: Option<String>,
+    /// The time the next scheduled backup is due, while the scheduler runs
+    pub next_backup_time: Option<chrono::DateTime<Utc>>,
+    /// The time a backup was last pushed to the configured backup targets
+    pub last_push_time: Option<chrono::DateTime<Utc>>,
+    /// The targets the last push failed on, with their errors
+    pub last_push_errors: Vec<String>,
+    /// The time of the last catch-up backup, taken for a periodic backup
+    /// missed while the machine slept or the scheduler wasn't running
+    pub last_catch_up_time: Option<chrono::DateTime<Utc>>,
+    /// When the backup the last catch-up made up for was due
+    pub missed_backup_time: Option<chrono::DateTime<Utc>>,
+}
+
+/// File in the backup directory recording the backup schedule across runs
+pub const SCHEDULER_STATE_FILE: &str = "kbnotes_scheduler_state.json";
+
+/// How often the scheduler compares the wall clock with the next due time,
+/// which notices backups missed while the machine was suspended
+const WAKE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How late a periodic backup must be to count as missed and be caught up
+const CATCH_UP_SLACK: chrono::Duration = chrono::Duration::minutes(2);
+
+/// The backup schedule persisted in [`SCHEDULER_STATE_FILE`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BackupSchedulerState {
+    /// When the last successful backup, scheduled or manual, was taken
+    pub last_backup_at: Option<chrono::DateTime<Utc>>,
+    /// When the next periodic backup is due
+    pub next_backup_at: Option<chrono::DateTime<Utc>>,
+}
+
+impl BackupSchedulerStatus {
+    /// Records the outcome of a backup run
+    pub fn record_run(&mut self, outcome: &Result<PathBuf>) {
+        let now = Utc::now();
+        self.last_run_time = Some(now);
+        match outcome {
+            Ok(path) => {
+                self.last_backup_time = Some(now);
+                self.last_backup_path = Some(path.clone());
+                self.last_error = None;
+            }
+            Err(e) => self.last_error = Some(e.to_string()),
+        }
+    }
+
+    /// Records the outcome of pushing a backup to the backup targets
+    pub fn record_push(&mut self, results: &[BackupPushResult]) {
+        self.last_push_time = Some(Utc::now());
+        self.last_push_errors = results
+            .iter()
+            .filter_map(|result| {
+                result
+                    .error
+                    .as_ref()
+                    .map(|error| format!("{}: {}", result.target, error))
+            })
+            .collect();
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -37,8 +106,9 @@ pub struct BackupScheduler {
     /// Handle to the scheduler task
     scheduler_task: Option<JoinHandle<()>>,
 
-    /// Current status of the scheduler
-    status: BackupSchedulerStatus,
+    /// Current status of the scheduler, shared with the scheduler task so
+    /// that it reflects the backups the task runs
+    status: Arc<RwLock<BackupSchedulerStatus>>,
 
     /// Weak reference to the storage
     storage: Option<Weak<Mutex<NoteStorage>>>,
@@ -55,11 +125,7 @@ impl BackupScheduler {
             config,
             command_tx,
             scheduler_task: None,
-            status: BackupSchedulerStatus {
-                is_running: false,
-                last_backup_time: None,
-                last_backup_path: None,
-            },
+            status: Arc::new(RwLock::new(BackupSchedulerStatus::default())),
             storage: None,
         }
     }
@@ -71,11 +137,14 @@ impl BackupScheduler {
     }
 
     /// Star the backup scheduler
+    ///
+    /// The scheduler always runs to take manual backups; it only takes
+    /// periodic ones when `auto_backup` is on. Those follow `backup_schedule`
+    /// when it is set, and run every `backup_frequency` hours otherwise.
     pub async fn start(&mut self) -> Result<()> {
         info!("Starting backup scheduler...");
-        if !self.config.auto_backup {
-            return Ok(()); // No need to start if auto backup is disabled
-        }
+        let periodic = self.config.auto_backup;
+        let schedule = self.config.cron_schedule()?;
 
         let storage = match &self.storage {
             Some(weak) => match weak.upgrade() {
@@ -99,40 +168,107 @@ impl BackupScheduler {
         self.command_tx = command_tx;
 
         let backup_frequency_secs = self.config.backup_frequency as u64 * 3600;
+        let backup_frequency = chrono::Duration::seconds(backup_frequency_secs as i64);
         let storage_clone = Arc::clone(&storage);
+        let status = Arc::clone(&self.status);
+        let config = self.config.clone();
+        let cron = schedule.is_some();
+        if periodic {
+            if let Some(schedule) = &schedule {
+                info!("Backups follow the schedule '{}' (UTC)", schedule);
+            }
+        }
+        // When the periodic backup following one at `after` is due
+        let due_after = move |after: DateTime<Utc>| match &schedule {
+            Some(schedule) => schedule.next_after(after),
+            None => Some(after + backup_frequency),
+        };
+
+        // A backup that fell due while the scheduler wasn't running is
+        // taken right away, otherwise the persisted schedule carries on
+        let now = Utc::now();
+        let state = read_scheduler_state(&config.backup_dir);
+        let missed = state
+            .last_backup_at
+            .and_then(&due_after)
+            .or(state.next_backup_at)
+            .filter(|due| periodic && *due <= now);
+        let mut due = match state.next_backup_at {
+            Some(next) if !cron && missed.is_none() && next > now => {
+                Some(next.min(now + backup_frequency))
+            }
+            _ => due_after(now),
+        };
+        let first_tick = time::Instant::now()
+            + due
+                .and_then(|due| (due - now).to_std().ok())
+                .unwrap_or_default();
+        self.update_status(|status| {
+            status.is_running = true;
+            status.next_backup_time = missed.or(due).filter(|_| periodic);
+        });
 
         let task = tokio::spawn(async move {
-            let mut interval = time::interval(Duration::from_secs(backup_frequency_secs));
-            interval.tick().await; // Initial tick
+            let mut interval =
+                time::interval_at(first_tick, Duration::from_secs(backup_frequency_secs));
+            let mut wake_check = time::interval(WAKE_CHECK_INTERVAL);
+
+            if let Some(missed) = missed {
+                run_scheduled_backup(&storage_clone, &config, &status, Some(missed)).await;
+                interval.reset();
+                due = due_after(Utc::now());
+                set_next_backup_time(&config, &status, due);
+            }
 
             loop {
-                tokio::select! {
-                    _ = interval.tick() => {
-                        let storage = Arc::clone(&storage_clone);
-                        match storage.lock().await.create_full_backup() {
-                            Ok(path) => info!("Scheduled backup completed at {}", path.display()),
-                            Err(e) => error!("Scheduled backup failed: {}", e),
-                        };
+                let until_due = due.map(|due| (due - Utc::now()).to_std().unwrap_or_default());
+
+                let fired = tokio::select! {
+                    _ = interval.tick(), if periodic && !cron => true,
+                    _ = time::sleep(until_due.unwrap_or_default()),
+                        if periodic && cron && until_due.is_some() => true,
+                    // Timers stand still while the machine sleeps, so a
+                    // backup that fell due meanwhile shows on the wall clock
+                    _ = wake_check.tick(), if periodic => {
+                        due.is_some_and(|due| Utc::now() - due > CATCH_UP_SLACK)
                     }
                     Some(cmd) = command_rx.recv() => match cmd {
                         BackupCommand::CreateBackupNow => {
                             let storage = Arc::clone(&storage_clone);
-                            match storage.lock().await.create_full_backup() {
+                            let outcome = storage.lock().await.create_full_backup();
+                            match &outcome {
                                 Ok(path) => info!("Manual backup completed at {}", path.display()),
                                 Err(e) => error!("Manual backup failed: {}", e),
                             };
+                            if let Ok(mut status) = status.write() {
+                                status.record_run(&outcome);
+                                save_scheduler_state(&config, &status);
+                            }
+                            if let Ok(path) = outcome {
+                                push_to_targets(&config, path, &status).await;
+                            }
+                            false
                         },
                         BackupCommand::Stop => {
                             info!("Backup scheduler stopping...");
                             break;
                         }
                     }
+                };
+                if !fired {
+                    continue;
                 }
+
+                let missed = due.filter(|due| Utc::now() - *due > CATCH_UP_SLACK);
+                run_scheduled_backup(&storage_clone, &config, &status, missed).await;
+                let now = Utc::now();
+                interval.reset();
+                due = due_after(due.map_or(now, |due| due.max(now)));
+                set_next_backup_time(&config, &status, due);
             }
         });
 
         self.scheduler_task = Some(task);
-        self.status.is_running = true;
 
         Ok(())
     }
@@ -152,7 +288,10 @@ impl BackupScheduler {
                 return Err(KbError::BackupFailed { message: error_mgs });
             }
 
-            self.status.is_running = false;
+            self.update_status(|status| {
+                status.is_running = false;
+                status.next_backup_time = None;
+            });
             info!("Backup scheduler stopped");
         } else {
             debug!("Backup scheduler is not running");
@@ -163,7 +302,7 @@ impl BackupScheduler {
 
     /// Create a backup immediately, regardless of the schedule
     pub async fn create_backup_now(&self) -> Result<()> {
-        if !self.status.is_running {
+        if !self.get_status().is_running {
             return Err(KbError::BackupFailed {
                 message: "Backup scheduler is not running".to_string(),
             });
@@ -181,12 +320,166 @@ impl BackupScheduler {
 
     /// Get the current status of the backup scheduler
     pub fn get_status(&self) -> BackupSchedulerStatus {
-        self.status.clone()
+        match self.status.read() {
+            Ok(status) => status.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
     }
 
     /// Update the scheduler's last backup information
     pub fn update_last_backup(&mut self, path: PathBuf) {
-        self.status.last_backup_time = Some(Utc::now());
-        self.status.last_backup_path = Some(path);
+        self.record_run(&Ok(path));
+    }
+
+    /// Pushes a backup to the configured backup targets and records the
+    /// outcome; nothing happens when no target is configured
+    pub async fn push_backup(&self, path: PathBuf) -> Vec<BackupPushResult> {
+        push_to_targets(&self.config, path, &self.status).await
+    }
+
+    /// Records the outcome of a backup taken outside the scheduler task
+    pub fn record_run(&self, outcome: &Result<PathBuf>) {
+        self.update_status(|status| {
+            status.record_run(outcome);
+            if outcome.is_ok() {
+                save_scheduler_state(&self.config, status);
+            }
+        });
+    }
+
+    /// Applies a change to the shared status
+    fn update_status(&self, change: impl FnOnce(&mut BackupSchedulerStatus)) {
+        match self.status.write() {
+            Ok(mut status) => change(&mut status),
+            Err(poisoned) => change(&mut poisoned.into_inner()),
+        }
+    }
+}
+
+/// Takes a scheduled backup after purging expired trash, records it, and
+/// pushes it to the backup targets
+///
+/// `missed` is the time a backup made up for was due, for catch-up backups.
+async fn run_scheduled_backup(
+    storage: &Mutex<NoteStorage>,
+    config: &Config,
+    status: &RwLock<BackupSchedulerStatus>,
+    missed: Option<DateTime<Utc>>,
+) {
+    if let Some(missed) = missed {
+        info!(
+            "Catching up on the backup that was due at {}",
+            missed.to_rfc3339()
+        );
+    }
+    let outcome = {
+        let storage = storage.lock().await;
+        match storage.purge_expired_trash() {
+            Ok(purged) if !purged.is_empty() => {
+                info!("Purged {} note(s) from the trash", purged.len())
+            }
+            Ok(_) => {}
+            Err(e) => error!("Purging the trash failed: {}", e),
+        };
+        storage.create_scheduled_backup()
+    };
+    match &outcome {
+        Ok(path) => info!("Scheduled backup completed at {}", path.display()),
+        Err(e) => error!("Scheduled backup failed: {}", e),
+    };
+    if let Ok(mut status) = status.write() {
+        status.record_run(&outcome);
+        if missed.is_some() {
+            status.last_catch_up_time = status.last_run_time;
+            status.missed_backup_time = missed;
+        }
+        save_scheduler_state(config, &status);
+    }
+    // Uploads run without holding the storage lock
+    if let Ok(path) = outcome {
+        push_to_targets(config, path, status).await;
+    }
+}
+
+/// Records when the next periodic backup is due, in the status and the
+/// scheduler state file
+fn set_next_backup_time(
+    config: &Config,
+    status: &RwLock<BackupSchedulerStatus>,
+    next_backup_time: Option<DateTime<Utc>>,
+) {
+    if let Ok(mut status) = status.write() {
+        status.next_backup_time = next_backup_time;
+        save_scheduler_state(config, &status);
+    }
+}
+
+/// Reads the scheduler state file, falling back to the time of the last
+/// backup in the incremental backup chain state
+fn read_scheduler_state(backup_dir: &Path) -> BackupSchedulerState {
+    let read = |name: &str| fs::read_to_string(backup_dir.join(name)).ok();
+    let state = read(SCHEDULER_STATE_FILE).and_then(|text| {
+        serde_json::from_str(&text)
+            .map_err(|e| warn!("Ignoring unreadable {}: {}", SCHEDULER_STATE_FILE, e))
+            .ok()
+    });
+    state.unwrap_or_else(|| BackupSchedulerState {
+        last_backup_at: read(BACKUP_STATE_FILE)
+            .and_then(|text| serde_json::from_str::<BackupChainState>(&text).ok())
+            .map(|chain| chain.last_backup_at),
+        next_backup_at: None,
+    })
+}
+
+/// Persists the last successful backup and the next due time from the
+/// status, logging rather than failing when the file can't be written
+fn save_scheduler_state(config: &Config, status: &BackupSchedulerStatus) {
+    let mut state = read_scheduler_state(&config.backup_dir);
+    state.last_backup_at = status.last_backup_time.or(state.last_backup_at);
+    state.next_backup_at = status.next_backup_time;
+
+    let path = config.backup_dir.join(SCHEDULER_STATE_FILE);
+    let written = (|| -> Result<()> {
+        let mut temp_file = NamedTempFile::new_in(&config.backup_dir)?;
+        temp_file.write_all(serde_json::to_string_pretty(&state)?.as_bytes())?;
+        temp_file.persist(&path).map_err(|e| KbError::Io(e.error))?;
+        Ok(())
+    })();
+    if let Err(e) = written {
+        warn!(
+            "Failed to record the backup schedule in {}: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+/// Uploads a backup to every configured target on a blocking thread and
+/// records the outcome in the shared status
+async fn push_to_targets(
+    config: &Config,
+    path: PathBuf,
+    status: &RwLock<BackupSchedulerStatus>,
+) -> Vec<BackupPushResult> {
+    if config.backup_targets.is_empty() {
+        return Vec::new();
+    }
+    let targets = config.backup_targets.clone();
+    let max_backups = config.max_backups;
+    let results = match tokio::task::spawn_blocking(move || {
+        push_backup(&targets, &path, max_backups)
+    })
+    .await
+    {
+        Ok(results) => results,
+        Err(e) => {
+            error!("Pushing the backup to its targets failed: {}", e);
+            return Vec::new();
+        }
+    };
+    match status.write() {
+        Ok(mut status) => status.record_push(&results),
+        Err(poisoned) => poisoned.into_inner().record_push(&results),
     }
+    results
 }
@@ -0,0 +1,621 @@
+//! Remote targets backup archives are shipped to after they are written.
+//!
+//! Targets are declared in the `backup_targets` section of the configuration.
+//! The backup scheduler uploads every archive it writes to each target and
+//! then prunes the target down to its retention, recording failures in the
+//! [`BackupSchedulerStatus`](crate::BackupSchedulerStatus); `kbnotes backup
+//! push` does the same on demand. A failing target never fails the backup
+//! itself, which is already safe on local disk.
+//!
+//! Two kinds of target exist: an S3-compatible bucket, signed with AWS
+//! Signature Version 4, and a command run for each archive, so that tools
+//! such as rclone can ship archives anywhere they support.
+use std::{fs::File, path::Path, process::Command, time::Duration};
+
+use chrono::Utc;
+use log::{info, warn};
+use ring::{digest, hmac};
+use serde::{Deserialize, Serialize};
+
+use crate::{parse_backup_archive_name, KbError, Result};
+
+/// Placeholder replaced by the archive's path in command target arguments
+pub const BACKUP_PATH_PLACEHOLDER: &str = "{path}";
+
+/// Placeholder replaced by the archive's file name in command target arguments
+pub const BACKUP_NAME_PLACEHOLDER: &str = "{name}";
+
+/// A remote location backup archives are uploaded to
+pub trait BackupTarget: Send + Sync {
+    /// Describes the target in logs and statuses, e.g. `s3://bucket/prefix`
+    fn name(&self) -> String;
+
+    /// Uploads a backup archive, stored under its file name
+    fn upload(&self, path: &Path) -> Result<()>;
+
+    /// Lists the file names of the backup archives stored on the target
+    fn list(&self) -> Result<Vec<String>>;
+
+    /// Removes the oldest full backups beyond `max`, with the incremental
+    /// backups that build on them (0 keeps all)
+    ///
+    /// # Returns
+    /// The file names of the removed archives
+    fn prune(&self, max: usize) -> Result<Vec<String>>;
+}
+
+/// A backup target as it appears in the configuration.
+///
+/// Example (JSON config):
+///
+/// ```json
+/// "backup_targets": [
+///   {
+///     "type": "s3",
+///     "endpoint": "https://s3.eu-west-1.amazonaws.com",
+///     "bucket": "my-notes",
+///     "region": "eu-west-1",
+///     "prefix": "laptop/"
+///   },
+///   {
+///     "type": "command",
+///     "upload": "rclone copyto {path} remote:kbnotes/{name}",
+///     "list": "rclone lsf remote:kbnotes",
+///     "remove": "rclone deletefile remote:kbnotes/{name}"
+///   }
+/// ]
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BackupTargetConfig {
+    /// An S3-compatible bucket
+    S3(S3TargetConfig),
+    /// Commands run for each archive
+    Command(CommandTargetConfig),
+}
+
+impl BackupTargetConfig {
+    /// Full backups kept on the target, falling back to `max_backups`
+    pub fn max_backups(&self, default: u32) -> u32 {
+        match self {
+            BackupTargetConfig::S3(config) => config.max_backups,
+            BackupTargetConfig::Command(config) => config.max_backups,
+        }
+        .unwrap_or(default)
+    }
+
+    /// Creates the target the configuration describes
+    pub fn build(&self) -> Box<dyn BackupTarget> {
+        match self {
+            BackupTargetConfig::S3(config) => Box::new(S3Target::new(config.clone())),
+            BackupTargetConfig::Command(config) => Box::new(CommandTarget::new(config.clone())),
+        }
+    }
+}
+
+/// Settings of an S3-compatible bucket (AWS S3, MinIO, Backblaze B2, ...)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct S3TargetConfig {
+    /// Base URL of the service, e.g. `https://s3.eu-west-1.amazonaws.com`;
+    /// buckets are addressed path-style below it
+    pub endpoint: String,
+
+    /// Bucket the archives are uploaded to
+    pub bucket: String,
+
+    /// Region the requests are signed for
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+
+    /// Prefix of the archives' keys, e.g. `laptop/`
+    #[serde(default)]
+    pub prefix: String,
+
+    /// Access key ID (`AWS_ACCESS_KEY_ID` when unset)
+    #[serde(default)]
+    pub access_key_id: Option<String>,
+
+    /// Secret access key (`AWS_SECRET_ACCESS_KEY` when unset)
+    #[serde(default)]
+    pub secret_access_key: Option<String>,
+
+    /// Timeout of a single request, in seconds
+    #[serde(default = "default_s3_timeout_secs")]
+    pub timeout_secs: u64,
+
+    /// Full backups kept in the bucket (`max_backups` when unset, 0 keeps all)
+    #[serde(default)]
+    pub max_backups: Option<u32>,
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_s3_timeout_secs() -> u64 {
+    300
+}
+
+/// Settings of a command target
+///
+/// Commands are split into arguments like a shell would, without running a
+/// shell: `{path}` and `{name}` in an argument are replaced by the archive's
+/// path and file name. Use `sh -c '...'` for pipes or redirections.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CommandTargetConfig {
+    /// Uploads the archive at `{path}`
+    pub upload: String,
+
+    /// Prints the file names of the uploaded archives, one per line; the
+    /// target can't be listed or pruned without it
+    #[serde(default)]
+    pub list: Option<String>,
+
+    /// Removes the archive named `{name}`; the target isn't pruned without it
+    #[serde(default)]
+    pub remove: Option<String>,
+
+    /// Full backups kept by pruning (`max_backups` when unset, 0 keeps all)
+    #[serde(default)]
+    pub max_backups: Option<u32>,
+}
+
+/// Outcome of shipping a backup archive to one target
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupPushResult {
+    /// The target, as described by [`BackupTarget::name`]
+    pub target: String,
+    /// Why the upload or pruning failed
+    pub error: Option<String>,
+    /// Archives removed from the target by pruning
+    pub pruned: Vec<String>,
+}
+
+/// Uploads a backup archive to every configured target, then prunes each
+/// target the upload succeeded on
+///
+/// # Arguments
+/// * `targets` - The configured targets
+/// * `path` - The archive to upload
+/// * `max_backups` - Retention of targets that don't set their own
+pub fn push_backup(
+    targets: &[BackupTargetConfig],
+    path: &Path,
+    max_backups: u32,
+) -> Vec<BackupPushResult> {
+    targets
+        .iter()
+        .map(|config| {
+            let target = config.build();
+            let name = target.name();
+            let outcome = target.upload(path).and_then(|()| {
+                info!("Uploaded {} to {}", path.display(), name);
+                target.prune(config.max_backups(max_backups) as usize)
+            });
+            match outcome {
+                Ok(pruned) => {
+                    if !pruned.is_empty() {
+                        info!("Pruned {} old backup(s) from {}", pruned.len(), name);
+                    }
+                    BackupPushResult {
+                        target: name,
+                        error: None,
+                        pruned,
+                    }
+                }
+                Err(e) => {
+                    warn!("Pushing {} to {} failed: {}", path.display(), name, e);
+                    BackupPushResult {
+                        target: name,
+                        error: Some(e.to_string()),
+                        pruned: Vec::new(),
+                    }
+                }
+            }
+        })
+        .collect()
+}
+
+/// Picks the archives pruning removes: the full backups beyond the newest
+/// `max`, and the incremental backups older than the oldest full backup kept
+///
+/// Names that aren't backup archives are never picked.
+pub fn archives_to_prune(names: &[String], max: usize) -> Vec<String> {
+    if max == 0 {
+        return Vec::new();
+    }
+    let mut archives: Vec<(&str, bool, &String)> = names
+        .iter()
+        .filter_map(|name| {
+            parse_backup_archive_name(name).map(|(stamp, incremental)| (stamp, incremental, name))
+        })
+        .collect();
+    // Newest first; the timestamps sort like the times they encode
+    archives.sort_by(|a, b| b.0.cmp(a.0));
+
+    let Some(&(oldest_kept, _, _)) = archives
+        .iter()
+        .filter(|(_, incremental, _)| !incremental)
+        .take(max)
+        .last()
+    else {
+        return Vec::new();
+    };
+    archives
+        .iter()
+        .filter(|(stamp, _, _)| *stamp < oldest_kept)
+        .map(|(_, _, name)| (*name).clone())
+        .collect()
+}
+
+/// File name of an archive, which is its name on the target
+fn archive_name(path: &Path) -> Result<String> {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| KbError::BackupFailed {
+            message: format!("{} is not a backup archive", path.display()),
+        })
+}
+
+/// Uploads archives to an S3-compatible bucket
+pub struct S3Target {
+    config: S3TargetConfig,
+    agent: ureq::Agent,
+}
+
+impl S3Target {
+    /// Creates a target for the bucket the configuration names
+    pub fn new(config: S3TargetConfig) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build();
+        Self { config, agent }
+    }
+
+    /// Credentials from the configuration, or from the environment
+    fn credentials(&self) -> Result<(String, String)> {
+        let read = |configured: &Option<String>, variable: &str| {
+            configured
+                .clone()
+                .or_else(|| std::env::var(variable).ok())
+                .ok_or_else(|| KbError::ConfigError {
+                    message: format!(
+                        "No S3 credentials for {}: set them in the configuration or in {}",
+                        self.name(),
+                        variable
+                    ),
+                })
+        };
+        Ok((
+            read(&self.config.access_key_id, "AWS_ACCESS_KEY_ID")?,
+            read(&self.config.secret_access_key, "AWS_SECRET_ACCESS_KEY")?,
+        ))
+    }
+
+    /// Sends a signed request for `key` in the bucket (the bucket itself when
+    /// `key` is empty)
+    fn request(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        body: Option<(File, u64)>,
+    ) -> Result<ureq::Response> {
+        let (access_key_id, secret_access_key) = self.credentials()?;
+        let endpoint = self.config.endpoint.trim_end_matches('/');
+        let host = endpoint
+            .split_once("://")
+            .map_or(endpoint, |(_, rest)| rest)
+            .split('/')
+            .next()
+            .unwrap_or_default();
+
+        let mut path = format!("/{}", uri_encode(&self.config.bucket, false));
+        if !key.is_empty() {
+            path.push('/');
+            path.push_str(&uri_encode(key, true));
+        }
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(name, value)| (uri_encode(name, false), uri_encode(value, false)))
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let now = Utc::now();
+        let signature = SigV4 {
+            access_key_id: &access_key_id,
+            secret_access_key: &secret_access_key,
+            region: &self.config.region,
+            amz_date: &now.format("%Y%m%dT%H%M%SZ").to_string(),
+        };
+        let authorization = signature.authorization(method, host, &path, &query);
+
+        let mut url = format!("{}{}", endpoint, path);
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&query);
+        }
+        let request = self
+            .agent
+            .request(method, &url)
+            .set("x-amz-date", signature.amz_date)
+            .set("x-amz-content-sha256", UNSIGNED_PAYLOAD)
+            .set("Authorization", &authorization);
+        let response = match body {
+            // S3 needs the length up front instead of a chunked upload
+            Some((file, length)) => request
+                .set("Content-Length", &length.to_string())
+                .send(file),
+            None => request.call(),
+        };
+        response.map_err(|e| KbError::BackupFailed {
+            message: match e {
+                ureq::Error::Status(status, response) => format!(
+                    "{} {} returned HTTP status {}: {}",
+                    method,
+                    url,
+                    status,
+                    response.into_string().unwrap_or_default().trim()
+                ),
+                e => format!("{} {} failed: {}", method, url, e),
+            },
+        })
+    }
+}
+
+impl BackupTarget for S3Target {
+    fn name(&self) -> String {
+        format!("s3://{}/{}", self.config.bucket, self.config.prefix)
+    }
+
+    fn upload(&self, path: &Path) -> Result<()> {
+        let key = format!("{}{}", self.config.prefix, archive_name(path)?);
+        let file = File::open(path)?;
+        let length = file.metadata()?.len();
+        self.request("PUT", &key, &[], Some((file, length)))?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", self.config.prefix.as_str())];
+            if let Some(token) = &continuation {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let body = self
+                .request("GET", "", &query, None)?
+                .into_string()
+                .map_err(|e| KbError::BackupFailed {
+                    message: format!("Failed to read the listing of {}: {}", self.name(), e),
+                })?;
+
+            names.extend(xml_values(&body, "Key").into_iter().filter_map(|key| {
+                key.strip_prefix(&self.config.prefix)
+                    .filter(|name| !name.contains('/'))
+                    .map(str::to_string)
+            }));
+            continuation = xml_values(&body, "NextContinuationToken")
+                .into_iter()
+                .next();
+            if continuation.is_none() {
+                return Ok(names);
+            }
+        }
+    }
+
+    fn prune(&self, max: usize) -> Result<Vec<String>> {
+        if max == 0 {
+            return Ok(Vec::new());
+        }
+        let pruned = archives_to_prune(&self.list()?, max);
+        for name in &pruned {
+            let key = format!("{}{}", self.config.prefix, name);
+            self.request("DELETE", &key, &[], None)?;
+        }
+        Ok(pruned)
+    }
+}
+
+/// Payload hash of requests whose body isn't signed, so archives can be
+/// streamed instead of hashed first
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// Signs requests with AWS Signature Version 4, over the `host`,
+/// `x-amz-content-sha256` and `x-amz-date` headers
+struct SigV4<'a> {
+    access_key_id: &'a str,
+    secret_access_key: &'a str,
+    region: &'a str,
+    /// Time of the request, as `YYYYMMDDTHHMMSSZ`
+    amz_date: &'a str,
+}
+
+impl SigV4<'_> {
+    /// Value of the `Authorization` header of a request
+    ///
+    /// `path` and `query` must already be URI-encoded, with the query's
+    /// parameters sorted.
+    fn authorization(&self, method: &str, host: &str, path: &str, query: &str) -> String {
+        const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+        let date = &self.amz_date[..8];
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method,
+            path,
+            query,
+            host,
+            UNSIGNED_PAYLOAD,
+            self.amz_date,
+            SIGNED_HEADERS,
+            UNSIGNED_PAYLOAD
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            self.amz_date,
+            scope,
+            hex(digest::digest(&digest::SHA256, canonical_request.as_bytes()).as_ref())
+        );
+
+        let sign = |key: &[u8], message: &str| {
+            hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), message.as_bytes())
+        };
+        let key = sign(format!("AWS4{}", self.secret_access_key).as_bytes(), date);
+        let key = sign(key.as_ref(), self.region);
+        let key = sign(key.as_ref(), "s3");
+        let key = sign(key.as_ref(), "aws4_request");
+        let signature = sign(key.as_ref(), &string_to_sign);
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key_id,
+            scope,
+            SIGNED_HEADERS,
+            hex(signature.as_ref())
+        )
+    }
+}
+
+/// Lowercase hexadecimal digits of some bytes
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Percent-encodes everything but unreserved characters, as SigV4 expects,
+/// keeping slashes when encoding a key
+fn uri_encode(value: &str, keep_slashes: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if keep_slashes => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Text of every `<tag>` element of an XML document
+fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut values = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        values.push(
+            rest[..end]
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&amp;", "&"),
+        );
+        rest = &rest[end + close.len()..];
+    }
+    values
+}
+
+/// Runs user-specified commands to upload, list and remove archives
+pub struct CommandTarget {
+    config: CommandTargetConfig,
+}
+
+impl CommandTarget {
+    /// Creates a target running the commands of the configuration
+    pub fn new(config: CommandTargetConfig) -> Self {
+        Self { config }
+    }
+
+    /// Runs a command with its placeholders replaced, returning its output
+    fn run(&self, command: &str, path: &str, name: &str) -> Result<String> {
+        let args = shell_words::split(command).map_err(|e| KbError::ConfigError {
+            message: format!("Invalid backup target command '{}': {}", command, e),
+        })?;
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| {
+                arg.replace(BACKUP_PATH_PLACEHOLDER, path)
+                    .replace(BACKUP_NAME_PLACEHOLDER, name)
+            })
+            .collect();
+        let Some((program, args)) = args.split_first() else {
+            return Err(KbError::ConfigError {
+                message: "Backup target command is empty".to_string(),
+            });
+        };
+
+        let output =
+            Command::new(program)
+                .args(args)
+                .output()
+                .map_err(|e| KbError::BackupFailed {
+                    message: format!("Failed to run '{}': {}", program, e),
+                })?;
+        if !output.status.success() {
+            return Err(KbError::BackupFailed {
+                message: format!(
+                    "'{}' failed ({}): {}",
+                    command,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            });
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+impl BackupTarget for CommandTarget {
+    fn name(&self) -> String {
+        format!("command `{}`", self.config.upload)
+    }
+
+    fn upload(&self, path: &Path) -> Result<()> {
+        let name = archive_name(path)?;
+        self.run(&self.config.upload, &path.to_string_lossy(), &name)?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        let Some(list) = &self.config.list else {
+            return Err(KbError::ConfigError {
+                message: format!("{} has no list command", self.name()),
+            });
+        };
+        Ok(self
+            .run(list, "", "")?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    fn prune(&self, max: usize) -> Result<Vec<String>> {
+        // Without both commands retention is left to the remote side
+        let Some(remove) = &self.config.remove else {
+            return Ok(Vec::new());
+        };
+        if max == 0 || self.config.list.is_none() {
+            return Ok(Vec::new());
+        }
+        let pruned = archives_to_prune(&self.list()?, max);
+        for name in &pruned {
+            self.run(remove, "", name)?;
+        }
+        Ok(pruned)
+    }
+}
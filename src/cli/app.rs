@@ -3,13 +3,15 @@
 //! This module handles the command-line interface for interacting with the
 //! note storage system.
 use std::{
+    collections::{HashMap, HashSet},
     fs::{read_to_string, OpenOptions},
-    io::{stdin, stdout, Write},
+    io::{stdin, stdout, IsTerminal, Write},
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Stdio},
     sync::Arc,
 };
 
+use chrono::Utc;
 use log::info;
 
 use shell_words::split;
@@ -17,10 +19,28 @@ use tempfile::Builder;
 use tokio::sync::Mutex;
 
 use crate::{
-    parse_tags, Commands, Config, EditNoteOptions, KbError, ListNotesOptions, Note, NoteStorage,
-    Result,
+    apply_tag_changes, canonical_project_path, create_template, examples_for, export_html,
+    export_html_single_file, export_json, export_json_single_file, export_markdown,
+    export_markdown_single_file, export_pdf, export_pdf_single_file, extract_tasks,
+    extract_wiki_links, find_section, format_due_date, group_by_due_date, hash_file,
+    list_templates, load_config_from_file, load_default_config, load_template, normalize_alias,
+    note_content_hash, note_from_json_value, notebook_tree, parse_age, parse_date_bound,
+    parse_due_date, parse_tags, purge_import_journals, render_examples, render_template,
+    render_unified_diff, save_config_to_file, search_snippet_with_mode, stream_json_values,
+    validate_notebook_path, AliasCommand, ArchiveBackupInfo, BackendKind, BackupAction,
+    BatchReport, Commands, Config, ConfigOrigin, CreateNoteOptions, DateBound, DateRangeOptions,
+    DueGroup, EditNoteOptions, ExportFormat, ExportOptions, HistoryCommand, ImportFileStatus,
+    ImportJournal, ImportJournalEntry, ImportOptions, KbError, LegacyDisposition, LineRange,
+    ListFilter, ListNotesOptions, MarkdownBlocks, Note, NoteFilter, NoteSort, NoteStorage,
+    PolicyCommand, RedactMode, Redactor, RestoreBackupSummary, RestoreFilter, RestoreOptions,
+    Result, SearchMode, SearchOptions, SearchRequest, SearchResults, SearchScope, SearchScore,
+    SearchSnippet, SnapshotCommand, SnippetField, StorageFormat, StructureFilter, TagMatch, Task,
+    TaskCommand, TemplateCommand, TemplateContext, TrashCommand, COMMAND_EXAMPLES, CORRUPT_DIR,
+    LEGACY_ARCHIVE_DIR, NOTEBOOK_SEPARATOR, PROJECT_MARKER_FILE, PROJECT_PATH_KEY, STATE_DIR,
 };
 
+use super::picker::{pick, PickerItem};
+
 /// CLI Application handler - processes CLI commands and interfaces with NoteStorage
 pub struct App {
     /// The note storage backend
@@ -29,316 +49,1094 @@ pub struct App {
     /// Application configuration
     config: Config,
 
+    /// Configuration file and where each setting comes from
+    config_origin: ConfigOrigin,
+
     /// Whether to display verbose output
     verbose: bool,
+
+    /// Whether to suppress informational output
+    quiet: bool,
+}
+
+/// Number of related notes suggested after a save
+const LINK_SUGGESTION_LIMIT: usize = 5;
+
+/// Number of notes an import saves together, which is also how often a
+/// JSON import reports progress
+const IMPORT_BATCH_SIZE: usize = 1000;
+
+/// How often `kbnotes reindex` prints the progress of the background job
+const REINDEX_REPORT_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Running totals of an import, shared by the per-file helpers
+#[derive(Default)]
+struct ImportRun {
+    /// Sources that failed, with the reason
+    failures: Vec<(String, String)>,
+    /// Content hashes of the notes in the vault, used to skip notes that
+    /// already exist (`None` when duplicates are imported anyway)
+    known: Option<HashSet<u64>>,
+    /// Notes skipped because an identical note exists
+    duplicates: usize,
+    /// Notes waiting to be saved as one batch
+    pending: Vec<Note>,
+    /// Where each pending note came from, for reporting failures
+    pending_sources: Vec<String>,
+    /// Notes saved so far
+    saved: usize,
+}
+
+impl ImportRun {
+    /// Returns true (and counts the note as skipped) when an identical note
+    /// is already in the vault
+    fn is_duplicate(&mut self, note: &Note) -> bool {
+        let duplicate = self
+            .known
+            .as_ref()
+            .is_some_and(|known| known.contains(&note_content_hash(note)));
+        if duplicate {
+            self.duplicates += 1;
+        }
+        duplicate
+    }
+
+    /// Queues a note for the next batch, so later copies of it are skipped
+    fn queue(&mut self, note: Note, source: String) {
+        if let Some(known) = self.known.as_mut() {
+            known.insert(note_content_hash(&note));
+        }
+        self.pending.push(note);
+        self.pending_sources.push(source);
+    }
+
+    /// Saves the queued notes as one batch, adding the notes that failed to
+    /// `failures`
+    ///
+    /// # Returns
+    ///
+    /// The error of each source that a note failed from
+    fn save_pending(
+        &mut self,
+        storage: &NoteStorage,
+        verbose: bool,
+    ) -> Result<HashMap<String, String>> {
+        let mut failed_sources = HashMap::new();
+        if self.pending.is_empty() {
+            return Ok(failed_sources);
+        }
+
+        let notes = std::mem::take(&mut self.pending);
+        let sources = std::mem::take(&mut self.pending_sources);
+        let report = storage.save_notes(&notes)?;
+        let mut failed: HashMap<String, String> = report.failed.into_iter().collect();
+        for (note, source) in notes.iter().zip(sources) {
+            match failed.remove(&note.id) {
+                Some(error) => {
+                    // A later copy of the note may still be imported
+                    if let Some(known) = self.known.as_mut() {
+                        known.remove(&note_content_hash(note));
+                    }
+                    self.failures.push((source.clone(), error.clone()));
+                    failed_sources.insert(source, error);
+                }
+                None => {
+                    self.saved += 1;
+                    if verbose {
+                        println!("Imported as note ID: {}", note.id);
+                    }
+                }
+            }
+        }
+        Ok(failed_sources)
+    }
 }
 
 impl App {
     /// Create a new CLI application with the given storage backend and config
-    pub fn new(note_storage: Arc<Mutex<NoteStorage>>, config: Config, verbose: bool) -> Self {
+    pub fn new(
+        note_storage: Arc<Mutex<NoteStorage>>,
+        config: Config,
+        config_origin: ConfigOrigin,
+        verbose: bool,
+        quiet: bool,
+    ) -> Self {
         Self {
             note_storage,
             config,
+            config_origin,
             verbose,
+            quiet,
         }
     }
 
     /// Run the CLI application with the given command
     pub async fn run(&self, command: Commands) -> Result<()> {
+        // Background reindexing waits while a command runs, but not for the
+        // command reindexing
+        let _foreground = match command {
+            Commands::Reindex { .. } => None,
+            _ => Some(self.note_storage.lock().await.enter_foreground()),
+        };
+
         match command {
-            Commands::Create {
+            Commands::Create(options) => self.create_note(options).await?,
+
+            Commands::View {
+                id,
                 title,
-                content,
+                json,
                 edit,
-                tags,
-                file,
-            } => self.create_note(title, content, file, tags, edit).await?,
+                section,
+                lines,
+                no_pager,
+            } => {
+                let id = self.select_note_id(id, title).await?;
+                self.handle_view(id, json, edit, section, lines, no_pager)
+                    .await?
+            }
 
-            Commands::View { id, json, edit } => {}
+            Commands::Stats { id, json, format } => {
+                let json = json || format == "json";
+                match id {
+                    Some(id) => self.handle_stats(id, json).await?,
+                    None => self.handle_vault_stats(json).await?,
+                }
+            }
 
             Commands::List(options) => self.list_notes(options).await?,
 
-            Commands::Search {
-                query,
-                limit,
-                format,
-                include_content,
-            } => {
-                self.handle_search(query, limit, format, include_content)
-                    .await?;
-            }
+            Commands::Search(options) => self.handle_search(options).await?,
 
             Commands::Edit(options) => self.handle_edit(options).await?,
 
-            Commands::Delete { id, force } => self.handle_delete(id, force).await?,
+            Commands::Here { create, list } => self.handle_here(create, list).await?,
+
+            Commands::Inbox {
+                tag,
+                mark_read,
+                mark_unread,
+            } => self.handle_inbox(tag, mark_read, mark_unread).await?,
+
+            Commands::Link { id, target } => self.handle_link(id, target).await?,
+
+            Commands::Attach { id, file, remove } => self.handle_attach(id, file, remove).await?,
+
+            Commands::Alias { action } => self.handle_alias(action).await?,
+
+            Commands::Template { action } => self.handle_template(action)?,
+
+            Commands::History { id, action } => self.handle_history(id, action).await?,
+
+            Commands::Snapshot { action } => self.handle_snapshot(action).await?,
+
+            Commands::Policies { action } => self.handle_policies(action).await?,
+
+            Commands::Pin { id } => self.handle_pin(id, true).await?,
+
+            Commands::Unpin { id } => self.handle_pin(id, false).await?,
+
+            Commands::Lock { id } => self.handle_lock(id, true).await?,
+
+            Commands::Unlock { id } => self.handle_lock(id, false).await?,
+
+            Commands::Due => self.handle_due().await?,
+
+            Commands::Notebooks { notebook } => self.handle_notebooks(notebook).await?,
+
+            Commands::Tasks { done, tag, action } => self.handle_tasks(done, tag, action).await?,
+
+            Commands::Archive { id } => self.handle_archive(id, true).await?,
+
+            Commands::Unarchive { id } => self.handle_archive(id, false).await?,
+
+            Commands::Delete {
+                id,
+                title,
+                tags,
+                force,
+                permanent,
+                include_snapshots,
+            } => {
+                if !tags.is_empty() {
+                    self.handle_bulk_delete(tags, force, permanent, include_snapshots)
+                        .await?
+                } else {
+                    let id = self.select_note_id(id, title).await?;
+                    self.handle_delete(id, force, permanent, include_snapshots)
+                        .await?
+                }
+            }
+
+            Commands::Trash { action } => self.handle_trash(action).await?,
 
             Commands::Tag {
                 id,
+                title,
+                filter_tags,
                 add,
                 remove,
                 list,
-            } => {}
+                force,
+            } => {
+                if !filter_tags.is_empty() {
+                    self.handle_bulk_tag(filter_tags, add, remove, force)
+                        .await?
+                } else {
+                    let id = self.select_note_id(id, title).await?;
+                    self.handle_tag(id, add, remove, list, force).await?
+                }
+            }
+
+            Commands::Backup {
+                output,
+                incremental,
+                action,
+            } => match action {
+                Some(BackupAction::List { format, no_count }) => {
+                    self.handle_backup_list(format, no_count).await?
+                }
+                Some(BackupAction::Status { format }) => self.handle_backup_status(format).await?,
+                Some(BackupAction::Diff {
+                    backup_file,
+                    show_content_diff: Some(id),
+                    ..
+                }) => self.handle_backup_note_diff(backup_file, id).await?,
+                Some(BackupAction::Diff {
+                    backup_file,
+                    show_content_diff: None,
+                    format,
+                }) => self.handle_backup_diff(backup_file, format).await?,
+                Some(BackupAction::Push { backup_file, .. }) => {
+                    // Without a backup file, clap requires --latest
+                    let backup_file = match backup_file {
+                        Some(backup_file) => backup_file,
+                        None => self.latest_backup().await?,
+                    };
+                    self.handle_backup_push(backup_file).await?
+                }
+                Some(BackupAction::Prune { older_than }) => {
+                    self.handle_backup_prune(older_than).await?
+                }
+                None => self.handle_backup(output, incremental).await?,
+            },
+
+            Commands::Restore {
+                backup_file,
+                latest: _,
+                force,
+                overwrite,
+                replace,
+                staged,
+                rollback,
+                ids,
+                tags,
+                no_attachments,
+                dry_run,
+                format,
+            } => {
+                if rollback {
+                    self.handle_restore_rollback(force).await?
+                } else {
+                    // Without a backup file, clap requires --latest
+                    let backup_file = match backup_file {
+                        Some(backup_file) => backup_file,
+                        None => self.latest_backup().await?,
+                    };
+                    let options = RestoreOptions {
+                        overwrite,
+                        replace,
+                        filter: RestoreFilter {
+                            ids: (!ids.is_empty()).then(|| ids.into_iter().collect()),
+                            tags,
+                            skip_attachments: no_attachments,
+                        },
+                        dry_run,
+                    };
+                    if dry_run {
+                        self.handle_restore_dry_run(backup_file, &options, &format)
+                            .await?
+                    } else if format == "json" {
+                        // The preview and prompt would get in the way of the JSON
+                        if !force {
+                            return Err(KbError::InvalidArgument {
+                                message: "restore --format json needs --force".to_string(),
+                            });
+                        }
+                        self.handle_restore_json(backup_file, &options).await?
+                    } else {
+                        self.handle_restore(backup_file, force, staged, &options)
+                            .await?
+                    }
+                }
+            }
 
-            Commands::Backup { output } => {}
+            Commands::Config { show, set, reset } => self.handle_config(show, set, reset).await?,
 
-            Commands::Restore { backup_file, force } => {}
+            Commands::Import(options) => self.handle_import(options).await?,
 
-            Commands::Config { show, set, reset } => {}
+            Commands::Export(options) => self.handle_export(options).await?,
 
-            Commands::Import {
-                source,
-                format,
-                tags,
-            } => {}
+            Commands::MigrateHistory {
+                delete,
+                archive,
+                dry_run,
+            } => {
+                self.handle_migrate_history(delete, archive, dry_run)
+                    .await?
+            }
 
-            Commands::Export {
-                output,
-                format,
-                tag,
-                single_file,
-            } => {}
+            Commands::MigrateFormat {
+                compress,
+                decompress,
+                threshold,
+                to,
+            } => {
+                self.handle_migrate_format(compress, decompress, threshold, to)
+                    .await?
+            }
+
+            Commands::MigrateBackend { to } => self.handle_migrate_backend(to).await?,
+
+            Commands::Status { json } => self.handle_status(json).await?,
+
+            Commands::Doctor { fix, corrupt } => {
+                if corrupt {
+                    self.handle_corrupt(fix).await?
+                } else {
+                    self.handle_doctor(fix).await?
+                }
+            }
+
+            Commands::Reindex { foreground } => self.handle_reindex(foreground).await?,
+
+            Commands::Changed { since, format } => self.handle_changed(since, format).await?,
+
+            Commands::Examples { command } => self.handle_examples(command)?,
         }
 
         Ok(())
     }
 
-    async fn create_note(
-        &self,
-        title: String,
-        content: Option<String>,
-        file: Option<PathBuf>,
-        tags: Option<String>,
-        no_editor: bool,
-    ) -> Result<()> {
+    async fn create_note(&self, options: CreateNoteOptions) -> Result<()> {
+        let CreateNoteOptions {
+            title,
+            content,
+            edit: no_editor,
+            tags,
+            file,
+            template,
+            due,
+            notebook,
+        } = options;
+
         // Your implementation from earlier, adapted to CliApp context
         let parsed_tags = parse_tags(tags);
 
+        // Reject a bad due date or notebook before opening an editor
+        let due_at = due
+            .map(|value| parse_due_date(&value, chrono::Local::now().date_naive()))
+            .transpose()?;
+        let notebook = notebook
+            .map(|path| validate_notebook_path(&path))
+            .transpose()?;
+
         // Get content based on the provided options
-        let note_content = match (content, file) {
-            (Some(c), _) => c,
+        let given_content = match (content, file) {
+            (Some(c), _) => Some(c),
             (_, Some(file_path)) => {
                 if !file_path.exists() {
                     return Err(KbError::FileNotFound {
                         file_path: file_path.display().to_string(),
                     });
                 }
-                read_to_string(file_path)?
+                Some(read_to_string(file_path)?)
             }
-            (None, None) => {
-                if no_editor {
-                    String::new()
+            (None, None) => None,
+        };
+
+        let note_content = match template {
+            Some(name) => {
+                let has_content = given_content.is_some();
+                let rendered = render_template(
+                    &load_template(&self.config.templates_dir, &name)?,
+                    &TemplateContext {
+                        title: title.clone(),
+                        now: chrono::Local::now(),
+                        content: given_content,
+                    },
+                );
+                // Without content to fill in, the template is edited before saving
+                if has_content || no_editor {
+                    rendered
                 } else {
-                    self.open_editor_for_content(&title)?
+                    self.open_editor_with_content(&title, &rendered)?
                 }
             }
+            None => match given_content {
+                Some(content) => content,
+                None if no_editor => String::new(),
+                None => self.open_editor_for_content(&title)?,
+            },
         };
 
         // Create and save the note
-        let note = Note::new(title, note_content, parsed_tags);
+        let mut note = Note::new(title, note_content, parsed_tags);
+        note.due_at = due_at;
+        note.notebook = notebook;
 
         self.note_storage.lock().await.save_note(&note)?;
         println!("Note created with ID: {}", note.id);
+
+        self.print_link_suggestions(&note.id).await;
         Ok(())
     }
 
-    fn open_editor_for_content(&self, title: &str) -> Result<String> {
-        // Create a temporary file with .md extension
-        let temp_file = Builder::new().suffix(".md").tempfile()?;
-        let temp_path = temp_file.path().to_path_buf();
-
-        // Get editor from config or environment
-        let editor_cmd = self.config.get_editor_command();
+    /// Print related notes worth linking, unless disabled or running non-interactively
+    async fn print_link_suggestions(&self, note_id: &str) {
+        if !self.config.suggest_links || self.quiet || !console::Term::stdout().is_term() {
+            return;
+        }
 
-        // Write template to the temp file
-        self.write_editor_template(&temp_path, title)?;
+        let suggestions = match self
+            .note_storage
+            .lock()
+            .await
+            .suggest_links(note_id, LINK_SUGGESTION_LIMIT)
+        {
+            Ok(suggestions) => suggestions,
+            Err(e) => {
+                info!("Skipping link suggestions: {}", e);
+                return;
+            }
+        };
 
-        // Open editor
-        info!("Opening editor to write note content. Save and exit when done...");
-        self.launch_editor(&editor_cmd, &temp_path)?;
+        if suggestions.is_empty() {
+            return;
+        }
 
-        // Read and process the content
-        let content = read_to_string(&temp_path)?;
-        Ok(self.process_editor_content(content))
+        println!();
+        for related in &suggestions {
+            println!(
+                "Related: [{}] {} ({:.2})",
+                related.id, related.title, related.score
+            );
+        }
+        println!(
+            "Link one with: kbnotes link {} {}",
+            note_id, suggestions[0].id
+        );
     }
 
-    fn write_editor_template(&self, path: &Path, title: &str) -> Result<()> {
-        let mut file = OpenOptions::new().write(true).open(path)?;
-
-        // Write template with helpful comments
-        writeln!(file, "# {}", title)?;
-        writeln!(file)?;
-        writeln!(file, "<!-- ")?;
-        writeln!(
-            file,
-            "Write your note content below. This note supports Markdown format."
-        )?;
-        writeln!(
-            file,
-            "Lines that start with <!-- and end with --> are comments and will be ignored."
-        )?;
-        writeln!(file, "Save and exit the editor when you're done.")?;
-        writeln!(file, "-->")?;
-        writeln!(file)?;
+    /// Show, create or list notes associated with project directories
+    async fn handle_here(&self, create: bool, list: bool) -> Result<()> {
+        let storage = self.note_storage.lock().await;
 
-        Ok(())
-    }
+        if list {
+            let project_notes = storage.get_project_notes()?;
+            if project_notes.is_empty() {
+                println!("No notes are associated with a project directory");
+                return Ok(());
+            }
 
-    fn launch_editor(&self, editor_cmd: &str, file_path: &Path) -> Result<()> {
-        // Convert file path to string once
-        let path_str = file_path.to_string_lossy();
+            for (note, path) in &project_notes {
+                let stale = if path.exists() {
+                    String::new()
+                } else {
+                    format!(
+                        " {}",
+                        console::style("(stale: path no longer exists)").yellow()
+                    )
+                };
+                println!("{} | {} | {}{}", note.id, note.title, path.display(), stale);
+            }
+            return Ok(());
+        }
 
-        // Handle shell-like command parsing
-        let args = split(editor_cmd).map_err(|e| KbError::EditorError {
-            message: format!("Failed to parse editor command: {}", e),
-        })?;
+        let cwd = std::env::current_dir()?;
+        if let Some((note, dir)) = storage.find_project_note(&cwd)? {
+            if create {
+                println!(
+                    "Directory {} is already associated with note {}",
+                    dir.display(),
+                    note.id
+                );
+            }
+            storage.record_note_view(&note.id)?;
+            return self.display_notes_text(&[note], true, None);
+        }
 
-        if args.is_empty() {
-            return Err(KbError::EditorError {
-                message: "Empty editor command".to_string(),
-            });
+        if !create {
+            println!(
+                "No note is associated with {}. Create one with: kbnotes here --create",
+                cwd.display()
+            );
+            return Ok(());
         }
 
-        // First word is the program name, rest are arguments
-        let program = &args[0];
+        let project_dir = canonical_project_path(&cwd);
+        let title = project_dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| project_dir.display().to_string());
 
-        // Create command
-        let mut command = Command::new(program);
+        let mut note = Note::new(title, String::new(), vec!["project".to_string()]);
+        note.metadata.insert(
+            PROJECT_PATH_KEY.to_string(),
+            project_dir.display().to_string(),
+        );
+        storage.save_note(&note)?;
 
-        // Add any arguments from the original command
-        if args.len() > 1 {
-            command.args(&args[1..]);
+        let marker = project_dir.join(PROJECT_MARKER_FILE);
+        if let Err(e) = std::fs::write(&marker, format!("{}\n", note.id)) {
+            // The recorded path still associates the note with the directory
+            println!("Warning: could not write {}: {}", marker.display(), e);
         }
 
-        // Add the file path as the final argument
-        command.arg(path_str.as_ref());
+        println!(
+            "Note '{}' created with ID: {} for {}",
+            note.title,
+            note.id,
+            project_dir.display()
+        );
+        Ok(())
+    }
 
-        // Execute the command
-        let status = command.status()?;
+    /// List unread notes, or mark notes read/unread in bulk
+    async fn handle_inbox(
+        &self,
+        tag: Option<String>,
+        mark_read: Option<Vec<String>>,
+        mark_unread: Option<Vec<String>>,
+    ) -> Result<()> {
+        let storage = self.note_storage.lock().await;
 
-        if !status.success() {
-            return Err(KbError::EditorError {
-                message: "Editor exited with non-zero status".to_string(),
-            });
+        if let Some(ids) = mark_unread {
+            let ids = ids
+                .iter()
+                .map(|id| storage.resolve_note_id(id))
+                .collect::<Result<Vec<String>>>()?;
+            let changed = storage.mark_notes_unread(&ids)?;
+            println!("Marked {} note(s) as unread", changed);
+            return Ok(());
         }
 
-        Ok(())
-    }
+        if let Some(ids) = mark_read {
+            // Without IDs, clear everything the inbox currently shows
+            let ids = if ids.is_empty() {
+                storage
+                    .get_unread_notes(tag.as_deref())?
+                    .into_iter()
+                    .map(|note| note.id)
+                    .collect()
+            } else {
+                ids.iter()
+                    .map(|id| storage.resolve_note_id(id))
+                    .collect::<Result<Vec<String>>>()?
+            };
+            let changed = storage.mark_notes_read(&ids)?;
+            println!("Marked {} note(s) as read", changed);
+            return Ok(());
+        }
 
-    fn process_editor_content(&self, content: String) -> String {
-        // Remove HTML comments from content
-        content
-            .lines()
-            .filter(|line| {
-                !line.trim_start().starts_with("<!--") && !line.trim_end().ends_with("-->")
-            })
-            .collect::<Vec<&str>>()
-            .join("\n")
-    }
+        let unread = storage.get_unread_notes(tag.as_deref())?;
+        let scope = match &tag {
+            Some(tag) => format!(" tagged '{}'", tag),
+            None => String::new(),
+        };
 
-    /// List notes according to provided filters and options
-    async fn list_notes(&self, options: ListNotesOptions) -> Result<()> {
-        // Step 1: Retrieve notes based on filters
-        let notes = self
-            .retrieve_filtered_notes(options.tag, options.search)
-            .await?;
+        if unread.is_empty() {
+            println!("No unread notes{}", scope);
+            return Ok(());
+        }
 
-        // Step 2: Sort notes based on sort criteria
-        let mut sorted_notes = self.sort_notes(notes, &options.sort_by, options.descending);
+        for note in &unread {
+            println!(
+                "{} | {} | {}",
+                note.id,
+                console::style(&note.title).bold(),
+                note.created_at.format("%Y-%m-%d %H:%M")
+            );
+        }
+        println!(
+            "\n{} unread note{}{}",
+            unread.len(),
+            if unread.len() == 1 { "" } else { "s" },
+            scope
+        );
+        Ok(())
+    }
 
-        // Step 3: Apply limit
-        if sorted_notes.len() > options.limit {
-            sorted_notes.truncate(options.limit);
+    /// Add a wiki-link to `target` in the "Related" section of note `id`
+    async fn handle_link(&self, id: String, target: String) -> Result<()> {
+        let storage = self.note_storage.lock().await;
+
+        let id = storage.resolve_note_id(&id)?;
+        let mut note = storage
+            .get_note(&id)
+            .ok_or_else(|| KbError::NoteNotFound { id: id.clone() })?;
+
+        let target = storage.resolve_note_id(&target)?;
+        let target_note = storage
+            .get_note(&target)
+            .ok_or_else(|| KbError::NoteNotFound { id: target.clone() })?;
+
+        if extract_wiki_links(&note.content)
+            .iter()
+            .any(|link| link == &target_note.id || link.eq_ignore_ascii_case(&target_note.title))
+        {
+            println!("Note {} already links to {}", note.id, target_note.id);
+            return Ok(());
         }
 
-        // Step 4: Display notes in requested format
-        self.display_notes(&sorted_notes, &options.format, options.detailed)?;
+        note.content = append_related_link(&note.content, &target_note.id);
+        note.updated_at = chrono::Utc::now();
+        storage.update_note(note.clone())?;
+
+        println!(
+            "Linked '{}' -> '{}' ({})",
+            note.title, target_note.title, target_note.id
+        );
         Ok(())
     }
 
-    /// Retrieve notes based on tag and search filters
-    async fn retrieve_filtered_notes(
-        &self,
-        tag: Option<String>,
-        search: Option<String>,
-    ) -> Result<Vec<Note>> {
-        let storage = self.note_storage.lock().await.clone();
-        match (tag, search) {
-            // Case 1: Filter by both tag and search term
-            (Some(tag_value), Some(search_term)) => {
-                // First, filter by tag
-                let tagged_notes = storage.get_notes_by_tag(&tag_value)?;
-
-                // Then filter the tagged notes by search term
-                let filtered_notes: Vec<Note> = tagged_notes
-                    .into_iter()
-                    .filter(|note| {
-                        note.title.contains(&search_term) || note.content.contains(&search_term)
-                    })
-                    .collect();
+    /// Pin or unpin a note
+    async fn handle_pin(&self, id: String, pinned: bool) -> Result<()> {
+        let storage = self.note_storage.lock().await;
+        let id = storage.resolve_note_id(&id)?;
+        let mut note = storage
+            .get_note(&id)
+            .ok_or_else(|| KbError::NoteNotFound { id: id.clone() })?;
+
+        let action = if pinned { "pinned" } else { "unpinned" };
+        if note.pinned == pinned {
+            println!("Note {} is already {}", note.id, action);
+            return Ok(());
+        }
 
-                Ok(filtered_notes)
-            }
+        note.pinned = pinned;
+        note.updated_at = chrono::Utc::now();
+        storage.update_note(note.clone())?;
 
-            // Case 2: Filter by tag only
-            (Some(tag_value), None) => storage.get_notes_by_tag(&tag_value),
+        println!("Note '{}' ({}) {}", note.title, note.id, action);
+        Ok(())
+    }
 
-            // Case 3: Filter by search term only
-            (None, Some(search_term)) => Ok(storage.search_notes(&search_term)),
+    /// List notes with a due date, grouped into overdue, today and upcoming
+    async fn handle_due(&self) -> Result<()> {
+        let mut notes = self.note_storage.lock().await.get_all_notes()?;
+        notes.retain(|note| !note.archived);
 
-            // Case 4: No filters, show all notes
-            (None, None) => Ok(Vec::new()),
+        let groups = group_by_due_date(notes, chrono::Local::now().date_naive());
+        if groups.is_empty() {
+            println!("No notes have a due date. Set one with: kbnotes edit <id> --due <date>");
+            return Ok(());
         }
-    }
 
-    /// Sort notes by specified criteria
-    fn sort_notes(&self, mut notes: Vec<Note>, sort_by: &str, descending: bool) -> Vec<Note> {
-        match sort_by {
-            "title" => {
-                notes.sort_by(|a, b| {
-                    let cmp = a.title.cmp(&b.title);
-                    if descending {
-                        cmp.reverse()
-                    } else {
-                        cmp
-                    }
-                });
+        for (i, (group, notes)) in groups.iter().enumerate() {
+            if i > 0 {
+                println!();
             }
-            "id" => {
-                notes.sort_by(|a, b| {
-                    let cmp = a.id.cmp(&b.id);
-                    if descending {
-                        cmp.reverse()
-                    } else {
-                        cmp
-                    }
-                });
+            let heading = format!("{} ({})", group.label(), notes.len());
+            match group {
+                DueGroup::Overdue => println!("{}", console::style(heading).red().bold()),
+                DueGroup::Today => println!("{}", console::style(heading).yellow().bold()),
+                DueGroup::Upcoming => println!("{}", console::style(heading).bold()),
             }
-            // Default is "date"
-            _ => {
-                notes.sort_by(|a, b| {
-                    let cmp = a.created_at.cmp(&b.created_at);
-                    if descending {
-                        cmp.reverse()
-                    } else {
-                        cmp
-                    }
-                });
+            for note in notes {
+                if let Some(due_at) = note.due_at {
+                    println!(
+                        "  {:16}  {} {}",
+                        format_due_date(due_at),
+                        note.title,
+                        console::style(format!("({})", note.id)).dim()
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// List the notebook tree with the number of notes in each notebook,
+    /// leaving out archived notes
+    async fn handle_notebooks(&self, notebook: Option<String>) -> Result<()> {
+        let storage = self.note_storage.lock().await;
+        let (mut notes, root) = match notebook {
+            Some(notebook) => {
+                let notebook = validate_notebook_path(&notebook)?;
+                (storage.get_notes_by_notebook(&notebook)?, Some(notebook))
+            }
+            None => (storage.get_all_notes()?, None),
+        };
+        notes.retain(|note| !note.archived);
+
+        let tree = notebook_tree(&notes);
+        if tree.is_empty() {
+            match root {
+                Some(root) => println!("Notebook '{}' holds no notes", root),
+                None => println!(
+                    "No notes are in a notebook. File one with: kbnotes edit <id> --notebook <path>"
+                ),
+            }
+            return Ok(());
+        }
+
+        // Notebooks above the requested one only lead the way to it
+        let min_depth = root
+            .as_deref()
+            .map(|root| root.matches(NOTEBOOK_SEPARATOR).count())
+            .unwrap_or(0);
+        for notebook in tree.iter().filter(|notebook| notebook.depth() >= min_depth) {
+            let name = if notebook.depth() == min_depth {
+                notebook.path.as_str()
+            } else {
+                notebook.name()
+            };
+            println!(
+                "{}{} {}",
+                "  ".repeat(notebook.depth() - min_depth),
+                console::style(name).bold(),
+                console::style(format!("({})", notebook.total)).dim()
+            );
+        }
+
+        if root.is_none() {
+            let loose = notes.iter().filter(|note| note.notebook.is_none()).count();
+            if loose > 0 {
+                println!("{} note(s) not in a notebook", loose);
+            }
+        }
+        Ok(())
+    }
+
+    /// List open or done tasks, or toggle one
+    async fn handle_tasks(
+        &self,
+        done: bool,
+        tag: Option<String>,
+        action: Option<TaskCommand>,
+    ) -> Result<()> {
+        let storage = self.note_storage.lock().await;
+
+        if let Some(TaskCommand::Check { id, line }) = action {
+            let id = storage.resolve_note_id(&id)?;
+            let task = storage.toggle_task(&id, line)?;
+            println!(
+                "{} task on line {} of note {}: {}",
+                if task.checked { "Checked" } else { "Unchecked" },
+                line,
+                id,
+                task.text
+            );
+            return Ok(());
+        }
+
+        let mut notes = match &tag {
+            Some(tag) => storage.get_notes_by_tag(tag, false)?,
+            None => storage.get_all_notes()?,
+        };
+        drop(storage);
+        notes.retain(|note| !note.archived);
+        notes.sort_by(|a, b| a.title.cmp(&b.title).then_with(|| a.id.cmp(&b.id)));
+
+        let mut count = 0;
+        for note in &notes {
+            let tasks: Vec<Task> = extract_tasks(&note.content)
+                .into_iter()
+                .filter(|task| task.checked == done)
+                .collect();
+            if tasks.is_empty() {
+                continue;
+            }
+
+            if count > 0 {
+                println!();
+            }
+            println!(
+                "{} {}",
+                console::style(&note.title).bold(),
+                console::style(format!("({})", note.id)).dim()
+            );
+            for task in &tasks {
+                println!(
+                    "  {:>5}  {}[{}] {}",
+                    task.line,
+                    "  ".repeat(task.depth),
+                    if task.checked { "x" } else { " " },
+                    task.text
+                );
             }
+            count += tasks.len();
+        }
+
+        let state = if done { "done" } else { "open" };
+        if count == 0 {
+            println!("No {} tasks found", state);
+        } else {
+            println!("\nFound {} {} tasks", count, state);
+        }
+        Ok(())
+    }
+
+    /// Lock or unlock a note
+    async fn handle_lock(&self, id: String, locked: bool) -> Result<()> {
+        let storage = self.note_storage.lock().await;
+        let id = storage.resolve_note_id(&id)?;
+
+        let action = if locked { "locked" } else { "unlocked" };
+        match storage.set_locked(&id, locked)? {
+            Some(note) => println!("Note '{}' ({}) {}", note.title, note.id, action),
+            None => println!("Note {} is already {}", id, action),
+        }
+        Ok(())
+    }
+
+    /// Archive or unarchive a note
+    async fn handle_archive(&self, id: String, archived: bool) -> Result<()> {
+        let storage = self.note_storage.lock().await;
+        let id = storage.resolve_note_id(&id)?;
+
+        let action = if archived { "archived" } else { "unarchived" };
+        match storage.set_archived(&id, archived)? {
+            Some(note) => println!("Note '{}' ({}) {}", note.title, note.id, action),
+            None => println!("Note {} is already {}", id, action),
+        }
+        Ok(())
+    }
+
+    async fn handle_attach(
+        &self,
+        id: String,
+        file: Option<PathBuf>,
+        remove: Option<String>,
+    ) -> Result<()> {
+        let storage = self.note_storage.lock().await;
+        let id = storage.resolve_note_id(&id)?;
+
+        if let Some(filename) = remove {
+            let attachment = storage.remove_attachment(&id, &filename)?;
+            println!(
+                "Removed attachment {} from note {}",
+                attachment.filename, id
+            );
+            return Ok(());
+        }
+
+        let file = file.ok_or_else(|| KbError::InvalidArgument {
+            message: "No file to attach".to_string(),
+        })?;
+        let attachment = storage.add_attachment(&id, &file)?;
+        println!(
+            "Attached {} to note {} ({}, {})",
+            attachment.filename,
+            id,
+            attachment.mime_type,
+            format_size(attachment.size)
+        );
+        Ok(())
+    }
+
+    fn open_editor_for_content(&self, title: &str) -> Result<String> {
+        // Create a temporary file with .md extension
+        let temp_file = Builder::new().suffix(".md").tempfile()?;
+        let temp_path = temp_file.path().to_path_buf();
+
+        // Get editor from config or environment
+        let editor_cmd = self.config.get_editor_command();
+
+        // Write template to the temp file
+        self.write_editor_template(&temp_path, title)?;
+
+        // Open editor
+        info!("Opening editor to write note content. Save and exit when done...");
+        self.launch_editor(&editor_cmd, &temp_path)?;
+
+        // Read and process the content
+        let content = read_to_string(&temp_path)?;
+        Ok(self.process_editor_content(content))
+    }
+
+    fn write_editor_template(&self, path: &Path, title: &str) -> Result<()> {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+
+        // Write template with helpful comments
+        writeln!(file, "# {}", title)?;
+        writeln!(file)?;
+        writeln!(file, "<!-- ")?;
+        writeln!(
+            file,
+            "Write your note content below. This note supports Markdown format."
+        )?;
+        writeln!(
+            file,
+            "Lines that start with <!-- and end with --> are comments and will be ignored."
+        )?;
+        writeln!(file, "Save and exit the editor when you're done.")?;
+        writeln!(file, "-->")?;
+        writeln!(file)?;
+
+        Ok(())
+    }
+
+    fn launch_editor(&self, editor_cmd: &str, file_path: &Path) -> Result<()> {
+        // Convert file path to string once
+        let path_str = file_path.to_string_lossy();
+
+        // Handle shell-like command parsing
+        let args = split(editor_cmd).map_err(|e| KbError::EditorError {
+            message: format!("Failed to parse editor command: {}", e),
+        })?;
+
+        if args.is_empty() {
+            return Err(KbError::EditorError {
+                message: "Empty editor command".to_string(),
+            });
+        }
+
+        // First word is the program name, rest are arguments
+        let program = &args[0];
+
+        // Create command
+        let mut command = Command::new(program);
+
+        // Add any arguments from the original command
+        if args.len() > 1 {
+            command.args(&args[1..]);
+        }
+
+        // Add the file path as the final argument
+        command.arg(path_str.as_ref());
+
+        // Execute the command
+        let status = command.status()?;
+
+        if !status.success() {
+            return Err(KbError::EditorError {
+                message: "Editor exited with non-zero status".to_string(),
+            });
+        }
+
+        Ok(())
+    }
+
+    fn process_editor_content(&self, content: String) -> String {
+        // Remove HTML comments from content
+        content
+            .lines()
+            .filter(|line| {
+                !line.trim_start().starts_with("<!--") && !line.trim_end().ends_with("-->")
+            })
+            .collect::<Vec<&str>>()
+            .join("\n")
+    }
+
+    /// List notes according to provided filters and options
+    ///
+    /// Shows the first `--limit` notes, or one page of `--page-size` notes
+    /// followed by the cursor of the next page.
+    async fn list_notes(&self, options: ListNotesOptions) -> Result<()> {
+        // Step 1: Gather the conditions and the order of the listing
+        let mut filter = date_filter(options.dates)?;
+        filter.tags = options.tags;
+        if options.all_tags {
+            filter.tag_match = TagMatch::All;
+        }
+        filter.exclude_tags = options.exclude_tags;
+        let ids = match options.search {
+            Some(search_term) => Some(
+                self.search_note_ids(&filter, search_term, options.include_archived)
+                    .await?,
+            ),
+            None => None,
+        };
+        let list_filter = ListFilter {
+            filter,
+            include_archived: options.include_archived,
+            pinned_only: options.pinned,
+            notebook: options
+                .notebook
+                .map(|notebook| validate_notebook_path(&notebook))
+                .transpose()?,
+            structure: parse_structure_filters(&options.has)?,
+            ids,
+        };
+        // Pinned notes come first whatever the field and direction
+        let sort = NoteSort {
+            field: options.sort_by.parse()?,
+            descending: options.descending,
+            pinned_first: true,
+        };
+
+        // Step 2: Read the requested page
+        let paged = options.page_size.is_some() || options.cursor.is_some();
+        let page = self.note_storage.lock().await.list_notes_page(
+            &list_filter,
+            &sort,
+            options.cursor,
+            options.page_size.unwrap_or(options.limit),
+        )?;
+
+        // Step 3: Display notes in requested format
+        if !paged {
+            return self
+                .display_notes(&page.notes, &options.format, options.detailed)
+                .await;
+        }
+        if options.format == "json" {
+            let notes = self.notes_json(&page.notes, options.detailed, None).await?;
+            let value = serde_json::json!({
+                "notes": notes,
+                "next_cursor": page.next_cursor,
+            });
+            println!("{}", serde_json::to_string_pretty(&value)?);
+            return Ok(());
+        }
+        self.display_notes(&page.notes, &options.format, options.detailed)
+            .await?;
+        if let Some(cursor) = page.next_cursor {
+            println!("More notes follow; continue with --cursor {}", cursor);
         }
+        Ok(())
+    }
 
-        notes
+    /// Finds the IDs of the notes matching a `list --search` term and the
+    /// tag and date filter
+    async fn search_note_ids(
+        &self,
+        filter: &NoteFilter,
+        search_term: String,
+        include_archived: bool,
+    ) -> Result<HashSet<String>> {
+        let storage = self.note_storage.lock().await.clone();
+        let notes = if filter.tags.is_empty() {
+            // Search term only, matched fuzzily
+            storage
+                .search_notes_filtered(
+                    &search_term,
+                    &SearchRequest {
+                        include_archived,
+                        filter: filter.clone(),
+                        ..SearchRequest::default()
+                    },
+                )?
+                .into_notes()
+        } else {
+            // Tags and search term, which must appear as written
+            storage.query_notes_containing(filter.clone(), &search_term)?
+        };
+        Ok(notes.into_iter().map(|note| note.id).collect())
     }
 
     /// Display notes in the requested format
-    fn display_notes(&self, notes: &[Note], format: &str, detailed: bool) -> Result<()> {
+    async fn display_notes(&self, notes: &[Note], format: &str, detailed: bool) -> Result<()> {
         if notes.is_empty() {
             println!("No notes found matching the criteria.");
             return Ok(());
         }
 
         match format {
-            "json" => self.display_notes_json(notes, detailed)?,
-            _ => self.display_notes_text(notes, detailed)?,
+            "json" => self.display_notes_json(notes, detailed, None).await?,
+            _ => self.display_notes_text(notes, detailed, None)?,
         }
 
         // Print count at the end
@@ -351,35 +1149,79 @@ impl App {
         Ok(())
     }
 
-    /// Display notes in JSON format
-    fn display_notes_json(&self, notes: &[Note], detailed: bool) -> Result<()> {
+    /// Display notes in JSON format, with the snippet and score of each
+    /// search result when `matches` is given
+    async fn display_notes_json(
+        &self,
+        notes: &[Note],
+        detailed: bool,
+        matches: Option<&HashMap<String, ResultMatch>>,
+    ) -> Result<()> {
+        let values = self.notes_json(notes, detailed, matches).await?;
+        println!("{}", serde_json::to_string_pretty(&values)?);
+        Ok(())
+    }
+
+    /// Builds the JSON values printed by `display_notes_json`
+    async fn notes_json(
+        &self,
+        notes: &[Note],
+        detailed: bool,
+        matches: Option<&HashMap<String, ResultMatch>>,
+    ) -> Result<Vec<serde_json::Value>> {
         // For JSON output, we'll either output the full notes or a simplified version
         if detailed {
-            // Full notes with all fields
-            println!("{}", serde_json::to_string_pretty(notes)?);
+            // Full notes with all fields, plus the structure of their content
+            let summaries = self.note_storage.lock().await.structure_summaries(notes)?;
+            let detailed_notes = notes
+                .iter()
+                .map(|note| {
+                    let mut value = serde_json::to_value(note)?;
+                    value["structure"] = serde_json::to_value(&summaries[&note.id])?;
+                    value["stats"] = serde_json::to_value(note.stats())?;
+                    if let Some(matches) = matches {
+                        add_match_fields(&mut value, matches.get(&note.id));
+                    }
+                    Ok(value)
+                })
+                .collect::<Result<Vec<serde_json::Value>>>()?;
+            Ok(detailed_notes)
         } else {
             // Simplified notes with just id, title, and tags
             let simplified_notes: Vec<serde_json::Value> = notes
                 .iter()
                 .map(|note| {
-                    serde_json::json!({
+                    let mut value = serde_json::json!({
                         "id": note.id,
                         "title": note.title,
                         "created_at": note.created_at,
                         "updated_at": note.updated_at.to_rfc3339(),
                         "tags": note.tags,
-                    })
+                        "pinned": note.pinned,
+                        "archived": note.archived,
+                        "due_at": note.due_at,
+                        "notebook": note.notebook,
+                        "locked": note.locked,
+                    });
+                    if let Some(matches) = matches {
+                        add_match_fields(&mut value, matches.get(&note.id));
+                    }
+                    value
                 })
                 .collect();
 
-            println!("{}", serde_json::to_string_pretty(&simplified_notes)?);
+            Ok(simplified_notes)
         }
-
-        Ok(())
     }
 
-    /// Display notes in text format
-    fn display_notes_text(&self, notes: &[Note], detailed: bool) -> Result<()> {
+    /// Display notes in text format, showing where each search result
+    /// matched (and with which score, if known) when `matches` is given
+    fn display_notes_text(
+        &self,
+        notes: &[Note],
+        detailed: bool,
+        matches: Option<&HashMap<String, ResultMatch>>,
+    ) -> Result<()> {
         // Use terminal width for formatting if available
         let term_width = terminal_size::terminal_size()
             .map(|(w, _)| w.0 as usize)
@@ -394,9 +1236,45 @@ impl App {
             // Format created date
             let created_at = note.created_at.format("%Y-%m-%d %H:%M");
 
-            // Print ID, title, and creation date
-            println!("ID: {} | Created: {}", note.id, created_at);
-            println!("Title: {}", console::style(&note.title).bold());
+            // Print ID, title, and creation and due dates
+            match note.due_at {
+                Some(due_at) => println!(
+                    "ID: {} | Created: {} | Due: {}",
+                    note.id,
+                    created_at,
+                    format_due_date(due_at)
+                ),
+                None => println!("ID: {} | Created: {}", note.id, created_at),
+            }
+            let mut markers = Vec::new();
+            if note.pinned {
+                markers.push(console::style("[pinned]").yellow().to_string());
+            }
+            if note.archived {
+                markers.push(console::style("[archived]").dim().to_string());
+            }
+            if note.locked {
+                markers.push(console::style("[locked]").red().to_string());
+            }
+            let result_match = matches.and_then(|matches| matches.get(&note.id));
+            let snippet = result_match.and_then(|result_match| result_match.snippet.as_ref());
+            let title = match snippet {
+                Some(snippet) if snippet.field == SnippetField::Title => snippet.highlighted(),
+                _ => console::style(&note.title).bold().to_string(),
+            };
+            if markers.is_empty() {
+                println!("Title: {}", title);
+            } else {
+                println!("Title: {} {}", title, markers.join(" "));
+            }
+            if let Some(score) = result_match.and_then(|result_match| result_match.score) {
+                let fields = score.fields.names();
+                if fields.is_empty() {
+                    println!("Score: {}", score.score);
+                } else {
+                    println!("Score: {} (matched {})", score.score, fields.join(", "));
+                }
+            }
 
             // Print tags if any
             if !note.tags.is_empty() {
@@ -409,10 +1287,19 @@ impl App {
 
                 println!("Tags: {}", console::style(tags).cyan());
             }
+            if let Some(notebook) = &note.notebook {
+                println!("Notebook: {}", notebook);
+            }
 
             // Print content preview or full content based on detailed flag
             if detailed {
+                println!("Stats: {}", note.stats().summary());
                 println!("\n{}", note.content);
+            } else if let Some(snippet) =
+                snippet.filter(|snippet| snippet.field == SnippetField::Content)
+            {
+                // Where the search matched instead of the first line
+                println!("\n{}", snippet.highlighted());
             } else {
                 // Get a content preview (first line or first N characters)
                 let preview = self.get_content_preview(&note.content, 100);
@@ -440,13 +1327,27 @@ impl App {
         }
     }
 
-    async fn handle_search(
-        &self,
-        query: String,
-        limit: usize,
-        format: String,
-        include_content: bool,
-    ) -> Result<()> {
+    async fn handle_search(&self, options: SearchOptions) -> Result<()> {
+        let SearchOptions {
+            query,
+            exact,
+            case_sensitive,
+            title_only,
+            content_only,
+            limit,
+            offset,
+            format,
+            include_content,
+            show_scores,
+            interactive,
+            then,
+            all,
+            exclude_tags,
+            has,
+            include_archived,
+            dates,
+        } = options;
+
         // Validate format
         let format = format.to_lowercase();
         if !["text", "json"].contains(&format.as_str()) {
@@ -455,68 +1356,492 @@ impl App {
             });
         }
 
-        // Perform the search
-        let mut results = self.note_storage.lock().await.clone().search_notes(&query);
-
-        // Apply limit if specified (0 means no limit)
-        if limit > 0 && results.len() > limit {
-            results = results.into_iter().take(limit).collect();
+        // Search, leaving out notes hidden by their tag policy, and keep only
+        // the requested page (a limit of 0 means no limit)
+        let mode = if exact || case_sensitive {
+            SearchMode::Exact { case_sensitive }
+        } else {
+            SearchMode::Fuzzy
+        };
+        let scope = if title_only {
+            SearchScope::Title
+        } else if content_only {
+            SearchScope::Content
+        } else {
+            SearchScope::All
+        };
+        let request = SearchRequest {
+            mode,
+            scope,
+            include_archived,
+            filter: NoteFilter {
+                exclude_tags,
+                ..date_filter(dates)?
+            },
+            structure: parse_structure_filters(&has)?,
+            hide_excluded_by_policy: !all,
+            offset,
+            limit: (limit > 0).then_some(limit),
+        };
+        let storage = self.note_storage.lock().await.clone();
+        let SearchResults {
+            total,
+            hidden,
+            hits,
+        } = storage.search_notes_filtered(&query, &request)?;
+
+        // Display results according to format, with where each one matched
+        // and, in JSON or when asked for, how well
+        let mut results = Vec::with_capacity(hits.len());
+        let mut matches = HashMap::with_capacity(hits.len());
+        for hit in hits {
+            let score = SearchScore {
+                score: hit.score,
+                fields: hit.matched_fields,
+            };
+            let result_match = ResultMatch {
+                snippet: search_snippet_with_mode(&hit.note, &query, mode, scope),
+                score: (show_scores || format == "json").then_some(score),
+            };
+            matches.insert(hit.note.id.clone(), result_match);
+            results.push(hit.note);
         }
-
-        // Display results according to format
-        match format.as_str() {
-            "json" => self.display_notes_json(&results, include_content)?,
-            _ => self.display_notes_text(&results, include_content)?,
+        if interactive && !results.is_empty() {
+            if stdin().is_terminal() && std::io::stderr().is_terminal() {
+                return self.pick_search_result(&results, &matches, &then).await;
+            }
+            eprintln!("Warning: --interactive needs a terminal; printing the results instead");
         }
-
-        // Report total count
-        if !results.is_empty() {
-            if limit > 0 && results.len() == limit {
-                println!(
-                    "\nShowing {} of many matching results. Use --limit to show more.",
-                    results.len()
-                );
-            } else {
-                println!("\nFound {} matching notes.", results.len());
+        if format == "json" {
+            let notes = self
+                .notes_json(&results, include_content, Some(&matches))
+                .await?;
+            let page = serde_json::json!({
+                "total": total,
+                "offset": offset,
+                "limit": request.limit,
+                "hidden": hidden,
+                "notes": notes,
+            });
+            println!("{}", serde_json::to_string_pretty(&page)?);
+            return Ok(());
+        }
+        self.display_notes_text(&results, include_content, Some(&matches))?;
+
+        // Report which part of the matches was shown
+        if results.is_empty() && total > 0 {
+            println!(
+                "No results past the first {}: the query matched {} notes.",
+                offset, total
+            );
+        } else if results.len() < total {
+            let last = offset + results.len();
+            println!(
+                "\nShowing {}–{} of {} matching notes.",
+                offset + 1,
+                last,
+                total
+            );
+            if last < total {
+                println!("Use --offset {} to see the next ones.", last);
             }
+        } else if total > 0 {
+            println!("\nFound {} matching notes.", total);
         } else {
             println!("No notes found matching query: \"{}\"", query);
         }
+        if hidden > 0 {
+            println!(
+                "{} more matching note(s) hidden by tag policies. Use --all to include them.",
+                hidden
+            );
+        }
 
         Ok(())
     }
 
-    async fn handle_edit(&self, options: EditNoteOptions) -> Result<()> {
-        // Validate input - check for conflicting options
-        if options.content.is_some() && options.file.is_some() {
-            return Err(KbError::ApplicationError {
-                message: "Cannot specify both --content and --file options".to_string(),
-            });
-        }
+    /// Lets the user pick one of the search results on the terminal, then
+    /// views or edits it as `action` says
+    ///
+    /// Leaving the picker without a choice fails with
+    /// `KbError::ApplicationError`, so the command exits with status 1.
+    async fn pick_search_result(
+        &self,
+        results: &[Note],
+        matches: &HashMap<String, ResultMatch>,
+        action: &str,
+    ) -> Result<()> {
+        let items: Vec<PickerItem> = results
+            .iter()
+            .map(|note| {
+                let mut label = note.title.clone();
+                if !note.tags.is_empty() {
+                    label.push_str(&format!(" [{}]", note.tags.join(", ")));
+                }
+                // The matching line when there is one, else the start of the note
+                let mut preview: Vec<String> = matches
+                    .get(&note.id)
+                    .and_then(|result_match| result_match.snippet.as_ref())
+                    .map(|snippet| vec![snippet.highlighted()])
+                    .unwrap_or_default();
+                preview.extend(
+                    note.content
+                        .lines()
+                        .filter(|line| !line.trim().is_empty())
+                        .map(str::to_string),
+                );
+                PickerItem { label, preview }
+            })
+            .collect();
 
-        if options.content.is_some() && options.open_editor {
+        let term = console::Term::stderr();
+        let choice = pick(&term, "Open a note", &items).map_err(KbError::Io)?;
+        let Some(index) = choice else {
             return Err(KbError::ApplicationError {
-                message: "Cannot specify both --content and --edit options".to_string(),
+                message: "No note selected".to_string(),
             });
+        };
+        let id = results[index].id.clone();
+        if action == "edit" {
+            self.edit_content_in_editor(&id).await
+        } else {
+            self.handle_view(id, false, false, None, None, false).await
         }
+    }
 
-        if options.file.is_some() && options.open_editor {
-            return Err(KbError::ApplicationError {
-                message: "Cannot specify both --file and --edit options".to_string(),
-            });
+    /// Resolves the note given by a reference or, with `--title`, by title
+    ///
+    /// When several notes share the title they are listed with their IDs; on
+    /// a terminal the user picks one, otherwise this fails with
+    /// `KbError::AmbiguousReference`.
+    async fn select_note_id(&self, id: Option<String>, title: Option<String>) -> Result<String> {
+        let title = match (id, title) {
+            (Some(id), _) => return self.note_storage.lock().await.resolve_note_id(&id),
+            (None, Some(title)) => title,
+            (None, None) => {
+                return Err(KbError::InvalidArgument {
+                    message: "Specify a note ID or --title".to_string(),
+                })
+            }
+        };
+
+        let mut notes = self.note_storage.lock().await.find_by_title(&title)?;
+        match notes.len() {
+            0 => Err(KbError::NoteNotFound { id: title }),
+            1 => Ok(notes.remove(0).id),
+            count => {
+                eprintln!("{} notes are titled '{}':", count, title);
+                for (i, note) in notes.iter().enumerate() {
+                    let tags = if note.tags.is_empty() {
+                        String::new()
+                    } else {
+                        format!(", tags: {}", note.tags.join(", "))
+                    };
+                    eprintln!(
+                        "  {}. {} (created {}{})",
+                        i + 1,
+                        note.id,
+                        note.created_at.format("%Y-%m-%d %H:%M"),
+                        tags
+                    );
+                }
+
+                if stdin().is_terminal() && stdout().is_terminal() {
+                    eprint!("Select a note [1-{}]: ", count);
+                    std::io::stderr().flush().map_err(KbError::Io)?;
+                    let mut input = String::new();
+                    stdin().read_line(&mut input).map_err(KbError::Io)?;
+                    return match input.trim().parse::<usize>() {
+                        Ok(choice) if (1..=count).contains(&choice) => {
+                            Ok(notes.swap_remove(choice - 1).id)
+                        }
+                        _ => Err(KbError::ApplicationError {
+                            message: "No note selected".to_string(),
+                        }),
+                    };
+                }
+
+                Err(KbError::AmbiguousReference {
+                    reference: title,
+                    candidates: notes.into_iter().map(|note| note.id).collect(),
+                })
+            }
+        }
+    }
+
+    /// Display a single note, optionally editing its content first
+    async fn handle_view(
+        &self,
+        id: String,
+        json: bool,
+        edit: bool,
+        section: Option<String>,
+        lines: Option<String>,
+        no_pager: bool,
+    ) -> Result<()> {
+        // Reject a bad range before opening an editor
+        let lines: Option<LineRange> = lines.map(|spec| spec.parse()).transpose()?;
+
+        let id = self.note_storage.lock().await.resolve_note_id(&id)?;
+
+        if edit {
+            self.edit_content_in_editor(&id).await?;
+        }
+
+        let note = {
+            let storage = self.note_storage.lock().await;
+            let note = storage
+                .get_note(&id)
+                .ok_or(KbError::NoteNotFound { id: id.clone() })?;
+            storage.record_note_view(&note.id)?;
+            note
+        };
+
+        if json {
+            let mut value = serde_json::to_value(&note)?;
+            value["stats"] = serde_json::to_value(note.stats())?;
+            println!("{}", serde_json::to_string_pretty(&value)?);
+            return Ok(());
+        }
+
+        let body = match (&section, &lines) {
+            (Some(name), _) => find_section(&note.content, name)?,
+            (_, Some(range)) => range.slice(&note.content),
+            _ => note.content.as_str(),
+        };
+
+        let mut header = vec![console::style(&note.title).bold().to_string()];
+        header.push(format!("ID:      {}", note.id));
+        if !note.tags.is_empty() {
+            let tags = note
+                .tags
+                .iter()
+                .map(|tag| format!("#{}", tag))
+                .collect::<Vec<_>>()
+                .join(" ");
+            header.push(format!("Tags:    {}", console::style(tags).cyan()));
+        }
+        if !note.aliases.is_empty() {
+            header.push(format!("Aliases: {}", note.aliases.join(", ")));
+        }
+        header.push(format!(
+            "Created: {}",
+            note.created_at.format("%Y-%m-%d %H:%M:%S")
+        ));
+        header.push(format!(
+            "Updated: {}",
+            note.updated_at.format("%Y-%m-%d %H:%M:%S")
+        ));
+        if let Some(due_at) = note.due_at {
+            header.push(format!("Due:     {}", format_due_date(due_at)));
+        }
+        if let Some(notebook) = &note.notebook {
+            header.push(format!("Notebook: {}", notebook));
+        }
+        if note.locked {
+            header.push(format!(
+                "Locked:  {}",
+                console::style("yes, unlock to edit").red()
+            ));
+        }
+        header.push(format!("Stats:   {}", note.stats().summary()));
+        if !note.attachments.is_empty() {
+            header.push("Attachments:".to_string());
+            let storage = self.note_storage.lock().await;
+            for attachment in &note.attachments {
+                header.push(format!(
+                    "  {} ({}, {}) {}",
+                    attachment.filename,
+                    attachment.mime_type,
+                    format_size(attachment.size),
+                    console::style(storage.attachment_file_path(attachment).display()).dim()
+                ));
+            }
+        }
+        if let Some(name) = &section {
+            header.push(format!("Section: {}", name));
+        }
+
+        let backlinks = self.note_storage.lock().await.get_backlinks(&note.id)?;
+        let mut footer = Vec::new();
+        if !backlinks.is_empty() {
+            footer.push(console::style("Linked from:").bold().to_string());
+            for source in &backlinks {
+                footer.push(format!(
+                    "  {} {}",
+                    source.title,
+                    console::style(format!("({})", source.id)).dim()
+                ));
+            }
+        }
+
+        // Page content that doesn't fit on one screen
+        let height = terminal_size::terminal_size().map(|(_, h)| h.0 as usize);
+        let pager = match height {
+            Some(height) if !no_pager && stdout().is_terminal() => {
+                let footer_lines = if footer.is_empty() {
+                    0
+                } else {
+                    footer.len() + 1
+                };
+                let total = header.len() + 1 + body.lines().count() + footer_lines;
+                (total >= height).then(spawn_pager).flatten()
+            }
+            _ => None,
+        };
+
+        match pager {
+            Some(mut pager) => {
+                if let Some(mut input) = pager.stdin.take() {
+                    write_view(&mut input, &header, body, &footer)?;
+                }
+                pager.wait().map_err(KbError::Io)?;
+            }
+            None => write_view(&mut stdout().lock(), &header, body, &footer)?,
+        }
+
+        Ok(())
+    }
+
+    /// Print the length statistics of a note
+    async fn handle_stats(&self, id: String, json: bool) -> Result<()> {
+        let note = {
+            let storage = self.note_storage.lock().await;
+            let id = storage.resolve_note_id(&id)?;
+            storage.get_note(&id).ok_or(KbError::NoteNotFound { id })?
+        };
+        let stats = note.stats();
+
+        if json {
+            let mut value = serde_json::to_value(stats)?;
+            value["id"] = serde_json::json!(note.id);
+            println!("{}", serde_json::to_string_pretty(&value)?);
+            return Ok(());
+        }
+
+        println!("{}", console::style(&note.title).bold());
+        println!("ID:           {}", note.id);
+        println!("Words:        {}", stats.words);
+        println!("Characters:   {}", stats.characters);
+        println!("Lines:        {}", stats.lines);
+        println!("Headings:     {}", stats.headings);
+        println!("Reading time: {} min", stats.reading_minutes);
+        Ok(())
+    }
+
+    /// Print aggregate statistics of the whole vault
+    async fn handle_vault_stats(&self, json: bool) -> Result<()> {
+        let stats = self.note_storage.lock().await.get_statistics()?;
+
+        if json {
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+            return Ok(());
+        }
+
+        println!(
+            "Notes:    {} ({} untagged, {} unread)",
+            stats.total_notes, stats.untagged_notes, stats.unread_notes
+        );
+        println!("Content:  {}", format_size(stats.content_bytes));
+        println!("Backups:  {}", format_size(stats.backup_bytes));
+
+        if !stats.notes_per_tag.is_empty() {
+            println!("\n{}", console::style("Notes per tag").bold());
+            let width = stats
+                .notes_per_tag
+                .iter()
+                .map(|count| count.tag.chars().count())
+                .max()
+                .unwrap_or(0);
+            for count in &stats.notes_per_tag {
+                println!("  {:width$}  {}", count.tag, count.notes, width = width);
+            }
+        }
+
+        if !stats.notes_per_month.is_empty() {
+            println!("\n{}", console::style("Notes created per month").bold());
+            for count in &stats.notes_per_month {
+                println!("  {}  {}", count.month, count.notes);
+            }
+        }
+
+        if !stats.largest_notes.is_empty() {
+            println!("\n{}", console::style("Largest notes").bold());
+            for size in &stats.largest_notes {
+                println!(
+                    "  {:>9}  {} ({})",
+                    format_size(size.bytes),
+                    size.title,
+                    size.id
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_edit(&self, options: EditNoteOptions) -> Result<()> {
+        // Validate input - check for conflicting options
+        if options.content.is_some() && options.file.is_some() {
+            return Err(KbError::ApplicationError {
+                message: "Cannot specify both --content and --file options".to_string(),
+            });
+        }
+
+        if options.content.is_some() && options.open_editor {
+            return Err(KbError::ApplicationError {
+                message: "Cannot specify both --content and --edit options".to_string(),
+            });
+        }
+
+        if options.file.is_some() && options.open_editor {
+            return Err(KbError::ApplicationError {
+                message: "Cannot specify both --file and --edit options".to_string(),
+            });
         }
 
+        // Reject malformed assignments before anything is changed
+        let set_meta = options
+            .set_meta
+            .iter()
+            .map(|assignment| parse_meta_assignment(assignment))
+            .collect::<Result<Vec<_>>>()?;
+        let due_at = options
+            .due
+            .as_deref()
+            .map(|value| parse_due_date(value, chrono::Local::now().date_naive()))
+            .transpose()?;
+        let notebook = options
+            .notebook
+            .as_deref()
+            .map(validate_notebook_path)
+            .transpose()?;
+
         // Retrieve the existing note
+        let id = self
+            .select_note_id(options.id.clone(), options.by_title.clone())
+            .await?;
         let mut note = self
             .note_storage
             .lock()
             .await
-            .clone()
-            .get_note(&options.id)
-            .unwrap();
+            .get_note(&id)
+            .ok_or(KbError::NoteNotFound { id })?;
+
+        // Refuse before an editor is opened on a locked note
+        if note.locked && !options.force {
+            return Err(KbError::NoteLocked { id: note.id });
+        }
 
-        // Update title if provided
+        // Update title if provided, keeping the old title resolvable as an alias
         if let Some(new_title) = options.title {
+            if new_title != note.title
+                && !note
+                    .aliases
+                    .iter()
+                    .any(|alias| normalize_alias(alias) == normalize_alias(&note.title))
+            {
+                note.aliases.push(note.title.clone());
+            }
             note.title = new_title;
         }
 
@@ -565,80 +1890,1946 @@ impl App {
             note.tags.retain(|tag| !remove.contains(tag));
         }
 
-        // Update the note's last modified time
-        note.updated_at = chrono::Utc::now();
+        // Handle metadata updates, removals last so `--set-meta k=v --unset-meta k` removes k
+        for (key, value) in set_meta {
+            note.metadata.insert(key, value);
+        }
+        for key in &options.unset_meta {
+            if note.metadata.remove(key.trim()).is_none() {
+                println!("Note {} has no metadata entry '{}'", note.id, key.trim());
+            }
+        }
+
+        if due_at.is_some() {
+            note.due_at = due_at;
+        } else if options.clear_due {
+            note.due_at = None;
+        }
+
+        if notebook.is_some() {
+            note.notebook = notebook;
+        } else if options.clear_notebook {
+            note.notebook = None;
+        }
+
+        // Update the note's last modified time
+        note.updated_at = chrono::Utc::now();
+
+        // Save the updated note
+        let storage = self.note_storage.lock().await;
+        if options.force {
+            storage.force_update_note(note.clone())?;
+        } else {
+            storage.update_note(note.clone())?;
+        }
+        drop(storage);
+
+        println!("Note {} updated successfully", note.id);
+
+        self.print_link_suggestions(&note.id).await;
+
+        Ok(())
+    }
+
+    // Helper function for reading content from file (reuse from create command)
+    fn read_content_from_file(&self, file_path: &str) -> Result<String> {
+        let path = Path::new(file_path);
+
+        if !path.exists() {
+            return Err(KbError::ApplicationError {
+                message: format!("File not found: {}", file_path),
+            });
+        }
+
+        if !path.is_file() {
+            return Err(KbError::ApplicationError {
+                message: format!("Not a file: {}", file_path),
+            });
+        }
+
+        read_to_string(path).map_err(KbError::Io)
+    }
+
+    /// Opens the configured editor on existing content and returns the result
+    ///
+    /// The title heading and hint written above the content are removed again,
+    /// so content the user didn't touch comes back unchanged.
+    fn open_editor_with_content(&self, title: &str, existing_content: &str) -> Result<String> {
+        let temp_file = Builder::new().suffix(".md").tempfile()?;
+        let temp_path = temp_file.path().to_path_buf();
+
+        let preamble = format!("# {}\n<!-- Edit your note below this line -->\n\n", title);
+        let mut temp_file = OpenOptions::new().write(true).open(&temp_path)?;
+        write!(temp_file, "{}{}", preamble, existing_content)?;
+        drop(temp_file);
+
+        self.launch_editor(&self.config.get_editor_command(), &temp_path)?;
+
+        let content = read_to_string(&temp_path).map_err(KbError::Io)?;
+        let content = content.strip_prefix(&preamble).unwrap_or(&content);
+        // Most editors end the file with a newline the content may not have had
+        let content = match content.strip_suffix('\n') {
+            Some(trimmed) if !existing_content.ends_with('\n') => trimmed,
+            _ => content,
+        };
+        Ok(content.to_string())
+    }
+
+    /// Edits a note's content in the editor and saves it if it changed
+    ///
+    /// The save fails if the note was modified while the editor was open. The
+    /// user can then reopen the editor on their version (after seeing what
+    /// changed) or give up, in which case their text is kept in a file.
+    async fn edit_content_in_editor(&self, id: &str) -> Result<()> {
+        let (mut note, mut version) = self
+            .note_storage
+            .lock()
+            .await
+            .get_note_with_version(id)
+            .ok_or_else(|| KbError::NoteNotFound { id: id.to_string() })?;
+        if note.locked {
+            return Err(KbError::NoteLocked { id: note.id });
+        }
+        let mut original = note.content.clone();
+        let mut draft = note.content.clone();
+
+        loop {
+            draft = self.open_editor_with_content(&note.title, &draft)?;
+            if draft == note.content {
+                println!("No changes made to note {}", note.id);
+                return Ok(());
+            }
+
+            let mut updated = note.clone();
+            updated.content = draft.clone();
+            updated.updated_at = Utc::now();
+
+            let saved = self
+                .note_storage
+                .lock()
+                .await
+                .update_note_with_version(updated, version);
+            match saved {
+                Ok(()) => {
+                    println!("Note {} updated successfully", note.id);
+                    self.print_link_suggestions(&note.id).await;
+                    return Ok(());
+                }
+                Err(KbError::ConcurrentModification {
+                    expected_timestamp,
+                    actual_timestamp,
+                    ..
+                }) => {
+                    println!(
+                        "{} Note {} was modified while you were editing it",
+                        console::style("!").red(),
+                        note.id
+                    );
+                    println!("  Opened at version:  {}", expected_timestamp.to_rfc3339());
+                    println!("  Current version:    {}", actual_timestamp.to_rfc3339());
+
+                    let latest = self.note_storage.lock().await.get_note_with_version(id);
+                    let Some((latest, latest_version)) = latest else {
+                        return Err(KbError::NoteNotFound { id: id.to_string() });
+                    };
+                    print!(
+                        "{}",
+                        render_unified_diff(&original, &latest.content, "opened", "current")
+                    );
+
+                    if !confirm("Reopen the editor with your changes to retry? [y/N] ")? {
+                        let kept = Builder::new()
+                            .prefix("kbnotes-edit-")
+                            .suffix(".md")
+                            .tempfile()?;
+                        std::fs::write(kept.path(), &draft)?;
+                        let (_, path) = kept.keep().map_err(|e| KbError::Io(e.error))?;
+                        println!("Edit aborted, your text was saved to {}", path.display());
+                        return Ok(());
+                    }
+
+                    // Retry against the current version, starting from the
+                    // user's text so nothing they wrote is lost
+                    original = latest.content.clone();
+                    note = latest;
+                    version = latest_version;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Add or remove tags of a note, listing the resulting tags when requested
+    async fn handle_tag(
+        &self,
+        id: String,
+        add: Option<String>,
+        remove: Option<String>,
+        list: bool,
+        force: bool,
+    ) -> Result<()> {
+        // Without changes to make, just show the tags
+        let list = list || (add.is_none() && remove.is_none());
+
+        let storage = self.note_storage.lock().await;
+        let id = storage.resolve_note_id(&id)?;
+        let mut note = storage
+            .get_note(&id)
+            .ok_or(KbError::NoteNotFound { id: id.clone() })?;
+
+        // Tags are matched like get_notes_by_tag does, ignoring case and
+        // Unicode normalization
+        let (added, removed) = apply_tag_changes(&mut note, &parse_tags(add), &parse_tags(remove));
+
+        if added > 0 || removed > 0 {
+            note.updated_at = chrono::Utc::now();
+            if force {
+                storage.force_update_note(note.clone())?;
+            } else {
+                storage.update_note(note.clone())?;
+            }
+            println!(
+                "Note {} updated: {} tag(s) added, {} tag(s) removed",
+                note.id, added, removed
+            );
+        } else if !list {
+            println!("Note {} already has the requested tags", note.id);
+        }
+
+        if list {
+            for tag in &note.tags {
+                println!("{}", tag);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handle adding and removing tags on every note carrying one of
+    /// `filter_tags`, after confirmation unless `force` is set
+    async fn handle_bulk_tag(
+        &self,
+        filter_tags: Vec<String>,
+        add: Option<String>,
+        remove: Option<String>,
+        force: bool,
+    ) -> Result<()> {
+        let (add, remove) = (parse_tags(add), parse_tags(remove));
+        if add.is_empty() && remove.is_empty() {
+            return Err(KbError::InvalidArgument {
+                message: "--filter-tag needs tags to --add or --remove".to_string(),
+            });
+        }
+
+        let filter = NoteFilter {
+            tags: filter_tags,
+            ..NoteFilter::default()
+        };
+        let storage = self.note_storage.lock().await;
+        let mut notes = storage.query_notes(filter.clone())?;
+        if notes.is_empty() {
+            println!("No notes carry the given tags.");
+            return Ok(());
+        }
+        notes.sort_by(|a, b| a.title.cmp(&b.title).then_with(|| a.id.cmp(&b.id)));
+        if !force && !confirm_bulk("Retag", &notes)? {
+            println!("Tag changes cancelled.");
+            return Ok(());
+        }
+
+        let report = storage.retag_notes(filter, &add, &remove, force)?;
+        println!("Updated {} note(s)", report.succeeded.len());
+        if !report.unchanged.is_empty() {
+            println!(
+                "{} note(s) already had the requested tags",
+                report.unchanged.len()
+            );
+        }
+        batch_result("updated", &report)
+    }
+
+    /// Handle alias add/remove/list operations
+    async fn handle_alias(&self, action: AliasCommand) -> Result<()> {
+        let storage = self.note_storage.lock().await;
+
+        match action {
+            AliasCommand::Add { id, alias } => {
+                let id = storage.resolve_note_id(&id)?;
+                let mut note = storage
+                    .get_note(&id)
+                    .ok_or(KbError::NoteNotFound { id })?;
+
+                let alias = alias.trim().to_string();
+                if alias.is_empty() {
+                    return Err(KbError::ApplicationError {
+                        message: "Alias cannot be empty".to_string(),
+                    });
+                }
+
+                // Refuse aliases that already point somewhere else
+                match storage.resolve_note_id(&alias) {
+                    Ok(existing) if existing != note.id => {
+                        return Err(KbError::ApplicationError {
+                            message: format!(
+                                "Alias '{}' already refers to note {}",
+                                alias, existing
+                            ),
+                        });
+                    }
+                    Err(KbError::AmbiguousReference { candidates, .. }) => {
+                        return Err(KbError::ApplicationError {
+                            message: format!(
+                                "Alias '{}' already refers to notes {}",
+                                alias,
+                                candidates.join(", ")
+                            ),
+                        });
+                    }
+                    _ => {}
+                }
+
+                if note
+                    .aliases
+                    .iter()
+                    .any(|existing| normalize_alias(existing) == normalize_alias(&alias))
+                {
+                    println!("Note {} already has alias '{}'", note.id, alias);
+                    return Ok(());
+                }
+
+                note.aliases.push(alias.clone());
+                note.updated_at = chrono::Utc::now();
+                storage.update_note(note.clone())?;
+                println!("Added alias '{}' to note {}", alias, note.id);
+            }
+
+            AliasCommand::Remove { id, alias } => {
+                let id = storage.resolve_note_id(&id)?;
+                let mut note = storage
+                    .get_note(&id)
+                    .ok_or(KbError::NoteNotFound { id })?;
+
+                let before = note.aliases.len();
+                note.aliases
+                    .retain(|existing| normalize_alias(existing) != normalize_alias(&alias));
+
+                if note.aliases.len() == before {
+                    println!("Note {} has no alias '{}'", note.id, alias);
+                    return Ok(());
+                }
+
+                note.updated_at = chrono::Utc::now();
+                storage.update_note(note.clone())?;
+                println!("Removed alias '{}' from note {}", alias, note.id);
+            }
+
+            AliasCommand::List { id } => {
+                let id = storage.resolve_note_id(&id)?;
+                let note = storage
+                    .get_note(&id)
+                    .ok_or(KbError::NoteNotFound { id })?;
+
+                if note.aliases.is_empty() {
+                    println!("Note {} has no aliases", note.id);
+                } else {
+                    for alias in &note.aliases {
+                        println!("{}", alias);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handle snapshot create/list/diff/restore operations
+    /// List the revisions of a note, or restore one
+    async fn handle_history(
+        &self,
+        id: Option<String>,
+        action: Option<HistoryCommand>,
+    ) -> Result<()> {
+        let storage = self.note_storage.lock().await;
+
+        if let Some(HistoryCommand::Restore { id, timestamp }) = action {
+            // The live note may be gone, so fall back to the raw ID
+            let id = storage.resolve_note_id(&id).unwrap_or(id);
+            let note = storage.restore_revision(&id, timestamp)?;
+            println!(
+                "Restored note '{}' ({}) to revision {}",
+                note.title, note.id, timestamp
+            );
+            return Ok(());
+        }
+
+        let id = id.ok_or_else(|| KbError::InvalidArgument {
+            message: "Missing note ID".to_string(),
+        })?;
+        let id = storage.resolve_note_id(&id).unwrap_or(id);
+        let revisions = storage.list_revisions(&id)?;
+        let live = storage.get_note(&id);
+        if revisions.is_empty() {
+            if live.is_none() {
+                return Err(KbError::NoteNotFound { id });
+            }
+            println!("Note {} has no revisions yet", id);
+            return Ok(());
+        }
+
+        // Newest first, each with the change in length from the version before
+        let lengths: Vec<usize> = revisions
+            .iter()
+            .map(|revision| revision.note.content.chars().count())
+            .collect();
+        let delta = |length: usize, previous: Option<usize>| match previous {
+            Some(previous) => format!("{:+}", length as i64 - previous as i64),
+            None => String::new(),
+        };
+
+        if let Some(live) = &live {
+            let length = live.content.chars().count();
+            println!(
+                "{:<13}  {}  {:>7} chars  {}",
+                console::style("current").bold(),
+                live.updated_at.format("%Y-%m-%d %H:%M:%S"),
+                length,
+                delta(length, lengths.last().copied())
+            );
+        }
+        for (i, revision) in revisions.iter().enumerate().rev() {
+            println!(
+                "{:<13}  {}  {:>7} chars  {}",
+                revision.timestamp,
+                revision.note.updated_at.format("%Y-%m-%d %H:%M:%S"),
+                lengths[i],
+                delta(lengths[i], i.checked_sub(1).map(|j| lengths[j]))
+            );
+        }
+        println!(
+            "\nRestore one with: kbnotes history restore {} <timestamp>",
+            id
+        );
+        Ok(())
+    }
+
+    async fn handle_snapshot(&self, action: SnapshotCommand) -> Result<()> {
+        let storage = self.note_storage.lock().await;
+
+        match action {
+            SnapshotCommand::Create { id, name } => {
+                let id = storage.resolve_note_id(&id)?;
+                let snapshot = storage.create_snapshot(&id, &name)?;
+                println!(
+                    "Snapshot '{}' of note {} created at {}",
+                    snapshot.name,
+                    id,
+                    snapshot.created_at.format("%Y-%m-%d %H:%M:%S")
+                );
+            }
+
+            SnapshotCommand::List { id } => {
+                let id = storage.resolve_note_id(&id)?;
+                let snapshots = storage.list_snapshots(&id)?;
+
+                if snapshots.is_empty() {
+                    println!("Note {} has no snapshots", id);
+                } else {
+                    for snapshot in &snapshots {
+                        println!(
+                            "{}  {}  ({} characters)",
+                            snapshot.created_at.format("%Y-%m-%d %H:%M:%S"),
+                            console::style(&snapshot.name).bold(),
+                            snapshot.note.content.chars().count()
+                        );
+                    }
+                }
+            }
+
+            SnapshotCommand::Diff { id, name } => {
+                let id = storage.resolve_note_id(&id)?;
+                let snapshot = storage.get_snapshot(&id, &name)?;
+                let live = storage
+                    .get_note(&id)
+                    .ok_or_else(|| KbError::NoteNotFound { id: id.clone() })?;
+
+                let old = format!("# {}\n\n{}", snapshot.note.title, snapshot.note.content);
+                let new = format!("# {}\n\n{}", live.title, live.content);
+
+                if old == new {
+                    println!("Note {} is identical to snapshot '{}'", id, name);
+                } else {
+                    print!(
+                        "{}",
+                        render_unified_diff(&old, &new, &format!("snapshot/{}", name), "live")
+                    );
+                }
+            }
+
+            SnapshotCommand::Restore { id, name, as_copy } => {
+                // The live note may be gone, so fall back to the raw ID
+                let id = storage.resolve_note_id(&id).unwrap_or(id);
+                let note = storage.restore_snapshot(&id, &name, as_copy)?;
+
+                if as_copy {
+                    println!(
+                        "Snapshot '{}' restored as new note '{}' ({})",
+                        name, note.title, note.id
+                    );
+                } else {
+                    println!("Snapshot '{}' restored into note {}", name, note.id);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create a full backup, in the backup directory or at `output`, or an
+    /// incremental one in the backup directory
+    async fn handle_backup(&self, output: Option<PathBuf>, incremental: bool) -> Result<()> {
+        let (backup_path, manifest) = {
+            let storage = self.note_storage.lock().await;
+            match output {
+                Some(output) => {
+                    let manifest = storage.create_full_backup_to(&output)?;
+                    (output, Some(manifest))
+                }
+                None => {
+                    let backup_path = if incremental {
+                        storage.create_incremental_backup()?
+                    } else {
+                        storage.create_full_backup()?
+                    };
+                    let manifest = storage.read_backup_manifest(&backup_path)?;
+                    (backup_path, manifest)
+                }
+            }
+        };
+
+        let size = std::fs::metadata(&backup_path)?.len();
+        println!("Backup created: {}", backup_path.display());
+        if let Some(manifest) = manifest {
+            println!("Notes:          {}", manifest.notes);
+            if let Some(chain) = &manifest.incremental {
+                println!("Changed since:  {}", chain.since.to_rfc3339());
+                println!("Builds on:      {}", chain.base);
+            }
+            if !manifest.excluded_notes.is_empty() {
+                println!(
+                    "Excluded:       {} (by tag policy)",
+                    manifest.excluded_notes.len()
+                );
+            }
+        }
+        println!("Size:           {}", format_size(size));
+        Ok(())
+    }
+
+    /// Remove old per-note backups from the backup directory
+    async fn handle_backup_prune(&self, older_than: Option<String>) -> Result<()> {
+        let orphan_age = match older_than {
+            Some(age) => Some(parse_age(&age)?),
+            None => self
+                .config
+                .orphan_backup_retention_days
+                .map(|days| chrono::Duration::days(days.into())),
+        };
+        let report = self.note_storage.lock().await.prune_backups(orphan_age)?;
+
+        if self.verbose {
+            for path in report.pruned.iter().chain(&report.orphaned) {
+                println!("Removed {}", path.display());
+            }
+        }
+        if report.moved > 0 {
+            println!(
+                "Moved {} backup(s) into their note's directory",
+                report.moved
+            );
+        }
+        println!(
+            "Removed {} backup(s) beyond max_note_backups ({} per note)",
+            report.pruned.len(),
+            self.config.max_note_backups
+        );
+        match orphan_age {
+            Some(_) => println!(
+                "Removed {} backup(s) and deletion record(s) of deleted notes",
+                report.orphaned.len()
+            ),
+            None => println!(
+                "Kept the backups of deleted notes (orphan_backup_retention_days is unset)"
+            ),
+        }
+        Ok(())
+    }
+
+    /// List the backups in the backup directory, newest first
+    async fn handle_backup_list(&self, format: String, no_count: bool) -> Result<()> {
+        let inventory = self.note_storage.lock().await.list_backups(!no_count)?;
+
+        if format == "json" {
+            println!("{}", serde_json::to_string_pretty(&inventory)?);
+            return Ok(());
+        }
+
+        if inventory.archives.is_empty() {
+            println!("No backup archives in {}", self.config.backup_dir.display());
+        } else {
+            println!("{}", console::style("Backup archives").bold());
+            // Incremental backups are shown under the full backup they build on
+            let print_archive = |archive: &ArchiveBackupInfo, indent: &str| {
+                let notes = archive
+                    .notes
+                    .map(|notes| format!("{} notes", notes))
+                    .unwrap_or_default();
+                println!(
+                    "{}{}  {:>10}  {:>11}  {}",
+                    indent,
+                    archive.created_at.format("%Y-%m-%d %H:%M:%S"),
+                    format_size(archive.size),
+                    notes,
+                    archive.path.display()
+                );
+            };
+            let bases: HashSet<&PathBuf> = inventory
+                .archives
+                .iter()
+                .filter(|archive| !archive.incremental)
+                .map(|archive| &archive.path)
+                .collect();
+            let orphaned = |archive: &ArchiveBackupInfo| {
+                archive.incremental
+                    && archive
+                        .base
+                        .as_ref()
+                        .is_none_or(|base| !bases.contains(base))
+            };
+            for archive in &inventory.archives {
+                if !archive.incremental {
+                    print_archive(archive, "  ");
+                    for increment in inventory
+                        .archives
+                        .iter()
+                        .filter(|other| other.base.as_ref() == Some(&archive.path))
+                    {
+                        print_archive(increment, "    + ");
+                    }
+                } else if orphaned(archive) {
+                    print_archive(archive, "  ? ");
+                }
+            }
+            if inventory.archives.iter().any(orphaned) {
+                println!("  (? marks incremental backups whose full backup is missing)");
+            }
+        }
+
+        if inventory.notes.is_empty() {
+            println!("No per-note backups");
+            return Ok(());
+        }
+        println!("{}", console::style("Per-note backups").bold());
+        for note in &inventory.notes {
+            let records = note
+                .backups
+                .iter()
+                .filter(|backup| backup.deletion_record)
+                .count();
+            print!(
+                "  {}  {}  {} backup(s)",
+                note.backups[0].created_at.format("%Y-%m-%d %H:%M:%S"),
+                note.note_id,
+                note.backups.len() - records
+            );
+            if records > 0 {
+                print!(", {} deletion record(s)", records);
+            }
+            println!();
+            if self.verbose {
+                for backup in &note.backups {
+                    println!(
+                        "      {}  {:>10}  {}",
+                        backup.created_at.format("%Y-%m-%d %H:%M:%S"),
+                        format_size(backup.size),
+                        backup.path.display()
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Compare a backup with the vault, listing the notes that differ
+    async fn handle_backup_diff(&self, backup_file: PathBuf, format: String) -> Result<()> {
+        let diff = self.note_storage.lock().await.diff_backup(&backup_file)?;
+
+        if format == "json" {
+            println!("{}", serde_json::to_string_pretty(&diff)?);
+            return Ok(());
+        }
+
+        println!("Backup: {}", diff.backup_file.display());
+        for (heading, entries) in [
+            ("Only in the backup", &diff.only_in_backup),
+            ("Only in the vault", &diff.only_in_vault),
+        ] {
+            println!(
+                "\n{}",
+                console::style(format!("{} ({})", heading, entries.len())).bold()
+            );
+            for entry in entries.iter() {
+                println!(
+                    "  {}  {}  {}",
+                    entry.updated_at.format("%Y-%m-%d %H:%M:%S"),
+                    entry.note_id,
+                    entry.title
+                );
+            }
+        }
+
+        println!(
+            "\n{}",
+            console::style(format!("Changed ({})", diff.changed.len())).bold()
+        );
+        for note in &diff.changed {
+            println!(
+                "  {}  {}  backup {} -> vault {}{}",
+                note.note_id,
+                note.title,
+                note.backup_updated_at.format("%Y-%m-%d %H:%M:%S"),
+                note.vault_updated_at.format("%Y-%m-%d %H:%M:%S"),
+                if note.content_changed {
+                    ""
+                } else {
+                    " (timestamps only)"
+                }
+            );
+        }
+
+        println!("\nUnchanged: {}", diff.unchanged);
+        for (note_id, error) in &diff.failed_notes {
+            println!(
+                "  {} {}: {}",
+                console::style("unreadable").red(),
+                note_id,
+                error
+            );
+        }
+        if !diff.changed.is_empty() {
+            println!("Use --show-content-diff <id> to see how a changed note differs.");
+        }
+        Ok(())
+    }
+
+    /// Print a unified diff of one note, from its backed-up version to the vault's
+    async fn handle_backup_note_diff(&self, backup_file: PathBuf, id: String) -> Result<()> {
+        let storage = self.note_storage.lock().await;
+        let backed_up = storage.read_backup_note(&backup_file, &id)?;
+        let current = storage.get_note(&id);
+        if backed_up.is_none() && current.is_none() {
+            return Err(KbError::NoteNotFound { id });
+        }
+
+        // A note missing on one side diffs against nothing
+        let text = |note: &Option<Note>| {
+            note.as_ref()
+                .map(|note| format!("# {}\n\n{}", note.title, note.content))
+                .unwrap_or_default()
+        };
+        let (old, new) = (text(&backed_up), text(&current));
+        if old == new {
+            println!(
+                "Note {} has the same content in the backup and the vault",
+                id
+            );
+        } else {
+            print!(
+                "{}",
+                render_unified_diff(
+                    &old,
+                    &new,
+                    &format!("backup/{}", id),
+                    &format!("vault/{}", id)
+                )
+            );
+        }
+        Ok(())
+    }
+
+    /// Print the backup scheduler's status and the newest backup on disk
+    async fn handle_backup_status(&self, format: String) -> Result<()> {
+        let (status, newest) = {
+            let storage = self.note_storage.lock().await;
+            let newest = storage.list_backups(false)?.archives.into_iter().next();
+            (storage.get_backup_status().await, newest)
+        };
+
+        if format == "json" {
+            let status = serde_json::json!({
+                "scheduler": status,
+                "newest_backup": newest,
+            });
+            println!("{}", serde_json::to_string_pretty(&status)?);
+            return Ok(());
+        }
+
+        let time = |time: Option<chrono::DateTime<chrono::Utc>>| {
+            time.map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+        };
+        println!(
+            "Scheduler:        {}",
+            if !status.is_running {
+                "stopped".to_string()
+            } else if !self.config.auto_backup {
+                "running, manual backups only (auto_backup is off)".to_string()
+            } else if let Some(schedule) = &self.config.backup_schedule {
+                format!("running, on schedule '{}' (UTC)", schedule)
+            } else {
+                format!("running, every {} hour(s)", self.config.backup_frequency)
+            }
+        );
+        println!(
+            "Next backup:      {}",
+            time(status.next_backup_time)
+                .map(|time| format!("{} UTC", time))
+                .unwrap_or_else(|| "not scheduled".to_string())
+        );
+        println!(
+            "Last run:         {}",
+            time(status.last_run_time).unwrap_or_else(|| "none this session".to_string())
+        );
+        match (time(status.last_backup_time), &status.last_backup_path) {
+            (Some(time), Some(path)) => {
+                println!("Last backup:      {} ({})", time, path.display())
+            }
+            _ => println!("Last backup:      none this session"),
+        }
+        if let Some(error) = &status.last_error {
+            println!("Last error:       {}", console::style(error).red());
+        }
+        if let (Some(caught_up), Some(missed)) = (
+            time(status.last_catch_up_time),
+            time(status.missed_backup_time),
+        ) {
+            println!(
+                "Catch-up:         {} (the backup due {} was missed)",
+                caught_up, missed
+            );
+        }
+        if !self.config.backup_targets.is_empty() {
+            println!(
+                "Last push:        {}",
+                time(status.last_push_time).unwrap_or_else(|| "none this session".to_string())
+            );
+            for error in &status.last_push_errors {
+                println!("Push error:       {}", console::style(error).red());
+            }
+        }
+        match newest {
+            Some(archive) => println!(
+                "Newest on disk:   {} ({})",
+                archive.created_at.format("%Y-%m-%d %H:%M:%S"),
+                archive.path.display()
+            ),
+            None => println!("Newest on disk:   none"),
+        }
+        Ok(())
+    }
+
+    /// Upload a backup to the configured backup targets
+    async fn handle_backup_push(&self, backup_file: PathBuf) -> Result<()> {
+        let results = self
+            .note_storage
+            .lock()
+            .await
+            .push_backup(&backup_file)
+            .await?;
+
+        println!("Pushing {}", backup_file.display());
+        for result in &results {
+            match &result.error {
+                None => {
+                    println!("  {} {}", console::style("ok").green(), result.target);
+                    for name in &result.pruned {
+                        println!("     pruned {}", name);
+                    }
+                }
+                Some(error) => println!(
+                    "  {} {}: {}",
+                    console::style("failed").red(),
+                    result.target,
+                    error
+                ),
+            }
+        }
+
+        let failed = results
+            .iter()
+            .filter(|result| result.error.is_some())
+            .count();
+        if failed > 0 {
+            return Err(KbError::BackupFailed {
+                message: format!("Pushing the backup failed on {} target(s)", failed),
+            });
+        }
+        Ok(())
+    }
+
+    /// Path to the newest full backup in the backup directory, for `restore --latest`
+    async fn latest_backup(&self) -> Result<PathBuf> {
+        let inventory = self.note_storage.lock().await.list_backups(false)?;
+        inventory
+            .archives
+            .into_iter()
+            .next()
+            .map(|archive| archive.path)
+            .ok_or_else(|| KbError::BackupFailed {
+                message: format!(
+                    "No full backups found in {}",
+                    self.config.backup_dir.display()
+                ),
+            })
+    }
+
+    /// Restore notes from a full backup after showing what it would change
+    async fn handle_restore(
+        &self,
+        backup_file: PathBuf,
+        force: bool,
+        staged: bool,
+        options: &RestoreOptions,
+    ) -> Result<()> {
+        let filter = &options.filter;
+        let preview = self
+            .note_storage
+            .lock()
+            .await
+            .preview_full_backup(&backup_file)?;
+
+        println!("Backup:         {}", preview.backup_file.display());
+        println!("Notes:          {}", preview.total_notes);
+        println!(
+            "Existing notes: {} ({})",
+            preview.existing_notes.len(),
+            if staged {
+                "the whole notes directory will be swapped out"
+            } else if options.overwrite || options.replace {
+                "will be replaced"
+            } else {
+                "will be kept"
+            }
+        );
+        if options.replace {
+            println!("Notes not in the backup will be moved to the trash");
+        }
+        if let Some(ids) = &filter.ids {
+            let mut ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+            ids.sort_unstable();
+            println!("Only IDs:       {}", ids.join(", "));
+        }
+        if !filter.tags.is_empty() {
+            println!("Only tags:      {}", filter.tags.join(", "));
+        }
+
+        if !force && !confirm("\nRestore this backup? [y/N]: ")? {
+            println!("Restore cancelled.");
+            return Ok(());
+        }
+
+        if staged {
+            let summary = self
+                .note_storage
+                .lock()
+                .await
+                .restore_full_backup_staged(&backup_file)
+                .await?;
+
+            println!(
+                "\nRestored {} note(s) from {}",
+                summary.notes_restored,
+                summary.backup_file.display()
+            );
+            println!(
+                "Previous notes directory kept at {}",
+                summary.previous_dir.display()
+            );
+            if !summary.excluded_notes.is_empty() {
+                println!(
+                    "{} note(s) excluded from the backup by tag policy are only in the previous directory",
+                    summary.excluded_notes.len()
+                );
+            }
+            for dir in &summary.pruned_dirs {
+                println!("Removed old directory {}", dir.display());
+            }
+            println!("Run `kbnotes restore --rollback` to switch back.");
+            return Ok(());
+        }
+
+        let summary = self
+            .note_storage
+            .lock()
+            .await
+            .restore_full_backup(&backup_file, options)?;
+
+        print!("\n{}", summary);
+        if summary.notes_removed > 0 {
+            println!("Notes missing from the backup were moved to the trash.");
+        }
+        restore_outcome(&summary)
+    }
+
+    /// Restore notes from a full backup and print the summary as JSON
+    async fn handle_restore_json(
+        &self,
+        backup_file: PathBuf,
+        options: &RestoreOptions,
+    ) -> Result<()> {
+        let summary = self
+            .note_storage
+            .lock()
+            .await
+            .restore_full_backup(&backup_file, options)?;
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+        restore_outcome(&summary)
+    }
+
+    /// Show what restoring a full backup would do, without writing anything
+    async fn handle_restore_dry_run(
+        &self,
+        backup_file: PathBuf,
+        options: &RestoreOptions,
+        format: &str,
+    ) -> Result<()> {
+        let summary = self
+            .note_storage
+            .lock()
+            .await
+            .restore_full_backup(&backup_file, options)?;
+
+        if format == "json" {
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+            return Ok(());
+        }
+
+        println!("Dry run of {}", summary.backup_file.display());
+        if !summary.actions.is_empty() {
+            println!("\n{:<9}  {:<36}  Title", "Action", "ID");
+            for action in &summary.actions {
+                println!(
+                    "{:<9}  {:<36}  {}",
+                    action.action,
+                    action.note_id,
+                    action.title.as_deref().unwrap_or("-")
+                );
+            }
+        }
+        for (note_id, error) in &summary.failed_notes {
+            println!(
+                "{:<9}  {:<36}  {}",
+                console::style("fail").red(),
+                note_id,
+                error
+            );
+        }
+        println!(
+            "\nWould restore {} of {} notes ({} skipped, {} filtered out, {} removed, {} failed). Nothing was written.",
+            summary.notes_restored,
+            summary.total_notes,
+            summary.notes_skipped,
+            summary.notes_filtered,
+            summary.notes_removed,
+            summary.failed_notes.len()
+        );
+        Ok(())
+    }
+
+    /// Switch back to the notes directory kept aside by the last staged restore
+    async fn handle_restore_rollback(&self, force: bool) -> Result<()> {
+        if !force
+            && !confirm(
+                "Replace the notes directory with the one kept by the last staged restore? [y/N]: ",
+            )?
+        {
+            println!("Rollback cancelled.");
+            return Ok(());
+        }
+
+        let summary = self.note_storage.lock().await.rollback_restore().await?;
+
+        println!(
+            "Rolled back to {} ({} notes)",
+            summary.restored_dir.display(),
+            summary.notes
+        );
+        println!(
+            "Notes directory before the rollback kept at {}",
+            summary.replaced_dir.display()
+        );
+        for dir in &summary.pruned_dirs {
+            println!("Removed old directory {}", dir.display());
+        }
+        Ok(())
+    }
+
+    /// Show the effective configuration, or update or reset the configuration file
+    async fn handle_config(&self, show: bool, set: Option<String>, reset: bool) -> Result<()> {
+        let config_path = &self.config_origin.path;
+        let modified = reset || set.is_some();
+
+        if reset {
+            save_config_to_file(&load_default_config()?, config_path)?;
+            println!("Reset configuration to defaults: {}", config_path.display());
+        }
+
+        if let Some(setting) = set {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| KbError::ConfigError {
+                    message: format!(
+                        "Expected key=value, got '{}'. Valid keys: {}",
+                        setting,
+                        Config::SETTABLE_KEYS.join(", ")
+                    ),
+                })?;
+            let key = key.trim();
+
+            // Start from the file rather than the effective configuration so
+            // command-line overrides are not persisted
+            let mut config = if config_path.exists() {
+                load_config_from_file(config_path)?.0
+            } else {
+                load_default_config()?
+            };
+            config.set_value(key, value)?;
+            save_config_to_file(&config, config_path)?;
+            println!(
+                "Set {} = {} in {}",
+                key,
+                value.trim(),
+                config_path.display()
+            );
+
+            if key == "id_policy" && config.id_policy != self.config.id_policy {
+                println!("Run `kbnotes doctor --fix` to move existing notes to the new layout");
+            }
+            if key == "storage_format" && config.storage_format != self.config.storage_format {
+                println!(
+                    "Run `kbnotes migrate-format --to {}` to convert existing notes",
+                    config.storage_format
+                );
+            }
+        }
+
+        if show || !modified {
+            println!(
+                "Configuration file: {}{}",
+                config_path.display(),
+                if config_path.exists() {
+                    ""
+                } else {
+                    " (not created yet)"
+                }
+            );
+
+            if let serde_json::Value::Object(settings) = serde_json::to_value(&self.config)? {
+                let width = settings.keys().map(|key| key.len()).max().unwrap_or(0);
+                for (key, value) in &settings {
+                    println!(
+                        "  {:width$}  {}  ({})",
+                        key,
+                        value,
+                        self.config_origin.source_of(key),
+                        width = width
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// List templates or create a new one
+    fn handle_template(&self, action: TemplateCommand) -> Result<()> {
+        let dir = &self.config.templates_dir;
+        match action {
+            TemplateCommand::List => {
+                let names = list_templates(dir)?;
+                if names.is_empty() {
+                    println!("No templates in {}", dir.display());
+                    println!("Create one with: kbnotes template new <name>");
+                }
+                for name in names {
+                    println!("{}", name);
+                }
+            }
+            TemplateCommand::New { name, no_edit } => {
+                let path = create_template(dir, &name)?;
+                println!("Created template {} at {}", name, path.display());
+                if !no_edit {
+                    self.launch_editor(&self.config.get_editor_command(), &path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Show the configured tag policies or apply auto-archiving
+    async fn handle_policies(&self, action: PolicyCommand) -> Result<()> {
+        match action {
+            PolicyCommand::Show => {
+                let policies = &self.config.tags.policies;
+                if policies.is_empty() {
+                    println!("No tag policies configured.");
+                    return Ok(());
+                }
+
+                let notes = self.note_storage.lock().await.get_all_notes()?;
+                for (tag, policy) in policies {
+                    let tagged = notes
+                        .iter()
+                        .filter(|note| note.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+                        .count();
+
+                    let mut rules = Vec::new();
+                    if let Some(days) = policy.auto_archive_after_days {
+                        rules.push(format!("auto-archive after {} days", days));
+                    }
+                    if let Some(excluded) = policy.exclude_from_backup {
+                        rules.push(
+                            if excluded {
+                                "excluded from backups"
+                            } else {
+                                "kept in backups"
+                            }
+                            .to_string(),
+                        );
+                    }
+                    if let Some(hidden) = policy.exclude_from_search_default {
+                        rules.push(
+                            if hidden {
+                                "hidden from search"
+                            } else {
+                                "shown in search"
+                            }
+                            .to_string(),
+                        );
+                    }
+                    if policy.protect_from_delete == Some(true) {
+                        rules.push("protected from deletion".to_string());
+                    }
+                    if rules.is_empty() {
+                        rules.push("no overrides".to_string());
+                    }
+
+                    println!("{} ({} notes): {}", tag, tagged, rules.join(", "));
+                }
+            }
+
+            PolicyCommand::Apply { dry_run } => {
+                let archived = self.note_storage.lock().await.apply_tag_policies(dry_run)?;
+
+                for note in &archived {
+                    println!(
+                        "  {} - {} (last updated {})",
+                        note.id,
+                        note.title,
+                        note.updated_at.format("%Y-%m-%d")
+                    );
+                }
+                if dry_run {
+                    println!("{} note(s) would be archived.", archived.len());
+                } else {
+                    println!("Archived {} note(s).", archived.len());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Export notes (optionally only those with a tag) into a directory
+    async fn handle_export(&self, options: ExportOptions) -> Result<()> {
+        let ExportOptions {
+            output,
+            format,
+            tags,
+            any_tag: _,
+            all_tags,
+            since,
+            until,
+            single_file,
+            sort_by,
+            descending,
+            redact,
+            json,
+        } = options;
+        let format: ExportFormat = format.parse()?;
+
+        let filter = NoteFilter {
+            tags,
+            tag_match: if all_tags {
+                TagMatch::All
+            } else {
+                TagMatch::Any
+            },
+            since: since
+                .map(|since| parse_date_bound(&since, DateBound::Start))
+                .transpose()?,
+            until: until
+                .map(|until| parse_date_bound(&until, DateBound::End))
+                .transpose()?,
+            ..NoteFilter::default()
+        };
+        if let (Some(since), Some(until)) = (filter.since, filter.until) {
+            if since > until {
+                return Err(KbError::InvalidArgument {
+                    message: "--since must not be later than --until".to_string(),
+                });
+            }
+        }
+
+        let sort = NoteSort {
+            field: sort_by.parse()?,
+            descending,
+            pinned_first: true,
+        };
+        let mut notes = self.note_storage.lock().await.query_notes(filter)?;
+        notes.sort_by(|a, b| sort.compare(a, b));
+
+        let mut report = None;
+        if let Some(mode) = redact {
+            let mode: RedactMode = mode.parse()?;
+            let redactor = Redactor::compile(&self.config.redaction)?;
+            let redacted = redactor.redact_notes(&notes, mode);
+            notes = redacted.notes.clone();
+            report = Some(redacted);
+        }
+
+        match (format, single_file) {
+            (ExportFormat::Markdown, true) => export_markdown_single_file(&notes, &output)?,
+            (ExportFormat::Json, true) => export_json_single_file(&notes, &output)?,
+            (ExportFormat::Json, false) => {
+                export_json(&notes, &output)?;
+            }
+            (ExportFormat::Html, true) => export_html_single_file(&notes, &output)?,
+            (ExportFormat::Html, false) => {
+                export_html(&notes, &output)?;
+            }
+            (ExportFormat::Pdf, _) => {
+                let total = notes.len();
+                let mut done = 0;
+                let progress = |note: &Note, path: &Path| {
+                    done += 1;
+                    if json {
+                        return;
+                    }
+                    println!(
+                        "  [{}/{}] {} -> {}",
+                        done,
+                        total,
+                        note.title,
+                        path.display()
+                    );
+                };
+                if single_file {
+                    export_pdf_single_file(&notes, &output, progress)?;
+                } else {
+                    export_pdf(&notes, &output, progress)?;
+                }
+            }
+            _ => {
+                export_markdown(&notes, &output)?;
+            }
+        }
+
+        if json {
+            let mut summary = serde_json::json!({
+                "exported": notes.len(),
+                "format": format.to_string(),
+                "output": output,
+            });
+            if let Some(report) = &report {
+                summary["redactions"] = serde_json::json!({
+                    "total": report.total(),
+                    "notes": report.counts,
+                });
+                summary["withheld"] = serde_json::json!(report.withheld);
+            }
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+            return Ok(());
+        }
+
+        println!(
+            "Exported {} note(s) as {} to {}",
+            notes.len(),
+            format,
+            output.display()
+        );
+        if let Some(report) = &report {
+            println!("Redactions applied: {}", report.total());
+            for count in report.counts.iter().filter(|count| count.redactions > 0) {
+                println!(
+                    "  {} ({}): {} redaction(s)",
+                    count.title, count.id, count.redactions
+                );
+            }
+            for withheld in &report.withheld {
+                println!(
+                    "  Withheld {}: still matches {}",
+                    withheld.id,
+                    withheld.leaks.join(", ")
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Import legacy per-note backups into snapshots and write a migration report
+    async fn handle_migrate_history(
+        &self,
+        delete: bool,
+        archive: bool,
+        dry_run: bool,
+    ) -> Result<()> {
+        let disposition = if delete {
+            LegacyDisposition::Delete
+        } else if archive {
+            LegacyDisposition::Archive
+        } else {
+            LegacyDisposition::Keep
+        };
+
+        let report = self
+            .note_storage
+            .lock()
+            .await
+            .migrate_legacy_history(disposition, dry_run)?;
+
+        for imported in &report.imported {
+            println!(
+                "{} {} -> {} ({})",
+                if dry_run { "would import" } else { "imported" },
+                imported.source.display(),
+                imported.snapshot,
+                imported.note_id
+            );
+        }
+        for skipped in &report.unparseable {
+            println!(
+                "{} {}: {}",
+                console::style("unparseable").red(),
+                skipped.path.display(),
+                skipped.reason
+            );
+        }
+        println!(
+            "\n{} imported, {} duplicate(s) skipped, {} unparseable",
+            report.imported.len(),
+            report.duplicates.len(),
+            report.unparseable.len()
+        );
+
+        if dry_run {
+            return Ok(());
+        }
+
+        let report_path = self.config.backup_dir.join(format!(
+            "history-migration-{}.json",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S")
+        ));
+        std::fs::write(&report_path, serde_json::to_string_pretty(&report)?)?;
+        println!("Report written to {}", report_path.display());
+
+        match disposition {
+            LegacyDisposition::Delete => println!("Migrated backup files were deleted"),
+            LegacyDisposition::Archive => println!(
+                "Migrated backup files were moved to {}",
+                self.config.backup_dir.join(LEGACY_ARCHIVE_DIR).display()
+            ),
+            LegacyDisposition::Keep => {}
+        }
+        Ok(())
+    }
+
+    /// Print note storage sizes, compressing, decompressing or converting
+    /// note files first
+    async fn handle_migrate_format(
+        &self,
+        compress: bool,
+        decompress: bool,
+        threshold: Option<u64>,
+        to: Option<String>,
+    ) -> Result<()> {
+        let mut storage = self.note_storage.lock().await;
+
+        if let Some(format) = to {
+            let format: StorageFormat = format.parse()?;
+            let converted = storage.migrate_storage_format(format)?;
+            println!("Converted {} note file(s) to {}", converted, format);
+
+            // Keep writing notes in the new format, leaving command-line
+            // overrides out of the file as `config --set` does
+            let config_path = &self.config_origin.path;
+            let mut config = if config_path.exists() {
+                load_config_from_file(config_path)?.0
+            } else {
+                load_default_config()?
+            };
+            if config.storage_format != format {
+                config.storage_format = format;
+                save_config_to_file(&config, config_path)?;
+                println!(
+                    "Set storage_format = {} in {}",
+                    format,
+                    config_path.display()
+                );
+            }
+        } else if compress {
+            let threshold = threshold
+                .or(self.config.compress_threshold_bytes)
+                .ok_or_else(|| KbError::ConfigError {
+                    message:
+                        "No compression threshold: pass --threshold or set compress_threshold_bytes"
+                            .to_string(),
+                })?;
+            let rewritten = storage.rewrite_note_files(Some(threshold))?;
+            println!(
+                "Rewrote {} note file(s) for a {} threshold",
+                rewritten,
+                format_size(threshold)
+            );
+            if self.config.compress_threshold_bytes != Some(threshold) {
+                println!("Set compress_threshold_bytes to keep compressing notes on save");
+            }
+        } else if decompress {
+            let rewritten = storage.rewrite_note_files(None)?;
+            println!("Decompressed {} note file(s)", rewritten);
+            if self.config.compress_threshold_bytes.is_some() {
+                println!("Unset compress_threshold_bytes to keep notes uncompressed on save");
+            }
+        }
+
+        let sizes = storage.storage_sizes()?;
+        println!(
+            "Notes:     {} ({} compressed)",
+            sizes.notes, sizes.compressed_notes
+        );
+        println!("Logical:   {}", format_size(sizes.logical_bytes));
+        println!("On disk:   {}", format_size(sizes.disk_bytes));
+        Ok(())
+    }
+
+    /// Move the notes to another backend and keep using it
+    async fn handle_migrate_backend(&self, to: String) -> Result<()> {
+        let kind: BackendKind = to.parse()?;
+        let mut storage = self.note_storage.lock().await;
+        let moved = storage.migrate_backend(kind)?;
+        println!("Moved {} note(s) to the {} backend", moved, kind);
+
+        // Keep using the new backend, leaving command-line overrides out of
+        // the file as `config --set` does
+        let config_path = &self.config_origin.path;
+        let mut config = if config_path.exists() {
+            load_config_from_file(config_path)?.0
+        } else {
+            load_default_config()?
+        };
+        if config.backend != kind {
+            config.backend = kind;
+            save_config_to_file(&config, config_path)?;
+            println!("Set backend = {} in {}", kind, config_path.display());
+        }
+        Ok(())
+    }
+
+    /// Print the vault location, note count, cache statistics and backup state
+    async fn handle_status(&self, json: bool) -> Result<()> {
+        let storage = self.note_storage.lock().await;
+        let cache = storage.cache_stats()?;
+        let durability = storage.durability_stats();
+        let unread = storage.unread_count()?;
+        let reindex = storage.reindex_progress();
+        let backups = storage.get_backup_status().await;
+
+        if json {
+            let status = serde_json::json!({
+                "notes_dir": self.config.notes_dir,
+                "backup_dir": self.config.backup_dir,
+                "backend": storage.backend_kind(),
+                "unread_notes": unread,
+                "cache": cache,
+                "fsync": durability,
+                "reindex": reindex,
+                "last_backup_time": backups.last_backup_time,
+                "last_backup_path": backups.last_backup_path,
+                "last_backup_error": backups.last_error,
+            });
+            println!("{}", serde_json::to_string_pretty(&status)?);
+            return Ok(());
+        }
+
+        println!("Notes directory:  {}", self.config.notes_dir.display());
+        println!("Backup directory: {}", self.config.backup_dir.display());
+        println!("Backend:          {}", storage.backend_kind());
+        println!("Unread notes:     {}", unread);
+        println!(
+            "Cache:            {} note(s), about {}",
+            cache.entries,
+            format_size(cache.bytes_estimate)
+        );
+        let lookups = cache.hits + cache.misses;
+        let hit_rate = if lookups == 0 {
+            "n/a".to_string()
+        } else {
+            format!("{:.0}%", cache.hits as f64 * 100.0 / lookups as f64)
+        };
+        println!(
+            "Cache lookups:    {} hit(s), {} miss(es), {} eviction(s), hit rate {}",
+            cache.hits, cache.misses, cache.evictions, hit_rate
+        );
+        println!(
+            "Fsync:            {} ({} file(s) and {} dir(s) synced, {} pending)",
+            durability.mode,
+            durability.synced_files,
+            durability.synced_directories,
+            durability.pending_syncs
+        );
+        if let Some(progress) = reindex {
+            println!("Search index:     {}", progress);
+        }
+        match (backups.last_backup_time, backups.last_backup_path) {
+            (Some(time), Some(path)) => println!(
+                "Last backup:      {} ({})",
+                time.format("%Y-%m-%d %H:%M:%S"),
+                path.display()
+            ),
+            _ => println!("Last backup:      none this session"),
+        }
+        if let Some(error) = &backups.last_error {
+            println!("Last backup error: {}", console::style(error).red());
+        }
+        Ok(())
+    }
+
+    /// Report note files that are misplaced under the configured ID policy,
+    /// moving them when `fix` is set
+    async fn handle_doctor(&self, fix: bool) -> Result<()> {
+        let mut storage = self.note_storage.lock().await;
+        println!("ID policy: {}", self.config.id_policy);
+
+        let repaired = storage.reconcile_structure_index()?;
+        if repaired > 0 {
+            println!("Refreshed {} stale note structure summaries", repaired);
+        }
+
+        let purged = purge_import_journals(&self.config.notes_dir.join(STATE_DIR), false)?;
+        if purged > 0 {
+            println!(
+                "Removed {} import journal(s) of directories that no longer exist",
+                purged
+            );
+        }
+
+        let report = storage.verify_integrity()?;
+        if report.is_clean() {
+            println!("All note files are readable and where the ID policy expects them");
+            return Ok(());
+        }
+
+        for (path, reason) in &report.unparsable {
+            println!(
+                "{} unreadable: {} could not be parsed ({})",
+                console::style("!").red(),
+                path.display(),
+                reason
+            );
+        }
+        for issue in &report.layout {
+            match &issue.collides_with {
+                Some(other) => println!(
+                    "{} collision: {} and {} both map to {}",
+                    console::style("!").red(),
+                    issue.note_id,
+                    other,
+                    issue.expected.display()
+                ),
+                None if issue.is_name_mismatch() => println!(
+                    "{} id mismatch: {} holds note {}, should be at {}",
+                    console::style("-").yellow(),
+                    issue.path.display(),
+                    issue.note_id,
+                    issue.expected.display()
+                ),
+                None => println!(
+                    "{} misplaced: {} should be at {}",
+                    console::style("-").yellow(),
+                    issue.path.display(),
+                    issue.expected.display()
+                ),
+            }
+        }
+        for id in &report.missing_files {
+            println!(
+                "{} missing: note {} is cached but its file is gone",
+                console::style("-").yellow(),
+                id
+            );
+        }
+        for collision in &report.alias_collisions {
+            match &collision.shadowed_by {
+                Some(id) => println!(
+                    "{} alias shadowed: '{}' of {} is the ID of note {}",
+                    console::style("-").yellow(),
+                    collision.alias,
+                    collision.note_ids.join(", "),
+                    id
+                ),
+                None => println!(
+                    "{} alias collision: '{}' is claimed by {}",
+                    console::style("-").yellow(),
+                    collision.alias,
+                    collision.note_ids.join(", ")
+                ),
+            }
+        }
 
-        // Save the updated note
-        self.note_storage.lock().await.update_note(note.clone())?;
+        let found = report.unparsable.len()
+            + report.layout.len()
+            + report.missing_files.len()
+            + report.alias_collisions.len();
+        if !fix {
+            println!(
+                "\n{} issue(s) found, run 'kbnotes doctor --fix' to repair them",
+                found
+            );
+            return Ok(());
+        }
 
-        println!("Note {} updated successfully", note.id);
+        let fixed = storage.migrate_layout()?;
+        for (path, reason) in &report.unparsable {
+            storage.quarantine_file(path, reason)?;
+        }
+        storage.load_notes()?;
+        println!("\nMigrated {} note file(s)", fixed);
+        if !report.unparsable.is_empty() {
+            println!(
+                "Moved {} unreadable file(s) to {}, see 'kbnotes doctor --corrupt'",
+                report.unparsable.len(),
+                self.config.notes_dir.join(CORRUPT_DIR).display()
+            );
+        }
+        if !report.missing_files.is_empty() {
+            println!(
+                "Reloaded the cache, dropping {} note(s) without a file",
+                report.missing_files.len()
+            );
+        }
 
+        let remaining = report.layout.len() - fixed;
+        if remaining > 0 {
+            println!(
+                "{} issue(s) need manual attention (rename one of the colliding notes)",
+                remaining
+            );
+        }
+        if !report.alias_collisions.is_empty() {
+            println!(
+                "{} alias collision(s) need manual attention (see 'kbnotes alias remove')",
+                report.alias_collisions.len()
+            );
+        }
         Ok(())
     }
 
-    // Helper function for reading content from file (reuse from create command)
-    fn read_content_from_file(&self, file_path: &str) -> Result<String> {
-        let path = Path::new(file_path);
-
-        if !path.exists() {
-            return Err(KbError::ApplicationError {
-                message: format!("File not found: {}", file_path),
-            });
+    /// List the quarantined note files and what can be recovered from each,
+    /// restoring the recoverable ones with `fix`
+    async fn handle_corrupt(&self, fix: bool) -> Result<()> {
+        let storage = self.note_storage.lock().await;
+        let files = storage.list_quarantined()?;
+        if files.is_empty() {
+            println!("No quarantined note files");
+            return Ok(());
         }
 
-        if !path.is_file() {
-            return Err(KbError::ApplicationError {
-                message: format!("Not a file: {}", file_path),
-            });
+        let mut recoverable = 0;
+        let mut restored = 0;
+        for file in &files {
+            println!(
+                "{} {}",
+                console::style("!").red(),
+                file.original
+                    .strip_prefix(&self.config.notes_dir)
+                    .unwrap_or(&file.original)
+                    .display()
+            );
+            if let Some(time) = file.quarantined_at {
+                println!(
+                    "    quarantined {}",
+                    time.with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M:%S")
+                );
+            }
+            if let Some(reason) = &file.reason {
+                println!("    reason: {}", reason.trim());
+            }
+            println!("    file: {}", file.path.display());
+
+            let note = match storage.salvage_quarantined(file) {
+                Ok(note) => note,
+                Err(e) => {
+                    println!("    {} not recoverable: {}", console::style("x").red(), e);
+                    continue;
+                }
+            };
+            recoverable += 1;
+            if !fix {
+                println!(
+                    "    {} recoverable as note {} '{}' ({} byte(s) of content)",
+                    console::style("+").green(),
+                    note.id,
+                    note.title,
+                    note.content.len()
+                );
+                continue;
+            }
+            match storage.restore_quarantined(file) {
+                Ok(note) => {
+                    println!(
+                        "    {} restored note {} '{}'",
+                        console::style("+").green(),
+                        note.id,
+                        note.title
+                    );
+                    restored += 1;
+                }
+                Err(e) => println!("    {} not restored: {}", console::style("x").red(), e),
+            }
         }
 
-        read_to_string(path).map_err(KbError::Io)
+        if fix {
+            println!(
+                "\nRestored {} of {} quarantined file(s)",
+                restored,
+                files.len()
+            );
+        } else {
+            println!(
+                "\n{} of {} quarantined file(s) can be recovered, run 'kbnotes doctor --corrupt --fix' to restore them",
+                recoverable,
+                files.len()
+            );
+        }
+        Ok(())
     }
 
-    // Helper function to open editor with existing content
-    fn open_editor_with_content(&self, title: &str, existing_content: &str) -> Result<String> {
-        // Create a temporary file for editing
-        // let mut temp_file = tempfile::NamedTempFile::new()
-        //     .map_err(KbError::Io);
-        let temp_file = Builder::new().suffix(".md").tempfile()?;
-        let temp_path = temp_file.path().to_path_buf();
-
-        let mut temp_file = OpenOptions::new().write(true).open(&temp_path)?;
+    /// Rebuild the search index, in the background behind other commands
+    /// while printing its progress, or in one go with `foreground`
+    async fn handle_reindex(&self, foreground: bool) -> Result<()> {
+        let count = if foreground {
+            self.note_storage.lock().await.rebuild_search_index()?
+        } else {
+            // The storage stays unlocked while the job runs, so that other
+            // commands sharing it are served in the meantime
+            let mut job = self.note_storage.lock().await.start_reindex()?;
+            loop {
+                tokio::select! {
+                    result = &mut job => {
+                        break result.map_err(|e| KbError::ApplicationError {
+                            message: format!("Reindex task failed: {}", e),
+                        })??;
+                    }
+                    _ = tokio::time::sleep(REINDEX_REPORT_INTERVAL) => {
+                        let progress = self.note_storage.lock().await.reindex_progress();
+                        if let Some(progress) = progress.filter(|_| !self.quiet) {
+                            println!("Search index: {}", progress);
+                        }
+                    }
+                }
+            }
+        };
+        println!("Indexed {} note(s)", count);
+        if !self.config.use_search_index {
+            println!(
+                "Searches ignore the index until it is enabled with 'kbnotes config --set use_search_index=true'"
+            );
+        }
+        Ok(())
+    }
 
-        // Write existing content to the file
-        writeln!(temp_file, "# {}", title)?;
-        writeln!(temp_file, "<!-- Edit your note below this line -->")?;
-        writeln!(temp_file, "\n{}", existing_content)?;
-
-        // Get editor command from config, or use default
-        let editor_cmd = self
-            .config
-            .editor_command
-            .clone()
-            .unwrap_or_else(|| "nano".to_string());
-
-        // Build and execute the editor command
-        let status = std::process::Command::new(&editor_cmd)
-            .arg(temp_path.clone())
-            .status()
-            .map_err(|e| KbError::ApplicationError {
-                message: format!("Failed to execute editor command: {}", e),
-            })?;
+    /// Print the notes changed or deleted since a point in time, one per line
+    /// or as a JSON array of `{id, updated_at}` and `{id, deleted_at, purged}`
+    async fn handle_changed(&self, since: String, format: String) -> Result<()> {
+        let since = parse_date_bound(&since, DateBound::Start)?;
+        let changes = self
+            .note_storage
+            .lock()
+            .await
+            .get_notes_modified_since(since)?;
 
-        if !status.success() {
-            return Err(KbError::ApplicationError {
-                message: "Editor exited with non-zero status".to_string(),
+        if format == "json" {
+            let modified = changes.modified.iter().map(|note| {
+                serde_json::json!({
+                    "id": note.id,
+                    "updated_at": note.updated_at.to_rfc3339(),
+                })
+            });
+            let deleted = changes.deleted.iter().map(|deleted| {
+                serde_json::json!({
+                    "id": deleted.id,
+                    "deleted_at": deleted.deleted_at.to_rfc3339(),
+                    "purged": deleted.purged,
+                })
             });
+            let entries: Vec<serde_json::Value> = modified.chain(deleted).collect();
+            println!("{}", serde_json::to_string_pretty(&entries)?);
+        } else if changes.modified.is_empty() && changes.deleted.is_empty() {
+            println!("No notes changed since {}", since.to_rfc3339());
+        } else {
+            for note in &changes.modified {
+                println!("{}\t{}", note.id, note.updated_at.to_rfc3339());
+            }
+            for deleted in &changes.deleted {
+                println!(
+                    "{}\t{}\t{}",
+                    deleted.id,
+                    deleted.deleted_at.to_rfc3339(),
+                    if deleted.purged { "purged" } else { "trashed" }
+                );
+            }
         }
+        Ok(())
+    }
 
-        // Read the updated content from the temp file
-        let content = read_to_string(&temp_path).map_err(KbError::Io)?;
-
-        Ok(content)
+    /// Print the registered examples of one command, or of every command
+    fn handle_examples(&self, command: Option<String>) -> Result<()> {
+        match command {
+            Some(command) => {
+                let examples = examples_for(&command).ok_or_else(|| KbError::ApplicationError {
+                    message: format!("No examples registered for command '{}'", command),
+                })?;
+                println!("{}", render_examples(examples));
+            }
+            None => {
+                for (i, entry) in COMMAND_EXAMPLES.iter().enumerate() {
+                    if i > 0 {
+                        println!();
+                    }
+                    println!("{}", console::style(entry.command).bold());
+                    println!("{}", render_examples(entry.examples));
+                }
+            }
+        }
+        Ok(())
     }
 
-    async fn handle_delete(&self, id: String, force: bool) -> Result<()> {
+    async fn handle_delete(
+        &self,
+        id: String,
+        force: bool,
+        permanent: bool,
+        include_snapshots: bool,
+    ) -> Result<()> {
         // Step 1: Fetch the note to be deleted (to verify it exists and show details in the prompt)
+        let id = self.note_storage.lock().await.resolve_note_id(&id)?;
         let note = match self.note_storage.lock().await.get_note(&id) {
             Some(note) => note,
             _ => {
@@ -646,6 +3837,33 @@ impl App {
             }
         };
 
+        // Tag policies and locks may protect the note, which only --force overrides
+        if !force
+            && self
+                .note_storage
+                .lock()
+                .await
+                .tag_policy(&note)
+                .protect_from_delete
+        {
+            return Err(KbError::NoteProtected { id: note.id });
+        }
+        if note.locked && !force {
+            return Err(KbError::NoteLocked { id: note.id });
+        }
+
+        // Links to the note will be left dangling
+        let backlinks = self.note_storage.lock().await.get_backlinks(&note.id)?;
+        if !backlinks.is_empty() {
+            println!(
+                "Warning: {} note(s) still link to this note:",
+                backlinks.len()
+            );
+            for source in &backlinks {
+                println!("  {} ({})", source.title, source.id);
+            }
+        }
+
         // Step 2: Show note details and prompt for confirmation (unless force flag is set)
         if !force {
             println!("You are about to delete the following note:");
@@ -671,7 +3889,7 @@ impl App {
             }
 
             // Ask for confirmation
-            println!("\nThis action cannot be undone!");
+            println!("\nThe note will be moved to the trash.");
             print!("Are you sure you want to delete this note? [y/N]: ");
             stdout().flush().map_err(KbError::Io)?;
 
@@ -688,37 +3906,156 @@ impl App {
         }
 
         // Step 3: Delete the note
-        self.note_storage.lock().await.delete_note(&id)?;
+        if permanent {
+            let storage = self.note_storage.lock().await;
+            storage.delete_note_permanently(&id)?;
+            if include_snapshots {
+                storage.delete_snapshots(&id)?;
+            }
+        } else if force {
+            self.note_storage.lock().await.force_delete_note(&id)?;
+        } else {
+            self.note_storage.lock().await.delete_note(&id)?;
+        }
 
         // Step 4: Provide feedback
-        println!(
-            "Note '{}' ({}) has been permanently deleted.",
-            note.title, note.id
-        );
+        if permanent {
+            println!(
+                "Note '{}' ({}) has been permanently deleted.",
+                note.title, note.id
+            );
+        } else {
+            println!(
+                "Note '{}' ({}) has been moved to the trash. Restore it with `kbnotes trash restore {}`.",
+                note.title, note.id, note.id
+            );
+        }
 
         Ok(())
     }
 
-    /// Handle importing notes from external sources
-    fn handle_import(
+    /// Handle deleting every note carrying one of `tags`, after confirmation
+    /// unless `force` is set
+    ///
+    /// Notes protected by a tag policy are only deleted with `force`, like
+    /// single deletions; they are reported along with other failures.
+    async fn handle_bulk_delete(
         &self,
-        path: String,
-        format: String,
-        tags: Option<String>,
-        title_from_filename: bool,
-        recursive: bool,
-        pattern: Option<String>,
-        verbose: bool,
+        tags: Vec<String>,
+        force: bool,
+        permanent: bool,
+        include_snapshots: bool,
     ) -> Result<()> {
+        let storage = self.note_storage.lock().await;
+        let mut notes = storage.query_notes(NoteFilter {
+            tags,
+            ..NoteFilter::default()
+        })?;
+        if notes.is_empty() {
+            println!("No notes carry the given tags.");
+            return Ok(());
+        }
+        notes.sort_by(|a, b| a.title.cmp(&b.title).then_with(|| a.id.cmp(&b.id)));
+        if !force && !confirm_bulk("Delete", &notes)? {
+            println!("Deletion cancelled.");
+            return Ok(());
+        }
+
+        let (protected, deletable): (Vec<Note>, Vec<Note>) = notes
+            .into_iter()
+            .partition(|note| !force && storage.tag_policy(note).protect_from_delete);
+        let ids: Vec<String> = deletable.into_iter().map(|note| note.id).collect();
+        let mut report = storage.delete_notes(&ids, force, permanent)?;
+        report.failed.extend(protected.into_iter().map(|note| {
+            (
+                note.id.clone(),
+                KbError::NoteProtected { id: note.id }.to_string(),
+            )
+        }));
+
+        if include_snapshots {
+            for id in &report.succeeded {
+                storage.delete_snapshots(id)?;
+            }
+        }
+
+        if permanent {
+            println!("Permanently deleted {} note(s)", report.succeeded.len());
+        } else {
+            println!(
+                "Moved {} note(s) to the trash. Restore them with `kbnotes trash restore <id>`.",
+                report.succeeded.len()
+            );
+        }
+        batch_result("deleted", &report)
+    }
+
+    /// Handle listing, restoring and purging deleted notes
+    async fn handle_trash(&self, action: TrashCommand) -> Result<()> {
+        let storage = self.note_storage.lock().await;
+
+        match action {
+            TrashCommand::List => {
+                let trashed = storage.list_trash()?;
+                if trashed.is_empty() {
+                    println!("The trash is empty");
+                }
+                for entry in &trashed {
+                    println!(
+                        "{}  {}  {}",
+                        entry.deleted_at.format("%Y-%m-%d %H:%M:%S"),
+                        entry.note.id,
+                        console::style(&entry.note.title).bold()
+                    );
+                }
+            }
+
+            TrashCommand::Restore { id } => {
+                let note = storage.restore_from_trash(id.trim())?;
+                println!(
+                    "Note '{}' ({}) restored from the trash",
+                    note.title, note.id
+                );
+            }
+
+            TrashCommand::Empty {
+                older_than,
+                include_snapshots,
+            } => {
+                let older_than = older_than.as_deref().map(parse_age).transpose()?;
+                let purged = storage.empty_trash(older_than)?;
+                println!("Purged {} note(s) from the trash", purged.len());
+                if include_snapshots {
+                    let mut snapshots = 0;
+                    for trashed in &purged {
+                        snapshots += storage.delete_snapshots(&trashed.note.id)?;
+                    }
+                    println!("Deleted {} snapshot(s) of the purged notes", snapshots);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handle importing notes from external sources
+    async fn handle_import(&self, options: ImportOptions) -> Result<()> {
+        let ImportOptions {
+            path,
+            format,
+            tags,
+            title_from_filename,
+            recursive,
+            pattern,
+            verbose,
+            resume,
+            force_reimport,
+        } = options;
+        // The global --verbose flag also shows import details
+        let verbose = verbose || self.verbose;
+
         // Parse tags from comma-separated string
-        let parsed_tags = tags
-            .map(|t| {
-                t.split(',')
-                    .map(|s| s.trim().to_string())
-                    .filter(|s| !s.is_empty())
-                    .collect::<Vec<String>>()
-            })
-            .unwrap_or_else(Vec::new);
+        let parsed_tags = parse_tags(tags);
 
         // Normalize format string
         let format = format.to_lowercase();
@@ -732,25 +4069,45 @@ impl App {
         let path = PathBuf::from(&path);
 
         // Import statistics
-        let mut total_files = 0;
-        let mut imported_notes = 0;
-        let mut failed_imports = 0;
+        let total_files;
+        let mut already_imported = 0;
+        let mut run = ImportRun::default();
+        if !force_reimport {
+            let notes = self.note_storage.lock().await.get_all_notes()?;
+            run.known = Some(notes.iter().map(note_content_hash).collect());
+        }
 
         // Process based on whether it's a file or directory
         if path.is_file() {
+            if resume {
+                return Err(KbError::InvalidArgument {
+                    message: "--resume only applies to directory imports".to_string(),
+                });
+            }
             if verbose {
                 println!("Importing file: {}", path.display());
             }
 
             // Import a single file
-            match self.import_file(&path, &format, &parsed_tags, title_from_filename) {
-                Ok(note_id) => {
-                    imported_notes += 1;
-                    println!("Imported note with ID: {}", note_id);
+            match self
+                .import_file(
+                    &path,
+                    format,
+                    &parsed_tags,
+                    title_from_filename,
+                    verbose,
+                    &mut run,
+                )
+                .await
+            {
+                Ok(_) => {
+                    run.save_pending(&*self.note_storage.lock().await, verbose)?;
+                    println!("Imported {} note(s) from {}", run.saved, path.display());
                 }
                 Err(e) => {
-                    failed_imports += 1;
                     eprintln!("Failed to import {}: {}", path.display(), e);
+                    run.failures
+                        .push((path.display().to_string(), e.to_string()));
                 }
             }
 
@@ -762,8 +4119,10 @@ impl App {
                     globset::GlobBuilder::new(&p)
                         .case_insensitive(true)
                         .build()
-                        .map_err(|e| KbError::ValidationFailed(format!("Invalid pattern: {}", e)))
-                        .and_then(|glob| Ok(globset::GlobSet::new(&[glob])?))
+                        .map(|glob| glob.compile_matcher())
+                        .map_err(|e| KbError::InvalidArgument {
+                            message: format!("Invalid pattern '{}': {}", p, e),
+                        })
                 })
                 .transpose()?;
 
@@ -787,26 +4146,26 @@ impl App {
             } else {
                 // Non-recursive, just list direct children
                 if let Ok(dir_entries) = std::fs::read_dir(&path) {
-                    for entry in dir_entries {
-                        if let Ok(entry) = entry {
-                            let path = entry.path();
-                            if path.is_file() {
-                                entries.push(path);
-                            }
+                    for entry in dir_entries.flatten() {
+                        let path = entry.path();
+                        if path.is_file() {
+                            entries.push(path);
                         }
                     }
                 }
             }
 
-            // Filter by pattern if needed
-            let filtered_entries = if let Some(matcher) = &pattern_matcher {
+            // Filter by pattern if needed, matching paths relative to the
+            // imported directory so `*.md` and `daily/*.md` both work
+            let mut filtered_entries = if let Some(matcher) = &pattern_matcher {
                 entries
                     .into_iter()
-                    .filter(|p| matcher.is_match(p))
+                    .filter(|p| matcher.is_match(p.strip_prefix(&path).unwrap_or(p)))
                     .collect::<Vec<_>>()
             } else {
                 entries
             };
+            filtered_entries.sort();
 
             total_files = filtered_entries.len();
 
@@ -814,52 +4173,150 @@ impl App {
                 println!("Found {} matching files", total_files);
             }
 
-            // Import each file
+            // Pick up the journal of an earlier, unfinished import of the
+            // same directory
+            let state_dir = self.config.notes_dir.join(STATE_DIR);
+            let root = path.canonicalize()?;
+            let mut journal = match ImportJournal::open(&state_dir, &root, format)? {
+                Some(journal) if !force_reimport => {
+                    println!(
+                        "Resuming the import of {} ({} file(s) already imported)",
+                        root.display(),
+                        journal.imported_files()
+                    );
+                    journal
+                }
+                None if resume => {
+                    return Err(KbError::InvalidArgument {
+                        message: format!("No unfinished import of {} to resume", root.display()),
+                    })
+                }
+                _ => ImportJournal::create(&state_dir, &root, format)?,
+            };
+
+            // Import each file; a file is journaled once its notes are saved
+            let mut unrecorded: Vec<(String, ImportJournalEntry)> = Vec::new();
             for file_path in filtered_entries {
+                let relative = file_path
+                    .strip_prefix(&path)
+                    .unwrap_or(&file_path)
+                    .to_string_lossy()
+                    .replace('\\', "/");
+                let content_hash = match hash_file(&file_path) {
+                    Ok(hash) => hash,
+                    Err(e) => {
+                        eprintln!("Failed to import {}: {}", file_path.display(), e);
+                        run.failures
+                            .push((file_path.display().to_string(), e.to_string()));
+                        continue;
+                    }
+                };
+                if journal.is_imported(&relative, &content_hash) {
+                    already_imported += 1;
+                    continue;
+                }
+
                 if verbose {
                     println!("Importing: {}", file_path.display());
                 }
 
-                match self.import_file(&file_path, &format, &parsed_tags, title_from_filename) {
-                    Ok(note_id) => {
-                        imported_notes += 1;
-                        if verbose {
-                            println!("Imported as note ID: {}", note_id);
-                        }
-                    }
+                let failures_before = run.failures.len();
+                let (notes, error) = match self
+                    .import_file(
+                        &file_path,
+                        format,
+                        &parsed_tags,
+                        title_from_filename,
+                        verbose,
+                        &mut run,
+                    )
+                    .await
+                {
+                    Ok(count) => (count, None),
                     Err(e) => {
-                        failed_imports += 1;
                         eprintln!("Failed to import {}: {}", file_path.display(), e);
+                        run.failures
+                            .push((file_path.display().to_string(), e.to_string()));
+                        (0, Some(e.to_string()))
                     }
+                };
+
+                // Notes of a JSON file can fail without failing the file
+                let status = if run.failures.len() == failures_before {
+                    ImportFileStatus::Imported
+                } else {
+                    ImportFileStatus::Failed
+                };
+                unrecorded.push((
+                    file_path.display().to_string(),
+                    ImportJournalEntry {
+                        path: relative,
+                        content_hash,
+                        status,
+                        notes,
+                        error,
+                    },
+                ));
+
+                if run.pending.len() >= IMPORT_BATCH_SIZE {
+                    self.record_import_batch(&mut run, &mut journal, &mut unrecorded, verbose)
+                        .await?;
                 }
             }
+            self.record_import_batch(&mut run, &mut journal, &mut unrecorded, verbose)
+                .await?;
+
+            if run.failures.is_empty() {
+                journal.remove()?;
+            } else {
+                println!("Progress saved: run the same import again to retry the failed files");
+            }
         } else {
-            return Err(KbError::ValidationFailed(format!(
-                "Path not found: {}",
-                path.display()
-            )));
+            return Err(KbError::FileNotFound {
+                file_path: path.display().to_string(),
+            });
         }
 
         // Show summary
         println!("\nImport summary:");
         println!("  Total files processed: {}", total_files);
-        println!("  Successfully imported: {}", imported_notes);
-        println!("  Failed imports: {}", failed_imports);
+        if already_imported > 0 {
+            println!("  Already imported earlier: {}", already_imported);
+        }
+        println!("  Successfully imported: {} new", run.saved);
+        if run.duplicates > 0 {
+            println!("  Duplicates skipped: {}", run.duplicates);
+        }
+        println!("  Failed imports: {}", run.failures.len());
+        for (source, error) in &run.failures {
+            println!("    {}: {}", source, error);
+        }
 
         Ok(())
     }
 
-    /// Import a single file as a note
-    fn import_file(
+    /// Import a single file, returning the number of notes created
+    ///
+    /// Notes of a multi-note JSON file that fail to import are added to
+    /// `run.failures` without failing the whole file. Notes identical to an
+    /// existing one are skipped.
+    async fn import_file(
         &self,
-        path: &PathBuf,
+        path: &Path,
         format: &str,
         tags: &[String],
         title_from_filename: bool,
-    ) -> Result<String> {
+        verbose: bool,
+        run: &mut ImportRun,
+    ) -> Result<usize> {
+        // JSON exports can be huge, so they are streamed instead of read at once
+        if format == "json" {
+            return self.import_json_file(path, tags, verbose, run).await;
+        }
+
         // Read the file content
-        let content = std::fs::read_to_string(path).map_err(|e| {
-            KbError::ValidationFailed(format!("Failed to read file {}: {}", path.display(), e))
+        let content = std::fs::read_to_string(path).map_err(|e| KbError::ApplicationError {
+            message: format!("Failed to read file {}: {}", path.display(), e),
         })?;
 
         // Determine the title
@@ -874,8 +4331,8 @@ impl App {
                 "markdown" => {
                     // Look for a markdown H1 heading (# Title)
                     let first_line = content.lines().next().unwrap_or("");
-                    if first_line.starts_with("# ") {
-                        first_line[2..].trim().to_string()
+                    if let Some(heading) = first_line.strip_prefix("# ") {
+                        heading.trim().to_string()
                     } else {
                         path.file_name()
                             .and_then(|s| s.to_str())
@@ -883,13 +4340,6 @@ impl App {
                             .to_string()
                     }
                 }
-                "json" => {
-                    // For JSON files, we'll handle differently in the parse_note_from_json function
-                    path.file_name()
-                        .and_then(|s| s.to_str())
-                        .unwrap_or("Unnamed Note")
-                        .to_string()
-                }
                 _ => {
                     // For other formats, use filename
                     path.file_name()
@@ -901,27 +4351,42 @@ impl App {
         };
 
         // Process content based on format
-        match format {
-            "markdown" => self.import_markdown_note(title, content, tags, path),
-            "json" => self.import_json_note(content, tags, path),
-            "text" => self.import_text_note(title, content, tags, path),
-            _ => Err(KbError::ValidationFailed(format!(
-                "Unsupported format: {}",
-                format
-            ))),
+        let note = match format {
+            "markdown" => Ok(self.import_markdown_note(title, content, tags, path, run)),
+            "text" => Ok(self.import_text_note(title, content, tags, path, run)),
+            _ => Err(KbError::InvalidFormat {
+                message: format!("Unsupported import format: {}", format),
+            }),
+        }?;
+
+        match note {
+            Some(note) => {
+                run.queue(note, path.display().to_string());
+                Ok(1)
+            }
+            None => {
+                if verbose {
+                    println!("Skipped, identical to an existing note");
+                }
+                Ok(0)
+            }
         }
     }
 
-    /// Import a markdown note
+    /// Builds a markdown note to import, unless an identical note exists
     fn import_markdown_note(
         &self,
         title: String,
         content: String,
         tags: &[String],
-        source_path: &PathBuf,
-    ) -> Result<String> {
+        source_path: &Path,
+        run: &mut ImportRun,
+    ) -> Option<Note> {
         // Create note with the provided content
         let mut note = Note::new(title, content, tags.to_vec());
+        if run.is_duplicate(&note) {
+            return None;
+        }
 
         // Add metadata
         note.metadata
@@ -931,90 +4396,108 @@ impl App {
         note.metadata
             .insert("imported_at".to_string(), Utc::now().to_rfc3339());
 
-        // Save the note
-        self.runtime
-            .block_on(async { self.storage.save_note(&note).await })?;
-
-        Ok(note.id)
+        Some(note)
     }
 
-    /// Import a JSON formatted note
-    fn import_json_note(
+    /// Import every note of a JSON file (a single object, an array or JSONL)
+    ///
+    /// Elements are parsed one at a time and saved in batches of
+    /// [`IMPORT_BATCH_SIZE`], so memory use does not grow with the file size.
+    /// Elements that fail are recorded in `run.failures` with their index, and
+    /// elements identical to an existing note are skipped.
+    async fn import_json_file(
         &self,
-        content: String,
+        path: &Path,
         extra_tags: &[String],
-        source_path: &PathBuf,
-    ) -> Result<String> {
-        // Parse JSON
-        let json: serde_json::Value = serde_json::from_str(&content)
-            .map_err(|e| KbError::ValidationFailed(format!("Invalid JSON: {}", e)))?;
-
-        // Extract note fields
-        let title = json
-            .get("title")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| KbError::ValidationFailed("JSON missing 'title' field".to_string()))?
-            .to_string();
-
-        let content = json
-            .get("content")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| KbError::ValidationFailed("JSON missing 'content' field".to_string()))?
-            .to_string();
-
-        // Extract tags if present and merge with extra_tags
-        let mut tags = extra_tags.to_vec();
-        if let Some(json_tags) = json.get("tags").and_then(|v| v.as_array()) {
-            for tag_value in json_tags {
-                if let Some(tag) = tag_value.as_str() {
-                    if !tag.is_empty() && !tags.contains(&tag.to_string()) {
-                        tags.push(tag.to_string());
-                    }
-                }
-            }
-        }
-
-        // Create the note
-        let mut note = Note::new(title, content, tags);
+        verbose: bool,
+        run: &mut ImportRun,
+    ) -> Result<usize> {
+        let file = std::fs::File::open(path).map_err(|e| KbError::ApplicationError {
+            message: format!("Failed to read file {}: {}", path.display(), e),
+        })?;
 
-        // Add metadata
-        note.metadata
-            .insert("source_file".to_string(), source_path.display().to_string());
-        note.metadata
-            .insert("import_format".to_string(), "json".to_string());
-        note.metadata
-            .insert("imported_at".to_string(), Utc::now().to_rfc3339());
+        // The parser calls back synchronously, so the storage stays locked for
+        // the whole file
+        let storage = self.note_storage.lock().await;
+        let saved_before = run.saved;
+        let result = stream_json_values(std::io::BufReader::new(file), |index, value| {
+            let source = format!("{}[{}]", path.display(), index);
+            match value.and_then(|json| note_from_json_value(&json, extra_tags, path)) {
+                Ok(note) if run.is_duplicate(&note) => {}
+                Ok(note) => run.queue(note, source),
+                Err(e) => run.failures.push((source, e.to_string())),
+            }
 
-        // Copy additional fields as metadata
-        for (key, value) in json.as_object().unwrap_or(&serde_json::Map::new()) {
-            // Skip fields we've already processed
-            if !["title", "content", "tags"].contains(&key.as_str()) {
-                if let Some(str_value) = value.as_str() {
-                    note.metadata.insert(key.clone(), str_value.to_string());
-                } else {
-                    // For non-string values, convert to string representation
-                    note.metadata.insert(key.clone(), value.to_string());
+            if run.pending.len() >= IMPORT_BATCH_SIZE {
+                if let Err(e) = run.save_pending(&storage, verbose) {
+                    run.failures
+                        .push((path.display().to_string(), e.to_string()));
+                }
+                if !self.quiet {
+                    println!(
+                        "  {} notes imported from {}...",
+                        run.saved - saved_before,
+                        path.display()
+                    );
                 }
             }
+        });
+        run.save_pending(&storage, verbose)?;
+        let imported = run.saved - saved_before;
+
+        match result {
+            Ok(shape) => info!(
+                "Imported {} notes from {:?} JSON file {}",
+                imported,
+                shape,
+                path.display()
+            ),
+            // Notes parsed before the error are kept
+            Err(e) => run.failures.push((
+                path.display().to_string(),
+                format!("Stopped after {} notes: {}", imported, e),
+            )),
         }
 
-        // Save the note
-        self.runtime
-            .block_on(async { self.storage.save_note(&note).await })?;
+        Ok(imported)
+    }
 
-        Ok(note.id)
+    /// Saves the notes queued by an import and journals the files they came
+    /// from, marking the files whose note failed to save as failed
+    async fn record_import_batch(
+        &self,
+        run: &mut ImportRun,
+        journal: &mut ImportJournal,
+        unrecorded: &mut Vec<(String, ImportJournalEntry)>,
+        verbose: bool,
+    ) -> Result<()> {
+        let failed = run.save_pending(&*self.note_storage.lock().await, verbose)?;
+        for (source, mut entry) in unrecorded.drain(..) {
+            if let Some(error) = failed.get(&source) {
+                eprintln!("Failed to import {}: {}", source, error);
+                entry.status = ImportFileStatus::Failed;
+                entry.notes = 0;
+                entry.error = Some(error.clone());
+            }
+            journal.record(entry)?;
+        }
+        Ok(())
     }
 
-    /// Import a plain text note
+    /// Builds a plain text note to import, unless an identical note exists
     fn import_text_note(
         &self,
         title: String,
         content: String,
         tags: &[String],
-        source_path: &PathBuf,
-    ) -> Result<String> {
+        source_path: &Path,
+        run: &mut ImportRun,
+    ) -> Option<Note> {
         // Create note with the provided content
         let mut note = Note::new(title, content, tags.to_vec());
+        if run.is_duplicate(&note) {
+            return None;
+        }
 
         // Add metadata
         note.metadata
@@ -1024,10 +4507,216 @@ impl App {
         note.metadata
             .insert("imported_at".to_string(), Utc::now().to_rfc3339());
 
-        // Save the note
-        self.runtime
-            .block_on(async { self.storage.save_note(&note).await })?;
+        Some(note)
+    }
+}
+
+/// Heading of the section that collects links added via `kbnotes link`
+const RELATED_HEADING: &str = "## Related";
+
+/// Splits a `--set-meta` argument into its key and value
+///
+/// The value may itself contain `=`; the key is trimmed and must not be empty.
+fn parse_meta_assignment(assignment: &str) -> Result<(String, String)> {
+    match assignment.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(KbError::InvalidArgument {
+            message: format!(
+                "Invalid metadata '{}': expected KEY=VALUE, e.g. --set-meta status=draft",
+                assignment
+            ),
+        }),
+    }
+}
+
+/// Formats a byte count with a binary unit, e.g. `1.5 KiB`
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Number of titles shown when confirming a bulk operation
+const BULK_SAMPLE_TITLES: usize = 5;
+
+/// Shows how many notes a bulk operation affects, with a few of their
+/// titles, and asks whether to go on
+fn confirm_bulk(action: &str, notes: &[Note]) -> Result<bool> {
+    println!("{} {} note(s):", action, notes.len());
+    for note in notes.iter().take(BULK_SAMPLE_TITLES) {
+        println!("  {} ({})", note.title, note.id);
+    }
+    if notes.len() > BULK_SAMPLE_TITLES {
+        println!("  ... and {} more", notes.len() - BULK_SAMPLE_TITLES);
+    }
+    confirm("\nProceed? [y/N]: ")
+}
+
+/// Lists the failures of a bulk operation, failing when there were any so
+/// scripts notice partial changes
+fn batch_result(verb: &str, report: &BatchReport) -> Result<()> {
+    if report.failed.is_empty() {
+        return Ok(());
+    }
+    println!("{} note(s) could not be {}:", report.failed.len(), verb);
+    for (note_id, error) in &report.failed {
+        println!("  {}: {}", note_id, error);
+    }
+    Err(KbError::ApplicationError {
+        message: format!("{} note(s) could not be {}", report.failed.len(), verb),
+    })
+}
+
+/// Fails a restore that left notes out, once its summary is printed
+fn restore_outcome(summary: &RestoreBackupSummary) -> Result<()> {
+    // A non-zero exit status lets scripts notice partial restores
+    if summary.failed_notes.is_empty() {
+        return Ok(());
+    }
+    Err(KbError::RestoreFailed {
+        message: format!(
+            "{} note(s) could not be restored from {}",
+            summary.failed_notes.len(),
+            summary.backup_file.display()
+        ),
+    })
+}
+
+/// Prints a yes/no question and returns true when the user answers yes
+fn confirm(prompt: &str) -> Result<bool> {
+    print!("{}", prompt);
+    stdout().flush().map_err(KbError::Io)?;
+
+    let mut input = String::new();
+    stdin().read_line(&mut input).map_err(KbError::Io)?;
+
+    let input = input.trim().to_lowercase();
+    Ok(input == "y" || input == "yes")
+}
+
+/// Starts the user's pager (`$PAGER`, or `less -R`) reading from a pipe
+///
+/// Returns `None` when no pager could be started, in which case the caller
+/// prints directly.
+fn spawn_pager() -> Option<std::process::Child> {
+    let command = std::env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| "less -R".to_string());
+    let args = split(&command).ok()?;
+    let (program, args) = args.split_first()?;
+
+    Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .ok()
+}
+
+/// Writes the header of a viewed note followed by its content, one top-level
+/// Markdown block at a time, and the footer (backlinks)
+///
+/// A closed output (the pager was quit early) ends the output without error.
+fn write_view(
+    out: &mut impl Write,
+    header: &[String],
+    body: &str,
+    footer: &[String],
+) -> Result<()> {
+    let result = (|| {
+        writeln!(out, "{}", header.join("\n"))?;
+        if !body.is_empty() {
+            writeln!(out)?;
+            for block in MarkdownBlocks::new(body) {
+                out.write_all(block.as_bytes())?;
+            }
+            if !body.ends_with('\n') {
+                writeln!(out)?;
+            }
+        }
+        if !footer.is_empty() {
+            writeln!(out, "\n{}", footer.join("\n"))?;
+        }
+        out.flush()
+    })();
+
+    match result {
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => Err(KbError::Io(e)),
+        _ => Ok(()),
+    }
+}
+
+/// Parses the values of a `--has` option
+/// Builds the filter for the `--created-*` and `--updated-*` options
+///
+/// Bounds in the future are accepted; they simply match no notes.
+fn date_filter(dates: DateRangeOptions) -> Result<NoteFilter> {
+    let parse = |value: Option<String>, bound| {
+        value
+            .map(|value| parse_date_bound(&value, bound))
+            .transpose()
+    };
+    Ok(NoteFilter {
+        since: parse(dates.created_after, DateBound::Start)?,
+        until: parse(dates.created_before, DateBound::End)?,
+        updated_since: parse(dates.updated_after, DateBound::Start)?,
+        updated_until: parse(dates.updated_before, DateBound::End)?,
+        ..NoteFilter::default()
+    })
+}
+
+/// Where and how well a search result matched
+struct ResultMatch {
+    /// The best matching part of the note
+    snippet: Option<SearchSnippet>,
+    /// Relevance and matched fields, when they are to be shown
+    score: Option<SearchScore>,
+}
+
+/// Adds the `snippet` and `match_positions` fields of a search result,
+/// `null` and empty when no single line matched, and its `score` and
+/// `matched_fields` when known
+fn add_match_fields(value: &mut serde_json::Value, result_match: Option<&ResultMatch>) {
+    let snippet = result_match.and_then(|result_match| result_match.snippet.as_ref());
+    value["snippet"] = serde_json::json!(snippet.map(|snippet| &snippet.text));
+    value["match_positions"] = serde_json::json!(snippet
+        .map(|snippet| snippet.match_positions.as_slice())
+        .unwrap_or_default());
+    if let Some(score) = result_match.and_then(|result_match| result_match.score) {
+        value["score"] = serde_json::json!(score.score);
+        value["matched_fields"] = serde_json::json!(score.fields.names());
+    }
+}
+
+fn parse_structure_filters(names: &[String]) -> Result<Vec<StructureFilter>> {
+    names.iter().map(|name| name.parse()).collect()
+}
 
-        Ok(note.id)
+/// Appends a wiki-link to the "Related" section at the bottom of the content,
+/// creating the section if it doesn't exist yet
+fn append_related_link(content: &str, target_id: &str) -> String {
+    let link_line = format!("- [[{}]]", target_id);
+    let trimmed = content.trim_end();
+
+    // Only reuse the section when it is the last one in the note
+    let last_heading = trimmed.lines().rev().find(|line| line.starts_with('#'));
+
+    if last_heading.is_some_and(|heading| heading.trim() == RELATED_HEADING) {
+        format!("{}\n{}\n", trimmed, link_line)
+    } else if trimmed.is_empty() {
+        format!("{}\n\n{}\n", RELATED_HEADING, link_line)
+    } else {
+        format!("{}\n\n{}\n\n{}\n", trimmed, RELATED_HEADING, link_line)
     }
 }
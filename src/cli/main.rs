@@ -28,6 +28,14 @@ pub struct Cli {
     #[clap(short, long)]
     pub verbose: bool,
 
+    /// Suppress informational output such as link suggestions
+    #[clap(short, long)]
+    pub quiet: bool,
+
+    /// Disable colors and highlighting, even on a terminal
+    #[clap(long)]
+    pub no_color: bool,
+
     /// Subcommands for the kbnotes application
     #[clap(subcommand)]
     pub command: Commands,
@@ -1,5 +1,6 @@
 mod app;
 mod main;
+mod picker;
 
 pub use app::App;
-pub use main::Cli;
\ No newline at end of file
+pub use main::Cli;
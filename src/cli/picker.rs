@@ -0,0 +1,106 @@
+//! Arrow-key picker for choosing one of several notes on a terminal
+//!
+//! The list and the preview of the selected entry are drawn on stderr, so
+//! stdout only receives what the chosen action prints afterwards.
+use std::io;
+
+use console::{style, Key, Term};
+
+/// Number of entries listed at once; longer lists scroll
+const VISIBLE_ITEMS: usize = 10;
+
+/// Maximum number of preview lines shown under the list
+const PREVIEW_LINES: usize = 6;
+
+/// One entry of the picker
+pub struct PickerItem {
+    /// Line shown in the list
+    pub label: String,
+    /// Lines shown under the list while the entry is selected
+    pub preview: Vec<String>,
+}
+
+/// Lets the user move through `items` with the arrow keys (or `j`/`k`) and
+/// choose one with Enter
+///
+/// # Returns
+///
+/// The index of the chosen item, or `None` when the user left with Esc, `q`
+/// or Ctrl+C
+pub fn pick(term: &Term, prompt: &str, items: &[PickerItem]) -> io::Result<Option<usize>> {
+    if items.is_empty() {
+        return Ok(None);
+    }
+
+    term.hide_cursor()?;
+    let mut selected = 0;
+    let mut drawn = 0;
+    let choice = loop {
+        term.clear_last_lines(drawn)?;
+        drawn = draw(term, prompt, items, selected)?;
+
+        match term.read_key_raw()? {
+            Key::ArrowUp | Key::Char('k') => selected = selected.saturating_sub(1),
+            Key::ArrowDown | Key::Char('j') | Key::Tab => {
+                selected = (selected + 1).min(items.len() - 1)
+            }
+            Key::PageUp => selected = selected.saturating_sub(VISIBLE_ITEMS),
+            Key::PageDown => selected = (selected + VISIBLE_ITEMS).min(items.len() - 1),
+            Key::Home => selected = 0,
+            Key::End => selected = items.len() - 1,
+            Key::Enter => break Some(selected),
+            Key::Escape | Key::CtrlC | Key::Char('q') => break None,
+            _ => {}
+        }
+    };
+    term.clear_last_lines(drawn)?;
+    term.show_cursor()?;
+    Ok(choice)
+}
+
+/// Draws the list around the selected item and its preview
+///
+/// # Returns
+///
+/// The number of lines written, to clear them before the next draw
+fn draw(term: &Term, prompt: &str, items: &[PickerItem], selected: usize) -> io::Result<usize> {
+    let width = term.size().1 as usize;
+    let fit = |line: &str| console::truncate_str(line, width.saturating_sub(1), "…").into_owned();
+
+    // Scroll so the selected item stays in view
+    let first = (selected + 1).saturating_sub(VISIBLE_ITEMS);
+    let mut lines = vec![format!(
+        "{} {}",
+        style(prompt).bold(),
+        style(format!(
+            "({}/{}, ↑↓ to move, Enter to open, Esc to cancel)",
+            selected + 1,
+            items.len()
+        ))
+        .dim()
+    )];
+    for (i, item) in items.iter().enumerate().skip(first).take(VISIBLE_ITEMS) {
+        if i == selected {
+            lines.push(format!(
+                "{} {}",
+                style(">").cyan(),
+                style(&item.label).bold()
+            ));
+        } else {
+            lines.push(format!("  {}", item.label));
+        }
+    }
+    lines.push(String::new());
+    lines.extend(
+        items[selected]
+            .preview
+            .iter()
+            .take(PREVIEW_LINES)
+            .map(|line| format!("  {}", line)),
+    );
+
+    for line in &lines {
+        term.write_line(&fit(line))?;
+    }
+    Ok(lines.len())
+}
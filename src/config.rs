@@ -1,7 +1,128 @@
-use std::path::PathBuf;
+use std::{
+    fs,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
 
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use which::which;
-use serde::{Deserialize, Serialize};
+
+use crate::{
+    BackendKind, BackupRetention, BackupTargetConfig, CronSchedule, IdPolicy, KbError,
+    RedactionConfig, Result, SearchWeights, ServerConfig, StorageFormat, TagsConfig, WebhookConfig,
+    DEFAULT_CONTENT_WEIGHT, DEFAULT_TITLE_WEIGHT,
+};
+
+/// Name of the configuration file read from the kbnotes home directory when
+/// `--config` is not given
+pub const DEFAULT_CONFIG_FILE: &str = "config.json";
+
+/// Name of the default templates directory, next to the notes directory
+pub const TEMPLATES_DIR: &str = "templates";
+
+/// When note writes are forced to stable storage with `fsync`.
+///
+/// `Always` survives a power loss right after a save is reported, at the cost of
+/// two extra sync calls per write (noticeable on slow disks and bulk imports).
+/// `OnClose` defers syncing to shutdown, which the command line runs after
+/// every command, failed ones included, and on Ctrl+C. `Never` leaves it to
+/// the OS.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FsyncMode {
+    /// Sync the note file before the rename and its directory after it
+    #[default]
+    Always,
+    /// Sync every written file and directory once when storage shuts down
+    OnClose,
+    /// Never sync explicitly
+    Never,
+}
+
+impl std::fmt::Display for FsyncMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            FsyncMode::Always => "always",
+            FsyncMode::OnClose => "on_close",
+            FsyncMode::Never => "never",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Compression applied to the entries of full and incremental backup archives
+///
+/// `None` stores entries as they are, which is much faster for large vaults.
+/// Note files that are already zstd-compressed are always stored as they are.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupCompression {
+    /// Store entries uncompressed
+    None,
+    /// Deflate, readable by every ZIP tool
+    #[default]
+    Deflate,
+    /// Bzip2, smaller but slower than deflate
+    Bzip2,
+    /// Zstandard, fast with a good ratio, but not supported by every ZIP tool
+    Zstd,
+}
+
+impl BackupCompression {
+    /// The ZIP compression method of the entries
+    pub fn method(self) -> zip::CompressionMethod {
+        match self {
+            BackupCompression::None => zip::CompressionMethod::Stored,
+            BackupCompression::Deflate => zip::CompressionMethod::Deflated,
+            BackupCompression::Bzip2 => zip::CompressionMethod::Bzip2,
+            BackupCompression::Zstd => zip::CompressionMethod::Zstd,
+        }
+    }
+
+    /// Levels the method accepts; `None` when it takes no level
+    pub fn level_range(self) -> Option<RangeInclusive<i64>> {
+        match self {
+            BackupCompression::None => None,
+            BackupCompression::Deflate => Some(0..=9),
+            BackupCompression::Bzip2 => Some(1..=9),
+            BackupCompression::Zstd => Some(-7..=22),
+        }
+    }
+
+    /// Checks that `level` suits the method
+    pub fn check_level(self, level: Option<i64>) -> Result<()> {
+        let Some(level) = level else {
+            return Ok(());
+        };
+        match self.level_range() {
+            Some(range) if range.contains(&level) => Ok(()),
+            Some(range) => Err(KbError::ConfigError {
+                message: format!(
+                    "backup_compression_level {} is out of range for {}: expected {} to {}",
+                    level,
+                    self,
+                    range.start(),
+                    range.end()
+                ),
+            }),
+            None => Err(KbError::ConfigError {
+                message: format!("backup_compression {} takes no compression level", self),
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for BackupCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            BackupCompression::None => "none",
+            BackupCompression::Deflate => "deflate",
+            BackupCompression::Bzip2 => "bzip2",
+            BackupCompression::Zstd => "zstd",
+        };
+        write!(f, "{}", name)
+    }
+}
 
 /// Application configuration settings.
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -15,9 +136,66 @@ pub struct Config {
     /// How often to create backups (in hours)
     pub backup_frequency: u32,
 
+    /// Cron expression for scheduled backups, evaluated in UTC; when set it
+    /// replaces `backup_frequency`
+    #[serde(default)]
+    pub backup_schedule: Option<String>,
+
     /// Maximum number of backups to keep
     pub max_backups: u32,
 
+    /// Age-based rotation of full backups in the backup directory; when set
+    /// it decides which ones are kept instead of `max_backups`
+    #[serde(default)]
+    pub backup_retention: Option<BackupRetention>,
+
+    /// Whether scheduled backups only write the notes updated since the
+    /// previous backup, on top of a periodic full backup
+    #[serde(default)]
+    pub incremental_backups: bool,
+
+    /// With incremental backups, how many scheduled runs make up a chain:
+    /// one full backup followed by incremental ones (1 makes every backup full)
+    #[serde(default = "default_full_backup_every")]
+    pub full_backup_every: u32,
+
+    /// Whether shutting down storage takes one last full backup
+    #[serde(default)]
+    pub backup_on_shutdown: bool,
+
+    /// Remote targets every backup archive is uploaded to after it is written
+    #[serde(default)]
+    pub backup_targets: Vec<BackupTargetConfig>,
+
+    /// Compression method of backup archive entries
+    #[serde(default)]
+    pub backup_compression: BackupCompression,
+
+    /// Compression level of backup archive entries, within the range of
+    /// `backup_compression` (the method's default when unset)
+    #[serde(default)]
+    pub backup_compression_level: Option<i64>,
+
+    /// Number of notes directories kept aside by staged restores and
+    /// rollbacks, per kind (0 keeps all)
+    #[serde(default = "default_max_pre_restore_dirs")]
+    pub max_pre_restore_dirs: u32,
+
+    /// Number of previous versions kept per note in its revision history
+    /// (0 keeps all)
+    #[serde(default = "default_max_revisions")]
+    pub max_revisions: u32,
+
+    /// Number of per-note backup files kept per note in the backup directory
+    /// (0 keeps all)
+    #[serde(default = "default_max_note_backups")]
+    pub max_note_backups: u32,
+
+    /// Days the per-note backups of deleted notes are kept before
+    /// `backup prune` removes them (kept for good when unset)
+    #[serde(default = "default_orphan_backup_retention_days")]
+    pub orphan_backup_retention_days: Option<u32>,
+
     /// Whether to encrypt notes (for future extension)
     pub encrypt_notes: bool,
 
@@ -29,6 +207,71 @@ pub struct Config {
 
     /// Whether to enable auto-saving (for future extension)
     pub auto_backup: bool,
+
+    /// Durability mode for note writes
+    #[serde(default)]
+    pub fsync: FsyncMode,
+
+    /// Whether to suggest related notes to link after saving a note
+    #[serde(default)]
+    pub suggest_links: bool,
+
+    /// Redaction rules applied when exporting with `--redact`
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+
+    /// How note IDs are turned into file names and backup entries
+    #[serde(default)]
+    pub id_policy: IdPolicy,
+
+    /// Notes whose serialized size exceeds this many bytes are stored
+    /// zstd-compressed (never compressed when unset)
+    #[serde(default)]
+    pub compress_threshold_bytes: Option<u64>,
+
+    /// Format new and updated note files are written in (files in the other
+    /// formats are still read)
+    #[serde(default)]
+    pub storage_format: StorageFormat,
+
+    /// Where notes are stored: one file per note or a SQLite database
+    /// (changed with `kbnotes migrate-backend`, which moves the notes)
+    #[serde(default)]
+    pub backend: BackendKind,
+
+    /// Endpoints notified about note changes
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+
+    /// Per-tag policies (auto-archiving, backup and search exclusion, delete protection)
+    #[serde(default)]
+    pub tags: TagsConfig,
+
+    /// Server mode settings (which tags unauthenticated visitors may see)
+    #[serde(default)]
+    pub server: ServerConfig,
+
+    /// Directory holding note templates (`<name>.md`) for `create --template`
+    #[serde(default = "default_templates_dir")]
+    pub templates_dir: PathBuf,
+
+    /// Whether to keep an on-disk full-text index (under `notes_dir/.index`)
+    /// and search through it instead of scanning every note
+    #[serde(default)]
+    pub use_search_index: bool,
+
+    /// How much a search match in a note's title counts (2 when unset)
+    #[serde(default)]
+    pub search_title_weight: Option<u32>,
+
+    /// How much a search match in a note's content counts (1 when unset)
+    #[serde(default)]
+    pub search_content_weight: Option<u32>,
+
+    /// Days deleted notes stay in the trash before they are purged (kept
+    /// until `trash empty` when unset)
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: Option<u32>,
     // /// Auto-save interval in minutes (if auto_save is enabled) (for future extension)
     // pub auto_save_interval: u32,
 
@@ -37,6 +280,264 @@ pub struct Config {
 }
 
 impl Config {
+    /// Creates a configuration with default settings for the given directories
+    pub fn with_dirs(notes_dir: PathBuf, backup_dir: PathBuf) -> Self {
+        // Next to the notes directory, e.g. ~/.kbnotes/templates
+        let templates_dir = notes_dir.parent().unwrap_or(&notes_dir).join(TEMPLATES_DIR);
+
+        Config {
+            notes_dir,
+            backup_dir,
+            backup_frequency: 24, // Daily backups
+            backup_schedule: None,
+            max_backups: 10, // Keep 10 backups
+            backup_retention: None,
+            incremental_backups: false,
+            full_backup_every: default_full_backup_every(),
+            backup_on_shutdown: false,
+            backup_targets: Vec::new(),
+            backup_compression: BackupCompression::default(),
+            backup_compression_level: None,
+            max_pre_restore_dirs: default_max_pre_restore_dirs(),
+            max_revisions: default_max_revisions(),
+            max_note_backups: default_max_note_backups(),
+            orphan_backup_retention_days: default_orphan_backup_retention_days(),
+            encrypt_notes: false, // No encryption by default
+            editor_command: None, // No custom editor
+            auto_save: true,      // Auto-save enabled
+            auto_backup: true,    // Auto-backup enabled
+            fsync: FsyncMode::Always,
+            suggest_links: false, // No link suggestions by default
+            redaction: RedactionConfig::default(),
+            id_policy: IdPolicy::default(),
+            compress_threshold_bytes: None, // No compression by default
+            storage_format: StorageFormat::default(),
+            backend: BackendKind::default(),
+            webhooks: Vec::new(),
+            tags: TagsConfig::default(),
+            server: ServerConfig::default(),
+            templates_dir,
+            use_search_index: false, // Scan notes in memory by default
+            search_title_weight: None,
+            search_content_weight: None,
+            trash_retention_days: default_trash_retention_days(),
+        }
+    }
+
+    /// Settings that can be changed with `config --set`
+    pub const SETTABLE_KEYS: &'static [&'static str] = &[
+        "notes_dir",
+        "backup_dir",
+        "backup_frequency",
+        "backup_schedule",
+        "max_backups",
+        "backup_retention",
+        "incremental_backups",
+        "full_backup_every",
+        "backup_on_shutdown",
+        "backup_compression",
+        "backup_compression_level",
+        "max_pre_restore_dirs",
+        "max_revisions",
+        "max_note_backups",
+        "orphan_backup_retention_days",
+        "encrypt_notes",
+        "editor_command",
+        "auto_save",
+        "auto_backup",
+        "fsync",
+        "suggest_links",
+        "id_policy",
+        "compress_threshold_bytes",
+        "storage_format",
+        "templates_dir",
+        "use_search_index",
+        "search_title_weight",
+        "search_content_weight",
+        "trash_retention_days",
+    ];
+
+    /// Parses `value` for the setting `key` and applies it
+    ///
+    /// # Arguments
+    /// * `key` - One of [`Config::SETTABLE_KEYS`]
+    /// * `value` - The new value; `none` or an empty value clears optional settings
+    ///
+    /// # Returns
+    /// * `Result<()>` - `ConfigError` for unknown keys and unparsable values
+    pub fn set_value(&mut self, key: &str, value: &str) -> Result<()> {
+        let value = value.trim();
+        match key {
+            "notes_dir" => self.notes_dir = parse_path(key, value)?,
+            "backup_dir" => self.backup_dir = parse_path(key, value)?,
+            "backup_frequency" => {
+                let hours: u32 = parse_value(key, value, "a positive number of hours")?;
+                if hours == 0 {
+                    return Err(KbError::ConfigError {
+                        message: "backup_frequency cannot be zero".to_string(),
+                    });
+                }
+                self.backup_frequency = hours;
+            }
+            "backup_schedule" => {
+                let schedule = parse_optional(value, |v| Ok(v.to_string()))?;
+                if let Some(expression) = &schedule {
+                    CronSchedule::parse(expression)?;
+                }
+                self.backup_schedule = schedule;
+            }
+            "max_backups" => self.max_backups = parse_value(key, value, "a number")?,
+            "backup_retention" => {
+                self.backup_retention = parse_optional(value, BackupRetention::parse)?
+            }
+            "incremental_backups" => {
+                self.incremental_backups = parse_value(key, value, "true or false")?
+            }
+            "full_backup_every" => {
+                let runs: u32 = parse_value(key, value, "a positive number of backups")?;
+                if runs == 0 {
+                    return Err(KbError::ConfigError {
+                        message: "full_backup_every cannot be zero".to_string(),
+                    });
+                }
+                self.full_backup_every = runs;
+            }
+            "backup_on_shutdown" => {
+                self.backup_on_shutdown = parse_value(key, value, "true or false")?
+            }
+            "backup_compression" => {
+                let compression: BackupCompression =
+                    parse_variant(key, value, "none, deflate, bzip2 or zstd")?;
+                compression.check_level(self.backup_compression_level)?;
+                self.backup_compression = compression;
+            }
+            "backup_compression_level" => {
+                let level = parse_optional(value, |v| parse_value(key, v, "a number or none"))?;
+                self.backup_compression.check_level(level)?;
+                self.backup_compression_level = level;
+            }
+            "max_pre_restore_dirs" => {
+                self.max_pre_restore_dirs = parse_value(key, value, "a number")?
+            }
+            "max_revisions" => self.max_revisions = parse_value(key, value, "a number")?,
+            "max_note_backups" => self.max_note_backups = parse_value(key, value, "a number")?,
+            "orphan_backup_retention_days" => {
+                let days =
+                    parse_optional(value, |v| parse_value(key, v, "a number of days or none"))?;
+                if days == Some(0) {
+                    return Err(KbError::ConfigError {
+                        message: "orphan_backup_retention_days cannot be zero".to_string(),
+                    });
+                }
+                self.orphan_backup_retention_days = days;
+            }
+            "encrypt_notes" => self.encrypt_notes = parse_value(key, value, "true or false")?,
+            "editor_command" => self.editor_command = parse_optional(value, |v| Ok(v.to_string()))?,
+            "auto_save" => self.auto_save = parse_value(key, value, "true or false")?,
+            "auto_backup" => self.auto_backup = parse_value(key, value, "true or false")?,
+            "fsync" => self.fsync = parse_variant(key, value, "always, on_close or never")?,
+            "suggest_links" => self.suggest_links = parse_value(key, value, "true or false")?,
+            "id_policy" => {
+                self.id_policy = parse_variant(key, value, "standard, strict_ascii or permissive")?
+            }
+            "compress_threshold_bytes" => {
+                self.compress_threshold_bytes =
+                    parse_optional(value, |v| parse_value(key, v, "a number of bytes or none"))?
+            }
+            "storage_format" => {
+                self.storage_format = parse_variant(key, value, "json, markdown or split")?
+            }
+            "backend" => {
+                return Err(KbError::ConfigError {
+                    message: "The backend holds the notes, so changing it means moving them: \
+                              run `kbnotes migrate-backend <files|sqlite>`"
+                        .to_string(),
+                })
+            }
+            "templates_dir" => self.templates_dir = parse_path(key, value)?,
+            "use_search_index" => self.use_search_index = parse_value(key, value, "true or false")?,
+            "search_title_weight" | "search_content_weight" => {
+                let weight = parse_optional(value, |v| parse_value(key, v, "a number or none"))?;
+                if weight == Some(0) {
+                    return Err(KbError::ConfigError {
+                        message: format!("{} cannot be zero", key),
+                    });
+                }
+                if key == "search_title_weight" {
+                    self.search_title_weight = weight;
+                } else {
+                    self.search_content_weight = weight;
+                }
+            }
+            "trash_retention_days" => {
+                let days =
+                    parse_optional(value, |v| parse_value(key, v, "a number of days or none"))?;
+                if days == Some(0) {
+                    return Err(KbError::ConfigError {
+                        message: "trash_retention_days cannot be zero".to_string(),
+                    });
+                }
+                self.trash_retention_days = days;
+            }
+            _ => {
+                return Err(KbError::ConfigError {
+                    message: format!(
+                        "Unknown configuration key '{}'. Valid keys: {}",
+                        key,
+                        Self::SETTABLE_KEYS.join(", ")
+                    ),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks settings whose values depend on each other, which a
+    /// configuration file can get wrong
+    pub fn validate(&self) -> Result<()> {
+        self.backup_compression
+            .check_level(self.backup_compression_level)?;
+        if let Some(retention) = &self.backup_retention {
+            retention.validate()?;
+        }
+        self.cron_schedule().map(|_| ())
+    }
+
+    /// A copy with webhook secrets and S3 secret access keys removed, as
+    /// written into backups
+    pub fn without_secrets(&self) -> Config {
+        let mut config = self.clone();
+        for webhook in &mut config.webhooks {
+            webhook.secret = None;
+        }
+        for target in &mut config.backup_targets {
+            if let BackupTargetConfig::S3(s3) = target {
+                s3.secret_access_key = None;
+            }
+        }
+        config
+    }
+
+    /// The parsed `backup_schedule`, if one is set
+    pub fn cron_schedule(&self) -> Result<Option<CronSchedule>> {
+        self.backup_schedule
+            .as_deref()
+            .map(CronSchedule::parse)
+            .transpose()
+    }
+
+    /// Weights of title and content matches in search scores, falling back
+    /// to the defaults for unset (or zero) weights
+    pub fn search_weights(&self) -> SearchWeights {
+        let weight = |configured: Option<u32>, default: u32| {
+            configured.filter(|&weight| weight > 0).unwrap_or(default) as i64
+        };
+        SearchWeights {
+            title: weight(self.search_title_weight, DEFAULT_TITLE_WEIGHT),
+            content: weight(self.search_content_weight, DEFAULT_CONTENT_WEIGHT),
+        }
+    }
+
     // This method provides smart fallbacks when no editor is configured
     pub fn get_editor_command(&self) -> String {
         // First try the configured editor
@@ -65,3 +566,210 @@ impl Config {
         }
     }
 }
+
+fn default_full_backup_every() -> u32 {
+    7
+}
+
+fn default_max_pre_restore_dirs() -> u32 {
+    3
+}
+
+fn default_max_revisions() -> u32 {
+    20
+}
+
+fn default_max_note_backups() -> u32 {
+    10
+}
+
+fn default_orphan_backup_retention_days() -> Option<u32> {
+    Some(90)
+}
+
+fn default_trash_retention_days() -> Option<u32> {
+    Some(30)
+}
+
+fn default_templates_dir() -> PathBuf {
+    kbnotes_home_dir()
+        .map(|dir| dir.join(TEMPLATES_DIR))
+        .unwrap_or_else(|_| PathBuf::from(TEMPLATES_DIR))
+}
+
+/// Where the effective value of a configuration setting comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// The built-in default
+    Default,
+    /// The configuration file
+    File,
+    /// A command-line option such as `--notes-dir`
+    CommandLine,
+}
+
+impl std::fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ConfigSource::Default => "default",
+            ConfigSource::File => "file",
+            ConfigSource::CommandLine => "cli override",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The configuration file behind the effective configuration, and which
+/// settings it and the command line provide
+#[derive(Debug, Clone, Default)]
+pub struct ConfigOrigin {
+    /// File the configuration is read from and written to by `config --set`
+    pub path: PathBuf,
+
+    /// Settings defined in the configuration file
+    pub file_keys: Vec<String>,
+
+    /// Settings overridden on the command line
+    pub cli_keys: Vec<String>,
+}
+
+impl ConfigOrigin {
+    /// Returns where the effective value of `key` comes from
+    pub fn source_of(&self, key: &str) -> ConfigSource {
+        if self.cli_keys.iter().any(|k| k == key) {
+            ConfigSource::CommandLine
+        } else if self.file_keys.iter().any(|k| k == key) {
+            ConfigSource::File
+        } else {
+            ConfigSource::Default
+        }
+    }
+}
+
+/// Returns the kbnotes home directory (`~/.kbnotes`)
+pub fn kbnotes_home_dir() -> Result<PathBuf> {
+    let home_dir = dirs::home_dir().ok_or_else(|| KbError::ApplicationError {
+        message: "Could not determine home directory".to_string(),
+    })?;
+    Ok(home_dir.join(".kbnotes"))
+}
+
+/// Path of the configuration file used when `--config` is not given
+pub fn default_config_path() -> Result<PathBuf> {
+    Ok(kbnotes_home_dir()?.join(DEFAULT_CONFIG_FILE))
+}
+
+/// Load the default configuration
+pub fn load_default_config() -> Result<Config> {
+    let kbnotes_dir = kbnotes_home_dir()?;
+    let notes_dir = kbnotes_dir.join("notes");
+    let backup_dir = kbnotes_dir.join("backups");
+
+    Ok(Config::with_dirs(notes_dir, backup_dir))
+}
+
+/// Load configuration from a file
+///
+/// Settings missing from the file keep their default values.
+///
+/// # Arguments
+/// * `config_path` - Path to a JSON configuration file
+///
+/// # Returns
+/// * `Result<(Config, Vec<String>)>` - The configuration and the settings the file defines
+pub fn load_config_from_file(config_path: &Path) -> Result<(Config, Vec<String>)> {
+    let config_file = fs::read_to_string(config_path).map_err(KbError::Io)?;
+
+    // Try to parse as JSON first
+    if config_path.extension().is_some_and(|ext| ext == "json") {
+        let file_value: serde_json::Value = serde_json::from_str(&config_file)?;
+        let serde_json::Value::Object(file_settings) = file_value else {
+            return Err(KbError::ConfigError {
+                message: format!(
+                    "Configuration file {} must contain a JSON object",
+                    config_path.display()
+                ),
+            });
+        };
+
+        let mut merged = serde_json::to_value(load_default_config()?)?;
+        let file_keys = file_settings.keys().cloned().collect();
+        if let serde_json::Value::Object(settings) = &mut merged {
+            settings.extend(file_settings);
+        }
+        let config: Config = serde_json::from_value(merged)?;
+        config.validate()?;
+        return Ok((config, file_keys));
+    }
+
+    // // Try to parse as TOML if not JSON
+    // if config_path.extension().is_some_and(|ext| ext == "toml") {
+    //     return toml::from_str(&config_file).map_err(|e| KbError::ApplicationError {
+    //         message: format!("Failed to parse TOML config: {}", e),
+    //     });
+    // }
+
+    // // Try YAML as a last resort
+    // if config_path.extension().is_some_and(|ext| ext == "yaml" || ext == "yml") {
+    //     return serde_yaml::from_str(&config_file).map_err(|e| KbError::ApplicationError {
+    //         message: format!("Failed to parse YAML config: {}", e)
+    //     });
+    // }
+
+    Err(KbError::ApplicationError {
+        message: format!("Unsupported config file format: {}", config_path.display()),
+    })
+}
+
+/// Writes a configuration to a JSON file, creating its directory if needed
+pub fn save_config_to_file(config: &Config, config_path: &Path) -> Result<()> {
+    if let Some(parent) = config_path.parent() {
+        fs::create_dir_all(parent).map_err(|_| KbError::DirectoryError {
+            path: parent.to_path_buf(),
+        })?;
+    }
+
+    let json = serde_json::to_string_pretty(config)?;
+    fs::write(config_path, json).map_err(KbError::Io)
+}
+
+/// Parses a setting with its `FromStr` implementation
+fn parse_value<T: std::str::FromStr>(key: &str, value: &str, expected: &str) -> Result<T> {
+    value.parse().map_err(|_| KbError::ConfigError {
+        message: format!(
+            "Invalid value '{}' for {}: expected {}",
+            value, key, expected
+        ),
+    })
+}
+
+/// Parses a setting stored as a snake_case enum variant
+fn parse_variant<T: DeserializeOwned>(key: &str, value: &str, expected: &str) -> Result<T> {
+    serde_json::from_value(serde_json::Value::String(value.to_lowercase())).map_err(|_| {
+        KbError::ConfigError {
+            message: format!(
+                "Invalid value '{}' for {}: expected {}",
+                value, key, expected
+            ),
+        }
+    })
+}
+
+/// Parses an optional setting, where `none` or an empty value clears it
+fn parse_optional<T>(value: &str, parse: impl FnOnce(&str) -> Result<T>) -> Result<Option<T>> {
+    if value.is_empty() || value.eq_ignore_ascii_case("none") {
+        Ok(None)
+    } else {
+        parse(value).map(Some)
+    }
+}
+
+/// Parses a directory setting
+fn parse_path(key: &str, value: &str) -> Result<PathBuf> {
+    if value.is_empty() {
+        return Err(KbError::ConfigError {
+            message: format!("{} cannot be empty", key),
+        });
+    }
+    Ok(PathBuf::from(value))
+}
@@ -0,0 +1,143 @@
+//! Due dates of notes.
+//!
+//! Due dates are stored in UTC but entered and grouped in local time: a bare
+//! date such as `2024-06-01` is due at the start of that local day, and a note
+//! counts as due today when its due date falls on the current local date.
+use chrono::{DateTime, Days, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+
+use crate::{KbError, Note, Result};
+
+/// Where a due date falls relative to the current day
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DueGroup {
+    /// Due before today
+    Overdue,
+    /// Due some time today
+    Today,
+    /// Due after today
+    Upcoming,
+}
+
+impl DueGroup {
+    /// Every group, in display order
+    pub const ALL: [DueGroup; 3] = [DueGroup::Overdue, DueGroup::Today, DueGroup::Upcoming];
+
+    /// Returns the group of a due date
+    ///
+    /// # Arguments
+    ///
+    /// * `due_at` - The due date
+    /// * `today` - The current local date
+    pub fn of(due_at: DateTime<Utc>, today: NaiveDate) -> Self {
+        let day = due_at.with_timezone(&Local).date_naive();
+        match day.cmp(&today) {
+            std::cmp::Ordering::Less => DueGroup::Overdue,
+            std::cmp::Ordering::Equal => DueGroup::Today,
+            std::cmp::Ordering::Greater => DueGroup::Upcoming,
+        }
+    }
+
+    /// Heading of the group in `due` output
+    pub fn label(&self) -> &'static str {
+        match self {
+            DueGroup::Overdue => "Overdue",
+            DueGroup::Today => "Today",
+            DueGroup::Upcoming => "Upcoming",
+        }
+    }
+}
+
+/// Parses a `--due` value
+///
+/// # Arguments
+///
+/// * `value` - An RFC 3339 timestamp, a `YYYY-MM-DD` date, `today`,
+///   `tomorrow`, or a number of days or weeks from today (`+3d`, `+2w`)
+/// * `today` - The current local date, which relative forms count from
+///
+/// # Returns
+///
+/// The due date (dates stand for the start of the local day), or
+/// `KbError::InvalidFormat` naming the accepted forms
+pub fn parse_due_date(value: &str, today: NaiveDate) -> Result<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    let invalid = || KbError::InvalidFormat {
+        message: format!(
+            "Invalid due date '{}': expected YYYY-MM-DD, RFC 3339, today, tomorrow, +<n>d or +<n>w",
+            value
+        ),
+    };
+
+    let lower = value.to_lowercase();
+    let day = match lower.as_str() {
+        "today" => today,
+        "tomorrow" => today.checked_add_days(Days::new(1)).ok_or_else(invalid)?,
+        _ => match lower.strip_prefix('+') {
+            Some(offset) => {
+                let (count, unit) = offset.split_at(offset.len().saturating_sub(1));
+                let count: u64 = count.parse().map_err(|_| invalid())?;
+                let days = match unit {
+                    "d" => count,
+                    "w" => count.checked_mul(7).ok_or_else(invalid)?,
+                    _ => return Err(invalid()),
+                };
+                today
+                    .checked_add_days(Days::new(days))
+                    .ok_or_else(invalid)?
+            }
+            None => NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| invalid())?,
+        },
+    };
+
+    // The earliest instant, for days starting inside a DST gap
+    Local
+        .from_local_datetime(&day.and_time(NaiveTime::MIN))
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(invalid)
+}
+
+/// Formats a due date in local time, leaving out a midnight time of day
+pub fn format_due_date(due_at: DateTime<Utc>) -> String {
+    let local = due_at.with_timezone(&Local);
+    if local.time() == NaiveTime::MIN {
+        local.format("%Y-%m-%d").to_string()
+    } else {
+        local.format("%Y-%m-%d %H:%M").to_string()
+    }
+}
+
+/// Sorts the notes that have a due date into groups
+///
+/// # Arguments
+///
+/// * `notes` - Notes to group; notes without a due date are skipped
+/// * `today` - The current local date
+///
+/// # Returns
+///
+/// The non-empty groups in [`DueGroup::ALL`] order, each sorted by due date
+pub fn group_by_due_date(notes: Vec<Note>, today: NaiveDate) -> Vec<(DueGroup, Vec<Note>)> {
+    let mut due: Vec<(DateTime<Utc>, Note)> = notes
+        .into_iter()
+        .filter_map(|note| note.due_at.map(|due_at| (due_at, note)))
+        .collect();
+    due.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.id.cmp(&b.1.id)));
+
+    DueGroup::ALL
+        .into_iter()
+        .map(|group| {
+            let notes: Vec<Note> = due
+                .iter()
+                .filter(|(due_at, _)| DueGroup::of(*due_at, today) == group)
+                .map(|(_, note)| note.clone())
+                .collect();
+            (group, notes)
+        })
+        .filter(|(_, notes)| !notes.is_empty())
+        .collect()
+}
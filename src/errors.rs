@@ -23,10 +23,52 @@ pub enum KbError {
     #[error("Zip error: {0}")]
     ZipError(#[from] zip::result::ZipError),
 
+    /// Errors of the SQLite storage backend.
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+
     /// Note was not found when performing an operation.
     #[error("Note not found: {id}")]
     NoteNotFound { id: String },
 
+    /// A tag policy protects the note from deletion.
+    #[error("Note {id} is protected from deletion by its tag policy (use --force to delete it)")]
+    NoteProtected { id: String },
+
+    /// The note is locked, so it cannot be changed or deleted.
+    #[error("Note {id} is locked (unlock it with `kbnotes unlock {id}`, or use --force)")]
+    NoteLocked { id: String },
+
+    /// A note reference (title or alias) matches more than one note.
+    #[error("Ambiguous note reference '{reference}': matches notes {}", .candidates.join(", "))]
+    AmbiguousReference {
+        reference: String,
+        candidates: Vec<String>,
+    },
+
+    /// No heading of the note matches the requested section.
+    #[error("Section not found: {name}")]
+    SectionNotFound { name: String },
+
+    /// A section name matches several headings of the note.
+    #[error("Ambiguous section '{name}': matches {}", .candidates.join(", "))]
+    AmbiguousSection {
+        name: String,
+        candidates: Vec<String>,
+    },
+
+    /// The note has no attachment with the given file name.
+    #[error("Note {note_id} has no attachment named '{filename}'")]
+    AttachmentNotFound { note_id: String, filename: String },
+
+    /// No template with the given name exists.
+    #[error("Template '{name}' not found in {}. {}", .dir.display(), available_templates(.available))]
+    TemplateNotFound {
+        name: String,
+        dir: PathBuf,
+        available: Vec<String>,
+    },
+
     /// Note with the same ID already exists.
     #[error("Note already exists: {id}")]
     NoteAlreadyExists { id: String },
@@ -72,4 +114,25 @@ pub enum KbError {
 
     #[error("{message}")]
     EditorError { message: String },
+
+    /// An argument passed by an embedding application is invalid
+    #[error("Invalid argument: {message}")]
+    InvalidArgument { message: String },
+
+    /// Two distinct note IDs map to the same file
+    #[error("Note ID {id} maps to {path}, which already holds note {existing}")]
+    IdCollision {
+        id: String,
+        existing: String,
+        path: PathBuf,
+    },
+}
+
+/// Lists the template names for `TemplateNotFound`
+fn available_templates(names: &[String]) -> String {
+    if names.is_empty() {
+        "No templates exist yet; create one with `kbnotes template new <name>`".to_string()
+    } else {
+        format!("Available templates: {}", names.join(", "))
+    }
 }
@@ -0,0 +1,997 @@
+//! Example invocations for every subcommand.
+//!
+//! Examples are registered as data rather than embedded in help strings, so the
+//! `--help` output and `kbnotes examples` are generated from the same source and
+//! every entry can be checked against the actual [`Cli`] definition.
+use clap::{CommandFactory, FromArgMatches};
+
+use crate::{Cli, KbError, Result};
+
+/// A single example invocation of a subcommand
+#[derive(Debug, Clone, Copy)]
+pub struct CommandExample {
+    /// Arguments following `kbnotes`, starting with the subcommand name
+    pub args: &'static [&'static str],
+    /// What the invocation does
+    pub description: &'static str,
+}
+
+/// Examples registered for one subcommand
+#[derive(Debug, Clone, Copy)]
+pub struct CommandExamples {
+    /// Name of the subcommand
+    pub command: &'static str,
+    /// Example invocations, in display order
+    pub examples: &'static [CommandExample],
+}
+
+/// Every registered example, grouped by subcommand
+pub const COMMAND_EXAMPLES: &[CommandExamples] = &[
+    CommandExamples {
+        command: "create",
+        examples: &[
+            CommandExample {
+                args: &[
+                    "create",
+                    "--title",
+                    "Meeting notes",
+                    "--tags",
+                    "work,meeting",
+                ],
+                description: "Create a tagged note, writing its content in your editor",
+            },
+            CommandExample {
+                args: &[
+                    "create",
+                    "-T",
+                    "Quick idea",
+                    "--content",
+                    "Try the new parser",
+                ],
+                description: "Create a note with inline content",
+            },
+            CommandExample {
+                args: &["create", "--title", "Reading list", "--file", "reading.md"],
+                description: "Create a note from the contents of a file",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "view",
+        examples: &[
+            CommandExample {
+                args: &["view", "abc123"],
+                description: "Show a note",
+            },
+            CommandExample {
+                args: &["view", "abc123", "--json"],
+                description: "Show a note as raw JSON",
+            },
+            CommandExample {
+                args: &["view", "abc123", "--edit"],
+                description: "Edit a note in your editor, then show it",
+            },
+            CommandExample {
+                args: &["view", "abc123", "--section", "Setup > Install"],
+                description: "Show one section of a note, including its subsections",
+            },
+            CommandExample {
+                args: &["view", "abc123", "--lines", "40:80", "--no-pager"],
+                description: "Print lines 40 to 80 without a pager",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "stats",
+        examples: &[
+            CommandExample {
+                args: &["stats"],
+                description: "Show note counts, tag usage and sizes for the whole vault",
+            },
+            CommandExample {
+                args: &["stats", "--format", "json"],
+                description: "Print the vault statistics as JSON, e.g. to track growth",
+            },
+            CommandExample {
+                args: &["stats", "abc123"],
+                description: "Show the length and reading time of one note",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "list",
+        examples: &[
+            CommandExample {
+                args: &["list", "--tag", "work"],
+                description: "List notes tagged 'work'",
+            },
+            CommandExample {
+                args: &["list", "--tag", "rust", "--tag", "async", "--all-tags"],
+                description: "List notes tagged both 'rust' and 'async'",
+            },
+            CommandExample {
+                args: &["list", "--search", "meeting", "--sort-by", "title"],
+                description: "List notes mentioning 'meeting', sorted by title",
+            },
+            CommandExample {
+                args: &["list", "--tag", "work", "--limit", "5", "--format", "json"],
+                description: "List up to five work notes as JSON",
+            },
+            CommandExample {
+                args: &["list", "--since", "2024-05-01T00:00:00Z"],
+                description: "List notes modified since a point in time",
+            },
+            CommandExample {
+                args: &["list", "--has", "tasks,code"],
+                description: "List notes with unchecked tasks and code blocks",
+            },
+            CommandExample {
+                args: &[
+                    "list",
+                    "--exclude-tag",
+                    "scratch",
+                    "--created-after",
+                    "2024-05-01",
+                ],
+                description: "List notes created since May 1st, hiding scratch notes",
+            },
+            CommandExample {
+                args: &["list", "--page-size", "100", "--format", "json"],
+                description: "List notes 100 at a time as JSON, with the cursor of the next page",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "search",
+        examples: &[
+            CommandExample {
+                args: &["search", "project ideas"],
+                description: "Search titles and content",
+            },
+            CommandExample {
+                args: &["search", "docker AND compose NOT kubernetes"],
+                description: "Combine search terms with AND, OR and NOT",
+            },
+            CommandExample {
+                args: &["search", "\"release checklist\" OR deploy"],
+                description: "Require an exact phrase, or another term",
+            },
+            CommandExample {
+                args: &["search", "E0502", "--exact"],
+                description: "Find an error code literally, without fuzzy matches",
+            },
+            CommandExample {
+                args: &["search", "roadmap", "--show-scores"],
+                description: "Show how well each result matched, and where",
+            },
+            CommandExample {
+                args: &["search", "docker", "--interactive", "--then", "edit"],
+                description: "Pick a result with the arrow keys and edit it",
+            },
+            CommandExample {
+                args: &["search", "roadmap", "--title-only"],
+                description: "Only match note titles",
+            },
+            CommandExample {
+                args: &["search", "meeting", "--include-content"],
+                description: "Search and show the full content of matches",
+            },
+            CommandExample {
+                args: &["search", "todo", "--limit", "5", "--format", "json"],
+                description: "Show the five best matches as JSON",
+            },
+            CommandExample {
+                args: &["search", "todo", "--limit", "20", "--offset", "20"],
+                description: "Show the second page of 20 matches",
+            },
+            CommandExample {
+                args: &["search", "design", "--has", "broken-links"],
+                description: "Find matching notes that link to notes that don't exist",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "edit",
+        examples: &[
+            CommandExample {
+                args: &["edit", "abc123", "--title", "Updated Title"],
+                description: "Rename a note (the old title stays usable as an alias)",
+            },
+            CommandExample {
+                args: &["edit", "abc123", "--content", "New content"],
+                description: "Replace a note's content",
+            },
+            CommandExample {
+                args: &["edit", "abc123", "--file", "updates.md"],
+                description: "Replace a note's content with a file",
+            },
+            CommandExample {
+                args: &["edit", "abc123", "--edit"],
+                description: "Edit a note's content in your editor",
+            },
+            CommandExample {
+                args: &["edit", "abc123", "--add-tags", "important,follow-up"],
+                description: "Add tags to a note",
+            },
+            CommandExample {
+                args: &[
+                    "edit",
+                    "abc123",
+                    "--set-meta",
+                    "status=draft",
+                    "--unset-meta",
+                    "reviewer",
+                ],
+                description: "Set one metadata entry of a note and remove another",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "attach",
+        examples: &[
+            CommandExample {
+                args: &["attach", "abc123", "receipt.pdf"],
+                description: "Attach a PDF to a note",
+            },
+            CommandExample {
+                args: &["attach", "abc123", "--remove", "receipt.pdf"],
+                description: "Remove an attachment and delete its stored copy",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "here",
+        examples: &[
+            CommandExample {
+                args: &["here"],
+                description: "Show the note of the current project directory",
+            },
+            CommandExample {
+                args: &["here", "--create"],
+                description: "Create a note for the current project directory",
+            },
+            CommandExample {
+                args: &["here", "--list"],
+                description: "List all project notes, flagging stale paths",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "inbox",
+        examples: &[
+            CommandExample {
+                args: &["inbox", "--tag", "inbox"],
+                description: "List unread notes tagged 'inbox'",
+            },
+            CommandExample {
+                args: &["inbox", "--mark-read", "abc123,def456"],
+                description: "Mark two notes as read",
+            },
+            CommandExample {
+                args: &["inbox", "--tag", "inbox", "--mark-read"],
+                description: "Mark every unread 'inbox' note as read",
+            },
+            CommandExample {
+                args: &["inbox", "--mark-unread", "abc123"],
+                description: "Put a note back into the inbox",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "link",
+        examples: &[CommandExample {
+            args: &["link", "abc123", "def456"],
+            description: "Add a link to note def456 in the Related section of abc123",
+        }],
+    },
+    CommandExamples {
+        command: "alias",
+        examples: &[
+            CommandExample {
+                args: &["alias", "add", "abc123", "Old title"],
+                description: "Make a note resolvable by another name",
+            },
+            CommandExample {
+                args: &["alias", "list", "abc123"],
+                description: "Show the aliases of a note",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "snapshot",
+        examples: &[
+            CommandExample {
+                args: &["snapshot", "create", "abc123", "--name", "before-rewrite"],
+                description: "Take a named snapshot of a note",
+            },
+            CommandExample {
+                args: &["snapshot", "diff", "abc123", "before-rewrite"],
+                description: "Compare a snapshot with the live note",
+            },
+            CommandExample {
+                args: &[
+                    "snapshot",
+                    "restore",
+                    "abc123",
+                    "before-rewrite",
+                    "--as-copy",
+                ],
+                description: "Restore a snapshot into a new note",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "policies",
+        examples: &[
+            CommandExample {
+                args: &["policies", "show"],
+                description: "List the tag policies and the notes they apply to",
+            },
+            CommandExample {
+                args: &["policies", "apply", "--dry-run"],
+                description: "Preview which notes auto-archiving would archive",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "delete",
+        examples: &[
+            CommandExample {
+                args: &["delete", "abc123"],
+                description: "Delete a note after confirmation",
+            },
+            CommandExample {
+                args: &["delete", "abc123", "--force"],
+                description: "Delete a note without asking",
+            },
+            CommandExample {
+                args: &["delete", "abc123", "--force", "--permanent"],
+                description: "Delete a note for good, skipping the trash",
+            },
+            CommandExample {
+                args: &["delete", "--tag", "temp"],
+                description: "Delete every note tagged temp after confirmation",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "trash",
+        examples: &[
+            CommandExample {
+                args: &["trash", "list"],
+                description: "List deleted notes",
+            },
+            CommandExample {
+                args: &["trash", "restore", "abc123"],
+                description: "Bring a deleted note back",
+            },
+            CommandExample {
+                args: &["trash", "empty", "--older-than", "30d"],
+                description: "Purge notes deleted more than 30 days ago",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "tag",
+        examples: &[
+            CommandExample {
+                args: &["tag", "abc123", "--add", "urgent,review"],
+                description: "Add tags to a note",
+            },
+            CommandExample {
+                args: &[
+                    "tag",
+                    "--filter-tag",
+                    "temp",
+                    "--add",
+                    "archive-2023",
+                    "--remove",
+                    "temp",
+                ],
+                description: "Retag every note tagged temp",
+            },
+            CommandExample {
+                args: &["tag", "abc123", "--list"],
+                description: "List the tags of a note",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "notebooks",
+        examples: &[
+            CommandExample {
+                args: &["notebooks"],
+                description: "Show the notebook tree with note counts",
+            },
+            CommandExample {
+                args: &["notebooks", "work"],
+                description: "Show the notebooks below work",
+            },
+            CommandExample {
+                args: &["list", "--notebook", "work/project-x"],
+                description: "List the notes of a notebook",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "lock",
+        examples: &[
+            CommandExample {
+                args: &["lock", "abc123"],
+                description: "Make a note read-only",
+            },
+            CommandExample {
+                args: &["edit", "abc123", "--title", "Final", "--force"],
+                description: "Edit a locked note without unlocking it",
+            },
+            CommandExample {
+                args: &["unlock", "abc123"],
+                description: "Allow changes to a note again",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "unlock",
+        examples: &[CommandExample {
+            args: &["unlock", "abc123"],
+            description: "Allow changes to a locked note again",
+        }],
+    },
+    CommandExamples {
+        command: "pin",
+        examples: &[
+            CommandExample {
+                args: &["pin", "abc123"],
+                description: "List a note before all unpinned ones",
+            },
+            CommandExample {
+                args: &["list", "--sort-by", "title"],
+                description: "Pinned notes stay on top whatever the sort order",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "unpin",
+        examples: &[CommandExample {
+            args: &["unpin", "abc123"],
+            description: "Return a pinned note to its normal place in the list",
+        }],
+    },
+    CommandExamples {
+        command: "archive",
+        examples: &[CommandExample {
+            args: &["archive", "abc123"],
+            description: "Hide a note from list and search without deleting it",
+        }],
+    },
+    CommandExamples {
+        command: "unarchive",
+        examples: &[CommandExample {
+            args: &["unarchive", "abc123"],
+            description: "Bring an archived note back into list and search",
+        }],
+    },
+    CommandExamples {
+        command: "due",
+        examples: &[
+            CommandExample {
+                args: &["due"],
+                description: "Show overdue notes, notes due today and upcoming ones",
+            },
+            CommandExample {
+                args: &["edit", "abc123", "--due", "+3d"],
+                description: "Make a note due in three days",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "tasks",
+        examples: &[
+            CommandExample {
+                args: &["tasks"],
+                description: "List the open tasks of all notes",
+            },
+            CommandExample {
+                args: &["tasks", "--done", "--tag", "work"],
+                description: "List the done tasks of notes tagged work",
+            },
+            CommandExample {
+                args: &["tasks", "check", "abc123", "12"],
+                description: "Toggle the task on line 12 of a note",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "template",
+        examples: &[
+            CommandExample {
+                args: &["template", "list"],
+                description: "List the available templates",
+            },
+            CommandExample {
+                args: &["template", "new", "meeting"],
+                description: "Create a template and open it in your editor",
+            },
+            CommandExample {
+                args: &[
+                    "create",
+                    "--title",
+                    "Standup",
+                    "--template",
+                    "meeting",
+                    "--content",
+                    "Notes go here",
+                ],
+                description: "Create a note from a template, filling its {{content}} placeholder",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "history",
+        examples: &[
+            CommandExample {
+                args: &["history", "abc123"],
+                description: "List the previous versions of a note",
+            },
+            CommandExample {
+                args: &["history", "restore", "abc123", "1717236000"],
+                description: "Bring back the version saved at a listed timestamp",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "backup",
+        examples: &[
+            CommandExample {
+                args: &["backup"],
+                description: "Back up all notes to the configured backup directory",
+            },
+            CommandExample {
+                args: &["backup", "--output", "notes-backup.zip"],
+                description: "Back up all notes to a specific file",
+            },
+            CommandExample {
+                args: &["backup", "--incremental"],
+                description: "Back up only the notes changed since the last backup",
+            },
+            CommandExample {
+                args: &["backup", "list"],
+                description: "List full backups and per-note backups, newest first",
+            },
+            CommandExample {
+                args: &["backup", "list", "--format", "json", "--no-count"],
+                description: "List backups as JSON without opening the archives",
+            },
+            CommandExample {
+                args: &["backup", "status"],
+                description: "Show when the scheduler last backed up, any error, and the next run",
+            },
+            CommandExample {
+                args: &["backup", "diff", "notes-backup.zip"],
+                description: "Compare a backup with the vault before restoring it",
+            },
+            CommandExample {
+                args: &[
+                    "backup",
+                    "diff",
+                    "notes-backup.zip",
+                    "--show-content-diff",
+                    "abc123",
+                ],
+                description: "Show how a note in the backup differs from the vault",
+            },
+            CommandExample {
+                args: &["backup", "push", "--latest"],
+                description: "Upload the newest backup to the configured backup targets",
+            },
+            CommandExample {
+                args: &["backup", "prune"],
+                description: "Remove old per-note backups and those of long-deleted notes",
+            },
+            CommandExample {
+                args: &["backup", "prune", "--older-than", "2w"],
+                description: "Also remove backups of notes deleted two weeks ago or more",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "restore",
+        examples: &[
+            CommandExample {
+                args: &["restore", "notes-backup.zip"],
+                description: "Restore missing notes from a backup archive",
+            },
+            CommandExample {
+                args: &["restore", "notes-backup.zip", "--overwrite", "--force"],
+                description: "Replace existing notes with the backup without asking",
+            },
+            CommandExample {
+                args: &["restore", "notes-backup.zip", "--staged"],
+                description:
+                    "Swap in the backup as a whole, keeping the current notes for rollback",
+            },
+            CommandExample {
+                args: &["restore", "--latest"],
+                description: "Restore missing notes from the newest backup in the backup directory",
+            },
+            CommandExample {
+                args: &["restore", "notes-backup.zip", "--ids", "abc123,def456"],
+                description: "Restore only the listed notes from a backup",
+            },
+            CommandExample {
+                args: &[
+                    "restore",
+                    "notes-backup.zip",
+                    "--tag",
+                    "project-x",
+                    "--overwrite",
+                ],
+                description: "Restore the notes tagged project-x, replacing existing ones",
+            },
+            CommandExample {
+                args: &["restore", "notes-backup.zip", "--overwrite", "--dry-run"],
+                description: "List the notes a restore would create, overwrite or skip",
+            },
+            CommandExample {
+                args: &["restore", "notes-backup.zip", "--replace"],
+                description: "Make the vault match a backup, trashing notes it doesn't hold",
+            },
+            CommandExample {
+                args: &["restore", "notes-backup.zip", "--no-attachments"],
+                description: "Restore the notes but not the files attached to them",
+            },
+            CommandExample {
+                args: &["restore", "notes-backup.zip", "--force", "--format", "json"],
+                description: "Restore without asking and print the summary as JSON",
+            },
+            CommandExample {
+                args: &["restore", "--latest", "--dry-run", "--format", "json"],
+                description: "Print the planned restore of the newest backup as JSON",
+            },
+            CommandExample {
+                args: &["restore", "--rollback"],
+                description: "Switch back to the notes directory kept by the last staged restore",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "config",
+        examples: &[
+            CommandExample {
+                args: &["config", "--show"],
+                description: "Show the current configuration",
+            },
+            CommandExample {
+                args: &["config", "--set", "backup_frequency=12"],
+                description: "Back up every 12 hours",
+            },
+            CommandExample {
+                args: &["config", "--set", "backup_schedule=30 2 * * MON-FRI"],
+                description: "Back up at 02:30 UTC on weekdays",
+            },
+            CommandExample {
+                args: &["config", "--set", "backup_retention=daily:14,weekly:13"],
+                description: "Keep daily backups for two weeks, then weekly ones for three months",
+            },
+            CommandExample {
+                args: &["config", "--reset"],
+                description: "Rewrite the configuration file with the defaults",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "import",
+        examples: &[
+            CommandExample {
+                args: &["import", "-p", "~/Documents/notes/", "-f", "markdown", "-r"],
+                description: "Import every Markdown file below a directory",
+            },
+            CommandExample {
+                args: &[
+                    "import",
+                    "-p",
+                    "exported_notes.json",
+                    "-f",
+                    "json",
+                    "-g",
+                    "imported,archive",
+                ],
+                description: "Import a JSON export and tag every imported note",
+            },
+            CommandExample {
+                args: &["import", "-p", "meeting_notes.md", "--title-from-filename"],
+                description: "Import a file, using its name as the note title",
+            },
+            CommandExample {
+                args: &["import", "-p", "~/Documents/notes/", "-r", "--resume"],
+                description: "Continue an import that stopped, skipping files already imported",
+            },
+            CommandExample {
+                args: &[
+                    "import",
+                    "-p",
+                    "~/Documents/notes/",
+                    "-r",
+                    "--force-reimport",
+                ],
+                description: "Import every file again, even notes that already exist",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "export",
+        examples: &[
+            CommandExample {
+                args: &["export", "--output", "export/", "--tag", "work"],
+                description: "Export work notes as Markdown files",
+            },
+            CommandExample {
+                args: &[
+                    "export",
+                    "--output",
+                    "recent/",
+                    "--tag",
+                    "work",
+                    "--tag",
+                    "project",
+                    "--since",
+                    "2024-01-01",
+                ],
+                description: "Export notes tagged work or project created since 2024",
+            },
+            CommandExample {
+                args: &[
+                    "export",
+                    "-o",
+                    "q1.md",
+                    "-s",
+                    "-t",
+                    "work",
+                    "-t",
+                    "meeting",
+                    "--all-tags",
+                    "--since",
+                    "2024-01-01",
+                    "--until",
+                    "2024-03-31",
+                ],
+                description: "Export work meetings from the first quarter of 2024 into one file",
+            },
+            CommandExample {
+                args: &[
+                    "export",
+                    "--output",
+                    "notes.html",
+                    "--format",
+                    "html",
+                    "--single-file",
+                ],
+                description: "Export all notes into one HTML page with a table of contents",
+            },
+            CommandExample {
+                args: &["export", "-o", "pdf/", "-f", "pdf", "-t", "work"],
+                description: "Export work notes as one PDF document per note",
+            },
+            CommandExample {
+                args: &[
+                    "export",
+                    "--output",
+                    "notes.json",
+                    "--format",
+                    "json",
+                    "--single-file",
+                    "--redact",
+                ],
+                description: "Export all notes into one redacted JSON file",
+            },
+            CommandExample {
+                args: &[
+                    "export",
+                    "-o",
+                    "notes.md",
+                    "--single-file",
+                    "--sort-by",
+                    "title",
+                ],
+                description: "Export all notes into one Markdown file, ordered by title",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "migrate-history",
+        examples: &[
+            CommandExample {
+                args: &["migrate-history", "--dry-run"],
+                description: "Show which legacy backup files would be imported",
+            },
+            CommandExample {
+                args: &["migrate-history", "--archive"],
+                description: "Import legacy backups as snapshots and archive the originals",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "migrate-format",
+        examples: &[
+            CommandExample {
+                args: &["migrate-format"],
+                description: "Show logical and on-disk note sizes",
+            },
+            CommandExample {
+                args: &["migrate-format", "--compress", "--threshold", "65536"],
+                description: "Compress every note larger than 64 KiB",
+            },
+            CommandExample {
+                args: &["migrate-format", "--decompress"],
+                description: "Store every note as plain JSON again",
+            },
+            CommandExample {
+                args: &["migrate-format", "--to", "markdown"],
+                description: "Store every note as Markdown with YAML front matter",
+            },
+            CommandExample {
+                args: &["migrate-format", "--to", "split"],
+                description: "Store note content as plain Markdown next to JSON metadata",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "migrate-backend",
+        examples: &[
+            CommandExample {
+                args: &["migrate-backend", "sqlite"],
+                description: "Move every note into a SQLite database in the notes directory",
+            },
+            CommandExample {
+                args: &["migrate-backend", "files"],
+                description: "Move the notes back to one file per note",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "status",
+        examples: &[
+            CommandExample {
+                args: &["status"],
+                description: "Show the vault location, cache statistics and last backup",
+            },
+            CommandExample {
+                args: &["status", "--json"],
+                description: "Print the status as JSON for monitoring",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "doctor",
+        examples: &[
+            CommandExample {
+                args: &["doctor"],
+                description: "List unreadable, misplaced and missing note files",
+            },
+            CommandExample {
+                args: &["doctor", "--fix"],
+                description: "Move misplaced note files after changing the ID policy",
+            },
+            CommandExample {
+                args: &["doctor", "--corrupt"],
+                description: "List quarantined note files and what can be recovered",
+            },
+            CommandExample {
+                args: &["doctor", "--corrupt", "--fix"],
+                description: "Restore the notes recovered from quarantined files",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "reindex",
+        examples: &[
+            CommandExample {
+                args: &["reindex"],
+                description: "Rebuild the search index, e.g. after restoring notes by hand",
+            },
+            CommandExample {
+                args: &["reindex", "--foreground"],
+                description: "Rebuild the search index in one go before a script searches it",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "changed",
+        examples: &[
+            CommandExample {
+                args: &[
+                    "changed",
+                    "--since",
+                    "2024-05-01T00:00:00Z",
+                    "--format",
+                    "json",
+                ],
+                description: "List the notes created or modified since a sync as JSON",
+            },
+            CommandExample {
+                args: &["changed", "--since", "2024-05-01"],
+                description: "Print IDs and update times of notes changed since May 1st",
+            },
+        ],
+    },
+    CommandExamples {
+        command: "examples",
+        examples: &[CommandExample {
+            args: &["examples", "search"],
+            description: "Show the examples of the search command",
+        }],
+    },
+];
+
+/// Returns the examples registered for a subcommand
+pub fn examples_for(command: &str) -> Option<&'static [CommandExample]> {
+    COMMAND_EXAMPLES
+        .iter()
+        .find(|entry| entry.command == command)
+        .map(|entry| entry.examples)
+}
+
+/// Formats examples as an indented help section
+pub fn render_examples(examples: &[CommandExample]) -> String {
+    let mut output = String::from("Examples:");
+    for example in examples {
+        output.push_str(&format!(
+            "\n  # {}\n  kbnotes {}",
+            example.description,
+            shell_words::join(example.args)
+        ));
+    }
+    output
+}
+
+/// Builds the clap command with the registered examples appended to the long
+/// help of each subcommand
+pub fn command_with_examples() -> clap::Command {
+    COMMAND_EXAMPLES
+        .iter()
+        .fold(Cli::command(), |command, entry| {
+            command.mut_subcommand(entry.command, |subcommand| {
+                subcommand.after_long_help(render_examples(entry.examples))
+            })
+        })
+}
+
+/// Parses the process arguments, with example sections in the generated help
+pub fn parse_cli() -> Cli {
+    let matches = command_with_examples().get_matches();
+    Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+}
+
+/// Parses every registered example through the CLI definition and checks
+/// that every subcommand has examples
+///
+/// # Returns
+///
+/// An error naming the first example that no longer parses, or the first
+/// subcommand without examples
+pub fn validate_examples() -> Result<()> {
+    for example in COMMAND_EXAMPLES.iter().flat_map(|entry| entry.examples) {
+        let args = std::iter::once("kbnotes").chain(example.args.iter().copied());
+        command_with_examples()
+            .try_get_matches_from(args)
+            .map_err(|e| KbError::ApplicationError {
+                message: format!(
+                    "Example 'kbnotes {}' does not parse: {}",
+                    shell_words::join(example.args),
+                    e
+                ),
+            })?;
+    }
+
+    for subcommand in Cli::command().get_subcommands() {
+        if examples_for(subcommand.get_name()).is_none() {
+            return Err(KbError::ApplicationError {
+                message: format!(
+                    "Subcommand '{}' has no registered examples",
+                    subcommand.get_name()
+                ),
+            });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,448 @@
+//! Exporting notes to files outside the vault.
+//!
+//! Each note becomes one file named after its title. Titles are reduced to a
+//! portable file stem, and notes whose stems collide get their ID appended so
+//! that no export overwrites another. Single-file exports instead write all
+//! notes into one document, in the order they are given.
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use pulldown_cmark::{html, Event, Options, Parser};
+
+use crate::{KbError, Note, PdfWriter, Result, SanitizationPolicy, StrictAsciiPolicy};
+
+/// Stylesheet embedded in exported HTML files
+const HTML_STYLE: &str = "body{font-family:-apple-system,'Segoe UI',Helvetica,Arial,sans-serif;\
+max-width:50rem;margin:2rem auto;padding:0 1rem;line-height:1.6;color:#222}\
+.meta{color:#666;font-size:.9rem}.tag{background:#eee;border-radius:3px;padding:0 .3rem}\
+pre{background:#f6f8fa;padding:.8rem;overflow-x:auto}code{font-family:Menlo,Consolas,monospace}\
+article+article{border-top:1px solid #ddd;margin-top:3rem}";
+
+/// Maximum number of characters taken from a title for a file name
+const MAX_FILE_STEM_CHARS: usize = 80;
+
+/// Formats notes can be exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    Html,
+    Pdf,
+}
+
+impl ExportFormat {
+    /// File extension of exported files, without the dot
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Json => "json",
+            ExportFormat::Html => "html",
+            ExportFormat::Pdf => "pdf",
+        }
+    }
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = KbError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "markdown" => Ok(ExportFormat::Markdown),
+            "json" => Ok(ExportFormat::Json),
+            "html" => Ok(ExportFormat::Html),
+            "pdf" => Ok(ExportFormat::Pdf),
+            other => Err(KbError::InvalidFormat {
+                message: format!(
+                    "Unknown export format '{}': use markdown, json, html or pdf",
+                    other
+                ),
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ExportFormat::Markdown => "markdown",
+            ExportFormat::Json => "json",
+            ExportFormat::Html => "html",
+            ExportFormat::Pdf => "pdf",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Turns a note title into a lowercase file stem made of letters, digits and
+/// dashes, e.g. "Meeting: Q3 / Plans" becomes "meeting-q3-plans"
+pub fn export_file_stem(title: &str) -> String {
+    let mut stem = String::new();
+    for c in title.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            stem.push(c);
+        } else if !stem.is_empty() && !stem.ends_with('-') {
+            stem.push('-');
+        }
+        if stem.chars().count() >= MAX_FILE_STEM_CHARS {
+            break;
+        }
+    }
+
+    let stem = stem.trim_end_matches('-');
+    if stem.is_empty() {
+        "untitled".to_string()
+    } else {
+        stem.to_string()
+    }
+}
+
+/// Assigns every note a file name inside `output_dir`
+///
+/// Notes are taken oldest first, so the oldest note keeps the plain title-based
+/// name and later notes with the same stem get their ID appended. Names are
+/// compared case-insensitively to stay unique on every filesystem.
+///
+/// # Arguments
+/// * `notes` - Notes to export
+/// * `output_dir` - Directory receiving the files
+/// * `format` - Determines the file extension
+///
+/// # Returns
+/// * `Vec<(&Note, PathBuf)>` - Each note with its target path, oldest first
+pub fn plan_export_paths<'a>(
+    notes: &'a [Note],
+    output_dir: &Path,
+    format: ExportFormat,
+) -> Vec<(&'a Note, PathBuf)> {
+    let mut ordered: Vec<&Note> = notes.iter().collect();
+    ordered.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+
+    let mut used = HashSet::new();
+    ordered
+        .into_iter()
+        .map(|note| {
+            let stem = export_file_stem(&note.title);
+            let mut file_name = format!("{}.{}", stem, format.extension());
+            if !used.insert(file_name.to_lowercase()) {
+                file_name = format!(
+                    "{}-{}.{}",
+                    stem,
+                    StrictAsciiPolicy.sanitize(&note.id),
+                    format.extension()
+                );
+                used.insert(file_name.to_lowercase());
+            }
+            (note, output_dir.join(file_name))
+        })
+        .collect()
+}
+
+/// Renders a note as Markdown: the title as an H1, a short metadata block and
+/// the content
+pub fn render_markdown(note: &Note) -> String {
+    let mut output = format!("# {}\n\n", note.title);
+
+    if !note.tags.is_empty() {
+        let tags: Vec<String> = note.tags.iter().map(|tag| format!("`{}`", tag)).collect();
+        output.push_str(&format!("- **Tags:** {}\n", tags.join(", ")));
+    }
+    if let Some(notebook) = &note.notebook {
+        output.push_str(&format!("- **Notebook:** {}\n", notebook));
+    }
+    output.push_str(&format!(
+        "- **Created:** {}\n",
+        note.created_at.format("%Y-%m-%d %H:%M UTC")
+    ));
+    output.push_str(&format!(
+        "- **Updated:** {}\n\n",
+        note.updated_at.format("%Y-%m-%d %H:%M UTC")
+    ));
+
+    output.push_str(note.content.trim_end());
+    output.push('\n');
+    output
+}
+
+/// Escapes text for use in HTML content and attribute values
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Converts Markdown to HTML
+///
+/// Raw HTML embedded in the Markdown is escaped rather than passed through,
+/// so an exported note can be opened safely by anyone.
+pub fn markdown_to_html(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let parser = Parser::new_ext(markdown, options).map(|event| match event {
+        Event::Html(raw) | Event::InlineHtml(raw) => Event::Text(raw),
+        other => other,
+    });
+
+    let mut output = String::new();
+    html::push_html(&mut output, parser);
+    output
+}
+
+/// Renders a note as an `<article>` with the given anchor: title, metadata
+/// line and the converted content
+fn render_html_article(note: &Note, anchor: &str) -> String {
+    let mut meta = Vec::new();
+    if !note.tags.is_empty() {
+        let tags: Vec<String> = note
+            .tags
+            .iter()
+            .map(|tag| format!("<span class=\"tag\">{}</span>", escape_html(tag)))
+            .collect();
+        meta.push(format!("Tags: {}", tags.join(" ")));
+    }
+    if let Some(notebook) = &note.notebook {
+        meta.push(format!("Notebook: {}", escape_html(notebook)));
+    }
+    meta.push(format!(
+        "Created {}",
+        note.created_at.format("%Y-%m-%d %H:%M UTC")
+    ));
+    meta.push(format!(
+        "Updated {}",
+        note.updated_at.format("%Y-%m-%d %H:%M UTC")
+    ));
+
+    format!(
+        "<article id=\"{}\">\n<h1>{}</h1>\n<p class=\"meta\">{}</p>\n{}</article>\n",
+        escape_html(anchor),
+        escape_html(&note.title),
+        meta.join(" &middot; "),
+        markdown_to_html(&note.content)
+    )
+}
+
+/// Wraps rendered HTML in a complete document
+fn html_document(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{}</title>\n<style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape_html(title),
+        HTML_STYLE,
+        body
+    )
+}
+
+/// Renders a note as a standalone HTML document
+pub fn render_html(note: &Note) -> String {
+    let anchor = export_file_stem(&note.title);
+    html_document(&note.title, &render_html_article(note, &anchor))
+}
+
+/// Writes one Markdown file per note into `output_dir`, creating it if needed
+///
+/// # Arguments
+/// * `notes` - Notes to export
+/// * `output_dir` - Directory receiving the files
+///
+/// # Returns
+/// * `Result<Vec<PathBuf>>` - Paths of the written files, oldest note first
+pub fn export_markdown(notes: &[Note], output_dir: &Path) -> Result<Vec<PathBuf>> {
+    prepare_output_dir(output_dir)?;
+
+    let mut written = Vec::with_capacity(notes.len());
+    for (note, path) in plan_export_paths(notes, output_dir, ExportFormat::Markdown) {
+        fs::write(&path, render_markdown(note))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Writes every note into a single Markdown file, each note starting with its
+/// title as an H1 and separated from the previous one by a horizontal rule
+///
+/// # Arguments
+/// * `notes` - Notes to export, in the order they should appear
+/// * `output_file` - The file to write; its directory is created if needed
+pub fn export_markdown_single_file(notes: &[Note], output_file: &Path) -> Result<()> {
+    let rendered: Vec<String> = notes.iter().map(render_markdown).collect();
+
+    prepare_output_file(output_file)?;
+    fs::write(output_file, rendered.join("\n---\n\n"))?;
+    Ok(())
+}
+
+/// Writes one JSON file per note into `output_dir`, creating it if needed
+///
+/// # Arguments
+/// * `notes` - Notes to export
+/// * `output_dir` - Directory receiving the files
+///
+/// # Returns
+/// * `Result<Vec<PathBuf>>` - Paths of the written files, oldest note first
+pub fn export_json(notes: &[Note], output_dir: &Path) -> Result<Vec<PathBuf>> {
+    prepare_output_dir(output_dir)?;
+
+    let mut written = Vec::with_capacity(notes.len());
+    for (note, path) in plan_export_paths(notes, output_dir, ExportFormat::Json) {
+        fs::write(&path, serde_json::to_string_pretty(note)?)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Writes every note into a single JSON file holding an array of notes, which
+/// `kbnotes import --format json` reads back
+///
+/// # Arguments
+/// * `notes` - Notes to export, in the order they should appear
+/// * `output_file` - The file to write; its directory is created if needed
+pub fn export_json_single_file(notes: &[Note], output_file: &Path) -> Result<()> {
+    prepare_output_file(output_file)?;
+    fs::write(output_file, serde_json::to_string_pretty(notes)?)?;
+    Ok(())
+}
+
+/// Creates the directory of a single-file export
+fn prepare_output_file(output_file: &Path) -> Result<()> {
+    match output_file.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(parent) => prepare_output_dir(parent),
+        None => Ok(()),
+    }
+}
+
+/// Creates the export directory, refusing paths that point to a file
+fn prepare_output_dir(output_dir: &Path) -> Result<()> {
+    if output_dir.is_file() {
+        return Err(KbError::InvalidArgument {
+            message: format!(
+                "Export output {} is a file, expected a directory",
+                output_dir.display()
+            ),
+        });
+    }
+    fs::create_dir_all(output_dir).map_err(|_| KbError::DirectoryError {
+        path: output_dir.to_path_buf(),
+    })
+}
+
+/// Writes one HTML document per note into `output_dir`, creating it if needed
+///
+/// # Arguments
+/// * `notes` - Notes to export
+/// * `output_dir` - Directory receiving the files
+///
+/// # Returns
+/// * `Result<Vec<PathBuf>>` - Paths of the written files, oldest note first
+pub fn export_html(notes: &[Note], output_dir: &Path) -> Result<Vec<PathBuf>> {
+    prepare_output_dir(output_dir)?;
+
+    let mut written = Vec::with_capacity(notes.len());
+    for (note, path) in plan_export_paths(notes, output_dir, ExportFormat::Html) {
+        fs::write(&path, render_html(note))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Writes every note into a single HTML document, preceded by a table of
+/// contents linking to each note
+///
+/// # Arguments
+/// * `notes` - Notes to export, in the order they should appear
+/// * `output_file` - The document to write; its directory is created if needed
+pub fn export_html_single_file(notes: &[Note], output_file: &Path) -> Result<()> {
+    // Anchors are unique the same way file names are
+    let mut planned: HashMap<&str, String> =
+        plan_export_paths(notes, Path::new(""), ExportFormat::Html)
+            .into_iter()
+            .map(|(note, path)| {
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                (note.id.as_str(), stem.into_owned())
+            })
+            .collect();
+    let anchors: Vec<(&Note, String)> = notes
+        .iter()
+        .map(|note| (note, planned.remove(note.id.as_str()).unwrap_or_default()))
+        .collect();
+
+    let mut body = String::from("<nav>\n<h1>Contents</h1>\n<ol>\n");
+    for (note, anchor) in &anchors {
+        body.push_str(&format!(
+            "<li><a href=\"#{}\">{}</a></li>\n",
+            escape_html(anchor),
+            escape_html(&note.title)
+        ));
+    }
+    body.push_str("</ol>\n</nav>\n");
+    for (note, anchor) in &anchors {
+        body.push_str(&render_html_article(note, anchor));
+    }
+
+    prepare_output_file(output_file)?;
+    fs::write(output_file, html_document("Notes", &body))?;
+    Ok(())
+}
+
+/// Writes one PDF document per note into `output_dir`, creating it if needed
+///
+/// # Arguments
+/// * `notes` - Notes to export
+/// * `output_dir` - Directory receiving the files
+/// * `progress` - Called with each note and its file once the file is written
+///
+/// # Returns
+/// * `Result<Vec<PathBuf>>` - Paths of the written files, oldest note first
+pub fn export_pdf(
+    notes: &[Note],
+    output_dir: &Path,
+    mut progress: impl FnMut(&Note, &Path),
+) -> Result<Vec<PathBuf>> {
+    prepare_output_dir(output_dir)?;
+
+    let mut written = Vec::with_capacity(notes.len());
+    for (note, path) in plan_export_paths(notes, output_dir, ExportFormat::Pdf) {
+        let mut writer = PdfWriter::new(&note.title)?;
+        writer.add_note(note);
+        fs::write(&path, writer.finish()?)?;
+        progress(note, &path);
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Writes every note into a single PDF document, each note starting on a new
+/// page with a bookmark in the document outline
+///
+/// # Arguments
+/// * `notes` - Notes to export, in the order they should appear
+/// * `output_file` - The document to write; its directory is created if needed
+/// * `progress` - Called with each note once it has been laid out
+pub fn export_pdf_single_file(
+    notes: &[Note],
+    output_file: &Path,
+    mut progress: impl FnMut(&Note, &Path),
+) -> Result<()> {
+    let mut writer = PdfWriter::new("Notes")?;
+    for note in notes {
+        writer.add_note(note);
+        progress(note, output_file);
+    }
+
+    prepare_output_file(output_file)?;
+    fs::write(output_file, writer.finish()?)?;
+    Ok(())
+}
@@ -0,0 +1,540 @@
+//! C-compatible interface for embedding kbnotes in other applications.
+//!
+//! Enabled with the `ffi` feature. The matching header is `include/kbnotes.h`.
+//!
+//! Conventions shared by every function:
+//!
+//! * Functions return a [`KbnotesStatus`]; on failure, [`kbnotes_last_error`]
+//!   describes the error on the calling thread.
+//! * Records cross the boundary as UTF-8 JSON strings. Strings returned
+//!   through `out` parameters are owned by the caller and must be released
+//!   with [`kbnotes_string_free`].
+//! * A vault owns its own tokio runtime, so callers need no async runtime.
+//!   A vault handle may be shared between threads; calls are serialized on the
+//!   storage lock like the CLI's.
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_void, CStr, CString},
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::PathBuf,
+    ptr,
+    sync::{mpsc, Arc},
+    thread::{self, JoinHandle},
+};
+
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{runtime::Runtime, sync::Mutex as TokioMutex, task::JoinHandle as TaskHandle};
+
+use crate::{Config, KbError, Note, NoteEvent, NoteStorage, Result};
+
+/// Flat error codes returned by every FFI function
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KbnotesStatus {
+    /// The call succeeded
+    Ok = 0,
+    /// A pointer argument was null, a string was not valid UTF-8, or a note
+    /// reference was ambiguous or not valid note JSON
+    InvalidArgument = 1,
+    /// The note does not exist
+    NotFound = 2,
+    /// The note was changed concurrently, is locked, or its ID is already taken
+    Conflict = 3,
+    /// Reading or writing the vault failed
+    Io = 4,
+    /// A note file or backup could not be parsed
+    Format = 5,
+    /// Any other failure, including panics caught at the boundary
+    Other = 6,
+}
+
+impl From<&KbError> for KbnotesStatus {
+    fn from(error: &KbError) -> Self {
+        match error {
+            KbError::NoteNotFound { .. } | KbError::FileNotFound { .. } => KbnotesStatus::NotFound,
+            KbError::InvalidArgument { .. } | KbError::AmbiguousReference { .. } => {
+                KbnotesStatus::InvalidArgument
+            }
+            KbError::NoteAlreadyExists { .. }
+            | KbError::ConcurrentModification { .. }
+            | KbError::NoteLocked { .. }
+            | KbError::IdCollision { .. } => KbnotesStatus::Conflict,
+            KbError::Io(_) | KbError::DirectoryError { .. } | KbError::Database(_) => {
+                KbnotesStatus::Io
+            }
+            KbError::Serialization(_) | KbError::ZipError(_) | KbError::InvalidFormat { .. } => {
+                KbnotesStatus::Format
+            }
+            _ => KbnotesStatus::Other,
+        }
+    }
+}
+
+/// Summary record returned by the list and search functions
+#[derive(Debug, Serialize)]
+struct NoteSummary {
+    id: String,
+    title: String,
+    tags: Vec<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<Note> for NoteSummary {
+    fn from(note: Note) -> Self {
+        NoteSummary {
+            id: note.id,
+            title: note.title,
+            tags: note.tags,
+            created_at: note.created_at,
+            updated_at: note.updated_at,
+        }
+    }
+}
+
+/// Note fields accepted by [`kbnotes_save_note`]
+#[derive(Debug, Deserialize)]
+struct NoteInput {
+    #[serde(default)]
+    id: Option<String>,
+    title: String,
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// An open vault
+pub struct KbnotesVault {
+    runtime: Runtime,
+    storage: Arc<TokioMutex<NoteStorage>>,
+}
+
+/// Called with a JSON-encoded note event and the `user_data` passed to
+/// [`kbnotes_subscribe`]. The string is only valid during the call.
+pub type KbnotesEventCallback = extern "C" fn(event_json: *const c_char, user_data: *mut c_void);
+
+/// An active change subscription
+pub struct KbnotesSubscription {
+    forwarder: TaskHandle<()>,
+    dispatcher: Option<JoinHandle<()>>,
+}
+
+/// Caller-owned context pointer; the caller guarantees it may be used from
+/// the dispatch thread
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Runs an FFI body, recording errors and turning panics into a status
+fn ffi_call(body: impl FnOnce() -> Result<()>) -> KbnotesStatus {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+    match catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => KbnotesStatus::Ok,
+        Ok(Err(e)) => {
+            let status = KbnotesStatus::from(&e);
+            set_last_error(e.to_string());
+            status
+        }
+        Err(_) => {
+            set_last_error("Internal panic in kbnotes".to_string());
+            KbnotesStatus::Other
+        }
+    }
+}
+
+fn invalid_argument(message: &str) -> KbError {
+    KbError::InvalidArgument {
+        message: message.to_string(),
+    }
+}
+
+/// Reads a required string argument
+///
+/// # Safety
+///
+/// `value` must be null or point to a NUL-terminated string
+unsafe fn read_str<'a>(value: *const c_char, name: &str) -> Result<&'a str> {
+    if value.is_null() {
+        return Err(invalid_argument(&format!("{} must not be null", name)));
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map_err(|_| invalid_argument(&format!("{} is not valid UTF-8", name)))
+}
+
+/// Reads an optional string argument, mapping null to `None`
+///
+/// # Safety
+///
+/// `value` must be null or point to a NUL-terminated string
+unsafe fn read_optional_str<'a>(value: *const c_char, name: &str) -> Result<Option<&'a str>> {
+    if value.is_null() {
+        Ok(None)
+    } else {
+        read_str(value, name).map(Some)
+    }
+}
+
+/// Resolves a vault handle
+///
+/// # Safety
+///
+/// `vault` must be null or a handle returned by [`kbnotes_vault_open`]
+unsafe fn read_vault<'a>(vault: *const KbnotesVault) -> Result<&'a KbnotesVault> {
+    vault
+        .as_ref()
+        .ok_or_else(|| invalid_argument("vault must not be null"))
+}
+
+/// Serializes `value` into a caller-owned string stored in `out`
+///
+/// # Safety
+///
+/// `out` must be null or valid for writes
+unsafe fn write_json<T: Serialize>(out: *mut *mut c_char, value: &T) -> Result<()> {
+    if out.is_null() {
+        return Err(invalid_argument("output pointer must not be null"));
+    }
+    let json = serde_json::to_string(value)?;
+    *out = CString::new(json)
+        .map_err(|_| invalid_argument("result contains a NUL byte"))?
+        .into_raw();
+    Ok(())
+}
+
+impl KbnotesVault {
+    fn open(notes_dir: PathBuf, backup_dir: PathBuf) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("kbnotes-ffi")
+            .enable_all()
+            .build()?;
+
+        let storage = Arc::new(TokioMutex::new(NoteStorage::new(Config::with_dirs(
+            notes_dir, backup_dir,
+        ))));
+        runtime.block_on(async { storage.lock().await.initialize(Arc::clone(&storage)).await })?;
+
+        Ok(KbnotesVault { runtime, storage })
+    }
+
+    /// Locks storage from a thread outside the runtime
+    fn storage(&self) -> tokio::sync::MutexGuard<'_, NoteStorage> {
+        self.storage.blocking_lock()
+    }
+}
+
+/// Opens a vault, loading its notes and starting the file watcher and
+/// backup scheduler
+///
+/// # Safety
+///
+/// `notes_dir` and `backup_dir` must be NUL-terminated strings and `out_vault`
+/// must be valid for writes. Release the vault with [`kbnotes_vault_close`].
+#[no_mangle]
+pub unsafe extern "C" fn kbnotes_vault_open(
+    notes_dir: *const c_char,
+    backup_dir: *const c_char,
+    out_vault: *mut *mut KbnotesVault,
+) -> KbnotesStatus {
+    ffi_call(|| {
+        let notes_dir = PathBuf::from(read_str(notes_dir, "notes_dir")?);
+        let backup_dir = PathBuf::from(read_str(backup_dir, "backup_dir")?);
+        if out_vault.is_null() {
+            return Err(invalid_argument("out_vault must not be null"));
+        }
+
+        let vault = KbnotesVault::open(notes_dir, backup_dir)?;
+        *out_vault = Box::into_raw(Box::new(vault));
+        Ok(())
+    })
+}
+
+/// Shuts a vault down and releases it. Subscriptions should be cancelled first.
+///
+/// # Safety
+///
+/// `vault` must be null or a handle returned by [`kbnotes_vault_open`] that no
+/// other thread is using. It must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn kbnotes_vault_close(vault: *mut KbnotesVault) -> KbnotesStatus {
+    ffi_call(|| {
+        if vault.is_null() {
+            return Ok(());
+        }
+        let vault = Box::from_raw(vault);
+        let result = vault
+            .runtime
+            .block_on(async { vault.storage.lock().await.shutdown().await });
+        drop(vault);
+        result
+    })
+}
+
+/// Lists note summaries as a JSON array, optionally restricted to a tag
+///
+/// # Safety
+///
+/// `vault` must be a valid handle, `tag` null or a NUL-terminated string, and
+/// `out_json` valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn kbnotes_list_notes(
+    vault: *const KbnotesVault,
+    tag: *const c_char,
+    out_json: *mut *mut c_char,
+) -> KbnotesStatus {
+    ffi_call(|| {
+        let vault = read_vault(vault)?;
+        let tag = read_optional_str(tag, "tag")?;
+
+        let mut notes = match tag {
+            Some(tag) => vault.storage().get_notes_by_tag(tag, true)?,
+            None => vault.storage().get_all_notes()?,
+        };
+        notes.sort_by_key(|note| std::cmp::Reverse(note.updated_at));
+
+        let summaries: Vec<NoteSummary> = notes.into_iter().map(NoteSummary::from).collect();
+        write_json(out_json, &summaries)
+    })
+}
+
+/// Searches titles and content, returning note summaries as a JSON array
+/// ordered by relevance
+///
+/// # Safety
+///
+/// `vault` must be a valid handle, `query` a NUL-terminated string, and
+/// `out_json` valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn kbnotes_search_notes(
+    vault: *const KbnotesVault,
+    query: *const c_char,
+    out_json: *mut *mut c_char,
+) -> KbnotesStatus {
+    ffi_call(|| {
+        let vault = read_vault(vault)?;
+        let query = read_str(query, "query")?;
+
+        let summaries: Vec<NoteSummary> = vault
+            .storage()
+            .search_notes(query, true)?
+            .into_notes()
+            .into_iter()
+            .map(NoteSummary::from)
+            .collect();
+        write_json(out_json, &summaries)
+    })
+}
+
+/// Returns a full note as a JSON object. `reference` may be an ID, title or alias.
+///
+/// # Safety
+///
+/// `vault` must be a valid handle, `reference` a NUL-terminated string, and
+/// `out_json` valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn kbnotes_get_note(
+    vault: *const KbnotesVault,
+    reference: *const c_char,
+    out_json: *mut *mut c_char,
+) -> KbnotesStatus {
+    ffi_call(|| {
+        let vault = read_vault(vault)?;
+        let reference = read_str(reference, "reference")?;
+
+        let storage = vault.storage();
+        let id = storage.resolve_note_id(reference)?;
+        let note = storage.get_note(&id).ok_or(KbError::NoteNotFound { id })?;
+        write_json(out_json, &note)
+    })
+}
+
+/// Creates or updates a note from a JSON object with `title` and optional
+/// `id`, `content` and `tags`. Without an `id` a new note is created. The
+/// saved note is returned as a JSON object.
+///
+/// # Safety
+///
+/// `vault` must be a valid handle, `note_json` a NUL-terminated string, and
+/// `out_json` valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn kbnotes_save_note(
+    vault: *const KbnotesVault,
+    note_json: *const c_char,
+    out_json: *mut *mut c_char,
+) -> KbnotesStatus {
+    ffi_call(|| {
+        let vault = read_vault(vault)?;
+        let input: NoteInput = serde_json::from_str(read_str(note_json, "note_json")?)
+            .map_err(|e| invalid_argument(&format!("note_json: {}", e)))?;
+
+        let storage = vault.storage();
+        let note = match input.id {
+            Some(id) => {
+                let mut note = storage.get_note(&id).ok_or(KbError::NoteNotFound { id })?;
+                note.title = input.title;
+                note.content = input.content;
+                note.tags = input.tags;
+                note.updated_at = Utc::now();
+                storage.update_note(note.clone())?;
+                note
+            }
+            None => {
+                let note = Note::new(input.title, input.content, input.tags);
+                storage.save_note(&note)?;
+                note
+            }
+        };
+        write_json(out_json, &note)
+    })
+}
+
+/// Deletes a note by ID, moving it to the trash
+///
+/// # Safety
+///
+/// `vault` must be a valid handle and `id` a NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn kbnotes_delete_note(
+    vault: *const KbnotesVault,
+    id: *const c_char,
+) -> KbnotesStatus {
+    ffi_call(|| {
+        let vault = read_vault(vault)?;
+        let id = read_str(id, "id")?;
+        vault.storage().delete_note(id)
+    })
+}
+
+/// Calls `callback` with a JSON-encoded event for every created, updated or
+/// deleted note, including changes made outside the vault handle.
+///
+/// Callbacks run one at a time on a dedicated thread and may call back into
+/// the vault. Events missed because the callback fell behind are skipped.
+///
+/// # Safety
+///
+/// `vault` must be a valid handle and `out_subscription` valid for writes.
+/// `user_data` must stay valid, and be usable from another thread, until
+/// [`kbnotes_unsubscribe`] returns.
+#[no_mangle]
+pub unsafe extern "C" fn kbnotes_subscribe(
+    vault: *const KbnotesVault,
+    callback: Option<KbnotesEventCallback>,
+    user_data: *mut c_void,
+    out_subscription: *mut *mut KbnotesSubscription,
+) -> KbnotesStatus {
+    ffi_call(|| {
+        let vault = read_vault(vault)?;
+        let callback = callback.ok_or_else(|| invalid_argument("callback must not be null"))?;
+        if out_subscription.is_null() {
+            return Err(invalid_argument("out_subscription must not be null"));
+        }
+
+        let mut receiver = vault.storage().subscribe_events();
+        let (sender, events) = mpsc::channel::<NoteEvent>();
+
+        // Forward from the async broadcast channel to a plain thread, so the
+        // callback never runs on a runtime thread and can block or re-enter
+        let forwarder = vault.runtime.spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => {
+                        if sender.send(event).is_err() {
+                            break;
+                        }
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("FFI subscriber lagged, skipped {} note events", skipped);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        let user_data = UserData(user_data);
+        let dispatcher = thread::Builder::new()
+            .name("kbnotes-ffi-events".to_string())
+            .spawn(move || {
+                let user_data = user_data;
+                for event in events {
+                    match serde_json::to_string(&event).map(CString::new) {
+                        Ok(Ok(json)) => callback(json.as_ptr(), user_data.0),
+                        _ => warn!("Could not encode note event for {}", event.note_id),
+                    }
+                }
+                debug!("FFI event dispatcher stopped");
+            })?;
+
+        *out_subscription = Box::into_raw(Box::new(KbnotesSubscription {
+            forwarder,
+            dispatcher: Some(dispatcher),
+        }));
+        Ok(())
+    })
+}
+
+/// Cancels a subscription. Once this returns, the callback is no longer
+/// running and will not be called again, unless this is called from the
+/// callback itself.
+///
+/// # Safety
+///
+/// `subscription` must be null or a handle returned by [`kbnotes_subscribe`].
+/// It must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn kbnotes_unsubscribe(
+    subscription: *mut KbnotesSubscription,
+) -> KbnotesStatus {
+    ffi_call(|| {
+        if subscription.is_null() {
+            return Ok(());
+        }
+        let mut subscription = Box::from_raw(subscription);
+
+        // Aborting the forwarder drops the channel sender, ending the dispatcher
+        subscription.forwarder.abort();
+        if let Some(dispatcher) = subscription.dispatcher.take() {
+            if dispatcher.thread().id() != thread::current().id() {
+                let _ = dispatcher.join();
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Returns the message of the last error on the calling thread, or null. The
+/// string stays valid until the next kbnotes call on this thread.
+#[no_mangle]
+pub extern "C" fn kbnotes_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Releases a string returned by a kbnotes function
+///
+/// # Safety
+///
+/// `value` must be null or a string returned through an `out_json` parameter,
+/// released at most once
+#[no_mangle]
+pub unsafe extern "C" fn kbnotes_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value));
+    }
+}
@@ -0,0 +1,679 @@
+//! The default storage backend: one file per note below `notes_dir`.
+//!
+//! A note with ID `abc` lives at `notes_dir/ab/abc.json` (or `.md`, or
+//! `.meta.json` next to `.md`, see [`StorageFormat`]), with the file name
+//! sanitized by the configured ID policy. Every write goes to a temporary
+//! file that is renamed over the note file, synced according to
+//! [`FsyncMode`]. Each change is remembered for a while as [`OwnChanges`], so
+//! the file watcher can skip its echo.
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::{debug, error, trace, warn};
+use rayon::prelude::*;
+use tempfile::NamedTempFile;
+use walkdir::WalkDir;
+
+use crate::{
+    encode_note_json, is_note_file, is_orphaned_split_content, is_split_content,
+    load_live_note_from_file, load_note_from_file, note_file_paths, note_to_markdown,
+    note_to_split, relative_note_path, BackendKind, BackendLoad, Config, DurabilityStats,
+    FsyncMode, KbError, Note, Result, SanitizationPolicy, StorageBackend, StorageFormat,
+    ATTACHMENTS_DIR,
+};
+
+/// How long a change made through the storage is remembered, which is more
+/// than the file watcher takes to report it
+const OWN_CHANGE_TTL: Duration = Duration::from_secs(10);
+
+/// Modification time and length of a file, or `None` once it is removed
+type FileState = Option<(Option<SystemTime>, u64)>;
+
+fn file_state(path: &Path) -> FileState {
+    fs::metadata(path)
+        .ok()
+        .map(|metadata| (metadata.modified().ok(), metadata.len()))
+}
+
+/// Note files recently written or removed by this process, each with the
+/// state it was left in
+///
+/// The file watcher skips events for these files as long as they are still in
+/// that state; a file changed again since, by another program, is reloaded.
+#[derive(Clone)]
+pub struct OwnChanges {
+    /// Paths are kept relative to this directory, since watcher events may
+    /// carry canonicalized paths
+    notes_dir: PathBuf,
+
+    /// When each file was changed, and the state it was left in
+    changes: Arc<Mutex<HashMap<PathBuf, (Instant, FileState)>>>,
+}
+
+impl OwnChanges {
+    /// Creates an empty set for the files below `notes_dir`
+    pub fn new(notes_dir: PathBuf) -> Self {
+        Self {
+            notes_dir,
+            changes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Remembers the state a file was just left in
+    pub fn record(&self, path: &Path) {
+        let Some(relative) = relative_note_path(&self.notes_dir, path) else {
+            return;
+        };
+        let state = file_state(path);
+        match self.changes.lock() {
+            Ok(mut changes) => {
+                changes.retain(|_, (at, _)| at.elapsed() < OWN_CHANGE_TTL);
+                changes.insert(relative, (Instant::now(), state));
+            }
+            Err(e) => warn!("Failed to record own change: {}", e),
+        }
+    }
+
+    /// Returns true when `path` is as this process recently left it
+    pub fn is_own(&self, path: &Path) -> bool {
+        let Some(relative) = relative_note_path(&self.notes_dir, path) else {
+            return false;
+        };
+        let Ok(mut changes) = self.changes.lock() else {
+            return false;
+        };
+        changes.retain(|_, (at, _)| at.elapsed() < OWN_CHANGE_TTL);
+        changes
+            .get(&relative)
+            .is_some_and(|(_, state)| *state == file_state(path))
+    }
+}
+
+/// Reads and writes note files in the layout of a [`Config`]
+#[derive(Clone)]
+pub struct FileBackend {
+    /// Where notes live, their format, compression and durability settings
+    config: Config,
+
+    /// Maps note IDs to file names
+    id_policy: Arc<dyn SanitizationPolicy>,
+
+    /// Files and directories written since startup that still need an fsync
+    /// (only used with `FsyncMode::OnClose`)
+    pending_syncs: Arc<Mutex<HashSet<PathBuf>>>,
+
+    /// Number of files synced since startup
+    synced_files: Arc<AtomicU64>,
+
+    /// Number of directories synced since startup
+    synced_directories: Arc<AtomicU64>,
+
+    /// Files written or removed lately, for the file watcher to skip
+    own_changes: OwnChanges,
+}
+
+impl FileBackend {
+    /// Creates a backend for the notes directory and settings of `config`
+    pub fn new(config: Config) -> Self {
+        let id_policy = config.id_policy.policy();
+        let own_changes = OwnChanges::new(config.notes_dir.clone());
+        Self {
+            config,
+            id_policy,
+            pending_syncs: Arc::new(Mutex::new(HashSet::new())),
+            synced_files: Arc::new(AtomicU64::new(0)),
+            synced_directories: Arc::new(AtomicU64::new(0)),
+            own_changes,
+        }
+    }
+
+    /// Returns a backend writing notes in `format`, sharing this one's
+    /// pending syncs, sync counters and own changes
+    pub(crate) fn with_format(&self, format: StorageFormat) -> Self {
+        let mut config = self.config.clone();
+        config.storage_format = format;
+        Self {
+            config,
+            ..self.clone()
+        }
+    }
+
+    /// Returns the files this backend changed lately
+    pub fn own_changes(&self) -> &OwnChanges {
+        &self.own_changes
+    }
+
+    /// Returns the file path a note has in the configured storage format
+    pub fn note_path(&self, note_id: &str) -> PathBuf {
+        self.note_path_in(note_id, self.config.storage_format)
+    }
+
+    /// Returns the file path a note has when stored in `format`
+    pub fn note_path_in(&self, note_id: &str, format: StorageFormat) -> PathBuf {
+        let (id_prefix, file_name) = self.note_file_name(note_id, format);
+        self.config.notes_dir.join(id_prefix).join(file_name)
+    }
+
+    /// Returns the path of the file a note is stored in, which is in
+    /// another storage format when the note hasn't been rewritten since the
+    /// format changed
+    pub fn find_note_path(&self, note_id: &str) -> PathBuf {
+        // A split note's content file is no note of its own
+        let path = self.note_path(note_id);
+        if path.exists() && is_note_file(&path) {
+            return path;
+        }
+        StorageFormat::ALL
+            .into_iter()
+            .map(|format| self.note_path_in(note_id, format))
+            .find(|other| other.exists() && is_note_file(other))
+            .unwrap_or(path)
+    }
+
+    /// Splits a note's location into its prefix directory and file name,
+    /// following the structure first_2_chars_of_name/name.json (or name.md)
+    pub(crate) fn note_file_name(&self, note_id: &str, format: StorageFormat) -> (String, String) {
+        note_file_name(self.id_policy.as_ref(), note_id, format)
+    }
+
+    /// Serializes a note into the files it is stored in with the configured
+    /// storage format, each with its path
+    ///
+    /// A split note's content file comes first, so that its sidecar, which
+    /// makes the pair a note, is written last.
+    pub(crate) fn encode_note(&self, note: &Note) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+        let path = self.note_path(&note.id);
+        match self.config.storage_format {
+            StorageFormat::Json => Ok(vec![(
+                path,
+                encode_note_json(
+                    serde_json::to_string_pretty(note)?,
+                    self.config.compress_threshold_bytes,
+                )?,
+            )]),
+            // Markdown files are there to be read and edited with other tools,
+            // so they are never compressed
+            StorageFormat::Markdown => Ok(vec![(path, note_to_markdown(note)?.into_bytes())]),
+            StorageFormat::Split => {
+                let (sidecar, content) = note_to_split(note)?;
+                let content_path = self.note_path_in(&note.id, StorageFormat::Markdown);
+                Ok(vec![
+                    (content_path, content.into_bytes()),
+                    (path, sidecar.into_bytes()),
+                ])
+            }
+        }
+    }
+
+    /// Writes a note in the configured storage format, each file to a
+    /// temporary file in its directory that is renamed over it
+    pub(crate) fn write_note_files(&self, note: &Note) -> Result<()> {
+        for path in self.write_note_files_unsynced(note)? {
+            self.sync_dir_after_persist(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a note's files as [`FileBackend::write_note_files`] does, but
+    /// leaves syncing their directories to the caller
+    ///
+    /// # Returns
+    ///
+    /// The paths of the files written
+    fn write_note_files_unsynced(&self, note: &Note) -> Result<Vec<PathBuf>> {
+        let mut files = self.encode_note(note)?;
+        // Converting a Markdown note to the split format overwrites it with
+        // its content: write the sidecar first, which reads the whole note
+        // from a Markdown file for as long as it is one
+        if files.len() > 1
+            && files[0].0.exists()
+            && !is_split_content(&files[0].0)
+            && !is_orphaned_split_content(&files[0].0)
+        {
+            files.reverse();
+        }
+        let mut written = Vec::with_capacity(files.len());
+        for (file_path, bytes) in files {
+            self.write_file(&file_path, &bytes)?;
+            written.push(file_path);
+        }
+        Ok(written)
+    }
+
+    /// Writes one note file atomically, without syncing its directory
+    fn write_file(&self, file_path: &Path, bytes: &[u8]) -> Result<()> {
+        debug!("File path for note: {}", file_path.display());
+
+        // Ensure the parent directory exists
+        let dir = file_path.parent().unwrap_or_else(|| Path::new("."));
+        if !dir.exists() {
+            debug!("Creating parent directory: {}", dir.display());
+            fs::create_dir_all(dir).map_err(|e| {
+                error!("Failed to create directory {}: {}", dir.display(), e);
+                KbError::Io(e)
+            })?;
+        }
+
+        // Create a temporary file in the same directory (for atomic operation)
+        debug!("Creating temporary file in directory: {}", dir.display());
+        let mut temp_file = NamedTempFile::new_in(dir).map_err(|e| {
+            error!("Failed to create temporary file: {}", e);
+            KbError::Io(e)
+        })?;
+
+        trace!("Writing to temporary file");
+        temp_file.write_all(bytes).map_err(|e| {
+            error!("Failed to write to temporary file: {}", e);
+            KbError::Io(e)
+        })?;
+
+        temp_file.flush().map_err(|e| {
+            error!("Failed to flush temporary file: {}", e);
+            KbError::Io(e)
+        })?;
+
+        self.sync_file_before_persist(temp_file.as_file())?;
+
+        // Atomically move the temporary file to the target location
+        debug!("Performing atomic move of temporary file to final location");
+        temp_file.persist(file_path).map_err(|e| {
+            error!(
+                "Failed to persist file {}: {}",
+                file_path.display(),
+                e.error
+            );
+            KbError::Io(e.error)
+        })?;
+
+        self.own_changes.record(file_path);
+        Ok(())
+    }
+
+    /// Removes the copies of a note in formats other than the configured one,
+    /// once it has been written in the configured format
+    pub(crate) fn remove_other_format_files(&self, note_id: &str) -> Result<()> {
+        let current = note_file_paths(&self.note_path(note_id));
+        for format in StorageFormat::ALL {
+            if format == self.config.storage_format {
+                continue;
+            }
+            // A split note's content file shares the name of a Markdown note
+            for path in note_file_paths(&self.note_path_in(note_id, format)) {
+                if path.exists() && !current.contains(&path) {
+                    debug!("Removing {} copy of note {}", format, note_id);
+                    fs::remove_file(&path)?;
+                    self.own_changes.record(&path);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Syncs a freshly written temp file according to the configured durability mode
+    pub(crate) fn sync_file_before_persist(&self, file: &File) -> Result<()> {
+        if self.config.fsync == FsyncMode::Always {
+            trace!("Syncing temporary file to disk");
+            file.sync_all().map_err(|e| {
+                error!("Failed to sync temporary file: {}", e);
+                KbError::Io(e)
+            })?;
+            self.synced_files.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Makes the rename of `file_path` durable by syncing its parent directory,
+    /// or records it for a sync at shutdown depending on the durability mode
+    pub(crate) fn sync_dir_after_persist(&self, file_path: &Path) -> Result<()> {
+        match self.config.fsync {
+            FsyncMode::Always => {
+                if let Some(parent) = file_path.parent() {
+                    sync_directory(parent)?;
+                    self.synced_directories.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            FsyncMode::OnClose => match self.pending_syncs.lock() {
+                Ok(mut pending) => {
+                    pending.insert(file_path.to_path_buf());
+                }
+                Err(e) => warn!("Failed to record pending sync: {}", e),
+            },
+            FsyncMode::Never => {}
+        }
+        Ok(())
+    }
+
+    /// Syncs every file written since startup and their directories
+    /// (`FsyncMode::OnClose`)
+    pub(crate) fn sync_pending_writes(&self) -> Result<()> {
+        let paths: Vec<PathBuf> = match self.pending_syncs.lock() {
+            Ok(mut pending) => pending.drain().collect(),
+            Err(_) => {
+                return Err(KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on pending syncs".to_string(),
+                })
+            }
+        };
+
+        let mut directories = HashSet::new();
+        for path in &paths {
+            // The file may have been deleted or replaced since it was written
+            if let Ok(file) = File::open(path) {
+                file.sync_all()?;
+                self.synced_files.fetch_add(1, Ordering::Relaxed);
+            }
+            if let Some(parent) = path.parent() {
+                directories.insert(parent.to_path_buf());
+            }
+        }
+
+        for dir in directories.iter().filter(|dir| dir.exists()) {
+            sync_directory(dir)?;
+            self.synced_directories.fetch_add(1, Ordering::Relaxed);
+        }
+
+        debug!(
+            "Synced {} files in {} directories",
+            paths.len(),
+            directories.len()
+        );
+        Ok(())
+    }
+
+    /// Returns the durability mode, the syncs made since startup and the
+    /// writes still waiting for one
+    pub fn durability_stats(&self) -> DurabilityStats {
+        let pending_syncs = match self.pending_syncs.lock() {
+            Ok(pending) => pending.len(),
+            Err(e) => {
+                warn!("Failed to read pending syncs: {}", e);
+                0
+            }
+        };
+        DurabilityStats {
+            mode: self.config.fsync,
+            synced_files: self.synced_files.load(Ordering::Relaxed),
+            synced_directories: self.synced_directories.load(Ordering::Relaxed),
+            pending_syncs,
+        }
+    }
+
+    /// Removes `dir_path` and then its parents as long as they are empty,
+    /// stopping at the notes directory
+    pub(crate) fn cleanup_empty_directory(&self, dir_path: &Path) {
+        // Skip if this is the root notes directory or doesn't exist
+        if !dir_path.exists() || dir_path == self.config.notes_dir {
+            return;
+        }
+
+        // Check if the directory is empty
+        match fs::read_dir(dir_path) {
+            Ok(entries) => {
+                if entries.count() == 0 {
+                    debug!("Removing empty directory: {}", dir_path.display());
+                    match fs::remove_dir(dir_path) {
+                        Ok(_) => {
+                            // Recursively check parent directory
+                            if let Some(parent) = dir_path.parent() {
+                                if parent != self.config.notes_dir {
+                                    self.cleanup_empty_directory(parent);
+                                }
+                            }
+                        }
+                        Err(e) => warn!(
+                            "Failed to remove empty directory {}: {}",
+                            dir_path.display(),
+                            e
+                        ),
+                    }
+                }
+            }
+            Err(e) => warn!("Failed to read directory {}: {}", dir_path.display(), e),
+        }
+    }
+}
+
+impl StorageBackend for FileBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Files
+    }
+
+    /// Reads every note file, in parallel
+    ///
+    /// When a note has files in several formats (a conversion was interrupted),
+    /// the newer copy wins, or the one in the configured format when both are
+    /// as new.
+    fn load_all(&self) -> Result<BackendLoad> {
+        if !self.config.notes_dir.exists() {
+            return Ok(BackendLoad::default());
+        }
+
+        // Walk the notes directory first; sorting makes the merge below
+        // independent of the order the filesystem lists files in
+        let paths: Vec<PathBuf> = WalkDir::new(&self.config.notes_dir)
+            .min_depth(1) // Skip the root directory
+            .sort_by_file_name()
+            .into_iter()
+            // Internal dot-directories (trash, history, search index etc.) and
+            // attachments never hold live notes
+            .filter_entry(|e| !is_internal_entry(e))
+            .filter_map(|e| e.ok())
+            // Only process note files, in any storage format
+            .filter(|e| e.file_type().is_file() && is_note_file(e.path()))
+            .map(|e| e.into_path())
+            .collect();
+
+        // Reading and parsing dominate the load, so spread them over all
+        // cores. A content file left behind by a split note whose sidecar is
+        // gone holds nothing but content: it is told apart while it is read
+        // anyway, and left where it is
+        let loaded: Vec<(PathBuf, Result<Note>)> = paths
+            .into_par_iter()
+            .filter_map(|path| match load_live_note_from_file(&path) {
+                Ok(Some(note)) => Some((path, Ok(note))),
+                Ok(None) => {
+                    warn!(
+                        "Skipping content file without a sidecar: {}",
+                        path.display()
+                    );
+                    None
+                }
+                Err(e) => Some((path, Err(e))),
+            })
+            .collect();
+
+        let mut notes: HashMap<String, Note> = HashMap::with_capacity(loaded.len());
+        let mut failed = Vec::new();
+        let configured_format = self.config.storage_format;
+        let preference = |note: &Note, path: &Path| {
+            (
+                note.updated_at,
+                StorageFormat::of_path(path) == Some(configured_format),
+            )
+        };
+        let mut preferences = HashMap::with_capacity(loaded.len());
+
+        for (path, result) in loaded {
+            match result {
+                Ok(note)
+                    if preferences
+                        .get(&note.id)
+                        .is_some_and(|existing| *existing >= preference(&note, &path)) =>
+                {
+                    debug!(
+                        "Skipping older copy of note {}: {}",
+                        note.id,
+                        path.display()
+                    );
+                }
+                Ok(note) => {
+                    preferences.insert(note.id.clone(), preference(&note, &path));
+                    notes.insert(note.id.clone(), note);
+                }
+                Err(e) => {
+                    // Collect errors but continue processing
+                    warn!("Failed to load note from {}: {}", path.display(), e);
+                    failed.push((path, e.to_string()));
+                }
+            }
+        }
+
+        Ok(BackendLoad {
+            notes: notes.into_values().collect(),
+            failed,
+        })
+    }
+
+    fn load(&self, note_id: &str) -> Result<Option<Note>> {
+        let file_path = self.find_note_path(note_id);
+        if !file_path.exists() {
+            return Ok(None);
+        }
+        debug!("Note file exists at: {}", file_path.display());
+        load_note_from_file(&file_path).map(Some)
+    }
+
+    /// Writes the note to temporary files in its directory and renames them
+    /// over the note files, then removes copies in other formats
+    fn save(&self, note: &Note) -> Result<()> {
+        trace!("Serializing note as {}", self.config.storage_format);
+        self.write_note_files(note)?;
+        self.remove_other_format_files(&note.id)
+    }
+
+    /// Writes every note before syncing the directories they are in, so that
+    /// with `FsyncMode::Always` each prefix directory is synced once for the
+    /// whole batch rather than once per note
+    fn save_batch(&self, notes: &[&Note]) -> Vec<Result<()>> {
+        let written: Vec<Result<Vec<PathBuf>>> = notes
+            .iter()
+            .map(|note| self.write_note_files_unsynced(note))
+            .collect();
+
+        let mut failed_dirs = HashMap::new();
+        if self.config.fsync == FsyncMode::Always {
+            let dirs: HashSet<&Path> = written
+                .iter()
+                .flatten()
+                .flatten()
+                .filter_map(|path| path.parent())
+                .collect();
+            for dir in dirs {
+                match sync_directory(dir) {
+                    Ok(()) => {
+                        self.synced_directories.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        failed_dirs.insert(dir.to_path_buf(), e.to_string());
+                    }
+                }
+            }
+        }
+
+        notes
+            .iter()
+            .zip(written)
+            .map(|(note, written)| {
+                let paths = written?;
+                if self.config.fsync == FsyncMode::Always {
+                    let failed = paths
+                        .iter()
+                        .filter_map(|path| path.parent())
+                        .find_map(|dir| failed_dirs.get_key_value(dir));
+                    if let Some((dir, e)) = failed {
+                        return Err(KbError::ApplicationError {
+                            message: format!("Failed to sync directory {}: {}", dir.display(), e),
+                        });
+                    }
+                } else {
+                    for path in &paths {
+                        self.sync_dir_after_persist(path)?;
+                    }
+                }
+                self.remove_other_format_files(&note.id)
+            })
+            .collect()
+    }
+
+    /// Removes the note file (a split note's sidecar before its content
+    /// file), and its prefix directory once empty
+    fn delete(&self, note_id: &str) -> Result<bool> {
+        let file_path = self.find_note_path(note_id);
+        if !file_path.exists() {
+            debug!("Note file doesn't exist on disk: {}", file_path.display());
+            return Ok(false);
+        }
+
+        for path in note_file_paths(&file_path) {
+            if !path.exists() {
+                continue;
+            }
+            debug!("Deleting note file: {}", path.display());
+            fs::remove_file(&path).map_err(|e| {
+                error!("Failed to delete note file {}: {}", path.display(), e);
+                KbError::Io(e)
+            })?;
+            self.own_changes.record(&path);
+        }
+        if let Some(parent) = file_path.parent() {
+            self.cleanup_empty_directory(parent);
+        }
+        Ok(true)
+    }
+
+    fn list_ids(&self) -> Result<Vec<String>> {
+        let mut ids: Vec<String> = self
+            .load_all()?
+            .notes
+            .into_iter()
+            .map(|note| note.id)
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+}
+
+/// Splits the location a note has in `format` under `id_policy` into its
+/// prefix directory and file name
+pub(crate) fn note_file_name(
+    id_policy: &dyn SanitizationPolicy,
+    note_id: &str,
+    format: StorageFormat,
+) -> (String, String) {
+    let name = id_policy.sanitize(note_id);
+    let id_prefix = name.chars().take(2).collect();
+    (id_prefix, format!("{}.{}", name, format.extension()))
+}
+
+/// Returns true for the vault's internal dot-directories and its
+/// attachments directory, which note walks skip
+pub(crate) fn is_internal_entry(entry: &walkdir::DirEntry) -> bool {
+    let name = entry.file_name().to_string_lossy();
+    name.starts_with('.') || (entry.depth() == 1 && name == ATTACHMENTS_DIR)
+}
+
+/// Syncs a directory so that renames and new entries inside it are durable
+#[cfg(unix)]
+pub(crate) fn sync_directory(dir: &Path) -> Result<()> {
+    File::open(dir)
+        .and_then(|handle| handle.sync_all())
+        .map_err(|e| {
+            error!("Failed to sync directory {}: {}", dir.display(), e);
+            KbError::Io(e)
+        })
+}
+
+/// Directories cannot be opened for syncing on this platform
+#[cfg(not(unix))]
+pub(crate) fn sync_directory(_dir: &Path) -> Result<()> {
+    Ok(())
+}
@@ -1,60 +1,142 @@
-use std::{collections::HashMap, fs, path::Path, sync::{Arc, Mutex}};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{BufRead, Cursor, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
 
-use log::{debug, error, trace};
+use chrono::{DateTime, Utc};
+use icu_normalizer::ComposingNormalizerBorrowed;
+use log::{debug, error, trace, warn};
 use notify::EventKind;
+use tokio::sync::broadcast;
 
-use crate::{KbError, Result, Note};
+use crate::{
+    has_front_matter, index_note_structure, is_note_file, is_orphaned_split_content,
+    is_split_content, note_file_as, note_file_name, note_file_stem, note_from_markdown,
+    note_from_split, parse_note, update_search_index, KbError, LegacyBackupKind, LegacyBackupName,
+    LinkIndex, Note, NoteEvent, NoteEventKind, OwnChanges, Result, SanitizationPolicy, SearchIndex,
+    StorageFormat, StructureIndex, ATTACHMENTS_DIR,
+};
+
+/// First bytes of a zstd frame, which mark a compressed note file
+pub const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// zstd level used for compressed notes (0 selects the library default)
+const ZSTD_LEVEL: i32 = 0;
+
+/// Index from normalized alias to the IDs of the notes carrying it
+pub type AliasIndex = HashMap<String, HashSet<String>>;
+
+/// Index from normalized title to the IDs of the notes carrying it
+pub type TitleIndex = HashMap<String, HashSet<String>>;
+
+/// Index from normalized tag (see [`normalize_tag`]) to the IDs of the notes carrying it
+pub type TagIndex = HashMap<String, HashSet<String>>;
 
 /// Handles file system events by updating the notes cache
+///
+/// Notes are keyed by the ID stored in their file. A file whose name doesn't
+/// match that ID is logged, and ignored while the note's own file exists
+/// (e.g. a copy made next to it). Renames drop the note under its old name
+/// unless the new name holds it. Files this process wrote or removed and no
+/// one changed since, as told by `own_changes`, are skipped, as are the
+/// temporary files writes go through. Changes that did not originate from
+/// this process (i.e. the cache does not already hold the same version) are
+/// announced on `events` as external.
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_fs_event(
     event: notify::Event,
-    notes_cache: &Arc<Mutex<HashMap<String, Note>>>,
-    // notes_dir: &PathBuf,
+    notes_cache: &Arc<RwLock<HashMap<String, Note>>>,
+    alias_index: &Arc<Mutex<AliasIndex>>,
+    title_index: &Arc<Mutex<TitleIndex>>,
+    tag_index: &Arc<Mutex<TagIndex>>,
+    structure_index: &Arc<Mutex<StructureIndex>>,
+    link_index: &Arc<Mutex<LinkIndex>>,
+    search_index: &Arc<Mutex<Option<SearchIndex>>>,
+    events: &broadcast::Sender<NoteEvent>,
+    id_policy: &dyn SanitizationPolicy,
+    notes_dir: &Path,
+    own_changes: &OwnChanges,
 ) {
+    let watched = WatchedVault {
+        notes_cache,
+        alias_index,
+        title_index,
+        tag_index,
+        structure_index,
+        link_index,
+        search_index,
+        events,
+        id_policy,
+        notes_dir,
+    };
+    let paths = event.paths.into_iter().filter(|path| {
+        if is_internal_path(notes_dir, path) || is_temp_file(path) {
+            return false;
+        }
+        if own_changes.is_own(path) {
+            trace!("Skipping own change of {}", path.display());
+            return false;
+        }
+        true
+    });
+
     match event.kind {
         EventKind::Create(_) | EventKind::Modify(_) => {
-            for path in event.paths {
-                if path.extension().is_some_and(|ext| ext == "json") {
-                    if let Some(_file_name) = path.file_name() {
-                        if let Some(file_stem) = path.file_stem() {
-                            let note_id = file_stem.to_string_lossy().to_string();
-
-                            // Load the note from file
-                            match load_note_from_file(&path) {
-                                Ok(note) => {
-                                    // Update cache
-                                    if let Ok(mut cache) = notes_cache.lock() {
-                                        cache.insert(note_id.clone(), note.clone());
-                                        debug!("Updated cache for note: {}", note_id);
-                                    }
-                                }
-                                Err(e) => {
-                                    error!(
-                                        "Failed to load note from changed file {}: {}",
-                                        path.display(),
-                                        e
-                                    );
-                                }
-                            }
-                        }
+            // Renames report the old name too, which is gone by now
+            let (present, vanished): (Vec<PathBuf>, Vec<PathBuf>) =
+                paths.partition(|path| path.exists());
+
+            // Both files of a split note change on every save: reload the
+            // note once, through its sidecar
+            let mut seen = HashSet::new();
+            let mut loaded = HashSet::new();
+            for path in present {
+                let path = if is_split_content(&path) {
+                    note_file_as(&path, StorageFormat::Split).unwrap_or(path)
+                } else {
+                    path
+                };
+                if !seen.insert(path.clone()) {
+                    continue;
+                }
+                if is_orphaned_split_content(&path) {
+                    trace!(
+                        "Waiting for the sidecar of split content file {}",
+                        path.display()
+                    );
+                    continue;
+                }
+                if !is_note_file(&path) {
+                    continue;
+                }
+                match read_note_file(&path) {
+                    Ok((note, edited)) => {
+                        loaded.insert(note.id.clone());
+                        watched.reload(&path, note, edited);
+                    }
+                    Err(e) => {
+                        error!(
+                            "Failed to load note from changed file {}: {}",
+                            path.display(),
+                            e
+                        );
                     }
                 }
             }
+
+            for path in vanished {
+                watched.forget(&path, &loaded);
+            }
         }
         EventKind::Remove(_) => {
-            for path in event.paths {
-                if path.extension().is_some_and(|ext| ext == "json") {
-                    if let Some(file_stem) = path.file_stem() {
-                        let note_id = file_stem.to_string_lossy().to_string();
-
-                        // Remove from cache
-                        if let Ok(mut cache) = notes_cache.lock() {
-                            if cache.remove(&note_id).is_some() {
-                                debug!("Removed note {} from cache due to file deletion", note_id);
-                            }
-                        }
-                    }
-                }
+            for path in paths {
+                watched.forget(&path, &HashSet::new());
             }
         }
         _ => {
@@ -63,15 +145,344 @@ pub async fn handle_fs_event(
     }
 }
 
+/// The cache, indexes and subscribers a file system event updates
+struct WatchedVault<'a> {
+    notes_cache: &'a Arc<RwLock<HashMap<String, Note>>>,
+    alias_index: &'a Arc<Mutex<AliasIndex>>,
+    title_index: &'a Arc<Mutex<TitleIndex>>,
+    tag_index: &'a Arc<Mutex<TagIndex>>,
+    structure_index: &'a Arc<Mutex<StructureIndex>>,
+    link_index: &'a Arc<Mutex<LinkIndex>>,
+    search_index: &'a Arc<Mutex<Option<SearchIndex>>>,
+    events: &'a broadcast::Sender<NoteEvent>,
+    id_policy: &'a dyn SanitizationPolicy,
+    notes_dir: &'a Path,
+}
+
+impl WatchedVault<'_> {
+    /// Returns where the note with `note_id` is stored in `format`
+    fn expected_path(&self, note_id: &str, format: StorageFormat) -> PathBuf {
+        let (id_prefix, file_name) = note_file_name(self.id_policy, note_id, format);
+        self.notes_dir.join(id_prefix).join(file_name)
+    }
+
+    /// Returns true when `path` is where the note with `note_id` is stored
+    /// in `format`, in whichever form the watcher reports it
+    fn is_expected_path(&self, path: &Path, note_id: &str, format: StorageFormat) -> bool {
+        relative_note_path(self.notes_dir, path)
+            == relative_note_path(self.notes_dir, &self.expected_path(note_id, format))
+    }
+
+    /// Puts a note read from a changed file into the cache and indexes
+    fn reload(&self, path: &Path, note: Note, edited: bool) {
+        // Keyed by the stored ID, which the file name may not spell out
+        let note_id = note.id.clone();
+        let format = StorageFormat::of_path(path).unwrap_or_default();
+        if !self.is_expected_path(path, &note_id, format) {
+            // A copy of a note next to its own file doesn't replace it
+            let own_file = StorageFormat::ALL
+                .into_iter()
+                .map(|format| self.expected_path(&note_id, format))
+                .find(|other| other.exists() && is_note_file(other));
+            if let Some(own_file) = own_file {
+                warn!(
+                    "Ignoring {}: it holds note {}, which is stored in {}",
+                    path.display(),
+                    note_id,
+                    own_file.display()
+                );
+                return;
+            }
+            warn!(
+                "{} holds note {}, which belongs in {}",
+                path.display(),
+                note_id,
+                self.expected_path(&note_id, format).display()
+            );
+        }
+
+        let cached = self
+            .notes_cache
+            .read()
+            .ok()
+            .and_then(|cache| cache.get(&note_id).cloned());
+        // Nothing changed, e.g. the second file of a split save
+        if cached.as_ref() == Some(&note) {
+            trace!("Note {} is unchanged on disk", note_id);
+            return;
+        }
+        // Content written by a save whose sidecar is still being written;
+        // its own event follows
+        if edited
+            && cached
+                .as_ref()
+                .is_some_and(|cached| cached.content == note.content)
+        {
+            trace!("Split note {} is being saved", note_id);
+            return;
+        }
+
+        // Update cache
+        if let Ok(mut index) = self.alias_index.lock() {
+            index_note_aliases(&mut index, &note);
+        }
+        if let Ok(mut index) = self.title_index.lock() {
+            index_note_title(&mut index, &note);
+        }
+        if let Ok(mut index) = self.tag_index.lock() {
+            index_note_tags(&mut index, &note);
+        }
+        if let Ok(mut index) = self.structure_index.lock() {
+            index_note_structure(&mut index, &note);
+        }
+        if let Ok(mut index) = self.link_index.lock() {
+            index.index_note(&note);
+        }
+        if let Ok(mut index) = self.search_index.lock() {
+            update_search_index(&mut index, |index| index.index_note(&note));
+        }
+        if let Ok(mut cache) = self.notes_cache.write() {
+            let updated_at = note.updated_at;
+            let kind = match cache.insert(note_id.clone(), note) {
+                // Echo of a write made through the storage
+                Some(previous) if previous.updated_at == updated_at => None,
+                Some(_) => Some(NoteEventKind::Updated),
+                None => Some(NoteEventKind::Created),
+            };
+            debug!("Updated cache for note: {}", note_id);
+
+            if let Some(kind) = kind {
+                let _ = self.events.send(NoteEvent {
+                    kind,
+                    note_id,
+                    updated_at: Some(updated_at),
+                    external: true,
+                });
+            }
+        }
+    }
+
+    /// Drops the note a removed (or renamed) file held, unless `kept` lists
+    /// it because another file of the same event holds it now
+    fn forget(&self, path: &Path, kept: &HashSet<String>) {
+        // The content file of a split note went first: the note stays
+        // until its sidecar goes as well
+        if is_split_content(path) {
+            debug!(
+                "Content file {} was removed, keeping its sidecar's note",
+                path.display()
+            );
+            return;
+        }
+        let Some(current) = StorageFormat::of_path(path) else {
+            return;
+        };
+        // The note was converted to another storage format
+        if StorageFormat::ALL.into_iter().any(|format| {
+            format != current
+                && note_file_as(path, format)
+                    .is_some_and(|other| other.exists() && !is_orphaned_split_content(&other))
+        }) {
+            return;
+        }
+        let Some(file_stem) = note_file_stem(path) else {
+            return;
+        };
+        let note_id =
+            note_id_for_removed_file(self.id_policy, self.notes_cache, OsStr::new(&file_stem));
+        if kept.contains(&note_id) {
+            debug!("Note {} was renamed from {}", note_id, path.display());
+            return;
+        }
+        // A stray copy went, not the note's own file
+        if !self.is_expected_path(path, &note_id, current)
+            && StorageFormat::ALL.into_iter().any(|format| {
+                let own_file = self.expected_path(&note_id, format);
+                own_file.exists() && is_note_file(&own_file)
+            })
+        {
+            debug!(
+                "Keeping note {}: {} was not its file",
+                note_id,
+                path.display()
+            );
+            return;
+        }
+
+        if let Ok(mut index) = self.alias_index.lock() {
+            unindex_note_aliases(&mut index, &note_id);
+        }
+        if let Ok(mut index) = self.title_index.lock() {
+            unindex_note_title(&mut index, &note_id);
+        }
+        if let Ok(mut index) = self.tag_index.lock() {
+            unindex_note_tags(&mut index, &note_id);
+        }
+        if let Ok(mut index) = self.structure_index.lock() {
+            index.remove(&note_id);
+        }
+        if let Ok(mut index) = self.link_index.lock() {
+            index.remove_note(&note_id);
+        }
+        if let Ok(mut index) = self.search_index.lock() {
+            update_search_index(&mut index, |index| index.remove_note(&note_id));
+        }
+
+        // Remove from cache
+        if let Ok(mut cache) = self.notes_cache.write() {
+            if cache.remove(&note_id).is_some() {
+                debug!("Removed note {} from cache due to file deletion", note_id);
+                let _ = self.events.send(NoteEvent {
+                    kind: NoteEventKind::Deleted,
+                    note_id,
+                    updated_at: None,
+                    external: true,
+                });
+            }
+        }
+    }
+}
+
+/// Recovers the ID of the note a deleted file was named after by the ID policy
+///
+/// Names shortened for long IDs can't be decoded, so those are looked up
+/// among the cached notes instead.
+fn note_id_for_removed_file(
+    id_policy: &dyn SanitizationPolicy,
+    notes_cache: &Arc<RwLock<HashMap<String, Note>>>,
+    file_stem: &OsStr,
+) -> String {
+    let name = file_stem.to_string_lossy();
+    if let Some(note_id) = id_policy.restore(&name) {
+        return note_id;
+    }
+
+    notes_cache
+        .read()
+        .ok()
+        .and_then(|cache| {
+            cache
+                .keys()
+                .find(|note_id| id_policy.sanitize(note_id) == name)
+                .cloned()
+        })
+        .unwrap_or_else(|| name.to_string())
+}
+
+/// Returns true for paths inside the vault's internal dot-directories
+/// (e.g. `.snapshots`) or its attachments directory, which never contain
+/// live notes
+pub fn is_internal_path(notes_dir: &Path, path: &Path) -> bool {
+    let Some(relative) = relative_note_path(notes_dir, path) else {
+        return false;
+    };
+
+    relative.parent().is_some_and(|parent| {
+        parent.starts_with(ATTACHMENTS_DIR)
+            || parent
+                .components()
+                .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+    })
+}
+
+/// Returns a path below the notes directory relative to it
+pub fn relative_note_path(notes_dir: &Path, path: &Path) -> Option<PathBuf> {
+    match path.strip_prefix(notes_dir) {
+        Ok(relative) => Some(relative.to_path_buf()),
+        // Watcher events may carry canonicalized paths
+        Err(_) => fs::canonicalize(notes_dir)
+            .ok()
+            .and_then(|dir| path.strip_prefix(dir).ok().map(Path::to_path_buf)),
+    }
+}
+
+/// Returns true for the temporary files note writes go through before they
+/// are renamed over the note file
+pub fn is_temp_file(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with(".tmp"))
+}
+
+/// Resolves symlinks in a project directory path when it exists
+pub fn canonical_project_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Compares two canonical project paths, ignoring case on platforms whose
+/// default filesystems are case-insensitive
+pub fn same_project_path(a: &Path, b: &Path) -> bool {
+    if cfg!(any(windows, target_os = "macos")) {
+        a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase()
+    } else {
+        a == b
+    }
+}
+
+/// Renders a unified line diff between two texts
+pub fn render_unified_diff(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    similar::TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(old_label, new_label)
+        .to_string()
+}
+
 /// Helper method to load a single note from file
 pub fn load_note_from_file(path: &Path) -> Result<Note> {
+    read_note_file(path).map(|(note, _)| note)
+}
+
+/// Loads a note like [`load_note_from_file`]
+///
+/// # Returns
+///
+/// The note, and whether it is a split note whose content file was edited
+/// since its sidecar was written
+fn read_note_file(path: &Path) -> Result<(Note, bool)> {
+    // Split notes are read through their sidecar
+    if is_split_content(path) {
+        if let Some(sidecar) = note_file_as(path, StorageFormat::Split) {
+            return read_note_file(&sidecar);
+        }
+    }
+
+    let text = read_note_text(path)?;
+    parse_note_text(path, &text)
+}
+
+/// Loads a note like [`load_note_from_file`], or returns `None` for the
+/// content file of a split note without its sidecar
+///
+/// Unlike [`is_orphaned_split_content`] followed by a load, this reads the
+/// file only once.
+pub fn load_live_note_from_file(path: &Path) -> Result<Option<Note>> {
+    if is_split_content(path) {
+        return load_note_from_file(path).map(Some);
+    }
+
+    let text = read_note_text(path)?;
+    if StorageFormat::of_path(path) == Some(StorageFormat::Markdown) && !has_front_matter(&text) {
+        return Ok(None);
+    }
+    parse_note_text(path, &text).map(|(note, _)| Some(note))
+}
+
+/// Reads the text of a note file, decompressing it if needed
+fn read_note_text(path: &Path) -> Result<String> {
     debug!("Loading note from file: {}", path.display());
-    let content = fs::read_to_string(path).map_err(|e| {
+    let bytes = fs::read(path).map_err(|e| {
         error!("Failed to open note file {}: {}", path.display(), e);
         KbError::Io(e)
     })?;
+    decode_note_bytes(bytes)
+}
 
-    let note: Note = serde_json::from_str(&content)?;
+/// Parses the text read from the note file at `path`, like [`read_note_file`]
+fn parse_note_text(path: &Path, text: &str) -> Result<(Note, bool)> {
+    let (note, edited) = match StorageFormat::of_path(path).unwrap_or_default() {
+        StorageFormat::Split => read_split_note(path, text)?,
+        format => (parse_note(text, format)?, false),
+    };
 
     // Validate note
     if note.id.is_empty() {
@@ -81,7 +492,538 @@ pub fn load_note_from_file(path: &Path) -> Result<Note> {
     }
 
     trace!("Successfully loaded note: {}", note.id);
-    Ok(note)
+    Ok((note, edited))
+}
+
+/// Reads the content file of the split note whose sidecar at `path` holds
+/// `sidecar`
+///
+/// Content edited by another tool since the sidecar was written is taken as
+/// is, keeping the sidecar's metadata, and moves `updated_at` up to the time
+/// of the edit.
+fn read_split_note(path: &Path, sidecar: &str) -> Result<(Note, bool)> {
+    let content_path = note_file_as(path, StorageFormat::Markdown)
+        .unwrap_or_else(|| path.with_extension(StorageFormat::Markdown.extension()));
+    let content = fs::read_to_string(&content_path).map_err(|e| KbError::InvalidFormat {
+        message: format!(
+            "Content file {} of split note {} can't be read: {}",
+            content_path.display(),
+            path.display(),
+            e
+        ),
+    })?;
+
+    let (mut note, edited) = note_from_split(sidecar, content)?;
+    if !edited {
+        return Ok((note, false));
+    }
+
+    if let Some(markdown) = markdown_in_content(&note) {
+        return Ok((markdown, false));
+    }
+
+    debug!(
+        "Content of split note {} was edited outside kbnotes",
+        note.id
+    );
+    if let Ok(modified) = fs::metadata(&content_path).and_then(|metadata| metadata.modified()) {
+        note.updated_at = note.updated_at.max(DateTime::<Utc>::from(modified));
+    }
+    Ok((note, true))
+}
+
+/// Returns the whole Markdown note a split note's content file holds when a
+/// conversion between the split and Markdown formats was interrupted
+fn markdown_in_content(note: &Note) -> Option<Note> {
+    note_from_markdown(&note.content)
+        .ok()
+        .filter(|markdown| markdown.id == note.id)
+}
+
+/// Returns true for a split sidecar whose content file still holds the
+/// whole Markdown note, so the pair is yet to be written in one format
+pub(crate) fn is_unfinished_conversion(sidecar: &Path) -> bool {
+    let Some(content_path) = note_file_as(sidecar, StorageFormat::Markdown) else {
+        return false;
+    };
+    let (Ok(sidecar), Ok(content)) = (
+        fs::read_to_string(sidecar),
+        fs::read_to_string(content_path),
+    ) else {
+        return false;
+    };
+    note_from_split(&sidecar, content)
+        .is_ok_and(|(note, edited)| edited && markdown_in_content(&note).is_some())
+}
+
+/// Merges file system events that queued up while the previous ones were
+/// handled, so a save touching several files reloads its note once
+///
+/// Runs of creations and modifications become a single modification;
+/// other events keep their place.
+pub fn coalesce_fs_events(events: Vec<notify::Event>) -> Vec<notify::Event> {
+    let mut merged: Vec<notify::Event> = Vec::with_capacity(events.len());
+    for event in events {
+        let is_change = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_));
+        match merged.last_mut() {
+            Some(last)
+                if is_change
+                    && matches!(last.kind, EventKind::Create(_) | EventKind::Modify(_)) =>
+            {
+                for path in event.paths {
+                    if !last.paths.contains(&path) {
+                        last.paths.push(path);
+                    }
+                }
+            }
+            _ => merged.push(event),
+        }
+    }
+    merged
+}
+
+/// Recognizes the file names of per-note backups written before snapshots
+///
+/// # Arguments
+///
+/// * `id_policy` - Policy used to decode the note ID part of the name
+/// * `file_name` - Name of a file in the backup directory
+///
+/// # Returns
+///
+/// The parsed name, or `None` when the file follows none of the legacy schemes
+pub fn parse_legacy_backup_name(
+    id_policy: &dyn SanitizationPolicy,
+    file_name: &str,
+) -> Option<LegacyBackupName> {
+    let stem = file_name.strip_suffix(".json")?;
+    let is_timestamp = |value: &str| !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit());
+
+    let (rest, last) = stem.rsplit_once('_')?;
+    if !is_timestamp(last) {
+        return None;
+    }
+
+    // Most specific scheme first, since note IDs may contain underscores
+    let (encoded_id, kind, timestamp) = match rest.rsplit_once('_') {
+        Some((prefix, ts)) if is_timestamp(ts) && prefix.ends_with("_pre_update") => (
+            prefix.strip_suffix("_pre_update")?,
+            LegacyBackupKind::PreUpdate,
+            ts,
+        ),
+        Some((prefix, ts)) if is_timestamp(ts) && prefix.ends_with("_post_update") => (
+            prefix.strip_suffix("_post_update")?,
+            LegacyBackupKind::PostUpdate,
+            ts,
+        ),
+        _ => match rest.strip_suffix("_predeletion") {
+            Some(id) => (id, LegacyBackupKind::PreDeletion, last),
+            None => (rest, LegacyBackupKind::Backup, last),
+        },
+    };
+
+    if encoded_id.is_empty() {
+        return None;
+    }
+
+    Some(LegacyBackupName {
+        note_id: id_policy
+            .restore(encoded_id)
+            .unwrap_or_else(|| encoded_id.to_string()),
+        kind,
+        timestamp: DateTime::from_timestamp(timestamp.parse().ok()?, 0)?,
+    })
+}
+
+/// Recognizes the `{id}_deletion_record_{ts}.txt` files written to the backup
+/// directory when a note is deleted
+///
+/// # Returns
+///
+/// The ID of the deleted note and when it was deleted, or `None` for any other
+/// file name
+pub fn parse_deletion_record_name(
+    id_policy: &dyn SanitizationPolicy,
+    file_name: &str,
+) -> Option<(String, DateTime<Utc>)> {
+    let (encoded_id, timestamp) = file_name
+        .strip_suffix(".txt")?
+        .rsplit_once("_deletion_record_")?;
+    if encoded_id.is_empty() || !timestamp.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    Some((
+        id_policy
+            .restore(encoded_id)
+            .unwrap_or_else(|| encoded_id.to_string()),
+        DateTime::from_timestamp(timestamp.parse().ok()?, 0)?,
+    ))
+}
+
+/// Reads the note stored in a legacy backup, filling in fields a damaged,
+/// truncated or older file lacks from its name
+///
+/// # Returns
+///
+/// The note, or an error when the file is not JSON or holds neither a title
+/// nor content
+pub fn salvage_legacy_note(json: &str, name: &LegacyBackupName) -> Result<Note> {
+    salvage_note_json(json, &name.note_id, name.timestamp)
+}
+
+/// Reads as much of a note as damaged or truncated JSON still holds
+///
+/// # Arguments
+///
+/// * `json` - The note JSON, possibly cut short
+/// * `fallback_id` - ID used when the JSON has none, also standing in for a missing title
+/// * `fallback_time` - Creation and update time used when the JSON has none
+///
+/// # Returns
+///
+/// The note, or an error when the text is not JSON or holds neither a title
+/// nor content
+pub fn salvage_note_json(
+    json: &str,
+    fallback_id: &str,
+    fallback_time: DateTime<Utc>,
+) -> Result<Note> {
+    if let Ok(note) = serde_json::from_str::<Note>(json) {
+        if !note.id.is_empty() {
+            return Ok(note);
+        }
+    }
+
+    // A truncated file still holds the fields written before the cut
+    let value: serde_json::Value = match serde_json::from_str(json) {
+        Ok(value) => value,
+        Err(e) if e.is_eof() => repair_truncated_json(json).ok_or(e)?,
+        Err(e) => return Err(e.into()),
+    };
+    // The cut may only have dropped fields that have defaults
+    if let Ok(note) = serde_json::from_value::<Note>(value.clone()) {
+        if !note.id.is_empty() {
+            return Ok(note);
+        }
+    }
+    let object = value.as_object().ok_or_else(|| KbError::InvalidFormat {
+        message: "Note is not a JSON object".to_string(),
+    })?;
+
+    let text = |key: &str| {
+        object
+            .get(key)
+            .and_then(serde_json::Value::as_str)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    };
+    let time = |key: &str| {
+        object
+            .get(key)
+            .and_then(serde_json::Value::as_str)
+            .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+            .map(|value| value.with_timezone(&Utc))
+    };
+
+    let (title, content) = (text("title"), text("content"));
+    if title.is_none() && content.is_none() {
+        return Err(KbError::InvalidFormat {
+            message: "Note holds neither a title nor content".to_string(),
+        });
+    }
+
+    Ok(Note {
+        id: text("id").unwrap_or_else(|| fallback_id.to_string()),
+        title: title.unwrap_or_else(|| fallback_id.to_string()),
+        content: content.unwrap_or_default(),
+        tags: object
+            .get("tags")
+            .and_then(serde_json::Value::as_array)
+            .map(|tags| {
+                tags.iter()
+                    .filter_map(serde_json::Value::as_str)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        created_at: time("created_at").unwrap_or(fallback_time),
+        updated_at: time("updated_at").unwrap_or(fallback_time),
+        aliases: Vec::new(),
+        metadata: HashMap::new(),
+        attachments: Vec::new(),
+        pinned: false,
+        archived: false,
+        due_at: None,
+        notebook: None,
+        locked: false,
+    })
+}
+
+/// Recovers what precedes the cut in truncated JSON, by dropping the last
+/// incomplete member and closing the strings, arrays and objects left open
+fn repair_truncated_json(json: &str) -> Option<serde_json::Value> {
+    std::iter::once(json.len())
+        .chain(json.rmatch_indices(',').map(|(i, _)| i))
+        .take(64)
+        .find_map(|end| serde_json::from_str(&close_open_json(&json[..end])).ok())
+}
+
+/// Appends the closing quote and brackets that `prefix` is missing
+fn close_open_json(prefix: &str) -> String {
+    let mut closers = Vec::new();
+    let (mut in_string, mut escaped) = (false, false);
+    for c in prefix.chars() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => closers.push('}'),
+            '[' => closers.push(']'),
+            '}' | ']' => {
+                closers.pop();
+            }
+            _ => {}
+        }
+    }
+
+    let mut repaired = prefix.to_string();
+    if in_string {
+        if escaped {
+            repaired.pop();
+        }
+        repaired.push('"');
+    }
+    repaired.extend(closers.into_iter().rev());
+    repaired
+}
+
+/// Hashes the versioned fields of a note (title, content and tags), so two
+/// copies of the same version compare equal regardless of timestamps
+pub fn note_content_hash(note: &Note) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    note.title.hash(&mut hasher);
+    note.content.hash(&mut hasher);
+    note.tags.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Returns true when note file bytes hold a zstd frame rather than plain text
+pub fn is_compressed_note(bytes: &[u8]) -> bool {
+    bytes.starts_with(&ZSTD_MAGIC)
+}
+
+/// Turns the bytes of a note file into its text, decompressing them
+/// when needed, so plain and compressed files can be mixed in one vault
+pub fn decode_note_bytes(bytes: Vec<u8>) -> Result<String> {
+    let bytes = if is_compressed_note(&bytes) {
+        zstd::decode_all(bytes.as_slice())?
+    } else {
+        bytes
+    };
+
+    String::from_utf8(bytes).map_err(|e| KbError::InvalidFormat {
+        message: format!("Note file is not valid UTF-8: {}", e),
+    })
+}
+
+/// Turns serialized note JSON into file bytes, compressing it when it is
+/// larger than `threshold` bytes
+pub fn encode_note_json(json: String, threshold: Option<u64>) -> Result<Vec<u8>> {
+    match threshold {
+        Some(threshold) if json.len() as u64 > threshold => {
+            Ok(zstd::encode_all(json.as_bytes(), ZSTD_LEVEL)?)
+        }
+        _ => Ok(json.into_bytes()),
+    }
+}
+
+/// Fails unless both paths are on the same filesystem, which renaming one
+/// into the place of the other requires
+#[cfg(unix)]
+pub fn ensure_same_filesystem(a: &Path, b: &Path) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    if fs::metadata(a)?.dev() != fs::metadata(b)?.dev() {
+        return Err(KbError::RestoreFailed {
+            message: format!(
+                "{} and {} are on different filesystems, so they cannot be swapped by renaming",
+                a.display(),
+                b.display()
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Fails unless both paths are on the same filesystem (not detectable here;
+/// a failing rename is reported instead)
+#[cfg(not(unix))]
+pub fn ensure_same_filesystem(_a: &Path, _b: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Copies a directory and everything below it to `target`
+pub fn copy_dir_recursive(source: &Path, target: &Path) -> Result<()> {
+    fs::create_dir_all(target)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let destination = target.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_recursive(&entry.path(), &destination)?;
+        } else {
+            fs::copy(entry.path(), destination)?;
+        }
+    }
+    Ok(())
+}
+
+/// Brings text to Unicode NFKC form, so that composed and decomposed
+/// accents compare equal while keeping letter case
+pub fn normalize_text(text: &str) -> Cow<'_, str> {
+    ComposingNormalizerBorrowed::new_nfkc().normalize(text)
+}
+
+/// Normalizes text for case-insensitive matching in searches and tag
+/// comparisons
+///
+/// The text is brought to Unicode NFKC form, so composed and decomposed
+/// accents (`é` and `e` + U+0301) and compatibility characters such as
+/// ligatures compare equal, then lowercased. A few characters are folded
+/// further, as Unicode case folding does: `ß` becomes `ss` and the final
+/// sigma `ς` becomes `σ`. Turkish dotted and dotless I (`İ`, `ı`) fold to a
+/// plain `i`, so `İzmir`, `IZMIR` and `izmir` all match.
+pub fn fold_text(text: &str) -> String {
+    let composed = normalize_text(text);
+    let mut folded = String::with_capacity(composed.len());
+    for c in composed.chars().flat_map(char::to_lowercase) {
+        match c {
+            'ß' => folded.push_str("ss"),
+            'ς' => folded.push('σ'),
+            'ı' => folded.push('i'),
+            // Left by lowercasing `İ` into `i` + combining dot above
+            '\u{307}' if folded.ends_with('i') => {}
+            c => folded.push(c),
+        }
+    }
+    folded
+}
+
+/// Normalizes a tag for comparisons: trimmed, then folded by [`fold_text`]
+pub fn normalize_tag(tag: &str) -> String {
+    fold_text(tag.trim())
+}
+
+/// Adds and removes tags on a note, comparing them like [`normalize_tag`]
+///
+/// Added tags are stored normalized and lowercased; tags the note already
+/// carries in an equivalent form are not added again.
+///
+/// # Returns
+///
+/// The numbers of tags added and removed
+pub fn apply_tag_changes(note: &mut Note, add: &[String], remove: &[String]) -> (usize, usize) {
+    let mut added = 0;
+    for tag in add {
+        let wanted = normalize_tag(tag);
+        if !note.tags.iter().any(|t| normalize_tag(t) == wanted) {
+            note.tags.push(normalize_text(tag.trim()).to_lowercase());
+            added += 1;
+        }
+    }
+
+    let to_remove: Vec<String> = remove.iter().map(|tag| normalize_tag(tag)).collect();
+    let before = note.tags.len();
+    note.tags.retain(|t| !to_remove.contains(&normalize_tag(t)));
+    (added, before - note.tags.len())
+}
+
+/// Normalizes an alias or title for case-insensitive lookups
+pub fn normalize_alias(alias: &str) -> String {
+    alias.trim().to_lowercase()
+}
+
+/// Replaces a note's entries in the alias index with its current aliases
+pub fn index_note_aliases(index: &mut AliasIndex, note: &Note) {
+    unindex_note_aliases(index, &note.id);
+    for alias in &note.aliases {
+        index
+            .entry(normalize_alias(alias))
+            .or_default()
+            .insert(note.id.clone());
+    }
+}
+
+/// Removes every alias index entry pointing at the given note
+pub fn unindex_note_aliases(index: &mut AliasIndex, note_id: &str) {
+    index.retain(|_, ids| {
+        ids.remove(note_id);
+        !ids.is_empty()
+    });
+}
+
+/// Replaces a note's entry in the title index with its current title
+pub fn index_note_title(index: &mut TitleIndex, note: &Note) {
+    unindex_note_title(index, &note.id);
+    index
+        .entry(normalize_alias(&note.title))
+        .or_default()
+        .insert(note.id.clone());
+}
+
+/// Removes the title index entry pointing at the given note
+pub fn unindex_note_title(index: &mut TitleIndex, note_id: &str) {
+    index.retain(|_, ids| {
+        ids.remove(note_id);
+        !ids.is_empty()
+    });
+}
+
+/// Replaces a note's entries in the tag index with its current tags
+pub fn index_note_tags(index: &mut TagIndex, note: &Note) {
+    unindex_note_tags(index, &note.id);
+    for tag in &note.tags {
+        index
+            .entry(normalize_tag(tag))
+            .or_default()
+            .insert(note.id.clone());
+    }
+}
+
+/// Removes every tag index entry pointing at the given note
+pub fn unindex_note_tags(index: &mut TagIndex, note_id: &str) {
+    index.retain(|_, ids| {
+        ids.remove(note_id);
+        !ids.is_empty()
+    });
+}
+
+/// Checks that a snapshot name is safe to use as a file name
+pub fn validate_snapshot_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(KbError::InvalidFormat {
+            message: format!(
+                "Invalid snapshot name '{}': use up to 64 letters, digits, '-', '_' or '.', not starting with '.'",
+                name
+            ),
+        })
+    }
 }
 
 // Helper method for parsing tags
@@ -94,3 +1036,265 @@ pub fn parse_tags(tags: Option<String>) -> Vec<String> {
     })
     .unwrap_or_default()
 }
+
+/// Extracts the targets of `[[wiki-link]]` references from note content
+pub fn extract_wiki_links(content: &str) -> Vec<String> {
+    let mut links = Vec::new();
+    let mut rest = content;
+
+    while let Some(start) = rest.find("[[") {
+        let after = &rest[start + 2..];
+        match after.find("]]") {
+            Some(end) => {
+                let target = after[..end].trim();
+                if !target.is_empty() && !target.contains('\n') {
+                    links.push(target.to_string());
+                }
+                rest = &after[end + 2..];
+            }
+            None => break,
+        }
+    }
+
+    links
+}
+
+/// Container shape of a JSON import file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonImportShape {
+    /// A single note object
+    Object,
+    /// An array of note objects
+    Array,
+    /// One note object per line (JSONL)
+    Lines,
+}
+
+/// Streams the elements of a JSON import file to `handle` one at a time
+///
+/// Arrays are walked element by element and JSONL files line by line, so
+/// memory use stays flat regardless of the file size. A JSONL line that fails
+/// to parse is reported to `handle` and skipped, while malformed JSON inside an
+/// array aborts the stream since the following elements can't be located.
+/// The file is only read as a single pretty-printed object when its first
+/// line is an unfinished object.
+///
+/// # Arguments
+///
+/// * `reader` - The import file
+/// * `handle` - Called with the index and parse result of every element
+///
+/// # Returns
+///
+/// The detected container shape
+pub fn stream_json_values<R: BufRead>(
+    mut reader: R,
+    mut handle: impl FnMut(usize, Result<serde_json::Value>),
+) -> Result<JsonImportShape> {
+    // Skip leading whitespace to peek at the first significant byte
+    let first = loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            break None;
+        }
+        match buffer.iter().position(|b| !b.is_ascii_whitespace()) {
+            Some(offset) => {
+                let byte = buffer[offset];
+                reader.consume(offset);
+                break Some(byte);
+            }
+            None => {
+                let len = buffer.len();
+                reader.consume(len);
+            }
+        }
+    };
+
+    match first {
+        // Nothing to import from an empty file
+        None => Ok(JsonImportShape::Lines),
+
+        Some(b'[') => {
+            let mut deserializer = serde_json::Deserializer::from_reader(reader);
+            serde::Deserializer::deserialize_seq(
+                &mut deserializer,
+                ElementVisitor {
+                    handle: &mut handle,
+                },
+            )?;
+            deserializer.end()?;
+            Ok(JsonImportShape::Array)
+        }
+
+        Some(_) => {
+            let mut first_line = String::new();
+            reader.read_line(&mut first_line)?;
+
+            let first_value = match serde_json::from_str::<serde_json::Value>(&first_line) {
+                Ok(value) => Ok(value),
+                // A pretty-printed object spans several lines, so its first
+                // line is an unfinished object
+                Err(_) if !first_line.trim_end().ends_with('}') => {
+                    let value = serde_json::from_reader(Cursor::new(first_line).chain(reader))?;
+                    handle(0, Ok(value));
+                    return Ok(JsonImportShape::Object);
+                }
+                // A complete but malformed first line fails on its own
+                Err(e) => Err(KbError::from(e)),
+            };
+            handle(0, first_value);
+
+            let mut index = 1;
+            for line in reader.lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                handle(index, serde_json::from_str(&line).map_err(KbError::from));
+                index += 1;
+            }
+            Ok(JsonImportShape::Lines)
+        }
+    }
+}
+
+/// Serde visitor handing array elements to a callback as they are parsed
+struct ElementVisitor<'a, F> {
+    handle: &'a mut F,
+}
+
+impl<'de, F> serde::de::Visitor<'de> for ElementVisitor<'_, F>
+where
+    F: FnMut(usize, Result<serde_json::Value>),
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("an array of notes")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<(), A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let mut index = 0;
+        while let Some(value) = seq.next_element::<serde_json::Value>()? {
+            (self.handle)(index, Ok(value));
+            index += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Builds a note from one exported JSON object
+///
+/// `title` and `content` are required. Tags are merged with `extra_tags`, the
+/// original `id` stays resolvable as an alias and every other field is kept
+/// as metadata. Objects written by `kbnotes export --format json` also keep
+/// their timestamps, aliases and metadata.
+pub fn note_from_json_value(
+    json: &serde_json::Value,
+    extra_tags: &[String],
+    source_path: &Path,
+) -> Result<Note> {
+    let object = json.as_object().ok_or_else(|| KbError::InvalidFormat {
+        message: "Expected a JSON object".to_string(),
+    })?;
+
+    // Extract note fields
+    let title = object
+        .get("title")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| KbError::InvalidFormat {
+            message: "JSON missing 'title' field".to_string(),
+        })?
+        .to_string();
+
+    let content = object
+        .get("content")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| KbError::InvalidFormat {
+            message: "JSON missing 'content' field".to_string(),
+        })?
+        .to_string();
+
+    // Extract tags if present and merge with extra_tags
+    let mut tags = extra_tags.to_vec();
+    if let Some(json_tags) = object.get("tags").and_then(|v| v.as_array()) {
+        for tag in json_tags.iter().filter_map(|v| v.as_str()) {
+            if !tag.is_empty() && !tags.iter().any(|t| t == tag) {
+                tags.push(tag.to_string());
+            }
+        }
+    }
+
+    let mut note = Note::new(title, content, tags);
+
+    // Keep the original ID resolvable after the import remaps it
+    if let Some(original_id) = object.get("id").and_then(|v| v.as_str()) {
+        note.aliases.push(original_id.to_string());
+    }
+
+    // Fields of exported notes that map onto the note itself
+    let timestamp = |key: &str| {
+        object
+            .get(key)
+            .and_then(|v| v.as_str())
+            .and_then(|v| DateTime::parse_from_rfc3339(v).ok())
+            .map(|v| v.with_timezone(&Utc))
+    };
+    if let Some(created_at) = timestamp("created_at") {
+        note.created_at = created_at;
+    }
+    if let Some(updated_at) = timestamp("updated_at") {
+        note.updated_at = updated_at;
+    }
+    if let Some(aliases) = object.get("aliases").and_then(|v| v.as_array()) {
+        for alias in aliases.iter().filter_map(|v| v.as_str()) {
+            if !note.aliases.iter().any(|a| a == alias) {
+                note.aliases.push(alias.to_string());
+            }
+        }
+    }
+    if let Some(metadata) = object.get("metadata").and_then(|v| v.as_object()) {
+        for (key, value) in metadata {
+            let value = match value.as_str() {
+                Some(text) => text.to_string(),
+                None => value.to_string(),
+            };
+            note.metadata.insert(key.clone(), value);
+        }
+    }
+
+    // Add metadata
+    note.metadata
+        .insert("source_file".to_string(), source_path.display().to_string());
+    note.metadata
+        .insert("import_format".to_string(), "json".to_string());
+    note.metadata
+        .insert("imported_at".to_string(), Utc::now().to_rfc3339());
+
+    // Copy additional fields as metadata
+    for (key, value) in object {
+        if ![
+            "title",
+            "content",
+            "tags",
+            "created_at",
+            "updated_at",
+            "aliases",
+            "metadata",
+        ]
+        .contains(&key.as_str())
+        {
+            let value = match value.as_str() {
+                Some(text) => text.to_string(),
+                // For non-string values, keep the JSON representation
+                None => value.to_string(),
+            };
+            note.metadata.insert(key.clone(), value);
+        }
+    }
+
+    Ok(note)
+}
@@ -0,0 +1,277 @@
+//! Progress journals that make directory imports resumable.
+//!
+//! While a directory is imported, every processed file is appended to a
+//! journal under `<notes_dir>/.state/imports/`, keyed by its path relative to
+//! the imported directory and a hash of its bytes. If the import stops early
+//! (a crash, or files that failed), running it again skips the files the
+//! journal already records as imported. The journal is removed once an import
+//! finishes without failures.
+//!
+//! Journals are JSON Lines: a header naming the imported directory, then one
+//! entry per file. Entries are only ever appended, so a torn last line left
+//! by a crash is simply ignored when the journal is read back.
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{KbError, Result};
+
+/// Directory (inside the state directory) holding import journals
+pub const IMPORT_JOURNAL_DIR: &str = "imports";
+
+/// Outcome of importing one file
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportFileStatus {
+    /// Every note of the file was imported (or already existed)
+    Imported,
+    /// The file, or some of its notes, could not be imported
+    Failed,
+}
+
+/// First line of a journal, identifying the import it belongs to
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImportJournalHeader {
+    /// Canonical path of the imported directory
+    pub root: PathBuf,
+    /// Import format the files are read as
+    pub format: String,
+    /// When the import was first started
+    pub started_at: DateTime<Utc>,
+}
+
+/// Journal line recording the outcome of one file
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ImportJournalEntry {
+    /// Path of the file relative to the imported directory, with `/` separators
+    pub path: String,
+    /// Hash of the file's bytes when it was imported
+    pub content_hash: String,
+    /// Whether the file was imported
+    pub status: ImportFileStatus,
+    /// Number of notes created from the file
+    pub notes: usize,
+    /// Why the file failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The progress journal of one directory import
+pub struct ImportJournal {
+    path: PathBuf,
+    header: ImportJournalHeader,
+    /// Latest entry of each file
+    entries: HashMap<String, ImportJournalEntry>,
+    writer: File,
+}
+
+impl ImportJournal {
+    /// Returns the journal file used for importing `root` as `format`
+    ///
+    /// # Arguments
+    ///
+    /// * `state_dir` - The vault's state directory
+    /// * `root` - Canonical path of the imported directory
+    /// * `format` - Import format
+    pub fn journal_path(state_dir: &Path, root: &Path, format: &str) -> PathBuf {
+        let key = format!("{}\0{}", root.display(), format);
+        state_dir
+            .join(IMPORT_JOURNAL_DIR)
+            .join(format!("{}.jsonl", stable_hash(key.as_bytes())))
+    }
+
+    /// Opens the journal left by an earlier import of the same directory
+    ///
+    /// # Returns
+    ///
+    /// The journal, ready to record more files, or `None` when the directory
+    /// has no unfinished import
+    pub fn open(state_dir: &Path, root: &Path, format: &str) -> Result<Option<Self>> {
+        let path = Self::journal_path(state_dir, root, format);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(KbError::Io(e)),
+        };
+
+        let mut lines = BufReader::new(file).lines();
+        let header: ImportJournalHeader = match lines.next().transpose()? {
+            Some(line) => serde_json::from_str(&line).map_err(|e| KbError::InvalidFormat {
+                message: format!("Corrupt import journal {}: {}", path.display(), e),
+            })?,
+            None => return Ok(None),
+        };
+        // A journal whose name collides with another import is not ours
+        if header.root != root || header.format != format {
+            return Ok(None);
+        }
+
+        let mut entries = HashMap::new();
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            // A line torn by a crash only loses the outcome of that one file
+            match serde_json::from_str::<ImportJournalEntry>(&line) {
+                Ok(entry) => {
+                    entries.insert(entry.path.clone(), entry);
+                }
+                Err(_) => warn!("Ignoring an incomplete line in {}", path.display()),
+            }
+        }
+
+        let mut writer = OpenOptions::new().append(true).open(&path)?;
+        // Start on a fresh line in case the last one was torn
+        writer.write_all(b"\n")?;
+
+        Ok(Some(Self {
+            path,
+            header,
+            entries,
+            writer,
+        }))
+    }
+
+    /// Starts a new journal, replacing any earlier one for the same import
+    pub fn create(state_dir: &Path, root: &Path, format: &str) -> Result<Self> {
+        let path = Self::journal_path(state_dir, root, format);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let header = ImportJournalHeader {
+            root: root.to_path_buf(),
+            format: format.to_string(),
+            started_at: Utc::now(),
+        };
+        let mut writer = File::create(&path)?;
+        writeln!(writer, "{}", serde_json::to_string(&header)?)?;
+
+        Ok(Self {
+            path,
+            header,
+            entries: HashMap::new(),
+            writer,
+        })
+    }
+
+    /// The header of the journal
+    pub fn header(&self) -> &ImportJournalHeader {
+        &self.header
+    }
+
+    /// Number of files recorded as imported
+    pub fn imported_files(&self) -> usize {
+        self.entries
+            .values()
+            .filter(|entry| entry.status == ImportFileStatus::Imported)
+            .count()
+    }
+
+    /// Checks whether a file was imported with exactly this content
+    pub fn is_imported(&self, path: &str, content_hash: &str) -> bool {
+        self.entries.get(path).is_some_and(|entry| {
+            entry.status == ImportFileStatus::Imported && entry.content_hash == content_hash
+        })
+    }
+
+    /// Appends the outcome of a file to the journal
+    pub fn record(&mut self, entry: ImportJournalEntry) -> Result<()> {
+        writeln!(self.writer, "{}", serde_json::to_string(&entry)?)?;
+        self.writer.flush()?;
+        self.entries.insert(entry.path.clone(), entry);
+        Ok(())
+    }
+
+    /// Deletes the journal once the import has completed
+    pub fn remove(self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(KbError::Io(e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Deletes import journals
+///
+/// # Arguments
+///
+/// * `state_dir` - The vault's state directory
+/// * `all` - Delete every journal instead of only those whose imported
+///   directory no longer exists
+///
+/// # Returns
+///
+/// The number of journals deleted
+pub fn purge_import_journals(state_dir: &Path, all: bool) -> Result<usize> {
+    let dir = state_dir.join(IMPORT_JOURNAL_DIR);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(KbError::Io(e)),
+    };
+
+    let mut purged = 0;
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("jsonl") {
+            continue;
+        }
+
+        let stale = all || {
+            let header = File::open(&path).ok().and_then(|file| {
+                let line = BufReader::new(file).lines().next()?.ok()?;
+                serde_json::from_str::<ImportJournalHeader>(&line).ok()
+            });
+            header.is_none_or(|header| !header.root.is_dir())
+        };
+        if stale {
+            fs::remove_file(&path)?;
+            purged += 1;
+        }
+    }
+
+    info!("Purged {} import journal(s)", purged);
+    Ok(purged)
+}
+
+/// Hashes a file's bytes for the journal without reading it into memory
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hash = fnv1a(hash, &buffer[..read]);
+    }
+    Ok(format!("{:016x}", hash))
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// FNV-1a, which unlike `DefaultHasher` is guaranteed to stay the same
+/// across Rust releases, so journals written by older builds still match
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// Hex FNV-1a hash of some bytes
+pub(crate) fn stable_hash(bytes: &[u8]) -> String {
+    format!("{:016x}", fnv1a(FNV_OFFSET_BASIS, bytes))
+}
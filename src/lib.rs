@@ -3,21 +3,91 @@
 //! This library provides functionality for creating, storing, searching, and managing notes
 //! with tags and content in Markdown format.
 
+mod attachment;
+mod backend;
 mod backup_scheduler;
+mod backup_targets;
 mod cli;
+mod due;
 mod errors;
+mod examples;
+mod export;
+mod file_backend;
+#[cfg(feature = "ffi")]
+mod ffi;
 mod helper;
+mod import_journal;
+mod links;
 mod note;
+mod note_format;
+mod notebook;
+mod pdf;
+mod quarantine;
+mod query;
+mod redaction;
+mod reindex;
+mod related;
+mod sanitize;
+mod retention;
+mod schedule;
+mod search_index;
+mod search_query;
+mod snippet;
+mod sections;
+mod sqlite_backend;
+mod stats;
 mod storage;
+mod structure;
+mod tag_policy;
+mod tasks;
+mod template;
+mod trash;
 mod types;
+mod visibility;
+mod webhooks;
 mod config;
 
 // Re-export key components
+pub use attachment::*;
+pub use backend::*;
 pub use backup_scheduler::*;
+pub use backup_targets::*;
 pub use config::*;
 pub use cli::*;
+pub use due::*;
 pub use errors::*;
+pub use examples::*;
+pub use export::*;
+pub use file_backend::*;
+#[cfg(feature = "ffi")]
+pub use ffi::*;
 pub use helper::*;
+pub use import_journal::*;
+pub use links::*;
 pub use note::*;
+pub use note_format::*;
+pub use notebook::*;
+pub use pdf::*;
+pub use quarantine::*;
+pub use query::*;
+pub use redaction::*;
+pub use reindex::*;
+pub use related::*;
+pub use sanitize::*;
+pub use retention::*;
+pub use schedule::*;
+pub use search_index::*;
+pub use search_query::*;
+pub use snippet::*;
+pub use sections::*;
+pub use sqlite_backend::*;
+pub use stats::*;
 pub use storage::*;
+pub use structure::*;
+pub use tag_policy::*;
+pub use tasks::*;
+pub use template::*;
+pub use trash::*;
 pub use types::*;
+pub use visibility::*;
+pub use webhooks::*;
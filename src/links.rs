@@ -0,0 +1,147 @@
+//! Index of the `[[wiki-links]]` between notes.
+//!
+//! Links are written as `[[note-id]]` or `[[Note Title]]` (aliases work too).
+//! The index keeps each note's link targets as written, plus the reverse
+//! mapping from normalized target to the notes linking to it. Targets are
+//! only resolved to note IDs when queried, so renaming a note or creating the
+//! note a dangling link points at never leaves the index stale.
+//!
+//! The index is rebuilt whenever notes are loaded and updated on every save,
+//! delete and file watcher reload. A copy is written to
+//! `<notes_dir>/.state/links.json` after loading and on shutdown, for tools
+//! that want the link graph without parsing every note. It is only rewritten
+//! when the links changed, so read-only commands leave it untouched.
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    io::Write,
+    path::Path,
+};
+
+use serde::Serialize;
+use tempfile::NamedTempFile;
+
+use crate::{extract_wiki_links, normalize_alias, Note, Result};
+
+/// File (inside the state directory) holding the persisted link index
+pub const LINK_INDEX_FILE: &str = "links.json";
+
+/// A link from one note to another
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NoteLink {
+    /// The link target as written between `[[` and `]]`
+    pub target: String,
+    /// ID of the note the target resolves to, `None` for broken or
+    /// ambiguous links
+    pub note_id: Option<String>,
+}
+
+/// Outgoing and incoming `[[wiki-links]]` of every note
+#[derive(Debug, Clone, Default)]
+pub struct LinkIndex {
+    /// Link targets of each note, as written
+    outgoing: HashMap<String, Vec<String>>,
+    /// Normalized link target to the IDs of the notes linking to it
+    incoming: HashMap<String, HashSet<String>>,
+    /// Whether the links may differ from the last copy saved
+    dirty: bool,
+}
+
+impl LinkIndex {
+    /// Replaces a note's entries with the links in its current content
+    pub fn index_note(&mut self, note: &Note) {
+        let targets = extract_wiki_links(&note.content);
+        if self.outgoing(&note.id) == targets.as_slice() {
+            return;
+        }
+        self.remove_note(&note.id);
+        self.dirty = true;
+
+        for target in &targets {
+            self.incoming
+                .entry(normalize_alias(target))
+                .or_default()
+                .insert(note.id.clone());
+        }
+        if !targets.is_empty() {
+            self.outgoing.insert(note.id.clone(), targets);
+        }
+    }
+
+    /// Drops the links of a note that no longer exists
+    pub fn remove_note(&mut self, note_id: &str) {
+        let Some(targets) = self.outgoing.remove(note_id) else {
+            return;
+        };
+        self.dirty = true;
+        for target in targets {
+            let key = normalize_alias(&target);
+            if let Some(sources) = self.incoming.get_mut(&key) {
+                sources.remove(note_id);
+                if sources.is_empty() {
+                    self.incoming.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Forgets every link
+    pub fn clear(&mut self) {
+        if !self.outgoing.is_empty() {
+            self.dirty = true;
+        }
+        self.outgoing.clear();
+        self.incoming.clear();
+    }
+
+    /// Link targets of a note, in the order they appear in its content
+    pub fn outgoing(&self, note_id: &str) -> &[String] {
+        self.outgoing.get(note_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// IDs of the notes linking to any of the given names
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - What a note can be linked by: its ID, title and aliases
+    pub fn sources_linking_to<'a>(
+        &self,
+        names: impl IntoIterator<Item = &'a str>,
+    ) -> HashSet<String> {
+        names
+            .into_iter()
+            .filter_map(|name| self.incoming.get(&normalize_alias(name)))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// Writes the outgoing links of every note to `path` as JSON, unless
+    /// they haven't changed since the last save or match the file already
+    /// there (as after rebuilding the index on load)
+    ///
+    /// # Returns
+    ///
+    /// Whether the file was written
+    pub fn save(&mut self, path: &Path) -> Result<bool> {
+        if !self.dirty && path.exists() {
+            return Ok(false);
+        }
+
+        // Sorted, so the file only changes when the links do
+        let sorted: BTreeMap<&String, &Vec<String>> = self.outgoing.iter().collect();
+        let json = serde_json::to_vec_pretty(&sorted)?;
+        if fs::read(path).is_ok_and(|existing| existing == json) {
+            self.dirty = false;
+            return Ok(false);
+        }
+
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        fs::create_dir_all(dir)?;
+        let mut temp_file = NamedTempFile::new_in(dir)?;
+        temp_file.write_all(&json)?;
+        temp_file.persist(path).map_err(|e| e.error)?;
+        self.dirty = false;
+        Ok(true)
+    }
+}
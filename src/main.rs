@@ -1,11 +1,22 @@
-use std::{fs, path::PathBuf, process, sync::Arc};
+use std::{
+    fs,
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
-use clap::Parser;
 use env_logger::Env;
 use log::{debug, error, info, warn};
 use tokio::sync::Mutex;
 
-use kbnotes::{App as CliApp, Cli, Config, KbError, NoteStorage, Result};
+use kbnotes::{
+    default_config_path, load_config_from_file, load_default_config, parse_cli, validate_webhooks,
+    App as CliApp, Cli, Config, ConfigOrigin, KbError, NoteStorage, Redactor, Result,
+    SHUTDOWN_BACKUP_TIMEOUT, WEBHOOK_DRAIN_TIMEOUT,
+};
 
 #[tokio::main]
 async fn main() {
@@ -16,30 +27,36 @@ async fn main() {
 
     info!("KBNotes application starting...");
 
-    // Parse CLI arguments using the derived structure
-    let cli = Cli::parse();
+    // Parse CLI arguments, with the registered examples in the help output
+    let cli = parse_cli();
+    if cli.no_color {
+        console::set_colors_enabled(false);
+    }
 
     // Initialize the storage system
     match initialize_storage(&cli).await {
-        Ok((storage, config)) => {
+        Ok((storage, config, origin)) => {
             info!("NoteStorage initialized successfully");
 
             // Get backup status
             let backup_status = storage.lock().await.get_backup_status().await;
-            info!(
-                "Backup scheduler status: {}",
-                if backup_status.is_running {
-                    "running"
-                } else {
-                    "stopped"
+            match backup_status.next_backup_time {
+                Some(next) if backup_status.is_running => info!(
+                    "Backup scheduler status: running, next backup at {}",
+                    next.to_rfc3339()
+                ),
+                _ if backup_status.is_running => {
+                    info!("Backup scheduler status: running, manual backups only")
                 }
-            );
+                _ => info!("Backup scheduler status: stopped"),
+            }
+            info!("Note write durability: fsync={}", config.fsync);
 
             // Set up ctrl-c handler for graceful shutdown
             setup_signal_handler(storage.clone());
 
             // Run the application until terminated
-            run_application(storage.clone(), config, cli).await;
+            run_application(storage.clone(), config, origin, cli).await;
         }
         Err(e) => {
             error!("Failed to initialize storage: {}", e);
@@ -49,9 +66,9 @@ async fn main() {
 }
 
 /// Initialize the storage system with configuration
-async fn initialize_storage(cli: &Cli) -> Result<(Arc<Mutex<NoteStorage>>, Config)> {
+async fn initialize_storage(cli: &Cli) -> Result<(Arc<Mutex<NoteStorage>>, Config, ConfigOrigin)> {
     // Step 1: Load configuration
-    let config = load_configuration(cli)?;
+    let (config, origin) = load_configuration(cli)?;
     info!("Configuration loaded successfully");
 
     // Step 2: Create the storage instance
@@ -68,24 +85,33 @@ async fn initialize_storage(cli: &Cli) -> Result<(Arc<Mutex<NoteStorage>>, Confi
         .await?;
 
     // Return the initialized storage instance
-    Ok((storage_arc, config))
+    Ok((storage_arc, config, origin))
 }
 
 /// Load configuration from file and/or command-line arguments
-fn load_configuration(cli: &Cli) -> Result<Config> {
+fn load_configuration(cli: &Cli) -> Result<(Config, ConfigOrigin)> {
     // Default configuration
     let mut config = load_default_config()?;
-    // Override with config file if specified
-    if let Some(config_path) = &cli.config {
-        match load_config_from_file(config_path) {
-            Ok(file_config) => {
-                info!("Loaded configuration from file: {}", config_path.display());
+    let mut origin = ConfigOrigin {
+        path: match &cli.config {
+            Some(config_path) => config_path.clone(),
+            None => default_config_path()?,
+        },
+        ..ConfigOrigin::default()
+    };
+
+    // Override with the config file if specified, or if the default one exists
+    if cli.config.is_some() || origin.path.exists() {
+        match load_config_from_file(&origin.path) {
+            Ok((file_config, file_keys)) => {
+                info!("Loaded configuration from file: {}", origin.path.display());
                 config = file_config;
+                origin.file_keys = file_keys;
             }
             Err(e) => {
                 warn!(
                     "Failed to load configuration from {}: {}",
-                    config_path.display(),
+                    origin.path.display(),
                     e
                 );
                 warn!("Falling back to default configuration");
@@ -97,69 +123,19 @@ fn load_configuration(cli: &Cli) -> Result<Config> {
     if let Some(notes_dir) = cli.notes_dir.clone() {
         info!("Using notes directory from command line: {}", notes_dir);
         config.notes_dir = PathBuf::from(notes_dir);
+        origin.cli_keys.push("notes_dir".to_string());
     }
 
     if let Some(backup_dir) = cli.backup_dir.clone() {
         info!("Using backup directory from command line: {}", backup_dir);
         config.backup_dir = PathBuf::from(backup_dir);
+        origin.cli_keys.push("backup_dir".to_string());
     }
 
     // Validate the configuration
     validate_configuration(&config)?;
 
-    Ok(config)
-}
-
-/// Load the default configuration
-fn load_default_config() -> Result<Config> {
-    // Get home directory for default paths
-    let home_dir = dirs::home_dir().ok_or_else(|| KbError::ApplicationError {
-        message: "Could not determine home directory".to_string(),
-    })?;
-
-    let notes_dir = home_dir.join(".kbnotes").join("notes");
-    let backup_dir = home_dir.join(".kbnotes").join("backups");
-
-    Ok(Config {
-        notes_dir,
-        backup_dir,
-        backup_frequency: 24, // Daily backups
-        max_backups: 10,      // Keep 10 backups
-        encrypt_notes: false, // No encryption by default
-        editor_command: None, // No custom editor
-        auto_save: true,      // Auto-save enabled
-        auto_backup: true,    // Auto-backup enabled
-    })
-}
-
-/// Load configuration from a file
-fn load_config_from_file(config_path: &PathBuf) -> Result<Config> {
-    use std::fs;
-
-    let config_file = fs::read_to_string(config_path).map_err(KbError::Io)?;
-
-    // Try to parse as JSON first
-    if config_path.ends_with(".json") {
-        return serde_json::from_str(&config_file).map_err(KbError::Serialization);
-    }
-
-    // // Try to parse as TOML if not JSON
-    // if config_path.ends_with(".toml") {
-    //     return toml::from_str(&config_file).map_err(|e| KbError::ApplicationError {
-    //         message: format!("Failed to parse TOML config: {}", e),
-    //     });
-    // }
-
-    // // Try YAML as a last resort
-    // if config_path.ends_with(".yaml") || config_path.ends_with(".yml") {
-    //     return serde_yaml::from_str(&config_file).map_err(|e| KbError::ApplicationError {
-    //         message: format!("Failed to parse YAML config: {}", e)
-    //     });
-    // }
-
-    Err(KbError::ApplicationError {
-        message: format!("Unsupported config file format: {}", config_path.display()),
-    })
+    Ok((config, origin))
 }
 
 /// Validate the configuration for required values and permissions
@@ -200,6 +176,10 @@ fn validate_configuration(config: &Config) -> Result<()> {
         // We'll check if we can create it during initialization
     }
 
+    // Compile redaction rules up front so a bad pattern is reported at startup
+    Redactor::compile(&config.redaction)?;
+    validate_webhooks(&config.webhooks)?;
+
     // Validate backup frequency (must be positive)
     if config.backup_frequency == 0 {
         return Err(KbError::ApplicationError {
@@ -210,6 +190,19 @@ fn validate_configuration(config: &Config) -> Result<()> {
     Ok(())
 }
 
+/// Set once the application starts shutting down, after a command or on
+/// Ctrl+C, so that the shutdown runs only once
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Claims the shutdown for the caller
+///
+/// # Returns
+///
+/// `false` when the application is already shutting down
+fn begin_shutdown() -> bool {
+    !SHUTTING_DOWN.swap(true, Ordering::SeqCst)
+}
+
 /// Gracefully shuts down the application
 async fn shutdown_application(storage: Arc<Mutex<NoteStorage>>) -> Result<()> {
     info!("Application shutting down...");
@@ -264,22 +257,42 @@ async fn shutdown_application(storage: Arc<Mutex<NoteStorage>>) -> Result<()> {
 }
 
 /// Enhanced application loop with multiple signal handling and proper timeout behavior
-async fn run_application(storage: Arc<Mutex<NoteStorage>>, config: Config, cli: Cli) {
+async fn run_application(
+    storage: Arc<Mutex<NoteStorage>>,
+    config: Config,
+    origin: ConfigOrigin,
+    cli: Cli,
+) {
     // Your main application logic here
     info!("Application is running. Press Ctrl+C to exit.");
 
     // Create our CLI application handler
-    let app = CliApp::new(storage, config, cli.verbose);
+    let app = CliApp::new(Arc::clone(&storage), config, origin, cli.verbose, cli.quiet);
 
     // Run the CLI command
-    match app.run(cli.command).await {
+    let succeeded = match app.run(cli.command).await {
         Ok(_) => {
             debug!("Command executed successfully");
+            true
         }
         Err(e) => {
             error!("Command execution failed: {}", e);
-            process::exit(1);
+            false
         }
+    };
+
+    // Shut down as on Ctrl+C whether or not the command succeeded, so the
+    // shutdown backup and the syncs deferred by `fsync = on_close` run on
+    // every exit
+    if !begin_shutdown() {
+        // The Ctrl+C handler is shutting down and exits when done
+        std::future::pending::<()>().await;
+    }
+    if let Err(e) = shutdown_application(storage).await {
+        error!("Errors occurred during shutdown: {}", e);
+    }
+    if !succeeded {
+        process::exit(1);
     }
 }
 
@@ -289,10 +302,21 @@ fn setup_signal_handler(storage: Arc<Mutex<NoteStorage>>) {
     tokio::spawn(async move {
         match tokio::signal::ctrl_c().await {
             Ok(()) => {
+                if !begin_shutdown() {
+                    info!("Received Ctrl+C, waiting for the shutdown under way");
+                    return;
+                }
                 info!("Received Ctrl+C, initiating shutdown");
 
                 // Execute shutdown with timeout
-                const SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+                const SHUTDOWN_TIMEOUT_SECS: u64 = 40;
+                // Leave room for the storage lock (5 s) and cache flush (5 s)
+                // around the final backup and the webhook drain, so neither
+                // outlasts the shutdown
+                const _: () = assert!(
+                    SHUTDOWN_BACKUP_TIMEOUT.as_secs() + WEBHOOK_DRAIN_TIMEOUT.as_secs() + 10
+                        < SHUTDOWN_TIMEOUT_SECS
+                );
 
                 match tokio::time::timeout(
                     tokio::time::Duration::from_secs(SHUTDOWN_TIMEOUT_SECS),
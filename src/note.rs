@@ -2,11 +2,40 @@
 //!
 //! This module contains the primary types used throughout the application,
 //! including Note and Config structures.
+use std::{collections::HashMap, sync::Mutex};
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use ulid::{Generator, Ulid};
+
+use crate::Attachment;
+
+/// Source of note IDs, shared so IDs made in the same millisecond still sort
+/// in creation order
+static ID_GENERATOR: Mutex<Generator> = Mutex::new(Generator::new());
+
+/// Generates the ID of a new note
+///
+/// IDs are lowercase [ULIDs](https://github.com/ulid/spec): 26 characters from
+/// `0-9a-z`, starting with the creation time so they sort by age. They never
+/// depend on the title, so notes with the same title cannot collide and IDs
+/// are safe to use as file names. Notes loaded from disk keep whatever ID
+/// they were saved with.
+pub fn generate_note_id() -> String {
+    // A poisoned lock or an exhausted millisecond only costs the ordering
+    let generated = match ID_GENERATOR.lock() {
+        Ok(mut generator) => generator.generate().ok(),
+        Err(_) => None,
+    };
+    let ulid = match generated {
+        Some(ulid) => ulid,
+        None => Ulid::new(),
+    };
+    ulid.to_string().to_lowercase()
+}
 
 /// Represents a single note in our system
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Note {
     /// Unique identifier for the note
     pub id: String,
@@ -20,18 +49,37 @@ pub struct Note {
     pub created_at: DateTime<Utc>,
     /// Last modification time
     pub updated_at: DateTime<Utc>,
+    /// Alternative names (old titles, external IDs) the note can be found by
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Free-form key/value data (import source, associated project path, ...)
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    /// Files attached to the note, stored under `attachments/<id>/`
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// Pinned notes are listed before all others
+    #[serde(default)]
+    pub pinned: bool,
+    /// Archived notes are left out of listings and searches unless asked for
+    #[serde(default)]
+    pub archived: bool,
+    /// When the note is due, if it has a due date
+    #[serde(default)]
+    pub due_at: Option<DateTime<Utc>>,
+    /// Notebook the note is filed in, such as `work/project-x`
+    #[serde(default)]
+    pub notebook: Option<String>,
+    /// Locked notes are read-only until unlocked, unless a change is forced
+    #[serde(default)]
+    pub locked: bool,
 }
 
 impl Note {
     /// Creates a new note with the given title and content
     pub fn new(title: String, content: String, tags: Vec<String>) -> Self {
         let now = Utc::now();
-        // Generate a unique ID using timestamp and title
-        let id = format!(
-            "{}-{}",
-            now.timestamp_millis(),
-            title.to_lowercase().replace(' ', "-")
-        );
+        let id = generate_note_id();
 
         Note {
             id,
@@ -40,6 +88,14 @@ impl Note {
             tags,
             created_at: now,
             updated_at: now,
+            aliases: Vec::new(),
+            metadata: HashMap::new(),
+            attachments: Vec::new(),
+            pinned: false,
+            archived: false,
+            due_at: None,
+            notebook: None,
+            locked: false,
         }
     }
 }
@@ -0,0 +1,276 @@
+//! On-disk formats of note files.
+//!
+//! Notes are stored either as JSON (`<id>.json`, optionally zstd-compressed)
+//! or as Markdown (`<id>.md`): a YAML front matter block holding every field
+//! but the content, followed by the content exactly as written. Only the
+//! first `---` line after the opening one closes the front matter, so
+//! content may contain `---` lines of its own. A vault may hold files of
+//! several formats, for instance halfway through a `migrate-format --to` run.
+//!
+//! The split format keeps the content alone in `<id>.md`, for other tools to
+//! edit, and every other field in a `<id>.meta.json` sidecar. The sidecar
+//! identifies the note and records a hash of the content it was written
+//! with, so a content file edited elsewhere is noticed when it is read.
+use std::{fs, path::Path, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+
+use crate::{stable_hash, KbError, Note, Result};
+
+/// Line opening and closing the front matter of Markdown note files
+const FRONT_MATTER_DELIMITER: &str = "---";
+
+/// Suffix of the sidecar holding every field but the content of a note
+/// stored in the split format
+pub const SPLIT_META_SUFFIX: &str = ".meta.json";
+
+/// Sidecar field recording the hash of the content it was written with
+const CONTENT_HASH_KEY: &str = "content_hash";
+
+/// The `storage_format` configuration setting
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageFormat {
+    /// Pretty-printed JSON (the default, so existing vaults keep their files)
+    #[default]
+    Json,
+    /// Markdown with YAML front matter
+    Markdown,
+    /// Plain Markdown content with a JSON sidecar for the other fields
+    Split,
+}
+
+impl StorageFormat {
+    /// Every format, in the order note lookups try them (split sidecars
+    /// before Markdown files, whose name split content files share)
+    pub const ALL: [StorageFormat; 3] = [
+        StorageFormat::Json,
+        StorageFormat::Split,
+        StorageFormat::Markdown,
+    ];
+
+    /// File extension of note files in this format (of the sidecar, for
+    /// the split format)
+    pub fn extension(&self) -> &'static str {
+        match self {
+            StorageFormat::Json => "json",
+            StorageFormat::Markdown => "md",
+            StorageFormat::Split => &SPLIT_META_SUFFIX[1..],
+        }
+    }
+
+    /// Returns the format of a note file, judging by its name
+    ///
+    /// The content file of a split note is named like a Markdown note file;
+    /// [`is_split_content`] tells them apart.
+    pub fn of_path(path: &Path) -> Option<Self> {
+        if path
+            .file_name()?
+            .to_string_lossy()
+            .ends_with(SPLIT_META_SUFFIX)
+        {
+            return Some(StorageFormat::Split);
+        }
+        let extension = path.extension()?;
+        [StorageFormat::Json, StorageFormat::Markdown]
+            .into_iter()
+            .find(|format| extension == format.extension())
+    }
+}
+
+impl std::fmt::Display for StorageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageFormat::Json => write!(f, "json"),
+            StorageFormat::Markdown => write!(f, "markdown"),
+            StorageFormat::Split => write!(f, "split"),
+        }
+    }
+}
+
+impl std::str::FromStr for StorageFormat {
+    type Err = KbError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "json" => Ok(StorageFormat::Json),
+            "markdown" | "md" => Ok(StorageFormat::Markdown),
+            "split" => Ok(StorageFormat::Split),
+            _ => Err(KbError::InvalidArgument {
+                message: format!(
+                    "Unknown storage format '{}': expected json, markdown or split",
+                    value
+                ),
+            }),
+        }
+    }
+}
+
+/// Returns true for files holding a note in one of the storage formats
+///
+/// Of a split note, only the sidecar counts: its content file is read along
+/// with it.
+pub fn is_note_file(path: &Path) -> bool {
+    StorageFormat::of_path(path).is_some() && !is_split_content(path)
+}
+
+/// Returns true for the content file of a split note, i.e. an `.md` file
+/// with a sidecar next to it
+pub fn is_split_content(path: &Path) -> bool {
+    StorageFormat::of_path(path) == Some(StorageFormat::Markdown)
+        && note_file_as(path, StorageFormat::Split).is_some_and(|sidecar| sidecar.exists())
+}
+
+/// Returns true for an `.md` file that is neither a Markdown note nor
+/// paired with a sidecar: the content file of a split note whose sidecar is
+/// not written yet, or was removed first by a delete
+pub fn is_orphaned_split_content(path: &Path) -> bool {
+    StorageFormat::of_path(path) == Some(StorageFormat::Markdown)
+        && !is_split_content(path)
+        && fs::read_to_string(path).is_ok_and(|text| !has_front_matter(&text))
+}
+
+/// Returns true when Markdown text starts with front matter, which a
+/// Markdown note has and the content file of a split note does not
+pub fn has_front_matter(text: &str) -> bool {
+    split_front_matter(text).is_some()
+}
+
+/// Returns the name a note file has without the suffix of its format,
+/// i.e. the note ID as sanitized by the ID policy
+pub fn note_file_stem(path: &Path) -> Option<String> {
+    let format = StorageFormat::of_path(path)?;
+    let name = path.file_name()?.to_string_lossy();
+    name.strip_suffix(&format!(".{}", format.extension()))
+        .map(str::to_string)
+}
+
+/// Returns the path the note stored at `path` has in `format`, in the same
+/// directory; a split sidecar and its content file are each other's
+/// `Split` and `Markdown` paths
+pub fn note_file_as(path: &Path, format: StorageFormat) -> Option<PathBuf> {
+    let stem = note_file_stem(path)?;
+    Some(path.with_file_name(format!("{}.{}", stem, format.extension())))
+}
+
+/// Returns every file making up the note stored at `path`: the file itself,
+/// and the content file of a split sidecar
+pub fn note_file_paths(path: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![path.to_path_buf()];
+    if StorageFormat::of_path(path) == Some(StorageFormat::Split) {
+        paths.extend(note_file_as(path, StorageFormat::Markdown));
+    }
+    paths
+}
+
+/// Parses the text of a note file in the given format
+///
+/// Of a split note, `text` is the sidecar, and the note comes back without
+/// its content (see [`note_from_split`]).
+pub fn parse_note(text: &str, format: StorageFormat) -> Result<Note> {
+    match format {
+        StorageFormat::Json => Ok(serde_json::from_str(text)?),
+        StorageFormat::Markdown => note_from_markdown(text),
+        StorageFormat::Split => Ok(note_from_split(text, String::new())?.0),
+    }
+}
+
+/// Serializes a note in the split format
+///
+/// # Returns
+///
+/// The sidecar JSON, which records a hash of the content, and the content
+pub fn note_to_split(note: &Note) -> Result<(String, String)> {
+    let serde_json::Value::Object(mut fields) = serde_json::to_value(note)? else {
+        return Err(KbError::InvalidFormat {
+            message: format!("Note {} did not serialize to an object", note.id),
+        });
+    };
+    fields.remove("content");
+    fields.insert(
+        CONTENT_HASH_KEY.to_string(),
+        stable_hash(note.content.as_bytes()).into(),
+    );
+    Ok((serde_json::to_string_pretty(&fields)?, note.content.clone()))
+}
+
+/// Parses a note written by [`note_to_split`]
+///
+/// # Returns
+///
+/// The note, and whether `content` differs from the content the sidecar was
+/// written with, i.e. the content file was edited by another tool since
+pub fn note_from_split(sidecar: &str, content: String) -> Result<(Note, bool)> {
+    let mut fields: serde_json::Map<String, serde_json::Value> = serde_json::from_str(sidecar)?;
+    let recorded = fields.remove(CONTENT_HASH_KEY);
+    let edited = recorded.as_ref().and_then(|hash| hash.as_str())
+        != Some(stable_hash(content.as_bytes()).as_str());
+    fields.insert("content".to_string(), content.into());
+    Ok((
+        serde_json::from_value(serde_json::Value::Object(fields))?,
+        edited,
+    ))
+}
+
+/// Serializes a note as Markdown with YAML front matter
+pub fn note_to_markdown(note: &Note) -> Result<String> {
+    let Value::Mapping(mut front_matter) = serde_yaml::to_value(note).map_err(yaml_error)? else {
+        return Err(KbError::InvalidFormat {
+            message: format!("Note {} did not serialize to a mapping", note.id),
+        });
+    };
+    front_matter.shift_remove("content");
+
+    let yaml = serde_yaml::to_string(&front_matter).map_err(yaml_error)?;
+    Ok(format!(
+        "{delimiter}\n{}{delimiter}\n{}",
+        yaml,
+        note.content,
+        delimiter = FRONT_MATTER_DELIMITER
+    ))
+}
+
+/// Parses a note written by [`note_to_markdown`]
+///
+/// # Returns
+///
+/// The note, or `KbError::InvalidFormat` when the front matter is missing,
+/// unterminated or lacks required fields
+pub fn note_from_markdown(text: &str) -> Result<Note> {
+    let (yaml, content) = split_front_matter(text).ok_or_else(|| KbError::InvalidFormat {
+        message: "Markdown note has no front matter enclosed in --- lines".to_string(),
+    })?;
+
+    let mut front_matter: Mapping = if yaml.trim().is_empty() {
+        Mapping::new()
+    } else {
+        serde_yaml::from_str(yaml).map_err(yaml_error)?
+    };
+    front_matter.insert("content".into(), content.into());
+    serde_yaml::from_value(Value::Mapping(front_matter)).map_err(yaml_error)
+}
+
+/// Splits Markdown note text into its front matter and its content
+fn split_front_matter(text: &str) -> Option<(&str, &str)> {
+    let after = text.strip_prefix(FRONT_MATTER_DELIMITER)?;
+    let rest = after
+        .strip_prefix('\n')
+        .or_else(|| after.strip_prefix("\r\n"))?;
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end_matches(['\r', '\n']) == FRONT_MATTER_DELIMITER {
+            return Some((&rest[..offset], &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Reports a YAML error as an invalid note file
+fn yaml_error(e: serde_yaml::Error) -> KbError {
+    KbError::InvalidFormat {
+        message: format!("Invalid note front matter: {}", e),
+    }
+}
@@ -0,0 +1,124 @@
+//! Notebooks, which organize notes into a hierarchy.
+//!
+//! A notebook is a `/`-separated path such as `work/project-x`. Notebooks
+//! exist as long as a note is filed in them or below them, and a notebook
+//! holds the notes of every notebook below it: `work` includes the notes in
+//! `work/project-x`. Paths are compared case-sensitively.
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::{KbError, Note, Result};
+
+/// Separator between the levels of a notebook path
+pub const NOTEBOOK_SEPARATOR: char = '/';
+
+/// Checks a notebook path given on the command line or through the API
+///
+/// # Arguments
+///
+/// * `path` - The notebook path, such as `work/project-x`
+///
+/// # Returns
+///
+/// The path without surrounding whitespace, or `KbError::InvalidArgument`
+/// when it is empty, starts or ends with `/`, has an empty level, or has a
+/// `.` or `..` level
+pub fn validate_notebook_path(path: &str) -> Result<String> {
+    let path = path.trim();
+    let invalid = |reason: &str| {
+        Err(KbError::InvalidArgument {
+            message: format!("Invalid notebook '{}': {}", path, reason),
+        })
+    };
+
+    if path.is_empty() {
+        return invalid("the path is empty");
+    }
+    if path.starts_with(NOTEBOOK_SEPARATOR) || path.ends_with(NOTEBOOK_SEPARATOR) {
+        return invalid("the path cannot start or end with '/'");
+    }
+    for level in path.split(NOTEBOOK_SEPARATOR) {
+        if level.trim().is_empty() {
+            return invalid("levels cannot be empty");
+        }
+        if level.trim() != level {
+            return invalid("levels cannot start or end with whitespace");
+        }
+        if level == "." || level == ".." {
+            return invalid("'.' and '..' are not allowed");
+        }
+        if level.chars().any(|c| c == '\\' || c.is_control()) {
+            return invalid("backslashes and control characters are not allowed");
+        }
+    }
+    Ok(path.to_string())
+}
+
+impl Note {
+    /// Returns true when the note is filed in `notebook` or in a notebook
+    /// below it
+    pub fn in_notebook(&self, notebook: &str) -> bool {
+        self.notebook.as_deref().is_some_and(|own| {
+            own.strip_prefix(notebook)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(NOTEBOOK_SEPARATOR))
+        })
+    }
+}
+
+/// A notebook and the number of notes in it, as listed by `kbnotes notebooks`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NotebookCount {
+    /// Full path of the notebook
+    pub path: String,
+    /// Notes filed directly in the notebook
+    pub notes: usize,
+    /// Notes in the notebook and every notebook below it
+    pub total: usize,
+}
+
+impl NotebookCount {
+    /// Nesting depth, 0 for a top-level notebook
+    pub fn depth(&self) -> usize {
+        self.path.matches(NOTEBOOK_SEPARATOR).count()
+    }
+
+    /// Last level of the path
+    pub fn name(&self) -> &str {
+        self.path
+            .rsplit(NOTEBOOK_SEPARATOR)
+            .next()
+            .unwrap_or(&self.path)
+    }
+}
+
+/// Builds the notebook tree of a set of notes
+///
+/// # Arguments
+///
+/// * `notes` - Notes to count; notes without a notebook are skipped
+///
+/// # Returns
+///
+/// Every notebook holding notes and every notebook above one, each followed
+/// by the notebooks below it, with levels sorted by name
+pub fn notebook_tree(notes: &[Note]) -> Vec<NotebookCount> {
+    // Keyed by level so that `work/a` sorts right after `work`, before `work-b`
+    let mut counts: BTreeMap<Vec<&str>, (usize, usize)> = BTreeMap::new();
+    for notebook in notes.iter().filter_map(|note| note.notebook.as_deref()) {
+        let levels: Vec<&str> = notebook.split(NOTEBOOK_SEPARATOR).collect();
+        for depth in 1..=levels.len() {
+            counts.entry(levels[..depth].to_vec()).or_default().1 += 1;
+        }
+        counts.entry(levels).or_default().0 += 1;
+    }
+
+    counts
+        .into_iter()
+        .map(|(levels, (notes, total))| NotebookCount {
+            path: levels.join("/"),
+            notes,
+            total,
+        })
+        .collect()
+}
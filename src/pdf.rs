@@ -0,0 +1,569 @@
+//! Rendering notes as PDF documents.
+//!
+//! Notes are laid out on A4 pages using the standard PDF fonts (Helvetica and
+//! Courier), so no font files have to be embedded. The Markdown content is
+//! reduced to headings, wrapped paragraphs, list items, quotes and code blocks.
+//! Text is wrapped to the page width and flows onto as many pages as needed;
+//! code lines that are too long are broken rather than cut off.
+//!
+//! The standard fonts only cover the Windows-1252 character set, other
+//! characters are printed as `?`.
+use printpdf::{
+    BuiltinFont, Color, Greyscale, IndirectFontRef, Line, Mm, PdfDocument, PdfDocumentReference,
+    PdfLayerReference, PdfPageIndex, Point,
+};
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+
+use crate::{KbError, Note, Result};
+
+const PAGE_WIDTH_MM: f32 = 210.0;
+const PAGE_HEIGHT_MM: f32 = 297.0;
+const MARGIN_MM: f32 = 20.0;
+const CONTENT_WIDTH_MM: f32 = PAGE_WIDTH_MM - 2.0 * MARGIN_MM;
+
+/// Indentation of each list, quote or footnote level
+const INDENT_MM: f32 = 7.0;
+
+/// Millimetres per typographic point
+const PT_TO_MM: f32 = 0.352_778;
+
+/// Line height as a multiple of the font size
+const LINE_SPACING: f32 = 1.35;
+
+const TITLE_SIZE: f32 = 20.0;
+const BODY_SIZE: f32 = 11.0;
+const CODE_SIZE: f32 = 9.0;
+const META_SIZE: f32 = 9.0;
+const FOOTER_SIZE: f32 = 8.0;
+
+/// Grey level of metadata and page numbers (0 is black)
+const MUTED_GREY: f32 = 0.4;
+
+/// Columns a tab advances to in code blocks
+const TAB_WIDTH: usize = 4;
+
+/// Advance widths of the printable ASCII characters (space to tilde) in
+/// Helvetica, in thousandths of the font size
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667,
+    611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500,
+    222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+/// Advance widths of the printable ASCII characters in Helvetica-Bold
+const HELVETICA_BOLD_WIDTHS: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611, 975, 722, 722, 722, 722, 667,
+    611, 778, 722, 278, 556, 722, 611, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 333, 278, 333, 584, 556, 333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556,
+    278, 889, 611, 611, 611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584,
+];
+
+/// Advance width of every Courier character
+const COURIER_WIDTH: u16 = 600;
+
+/// Width assumed for non-ASCII characters, on the wide side so lines never
+/// run into the margin
+const FALLBACK_WIDTH: u16 = 722;
+
+/// Characters of Windows-1252 outside ASCII and Latin-1
+const WIN_ANSI_EXTRAS: &str = "€‚ƒ„…†‡ˆ‰Š‹ŒŽ‘’“”•–—˜™š›œžŸ";
+
+#[derive(Debug, Clone, Copy)]
+enum FontStyle {
+    Regular,
+    Bold,
+    Mono,
+}
+
+impl FontStyle {
+    fn char_width(self, c: char) -> u16 {
+        let table = match self {
+            FontStyle::Mono => return COURIER_WIDTH,
+            FontStyle::Regular => &HELVETICA_WIDTHS,
+            FontStyle::Bold => &HELVETICA_BOLD_WIDTHS,
+        };
+        match c {
+            ' '..='~' => table[c as usize - ' ' as usize],
+            _ => FALLBACK_WIDTH,
+        }
+    }
+
+    /// Width of `text` set at `size` points, in millimetres
+    fn text_width(self, text: &str, size: f32) -> f32 {
+        let units: u32 = text.chars().map(|c| u32::from(self.char_width(c))).sum();
+        units as f32 / 1000.0 * size * PT_TO_MM
+    }
+}
+
+/// Replaces characters the standard fonts cannot show with `?`
+fn encodable(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            ' '..='~' | '\u{A0}'..='\u{FF}' => c,
+            '\t' => ' ',
+            _ if WIN_ANSI_EXTRAS.contains(c) => c,
+            _ => '?',
+        })
+        .collect()
+}
+
+/// Breaks text into lines no wider than `width` millimetres
+///
+/// Newlines force a break. Words longer than a line are split between
+/// characters.
+fn wrap(text: &str, style: FontStyle, size: f32, width: f32) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_string()
+            } else {
+                format!("{} {}", line, word)
+            };
+            if style.text_width(&candidate, size) <= width {
+                line = candidate;
+                continue;
+            }
+
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            for c in word.chars() {
+                line.push(c);
+                if line.chars().count() > 1 && style.text_width(&line, size) > width {
+                    line.pop();
+                    lines.push(std::mem::replace(&mut line, c.to_string()));
+                }
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Kinds of blocks a note's content is laid out as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockKind {
+    Heading(usize),
+    Paragraph,
+    Code,
+    Rule,
+}
+
+/// A block of content with its indentation and an optional list marker
+#[derive(Debug)]
+struct Block {
+    kind: BlockKind,
+    text: String,
+    indent: f32,
+    marker: Option<String>,
+}
+
+/// Collects the blocks of a Markdown document from its parser events
+#[derive(Default)]
+struct BlockBuilder {
+    blocks: Vec<Block>,
+    text: String,
+    /// Open lists with the next number of ordered ones
+    lists: Vec<Option<u64>>,
+    quote_depth: usize,
+    in_footnote: bool,
+    marker: Option<String>,
+}
+
+impl BlockBuilder {
+    fn indent(&self) -> f32 {
+        (self.lists.len() + self.quote_depth + usize::from(self.in_footnote)) as f32 * INDENT_MM
+    }
+
+    /// Ends the current block, dropping it if it holds no text
+    fn flush(&mut self, kind: BlockKind) {
+        let text = std::mem::take(&mut self.text);
+        if kind != BlockKind::Code && text.trim().is_empty() {
+            return;
+        }
+        self.blocks.push(Block {
+            kind,
+            text,
+            indent: self.indent(),
+            marker: self.marker.take(),
+        });
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Start(Tag::Heading { .. })
+            | Event::Start(Tag::Paragraph)
+            | Event::Start(Tag::CodeBlock(_))
+            | Event::Start(Tag::TableHead)
+            | Event::Start(Tag::TableRow) => self.flush(BlockKind::Paragraph),
+            Event::End(TagEnd::Heading(level)) => self.flush(BlockKind::Heading(level as usize)),
+            Event::End(TagEnd::Paragraph)
+            | Event::End(TagEnd::TableHead)
+            | Event::End(TagEnd::TableRow)
+            | Event::End(TagEnd::Item) => self.flush(BlockKind::Paragraph),
+            Event::End(TagEnd::CodeBlock) => {
+                let trimmed = self.text.trim_end_matches('\n').len();
+                self.text.truncate(trimmed);
+                self.flush(BlockKind::Code);
+            }
+            Event::Start(Tag::List(start)) => {
+                self.flush(BlockKind::Paragraph);
+                self.lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                self.flush(BlockKind::Paragraph);
+                self.lists.pop();
+            }
+            Event::Start(Tag::Item) => {
+                self.flush(BlockKind::Paragraph);
+                self.marker = Some(match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}.", *number - 1)
+                    }
+                    _ => "•".to_string(),
+                });
+            }
+            Event::Start(Tag::BlockQuote(_)) => {
+                self.flush(BlockKind::Paragraph);
+                self.quote_depth += 1;
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                self.flush(BlockKind::Paragraph);
+                self.quote_depth = self.quote_depth.saturating_sub(1);
+            }
+            Event::Start(Tag::FootnoteDefinition(label)) => {
+                self.flush(BlockKind::Paragraph);
+                self.in_footnote = true;
+                self.marker = Some(format!("[{}]", label));
+            }
+            Event::End(TagEnd::FootnoteDefinition) => {
+                self.flush(BlockKind::Paragraph);
+                self.in_footnote = false;
+            }
+            Event::Start(Tag::TableCell) if !self.text.is_empty() => self.text.push_str(" | "),
+            Event::Text(text)
+            | Event::Code(text)
+            | Event::Html(text)
+            | Event::InlineHtml(text)
+            | Event::InlineMath(text)
+            | Event::DisplayMath(text) => self.text.push_str(&text),
+            Event::FootnoteReference(label) => self.text.push_str(&format!("[{}]", label)),
+            Event::TaskListMarker(checked) => {
+                self.text.push_str(if checked { "[x] " } else { "[ ] " })
+            }
+            Event::SoftBreak => self.text.push(' '),
+            Event::HardBreak => self.text.push('\n'),
+            Event::Rule => {
+                self.flush(BlockKind::Paragraph);
+                self.blocks.push(Block {
+                    kind: BlockKind::Rule,
+                    text: String::new(),
+                    indent: 0.0,
+                    marker: None,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Splits Markdown into the blocks it is laid out as
+fn markdown_blocks(markdown: &str) -> Vec<Block> {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+
+    let mut builder = BlockBuilder::default();
+    for event in Parser::new_ext(markdown, options) {
+        builder.handle(event);
+    }
+    builder.flush(BlockKind::Paragraph);
+    builder.blocks
+}
+
+/// Lays out notes page by page into a PDF document
+///
+/// Every note starts on a new page and gets a bookmark, so a document holding
+/// several notes can be navigated from the viewer's outline.
+pub struct PdfWriter {
+    doc: PdfDocumentReference,
+    regular: IndirectFontRef,
+    bold: IndirectFontRef,
+    mono: IndirectFontRef,
+    layer: PdfLayerReference,
+    page: PdfPageIndex,
+    pages: usize,
+    /// Top of the next line, in millimetres from the bottom of the page
+    y: f32,
+    /// True while nothing has been written to the current page
+    fresh_page: bool,
+    /// Grey level text is currently written in
+    grey: f32,
+}
+
+impl PdfWriter {
+    /// Starts an empty document
+    ///
+    /// # Arguments
+    /// * `title` - Title stored in the document properties
+    pub fn new(title: &str) -> Result<Self> {
+        let (doc, page, layer) =
+            PdfDocument::new(title, Mm(PAGE_WIDTH_MM), Mm(PAGE_HEIGHT_MM), "Content");
+        let font = |builtin| doc.add_builtin_font(builtin).map_err(pdf_error);
+        let regular = font(BuiltinFont::Helvetica)?;
+        let bold = font(BuiltinFont::HelveticaBold)?;
+        let mono = font(BuiltinFont::Courier)?;
+        let layer = doc.get_page(page).get_layer(layer);
+
+        let mut writer = Self {
+            doc,
+            regular,
+            bold,
+            mono,
+            layer,
+            page,
+            pages: 1,
+            y: PAGE_HEIGHT_MM - MARGIN_MM,
+            fresh_page: true,
+            grey: 0.0,
+        };
+        writer.page_number();
+        Ok(writer)
+    }
+
+    /// Lays out a note: its title, a metadata line and the content
+    pub fn add_note(&mut self, note: &Note) {
+        if !self.fresh_page {
+            self.new_page();
+        }
+        self.doc.add_bookmark(note.title.clone(), self.page);
+
+        self.wrapped(&note.title, FontStyle::Bold, TITLE_SIZE, 0.0, None);
+        self.gap(1.0);
+
+        let mut meta = Vec::new();
+        if !note.tags.is_empty() {
+            meta.push(format!("Tags: {}", note.tags.join(", ")));
+        }
+        if let Some(notebook) = &note.notebook {
+            meta.push(format!("Notebook: {}", notebook));
+        }
+        meta.push(format!(
+            "Created {}",
+            note.created_at.format("%Y-%m-%d %H:%M UTC")
+        ));
+        meta.push(format!(
+            "Updated {}",
+            note.updated_at.format("%Y-%m-%d %H:%M UTC")
+        ));
+        self.grey = MUTED_GREY;
+        self.set_grey(MUTED_GREY);
+        self.wrapped(&meta.join(" · "), FontStyle::Regular, META_SIZE, 0.0, None);
+        self.grey = 0.0;
+        self.set_grey(0.0);
+        self.gap(5.0);
+
+        for block in markdown_blocks(&note.content) {
+            match block.kind {
+                BlockKind::Heading(level) => {
+                    let size = match level {
+                        1 => 16.0,
+                        2 => 14.0,
+                        _ => 12.0,
+                    };
+                    self.gap(3.0);
+                    self.wrapped(&block.text, FontStyle::Bold, size, block.indent, None);
+                    self.gap(1.5);
+                }
+                BlockKind::Paragraph => {
+                    let list_item = block.marker.is_some();
+                    self.wrapped(
+                        &block.text,
+                        FontStyle::Regular,
+                        BODY_SIZE,
+                        block.indent,
+                        block.marker.as_deref(),
+                    );
+                    self.gap(if list_item { 1.0 } else { 2.5 });
+                }
+                BlockKind::Code => {
+                    self.gap(1.0);
+                    self.code(&block.text, block.indent + 4.0);
+                    self.gap(3.0);
+                }
+                BlockKind::Rule => self.rule(),
+            }
+        }
+    }
+
+    /// Returns the finished document
+    pub fn finish(self) -> Result<Vec<u8>> {
+        let PdfWriter { doc, layer, .. } = self;
+        // The layer refers back to the document, which can only be saved once
+        // nothing else holds on to it
+        drop(layer);
+        doc.save_to_bytes().map_err(pdf_error)
+    }
+
+    fn new_page(&mut self) {
+        let (page, layer) = self
+            .doc
+            .add_page(Mm(PAGE_WIDTH_MM), Mm(PAGE_HEIGHT_MM), "Content");
+        self.layer = self.doc.get_page(page).get_layer(layer);
+        self.page = page;
+        self.pages += 1;
+        self.y = PAGE_HEIGHT_MM - MARGIN_MM;
+        self.fresh_page = true;
+        self.page_number();
+        self.set_grey(self.grey);
+    }
+
+    /// Prints the page number centred in the bottom margin
+    fn page_number(&mut self) {
+        let number = self.pages.to_string();
+        let width = FontStyle::Regular.text_width(&number, FOOTER_SIZE);
+        self.set_grey(MUTED_GREY);
+        self.layer.use_text(
+            number,
+            FOOTER_SIZE,
+            Mm((PAGE_WIDTH_MM - width) / 2.0),
+            Mm(MARGIN_MM / 2.0),
+            &self.regular,
+        );
+        self.set_grey(0.0);
+    }
+
+    fn set_grey(&self, level: f32) {
+        self.layer
+            .set_fill_color(Color::Greyscale(Greyscale::new(level, None)));
+    }
+
+    fn font(&self, style: FontStyle) -> &IndirectFontRef {
+        match style {
+            FontStyle::Regular => &self.regular,
+            FontStyle::Bold => &self.bold,
+            FontStyle::Mono => &self.mono,
+        }
+    }
+
+    /// Moves down by `height` millimetres, unless at the top of a page
+    fn gap(&mut self, height: f32) {
+        if !self.fresh_page {
+            self.y -= height;
+        }
+    }
+
+    /// Writes one line at `x` millimetres from the left margin, starting a new
+    /// page when it does not fit on the current one
+    fn line(&mut self, text: &str, style: FontStyle, size: f32, x: f32) {
+        let height = size * PT_TO_MM * LINE_SPACING;
+        if self.y - height < MARGIN_MM {
+            self.new_page();
+        }
+        if !text.is_empty() {
+            let baseline = self.y - size * PT_TO_MM;
+            self.layer.use_text(
+                text,
+                size,
+                Mm(MARGIN_MM + x),
+                Mm(baseline),
+                self.font(style),
+            );
+        }
+        self.y -= height;
+        self.fresh_page = false;
+    }
+
+    /// Writes text wrapped to the content width, with an optional list marker
+    /// hanging in the indentation before the first line
+    fn wrapped(
+        &mut self,
+        text: &str,
+        style: FontStyle,
+        size: f32,
+        indent: f32,
+        marker: Option<&str>,
+    ) {
+        let text = encodable(text);
+        let lines = wrap(&text, style, size, CONTENT_WIDTH_MM - indent);
+        for (index, line) in lines.iter().enumerate() {
+            if index == 0 {
+                if let Some(marker) = marker {
+                    // Written first so a page break moves marker and line together
+                    let height = size * PT_TO_MM * LINE_SPACING;
+                    if self.y - height < MARGIN_MM {
+                        self.new_page();
+                    }
+                    self.layer.use_text(
+                        encodable(marker),
+                        size,
+                        Mm(MARGIN_MM + (indent - INDENT_MM).max(0.0)),
+                        Mm(self.y - size * PT_TO_MM),
+                        &self.regular,
+                    );
+                }
+            }
+            self.line(line, style, size, indent);
+        }
+    }
+
+    /// Writes a code block line by line, breaking lines wider than the page
+    fn code(&mut self, text: &str, indent: f32) {
+        let char_width = FontStyle::Mono.text_width(" ", CODE_SIZE);
+        let columns = (((CONTENT_WIDTH_MM - indent) / char_width) as usize).max(1);
+
+        for source_line in text.split('\n') {
+            let mut expanded = String::new();
+            for c in source_line.chars() {
+                if c == '\t' {
+                    let spaces = TAB_WIDTH - expanded.chars().count() % TAB_WIDTH;
+                    expanded.push_str(&" ".repeat(spaces));
+                } else {
+                    expanded.push(c);
+                }
+            }
+
+            let chars: Vec<char> = encodable(&expanded).chars().collect();
+            if chars.is_empty() {
+                self.line("", FontStyle::Mono, CODE_SIZE, indent);
+            }
+            for chunk in chars.chunks(columns) {
+                let chunk: String = chunk.iter().collect();
+                self.line(&chunk, FontStyle::Mono, CODE_SIZE, indent);
+            }
+        }
+    }
+
+    /// Draws a horizontal rule across the content width
+    fn rule(&mut self) {
+        if self.y - 4.0 < MARGIN_MM {
+            self.new_page();
+        }
+        let y = Mm(self.y - 2.0);
+        self.layer
+            .set_outline_color(Color::Greyscale(Greyscale::new(0.7, None)));
+        self.layer.set_outline_thickness(0.5);
+        self.layer.add_line(Line {
+            points: vec![
+                (Point::new(Mm(MARGIN_MM), y), false),
+                (Point::new(Mm(PAGE_WIDTH_MM - MARGIN_MM), y), false),
+            ],
+            is_closed: false,
+        });
+        self.y -= 4.0;
+        self.fresh_page = false;
+    }
+}
+
+fn pdf_error(e: printpdf::Error) -> KbError {
+    KbError::ApplicationError {
+        message: format!("Failed to render PDF: {}", e),
+    }
+}
@@ -0,0 +1,76 @@
+//! Quarantine for note files that can't be read.
+//!
+//! Loading moves such a file from `notes_dir/<path>` to
+//! `notes_dir/.corrupt/<path>.<timestamp>`, next to a file ending in
+//! [`CORRUPT_REASON_SUFFIX`] that holds the error, so the note no longer
+//! vanishes silently while the broken file is kept for `kbnotes doctor
+//! --corrupt` to recover.
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Serialize;
+
+/// Directory (inside `notes_dir`) holding note files that could not be read
+pub const CORRUPT_DIR: &str = ".corrupt";
+
+/// Suffix of the file (next to a quarantined file) recording why it could not be read
+pub const CORRUPT_REASON_SUFFIX: &str = ".reason";
+
+/// Format of the timestamp appended to quarantined file names
+const QUARANTINE_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// A note file waiting in the quarantine
+#[derive(Debug, Clone, Serialize)]
+pub struct QuarantinedFile {
+    /// Where the file is now
+    pub path: PathBuf,
+    /// Where the file was in the vault
+    pub original: PathBuf,
+    /// When the file was quarantined, if its name still says
+    pub quarantined_at: Option<DateTime<Utc>>,
+    /// Why the file could not be read, if recorded
+    pub reason: Option<String>,
+}
+
+/// Returns the name (relative to [`CORRUPT_DIR`]) a file at `relative`
+/// gets when quarantined at `at`
+///
+/// # Arguments
+///
+/// * `relative` - Path of the file relative to `notes_dir`
+/// * `at` - Time of the quarantine
+/// * `attempt` - Number of names already taken, appended to tell them apart
+pub fn quarantine_name(relative: &Path, at: DateTime<Utc>, attempt: usize) -> PathBuf {
+    let mut name = relative.as_os_str().to_os_string();
+    name.push(format!(".{}", at.format(QUARANTINE_TIMESTAMP_FORMAT)));
+    if attempt > 0 {
+        name.push(format!("-{}", attempt));
+    }
+    PathBuf::from(name)
+}
+
+/// Reverses [`quarantine_name`]
+///
+/// # Returns
+///
+/// The original path relative to `notes_dir` and the time of the quarantine,
+/// or the name unchanged and `None` when it carries no timestamp
+pub fn parse_quarantine_name(name: &Path) -> (PathBuf, Option<DateTime<Utc>>) {
+    if let Some((original, suffix)) = name.to_str().and_then(|name| name.rsplit_once('.')) {
+        let stamp = suffix.split_once('-').map_or(suffix, |(stamp, _)| stamp);
+        if let Ok(time) = NaiveDateTime::parse_from_str(stamp, QUARANTINE_TIMESTAMP_FORMAT) {
+            return (PathBuf::from(original), Some(time.and_utc()));
+        }
+    }
+    (name.to_path_buf(), None)
+}
+
+/// Returns the path of the file recording why `path` was quarantined
+pub fn quarantine_reason_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(CORRUPT_REASON_SUFFIX);
+    PathBuf::from(name)
+}
@@ -0,0 +1,494 @@
+//! Criteria for selecting notes by tags, creation date and update date.
+//!
+//! A [`NoteFilter`] is evaluated by [`NoteStorage::query_notes`](crate::NoteStorage::query_notes)
+//! so commands that select notes (`export`, `list` and `search`) agree on what
+//! matches. Searches take a [`SearchRequest`], which adds structural and tag
+//! policy conditions and the page of results to return. Listings take a
+//! [`ListFilter`] and a [`NoteSort`] and are read one [`NotePage`] at a time.
+use std::{cmp::Ordering, collections::HashSet};
+
+use chrono::{DateTime, Days, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    normalize_tag, KbError, MatchedFields, Note, Result, SearchMode, SearchScope, StructureFilter,
+};
+
+/// How the tags of a [`NoteFilter`] are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TagMatch {
+    /// The note carries at least one of the tags
+    #[default]
+    Any,
+    /// The note carries every tag
+    All,
+}
+
+/// Conditions a note must meet to be selected; an empty filter matches every note
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NoteFilter {
+    /// Tags to look for, compared case-insensitively (see [`normalize_tag`])
+    pub tags: Vec<String>,
+    /// Whether any or all of `tags` are required
+    pub tag_match: TagMatch,
+    /// Notes carrying any of these tags are left out, even if they carry
+    /// wanted `tags` too; compared case-insensitively
+    pub exclude_tags: Vec<String>,
+    /// Only notes created at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Only notes created before or at this time
+    pub until: Option<DateTime<Utc>>,
+    /// Only notes last updated at or after this time
+    pub updated_since: Option<DateTime<Utc>>,
+    /// Only notes last updated before or at this time
+    pub updated_until: Option<DateTime<Utc>>,
+}
+
+impl NoteFilter {
+    /// Checks whether a note meets every condition of the filter
+    pub fn matches(&self, note: &Note) -> bool {
+        if self.since.is_some_and(|since| note.created_at < since)
+            || self.until.is_some_and(|until| note.created_at > until)
+            || self
+                .updated_since
+                .is_some_and(|since| note.updated_at < since)
+            || self
+                .updated_until
+                .is_some_and(|until| note.updated_at > until)
+        {
+            return false;
+        }
+
+        let has_tag = |wanted: &String| {
+            let wanted = normalize_tag(wanted);
+            note.tags.iter().any(|tag| normalize_tag(tag) == wanted)
+        };
+        let included = self.tags.is_empty()
+            || match self.tag_match {
+                TagMatch::Any => self.tags.iter().any(has_tag),
+                TagMatch::All => self.tags.iter().all(has_tag),
+            };
+        included && !self.exclude_tags.iter().any(has_tag)
+    }
+}
+
+/// Notes of a backup to restore; the default restores every note
+#[derive(Debug, Clone, Default)]
+pub struct RestoreFilter {
+    /// Only these notes; `None` for any note
+    pub ids: Option<HashSet<String>>,
+    /// Only notes carrying at least one of these tags, compared
+    /// case-insensitively (see [`normalize_tag`])
+    pub tags: Vec<String>,
+    /// Leave attached files in the backup, restoring the notes alone
+    pub skip_attachments: bool,
+}
+
+impl RestoreFilter {
+    /// Checks the ID condition, which needs nothing but the archive entry
+    pub fn matches_id(&self, note_id: &str) -> bool {
+        self.ids.as_ref().is_none_or(|ids| ids.contains(note_id))
+    }
+
+    /// Checks every condition of the filter
+    pub fn matches(&self, note: &Note) -> bool {
+        self.matches_id(&note.id)
+            && (self.tags.is_empty()
+                || self.tags.iter().any(|wanted| {
+                    let wanted = normalize_tag(wanted);
+                    note.tags.iter().any(|tag| normalize_tag(tag) == wanted)
+                }))
+    }
+}
+
+/// How to restore a full backup; the default restores every note of the
+/// backup, keeping the notes that already exist
+#[derive(Debug, Clone, Default)]
+pub struct RestoreOptions {
+    /// Overwrite existing notes instead of preserving them
+    pub overwrite: bool,
+    /// Make the vault match the backup: overwrite existing notes and move
+    /// the notes the backup doesn't hold to the trash; can't be combined
+    /// with a filter
+    pub replace: bool,
+    /// Which notes of the backup to restore, and whether their attached
+    /// files come back too; notes it leaves out are counted apart from the
+    /// ones skipped because they exist
+    pub filter: RestoreFilter,
+    /// Read every note and report what would happen without writing
+    /// anything, leaving the cache, notes and backups untouched
+    pub dry_run: bool,
+}
+
+/// Narrows a listing beyond its [`NoteFilter`]; the default lists every
+/// unarchived note
+#[derive(Debug, Clone, Default)]
+pub struct ListFilter {
+    /// Tag and date conditions the notes must meet
+    pub filter: NoteFilter,
+    /// Include archived notes
+    pub include_archived: bool,
+    /// Only pinned notes
+    pub pinned_only: bool,
+    /// Only notes in this notebook or the notebooks below it
+    pub notebook: Option<String>,
+    /// Structural predicates the notes must all satisfy (`--has`)
+    pub structure: Vec<StructureFilter>,
+    /// Only these notes, e.g. the matches of a search; `None` for any note
+    pub ids: Option<HashSet<String>>,
+}
+
+impl ListFilter {
+    /// Checks the conditions that need nothing but the note itself, i.e.
+    /// all of them except `structure`
+    pub fn matches(&self, note: &Note) -> bool {
+        (self.include_archived || !note.archived)
+            && (!self.pinned_only || note.pinned)
+            && self
+                .notebook
+                .as_deref()
+                .is_none_or(|notebook| note.in_notebook(notebook))
+            && self.ids.as_ref().is_none_or(|ids| ids.contains(&note.id))
+            && self.filter.matches(note)
+    }
+}
+
+/// Field a listing is ordered by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortField {
+    /// Creation time
+    #[default]
+    Date,
+    /// Title
+    Title,
+    /// Note ID
+    Id,
+    /// Due date; notes without one come last in either direction
+    Due,
+}
+
+impl SortField {
+    /// Names accepted on the command line
+    pub const NAMES: [&'static str; 4] = ["date", "title", "id", "due"];
+}
+
+impl std::str::FromStr for SortField {
+    type Err = KbError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "date" => Ok(SortField::Date),
+            "title" => Ok(SortField::Title),
+            "id" => Ok(SortField::Id),
+            "due" => Ok(SortField::Due),
+            other => Err(KbError::InvalidArgument {
+                message: format!(
+                    "Unknown sort field '{}': use {}",
+                    other,
+                    SortField::NAMES.join(", ")
+                ),
+            }),
+        }
+    }
+}
+
+/// Order of a listing
+///
+/// Ties are broken by note ID, so the order is total and the same on every
+/// run, which lets a [`NotePage`] cursor resume exactly where a page ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct NoteSort {
+    /// Field the notes are ordered by
+    pub field: SortField,
+    /// Largest values first
+    pub descending: bool,
+    /// Put pinned notes before the others, whatever the field and direction
+    pub pinned_first: bool,
+}
+
+impl NoteSort {
+    /// Compares two notes in this order
+    pub fn compare(&self, a: &Note, b: &Note) -> Ordering {
+        self.compare_keys(&SortKey::of(self, a), &SortKey::of(self, b))
+    }
+
+    fn compare_keys(&self, a: &SortKey, b: &SortKey) -> Ordering {
+        let direction = |cmp: Ordering| if self.descending { cmp.reverse() } else { cmp };
+        let pinned = if self.pinned_first {
+            b.pinned.cmp(&a.pinned)
+        } else {
+            Ordering::Equal
+        };
+        pinned.then_with(|| match self.field {
+            SortField::Title => direction(a.text.cmp(b.text).then_with(|| a.id.cmp(b.id))),
+            SortField::Id => direction(a.id.cmp(b.id)),
+            SortField::Date => direction(a.time.cmp(&b.time).then_with(|| a.id.cmp(b.id))),
+            SortField::Due => match (a.time, b.time) {
+                (Some(a_due), Some(b_due)) => {
+                    direction(a_due.cmp(&b_due).then_with(|| a.id.cmp(b.id)))
+                }
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => a.id.cmp(b.id),
+            },
+        })
+    }
+}
+
+/// The values of a note that decide its place in a [`NoteSort`]
+struct SortKey<'a> {
+    pinned: bool,
+    /// The title when sorting by title, empty otherwise
+    text: &'a str,
+    /// The creation or due time when sorting by date or due date
+    time: Option<DateTime<Utc>>,
+    id: &'a str,
+}
+
+impl<'a> SortKey<'a> {
+    fn of(sort: &NoteSort, note: &'a Note) -> Self {
+        SortKey {
+            pinned: note.pinned,
+            text: match sort.field {
+                SortField::Title => &note.title,
+                _ => "",
+            },
+            time: match sort.field {
+                SortField::Date => Some(note.created_at),
+                SortField::Due => note.due_at,
+                _ => None,
+            },
+            id: &note.id,
+        }
+    }
+}
+
+/// Position of the last note of a page, as carried by a page cursor
+#[derive(Debug, Serialize, Deserialize)]
+struct PageCursor {
+    sort: NoteSort,
+    pinned: bool,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    time: Option<DateTime<Utc>>,
+    id: String,
+}
+
+impl PageCursor {
+    fn key(&self) -> SortKey<'_> {
+        SortKey {
+            pinned: self.pinned,
+            text: &self.text,
+            time: self.time,
+            id: &self.id,
+        }
+    }
+
+    /// Encodes the cursor as hex digits of its JSON form, so it can be passed
+    /// around as a single shell word
+    fn encode(&self) -> Result<String> {
+        let json = serde_json::to_vec(self)?;
+        Ok(json.iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
+
+    fn decode(cursor: &str, sort: &NoteSort) -> Result<Self> {
+        let invalid = || KbError::InvalidArgument {
+            message: format!("Invalid page cursor '{}'", cursor),
+        };
+        if !cursor.len().is_multiple_of(2) || !cursor.is_ascii() {
+            return Err(invalid());
+        }
+        let bytes = (0..cursor.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16))
+            .collect::<std::result::Result<Vec<u8>, _>>()
+            .map_err(|_| invalid())?;
+        let decoded: PageCursor = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+        if decoded.sort != *sort {
+            return Err(KbError::InvalidArgument {
+                message: "Page cursor belongs to a listing with a different sort order".to_string(),
+            });
+        }
+        Ok(decoded)
+    }
+}
+
+/// One page of a listing
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NotePage {
+    /// The notes of the page, in the order of the listing
+    pub notes: Vec<Note>,
+    /// Cursor for the page after this one, `None` on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// Picks one page out of a set of notes
+///
+/// # Arguments
+///
+/// * `notes` - Every note of the listing, in any order
+/// * `sort` - Order of the listing
+/// * `cursor` - Cursor of a previous page of the same listing, or `None` for
+///   the first page
+/// * `page_size` - Maximum number of notes in the page
+///
+/// # Returns
+///
+/// Clones of the notes that follow the cursor, or `KbError::InvalidArgument`
+/// when the page size is zero or the cursor is malformed or belongs to a
+/// different sort order
+pub fn paginate_notes<'a>(
+    notes: impl IntoIterator<Item = &'a Note>,
+    sort: &NoteSort,
+    cursor: Option<&str>,
+    page_size: usize,
+) -> Result<NotePage> {
+    if page_size == 0 {
+        return Err(KbError::InvalidArgument {
+            message: "Page size must be at least 1".to_string(),
+        });
+    }
+    let after = cursor
+        .map(|cursor| PageCursor::decode(cursor, sort))
+        .transpose()?;
+
+    let mut remaining: Vec<&Note> = notes
+        .into_iter()
+        .filter(|note| {
+            after.as_ref().is_none_or(|after| {
+                sort.compare_keys(&SortKey::of(sort, note), &after.key()) == Ordering::Greater
+            })
+        })
+        .collect();
+    let has_more = remaining.len() > page_size;
+    if has_more {
+        remaining.select_nth_unstable_by(page_size, |a, b| sort.compare(a, b));
+        remaining.truncate(page_size);
+    }
+    remaining.sort_unstable_by(|a, b| sort.compare(a, b));
+
+    let next_cursor = match remaining.last() {
+        Some(last) if has_more => {
+            let key = SortKey::of(sort, last);
+            Some(
+                PageCursor {
+                    sort: *sort,
+                    pinned: key.pinned,
+                    text: key.text.to_string(),
+                    time: key.time,
+                    id: key.id.to_string(),
+                }
+                .encode()?,
+            )
+        }
+        _ => None,
+    };
+
+    Ok(NotePage {
+        notes: remaining.into_iter().cloned().collect(),
+        next_cursor,
+    })
+}
+
+/// Narrows a search beyond its query and picks the page of results to
+/// return; the default returns every unarchived match
+#[derive(Debug, Clone, Default)]
+pub struct SearchRequest {
+    /// Fuzzy or literal matching of the query
+    pub mode: SearchMode,
+    /// Fields of the notes the query is matched against
+    pub scope: SearchScope,
+    /// Include archived notes
+    pub include_archived: bool,
+    /// Tag and date conditions the notes must meet
+    pub filter: NoteFilter,
+    /// Structural predicates the notes must all satisfy (`--has`)
+    pub structure: Vec<StructureFilter>,
+    /// Leave out notes whose tag policy hides them from search, counting
+    /// them in [`SearchResults::hidden`]
+    pub hide_excluded_by_policy: bool,
+    /// Number of best matches to skip
+    pub offset: usize,
+    /// Maximum number of notes to return, `None` for all of them
+    pub limit: Option<usize>,
+}
+
+/// A note matching a search, with how well it matched
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub note: Note,
+    /// Relevance of the note; higher is better, and only comparable between
+    /// results of the same query
+    pub score: i64,
+    /// Fields of the note the query matched
+    pub matched_fields: MatchedFields,
+}
+
+/// One page of search results
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchResults {
+    /// Number of matching notes before paging
+    pub total: usize,
+    /// Matching notes left out because their tag policy hides them
+    pub hidden: usize,
+    /// The matches of the requested page, best first
+    pub hits: Vec<SearchHit>,
+}
+
+impl SearchResults {
+    /// The notes of the page, best match first, without their scores
+    pub fn into_notes(self) -> Vec<Note> {
+        self.hits.into_iter().map(|hit| hit.note).collect()
+    }
+}
+
+/// Which end of a date range a value is parsed for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateBound {
+    /// A bare date means the start of that day
+    Start,
+    /// A bare date means the end of that day, so the whole day is included
+    End,
+}
+
+/// Parses a date bound such as `--since` or `--created-after`
+///
+/// # Arguments
+///
+/// * `value` - An RFC 3339 timestamp or a `YYYY-MM-DD` date (in local time)
+/// * `bound` - Whether a bare date stands for the start or the end of the day
+///
+/// # Returns
+///
+/// The point in time, or `KbError::InvalidFormat` naming the expected formats
+pub fn parse_date_bound(value: &str, bound: DateBound) -> Result<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    let invalid = || KbError::InvalidFormat {
+        message: format!(
+            "Invalid date '{}': expected YYYY-MM-DD or RFC 3339 (e.g. 2024-05-01T09:00:00Z)",
+            value
+        ),
+    };
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| invalid())?;
+    let day = match bound {
+        DateBound::Start => date,
+        // The end of a day is the instant before the next one starts
+        DateBound::End => date.checked_add_days(Days::new(1)).ok_or_else(invalid)?,
+    };
+    // The earliest instant, for days starting inside a DST gap
+    let time = Local
+        .from_local_datetime(&day.and_time(NaiveTime::MIN))
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(invalid)?;
+    Ok(match bound {
+        DateBound::Start => time,
+        DateBound::End => time - chrono::Duration::nanoseconds(1),
+    })
+}
@@ -0,0 +1,262 @@
+//! Redaction rules for sharing sanitized notes.
+//!
+//! Rules are declared in the `redaction` section of the configuration and
+//! compiled once into a [`Redactor`], which rewrites note titles, content and
+//! tags before they leave the vault (e.g. during export).
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{KbError, Note, Result};
+
+/// A single named redaction rule as it appears in the configuration.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RedactionRule {
+    /// Human readable name of the rule (e.g. "aws-access-key")
+    pub name: String,
+
+    /// Regular expression matching the text to redact
+    pub pattern: String,
+
+    /// Replacement text for every match
+    #[serde(default = "default_replacement")]
+    pub replacement: String,
+
+    /// Marks a "must never leak" guard: the pattern is not replaced but checked
+    /// after all other rules ran, and any match blocks the export in strict mode
+    #[serde(default)]
+    pub never_leak: bool,
+}
+
+fn default_replacement() -> String {
+    "[REDACTED]".to_string()
+}
+
+/// The `redaction` configuration section.
+///
+/// Example (JSON config):
+///
+/// ```json
+/// "redaction": {
+///   "rules": [
+///     { "name": "aws-key", "pattern": "AKIA[0-9A-Z]{16}" },
+///     { "name": "email", "pattern": "[\\w.+-]+@[\\w-]+\\.[\\w.]+", "replacement": "<email>" },
+///     { "name": "secret-line", "pattern": "(?m)^.*#secret.*$" },
+///     { "name": "no-aws-keys", "pattern": "AKIA[0-9A-Z]{16}", "never_leak": true }
+///   ]
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RedactionConfig {
+    /// Rules applied in declaration order
+    #[serde(default)]
+    pub rules: Vec<RedactionRule>,
+}
+
+/// How strictly redaction is enforced during an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedactMode {
+    /// Apply the rules and export whatever remains
+    Standard,
+    /// Apply the rules and refuse to export notes still matching a `never_leak` rule
+    Strict,
+}
+
+impl std::str::FromStr for RedactMode {
+    type Err = KbError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "standard" => Ok(RedactMode::Standard),
+            "strict" => Ok(RedactMode::Strict),
+            other => Err(KbError::InvalidFormat {
+                message: format!("Unknown redaction mode '{}': use standard or strict", other),
+            }),
+        }
+    }
+}
+
+/// A rule whose pattern has been compiled.
+#[derive(Debug, Clone)]
+struct CompiledRule {
+    name: String,
+    regex: Regex,
+    replacement: String,
+    never_leak: bool,
+}
+
+/// Result of redacting a single note.
+#[derive(Debug, Clone)]
+pub struct RedactedNote {
+    /// The note with all rules applied
+    pub note: Note,
+    /// Total number of replacements made across all redacted fields
+    pub redactions: usize,
+    /// Names of `never_leak` rules that still match after redaction
+    pub leaks: Vec<String>,
+}
+
+/// Number of replacements made in one note.
+#[derive(Debug, Clone, Serialize)]
+pub struct NoteRedactions {
+    /// ID of the note
+    pub id: String,
+    /// Title of the note before redaction
+    pub title: String,
+    /// Replacements made across all redacted fields
+    pub redactions: usize,
+}
+
+/// A note refused by a strict redaction because it still leaks.
+#[derive(Debug, Clone, Serialize)]
+pub struct WithheldNote {
+    /// ID of the note
+    pub id: String,
+    /// Names of the `never_leak` rules still matching
+    pub leaks: Vec<String>,
+}
+
+/// Result of redacting every note of an export.
+#[derive(Debug, Clone, Default)]
+pub struct RedactionReport {
+    /// The redacted notes that may be exported, in input order
+    pub notes: Vec<Note>,
+    /// Replacement counts for every input note, in input order
+    pub counts: Vec<NoteRedactions>,
+    /// Notes refused in strict mode
+    pub withheld: Vec<WithheldNote>,
+}
+
+impl RedactionReport {
+    /// Total number of replacements across all notes
+    pub fn total(&self) -> usize {
+        self.counts.iter().map(|count| count.redactions).sum()
+    }
+}
+
+/// Compiled set of redaction rules.
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    rules: Vec<CompiledRule>,
+}
+
+impl Redactor {
+    /// Compiles every rule in the configuration, failing on the first invalid pattern
+    pub fn compile(config: &RedactionConfig) -> Result<Self> {
+        let mut rules = Vec::with_capacity(config.rules.len());
+
+        for rule in &config.rules {
+            let regex = Regex::new(&rule.pattern).map_err(|e| KbError::ConfigError {
+                message: format!(
+                    "Invalid redaction pattern '{}' in rule '{}': {}",
+                    rule.pattern, rule.name, e
+                ),
+            })?;
+
+            rules.push(CompiledRule {
+                name: rule.name.clone(),
+                regex,
+                replacement: rule.replacement.clone(),
+                never_leak: rule.never_leak,
+            });
+        }
+
+        Ok(Self { rules })
+    }
+
+    /// Returns true when no rules are configured
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Applies every replacing rule to the note's title, content, tags, aliases and
+    /// metadata values
+    pub fn redact_note(&self, note: &Note) -> RedactedNote {
+        let mut redacted = note.clone();
+        let mut redactions = 0;
+
+        redacted.title = self.redact_text(&note.title, &mut redactions);
+        redacted.content = self.redact_text(&note.content, &mut redactions);
+        redacted.tags = note
+            .tags
+            .iter()
+            .map(|tag| self.redact_text(tag, &mut redactions))
+            .collect();
+        redacted.aliases = note
+            .aliases
+            .iter()
+            .map(|alias| self.redact_text(alias, &mut redactions))
+            .collect();
+        redacted.metadata = note
+            .metadata
+            .iter()
+            .map(|(key, value)| (key.clone(), self.redact_text(value, &mut redactions)))
+            .collect();
+
+        let leaks = self.find_leaks(&redacted);
+
+        RedactedNote {
+            note: redacted,
+            redactions,
+            leaks,
+        }
+    }
+
+    /// Redacts every note, withholding the ones that still leak in strict mode
+    pub fn redact_notes(&self, notes: &[Note], mode: RedactMode) -> RedactionReport {
+        let mut report = RedactionReport::default();
+
+        for note in notes {
+            let redacted = self.redact_note(note);
+            report.counts.push(NoteRedactions {
+                id: note.id.clone(),
+                title: note.title.clone(),
+                redactions: redacted.redactions,
+            });
+            if mode == RedactMode::Strict && !redacted.leaks.is_empty() {
+                report.withheld.push(WithheldNote {
+                    id: note.id.clone(),
+                    leaks: redacted.leaks,
+                });
+            } else {
+                report.notes.push(redacted.note);
+            }
+        }
+
+        report
+    }
+
+    /// Applies every replacing rule to a piece of text, counting the replacements made
+    fn redact_text(&self, text: &str, count: &mut usize) -> String {
+        let mut output = text.to_string();
+        for rule in self.rules.iter().filter(|rule| !rule.never_leak) {
+            let matches = rule.regex.find_iter(&output).count();
+            if matches > 0 {
+                *count += matches;
+                output = rule
+                    .regex
+                    .replace_all(&output, rule.replacement.as_str())
+                    .into_owned();
+            }
+        }
+        output
+    }
+
+    /// Names of `never_leak` rules that still match anywhere in the note
+    fn find_leaks(&self, note: &Note) -> Vec<String> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.never_leak)
+            .filter(|rule| {
+                rule.regex.is_match(&note.title)
+                    || rule.regex.is_match(&note.content)
+                    || note.tags.iter().any(|tag| rule.regex.is_match(tag))
+                    || note.aliases.iter().any(|alias| rule.regex.is_match(alias))
+                    || note
+                        .metadata
+                        .values()
+                        .any(|value| rule.regex.is_match(value))
+            })
+            .map(|rule| rule.name.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,248 @@
+//! Background rebuilds of the search index.
+//!
+//! `kbnotes reindex` rebuilds the index in small batches and yields between
+//! them, so it doesn't hold the notes cache or the index for long. While a
+//! foreground command runs, signaled through [`ForegroundActivity`], it
+//! pauses altogether. Notes not indexed yet are still found: searches scan
+//! them as they would without an index.
+//!
+//! Other commands usually run in other processes than the rebuild, so both
+//! directions also go through files next to the index: foreground commands
+//! hold a shared lock on [`FOREGROUND_LOCK_FILE`], and a rebuild holds
+//! [`REINDEX_LOCK_FILE`] while it writes its progress to
+//! [`REINDEX_PROGRESS_FILE`] for `kbnotes status`.
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions, TryLockError},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::Result;
+
+/// Number of notes a background reindex indexes before it yields
+pub const REINDEX_BATCH_SIZE: usize = 100;
+
+/// How long a paused background reindex waits before it checks again
+/// whether the foreground is idle
+pub const REINDEX_PAUSE: Duration = Duration::from_millis(50);
+
+/// File foreground commands hold a shared lock on while they run
+pub const FOREGROUND_LOCK_FILE: &str = "foreground.lock";
+
+/// File a running rebuild holds an exclusive lock on
+pub const REINDEX_LOCK_FILE: &str = "reindex.lock";
+
+/// File a running rebuild writes its [`ReindexProgress`] to
+pub const REINDEX_PROGRESS_FILE: &str = "reindex.json";
+
+/// Counts the foreground commands running, which background reindexing
+/// waits for; clones share the count
+#[derive(Debug, Clone, Default)]
+pub struct ForegroundActivity {
+    active: Arc<AtomicUsize>,
+    /// Directory of the lock file shared with other processes, if any
+    dir: Option<PathBuf>,
+}
+
+impl ForegroundActivity {
+    /// Tracks the foreground commands of this process and, through
+    /// [`FOREGROUND_LOCK_FILE`] in `dir`, those of other processes
+    pub fn in_dir(dir: PathBuf) -> Self {
+        ForegroundActivity {
+            active: Arc::default(),
+            dir: Some(dir),
+        }
+    }
+
+    /// Marks a foreground command as running until the guard is dropped
+    pub fn enter(&self) -> ForegroundGuard {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        // The directory only exists once something was indexed, and
+        // commands shouldn't create it in vaults that never were
+        let lock = self
+            .dir
+            .as_deref()
+            .filter(|dir| dir.is_dir())
+            .and_then(lock_foreground);
+        ForegroundGuard {
+            active: Arc::clone(&self.active),
+            _lock: lock,
+        }
+    }
+
+    /// Whether any foreground command is running, in this process or in
+    /// another one
+    pub fn is_active(&self) -> bool {
+        if self.active.load(Ordering::SeqCst) > 0 {
+            return true;
+        }
+        let Some(dir) = &self.dir else {
+            return false;
+        };
+        match open_lock_file(dir, FOREGROUND_LOCK_FILE) {
+            // A shared lock held anywhere keeps the exclusive one from being
+            // taken; it is released again when the file is dropped
+            Ok(file) => matches!(file.try_lock(), Err(TryLockError::WouldBlock)),
+            Err(e) => {
+                warn!("Failed to open {}: {}", FOREGROUND_LOCK_FILE, e);
+                false
+            }
+        }
+    }
+}
+
+/// Keeps a foreground command marked as running, see
+/// [`ForegroundActivity::enter`]
+#[derive(Debug)]
+#[must_use = "the command only counts as running while the guard is kept"]
+pub struct ForegroundGuard {
+    active: Arc<AtomicUsize>,
+    /// Shared lock for other processes, released when dropped
+    _lock: Option<File>,
+}
+
+impl Drop for ForegroundGuard {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// How far a background reindex got, as reported by `kbnotes status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReindexProgress {
+    /// Notes indexed so far
+    pub indexed: usize,
+    /// Notes the reindex started with
+    pub total: usize,
+    /// Whether it is waiting for a foreground command to finish
+    pub paused: bool,
+}
+
+impl ReindexProgress {
+    /// Reads the progress a rebuild in another process reports in `dir`
+    ///
+    /// # Returns
+    ///
+    /// `None` when no rebuild is running, including when one stopped
+    /// without removing its progress file
+    pub fn read(dir: &Path) -> Option<ReindexProgress> {
+        let lock = File::open(dir.join(REINDEX_LOCK_FILE)).ok()?;
+        // The lock is free unless a rebuild holds it
+        if lock.try_lock_shared().is_ok() {
+            return None;
+        }
+        let text = fs::read_to_string(dir.join(REINDEX_PROGRESS_FILE)).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+}
+
+impl fmt::Display for ReindexProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "indexing {}/{}",
+            group_digits(self.indexed),
+            group_digits(self.total)
+        )?;
+        if self.paused {
+            write!(f, " (paused)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Marks a rebuild as running for other processes until dropped, see
+/// [`ReindexLock::acquire`]
+#[derive(Debug)]
+pub struct ReindexLock {
+    dir: PathBuf,
+    /// Exclusive lock, released when dropped
+    _file: File,
+}
+
+impl ReindexLock {
+    /// Takes the lock of rebuilds writing their progress to `dir`
+    ///
+    /// # Returns
+    ///
+    /// `None` when another rebuild holds it
+    pub fn acquire(dir: &Path) -> Result<Option<ReindexLock>> {
+        let file = open_lock_file(dir, REINDEX_LOCK_FILE)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(ReindexLock {
+                dir: dir.to_path_buf(),
+                _file: file,
+            })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+
+    /// Writes the rebuild's progress for [`ReindexProgress::read`]
+    pub fn report(&self, progress: &ReindexProgress) -> Result<()> {
+        let temp_file = NamedTempFile::new_in(&self.dir)?;
+        serde_json::to_writer(&temp_file, progress)?;
+        temp_file
+            .persist(self.dir.join(REINDEX_PROGRESS_FILE))
+            .map_err(|e| e.error)?;
+        Ok(())
+    }
+}
+
+impl Drop for ReindexLock {
+    fn drop(&mut self) {
+        // Runs before the lock is released with the file
+        if let Err(e) = fs::remove_file(self.dir.join(REINDEX_PROGRESS_FILE)) {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("Failed to remove {}: {}", REINDEX_PROGRESS_FILE, e);
+            }
+        }
+    }
+}
+
+/// Takes a shared lock on [`FOREGROUND_LOCK_FILE`] in `dir`, held until the
+/// returned file is dropped
+fn lock_foreground(dir: &Path) -> Option<File> {
+    let lock = open_lock_file(dir, FOREGROUND_LOCK_FILE)
+        .and_then(|file| file.lock_shared().map(|()| file));
+    match lock {
+        Ok(file) => Some(file),
+        Err(e) => {
+            warn!("Failed to lock {}: {}", FOREGROUND_LOCK_FILE, e);
+            None
+        }
+    }
+}
+
+/// Opens (creating it and its directory if needed) a file to lock
+fn open_lock_file(dir: &Path, name: &str) -> io::Result<File> {
+    fs::create_dir_all(dir)?;
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(name))
+}
+
+/// Writes a count with thousands separators, e.g. `4,200`
+fn group_digits(count: usize) -> String {
+    let digits = count.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
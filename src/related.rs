@@ -0,0 +1,122 @@
+//! Related-note ranking for link suggestions.
+//!
+//! Notes are compared with a lightweight TF-IDF cosine similarity computed over
+//! a bounded candidate set, so suggestions stay fast on large vaults.
+use std::collections::{HashMap, HashSet};
+
+use crate::Note;
+
+/// Minimum similarity a candidate must reach to be suggested
+pub const RELATED_SCORE_THRESHOLD: f64 = 0.1;
+
+/// Maximum number of candidate notes evaluated per suggestion request
+pub const MAX_RELATED_CANDIDATES: usize = 500;
+
+/// Common words that carry no meaning for similarity
+const STOP_WORDS: &[&str] = &[
+    "the", "and", "for", "are", "but", "not", "you", "all", "any", "can", "has", "have", "was",
+    "were", "this", "that", "with", "from", "they", "will", "would", "there", "their", "what",
+    "about", "which", "when", "into", "than", "then", "them", "these", "those", "its", "our",
+];
+
+/// A note suggested as related to another one
+#[derive(Debug, Clone)]
+pub struct RelatedNote {
+    /// ID of the related note
+    pub id: String,
+    /// Title of the related note
+    pub title: String,
+    /// Cosine similarity in the range 0.0..=1.0
+    pub score: f64,
+}
+
+/// Splits text into lowercase terms, dropping short words and stop words
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= 3)
+        .map(|word| word.to_lowercase())
+        .filter(|word| !STOP_WORDS.contains(&word.as_str()))
+        .collect()
+}
+
+/// Counts term occurrences in a note's title and content
+fn term_frequencies(note: &Note) -> HashMap<String, f64> {
+    let mut frequencies = HashMap::new();
+    for term in tokenize(&note.title)
+        .into_iter()
+        .chain(tokenize(&note.content))
+    {
+        *frequencies.entry(term).or_insert(0.0) += 1.0;
+    }
+    frequencies
+}
+
+/// Ranks candidates by TF-IDF cosine similarity to the target note
+///
+/// Returns at most `limit` notes scoring above [`RELATED_SCORE_THRESHOLD`],
+/// highest score first.
+pub fn rank_related(target: &Note, candidates: &[&Note], limit: usize) -> Vec<RelatedNote> {
+    let target_tf = term_frequencies(target);
+    if target_tf.is_empty() || candidates.is_empty() {
+        return Vec::new();
+    }
+
+    let candidate_tfs: Vec<HashMap<String, f64>> =
+        candidates.iter().map(|note| term_frequencies(note)).collect();
+
+    // Document frequency over the candidate set plus the target itself
+    let mut document_frequency: HashMap<&str, f64> = HashMap::new();
+    for tf in candidate_tfs.iter().chain(std::iter::once(&target_tf)) {
+        let unique: HashSet<&str> = tf.keys().map(String::as_str).collect();
+        for term in unique {
+            *document_frequency.entry(term).or_insert(0.0) += 1.0;
+        }
+    }
+
+    let documents = (candidates.len() + 1) as f64;
+    let idf = |term: &str| -> f64 {
+        let df = document_frequency.get(term).copied().unwrap_or(1.0);
+        (documents / df).ln() + 1.0
+    };
+
+    let weigh = |tf: &HashMap<String, f64>| -> HashMap<String, f64> {
+        tf.iter()
+            .map(|(term, count)| (term.clone(), count * idf(term)))
+            .collect()
+    };
+
+    let norm = |vector: &HashMap<String, f64>| -> f64 {
+        vector.values().map(|w| w * w).sum::<f64>().sqrt()
+    };
+
+    let target_vector = weigh(&target_tf);
+    let target_norm = norm(&target_vector);
+
+    let mut related: Vec<RelatedNote> = candidates
+        .iter()
+        .zip(candidate_tfs.iter())
+        .filter_map(|(note, tf)| {
+            let vector = weigh(tf);
+            let candidate_norm = norm(&vector);
+            if candidate_norm == 0.0 || target_norm == 0.0 {
+                return None;
+            }
+
+            let dot: f64 = target_vector
+                .iter()
+                .filter_map(|(term, weight)| vector.get(term).map(|w| w * weight))
+                .sum();
+            let score = dot / (target_norm * candidate_norm);
+
+            (score >= RELATED_SCORE_THRESHOLD).then(|| RelatedNote {
+                id: note.id.clone(),
+                title: note.title.clone(),
+                score,
+            })
+        })
+        .collect();
+
+    related.sort_by(|a, b| b.score.total_cmp(&a.score));
+    related.truncate(limit);
+    related
+}
@@ -0,0 +1,135 @@
+//! Age-based rotation of full backups, the `backup_retention` setting.
+//!
+//! A [`BackupRetention`] either keeps the backups of the last `max_age_days`
+//! days, or keeps backups in tiers: the newest backup of each of the last
+//! `daily` days, `weekly` ISO weeks and `monthly` months that have one, as
+//! `borg prune` does. Days, weeks and months are counted in UTC. The newest
+//! backup is always kept.
+use std::collections::HashSet;
+
+use chrono::{DateTime, Datelike, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{KbError, Result};
+
+/// Identifies the day, ISO week or month a backup was taken in
+type Period = fn(DateTime<Utc>) -> (i32, u32);
+
+/// Which full backups to keep, by the time they were taken
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BackupRetention {
+    /// Keep every backup taken in the last this many days
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_days: Option<u32>,
+
+    /// Keep the newest backup of each of the last this many days with a backup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily: Option<u32>,
+
+    /// Keep the newest backup of each of the last this many ISO weeks with a backup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weekly: Option<u32>,
+
+    /// Keep the newest backup of each of the last this many months with a backup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly: Option<u32>,
+}
+
+impl BackupRetention {
+    /// Parses a policy written as comma-separated `name:count` pairs, e.g.
+    /// `max_age_days:30` or `daily:14,weekly:13`
+    pub fn parse(value: &str) -> Result<Self> {
+        let mut retention = Self::default();
+        for part in value.split(',').map(str::trim) {
+            let invalid = |reason: &str| KbError::ConfigError {
+                message: format!("Invalid backup_retention '{}': {}", value, reason),
+            };
+            let (name, count) = part
+                .split_once(':')
+                .ok_or_else(|| invalid(&format!("expected name:count, found '{}'", part)))?;
+            let count: u32 = count
+                .trim()
+                .parse()
+                .map_err(|_| invalid(&format!("'{}' is not a number", count.trim())))?;
+            let slot = match name.trim() {
+                "max_age_days" => &mut retention.max_age_days,
+                "daily" => &mut retention.daily,
+                "weekly" => &mut retention.weekly,
+                "monthly" => &mut retention.monthly,
+                other => {
+                    return Err(invalid(&format!(
+                        "unknown '{}' (max_age_days, daily, weekly or monthly)",
+                        other
+                    )))
+                }
+            };
+            *slot = Some(count);
+        }
+        retention.validate()?;
+        Ok(retention)
+    }
+
+    /// Checks that the policy is either an age or a set of tiers, keeping
+    /// something
+    pub fn validate(&self) -> Result<()> {
+        let counts = [self.daily, self.weekly, self.monthly];
+        let tiered = counts.iter().any(Option::is_some);
+        let reason = match self.max_age_days {
+            Some(_) if tiered => "max_age_days can't be combined with daily, weekly or monthly",
+            Some(0) => "max_age_days cannot be zero",
+            None if !tiered => "set max_age_days, or daily, weekly or monthly counts",
+            None if counts.iter().all(|count| count.unwrap_or(0) == 0) => {
+                "at least one of daily, weekly or monthly must be above zero"
+            }
+            _ => return Ok(()),
+        };
+        Err(KbError::ConfigError {
+            message: format!("Invalid backup_retention: {}", reason),
+        })
+    }
+
+    /// Picks the backups the policy lets go
+    ///
+    /// # Arguments
+    ///
+    /// * `backups` - When each backup was taken, in any order
+    /// * `now` - The time ages are measured from
+    ///
+    /// # Returns
+    ///
+    /// The indices into `backups` of the backups to remove, in ascending order
+    pub fn expired(&self, backups: &[DateTime<Utc>], now: DateTime<Utc>) -> Vec<usize> {
+        let mut newest_first: Vec<usize> = (0..backups.len()).collect();
+        newest_first.sort_by_key(|&i| std::cmp::Reverse(backups[i]));
+
+        let mut kept: HashSet<usize> = newest_first.first().copied().into_iter().collect();
+        if let Some(days) = self.max_age_days {
+            let cutoff = now - Duration::days(i64::from(days));
+            kept.extend(newest_first.iter().filter(|&&i| backups[i] >= cutoff));
+        }
+        let tiers: [(Option<u32>, Period); 3] = [
+            (self.daily, |time| (time.year(), time.ordinal())),
+            (self.weekly, |time| {
+                let week = time.iso_week();
+                (week.year(), week.week())
+            }),
+            (self.monthly, |time| (time.year(), time.month())),
+        ];
+        for (count, period) in tiers {
+            let mut periods = Vec::new();
+            for &i in &newest_first {
+                if periods.len() == count.unwrap_or(0) as usize {
+                    break;
+                }
+                // Newest first, so the first backup of a period is its newest
+                let key = period(backups[i]);
+                if periods.last() != Some(&key) {
+                    periods.push(key);
+                    kept.insert(i);
+                }
+            }
+        }
+
+        (0..backups.len()).filter(|i| !kept.contains(i)).collect()
+    }
+}
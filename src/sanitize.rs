@@ -0,0 +1,171 @@
+//! Policies turning note IDs into file and ZIP entry names.
+//!
+//! Every policy percent-encodes the characters it does not allow (plus `%`
+//! itself and a leading `.`), which gives two guarantees the storage layer
+//! relies on:
+//!
+//! * **Stability** – the same ID always maps to the same name. The set of
+//!   allowed characters of an existing policy must never change; a different
+//!   mapping needs a new policy.
+//! * **Reversibility** – [`SanitizationPolicy::restore`] recovers the original
+//!   ID, so two distinct IDs never share a name. The only remaining source of
+//!   collisions is a case-insensitive filesystem, which storage checks for
+//!   before creating a note.
+//!
+//! The one exception are IDs whose encoding is longer than
+//! [`MAX_NAME_BYTES`]: their names are cut short and end in `%h` and a hash of
+//! the whole ID, so they fit in a file name together with the suffixes of
+//! backup files. Such names are still stable but cannot be restored; storage
+//! maps them back through the notes it knows.
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::stable_hash;
+
+/// Longest name [`SanitizationPolicy::sanitize`] returns, leaving room below
+/// the usual 255 byte file name limit for `.json` and backup suffixes
+pub const MAX_NAME_BYTES: usize = 200;
+
+/// Marks a shortened name; `%` is always escaped, so no full name contains it
+const HASHED_NAME_MARKER: &str = "%h";
+
+/// Maps note IDs to names that are safe to use as file names and ZIP entries
+pub trait SanitizationPolicy: Send + Sync {
+    /// Name of the policy as used in the configuration
+    fn name(&self) -> &'static str;
+
+    /// Returns true when `c` may appear unescaped in a name
+    fn allows(&self, c: char) -> bool;
+
+    /// Converts an ID into a file or ZIP entry name
+    fn sanitize(&self, id: &str) -> String {
+        // Room left for the part of a long name kept before its hash
+        let budget = MAX_NAME_BYTES - HASHED_NAME_MARKER.len() - 16;
+        let mut name = String::with_capacity(id.len());
+        let mut cut = 0;
+        for (i, c) in id.chars().enumerate() {
+            if name.len() <= budget {
+                cut = name.len();
+            }
+            // A leading dot would hide the file or mark it as internal
+            if c != '%' && self.allows(c) && !(i == 0 && c == '.') {
+                name.push(c);
+            } else {
+                let mut buffer = [0; 4];
+                for byte in c.encode_utf8(&mut buffer).bytes() {
+                    name.push_str(&format!("%{:02X}", byte));
+                }
+            }
+        }
+
+        if name.len() > MAX_NAME_BYTES {
+            name.truncate(cut);
+            name.push_str(HASHED_NAME_MARKER);
+            name.push_str(&stable_hash(id.as_bytes()));
+        }
+        name
+    }
+
+    /// Recovers the ID a name was produced from, or `None` when the name is
+    /// not a valid encoding or was shortened
+    fn restore(&self, name: &str) -> Option<String> {
+        let mut bytes = Vec::with_capacity(name.len());
+        let mut rest = name.as_bytes();
+        while let Some((&byte, tail)) = rest.split_first() {
+            if byte == b'%' {
+                let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+                if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return None;
+                }
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &tail[2..];
+            } else {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+        String::from_utf8(bytes).ok()
+    }
+}
+
+/// Keeps letters and digits of any script plus `-`, `_` and `.`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StandardPolicy;
+
+impl SanitizationPolicy for StandardPolicy {
+    fn name(&self) -> &'static str {
+        "standard"
+    }
+
+    fn allows(&self, c: char) -> bool {
+        c.is_alphanumeric() || matches!(c, '-' | '_' | '.')
+    }
+}
+
+/// Keeps only ASCII letters, digits, `-`, `_` and `.`, for filesystems and
+/// archive tools that mishandle anything else
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StrictAsciiPolicy;
+
+impl SanitizationPolicy for StrictAsciiPolicy {
+    fn name(&self) -> &'static str {
+        "strict_ascii"
+    }
+
+    fn allows(&self, c: char) -> bool {
+        c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')
+    }
+}
+
+/// Only escapes path separators and control characters, keeping the names
+/// used before sanitization was introduced
+///
+/// On Windows it also escapes the characters Windows does not allow in file
+/// names (`<>:"|?*`). Files with such names could never be created there, so
+/// this changes no existing layout.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PermissivePolicy;
+
+impl SanitizationPolicy for PermissivePolicy {
+    fn name(&self) -> &'static str {
+        "permissive"
+    }
+
+    fn allows(&self, c: char) -> bool {
+        if cfg!(windows) && matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*') {
+            return false;
+        }
+        !c.is_control() && !matches!(c, '/' | '\\')
+    }
+}
+
+/// The `id_policy` configuration setting
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IdPolicy {
+    /// See [`StandardPolicy`]
+    Standard,
+    /// See [`StrictAsciiPolicy`]
+    StrictAscii,
+    /// See [`PermissivePolicy`] (the default, so existing vaults keep their layout)
+    #[default]
+    Permissive,
+}
+
+impl IdPolicy {
+    /// Returns the policy implementation for this setting
+    pub fn policy(&self) -> Arc<dyn SanitizationPolicy> {
+        match self {
+            IdPolicy::Standard => Arc::new(StandardPolicy),
+            IdPolicy::StrictAscii => Arc::new(StrictAsciiPolicy),
+            IdPolicy::Permissive => Arc::new(PermissivePolicy),
+        }
+    }
+}
+
+impl std::fmt::Display for IdPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.policy().name())
+    }
+}
@@ -0,0 +1,85 @@
+//! Cron expressions for the `backup_schedule` setting.
+//!
+//! A [`CronSchedule`] wraps a schedule of the `cron` crate. That crate wants
+//! a leading seconds field, so the usual five fields (minute, hour, day of
+//! month, month, day of week) are read as if `0` seconds came first; six or
+//! seven fields (with a trailing year) and shorthands such as `@daily` are
+//! passed on as they are. Fields accept `*`, values, ranges (`9-17`), steps
+//! (`*/15`), lists (`1,15`), and month and weekday names (`JAN`, `MON-FRI`).
+//!
+//! Numbers and day matching follow the `cron` crate rather than Vixie cron:
+//! days of the week count from 1 = Sunday to 7 = Saturday, so names are the
+//! clearer choice, and when both the day of month and the day of week are
+//! restricted, a day has to match both.
+//!
+//! Occurrences are computed in UTC, so daylight saving time changes never
+//! skip or repeat a run.
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+
+use crate::{KbError, Result};
+
+/// A parsed cron expression
+#[derive(Debug, Clone)]
+pub struct CronSchedule {
+    expression: String,
+    schedule: Schedule,
+}
+
+impl CronSchedule {
+    /// Parses a cron expression of five fields, six with seconds first, or
+    /// seven with a year last
+    pub fn parse(expression: &str) -> Result<Self> {
+        let expression = expression.trim();
+        let fields = expression.split_whitespace().count();
+        let full = match fields {
+            _ if expression.starts_with('@') => expression.to_string(),
+            5 => format!("0 {}", expression),
+            6 | 7 => expression.to_string(),
+            count => {
+                return Err(invalid(
+                    expression,
+                    &format!("expected 5, 6 or 7 fields, found {}", count),
+                ))
+            }
+        };
+        let schedule = Schedule::from_str(&full).map_err(|e| {
+            // The crate quotes the expression with a marker under the error
+            // before its message, which is all that is kept
+            let message = e.to_string();
+            invalid(expression, message.lines().last().unwrap_or_default())
+        })?;
+        Ok(Self {
+            expression: expression.to_string(),
+            schedule,
+        })
+    }
+
+    /// The first occurrence strictly after `after`, or `None` if there is
+    /// none (e.g. `0 0 31 2 *`)
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.schedule.after(&after).next()
+    }
+}
+
+impl PartialEq for CronSchedule {
+    fn eq(&self, other: &Self) -> bool {
+        self.expression == other.expression
+    }
+}
+
+impl Eq for CronSchedule {}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+fn invalid(expression: &str, reason: &str) -> KbError {
+    KbError::ConfigError {
+        message: format!("Invalid cron expression '{}': {}", expression, reason),
+    }
+}
@@ -0,0 +1,349 @@
+//! Persistent full-text search index.
+//!
+//! With `use_search_index` enabled, the words of every note's title, content
+//! and tags are kept in an inverted index written to
+//! `<notes_dir>/.index/search.json`, so a search only scores the notes that
+//! can match instead of fuzzy-scanning all of them. The index narrows the
+//! candidates by word prefix: each word of a query must start a word of the
+//! note (`dock` finds `docker`, `dkr` no longer does). Quoted phrases and
+//! `NOT` are still checked against the notes themselves.
+//!
+//! The file carries a format version and a checksum of its entries. A file
+//! that can't be read back is treated as corrupt and rebuilt from the notes;
+//! entries for notes that changed while kbnotes wasn't watching are
+//! refreshed when notes are loaded.
+//!
+//! A background rebuild (see
+//! [`NoteStorage::start_reindex`](crate::NoteStorage::start_reindex)) starts
+//! from an index whose notes are all pending: until a note is indexed, every
+//! search takes it as a candidate, so it is scanned like without an index.
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs,
+    ops::Bound,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::{fold_text, stable_hash, KbError, Note, Result, SearchExpr};
+
+/// Directory (inside `notes_dir`) holding the search index
+pub const SEARCH_INDEX_DIR: &str = ".index";
+
+/// File (inside [`SEARCH_INDEX_DIR`]) holding the search index
+pub const SEARCH_INDEX_FILE: &str = "search.json";
+
+/// Format of the index file; files of other versions are rebuilt
+///
+/// Version 2 keys words by their Unicode-normalized form.
+const SEARCH_INDEX_VERSION: u32 = 2;
+
+/// Splits text into the words the index is keyed by, normalized with
+/// [`fold_text`] like search queries are
+pub fn index_words(text: &str) -> impl Iterator<Item = String> {
+    fold_text(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+/// Indexed words of one note
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct IndexedNote {
+    /// Hash of the indexed text, to tell whether the note changed
+    hash: String,
+    /// Distinct words, sorted
+    words: Vec<String>,
+}
+
+impl IndexedNote {
+    fn of(note: &Note) -> Self {
+        let mut text = String::new();
+        for part in [&note.title, &note.content].into_iter().chain(&note.tags) {
+            text.push_str(part);
+            text.push('\0');
+        }
+        let words: BTreeSet<String> = index_words(&text).collect();
+        IndexedNote {
+            hash: stable_hash(text.as_bytes()),
+            words: words.into_iter().collect(),
+        }
+    }
+}
+
+/// Layout of the index file
+#[derive(Serialize, Deserialize)]
+struct SearchIndexFile {
+    version: u32,
+    /// Hash of the serialized `notes`
+    checksum: String,
+    notes: BTreeMap<String, IndexedNote>,
+}
+
+/// Inverted index from words to the notes containing them
+#[derive(Debug, Clone)]
+pub struct SearchIndex {
+    /// File the index is saved to
+    path: PathBuf,
+    /// Indexed words of each note, by note ID
+    notes: BTreeMap<String, IndexedNote>,
+    /// Word to the IDs of the notes containing it, ordered for prefix lookups
+    postings: BTreeMap<String, HashSet<String>>,
+    /// Notes still to be indexed by a background rebuild, which searches
+    /// can't rule out; never saved
+    pending: HashSet<String>,
+}
+
+impl SearchIndex {
+    /// Path of the index file for a notes directory
+    pub fn path_in(notes_dir: &Path) -> PathBuf {
+        notes_dir.join(SEARCH_INDEX_DIR).join(SEARCH_INDEX_FILE)
+    }
+
+    /// Indexes the given notes from scratch
+    pub fn build<'a>(path: PathBuf, notes: impl IntoIterator<Item = &'a Note>) -> Self {
+        let mut index = SearchIndex::pending(path, []);
+        for note in notes {
+            index.index_note(note);
+        }
+        index
+    }
+
+    /// Starts an empty index of notes that are still to be indexed, which
+    /// every search takes as candidates until they are
+    pub fn pending(path: PathBuf, note_ids: impl IntoIterator<Item = String>) -> Self {
+        SearchIndex {
+            path,
+            notes: BTreeMap::new(),
+            postings: BTreeMap::new(),
+            pending: note_ids.into_iter().collect(),
+        }
+    }
+
+    /// Reads the index saved at `path`
+    ///
+    /// # Returns
+    ///
+    /// `None` when there is no index file, or `KbError::InvalidFormat` when
+    /// the file is corrupt or was written in another format version
+    pub fn load(path: PathBuf) -> Result<Option<Self>> {
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let corrupt = |problem: String| KbError::InvalidFormat {
+            message: format!("Search index {} is corrupt: {}", path.display(), problem),
+        };
+
+        let file: SearchIndexFile =
+            serde_json::from_slice(&bytes).map_err(|e| corrupt(e.to_string()))?;
+        if file.version != SEARCH_INDEX_VERSION {
+            return Err(KbError::InvalidFormat {
+                message: format!(
+                    "Search index {} has format version {}, expected {}",
+                    path.display(),
+                    file.version,
+                    SEARCH_INDEX_VERSION
+                ),
+            });
+        }
+        if checksum(&file.notes)? != file.checksum {
+            return Err(corrupt("checksum mismatch".to_string()));
+        }
+
+        let mut postings: BTreeMap<String, HashSet<String>> = BTreeMap::new();
+        for (note_id, indexed) in &file.notes {
+            for word in &indexed.words {
+                postings
+                    .entry(word.clone())
+                    .or_default()
+                    .insert(note_id.clone());
+            }
+        }
+        Ok(Some(SearchIndex {
+            path,
+            notes: file.notes,
+            postings,
+            pending: HashSet::new(),
+        }))
+    }
+
+    /// Writes the index to its file
+    pub fn save(&self) -> Result<()> {
+        let dir = self.path.parent().unwrap_or_else(|| Path::new("."));
+        fs::create_dir_all(dir)?;
+
+        let file = SearchIndexFile {
+            version: SEARCH_INDEX_VERSION,
+            checksum: checksum(&self.notes)?,
+            notes: self.notes.clone(),
+        };
+        let temp_file = NamedTempFile::new_in(dir)?;
+        serde_json::to_writer(&temp_file, &file)?;
+        temp_file.persist(&self.path).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// Number of indexed notes
+    pub fn len(&self) -> usize {
+        self.notes.len()
+    }
+
+    /// Whether no note is indexed
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    /// Number of notes still to be indexed
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Replaces a note's entries with the words of its current text
+    ///
+    /// # Returns
+    ///
+    /// Whether the index changed
+    pub fn index_note(&mut self, note: &Note) -> bool {
+        self.pending.remove(&note.id);
+        let indexed = IndexedNote::of(note);
+        if self
+            .notes
+            .get(&note.id)
+            .is_some_and(|current| current.hash == indexed.hash)
+        {
+            return false;
+        }
+
+        self.remove_note(&note.id);
+        for word in &indexed.words {
+            self.postings
+                .entry(word.clone())
+                .or_default()
+                .insert(note.id.clone());
+        }
+        self.notes.insert(note.id.clone(), indexed);
+        true
+    }
+
+    /// Drops the entries of a note that no longer exists
+    ///
+    /// # Returns
+    ///
+    /// Whether the note was indexed
+    pub fn remove_note(&mut self, note_id: &str) -> bool {
+        self.pending.remove(note_id);
+        let Some(indexed) = self.notes.remove(note_id) else {
+            return false;
+        };
+        for word in indexed.words {
+            if let Some(ids) = self.postings.get_mut(&word) {
+                ids.remove(note_id);
+                if ids.is_empty() {
+                    self.postings.remove(&word);
+                }
+            }
+        }
+        true
+    }
+
+    /// Brings the index in line with the given notes, reindexing the ones
+    /// that changed and dropping the ones that are gone
+    ///
+    /// # Returns
+    ///
+    /// The number of notes reindexed or dropped
+    pub fn sync(&mut self, notes: &HashMap<String, Note>) -> usize {
+        let gone: Vec<String> = self
+            .notes
+            .keys()
+            .filter(|note_id| !notes.contains_key(*note_id))
+            .cloned()
+            .collect();
+        let mut changed = 0;
+        for note_id in gone {
+            self.remove_note(&note_id);
+            changed += 1;
+        }
+        for note in notes.values() {
+            if self.index_note(note) {
+                changed += 1;
+            }
+        }
+        changed
+    }
+
+    /// IDs of the notes that can match a search query
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query as typed
+    /// * `expr` - The query parsed by [`parse_search_query`](crate::parse_search_query)
+    ///
+    /// # Returns
+    ///
+    /// A superset of the matching notes, including every pending note, or
+    /// `None` when the index can't narrow the search (e.g. for a query made
+    /// only of `NOT` terms)
+    pub fn candidates(&self, query: &str, expr: Option<&SearchExpr>) -> Option<HashSet<String>> {
+        let mut candidates = match expr {
+            Some(expr) => self.expr_candidates(expr),
+            None => self.words_candidates(query),
+        }?;
+        candidates.extend(self.pending.iter().cloned());
+        Some(candidates)
+    }
+
+    fn expr_candidates(&self, expr: &SearchExpr) -> Option<HashSet<String>> {
+        match expr {
+            SearchExpr::Term(term) => self.words_candidates(term),
+            SearchExpr::Phrase(phrase) => {
+                // Words of a phrase may sit inside longer words at its ends
+                intersect(index_words(phrase).map(|word| {
+                    self.postings
+                        .iter()
+                        .filter(|(key, _)| key.contains(&word))
+                        .flat_map(|(_, ids)| ids.iter().cloned())
+                        .collect()
+                }))
+            }
+            SearchExpr::And(left, right) => {
+                match (self.expr_candidates(left), self.expr_candidates(right)) {
+                    (Some(left), Some(right)) => Some(&left & &right),
+                    (left, right) => left.or(right),
+                }
+            }
+            SearchExpr::Or(left, right) => {
+                let mut left = self.expr_candidates(left)?;
+                left.extend(self.expr_candidates(right)?);
+                Some(left)
+            }
+            SearchExpr::Not(_) => None,
+        }
+    }
+
+    /// Notes with a word starting with each word of `text`
+    fn words_candidates(&self, text: &str) -> Option<HashSet<String>> {
+        intersect(index_words(text).map(|word| {
+            self.postings
+                .range::<str, _>((Bound::Included(word.as_str()), Bound::Unbounded))
+                .take_while(|(key, _)| key.starts_with(&word))
+                .flat_map(|(_, ids)| ids.iter().cloned())
+                .collect()
+        }))
+    }
+}
+
+/// Intersects sets of note IDs, `None` for no sets at all
+fn intersect(sets: impl Iterator<Item = HashSet<String>>) -> Option<HashSet<String>> {
+    sets.reduce(|all, set| &all & &set)
+}
+
+fn checksum(notes: &BTreeMap<String, IndexedNote>) -> Result<String> {
+    Ok(stable_hash(&serde_json::to_vec(notes)?))
+}
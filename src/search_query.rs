@@ -0,0 +1,495 @@
+//! Boolean search queries.
+//!
+//! A query such as `docker AND compose NOT kubernetes` combines search terms
+//! with the operators `AND`, `OR` and `NOT` (written in capitals), parentheses
+//! and quoted phrases. `NOT` binds tightest, then `AND`, then `OR`; terms next
+//! to each other without an operator must all match. Bare terms are matched
+//! fuzzily against a note's title, content and tags, while a `"quoted phrase"`
+//! must appear in one of them as written, ignoring case. Queries without any
+//! operator, quote or parenthesis keep the plain fuzzy search.
+//!
+//! Queries and notes are compared after Unicode normalization (see
+//! [`fold_text`]), so composed and decomposed accents match each other.
+//!
+//! In [`SearchMode::Exact`] the whole query is instead taken literally, as a
+//! substring that must appear as written (optionally respecting case).
+//! A [`SearchScope`] restricts either mode to the title or the content, and
+//! [`SearchWeights`] set how much a title match counts against a content one.
+use std::ops::Add;
+
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use serde::Serialize;
+
+use crate::{fold_text, normalize_text, KbError, Note, Result};
+
+/// Weight of title matches unless configured otherwise
+pub const DEFAULT_TITLE_WEIGHT: u32 = 2;
+
+/// Weight of content matches unless configured otherwise
+pub const DEFAULT_CONTENT_WEIGHT: u32 = 1;
+
+/// How a search query is compared with notes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SearchMode {
+    /// Fuzzy matching, with boolean operators and quoted phrases
+    #[default]
+    Fuzzy,
+    /// The whole query is a literal substring; operators and quotes are
+    /// matched as written
+    Exact {
+        /// Whether letter case must match too
+        case_sensitive: bool,
+    },
+}
+
+/// Which fields of a note a search looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SearchScope {
+    /// Title, content and tags
+    #[default]
+    All,
+    /// Only the title
+    Title,
+    /// Only the content
+    Content,
+}
+
+impl SearchScope {
+    fn title(self) -> bool {
+        matches!(self, SearchScope::All | SearchScope::Title)
+    }
+
+    fn content(self) -> bool {
+        matches!(self, SearchScope::All | SearchScope::Content)
+    }
+
+    fn tags(self) -> bool {
+        self == SearchScope::All
+    }
+}
+
+/// How much a match in the title and in the content adds to a note's
+/// score; tag matches always count once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchWeights {
+    /// Multiplier of title matches
+    pub title: i64,
+    /// Multiplier of content matches
+    pub content: i64,
+}
+
+impl Default for SearchWeights {
+    fn default() -> Self {
+        SearchWeights {
+            title: DEFAULT_TITLE_WEIGHT as i64,
+            content: DEFAULT_CONTENT_WEIGHT as i64,
+        }
+    }
+}
+
+/// Fields of a note a search query matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct MatchedFields {
+    /// The title matched
+    pub title: bool,
+    /// The content matched
+    pub content: bool,
+    /// At least one tag matched
+    pub tags: bool,
+}
+
+impl MatchedFields {
+    /// Names of the matched fields, in note order
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            (self.title, "title"),
+            (self.content, "content"),
+            (self.tags, "tags"),
+        ]
+        .into_iter()
+        .filter_map(|(matched, name)| matched.then_some(name))
+        .collect()
+    }
+}
+
+/// Relevance of a note for a query, with the fields that contributed to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SearchScore {
+    /// Weighted sum of the field scores; 0 means no match
+    pub score: i64,
+    /// Fields that added to the score
+    pub fields: MatchedFields,
+}
+
+impl SearchScore {
+    /// Adds the score of a field, marking it matched when it scored
+    fn field(mut self, score: i64, mark: impl FnOnce(&mut MatchedFields)) -> Self {
+        if score > 0 {
+            self.score += score;
+            mark(&mut self.fields);
+        }
+        self
+    }
+
+    /// The score if the note matched, `None` otherwise
+    fn matched(self) -> Option<Self> {
+        (self.score > 0).then_some(self)
+    }
+}
+
+impl Add for SearchScore {
+    type Output = SearchScore;
+
+    fn add(self, other: SearchScore) -> SearchScore {
+        SearchScore {
+            score: self.score + other.score,
+            fields: MatchedFields {
+                title: self.fields.title || other.fields.title,
+                content: self.fields.content || other.fields.content,
+                tags: self.fields.tags || other.fields.tags,
+            },
+        }
+    }
+}
+
+/// A parsed boolean search query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchExpr {
+    /// A word matched fuzzily
+    Term(String),
+    /// A quoted phrase that must appear as a substring
+    Phrase(String),
+    /// Both sides must match
+    And(Box<SearchExpr>, Box<SearchExpr>),
+    /// Either side must match
+    Or(Box<SearchExpr>, Box<SearchExpr>),
+    /// The inner expression must not match
+    Not(Box<SearchExpr>),
+}
+
+impl SearchExpr {
+    /// Scores a note against the expression
+    ///
+    /// # Returns
+    ///
+    /// `None` when the note doesn't match, otherwise its relevance; title
+    /// and content matches are multiplied by `weights`, fields outside
+    /// `scope` and negated terms add nothing
+    pub fn score(
+        &self,
+        note: &Note,
+        scope: SearchScope,
+        weights: SearchWeights,
+        matcher: &SkimMatcherV2,
+    ) -> Option<SearchScore> {
+        match self {
+            SearchExpr::Term(term) => {
+                let tag_score = if scope.tags() {
+                    let term = fold_text(term);
+                    note.tags
+                        .iter()
+                        .filter_map(|tag| matcher.fuzzy_match(&fold_text(tag), &term))
+                        .max()
+                        .unwrap_or(0)
+                } else {
+                    0
+                };
+                fuzzy_score(note, term, scope, weights, matcher)
+                    .field(tag_score, |fields| fields.tags = true)
+                    .matched()
+            }
+            SearchExpr::Phrase(phrase) => {
+                exact_score(note, phrase, false, scope, weights).matched()
+            }
+            SearchExpr::And(left, right) => Some(
+                left.score(note, scope, weights, matcher)?
+                    + right.score(note, scope, weights, matcher)?,
+            ),
+            SearchExpr::Or(left, right) => match (
+                left.score(note, scope, weights, matcher),
+                right.score(note, scope, weights, matcher),
+            ) {
+                (None, None) => None,
+                (left, right) => Some(left.unwrap_or_default() + right.unwrap_or_default()),
+            },
+            SearchExpr::Not(inner) => match inner.score(note, scope, weights, matcher) {
+                Some(_) => None,
+                None => Some(SearchScore::default()),
+            },
+        }
+    }
+}
+
+/// Relevance of a note for a plain fuzzy query, scoring 0 when it doesn't
+/// match
+///
+/// Title and content matches are multiplied by `weights`, and fields outside
+/// `scope` count 0. Tags are not looked at.
+pub fn fuzzy_score(
+    note: &Note,
+    query: &str,
+    scope: SearchScope,
+    weights: SearchWeights,
+    matcher: &SkimMatcherV2,
+) -> SearchScore {
+    let query = fold_text(query);
+    let score = |include: bool, text: &str| {
+        if include {
+            matcher.fuzzy_match(&fold_text(text), &query).unwrap_or(0)
+        } else {
+            0
+        }
+    };
+    SearchScore::default()
+        .field(
+            score(scope.title(), &note.title) * weights.title,
+            |fields| fields.title = true,
+        )
+        .field(
+            score(scope.content(), &note.content) * weights.content,
+            |fields| fields.content = true,
+        )
+}
+
+/// Relevance of a note for a literal query, scoring 0 when it doesn't appear
+///
+/// The score is the number of occurrences in the title and content,
+/// multiplied by `weights`, plus those in the tags, leaving out the fields
+/// outside `scope`.
+pub fn exact_score(
+    note: &Note,
+    query: &str,
+    case_sensitive: bool,
+    scope: SearchScope,
+    weights: SearchWeights,
+) -> SearchScore {
+    if query.is_empty() {
+        return SearchScore::default();
+    }
+    let normalize = |text: &str| {
+        if case_sensitive {
+            normalize_text(text).into_owned()
+        } else {
+            fold_text(text)
+        }
+    };
+    let query = normalize(query);
+    let count = |include: bool, text: &str| {
+        if include {
+            normalize(text).matches(&query).count() as i64
+        } else {
+            0
+        }
+    };
+    let tags = note.tags.iter().map(|tag| count(scope.tags(), tag)).sum();
+    SearchScore::default()
+        .field(
+            count(scope.title(), &note.title) * weights.title,
+            |fields| fields.title = true,
+        )
+        .field(
+            count(scope.content(), &note.content) * weights.content,
+            |fields| fields.content = true,
+        )
+        .field(tags, |fields| fields.tags = true)
+}
+
+/// A token of a search query
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Phrase(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Word(word) => format!("'{}'", word),
+            Token::Phrase(phrase) => format!("\"{}\"", phrase),
+            Token::And => "'AND'".to_string(),
+            Token::Or => "'OR'".to_string(),
+            Token::Not => "'NOT'".to_string(),
+            Token::Open => "'('".to_string(),
+            Token::Close => "')'".to_string(),
+        }
+    }
+}
+
+/// Parses a search query
+///
+/// # Arguments
+///
+/// * `query` - The query as typed, such as `docker AND "compose file" NOT k8s`
+///
+/// # Returns
+///
+/// The expression tree, `None` for a query without operators, quotes or
+/// parentheses (which is searched fuzzily as a whole), or
+/// `KbError::InvalidFormat` naming the column of the problem
+pub fn parse_search_query(query: &str) -> Result<Option<SearchExpr>> {
+    let tokens = tokenize(query)?;
+    if tokens
+        .iter()
+        .all(|(token, _)| matches!(token, Token::Word(_)))
+    {
+        return Ok(None);
+    }
+
+    let mut parser = Parser {
+        tokens,
+        next: 0,
+        end: query.chars().count() + 1,
+    };
+    let expr = parser.parse_or()?;
+    match parser.peek() {
+        None => Ok(Some(expr)),
+        Some((Token::Close, column)) => Err(query_error(column, "unmatched ')'")),
+        Some((token, column)) => Err(query_error(
+            column,
+            &format!("unexpected {}", token.describe()),
+        )),
+    }
+}
+
+/// Splits a query into tokens, each with the column it starts at (1-based,
+/// in characters)
+fn tokenize(query: &str) -> Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().enumerate().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        let column = index + 1;
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push((Token::Open, column)),
+            ')' => tokens.push((Token::Close, column)),
+            '"' => {
+                let mut phrase = String::new();
+                let mut closed = false;
+                for (_, c) in chars.by_ref() {
+                    if c == '"' {
+                        closed = true;
+                        break;
+                    }
+                    phrase.push(c);
+                }
+                if !closed {
+                    return Err(query_error(column, "unterminated quoted phrase"));
+                }
+                if phrase.trim().is_empty() {
+                    return Err(query_error(column, "empty quoted phrase"));
+                }
+                tokens.push((Token::Phrase(phrase), column));
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '"' | '(' | ')') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                let token = match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Word(word),
+                };
+                tokens.push((token, column));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser over the tokens of a query
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    next: usize,
+    /// Column just past the end of the query, reported for missing terms
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<(Token, usize)> {
+        self.tokens.get(self.next).cloned()
+    }
+
+    fn advance(&mut self) -> Option<(Token, usize)> {
+        let token = self.peek();
+        self.next += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<SearchExpr> {
+        let mut expr = self.parse_and()?;
+        while let Some((Token::Or, _)) = self.peek() {
+            self.advance();
+            let right = self.parse_and()?;
+            expr = SearchExpr::Or(Box::new(expr), Box::new(right));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<SearchExpr> {
+        let mut expr = self.parse_unary()?;
+        loop {
+            match self.peek() {
+                Some((Token::And, _)) => {
+                    self.advance();
+                }
+                // Terms next to each other must all match
+                Some((Token::Word(_) | Token::Phrase(_) | Token::Not | Token::Open, _)) => {}
+                _ => return Ok(expr),
+            }
+            let right = self.parse_unary()?;
+            expr = SearchExpr::And(Box::new(expr), Box::new(right));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<SearchExpr> {
+        if let Some((Token::Not, _)) = self.peek() {
+            self.advance();
+            return Ok(SearchExpr::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<SearchExpr> {
+        let previous = self.next.checked_sub(1).map(|i| self.tokens[i].0.clone());
+        match self.advance() {
+            Some((Token::Word(word), _)) => Ok(SearchExpr::Term(word)),
+            Some((Token::Phrase(phrase), _)) => Ok(SearchExpr::Phrase(phrase)),
+            Some((Token::Open, column)) => {
+                let expr = self.parse_or()?;
+                match self.advance() {
+                    Some((Token::Close, _)) => Ok(expr),
+                    _ => Err(query_error(column, "unclosed '('")),
+                }
+            }
+            Some((token, column)) => Err(query_error(
+                column,
+                &format!("expected a search term, found {}", token.describe()),
+            )),
+            None => Err(query_error(
+                self.end,
+                &match previous {
+                    Some(token) => format!("expected a search term after {}", token.describe()),
+                    None => "expected a search term".to_string(),
+                },
+            )),
+        }
+    }
+}
+
+fn query_error(column: usize, problem: &str) -> KbError {
+    KbError::InvalidFormat {
+        message: format!("Invalid search query at column {}: {}", column, problem),
+    }
+}
@@ -0,0 +1,288 @@
+//! Navigating the content of large notes without printing them whole.
+//!
+//! [`MarkdownBlocks`] splits content into its top-level Markdown blocks as the
+//! parser reaches them, so a caller can print (or render) a note piece by
+//! piece. [`find_section`] cuts out the part of a note below a heading, and
+//! [`LineRange`] selects raw lines.
+use pulldown_cmark::{Event, OffsetIter, Options, Parser, Tag, TagEnd};
+
+use crate::{normalize_alias, KbError, Result};
+
+/// Separator between heading names in a section path, e.g. `Setup > Install`
+pub const SECTION_PATH_SEPARATOR: char = '>';
+
+/// A heading found in note content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heading {
+    /// Heading level, 1 for `#` to 6 for `######`
+    pub level: u8,
+    /// Heading text without Markdown markup
+    pub title: String,
+    /// Line the heading starts on (1-based)
+    pub line: usize,
+    /// Titles of the enclosing headings, outermost first
+    pub parents: Vec<String>,
+    /// Byte offset where the heading starts
+    pub start: usize,
+    /// Byte offset where its section ends: the next heading of the same or a
+    /// higher level, or the end of the content
+    pub end: usize,
+}
+
+impl Heading {
+    /// The heading's title preceded by its parents, e.g. `Setup > Install`
+    pub fn path(&self) -> String {
+        let mut parts = self.parents.clone();
+        parts.push(self.title.clone());
+        parts.join(&format!(" {} ", SECTION_PATH_SEPARATOR))
+    }
+}
+
+/// Lists the headings of Markdown content in document order
+pub fn parse_headings(content: &str) -> Vec<Heading> {
+    let mut headings: Vec<Heading> = Vec::new();
+    let mut current: Option<Heading> = None;
+    // Line numbers are counted incrementally so huge notes are scanned once
+    let (mut counted_to, mut line) = (0, 1);
+
+    for (event, range) in Parser::new_ext(content, Options::empty()).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                line += content[counted_to..range.start].matches('\n').count();
+                counted_to = range.start;
+                current = Some(Heading {
+                    level: level as u8,
+                    title: String::new(),
+                    line,
+                    parents: Vec::new(),
+                    start: range.start,
+                    end: content.len(),
+                });
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(heading) = current.as_mut() {
+                    heading.title.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some(mut heading) = current.take() {
+                    heading.title = heading.title.trim().to_string();
+                    headings.push(heading);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Close sections and record parents now that all headings are known
+    let mut open: Vec<usize> = Vec::new();
+    for i in 0..headings.len() {
+        while let Some(&last) = open.last() {
+            if headings[last].level < headings[i].level {
+                break;
+            }
+            headings[last].end = headings[i].start;
+            open.pop();
+        }
+        headings[i].parents = open.iter().map(|&j| headings[j].title.clone()).collect();
+        open.push(i);
+    }
+
+    headings
+}
+
+/// Returns the section below a heading, including the heading itself and
+/// every section nested under it
+///
+/// Headings are matched case-insensitively. When several headings share the
+/// name, the enclosing headings can be given as a path (`Setup > Install`);
+/// each part must name an ancestor, from the outside in.
+///
+/// # Arguments
+///
+/// * `content` - Markdown content of a note
+/// * `name` - Heading title, or path of heading titles
+///
+/// # Returns
+///
+/// The section's text, `KbError::SectionNotFound` when no heading matches or
+/// `KbError::AmbiguousSection` listing the paths of all matching headings
+pub fn find_section<'a>(content: &'a str, name: &str) -> Result<&'a str> {
+    let parts: Vec<String> = name
+        .split(SECTION_PATH_SEPARATOR)
+        .map(normalize_alias)
+        .collect();
+    let Some((title, ancestors)) = parts.split_last() else {
+        return Err(KbError::SectionNotFound {
+            name: name.to_string(),
+        });
+    };
+
+    let headings = parse_headings(content);
+    let mut matches: Vec<&Heading> = headings
+        .iter()
+        .filter(|heading| normalize_alias(&heading.title) == *title)
+        .filter(|heading| {
+            // Every requested ancestor must appear among the parents, in order
+            let mut parents = heading.parents.iter().map(|p| normalize_alias(p));
+            ancestors
+                .iter()
+                .all(|ancestor| parents.any(|parent| parent == *ancestor))
+        })
+        .collect();
+
+    match matches.len() {
+        0 => Err(KbError::SectionNotFound {
+            name: name.to_string(),
+        }),
+        1 => {
+            let heading = matches.remove(0);
+            Ok(content[heading.start..heading.end].trim_end())
+        }
+        _ => Err(KbError::AmbiguousSection {
+            name: name.to_string(),
+            candidates: matches
+                .iter()
+                .map(|heading| format!("{} (line {})", heading.path(), heading.line))
+                .collect(),
+        }),
+    }
+}
+
+/// Iterator over the top-level Markdown blocks of some content
+///
+/// Each item runs up to the end of one block and includes the blank lines
+/// before it, so the items concatenated give back the content unchanged.
+/// The content is parsed incrementally as items are requested.
+pub struct MarkdownBlocks<'a> {
+    content: &'a str,
+    events: OffsetIter<'a>,
+    /// Nesting depth of the event stream
+    depth: usize,
+    /// Byte offset up to which content has been returned
+    emitted: usize,
+}
+
+impl<'a> MarkdownBlocks<'a> {
+    /// Starts splitting `content` into blocks
+    pub fn new(content: &'a str) -> Self {
+        let options = Options::ENABLE_TABLES
+            | Options::ENABLE_STRIKETHROUGH
+            | Options::ENABLE_TASKLISTS
+            | Options::ENABLE_FOOTNOTES;
+        Self {
+            content,
+            events: Parser::new_ext(content, options).into_offset_iter(),
+            depth: 0,
+            emitted: 0,
+        }
+    }
+
+    /// Returns the content from the last emitted offset up to `end`
+    fn emit(&mut self, end: usize) -> Option<&'a str> {
+        let end = end.max(self.emitted);
+        let chunk = &self.content[self.emitted..end];
+        self.emitted = end;
+        Some(chunk)
+    }
+}
+
+impl<'a> Iterator for MarkdownBlocks<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for (event, range) in self.events.by_ref() {
+            match event {
+                Event::Start(_) => self.depth += 1,
+                Event::End(_) => {
+                    self.depth = self.depth.saturating_sub(1);
+                    if self.depth == 0 {
+                        return self.emit(range.end);
+                    }
+                }
+                // Blocks without children, such as thematic breaks
+                _ if self.depth == 0 => return self.emit(range.end),
+                _ => {}
+            }
+        }
+
+        // Trailing whitespace (or anything the parser skipped)
+        if self.emitted < self.content.len() {
+            return self.emit(self.content.len());
+        }
+        None
+    }
+}
+
+/// A range of lines, 1-based and inclusive, written `A:B`, `A:`, `:B` or `A`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRange {
+    /// First line to include
+    pub start: usize,
+    /// Last line to include (`None` for the end of the content)
+    pub end: Option<usize>,
+}
+
+impl LineRange {
+    /// Returns the selected lines of `content`, which may be empty when the
+    /// range starts after its last line
+    pub fn slice<'a>(&self, content: &'a str) -> &'a str {
+        let offset_of_line = |line: usize| -> usize {
+            if line <= 1 {
+                return 0;
+            }
+            content
+                .match_indices('\n')
+                .nth(line - 2)
+                .map(|(i, _)| i + 1)
+                .unwrap_or(content.len())
+        };
+
+        let start = offset_of_line(self.start);
+        let end = match self.end {
+            Some(end) => offset_of_line(end + 1),
+            None => content.len(),
+        };
+        &content[start..end.max(start)]
+    }
+}
+
+impl std::str::FromStr for LineRange {
+    type Err = KbError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || KbError::InvalidFormat {
+            message: format!(
+                "Invalid line range '{}': use A:B, A:, :B or A with line numbers starting at 1",
+                s
+            ),
+        };
+        let number = |part: &str| -> Result<Option<usize>> {
+            let part = part.trim();
+            if part.is_empty() {
+                return Ok(None);
+            }
+            match part.parse::<usize>() {
+                Ok(0) | Err(_) => Err(invalid()),
+                Ok(line) => Ok(Some(line)),
+            }
+        };
+
+        let (start, end) = match s.split_once(':') {
+            Some((start, end)) => (number(start)?, number(end)?),
+            None => {
+                let line = number(s)?.ok_or_else(invalid)?;
+                (Some(line), Some(line))
+            }
+        };
+
+        let range = LineRange {
+            start: start.unwrap_or(1),
+            end,
+        };
+        if range.end.is_some_and(|end| end < range.start) {
+            return Err(invalid());
+        }
+        Ok(range)
+    }
+}
@@ -0,0 +1,248 @@
+//! Snippets showing where a search matched a note.
+//!
+//! A snippet is the line of the title or content that best matches the
+//! query, cut to [`SNIPPET_WIDTH`] characters around the match, with the
+//! positions of the matched characters. Positions count characters (not
+//! bytes) of the snippet text, so they stay valid once the text is printed.
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use serde::Serialize;
+
+use crate::{parse_search_query, Note, SearchExpr, SearchMode, SearchScope};
+
+/// Longest snippet, in characters, not counting the `…` marking a cut
+pub const SNIPPET_WIDTH: usize = 120;
+
+/// Marks text left out at either end of a snippet
+const ELLIPSIS: char = '…';
+
+/// Part of the note a snippet was taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SnippetField {
+    Title,
+    Content,
+}
+
+/// The best matching part of a note for a search query
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SearchSnippet {
+    /// Where the snippet comes from
+    pub field: SnippetField,
+    /// The matching line, windowed around the match
+    pub text: String,
+    /// Character positions of the matched characters in `text`, ascending
+    pub match_positions: Vec<usize>,
+}
+
+impl SearchSnippet {
+    /// Returns the text with the matched characters highlighted
+    ///
+    /// Highlighting is left out when colors are disabled, as they are with
+    /// `--no-color` or when output doesn't go to a terminal.
+    pub fn highlighted(&self) -> String {
+        if !console::colors_enabled() || self.match_positions.is_empty() {
+            return self.text.clone();
+        }
+
+        let mut out = String::new();
+        let mut run = String::new();
+        let mut matches = self.match_positions.iter().peekable();
+        for (i, c) in self.text.chars().enumerate() {
+            if matches.next_if(|&&position| position == i).is_some() {
+                run.push(c);
+                continue;
+            }
+            if !run.is_empty() {
+                out.push_str(&console::style(&run).yellow().bold().to_string());
+                run.clear();
+            }
+            out.push(c);
+        }
+        if !run.is_empty() {
+            out.push_str(&console::style(&run).yellow().bold().to_string());
+        }
+        out
+    }
+}
+
+/// What a snippet looks for: the terms of a query that must be present
+enum Needle {
+    Fuzzy(String),
+    Phrase { text: String, case_sensitive: bool },
+}
+
+impl Needle {
+    fn find(&self, text: &str, matcher: &SkimMatcherV2) -> Option<(i64, Vec<usize>)> {
+        match self {
+            Needle::Fuzzy(term) => matcher.fuzzy_indices(text, term),
+            Needle::Phrase {
+                text: phrase,
+                case_sensitive,
+            } => {
+                let (chars, phrase): (Vec<char>, Vec<char>) = if *case_sensitive {
+                    (text.chars().collect(), phrase.chars().collect())
+                } else {
+                    (
+                        text.to_lowercase().chars().collect(),
+                        phrase.to_lowercase().chars().collect(),
+                    )
+                };
+                // Lowercasing can change the length of some characters; only
+                // look for phrases where it didn't
+                if chars.len() != text.chars().count() || phrase.is_empty() {
+                    return None;
+                }
+                let start = chars
+                    .windows(phrase.len())
+                    .position(|window| window == phrase.as_slice())?;
+                // Rank phrases like a fuzzy match of the same length
+                Some((
+                    16 * phrase.len() as i64,
+                    (start..start + phrase.len()).collect(),
+                ))
+            }
+        }
+    }
+}
+
+/// Collects the terms a note must contain for an expression to match,
+/// leaving out negated ones
+fn collect_needles(expr: &SearchExpr, needles: &mut Vec<Needle>) {
+    match expr {
+        SearchExpr::Term(term) => needles.push(Needle::Fuzzy(term.clone())),
+        SearchExpr::Phrase(phrase) => needles.push(Needle::Phrase {
+            text: phrase.clone(),
+            case_sensitive: false,
+        }),
+        SearchExpr::And(left, right) | SearchExpr::Or(left, right) => {
+            collect_needles(left, needles);
+            collect_needles(right, needles);
+        }
+        SearchExpr::Not(_) => {}
+    }
+}
+
+/// Finds the snippet of a note that best matches a search query
+///
+/// # Arguments
+///
+/// * `note` - A note returned for the query
+/// * `query` - The query as passed to [`NoteStorage::search_notes`](crate::NoteStorage::search_notes)
+///
+/// # Returns
+///
+/// The title or content line with the best match (title matches count
+/// double, as in search scoring), or `None` when no single line matches,
+/// for instance for a query made only of `NOT` terms
+pub fn search_snippet(note: &Note, query: &str) -> Option<SearchSnippet> {
+    search_snippet_with_mode(note, query, SearchMode::Fuzzy, SearchScope::All)
+}
+
+/// Finds the snippet of a note that best matches a search query compared
+/// the given way, e.g. literally for `search --exact`, only looking at the
+/// title or the content when `scope` says so
+pub fn search_snippet_with_mode(
+    note: &Note,
+    query: &str,
+    mode: SearchMode,
+    scope: SearchScope,
+) -> Option<SearchSnippet> {
+    let needles = match mode {
+        SearchMode::Exact { case_sensitive } => vec![Needle::Phrase {
+            text: query.to_string(),
+            case_sensitive,
+        }],
+        SearchMode::Fuzzy => match parse_search_query(query) {
+            Ok(Some(expr)) => {
+                let mut needles = Vec::new();
+                collect_needles(&expr, &mut needles);
+                needles
+            }
+            _ => vec![Needle::Fuzzy(query.to_string())],
+        },
+    };
+    let matcher = SkimMatcherV2::default();
+
+    let title =
+        (scope != SearchScope::Content).then_some((SnippetField::Title, note.title.as_str(), 2));
+    let content = (scope != SearchScope::Title).then_some(&note.content);
+    let lines = title.into_iter().chain(
+        content
+            .into_iter()
+            .flat_map(|content| content.lines())
+            .map(|line| (SnippetField::Content, line, 1)),
+    );
+    let mut best: Option<(i64, SnippetField, &str, Vec<usize>)> = None;
+    for (field, line, weight) in lines {
+        let mut score = 0;
+        let mut positions = Vec::new();
+        for needle in &needles {
+            if let Some((needle_score, indices)) = needle.find(line, &matcher) {
+                score += needle_score * weight;
+                positions.extend(indices);
+            }
+        }
+        if score > 0 && best.as_ref().is_none_or(|(best, ..)| score > *best) {
+            best = Some((score, field, line, positions));
+        }
+    }
+
+    let (_, field, line, mut positions) = best?;
+    positions.sort_unstable();
+    positions.dedup();
+    let (text, match_positions) = window(line, &positions);
+    Some(SearchSnippet {
+        field,
+        text,
+        match_positions,
+    })
+}
+
+/// Cuts a line to [`SNIPPET_WIDTH`] characters centered on the matched
+/// positions, moving the positions along
+fn window(line: &str, positions: &[usize]) -> (String, Vec<usize>) {
+    let chars: Vec<char> = line.chars().collect();
+    // Indentation says nothing about the match
+    let indent = chars.iter().take_while(|c| c.is_whitespace()).count();
+    let chars = &chars[indent..];
+    let positions: Vec<usize> = positions
+        .iter()
+        .filter_map(|position| position.checked_sub(indent))
+        .collect();
+
+    if chars.len() <= SNIPPET_WIDTH {
+        return (chars.iter().collect(), positions);
+    }
+
+    let (first, last) = match (positions.first(), positions.last()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _ => (0, 0),
+    };
+    // Center on the whole match when it fits, otherwise on its start
+    let center = if last - first < SNIPPET_WIDTH {
+        (first + last) / 2
+    } else {
+        first
+    };
+    let start = center
+        .saturating_sub(SNIPPET_WIDTH / 2)
+        .min(chars.len() - SNIPPET_WIDTH);
+    let end = start + SNIPPET_WIDTH;
+
+    let mut text = String::new();
+    let mut shift = 0;
+    if start > 0 {
+        text.push(ELLIPSIS);
+        shift = 1;
+    }
+    text.extend(&chars[start..end]);
+    if end < chars.len() {
+        text.push(ELLIPSIS);
+    }
+    let positions = positions
+        .into_iter()
+        .filter(|position| (start..end).contains(position))
+        .map(|position| position - start + shift)
+        .collect();
+    (text, positions)
+}
@@ -0,0 +1,328 @@
+//! SQLite storage backend, selected with `backend = "sqlite"`.
+//!
+//! Notes are kept in a single database at `notes_dir/notes.db` in WAL mode.
+//! Each row holds the note's JSON next to the columns queries filter on;
+//! normalized tags get a table of their own, so tag and date filters run as
+//! SQL instead of over every note.
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension};
+
+use crate::{
+    normalize_tag, BackendKind, BackendLoad, FsyncMode, KbError, Note, NoteFilter, Result,
+    StorageBackend, TagMatch,
+};
+
+/// Tables and indexes of the database, created when it is opened
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS notes (
+        id TEXT PRIMARY KEY NOT NULL,
+        title TEXT NOT NULL,
+        content TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS note_tags (
+        note_id TEXT NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (note_id, tag)
+    );
+    CREATE INDEX IF NOT EXISTS note_tags_by_tag ON note_tags(tag, note_id);
+    CREATE INDEX IF NOT EXISTS notes_by_created_at ON notes(created_at);
+    CREATE INDEX IF NOT EXISTS notes_by_updated_at ON notes(updated_at);
+    PRAGMA user_version = 1;
+";
+
+/// How long a write waits for another connection's transaction to finish
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Stores notes in a SQLite database
+///
+/// The connection is opened on first use, creating the database when it does
+/// not exist yet.
+pub struct SqliteBackend {
+    /// Location of the database
+    path: PathBuf,
+
+    /// Durability mode, mapped to SQLite's `synchronous` setting
+    fsync: FsyncMode,
+
+    /// Open connection (`None` until first use or after a release)
+    connection: Mutex<Option<Connection>>,
+}
+
+impl SqliteBackend {
+    /// Creates a backend for the database at `path`
+    pub fn new(path: PathBuf, fsync: FsyncMode) -> Self {
+        Self {
+            path,
+            fsync,
+            connection: Mutex::new(None),
+        }
+    }
+
+    /// Location of the database
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Runs `f` on the connection, opening it first if needed
+    fn with_connection<T>(&self, f: impl FnOnce(&mut Connection) -> Result<T>) -> Result<T> {
+        let mut connection =
+            self.connection
+                .lock()
+                .map_err(|_| KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on database connection".to_string(),
+                })?;
+        let connection = match &mut *connection {
+            Some(connection) => connection,
+            slot @ None => slot.insert(self.open()?),
+        };
+        f(connection)
+    }
+
+    /// Opens the database in WAL mode and creates missing tables
+    fn open(&self) -> Result<Connection> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let connection = Connection::open(&self.path)?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        connection
+            .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        // In WAL mode, NORMAL only risks the latest commits on power loss
+        let synchronous = match self.fsync {
+            FsyncMode::Always => "FULL",
+            FsyncMode::OnClose => "NORMAL",
+            FsyncMode::Never => "OFF",
+        };
+        connection.pragma_update(None, "synchronous", synchronous)?;
+        connection.pragma_update(None, "foreign_keys", "ON")?;
+        // Overwrite deleted notes, so they don't linger in free pages (and in
+        // backups, which drop notes excluded from them after copying)
+        connection.pragma_update(None, "secure_delete", "ON")?;
+        connection.execute_batch(SCHEMA)?;
+        debug!("Opened note database {}", self.path.display());
+        Ok(connection)
+    }
+}
+
+impl StorageBackend for SqliteBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Sqlite
+    }
+
+    fn load_all(&self) -> Result<BackendLoad> {
+        self.with_connection(|connection| {
+            let mut statement = connection.prepare("SELECT id, data FROM notes ORDER BY id")?;
+            let rows = statement.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?;
+
+            let mut load = BackendLoad::default();
+            for row in rows {
+                let (id, data) = row?;
+                match serde_json::from_str::<Note>(&data) {
+                    Ok(note) => load.notes.push(note),
+                    Err(e) => {
+                        warn!(
+                            "Failed to load note {} from {}: {}",
+                            id,
+                            self.path.display(),
+                            e
+                        );
+                        load.failed
+                            .push((self.path.clone(), format!("note {}: {}", id, e)));
+                    }
+                }
+            }
+            Ok(load)
+        })
+    }
+
+    fn load(&self, note_id: &str) -> Result<Option<Note>> {
+        let data: Option<String> = self.with_connection(|connection| {
+            Ok(connection
+                .query_row("SELECT data FROM notes WHERE id = ?1", [note_id], |row| {
+                    row.get(0)
+                })
+                .optional()?)
+        })?;
+        Ok(data
+            .map(|data| serde_json::from_str::<Note>(&data))
+            .transpose()?)
+    }
+
+    /// Replaces the note's row and tags in one transaction
+    fn save(&self, note: &Note) -> Result<()> {
+        let data = serde_json::to_string(note)?;
+        let tags: BTreeSet<String> = note.tags.iter().map(|tag| normalize_tag(tag)).collect();
+        self.with_connection(|connection| {
+            let transaction = connection.transaction()?;
+            transaction.execute(
+                "INSERT INTO notes (id, title, content, created_at, updated_at, data)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT(id) DO UPDATE SET
+                     title = excluded.title,
+                     content = excluded.content,
+                     created_at = excluded.created_at,
+                     updated_at = excluded.updated_at,
+                     data = excluded.data",
+                params![
+                    note.id,
+                    note.title,
+                    note.content,
+                    timestamp(note.created_at),
+                    timestamp(note.updated_at),
+                    data
+                ],
+            )?;
+            transaction.execute("DELETE FROM note_tags WHERE note_id = ?1", [&note.id])?;
+            {
+                let mut insert =
+                    transaction.prepare("INSERT INTO note_tags (note_id, tag) VALUES (?1, ?2)")?;
+                for tag in &tags {
+                    insert.execute(params![note.id, tag])?;
+                }
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+    }
+
+    fn delete(&self, note_id: &str) -> Result<bool> {
+        self.with_connection(|connection| {
+            // Tags go with the note through the foreign key
+            let deleted = connection.execute("DELETE FROM notes WHERE id = ?1", [note_id])?;
+            Ok(deleted > 0)
+        })
+    }
+
+    fn list_ids(&self) -> Result<Vec<String>> {
+        self.with_connection(|connection| {
+            let mut statement = connection.prepare("SELECT id FROM notes ORDER BY id")?;
+            let ids = statement
+                .query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(ids)
+        })
+    }
+
+    /// Runs the filter as a single SQL query over the indexed columns
+    fn query(&self, filter: &NoteFilter, text: Option<&str>) -> Result<Vec<Note>> {
+        let mut sql = String::from("SELECT data FROM notes WHERE 1 = 1");
+        let mut values: Vec<Value> = Vec::new();
+
+        for (bound, column, operator) in [
+            (filter.since, "created_at", ">="),
+            (filter.until, "created_at", "<="),
+            (filter.updated_since, "updated_at", ">="),
+            (filter.updated_until, "updated_at", "<="),
+        ] {
+            if let Some(bound) = bound {
+                sql.push_str(&format!(" AND {} {} ?", column, operator));
+                values.push(Value::Integer(timestamp(bound)));
+            }
+        }
+
+        let tags: BTreeSet<String> = filter.tags.iter().map(|tag| normalize_tag(tag)).collect();
+        if !tags.is_empty() {
+            sql.push_str(&format!(
+                " AND id IN (SELECT note_id FROM note_tags WHERE tag IN ({})",
+                placeholders(tags.len())
+            ));
+            if filter.tag_match == TagMatch::All {
+                sql.push_str(" GROUP BY note_id HAVING COUNT(*) = ?");
+            }
+            sql.push(')');
+            let count = tags.len() as i64;
+            values.extend(tags.into_iter().map(Value::Text));
+            if filter.tag_match == TagMatch::All {
+                values.push(Value::Integer(count));
+            }
+        }
+
+        let excluded: BTreeSet<String> = filter
+            .exclude_tags
+            .iter()
+            .map(|tag| normalize_tag(tag))
+            .collect();
+        if !excluded.is_empty() {
+            sql.push_str(&format!(
+                " AND id NOT IN (SELECT note_id FROM note_tags WHERE tag IN ({}))",
+                placeholders(excluded.len())
+            ));
+            values.extend(excluded.into_iter().map(Value::Text));
+        }
+
+        if let Some(text) = text {
+            sql.push_str(" AND (instr(title, ?) > 0 OR instr(content, ?) > 0)");
+            values.push(Value::Text(text.to_string()));
+            values.push(Value::Text(text.to_string()));
+        }
+
+        debug!("Querying note database: {}", sql);
+        let rows: Vec<String> = self.with_connection(|connection| {
+            let mut statement = connection.prepare(&sql)?;
+            let rows = statement
+                .query_map(params_from_iter(values), |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(rows)
+        })?;
+        rows.iter()
+            .map(|data| Ok(serde_json::from_str::<Note>(data)?))
+            .collect()
+    }
+
+    fn indexed_queries(&self) -> bool {
+        true
+    }
+
+    /// Copies the database with `VACUUM INTO`, which sees a consistent state
+    /// even while notes are being written
+    fn snapshot(&self, target: &Path) -> Result<bool> {
+        if target.exists() {
+            fs::remove_file(target)?;
+        }
+        self.with_connection(|connection| {
+            connection.execute("VACUUM INTO ?1", [target.to_string_lossy()])?;
+            Ok(true)
+        })
+    }
+
+    fn release(&self) {
+        match self.connection.lock() {
+            Ok(mut connection) => {
+                if connection.take().is_some() {
+                    debug!("Closed note database {}", self.path.display());
+                }
+            }
+            Err(e) => warn!("Failed to close note database: {}", e),
+        }
+    }
+}
+
+/// Stores times as nanoseconds since the epoch, so they compare like
+/// `DateTime`s; times beyond the years 1677 to 2262 are clamped
+fn timestamp(time: DateTime<Utc>) -> i64 {
+    time.timestamp_nanos_opt()
+        .unwrap_or(if time.timestamp() < 0 {
+            i64::MIN
+        } else {
+            i64::MAX
+        })
+}
+
+/// Returns `?, ?, ...` with `count` placeholders
+fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
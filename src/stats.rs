@@ -0,0 +1,114 @@
+//! Length statistics of note content.
+//!
+//! Characters and lines are counted on the raw Markdown, code included.
+//! Words are counted on the text a reader sees: Markdown markup, link
+//! destinations, raw HTML and code blocks are left out, so the reading time
+//! estimate is not inflated by pasted code or logs.
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use serde::Serialize;
+
+use crate::Note;
+
+/// Reading speed the reading time estimate assumes
+pub const WORDS_PER_MINUTE: usize = 200;
+
+/// Length statistics of a note's content
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct NoteStats {
+    /// Words outside code blocks
+    pub words: usize,
+    /// Characters (not bytes) of the whole content
+    pub characters: usize,
+    /// Lines of the whole content
+    pub lines: usize,
+    /// Headings of any level
+    pub headings: usize,
+    /// Estimated reading time, rounded up to whole minutes
+    pub reading_minutes: usize,
+}
+
+impl NoteStats {
+    /// One-line summary as shown by `view` and `list --detailed`
+    pub fn summary(&self) -> String {
+        let count =
+            |n: usize, noun: &str| format!("{} {}{}", n, noun, if n == 1 { "" } else { "s" });
+        format!(
+            "{}, {}, {}, {}, {} min read",
+            count(self.words, "word"),
+            count(self.characters, "character"),
+            count(self.lines, "line"),
+            count(self.headings, "heading"),
+            self.reading_minutes
+        )
+    }
+}
+
+impl Note {
+    /// Computes the length statistics of the note's content
+    pub fn stats(&self) -> NoteStats {
+        compute_stats(&self.content)
+    }
+}
+
+/// Computes the length statistics of Markdown content
+pub fn compute_stats(content: &str) -> NoteStats {
+    let mut stats = NoteStats {
+        characters: content.chars().count(),
+        lines: content.lines().count(),
+        ..NoteStats::default()
+    };
+
+    // Whether the last run of text ended inside a word
+    let mut in_word = false;
+
+    let options = Options::ENABLE_TASKLISTS | Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES;
+    let mut in_code_block = false;
+    for event in Parser::new_ext(content, options) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => in_code_block = false,
+            Event::Start(Tag::Heading { .. }) => stats.headings += 1,
+            Event::Text(text) | Event::Code(text) if !in_code_block => {
+                stats.words += count_words(&text, &mut in_word);
+            }
+            // Inline markup can split a word, as in `**bold**er`
+            Event::Start(
+                Tag::Emphasis
+                | Tag::Strong
+                | Tag::Strikethrough
+                | Tag::Link { .. }
+                | Tag::Image { .. },
+            )
+            | Event::End(
+                TagEnd::Emphasis
+                | TagEnd::Strong
+                | TagEnd::Strikethrough
+                | TagEnd::Link
+                | TagEnd::Image,
+            ) => {}
+            // Line breaks and block boundaries separate words even without
+            // whitespace
+            Event::SoftBreak | Event::HardBreak | Event::Start(_) | Event::End(_) => {
+                in_word = false
+            }
+            _ => {}
+        }
+    }
+
+    stats.reading_minutes = stats.words.div_ceil(WORDS_PER_MINUTE);
+    stats
+}
+
+/// Counts the words starting in a run of text
+fn count_words(text: &str, in_word: &mut bool) -> usize {
+    let mut words = 0;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            *in_word = false;
+        } else if !*in_word {
+            *in_word = true;
+            words += 1;
+        }
+    }
+    words
+}
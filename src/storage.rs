@@ -1,33 +1,159 @@
 use std::{
-    cmp::{Ordering, Reverse},
-    collections::{BinaryHeap, HashMap, HashSet},
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashMap, HashSet},
     fs::{self, File},
     io::Write,
     path::{Path, PathBuf},
-    sync::{mpsc as std_mpsc, Arc, Mutex},
-    time::{Duration, SystemTime},
+    sync::{
+        atomic::{AtomicU64, Ordering as AtomicOrdering},
+        mpsc as std_mpsc, Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use log::{debug, error, info, trace, warn};
 use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use tempfile::NamedTempFile;
-use tokio::sync::{mpsc, Mutex as TokioMutex};
+use tokio::sync::{broadcast, mpsc, Mutex as TokioMutex};
 use walkdir::WalkDir;
 use zip::{write::FileOptions, ZipArchive, ZipWriter};
 
 use crate::{
-    handle_fs_event, load_note_from_file, BackupScheduler, BackupSchedulerStatus, Config,
-    ConflictResolution, KbError, Note, NoteVersion, RestoreBackupSummary, Result,
+    apply_tag_changes, canonical_project_path, coalesce_fs_events, copy_dir_recursive,
+    decode_note_bytes, encode_note_json, ensure_same_filesystem, exact_score, extract_wiki_links,
+    fuzzy_score, handle_fs_event, index_note_aliases, index_note_structure, index_note_tags,
+    index_note_title, is_compressed_note, is_internal_entry, is_note_file,
+    is_unfinished_conversion, load_note_from_file, mime_type_for_path, normalize_alias,
+    normalize_tag, note_content_hash, note_file_as, note_file_paths, note_file_stem,
+    note_from_split, note_to_markdown, open_backend, paginate_notes, parse_deletion_record_name,
+    parse_legacy_backup_name, parse_note, parse_quarantine_name, parse_search_query,
+    quarantine_name, quarantine_reason_path, rank_related, salvage_legacy_note, salvage_note_json,
+    same_project_path, summarize_structure, toggle_task, unindex_note_aliases, unindex_note_tags,
+    unindex_note_title, unique_attachment_name, validate_notebook_path, validate_snapshot_name,
+    AccessState, AliasCollision, AliasIndex, ArchiveBackupInfo, Attachment, Audience, BackendKind,
+    BackendLoad, BackupChainState, BackupDiff, BackupDiffEntry, BackupInventory, BackupManifest,
+    BackupPreview, BackupPruneReport, BackupPushResult, BackupScheduler, BackupSchedulerStatus,
+    BatchReport, CacheStats, ChangedNote, Config, ConflictResolution, DeletedNote, DurabilityStats,
+    EffectiveTagPolicy, FileBackend, ForegroundActivity, ForegroundGuard, FsyncMode,
+    HistoryMigrationReport, ImportedLegacyBackup, IncrementalManifest, IntegrityReport, KbError,
+    LayoutIssue, LegacyBackupName, LegacyDisposition, LinkIndex, ListFilter, LoadReport,
+    MonthCount, Note, NoteBackupInfo, NoteBackupsInfo, NoteChanges, NoteEvent, NoteEventKind,
+    NoteFilter, NoteLink, NotePage, NoteRestoreAction, NoteRevision, NoteScope, NoteSize,
+    NoteSnapshot, NoteSort, NoteVersion, QuarantinedFile, ReindexLock, ReindexProgress,
+    RelatedNote, RestoreAction, RestoreBackupSummary, RestoreOptions, RestoreRollbackSummary,
+    Result, SanitizationPolicy, SearchExpr, SearchHit, SearchIndex, SearchMode, SearchRequest,
+    SearchResults, SearchScore, SkippedLegacyBackup, SqliteBackend, StagedRestoreSummary,
+    StorageBackend, StorageFormat, StorageSizes, StructureFilter, StructureIndex, StructureSummary,
+    TagCount, TagIndex, TagMatch, Task, TitleIndex, TrashedNote, VaultStatistics,
+    WebhookDispatcher, ARCHIVED_AT_KEY, ARCHIVED_TAG, ATTACHMENTS_DIR, CORRUPT_DIR,
+    CORRUPT_REASON_SUFFIX, DELETED_AT_KEY, LINK_INDEX_FILE, MAX_RELATED_CANDIDATES,
+    PURGE_JOURNAL_FILE, REINDEX_BATCH_SIZE, REINDEX_PAUSE, SEARCH_INDEX_DIR, SQLITE_DB_FILE,
+    TRASHED_ATTACHMENTS_SUFFIX, TRASH_DIR, WEBHOOK_DEAD_LETTER_FILE, WEBHOOK_DRAIN_TIMEOUT,
 };
 
+/// Directory (inside `notes_dir`) holding per-note snapshots
+pub const SNAPSHOTS_DIR: &str = ".snapshots";
+
+/// Directory (inside `notes_dir`) holding the revision history of each note
+pub const HISTORY_DIR: &str = ".history";
+
+/// Metadata key recording the directory a project note belongs to
+pub const PROJECT_PATH_KEY: &str = "project_path";
+
+/// Marker file placed in a project directory, containing the ID of its note
+pub const PROJECT_MARKER_FILE: &str = ".kbnote";
+
+/// A note file's note ID, path and the location its ID places it at
+type NoteFile = (String, PathBuf, PathBuf);
+
+/// Each note ID of a backup chain with the archive (by its position in the
+/// chain) and the entry holding the note's latest version
+type ChainNoteEntries = HashMap<String, (usize, String)>;
+
+/// Number of largest notes reported by [`NoteStorage::get_statistics`]
+pub const LARGEST_NOTES_SHOWN: usize = 10;
+
+/// Directory (inside `notes_dir`) holding sidecar state that must not touch note files
+pub const STATE_DIR: &str = ".state";
+
+/// File (inside [`STATE_DIR`]) recording read/unread state and view times
+const ACCESS_STATE_FILE: &str = "access.json";
+
+/// Directory (inside `backup_dir`) receiving legacy backup files after migration
+pub const LEGACY_ARCHIVE_DIR: &str = "legacy-archive";
+
+/// Entry (at the root of a full backup) describing its contents
+pub const BACKUP_MANIFEST_ENTRY: &str = "manifest.json";
+
+/// Entry (at the root of a backup) holding the effective configuration,
+/// without secrets
+pub const BACKUP_CONFIG_ENTRY: &str = "config.json";
+
+/// Layout version of the backups this version writes; restoring a backup
+/// of a newer layout is refused
+pub const BACKUP_FORMAT_VERSION: u32 = 1;
+
+/// File name prefix of the full backups written into the backup directory
+const FULL_BACKUP_PREFIX: &str = "kbnotes_backup_";
+
+/// File name prefix of the incremental backups written into the backup directory
+const INCREMENTAL_BACKUP_PREFIX: &str = "kbnotes_incremental_";
+
+/// How long shutdown waits for the final backup of `backup_on_shutdown`
+pub const SHUTDOWN_BACKUP_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Timestamp format in the file names of full and incremental backups
+const FULL_BACKUP_TIMESTAMP_FORMAT: &str = "%Y%m%d_%H%M%S";
+
+/// File in the backup directory tracking the incremental backup chain
+pub const BACKUP_STATE_FILE: &str = "kbnotes_backup_state.json";
+
+/// Suffix (after `<notes_dir>.`) of the directory a staged restore extracts into
+pub const STAGED_RESTORE_SUFFIX: &str = "restore-";
+
+/// Suffix of the notes directory kept aside by a staged restore
+pub const PRE_RESTORE_SUFFIX: &str = "pre-restore-";
+
+/// Suffix of the notes directory kept aside by a rollback
+pub const ROLLED_BACK_SUFFIX: &str = "rolled-back-";
+
+/// Number of notes read back from a staged restore before it is swapped in
+const RESTORE_VALIDATION_SAMPLE: usize = 20;
+
+/// Number of note events buffered per subscriber before it starts lagging
+pub const NOTE_EVENT_CAPACITY: usize = 256;
+
+/// How long the file watcher collects events after the first of a burst
+/// before handling them together
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
 /// Manages the storage, retrieval, and synchronization of notes.
 pub struct NoteStorage {
     /// Application configuration
     config: Config,
 
-    /// In-memory cache of notes, indexed by note ID
-    notes_cache: Arc<Mutex<HashMap<String, Note>>>,
+    /// In-memory cache of notes, indexed by note ID; readers share it and
+    /// only saves, deletions and reloads take it exclusively
+    notes_cache: Arc<RwLock<HashMap<String, Note>>>,
+
+    /// Alias lookup index, rebuilt from note data whenever notes are loaded
+    alias_index: Arc<Mutex<AliasIndex>>,
+
+    /// Title lookup index, kept alongside the alias index
+    title_index: Arc<Mutex<TitleIndex>>,
+
+    /// Tag lookup index, for tag queries without scanning every note
+    tag_index: Arc<Mutex<TagIndex>>,
+
+    /// Structure of each note's content, for `--has` filters
+    structure_index: Arc<Mutex<StructureIndex>>,
+
+    /// `[[wiki-links]]` between notes, for backlinks
+    link_index: Arc<Mutex<LinkIndex>>,
+
+    /// Full-text search index (`None` unless `use_search_index` is enabled)
+    search_index: Arc<Mutex<Option<SearchIndex>>>,
 
     /// File system watcher to detect changes to note files
     watcher: Option<RecommendedWatcher>,
@@ -37,6 +163,59 @@ pub struct NoteStorage {
 
     /// Backup scheduler for automated backups
     backup_scheduler: Arc<TokioMutex<BackupScheduler>>,
+
+    /// Layout of note files in `notes_dir`, which also holds history, trash,
+    /// snapshots and attachments whichever backend stores the notes
+    files: FileBackend,
+
+    /// Where notes are persisted, as chosen by the `backend` setting
+    backend: Arc<dyn StorageBackend>,
+
+    /// Broadcast channel announcing note changes to subscribers
+    events: broadcast::Sender<NoteEvent>,
+
+    /// Read/unread state and view times, persisted in a sidecar file
+    access_state: Arc<Mutex<AccessState>>,
+
+    /// Maps note IDs to file and backup entry names
+    id_policy: Arc<dyn SanitizationPolicy>,
+
+    /// Delivers note changes to the configured webhooks (`None` without webhooks)
+    webhooks: Option<Arc<WebhookDispatcher>>,
+
+    /// Hit, miss and eviction counts of the notes cache, shared by all clones
+    cache_counters: Arc<CacheCounters>,
+
+    /// IDs of notes whose cached copy may not match what is stored, written
+    /// back by the flush on shutdown
+    dirty_notes: Arc<Mutex<HashSet<String>>>,
+
+    /// Foreground commands running, which background reindexing waits for
+    foreground: ForegroundActivity,
+
+    /// Progress of the background reindex (`None` while none runs)
+    reindex_progress: Arc<Mutex<Option<ReindexProgress>>>,
+}
+
+/// Counters behind [`CacheStats`]
+#[derive(Default)]
+struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// A per-note file in the backup directory
+struct NoteBackupFile {
+    path: PathBuf,
+    /// ID of the note, decoded from the file name
+    note_id: String,
+    /// When the file was written, by its name
+    timestamp: DateTime<Utc>,
+    /// Orders files written within the same second
+    modified: SystemTime,
+    /// Whether this is a deletion record rather than a JSON backup
+    record: bool,
 }
 
 impl NoteStorage {
@@ -57,18 +236,48 @@ impl NoteStorage {
     /// A Result containing the new NoteStorage instance or an error
     pub fn new(config: Config) -> Self {
         // Initialize empty notes cache
-        let notes_cache = Arc::new(Mutex::new(HashMap::new()));
+        let notes_cache = Arc::new(RwLock::new(HashMap::new()));
 
         // Initialize scheduler
         let backup_scheduler = BackupScheduler::new(config.clone());
 
+        let id_policy = config.id_policy.policy();
+        let files = FileBackend::new(config.clone());
+        let backend = open_backend(config.backend, &config, &files);
+
+        let webhooks = WebhookDispatcher::start(
+            config.webhooks.clone(),
+            config
+                .notes_dir
+                .join(STATE_DIR)
+                .join(WEBHOOK_DEAD_LETTER_FILE),
+        )
+        .map(Arc::new);
+        let foreground = ForegroundActivity::in_dir(config.notes_dir.join(SEARCH_INDEX_DIR));
+
         // Create the storage instance
         Self {
             config,
             notes_cache,
+            alias_index: Arc::new(Mutex::new(AliasIndex::new())),
+            title_index: Arc::new(Mutex::new(TitleIndex::new())),
+            tag_index: Arc::new(Mutex::new(TagIndex::new())),
+            structure_index: Arc::new(Mutex::new(StructureIndex::new())),
+            link_index: Arc::new(Mutex::new(LinkIndex::default())),
+            search_index: Arc::new(Mutex::new(None)),
             watcher: None,
             initialized: false,
             backup_scheduler: Arc::new(TokioMutex::new(backup_scheduler)),
+            files,
+            backend,
+            events: broadcast::channel(NOTE_EVENT_CAPACITY).0,
+            access_state: Arc::new(Mutex::new(AccessState::default())),
+            id_policy,
+            webhooks,
+            cache_counters: Arc::new(CacheCounters::default()),
+            dirty_notes: Arc::new(Mutex::new(HashSet::new())),
+            foreground,
+            reindex_progress: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -117,6 +326,15 @@ impl NoteStorage {
         self.load_notes()?;
         info!("Loaded notes successfully");
 
+        // Notes past the trash retention period go for good
+        match self.purge_expired_trash() {
+            Ok(purged) if !purged.is_empty() => {
+                info!("Purged {} note(s) from the trash", purged.len())
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to purge the trash: {}", e),
+        }
+
         {
             let mut scheduler = self.backup_scheduler.lock().await;
             scheduler.set_storage(Arc::clone(&storage)); // Set weak reference
@@ -140,10 +358,12 @@ impl NoteStorage {
 
     /// Loads all notes from disk into the in-memory cache
     ///
+    /// Note files that can't be read are moved to [`CORRUPT_DIR`].
+    ///
     /// # Returns
     ///
-    /// The number of notes loaded in case of success or an error
-    pub fn load_notes(&mut self) -> Result<usize> {
+    /// The number of notes loaded and the files that failed, or an error
+    pub fn load_notes(&mut self) -> Result<LoadReport> {
         // Ensure notes directory exists
         if !self.config.notes_dir.exists() {
             fs::create_dir_all(&self.config.notes_dir).map_err(KbError::Io)?;
@@ -151,138 +371,187 @@ impl NoteStorage {
                 "Created notes directory: {}",
                 self.config.notes_dir.display()
             );
-            return Ok(0); // No notes to load from an empty directory
+            self.open_search_index(&HashMap::new())?;
+            self.initialized = true;
+            return Ok(LoadReport::default()); // No notes to load from an empty directory
         }
 
-        // Pre-allocate a HashMap to hold all notes before acquiring the lock
-        let mut notes_buffer = HashMap::with_capacity(100); // Initial capacity estimation
-        let mut load_errors = Vec::new();
-
-        // Walk the notes directory and load all notes
-        for entry in WalkDir::new(&self.config.notes_dir)
-            .min_depth(1) // Skip the root directory
+        let BackendLoad {
+            notes,
+            failed: load_errors,
+        } = self.backend.load_all()?;
+        let notes_buffer: HashMap<String, Note> = notes
             .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            let path = entry.path();
+            .map(|note| (note.id.clone(), note))
+            .collect();
 
-            // Only process JSON files
-            if path.is_file() && path.extension().is_some_and(|ext| ext == "json") {
-                match load_note_from_file(path) {
-                    Ok(note) => {
-                        // Add to our temporary buffer instead of directly to cache
-                        notes_buffer.insert(note.id.clone(), note);
-                    }
-                    Err(e) => {
-                        // Collect errors but continue processing
-                        let error_msg =
-                            format!("Failed to load note from {}: {}", path.display(), e);
-                        warn!("{}", error_msg);
-                        load_errors.push((path.to_path_buf(), error_msg));
-                    }
-                }
+        let notes_count = notes_buffer.len();
+
+        // Rebuild the alias index from the loaded note data
+        {
+            let mut index =
+                self.alias_index
+                    .lock()
+                    .map_err(|_| KbError::LockAcquisitionFailed {
+                        message: "Failed to acquire lock on alias index during load operation"
+                            .to_string(),
+                    })?;
+            index.clear();
+            for note in notes_buffer.values() {
+                index_note_aliases(&mut index, note);
             }
         }
 
-        let notes_count = notes_buffer.len();
+        // Rebuild the title index the same way
+        {
+            let mut index =
+                self.title_index
+                    .lock()
+                    .map_err(|_| KbError::LockAcquisitionFailed {
+                        message: "Failed to acquire lock on title index during load operation"
+                            .to_string(),
+                    })?;
+            index.clear();
+            for note in notes_buffer.values() {
+                index_note_title(&mut index, note);
+            }
+        }
 
-        // Now acquire the lock only once to update the cache with all loaded notes
-        if notes_count > 0 {
-            // Minimize time holding the lock by using a single batch operation
-            match self.notes_cache.lock() {
-                Ok(mut cache) => {
-                    // Use extend to efficiently add all items at once
-                    cache.clear(); // Clear existing cache
-                    cache.reserve(notes_count); // Pre-allocate capacity
-                    cache.extend(notes_buffer);
+        // And the tag index
+        {
+            let mut index = self
+                .tag_index
+                .lock()
+                .map_err(|_| KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on tag index during load operation"
+                        .to_string(),
+                })?;
+            index.clear();
+            for note in notes_buffer.values() {
+                index_note_tags(&mut index, note);
+            }
+        }
 
-                    info!("Loaded {} notes into cache", notes_count);
-                }
-                Err(_) => {
-                    return Err(KbError::LockAcquisitionFailed {
-                        message: "Failed to acquire lock on notes cache during load operation"
+        // Rebuild the structure index the same way, parsing each note once
+        {
+            let mut index =
+                self.structure_index
+                    .lock()
+                    .map_err(|_| KbError::LockAcquisitionFailed {
+                        message: "Failed to acquire lock on structure index during load operation"
                             .to_string(),
-                    });
+                    })?;
+            index.clear();
+            for note in notes_buffer.values() {
+                index_note_structure(&mut index, note);
+            }
+        }
+
+        // Rebuild the link index, and write it out for external tools
+        {
+            let mut index = self
+                .link_index
+                .lock()
+                .map_err(|_| KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on link index during load operation"
+                        .to_string(),
+                })?;
+            index.clear();
+            for note in notes_buffer.values() {
+                index.index_note(note);
+            }
+        }
+        if let Err(e) = self.save_link_index() {
+            warn!("Failed to save link index: {}", e);
+        }
+
+        // Bring the search index up to date, rebuilding it when it is corrupt
+        self.open_search_index(&notes_buffer)?;
+
+        // Restore read/unread state, forgetting notes that no longer exist
+        if let Err(e) = self.load_access_state(&notes_buffer) {
+            warn!("Failed to load note access state: {}", e);
+        }
+
+        // Now acquire the lock only once to update the cache with all loaded notes.
+        // The cache is replaced even when nothing was loaded, so reloading an
+        // emptied directory does not keep stale notes around.
+        match self.notes_cache.write() {
+            Ok(mut cache) => {
+                // Use extend to efficiently add all items at once
+                cache.clear(); // Clear existing cache
+                cache.reserve(notes_count); // Pre-allocate capacity
+                cache.extend(notes_buffer);
+                if let Ok(mut dirty) = self.dirty_notes.lock() {
+                    dirty.clear();
                 }
+
+                info!("Loaded {} notes into cache", notes_count);
+            }
+            Err(_) => {
+                return Err(KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on notes cache during load operation"
+                        .to_string(),
+                });
             }
         }
 
-        // Handle any load errors
-        if !load_errors.is_empty() {
+        // Move unreadable files aside so they don't go unnoticed on every load;
+        // rows of the database stay where they are
+        if !load_errors.is_empty() && self.backend.kind() != BackendKind::Files {
+            error!(
+                "{} unreadable note(s) in {}",
+                load_errors.len(),
+                self.config.notes_dir.join(SQLITE_DB_FILE).display()
+            );
+        } else if !load_errors.is_empty() {
+            let quarantined = load_errors
+                .iter()
+                .filter(|(path, reason)| match self.quarantine_file(path, reason) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        error!("Failed to quarantine {}: {}", path.display(), e);
+                        false
+                    }
+                })
+                .count();
             error!(
-                "Encountered {} errors while loading notes",
-                load_errors.len()
+                "Quarantined {} of {} unreadable note file(s) in {}, run 'kbnotes doctor --corrupt' to recover them",
+                quarantined,
+                load_errors.len(),
+                self.config.notes_dir.join(CORRUPT_DIR).display()
             );
-            // Could return errors as part of a more detailed result if needed
         }
 
         self.initialized = true;
-        Ok(notes_count)
+        Ok(LoadReport {
+            loaded: notes_count,
+            failed: load_errors,
+        })
     }
 
     /// Saves a note to storage using atomic operations to prevent data corruption
     pub fn save_note(&self, note: &Note) -> Result<()> {
         info!("Saving note: {}", note.id);
 
-        // Generate the file path based on the note id
-        let file_path = self.get_note_path(&note.id);
-        debug!("File path for note: {}", file_path.display());
-
-        // Ensure the parent directory exists
-        if let Some(parent) = file_path.parent() {
-            if !parent.exists() {
-                debug!("Creating parent directory: {}", parent.display());
-                fs::create_dir_all(parent).map_err(|e| {
-                    error!("Failed to create directory {}: {}", parent.display(), e);
-                    KbError::Io(e)
-                })?;
-            }
-        }
-
-        // Create a temporary file in the same directory (for atomic operation)
-        let dir = file_path.parent().unwrap_or_else(|| Path::new("."));
-        debug!("Creating temporary file in directory: {}", dir.display());
-        let mut temp_file = NamedTempFile::new_in(dir).map_err(|e| {
-            error!("Failed to create temporary file: {}", e);
-            KbError::Io(e)
-        })?;
-
-        // Serialize the note to JSON
-        trace!("Serializing note to JSON");
-        let json = serde_json::to_string_pretty(note).map_err(|e| {
-            error!("Failed to serialize note: {}", e);
-            KbError::Serialization(e)
-        })?;
-
-        // Write to the temporary file
-        trace!("Writing to temporary file");
-        temp_file.write_all(json.as_bytes()).map_err(|e| {
-            error!("Failed to write to temporary file: {}", e);
-            KbError::Io(e)
-        })?;
-
-        temp_file.flush().map_err(|e| {
-            error!("Failed to flush temporary file: {}", e);
-            KbError::Io(e)
-        })?;
-
-        // Atomically move the temporary file to the target location
-        debug!("Performing atomic move of temporary file to final location");
-        temp_file.persist(&file_path).map_err(|e| {
-            error!(
-                "Failed to persist file {}: {}",
-                file_path.display(),
-                e.error
-            );
-            KbError::Io(e.error)
-        })?;
+        self.check_id_collision(note)?;
+        self.persist_note(note)?;
 
         // If we're initialized, update the cache as well
+        let mut event_kind = NoteEventKind::Created;
         if self.initialized {
             debug!("Updating note in cache");
-            match self.notes_cache.lock() {
+            self.index_aliases(note);
+            self.index_title(note);
+            self.index_tags(note);
+            self.index_structure(note);
+            self.index_links(note);
+            self.index_search(note);
+            match self.notes_cache.write() {
                 Ok(mut cache) => {
-                    cache.insert(note.id.clone(), note.clone());
+                    if cache.insert(note.id.clone(), note.clone()).is_some() {
+                        event_kind = NoteEventKind::Updated;
+                    }
                     trace!("Cache updated successfully");
                 }
                 Err(e) => {
@@ -305,671 +574,5354 @@ impl NoteStorage {
             }
         }
 
+        // New notes start out unread
+        if event_kind == NoteEventKind::Created {
+            if let Err(e) = self.mark_notes_unread(std::slice::from_ref(&note.id)) {
+                warn!("Failed to mark note {} as unread: {}", note.id, e);
+            }
+        }
+
+        self.publish_event(event_kind, note);
+
         info!("Note saved successfully: {}", note.id);
         Ok(())
     }
 
-    /// Helper method to get the file path for a note
-    fn get_note_path(&self, note_id: &str) -> PathBuf {
-        // Create path with structure: notes_dir/first_2_chars_of_id/note_id.json
-        let id_prefix = if note_id.len() >= 2 {
-            &note_id[0..2]
-        } else {
-            note_id
-        };
-
-        self.config
-            .notes_dir
-            .join(id_prefix)
-            .join(format!("{}.json", note_id))
-    }
+    /// Saves several notes at once, for imports and restores
+    ///
+    /// Each note is written as by [`NoteStorage::save_note`], but the cache is
+    /// updated under a single lock and the search index is saved once. No
+    /// per-note backups are written, even with `auto_backup` enabled: the
+    /// batch is left to the next full backup. A note that fails to save
+    /// doesn't stop the others.
+    ///
+    /// # Arguments
+    ///
+    /// * `notes` - The notes to save
+    ///
+    /// # Returns
+    ///
+    /// Which notes were saved and why the others weren't
+    pub fn save_notes(&self, notes: &[Note]) -> Result<BatchReport> {
+        info!("Saving a batch of {} notes", notes.len());
+        let mut report = BatchReport::default();
 
-    /// Creates a backup of the note in the backup directory
-    fn backup_note(&self, note: &Note) -> Result<()> {
-        debug!("Creating backup for note: {}", note.id);
-        // Create a timestamped backup path
-        let timestamp = Utc::now().timestamp();
+        let mut checked = Vec::with_capacity(notes.len());
+        for note in notes {
+            match self.check_id_collision(note) {
+                Ok(()) => checked.push(note),
+                Err(e) => {
+                    warn!("Failed to save note {}: {}", note.id, e);
+                    report.failed.push((note.id.clone(), e.to_string()));
+                }
+            }
+        }
 
-        let backup_path = self
-            .config
-            .backup_dir
-            .join(format!("{}_{}.json", note.id, timestamp));
+        // Written in one go, so that directories are synced once per batch
+        let mut saved = Vec::with_capacity(checked.len());
+        for (note, result) in checked.iter().zip(self.persist_notes(&checked)) {
+            match result {
+                Ok(()) => saved.push(*note),
+                Err(e) => {
+                    warn!("Failed to save note {}: {}", note.id, e);
+                    report.failed.push((note.id.clone(), e.to_string()));
+                }
+            }
+        }
 
-        debug!("Backup path: {}", backup_path.display());
+        // Index the saved notes, saving the search index and taking the cache
+        // lock only once for the whole batch
+        let mut kinds = vec![NoteEventKind::Created; saved.len()];
+        if self.initialized {
+            for note in &saved {
+                self.index_aliases(note);
+                self.index_title(note);
+                self.index_tags(note);
+                self.index_structure(note);
+                self.index_links(note);
+            }
+            match self.search_index.lock() {
+                Ok(mut index) => update_search_index(&mut index, |index| {
+                    // Every note is indexed, so no short-circuiting `any`
+                    saved.iter().filter(|note| index.index_note(note)).count() > 0
+                }),
+                Err(e) => warn!("Failed to acquire lock for search index update: {}", e),
+            }
+            match self.notes_cache.write() {
+                Ok(mut cache) => {
+                    for (note, kind) in saved.iter().zip(kinds.iter_mut()) {
+                        if cache.insert(note.id.clone(), (*note).clone()).is_some() {
+                            *kind = NoteEventKind::Updated;
+                        }
+                    }
+                    trace!("Cache updated successfully");
+                }
+                Err(e) => {
+                    // Continue since the files are saved already
+                    warn!("Failed to acquire lock for cache update: {}", e);
+                }
+            }
+        }
 
-        // Ensure backup directory exists
-        if !self.config.backup_dir.exists() {
-            debug!(
-                "Creating backup directory: {}",
-                self.config.backup_dir.display()
+        // New notes start out unread
+        let created: Vec<String> = saved
+            .iter()
+            .zip(&kinds)
+            .filter(|(_, kind)| **kind == NoteEventKind::Created)
+            .map(|(note, _)| note.id.clone())
+            .collect();
+        if let Err(e) = self.mark_notes_unread(&created) {
+            warn!(
+                "Failed to mark {} new notes as unread: {}",
+                created.len(),
+                e
             );
-            fs::create_dir_all(&self.config.backup_dir).map_err(|e| {
-                error!("Failed to create backup directory: {}", e);
-                KbError::Io(e)
-            })?;
         }
 
-        // Write the note to the backup file
-        trace!("Serializing note for backup");
-        let json = serde_json::to_string_pretty(note).map_err(|e| {
-            error!("Failed to serialize note for backup: {}", e);
-            KbError::Serialization(e)
-        })?;
+        for (note, kind) in saved.into_iter().zip(kinds) {
+            self.publish_event(kind, note);
+            report.succeeded.push(note.id.clone());
+        }
 
-        trace!("Writing backup file");
-        fs::write(&backup_path, json).map_err(|e| {
-            error!(
-                "Failed to write backup file {}: {}",
-                backup_path.display(),
-                e
-            );
-            KbError::Io(e)
-        })?;
+        info!("Saved {} of {} notes", report.succeeded.len(), notes.len());
+        Ok(report)
+    }
 
-        info!("Backup created successfully at: {}", backup_path.display());
+    /// Makes sure a new note doesn't take over the file of another one, which
+    /// can happen when IDs differ only in case on a case-insensitive filesystem
+    fn check_id_collision(&self, note: &Note) -> Result<()> {
+        if self.backend.kind() != BackendKind::Files || self.get_note(&note.id).is_some() {
+            return Ok(());
+        }
+        let file_path = self.files.note_path(&note.id);
+        if !file_path.exists() {
+            return Ok(());
+        }
+        if let Ok(existing) = load_note_from_file(&file_path) {
+            if existing.id != note.id {
+                error!(
+                    "Note ID {} collides with note {} at {}",
+                    note.id,
+                    existing.id,
+                    file_path.display()
+                );
+                return Err(KbError::IdCollision {
+                    id: note.id.clone(),
+                    existing: existing.id,
+                    path: file_path,
+                });
+            }
+        }
         Ok(())
     }
 
-    /// Restores a single note from its most recent backup
-    ///
-    /// # Arguments
+    /// Subscribes to note change events
     ///
-    /// * `note_id` - The ID of the note to restore
+    /// Every create, update and delete made through this storage is announced,
+    /// as well as changes picked up by the file watcher (flagged as external).
+    /// A subscriber that falls more than [`NOTE_EVENT_CAPACITY`] events behind
+    /// loses the oldest ones and receives `RecvError::Lagged` on its next read.
     ///
     /// # Returns
     ///
-    /// The restored note in case of success or an error
-    pub fn restore_note_from_backup(&self, note_id: &str) -> Result<Note> {
-        // Construct the backup directory path for this note
-        let note_backup_dir = self.config.backup_dir.join(note_id);
+    /// A receiver yielding every event published after this call
+    pub fn subscribe_events(&self) -> broadcast::Receiver<NoteEvent> {
+        self.events.subscribe()
+    }
 
-        if !note_backup_dir.exists() {
-            let error = format!("No backup directory found for note {}", note_id);
-            error!("{}", error);
-            return Err(KbError::BackupFailed { message: error });
-        }
+    /// Returns a read-only view of the notes `audience` may see, according to
+    /// the `server.visibility` configuration
+    pub fn scope(&self, audience: Audience) -> NoteScope {
+        NoteScope::new(self.clone(), self.config.server.clone(), audience)
+    }
 
-        // Find all backup files for this note
-        let mut backup_files: Vec<_> = WalkDir::new(&note_backup_dir)
-            .max_depth(1)
-            .into_iter()
-            .filter_map(|entry| entry.ok())
-            .filter(|entry| {
-                entry.path().is_file() && entry.path().extension().is_some_and(|ext| ext == "json")
-            })
-            .collect();
+    /// Announces a change made through this storage to event subscribers and
+    /// queues it for the matching webhooks
+    fn publish_event(&self, kind: NoteEventKind, note: &Note) {
+        let updated_at = (kind != NoteEventKind::Deleted).then_some(note.updated_at);
+
+        // Sending only fails when nobody is subscribed, which is fine
+        let _ = self.events.send(NoteEvent {
+            kind,
+            note_id: note.id.clone(),
+            updated_at,
+            external: false,
+        });
 
-        if backup_files.is_empty() {
-            let error = format!("No backup files found for note {}", note_id);
-            error!("{}", error);
-            return Err(KbError::BackupFailed { message: error });
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.dispatch(kind, note);
         }
+    }
 
-        // Sort backups by modification time (newest first)
-        backup_files.sort_by_key(|entry| {
-            fs::metadata(entry.path())
-                .and_then(|meta| meta.modified())
-                .unwrap_or(std::time::SystemTime::UNIX_EPOCH)
-        });
-        backup_files.reverse(); // Now newest is first
-
-        // Get the most recent backup
-        let latest_backup_path = backup_files[0].path();
+    /// Helper method to get the path of a note inside a backup archive
+    fn get_note_entry_name(&self, note_id: &str, format: StorageFormat) -> String {
+        let (id_prefix, file_name) = self.files.note_file_name(note_id, format);
+        format!("{}/{}", id_prefix, file_name)
+    }
 
-        // Read and deserialize the backup file
-        let backup_content = fs::read_to_string(latest_backup_path).map_err(|e| {
-            let error = format!("No backup files found for note {}", note_id);
-            error!("{}", error);
-            KbError::BackupFailed {
+    /// Rewrites every note file in `format` and makes it the format new and
+    /// updated notes are written in
+    ///
+    /// Each note is written to its new file before the old one is removed, so
+    /// an interrupted run leaves every note readable; running it again
+    /// finishes the conversion.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The storage format to convert to
+    ///
+    /// # Returns
+    ///
+    /// The number of note files converted
+    pub fn migrate_storage_format(&mut self, format: StorageFormat) -> Result<usize> {
+        if self.backend.kind() != BackendKind::Files {
+            return Err(KbError::InvalidArgument {
                 message: format!(
-                    "Failed to read backup file {}: {}",
-                    latest_backup_path.display(),
-                    e
+                    "Notes are stored in the {} backend, which has no note files to convert",
+                    self.backend.kind()
                 ),
+            });
+        }
+        self.config.storage_format = format;
+        self.files = self.files.with_format(format);
+        self.backend = open_backend(BackendKind::Files, &self.config, &self.files);
+
+        let mut files = Vec::new();
+        for entry in WalkDir::new(&self.config.notes_dir)
+            .min_depth(1)
+            .into_iter()
+            .filter_entry(|e| !is_internal_entry(e))
+            .filter_map(|e| e.ok())
+        {
+            let path = entry.path();
+            let source_format = StorageFormat::of_path(path);
+            if path.is_file()
+                && is_note_file(path)
+                && (source_format.is_some_and(|f| f != format)
+                    || (source_format == Some(StorageFormat::Split)
+                        && is_unfinished_conversion(path)))
+            {
+                files.push(path.to_path_buf());
             }
-        })?;
+        }
 
-        let restored_note: Note = serde_json::from_str(&backup_content)?;
+        let mut converted = 0;
+        for path in files {
+            let note = load_note_from_file(&path)?;
+            let target = self.files.note_path(&note.id);
+            let sources = note_file_paths(&path);
+
+            // Of two copies of the same note, the newer one wins; a split
+            // note's content file is no copy of a Markdown note it becomes
+            let newer_exists = target.exists()
+                && !sources.contains(&target)
+                && load_note_from_file(&target)
+                    .is_ok_and(|existing| existing.updated_at >= note.updated_at);
+            if !newer_exists {
+                self.files.write_note_files(&note)?;
+            }
+
+            let written = note_file_paths(&target);
+            for source in sources {
+                if source.exists() && !written.contains(&source) {
+                    fs::remove_file(&source)?;
+                    self.files.own_changes().record(&source);
+                }
+            }
+            debug!("Converted note {} to {}", note.id, format);
+            converted += 1;
+        }
+
+        info!("Converted {} note files to {}", converted, format);
+        Ok(converted)
+    }
+
+    /// Moves every note into another backend and makes it the one notes are
+    /// stored in
+    ///
+    /// The notes are copied before anything is removed: the old backend's
+    /// files or database are left in place, so an interrupted run loses
+    /// nothing and running it again finishes the move. Notes the target
+    /// holds but the source doesn't, left over from an earlier migration,
+    /// are removed from the target.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The backend to move the notes to
+    ///
+    /// # Returns
+    ///
+    /// The number of notes moved, or an error when the vault already uses
+    /// `kind` or some notes can't be read
+    pub fn migrate_backend(&mut self, kind: BackendKind) -> Result<usize> {
+        if self.backend.kind() == kind {
+            return Err(KbError::InvalidArgument {
+                message: format!("Notes are already stored in the {} backend", kind),
+            });
+        }
+
+        let source = self.backend.load_all()?;
+        if !source.failed.is_empty() {
+            return Err(KbError::ApplicationError {
+                message: format!(
+                    "{} note(s) can't be read, run 'kbnotes doctor' before migrating: {}",
+                    source.failed.len(),
+                    source
+                        .failed
+                        .iter()
+                        .map(|(path, e)| format!("{} ({})", path.display(), e))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            });
+        }
+
+        let target = open_backend(kind, &self.config, &self.files);
+        for note in &source.notes {
+            target.save(note)?;
+        }
+        let moved: HashSet<&str> = source.notes.iter().map(|note| note.id.as_str()).collect();
+        for id in target.list_ids()? {
+            if !moved.contains(id.as_str()) {
+                debug!("Removing stale note {} from the {} backend", id, kind);
+                target.delete(&id)?;
+            }
+        }
+
+        self.backend.release();
+        self.backend = target;
+        self.config.backend = kind;
+        info!("Moved {} notes to the {} backend", source.notes.len(), kind);
+        Ok(source.notes.len())
+    }
+
+    /// Returns the backend notes are stored in
+    pub fn backend_kind(&self) -> BackendKind {
+        self.backend.kind()
+    }
+
+    /// Measures how much space notes take in memory form and on disk
+    ///
+    /// # Returns
+    ///
+    /// Logical (serialized JSON) and on-disk sizes of all note files, or of
+    /// the database with the SQLite backend
+    pub fn storage_sizes(&self) -> Result<StorageSizes> {
+        let mut sizes = StorageSizes::default();
+        // Serialize under the read lock rather than cloning every note, and
+        // read the files once it is released
+        let logical: Vec<(String, u64)> = {
+            let cache = self
+                .notes_cache
+                .read()
+                .map_err(|_| KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on notes cache".to_string(),
+                })?;
+            cache
+                .values()
+                .map(|note| {
+                    Ok((
+                        note.id.clone(),
+                        serde_json::to_string_pretty(note)?.len() as u64,
+                    ))
+                })
+                .collect::<Result<_>>()?
+        };
+        if self.backend.kind() != BackendKind::Files {
+            let database = self.config.notes_dir.join(SQLITE_DB_FILE);
+            sizes.notes = logical.len();
+            sizes.logical_bytes = logical.iter().map(|(_, bytes)| bytes).sum();
+            sizes.disk_bytes = fs::metadata(&database).map_or(0, |metadata| metadata.len());
+            return Ok(sizes);
+        }
+        for (id, logical_bytes) in logical {
+            let path = self.files.find_note_path(&id);
+            let Ok(bytes) = fs::read(&path) else {
+                warn!("Note file missing for {}: {}", id, path.display());
+                continue;
+            };
+            // The content file of a split note
+            let companions: u64 = note_file_paths(&path)[1..]
+                .iter()
+                .filter_map(|path| fs::metadata(path).ok())
+                .map(|metadata| metadata.len())
+                .sum();
+
+            sizes.notes += 1;
+            sizes.logical_bytes += logical_bytes;
+            sizes.disk_bytes += bytes.len() as u64 + companions;
+            if is_compressed_note(&bytes) {
+                sizes.compressed_notes += 1;
+            }
+        }
+        Ok(sizes)
+    }
+
+    /// Computes aggregate statistics of the vault
+    ///
+    /// The cache is scanned once under its lock and only the counts, and the
+    /// IDs and titles of the largest notes, are copied out.
+    ///
+    /// # Returns
+    ///
+    /// Note, tag and month counts, content and backup sizes, and the
+    /// [`LARGEST_NOTES_SHOWN`] largest notes
+    pub fn get_statistics(&self) -> Result<VaultStatistics> {
+        let mut stats = VaultStatistics::default();
+        let mut per_month: BTreeMap<String, usize> = BTreeMap::new();
+        let mut largest: BinaryHeap<Reverse<(u64, NoteSize)>> = BinaryHeap::new();
+        stats.unread_notes = self.unread_count()?;
+        {
+            let cache = self
+                .notes_cache
+                .read()
+                .map_err(|_| KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on notes cache".to_string(),
+                })?;
+            for note in cache.values() {
+                let bytes = note.content.len() as u64;
+                stats.total_notes += 1;
+                stats.content_bytes += bytes;
+                if note.tags.is_empty() {
+                    stats.untagged_notes += 1;
+                }
+                let month = note
+                    .created_at
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m");
+                *per_month.entry(month.to_string()).or_default() += 1;
+
+                // Only the largest notes so far keep a copy of their ID and title
+                let smallest_kept = largest.peek().map(|Reverse((bytes, _))| *bytes);
+                if largest.len() < LARGEST_NOTES_SHOWN || smallest_kept <= Some(bytes) {
+                    largest.push(Reverse((
+                        bytes,
+                        NoteSize {
+                            id: note.id.clone(),
+                            title: note.title.clone(),
+                            bytes,
+                        },
+                    )));
+                    if largest.len() > LARGEST_NOTES_SHOWN {
+                        largest.pop();
+                    }
+                }
+            }
+        }
+
+        stats.notes_per_tag = self
+            .list_tags()?
+            .into_iter()
+            .map(|(tag, notes)| TagCount { tag, notes })
+            .collect();
+        stats.notes_per_month = per_month
+            .into_iter()
+            .map(|(month, notes)| MonthCount { month, notes })
+            .collect();
+        stats.largest_notes = largest
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((_, size))| size)
+            .collect();
+        stats.backup_bytes = WalkDir::new(&self.config.backup_dir)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .filter_map(|entry| entry.metadata().ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        Ok(stats)
+    }
+
+    /// Rewrites note files whose compression does not match `threshold`
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold` - Compress notes larger than this many bytes, or
+    ///   decompress every note when `None`
+    ///
+    /// # Returns
+    ///
+    /// The number of note files rewritten
+    pub fn rewrite_note_files(&self, threshold: Option<u64>) -> Result<usize> {
+        // The database stores notes uncompressed
+        if self.backend.kind() != BackendKind::Files {
+            return Ok(0);
+        }
+        let mut rewritten = 0;
+        for note in self.get_all_notes()? {
+            // Only JSON note files are ever compressed
+            let path = self.files.find_note_path(&note.id);
+            if StorageFormat::of_path(&path) != Some(StorageFormat::Json) {
+                continue;
+            }
+            let current = fs::read(&path)?;
+            let bytes = encode_note_json(serde_json::to_string_pretty(&note)?, threshold)?;
+            if is_compressed_note(&current) == is_compressed_note(&bytes) {
+                continue;
+            }
+
+            let dir = path.parent().unwrap_or_else(|| Path::new("."));
+            let mut temp_file = NamedTempFile::new_in(dir)?;
+            temp_file.write_all(&bytes)?;
+            temp_file.flush()?;
+            self.files.sync_file_before_persist(temp_file.as_file())?;
+            temp_file.persist(&path).map_err(|e| KbError::Io(e.error))?;
+            self.files.own_changes().record(&path);
+            self.files.sync_dir_after_persist(&path)?;
+
+            debug!(
+                "Rewrote note {} {}",
+                note.id,
+                if is_compressed_note(&bytes) {
+                    "compressed"
+                } else {
+                    "uncompressed"
+                }
+            );
+            rewritten += 1;
+        }
+
+        info!("Rewrote {} note files", rewritten);
+        Ok(rewritten)
+    }
+
+    /// Checks every note file against the configured ID policy
+    ///
+    /// # Returns
+    ///
+    /// The note files that are not where the policy places them, or whose
+    /// location is shared with another note
+    pub fn check_layout(&self) -> Result<Vec<LayoutIssue>> {
+        let (files, unreadable) = self.scan_note_files();
+        for (path, e) in unreadable {
+            warn!("Skipping unreadable note file {}: {}", path.display(), e);
+        }
+        Ok(layout_issues(files))
+    }
+
+    /// Reads the ID of every note file in the vault
+    ///
+    /// # Returns
+    ///
+    /// Each readable file's note ID, path and expected location, and the
+    /// files that could not be parsed along with why
+    fn scan_note_files(&self) -> (Vec<NoteFile>, Vec<(PathBuf, KbError)>) {
+        let mut files = Vec::new();
+        let mut unreadable = Vec::new();
+        if self.backend.kind() != BackendKind::Files {
+            return (files, unreadable);
+        }
+        for entry in WalkDir::new(&self.config.notes_dir)
+            .min_depth(1)
+            .into_iter()
+            .filter_entry(|e| !is_internal_entry(e))
+            .filter_map(|e| e.ok())
+        {
+            let path = entry.path();
+            let Some(format) =
+                StorageFormat::of_path(path).filter(|_| path.is_file() && is_note_file(path))
+            else {
+                continue;
+            };
+
+            // Placement only: converting between formats is `migrate_storage_format`'s job
+            match load_note_from_file(path) {
+                Ok(note) => {
+                    let expected = self.files.note_path_in(&note.id, format);
+                    files.push((note.id, path.to_path_buf(), expected));
+                }
+                Err(e) => unreadable.push((path.to_path_buf(), e)),
+            }
+        }
+
+        (files, unreadable)
+    }
+
+    /// Checks the vault for files and cache entries that don't agree
+    ///
+    /// # Returns
+    ///
+    /// Note files that can't be parsed, note files that are not where their
+    /// ID places them, and cached notes whose file (or database row) is gone
+    pub fn verify_integrity(&self) -> Result<IntegrityReport> {
+        let (files, unreadable) = self.scan_note_files();
+        let stored = match self.backend.kind() {
+            BackendKind::Files => Vec::new(),
+            _ => self.backend.list_ids()?,
+        };
+        // A misplaced file still backs its note
+        let on_disk: HashSet<&str> = files
+            .iter()
+            .map(|(id, _, _)| id.as_str())
+            .chain(stored.iter().map(String::as_str))
+            .collect();
+
+        let mut missing_files: Vec<String> = {
+            let cache = self
+                .notes_cache
+                .read()
+                .map_err(|_| KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on notes cache".to_string(),
+                })?;
+            cache
+                .keys()
+                .filter(|id| {
+                    !on_disk.contains(id.as_str())
+                        && (self.backend.kind() != BackendKind::Files
+                            || !self.files.find_note_path(id).exists())
+                })
+                .cloned()
+                .collect()
+        };
+        missing_files.sort();
+
+        let mut unparsable: Vec<(PathBuf, String)> = unreadable
+            .into_iter()
+            .map(|(path, e)| (path, e.to_string()))
+            .collect();
+        unparsable.sort();
+
+        Ok(IntegrityReport {
+            unparsable,
+            layout: layout_issues(files),
+            missing_files,
+            alias_collisions: self.alias_collisions()?,
+        })
+    }
+
+    /// Finds aliases carried by several notes and aliases equal to the ID of
+    /// another note, which resolves first
+    fn alias_collisions(&self) -> Result<Vec<AliasCollision>> {
+        let ids_by_alias: HashMap<String, String> = self
+            .notes_cache
+            .read()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on notes cache".to_string(),
+            })?
+            .keys()
+            .map(|id| (normalize_alias(id), id.clone()))
+            .collect();
+        let index = self
+            .alias_index
+            .lock()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on alias index".to_string(),
+            })?;
+
+        let mut collisions: Vec<AliasCollision> = index
+            .iter()
+            .filter_map(|(alias, ids)| {
+                let shadowed_by = ids_by_alias
+                    .get(alias)
+                    .filter(|id| !ids.contains(*id))
+                    .cloned();
+                if ids.len() < 2 && shadowed_by.is_none() {
+                    return None;
+                }
+                let mut note_ids: Vec<String> = ids.iter().cloned().collect();
+                note_ids.sort();
+                Some(AliasCollision {
+                    alias: alias.clone(),
+                    note_ids,
+                    shadowed_by,
+                })
+            })
+            .collect();
+        collisions.sort_by(|a, b| a.alias.cmp(&b.alias));
+        Ok(collisions)
+    }
+
+    /// Moves a note file that can't be parsed into [`CORRUPT_DIR`], keeping
+    /// its path relative to `notes_dir` and recording why next to it
+    ///
+    /// # Returns
+    ///
+    /// The new location of the file
+    pub fn quarantine_file(&self, path: &Path, reason: &str) -> Result<PathBuf> {
+        let relative =
+            path.strip_prefix(&self.config.notes_dir)
+                .map_err(|_| KbError::InvalidArgument {
+                    message: format!("{} is not inside the notes directory", path.display()),
+                })?;
+        let corrupt_dir = self.config.notes_dir.join(CORRUPT_DIR);
+        let now = Utc::now();
+        let mut attempt = 0;
+        let mut target = corrupt_dir.join(quarantine_name(relative, now, attempt));
+        while target.exists() {
+            attempt += 1;
+            target = corrupt_dir.join(quarantine_name(relative, now, attempt));
+        }
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(path, &target)?;
+        fs::write(quarantine_reason_path(&target), reason)?;
+        self.files.sync_dir_after_persist(&target)?;
+        if let Some(parent) = path.parent() {
+            if parent != self.config.notes_dir {
+                self.files.cleanup_empty_directory(parent);
+            }
+        }
+
+        warn!(
+            "Quarantined unreadable note file {} to {}",
+            path.display(),
+            target.display()
+        );
+        Ok(target)
+    }
+
+    /// Lists the note files in [`CORRUPT_DIR`]
+    ///
+    /// # Returns
+    ///
+    /// The quarantined files, sorted by path
+    pub fn list_quarantined(&self) -> Result<Vec<QuarantinedFile>> {
+        let corrupt_dir = self.config.notes_dir.join(CORRUPT_DIR);
+        let mut files = Vec::new();
+        if !corrupt_dir.is_dir() {
+            return Ok(files);
+        }
+
+        for entry in WalkDir::new(&corrupt_dir).sort_by_file_name() {
+            let entry = entry.map_err(|e| KbError::Io(e.into()))?;
+            let path = entry.path();
+            if !entry.file_type().is_file()
+                || path.to_string_lossy().ends_with(CORRUPT_REASON_SUFFIX)
+            {
+                continue;
+            }
+
+            let relative = path.strip_prefix(&corrupt_dir).unwrap_or(path);
+            let (original, quarantined_at) = parse_quarantine_name(relative);
+            files.push(QuarantinedFile {
+                path: path.to_path_buf(),
+                original: self.config.notes_dir.join(original),
+                quarantined_at,
+                reason: fs::read_to_string(quarantine_reason_path(path)).ok(),
+            });
+        }
+        Ok(files)
+    }
+
+    /// Reads as much of a quarantined note as its file still holds,
+    /// repairing truncated JSON
+    ///
+    /// # Returns
+    ///
+    /// The recovered note, or an error when nothing usable is left
+    pub fn salvage_quarantined(&self, file: &QuarantinedFile) -> Result<Note> {
+        let text = decode_note_bytes(fs::read(&file.path)?)?;
+        let format = StorageFormat::of_path(&file.original).unwrap_or_default();
+        // Only the sidecar of a split note is quarantined; its content file
+        // stays in the vault
+        let content = match format {
+            StorageFormat::Split => note_file_as(&file.original, StorageFormat::Markdown)
+                .and_then(|path| fs::read_to_string(path).ok()),
+            _ => None,
+        };
+        let parsed = match &content {
+            Some(content) => note_from_split(&text, content.clone()).map(|(note, _)| note),
+            None => parse_note(&text, format),
+        };
+        match parsed {
+            Ok(note) if !note.id.is_empty() => Ok(note),
+            // Markdown front matter has no truncation repair
+            Err(e) if format == StorageFormat::Markdown => Err(e),
+            _ => {
+                // The file name still encodes the ID the note was saved under
+                let stem = note_file_stem(&file.original).unwrap_or_default();
+                let fallback_id = self.id_policy.restore(&stem).unwrap_or(stem);
+                let fallback_time = file.quarantined_at.unwrap_or_else(Utc::now);
+                let mut note = salvage_note_json(&text, &fallback_id, fallback_time)?;
+                if let Some(content) = content {
+                    note.content = content;
+                }
+                Ok(note)
+            }
+        }
+    }
+
+    /// Saves the note recovered from a quarantined file back into the vault
+    /// and removes the file from the quarantine
+    ///
+    /// # Returns
+    ///
+    /// The restored note, or an error when nothing could be recovered or a
+    /// note with the same ID already exists
+    pub fn restore_quarantined(&self, file: &QuarantinedFile) -> Result<Note> {
+        let note = self.salvage_quarantined(file)?;
+        if self.get_note(&note.id).is_some() {
+            return Err(KbError::ApplicationError {
+                message: format!(
+                    "Note {} already exists, not replacing it with the copy recovered from {}",
+                    note.id,
+                    file.path.display()
+                ),
+            });
+        }
+
+        self.save_note(&note)?;
+        fs::remove_file(&file.path)?;
+        let reason = quarantine_reason_path(&file.path);
+        if reason.exists() {
+            fs::remove_file(reason)?;
+        }
+        if let Some(parent) = file.path.parent() {
+            self.files.cleanup_empty_directory(parent);
+        }
+
+        info!("Recovered note {} from {}", note.id, file.path.display());
+        Ok(note)
+    }
+
+    /// Moves misplaced note files to where the configured ID policy expects them
+    ///
+    /// Files whose expected location belongs to another note are left alone.
+    /// When both locations hold the same note, the older copy is removed.
+    ///
+    /// # Returns
+    ///
+    /// The number of note files moved or removed
+    pub fn migrate_layout(&self) -> Result<usize> {
+        let mut fixed = 0;
+
+        for issue in self.check_layout()? {
+            if issue.path == issue.expected {
+                continue;
+            }
+            if let Some(other) = &issue.collides_with {
+                warn!(
+                    "Not migrating note {}: {} is claimed by note {}",
+                    issue.note_id,
+                    issue.expected.display(),
+                    other
+                );
+                continue;
+            }
+
+            // On case-insensitive filesystems both paths may name the same file
+            let same_file = match (
+                fs::canonicalize(&issue.path),
+                fs::canonicalize(&issue.expected),
+            ) {
+                (Ok(current), Ok(expected)) => current == expected,
+                _ => false,
+            };
+
+            if issue.expected.exists() && !same_file {
+                let existing = load_note_from_file(&issue.expected)?;
+                if existing.id != issue.note_id {
+                    warn!(
+                        "Not migrating note {}: {} holds note {}",
+                        issue.note_id,
+                        issue.expected.display(),
+                        existing.id
+                    );
+                    continue;
+                }
+
+                // Two copies of the same note: keep the newer one
+                let misplaced = load_note_from_file(&issue.path)?;
+                if misplaced.updated_at <= existing.updated_at {
+                    debug!("Removing stale copy {}", issue.path.display());
+                    for path in note_file_paths(&issue.path) {
+                        if path.exists() {
+                            fs::remove_file(&path)?;
+                        }
+                    }
+                } else {
+                    move_note_files(&issue.path, &issue.expected)?;
+                }
+            } else {
+                if let Some(parent) = issue.expected.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                move_note_files(&issue.path, &issue.expected)?;
+            }
+
+            self.files.sync_dir_after_persist(&issue.expected)?;
+            if let Some(parent) = issue.path.parent() {
+                if parent != self.config.notes_dir {
+                    self.files.cleanup_empty_directory(parent);
+                }
+            }
+
+            info!(
+                "Migrated note {} from {} to {}",
+                issue.note_id,
+                issue.path.display(),
+                issue.expected.display()
+            );
+            fixed += 1;
+        }
+
+        Ok(fixed)
+    }
+
+    /// Creates a backup of the note in the backup directory
+    fn backup_note(&self, note: &Note) -> Result<()> {
+        debug!("Creating backup for note: {}", note.id);
+        // Create a timestamped backup path
+        let timestamp = Utc::now().timestamp();
+
+        let note_backup_dir = self.note_backup_dir(&note.id);
+        let backup_path = note_backup_dir.join(format!(
+            "{}_{}.json",
+            self.id_policy.sanitize(&note.id),
+            timestamp
+        ));
+
+        debug!("Backup path: {}", backup_path.display());
+
+        // Ensure the note's backup directory exists
+        if !note_backup_dir.exists() {
+            debug!("Creating backup directory: {}", note_backup_dir.display());
+            fs::create_dir_all(&note_backup_dir).map_err(|e| {
+                error!("Failed to create backup directory: {}", e);
+                KbError::Io(e)
+            })?;
+        }
+
+        // Write the note to the backup file
+        trace!("Serializing note for backup");
+        let json = serde_json::to_string_pretty(note).map_err(|e| {
+            error!("Failed to serialize note for backup: {}", e);
+            KbError::Serialization(e)
+        })?;
+
+        trace!("Writing backup file");
+        fs::write(&backup_path, json).map_err(|e| {
+            error!(
+                "Failed to write backup file {}: {}",
+                backup_path.display(),
+                e
+            );
+            KbError::Io(e)
+        })?;
+
+        info!("Backup created successfully at: {}", backup_path.display());
+        self.prune_note_backups_logged(&note.id);
+        Ok(())
+    }
+
+    /// Restores a single note from its most recent backup holding another
+    /// version than the current note
+    ///
+    /// Saving a note backs up the version saved, so the newest backup is
+    /// usually the current note itself; backups with the same title, content
+    /// and tags (see [`note_content_hash`]) are passed over. A deleted note
+    /// comes back from its newest backup.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note to restore
+    ///
+    /// # Returns
+    ///
+    /// The restored note in case of success or an error, also when no backup
+    /// differs from the current note
+    pub fn restore_note_from_backup(&self, note_id: &str) -> Result<Note> {
+        // The note's backups, in its own directory or left flat from before
+        let mut backups: Vec<NoteBackupFile> = self
+            .note_backup_files()?
+            .into_iter()
+            .filter(|file| !file.record && file.note_id == note_id)
+            .collect();
+        if backups.is_empty() {
+            let error = format!("No backup files found for note {}", note_id);
+            error!("{}", error);
+            return Err(KbError::BackupFailed { message: error });
+        }
+        backups.sort_by_key(|file| Reverse((file.timestamp, file.modified)));
+
+        // Read the backups newest first, up to one with another version
+        let current = self.get_note(note_id).map(|note| note_content_hash(&note));
+        let mut found = None;
+        for backup in backups {
+            let backup_content = fs::read_to_string(&backup.path).map_err(|e| {
+                error!("Failed to read backup of note {}", note_id);
+                KbError::BackupFailed {
+                    message: format!(
+                        "Failed to read backup file {}: {}",
+                        backup.path.display(),
+                        e
+                    ),
+                }
+            })?;
+            let note: Note = serde_json::from_str(&backup_content)?;
+            if current != Some(note_content_hash(&note)) {
+                found = Some((backup, note));
+                break;
+            }
+        }
+        let Some((latest, restored_note)) = found else {
+            let error = format!(
+                "No backup of note {} differs from the current note",
+                note_id
+            );
+            error!("{}", error);
+            return Err(KbError::BackupFailed { message: error });
+        };
 
         // Save the restored note back to storage
         self.save_note(&restored_note)?;
+        self.discard_trash_entries(&HashSet::from([restored_note.id.clone()]));
+
+        // Log the restoration
+        info!(
+            "Note {} successfully restored from backup created at {}",
+            note_id,
+            DateTime::<chrono::Local>::from(latest.timestamp).format("%Y-%m-%d %H:%M:%S")
+        );
+
+        Ok(restored_note)
+    }
+
+    /// Retrieves a note by its ID from the storage
+    /// Returns Some(Note) if found, or None if not found
+    pub fn get_note(&self, note_id: &str) -> Option<Note> {
+        debug!("Retrieving note by ID: {}", note_id);
+
+        // First, try to get from cache
+        match self.notes_cache.read() {
+            Ok(cache) => {
+                // If found in cache, clone and return it
+                if let Some(note) = cache.get(note_id) {
+                    trace!("Note found in cache: {}", note_id);
+                    self.cache_counters
+                        .hits
+                        .fetch_add(1, AtomicOrdering::Relaxed);
+                    return Some(note.clone());
+                }
+            }
+            Err(e) => {
+                error!("Failed to acquire lock on cache: {}", e);
+                // Fall through to file system check
+            }
+        }
+
+        // Not found in cache or couldn't access cache, try to load from disk
+        debug!("Note not found in cache, checking storage: {}", note_id);
+        self.cache_counters
+            .misses
+            .fetch_add(1, AtomicOrdering::Relaxed);
+        match self.backend.load(note_id) {
+            Ok(Some(note)) => {
+                // Update cache with the found note
+                self.index_aliases(&note);
+                self.index_title(&note);
+                self.index_tags(&note);
+                self.index_structure(&note);
+                self.index_links(&note);
+                if let Ok(mut cache) = self.notes_cache.write() {
+                    trace!("Updating cache with note loaded from disk");
+                    cache.insert(note_id.to_string(), note.clone());
+                } else {
+                    warn!("Failed to acquire lock to update cache");
+                }
+                return Some(note);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("Error loading note {}: {}", note_id, e);
+                return None;
+            }
+        }
+
+        // Not found
+        debug!("Note not found: {}", note_id);
+        None
+    }
+
+    /// Loads notes into the cache ahead of time, so the first `get_note` of
+    /// each is served from memory
+    ///
+    /// Priming does not count as cache hits or misses.
+    ///
+    /// # Arguments
+    ///
+    /// * `ids` - IDs of the notes to load; IDs without a stored note are skipped
+    ///
+    /// # Returns
+    ///
+    /// The number of notes read from disk (notes already cached are not counted)
+    pub fn prime_cache(&self, ids: &[String]) -> Result<usize> {
+        let missing: Vec<&String> = {
+            let cache = self
+                .notes_cache
+                .read()
+                .map_err(|_| KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on notes cache".to_string(),
+                })?;
+            ids.iter().filter(|id| !cache.contains_key(*id)).collect()
+        };
+
+        // Read outside the lock, so other readers aren't held up by disk I/O
+        let mut loaded = Vec::with_capacity(missing.len());
+        for id in missing {
+            let Some(note) = self.backend.load(id)? else {
+                warn!("Cannot prime cache with {}: no stored note", id);
+                continue;
+            };
+            self.index_aliases(&note);
+            self.index_title(&note);
+            self.index_tags(&note);
+            self.index_structure(&note);
+            self.index_links(&note);
+            loaded.push(note);
+        }
+
+        let count = loaded.len();
+        let mut cache = self
+            .notes_cache
+            .write()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on notes cache".to_string(),
+            })?;
+        for note in loaded {
+            // A note saved meanwhile is newer than the file we read
+            cache.entry(note.id.clone()).or_insert(note);
+        }
+
+        debug!("Primed cache with {} note(s)", count);
+        Ok(count)
+    }
+
+    /// Reads notes from storage into the cache again, dropping the ones no
+    /// longer stored, so that the cache agrees with disk after a bulk write
+    ///
+    /// # Returns
+    ///
+    /// The number of notes whose cached copy was missing, stale or dropped
+    fn refresh_cached_notes(&self, ids: &HashSet<String>) -> Result<usize> {
+        // Read outside the lock, like `prime_cache`
+        let mut stored = Vec::with_capacity(ids.len());
+        for id in ids {
+            match self.backend.load(id) {
+                Ok(note) => stored.push((id, note)),
+                Err(e) => warn!("Cannot refresh cached note {}: {}", id, e),
+            }
+        }
+
+        let mut cache = self
+            .notes_cache
+            .write()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on notes cache".to_string(),
+            })?;
+        let mut refreshed = 0;
+        for (id, note) in stored {
+            match note {
+                Some(note) if cache.get(id) != Some(&note) => {
+                    self.index_aliases(&note);
+                    self.index_title(&note);
+                    self.index_tags(&note);
+                    self.index_structure(&note);
+                    self.index_links(&note);
+                    cache.insert(id.clone(), note);
+                    refreshed += 1;
+                }
+                Some(_) => {}
+                None => refreshed += usize::from(cache.remove(id).is_some()),
+            }
+        }
+        Ok(refreshed)
+    }
+
+    /// Drops notes from the cache; later reads load them from disk again
+    ///
+    /// The cache also backs listings and searches, which leave evicted notes
+    /// out until they are read again or `load_notes` runs.
+    ///
+    /// # Returns
+    ///
+    /// The number of notes that were cached and have been dropped
+    pub fn evict(&self, ids: &[String]) -> Result<usize> {
+        let mut cache = self
+            .notes_cache
+            .write()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on notes cache".to_string(),
+            })?;
+        let evicted = ids.iter().filter(|id| cache.remove(*id).is_some()).count();
+
+        self.cache_counters
+            .evictions
+            .fetch_add(evicted as u64, AtomicOrdering::Relaxed);
+        debug!("Evicted {} note(s) from the cache", evicted);
+        Ok(evicted)
+    }
+
+    /// Drops every note from the cache (see [`NoteStorage::evict`])
+    ///
+    /// # Returns
+    ///
+    /// The number of notes dropped
+    pub fn clear_cache(&self) -> Result<usize> {
+        let mut cache = self
+            .notes_cache
+            .write()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on notes cache".to_string(),
+            })?;
+        let evicted = cache.len();
+        cache.clear();
+
+        self.cache_counters
+            .evictions
+            .fetch_add(evicted as u64, AtomicOrdering::Relaxed);
+        info!("Cleared {} note(s) from the cache", evicted);
+        Ok(evicted)
+    }
+
+    /// Returns the size of the cache and its hit, miss and eviction counts
+    /// since the storage was created
+    pub fn cache_stats(&self) -> Result<CacheStats> {
+        let cache = self
+            .notes_cache
+            .read()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on notes cache".to_string(),
+            })?;
+        let bytes_estimate = cache
+            .values()
+            .map(|note| {
+                let text = note.id.len()
+                    + note.title.len()
+                    + note.content.len()
+                    + note.tags.iter().map(String::len).sum::<usize>()
+                    + note.aliases.iter().map(String::len).sum::<usize>()
+                    + note
+                        .metadata
+                        .iter()
+                        .map(|(key, value)| key.len() + value.len())
+                        .sum::<usize>();
+                (text + std::mem::size_of::<Note>()) as u64
+            })
+            .sum();
+
+        Ok(CacheStats {
+            entries: cache.len(),
+            bytes_estimate,
+            hits: self.cache_counters.hits.load(AtomicOrdering::Relaxed),
+            misses: self.cache_counters.misses.load(AtomicOrdering::Relaxed),
+            evictions: self.cache_counters.evictions.load(AtomicOrdering::Relaxed),
+        })
+    }
+
+    /// Returns the fsync mode and how many note files and directories were
+    /// synced, or are still waiting for a sync, since the storage was created
+    pub fn durability_stats(&self) -> DurabilityStats {
+        self.files.durability_stats()
+    }
+
+    /// Finds the notes with the given title
+    ///
+    /// Titles are compared case-insensitively and ignoring surrounding
+    /// whitespace, using the title index rather than scanning every note.
+    ///
+    /// # Arguments
+    ///
+    /// * `title` - The title to look for
+    ///
+    /// # Returns
+    ///
+    /// The matching notes sorted by ID, empty when no note has that title, or
+    /// `KbError::LockAcquisitionFailed` when the title index can't be read
+    pub fn find_by_title(&self, title: &str) -> Result<Vec<Note>> {
+        let ids: Vec<String> = self
+            .title_index
+            .lock()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on title index".to_string(),
+            })?
+            .get(&normalize_alias(title))
+            .map(|ids| ids.iter().cloned().collect())
+            .unwrap_or_default();
+
+        let mut notes: Vec<Note> = ids.iter().filter_map(|id| self.get_note(id)).collect();
+        notes.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(notes)
+    }
+
+    /// Resolves a note reference to a note ID
+    ///
+    /// The reference is tried as a note ID first, then as an exact
+    /// (case-insensitive) title, and finally looked up in the alias index.
+    ///
+    /// # Arguments
+    ///
+    /// * `reference` - A note ID, title, or alias
+    ///
+    /// # Returns
+    ///
+    /// The ID of the single matching note, `KbError::AmbiguousReference` when
+    /// the title or alias matches several notes, or `KbError::NoteNotFound`
+    pub fn resolve_note_id(&self, reference: &str) -> Result<String> {
+        if self.get_note(reference).is_some() {
+            return Ok(reference.to_string());
+        }
+
+        let normalized = normalize_alias(reference);
+
+        let title_matches: Vec<String> = {
+            let index = self
+                .title_index
+                .lock()
+                .map_err(|_| KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on title index".to_string(),
+                })?;
+
+            index
+                .get(&normalized)
+                .map(|ids| ids.iter().cloned().collect())
+                .unwrap_or_default()
+        };
+
+        let mut candidates = if title_matches.is_empty() {
+            let index = self
+                .alias_index
+                .lock()
+                .map_err(|_| KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on alias index".to_string(),
+                })?;
+
+            index
+                .get(&normalized)
+                .map(|ids| ids.iter().cloned().collect::<Vec<String>>())
+                .unwrap_or_default()
+        } else {
+            title_matches
+        };
+
+        match candidates.len() {
+            0 => Err(KbError::NoteNotFound {
+                id: reference.to_string(),
+            }),
+            1 => Ok(candidates.remove(0)),
+            _ => {
+                candidates.sort();
+                Err(KbError::AmbiguousReference {
+                    reference: reference.to_string(),
+                    candidates,
+                })
+            }
+        }
+    }
+
+    /// Updates the alias index entries for a note
+    fn index_aliases(&self, note: &Note) {
+        match self.alias_index.lock() {
+            Ok(mut index) => index_note_aliases(&mut index, note),
+            Err(e) => warn!("Failed to acquire lock for alias index update: {}", e),
+        }
+    }
+
+    /// Updates the title index entry for a note
+    fn index_title(&self, note: &Note) {
+        match self.title_index.lock() {
+            Ok(mut index) => index_note_title(&mut index, note),
+            Err(e) => warn!("Failed to acquire lock for title index update: {}", e),
+        }
+    }
+
+    /// Updates the tag index entries for a note
+    fn index_tags(&self, note: &Note) {
+        match self.tag_index.lock() {
+            Ok(mut index) => index_note_tags(&mut index, note),
+            Err(e) => warn!("Failed to acquire lock for tag index update: {}", e),
+        }
+    }
+
+    /// Updates the structure index entry for a note
+    fn index_structure(&self, note: &Note) {
+        match self.structure_index.lock() {
+            Ok(mut index) => index_note_structure(&mut index, note),
+            Err(e) => warn!("Failed to acquire lock for structure index update: {}", e),
+        }
+    }
+
+    /// Updates the link index entries for a note
+    fn index_links(&self, note: &Note) {
+        match self.link_index.lock() {
+            Ok(mut index) => index.index_note(note),
+            Err(e) => warn!("Failed to acquire lock for link index update: {}", e),
+        }
+    }
+
+    /// Updates the search index entries for a note and saves the index
+    fn index_search(&self, note: &Note) {
+        match self.search_index.lock() {
+            Ok(mut index) => update_search_index(&mut index, |index| index.index_note(note)),
+            Err(e) => warn!("Failed to acquire lock for search index update: {}", e),
+        }
+    }
+
+    /// Drops a deleted note from the search index and saves the index
+    fn unindex_search(&self, note_id: &str) {
+        match self.search_index.lock() {
+            Ok(mut index) => update_search_index(&mut index, |index| index.remove_note(note_id)),
+            Err(e) => warn!("Failed to acquire lock for search index update: {}", e),
+        }
+    }
+
+    /// Loads the search index when `use_search_index` is enabled, refreshing
+    /// the entries of notes changed since it was saved
+    ///
+    /// A missing index is built, and one that can't be read back (corrupt,
+    /// or from another format version) is rebuilt from the notes.
+    fn open_search_index(&self, notes: &HashMap<String, Note>) -> Result<()> {
+        let mut slot = self
+            .search_index
+            .lock()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on search index during load operation".to_string(),
+            })?;
+        if !self.config.use_search_index {
+            *slot = None;
+            return Ok(());
+        }
+
+        let path = SearchIndex::path_in(&self.config.notes_dir);
+        let (index, changed) = match SearchIndex::load(path.clone()) {
+            Ok(Some(mut index)) => {
+                let changed = index.sync(notes);
+                debug!("Refreshed {} search index entries", changed);
+                (index, changed > 0)
+            }
+            Ok(None) => {
+                info!("Building search index for {} notes", notes.len());
+                (SearchIndex::build(path, notes.values()), true)
+            }
+            Err(e) => {
+                warn!("Rebuilding search index: {}", e);
+                (SearchIndex::build(path, notes.values()), true)
+            }
+        };
+        if changed {
+            if let Err(e) = index.save() {
+                warn!("Failed to save search index: {}", e);
+            }
+        }
+        *slot = Some(index);
+        Ok(())
+    }
+
+    /// Rebuilds the search index from scratch out of the cached notes and
+    /// writes it to `notes_dir/.index`, all in one go
+    ///
+    /// The index is written even when `use_search_index` is disabled, but
+    /// searches only use it while the setting is on. See
+    /// [`NoteStorage::start_reindex`] for a rebuild that gives way to
+    /// foreground commands.
+    ///
+    /// # Returns
+    ///
+    /// The number of indexed notes
+    pub fn rebuild_search_index(&self) -> Result<usize> {
+        let index = {
+            let cache = self
+                .notes_cache
+                .read()
+                .map_err(|_| KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on notes cache".to_string(),
+                })?;
+            SearchIndex::build(SearchIndex::path_in(&self.config.notes_dir), cache.values())
+        };
+        index.save()?;
+        let count = index.len();
+        info!("Rebuilt search index with {} notes", count);
+
+        if self.config.use_search_index {
+            let mut slot =
+                self.search_index
+                    .lock()
+                    .map_err(|_| KbError::LockAcquisitionFailed {
+                        message: "Failed to acquire lock on search index".to_string(),
+                    })?;
+            *slot = Some(index);
+        }
+        Ok(count)
+    }
+
+    /// Starts rebuilding the search index in the background
+    ///
+    /// The notes are indexed in batches of [`REINDEX_BATCH_SIZE`], yielding
+    /// between batches and pausing while a foreground command runs (see
+    /// [`NoteStorage::enter_foreground`]). Searches meanwhile use the notes
+    /// indexed so far and scan the others. With `use_search_index` disabled
+    /// nothing searches the index, so it is rebuilt in one go on a blocking
+    /// thread instead.
+    ///
+    /// # Returns
+    ///
+    /// The task of the rebuild, which yields the number of indexed notes, or
+    /// an error when a reindex is already running, in this process or in
+    /// another one
+    pub fn start_reindex(&self) -> Result<tokio::task::JoinHandle<Result<usize>>> {
+        if !self.config.use_search_index {
+            let storage = self.clone();
+            return Ok(tokio::task::spawn_blocking(move || {
+                storage.rebuild_search_index()
+            }));
+        }
+
+        // Other processes learn from the lock that a reindex is running
+        let index_dir = self.config.notes_dir.join(SEARCH_INDEX_DIR);
+        let Some(lock) = ReindexLock::acquire(&index_dir)? else {
+            return Err(KbError::ApplicationError {
+                message: "A reindex is already running".to_string(),
+            });
+        };
+
+        let mut note_ids: Vec<String> = self.cached_note_ids()?.into_iter().collect();
+        note_ids.sort_unstable();
+        let progress = ReindexProgress {
+            indexed: 0,
+            total: note_ids.len(),
+            paused: false,
+        };
+        lock.report(&progress)?;
+        match self.reindex_progress.lock() {
+            Ok(mut slot) => *slot = Some(progress),
+            Err(e) => warn!("Failed to acquire lock on reindex progress: {}", e),
+        }
+        info!("Reindexing {} notes in the background", note_ids.len());
+
+        // Every note is pending until its batch comes up
+        let index = SearchIndex::pending(
+            SearchIndex::path_in(&self.config.notes_dir),
+            note_ids.iter().cloned(),
+        );
+        match self.search_index.lock() {
+            Ok(mut slot) => *slot = Some(index),
+            Err(e) => warn!("Failed to acquire lock on search index: {}", e),
+        }
+
+        let storage = self.clone();
+        Ok(tokio::spawn(async move {
+            let result = storage.run_reindex(note_ids, &lock).await;
+            if let Ok(mut progress) = storage.reindex_progress.lock() {
+                *progress = None;
+            }
+            result
+        }))
+    }
+
+    /// Indexes the notes of a background reindex batch by batch
+    async fn run_reindex(&self, note_ids: Vec<String>, lock: &ReindexLock) -> Result<usize> {
+        for batch in note_ids.chunks(REINDEX_BATCH_SIZE) {
+            // Foreground commands go first
+            while self.foreground.is_active() {
+                self.update_reindex_progress(lock, |progress| progress.paused = true);
+                tokio::time::sleep(REINDEX_PAUSE).await;
+            }
+
+            // Copy the batch out of the cache, so neither lock is held long
+            let notes: HashMap<&str, Note> = {
+                let cache =
+                    self.notes_cache
+                        .read()
+                        .map_err(|_| KbError::LockAcquisitionFailed {
+                            message: "Failed to acquire lock on notes cache".to_string(),
+                        })?;
+                batch
+                    .iter()
+                    .filter_map(|id| Some((id.as_str(), cache.get(id)?.clone())))
+                    .collect()
+            };
+            if let Ok(mut slot) = self.search_index.lock() {
+                if let Some(index) = slot.as_mut() {
+                    for id in batch {
+                        // Deleted since the reindex started
+                        match notes.get(id.as_str()) {
+                            Some(note) => index.index_note(note),
+                            None => index.remove_note(id),
+                        };
+                    }
+                }
+            }
+
+            self.update_reindex_progress(lock, |progress| {
+                progress.indexed += batch.len();
+                progress.paused = false;
+            });
+            tokio::task::yield_now().await;
+        }
+
+        let slot = self
+            .search_index
+            .lock()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on search index".to_string(),
+            })?;
+        let Some(index) = slot.as_ref() else {
+            return Ok(0);
+        };
+        index.save()?;
+        info!("Rebuilt search index with {} notes", index.len());
+        Ok(index.len())
+    }
+
+    /// Applies a change to the progress of the running background reindex
+    /// and reports it to other processes if it changed
+    fn update_reindex_progress(
+        &self,
+        lock: &ReindexLock,
+        change: impl FnOnce(&mut ReindexProgress),
+    ) {
+        let Ok(mut slot) = self.reindex_progress.lock() else {
+            return;
+        };
+        let Some(progress) = slot.as_mut() else {
+            return;
+        };
+        let before = *progress;
+        change(progress);
+        if *progress != before {
+            if let Err(e) = lock.report(progress) {
+                warn!("Failed to report reindex progress: {}", e);
+            }
+        }
+    }
+
+    /// Returns how far a background reindex got, whether it runs in this
+    /// process or in another one, `None` while none runs
+    pub fn reindex_progress(&self) -> Option<ReindexProgress> {
+        let progress = self
+            .reindex_progress
+            .lock()
+            .ok()
+            .and_then(|progress| *progress);
+        progress.or_else(|| ReindexProgress::read(&self.config.notes_dir.join(SEARCH_INDEX_DIR)))
+    }
+
+    /// Marks a foreground command as running until the guard is dropped,
+    /// pausing background reindexing meanwhile, also in other processes
+    /// using the same notes directory
+    ///
+    /// The CLI holds one for every command but `reindex`; embedders should
+    /// hold one while serving a request.
+    pub fn enter_foreground(&self) -> ForegroundGuard {
+        self.foreground.enter()
+    }
+
+    /// Returns the notes whose content links to a note, by its ID, title or
+    /// one of its aliases
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the linked note
+    ///
+    /// # Returns
+    ///
+    /// The linking notes sorted by title, empty when the note doesn't exist,
+    /// or `KbError::LockAcquisitionFailed` when the link index can't be read
+    pub fn get_backlinks(&self, note_id: &str) -> Result<Vec<Note>> {
+        let Some(note) = self.get_note(note_id) else {
+            return Ok(Vec::new());
+        };
+
+        let sources = self
+            .link_index
+            .lock()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on link index".to_string(),
+            })?
+            .sources_linking_to(
+                [note.id.as_str(), note.title.as_str()]
+                    .into_iter()
+                    .chain(note.aliases.iter().map(String::as_str)),
+            );
+
+        let mut backlinks: Vec<Note> = sources
+            .iter()
+            .filter(|source| **source != note.id)
+            .filter_map(|source| self.get_note(source))
+            .collect();
+        backlinks.sort_by(|a, b| a.title.cmp(&b.title).then_with(|| a.id.cmp(&b.id)));
+        Ok(backlinks)
+    }
+
+    /// Returns the `[[wiki-links]]` in a note's content, resolved to note IDs
+    /// the same way `resolve_note_id` resolves references
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the linking note
+    ///
+    /// # Returns
+    ///
+    /// The links in the order they appear, empty when the note doesn't exist
+    /// or has no links, or `KbError::LockAcquisitionFailed` when the link
+    /// index can't be read
+    pub fn get_outgoing_links(&self, note_id: &str) -> Result<Vec<NoteLink>> {
+        let targets = self
+            .link_index
+            .lock()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on link index".to_string(),
+            })?
+            .outgoing(note_id)
+            .to_vec();
+
+        Ok(targets
+            .into_iter()
+            .map(|target| NoteLink {
+                note_id: self.resolve_note_id(&target).ok(),
+                target,
+            })
+            .collect())
+    }
+
+    /// Writes the link index to its file in the state directory, if it changed
+    fn save_link_index(&self) -> Result<()> {
+        let mut index = self
+            .link_index
+            .lock()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on link index".to_string(),
+            })?;
+        index.save(&self.config.notes_dir.join(STATE_DIR).join(LINK_INDEX_FILE))?;
+        Ok(())
+    }
+
+    /// Returns the structure of the given notes along with their broken links
+    ///
+    /// Structures come from the index maintained on load and save; a note
+    /// missing from it (e.g. one that was never saved) is parsed and indexed.
+    /// Wiki-link targets are resolved against the IDs, titles and aliases of
+    /// all cached notes.
+    ///
+    /// # Arguments
+    ///
+    /// * `notes` - The notes to summarize
+    ///
+    /// # Returns
+    ///
+    /// Summaries keyed by note ID
+    pub fn structure_summaries(&self, notes: &[Note]) -> Result<HashMap<String, StructureSummary>> {
+        let cache = self
+            .notes_cache
+            .read()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on notes cache".to_string(),
+            })?;
+        self.summarize_structures(&cache, notes)
+    }
+
+    /// Does the work of [`NoteStorage::structure_summaries`] for callers that
+    /// already hold the notes cache lock
+    fn summarize_structures<'a>(
+        &self,
+        cache: &HashMap<String, Note>,
+        notes: impl IntoIterator<Item = &'a Note>,
+    ) -> Result<HashMap<String, StructureSummary>> {
+        let known: HashSet<String> = {
+            let aliases = self
+                .alias_index
+                .lock()
+                .map_err(|_| KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on alias index".to_string(),
+                })?;
+
+            cache
+                .values()
+                .flat_map(|note| [normalize_alias(&note.id), normalize_alias(&note.title)])
+                .chain(aliases.keys().cloned())
+                .collect()
+        };
+
+        let mut index =
+            self.structure_index
+                .lock()
+                .map_err(|_| KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on structure index".to_string(),
+                })?;
+
+        let mut summaries = HashMap::new();
+        for note in notes {
+            let structure = index
+                .entry(note.id.clone())
+                .or_insert_with(|| summarize_structure(&note.content))
+                .clone();
+            let broken_links = structure
+                .wiki_links
+                .iter()
+                .filter(|link| !known.contains(&normalize_alias(link)))
+                .cloned()
+                .collect();
+            summaries.insert(
+                note.id.clone(),
+                StructureSummary {
+                    structure,
+                    broken_links,
+                },
+            );
+        }
+        Ok(summaries)
+    }
+
+    /// Re-parses every cached note and repairs its structure index entry
+    ///
+    /// # Returns
+    ///
+    /// The number of entries that were missing, stale, or left over from
+    /// notes that no longer exist
+    pub fn reconcile_structure_index(&self) -> Result<usize> {
+        let cache = self
+            .notes_cache
+            .read()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on notes cache".to_string(),
+            })?;
+        let mut index =
+            self.structure_index
+                .lock()
+                .map_err(|_| KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on structure index".to_string(),
+                })?;
+
+        let before = index.len();
+        index.retain(|id, _| cache.contains_key(id));
+        let mut repaired = before - index.len();
+
+        for note in cache.values() {
+            let structure = summarize_structure(&note.content);
+            if index.get(&note.id) != Some(&structure) {
+                index.insert(note.id.clone(), structure);
+                repaired += 1;
+            }
+        }
+
+        debug!("Repaired {} structure index entries", repaired);
+        Ok(repaired)
+    }
+
+    /// Keeps the notes whose structure satisfies every filter
+    ///
+    /// # Arguments
+    ///
+    /// * `notes` - The notes to filter
+    /// * `filters` - Structural predicates that must all hold
+    ///
+    /// # Returns
+    ///
+    /// The matching notes, in their original order
+    pub fn filter_by_structure(
+        &self,
+        notes: Vec<Note>,
+        filters: &[StructureFilter],
+    ) -> Result<Vec<Note>> {
+        if filters.is_empty() {
+            return Ok(notes);
+        }
+
+        let summaries = self.structure_summaries(&notes)?;
+        Ok(notes
+            .into_iter()
+            .filter(|note| {
+                summaries
+                    .get(&note.id)
+                    .is_some_and(|summary| filters.iter().all(|filter| filter.matches(summary)))
+            })
+            .collect())
+    }
+
+    /// Helper method to get the path of the access state sidecar file
+    fn get_access_state_path(&self) -> PathBuf {
+        self.config
+            .notes_dir
+            .join(STATE_DIR)
+            .join(ACCESS_STATE_FILE)
+    }
+
+    /// Loads the access state sidecar, keeping only entries of existing notes
+    fn load_access_state(&self, notes: &HashMap<String, Note>) -> Result<()> {
+        let path = self.get_access_state_path();
+        let mut state: AccessState = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            AccessState::default()
+        };
+
+        state.unread.retain(|id| notes.contains_key(id));
+        state.last_viewed.retain(|id, _| notes.contains_key(id));
+
+        let mut current = self
+            .access_state
+            .lock()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on access state".to_string(),
+            })?;
+        *current = state;
+        debug!("Loaded access state: {} unread notes", current.unread.len());
+        Ok(())
+    }
+
+    /// Applies a change to the access state and persists it when `change`
+    /// reports that something was modified
+    fn update_access_state(&self, change: impl FnOnce(&mut AccessState) -> bool) -> Result<()> {
+        let mut state = self
+            .access_state
+            .lock()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on access state".to_string(),
+            })?;
+
+        if !change(&mut state) {
+            return Ok(());
+        }
+
+        let path = self.get_access_state_path();
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        fs::create_dir_all(dir)?;
+
+        let mut temp_file = NamedTempFile::new_in(dir)?;
+        temp_file.write_all(serde_json::to_string_pretty(&*state)?.as_bytes())?;
+        temp_file.flush()?;
+        self.files.sync_file_before_persist(temp_file.as_file())?;
+        temp_file.persist(&path).map_err(|e| KbError::Io(e.error))?;
+        self.files.sync_dir_after_persist(&path)?;
+
+        trace!("Access state saved to {}", path.display());
+        Ok(())
+    }
+
+    /// Records that a note was viewed, clearing its unread state
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the viewed note
+    pub fn record_note_view(&self, note_id: &str) -> Result<()> {
+        self.update_access_state(|state| {
+            state.unread.remove(note_id);
+            state.last_viewed.insert(note_id.to_string(), Utc::now());
+            true
+        })
+    }
+
+    /// Marks notes as read without recording a view
+    ///
+    /// # Arguments
+    ///
+    /// * `note_ids` - The IDs of the notes to mark
+    ///
+    /// # Returns
+    ///
+    /// The number of notes that were unread before
+    pub fn mark_notes_read(&self, note_ids: &[String]) -> Result<usize> {
+        let mut changed = 0;
+        self.update_access_state(|state| {
+            changed = note_ids
+                .iter()
+                .filter(|id| state.unread.remove(id.as_str()))
+                .count();
+            changed > 0
+        })?;
+        Ok(changed)
+    }
+
+    /// Marks notes as unread again
+    ///
+    /// # Arguments
+    ///
+    /// * `note_ids` - The IDs of the notes to mark
+    ///
+    /// # Returns
+    ///
+    /// The number of notes that were read before
+    pub fn mark_notes_unread(&self, note_ids: &[String]) -> Result<usize> {
+        let mut changed = 0;
+        self.update_access_state(|state| {
+            changed = note_ids
+                .iter()
+                .filter(|id| state.unread.insert(id.to_string()))
+                .count();
+            changed > 0
+        })?;
+        Ok(changed)
+    }
+
+    /// Returns true when the note has not been viewed since it was created or
+    /// marked unread
+    pub fn is_note_unread(&self, note_id: &str) -> bool {
+        self.access_state
+            .lock()
+            .map(|state| state.unread.contains(note_id))
+            .unwrap_or(false)
+    }
+
+    /// Returns the number of existing notes that are unread
+    pub fn unread_count(&self) -> Result<usize> {
+        let unread: Vec<String> = self
+            .access_state
+            .lock()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on access state".to_string(),
+            })?
+            .unread
+            .iter()
+            .cloned()
+            .collect();
+        let cache = self
+            .notes_cache
+            .read()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on notes cache".to_string(),
+            })?;
+        Ok(unread.iter().filter(|id| cache.contains_key(*id)).count())
+    }
+
+    /// Retrieves all unread notes, oldest first
+    ///
+    /// # Arguments
+    ///
+    /// * `tag` - Only return notes carrying this tag
+    ///
+    /// # Returns
+    ///
+    /// A vector of unread notes
+    pub fn get_unread_notes(&self, tag: Option<&str>) -> Result<Vec<Note>> {
+        let unread: Vec<String> = self
+            .access_state
+            .lock()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on access state".to_string(),
+            })?
+            .unread
+            .iter()
+            .cloned()
+            .collect();
+
+        let search_tag = tag.map(normalize_tag);
+        let mut notes: Vec<Note> = unread
+            .iter()
+            .filter_map(|id| self.get_note(id))
+            .filter(|note| match &search_tag {
+                Some(search_tag) => note.tags.iter().any(|t| normalize_tag(t) == *search_tag),
+                None => true,
+            })
+            .collect();
+
+        notes.sort_by_key(|note| note.created_at);
+        Ok(notes)
+    }
+
+    /// Retrieves the notes in a notebook and the notebooks below it
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - The notebook path; `work` matches `work` and `work/project-x`
+    ///   but not `workshop`
+    ///
+    /// # Returns
+    ///
+    /// The matching notes, archived ones included, oldest first, or
+    /// `KbError::InvalidArgument` when the path is not a valid notebook path
+    pub fn get_notes_by_notebook(&self, prefix: &str) -> Result<Vec<Note>> {
+        let prefix = validate_notebook_path(prefix)?;
+        info!("Retrieving notes in notebook: {}", prefix);
+
+        let mut notes: Vec<Note> = {
+            let cache = self
+                .notes_cache
+                .read()
+                .map_err(|_| KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on notes cache".to_string(),
+                })?;
+
+            cache
+                .values()
+                .filter(|note| note.in_notebook(&prefix))
+                .cloned()
+                .collect()
+        };
+
+        notes.sort_by(|a, b| {
+            a.created_at
+                .cmp(&b.created_at)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        Ok(notes)
+    }
+
+    /// Retrieves all notes with a specific tag
+    ///
+    /// # Arguments
+    ///
+    /// * `tag` - The tag to search for
+    /// * `include_archived` - Whether archived notes are included
+    ///
+    /// # Returns
+    ///
+    /// A vector of notes that have the specified tag
+    pub fn get_notes_by_tag(&self, tag: &str, include_archived: bool) -> Result<Vec<Note>> {
+        self.get_notes_by_tags(&[tag.to_string()], TagMatch::Any, include_archived)
+    }
+
+    /// Retrieves the notes carrying any or all of several tags
+    ///
+    /// Tags are compared after [`normalize_tag`], so case and Unicode
+    /// normalization don't matter, and the cache is scanned once whatever the
+    /// number of tags.
+    ///
+    /// # Arguments
+    ///
+    /// * `tags` - The tags to search for; an empty list selects no notes
+    /// * `mode` - Whether a note needs any or all of `tags`
+    /// * `include_archived` - Whether archived notes are included
+    ///
+    /// # Returns
+    ///
+    /// The matching notes, in no particular order
+    pub fn get_notes_by_tags(
+        &self,
+        tags: &[String],
+        mode: TagMatch,
+        include_archived: bool,
+    ) -> Result<Vec<Note>> {
+        info!("Retrieving notes by tags ({:?}): {}", mode, tags.join(", "));
+        if tags.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Look up the IDs in the tag index, then fetch only those notes
+        let ids: HashSet<String> = {
+            let index = self
+                .tag_index
+                .lock()
+                .map_err(|_| KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on tag index".to_string(),
+                })?;
+            let wanted: HashSet<String> = tags.iter().map(|tag| normalize_tag(tag)).collect();
+            let mut sets = wanted
+                .iter()
+                .map(|tag| index.get(tag).cloned().unwrap_or_default());
+            let first = sets.next().unwrap_or_default();
+            match mode {
+                TagMatch::Any => sets.fold(first, |ids, more| &ids | &more),
+                TagMatch::All => sets.fold(first, |ids, more| &ids & &more),
+            }
+        };
+
+        let cache = self
+            .notes_cache
+            .read()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on notes cache".to_string(),
+            })?;
+        debug!("Fetching {} notes from the tag index", ids.len());
+
+        let matching_notes: Vec<Note> = ids
+            .iter()
+            .filter_map(|id| cache.get(id))
+            .filter(|note| include_archived || !note.archived)
+            .cloned()
+            .collect();
+
+        info!(
+            "Found {} notes with tags: {}",
+            matching_notes.len(),
+            tags.join(", ")
+        );
+        Ok(matching_notes)
+    }
+
+    /// Counts the notes carrying each tag, archived ones included
+    ///
+    /// # Returns
+    ///
+    /// Each normalized tag with its number of notes, most used first and
+    /// then by tag
+    pub fn list_tags(&self) -> Result<Vec<(String, usize)>> {
+        let index = self
+            .tag_index
+            .lock()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on tag index".to_string(),
+            })?;
+        let mut tags: Vec<(String, usize)> = index
+            .iter()
+            .map(|(tag, ids)| (tag.clone(), ids.len()))
+            .collect();
+        tags.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(tags)
+    }
+
+    /// Retrieves every note in the cache
+    ///
+    /// # Returns
+    ///
+    /// All notes, in no particular order
+    pub fn get_all_notes(&self) -> Result<Vec<Note>> {
+        let cache = self
+            .notes_cache
+            .read()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on notes cache".to_string(),
+            })?;
+        Ok(cache.values().cloned().collect())
+    }
+
+    /// Retrieves the notes created, modified or deleted at or after a point
+    /// in time, for tools that mirror the vault incrementally
+    ///
+    /// Archived notes are included, archiving being a change too. Deletions
+    /// come from the trash and from the journal of purged notes, so notes
+    /// deleted for good are reported as well. A deleted note whose ID is
+    /// live again is only reported if it was modified.
+    ///
+    /// # Arguments
+    ///
+    /// * `since` - Earliest update or deletion time to report
+    ///
+    /// # Returns
+    ///
+    /// The changed notes, least recently updated first, and the deleted ones,
+    /// least recently deleted first
+    pub fn get_notes_modified_since(&self, since: DateTime<Utc>) -> Result<NoteChanges> {
+        let mut modified = self.query_notes(NoteFilter {
+            updated_since: Some(since),
+            ..NoteFilter::default()
+        })?;
+        modified.sort_by(|a, b| {
+            a.updated_at
+                .cmp(&b.updated_at)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+
+        // The latest deletion of each ID wins
+        let mut latest: HashMap<String, DeletedNote> = HashMap::new();
+        let trashed = self.list_trash()?.into_iter().map(|trashed| DeletedNote {
+            id: trashed.note.id,
+            deleted_at: trashed.deleted_at,
+            purged: false,
+        });
+        for deleted in self.read_purge_journal()?.into_iter().chain(trashed) {
+            if deleted.deleted_at < since || self.get_note(&deleted.id).is_some() {
+                continue;
+            }
+            match latest.get(&deleted.id) {
+                Some(known) if known.deleted_at > deleted.deleted_at => {}
+                _ => {
+                    latest.insert(deleted.id.clone(), deleted);
+                }
+            }
+        }
+        let mut deleted: Vec<DeletedNote> = latest.into_values().collect();
+        deleted.sort_by(|a, b| {
+            a.deleted_at
+                .cmp(&b.deleted_at)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+
+        Ok(NoteChanges { modified, deleted })
+    }
+
+    /// Retrieves the notes matching a filter
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - Tag, creation date and update date conditions; an empty
+    ///   filter selects every note
+    ///
+    /// # Returns
+    ///
+    /// The matching notes, in no particular order
+    pub fn query_notes(&self, filter: NoteFilter) -> Result<Vec<Note>> {
+        debug!("Querying notes with {:?}", filter);
+
+        // An indexed backend answers without going through every note
+        if self.backend.indexed_queries() {
+            let notes = self.backend.query(&filter, None)?;
+            info!("Query matched {} notes", notes.len());
+            return Ok(notes);
+        }
+
+        let cache = self
+            .notes_cache
+            .read()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on notes cache".to_string(),
+            })?;
+        let notes: Vec<Note> = cache
+            .values()
+            .filter(|note| filter.matches(note))
+            .cloned()
+            .collect();
+
+        info!("Query matched {} of {} notes", notes.len(), cache.len());
+        Ok(notes)
+    }
+
+    /// Retrieves the notes matching a filter whose title or content contains
+    /// `text` as written
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - Tag, creation date and update date conditions
+    /// * `text` - Text the title or content must contain, case-sensitively
+    ///
+    /// # Returns
+    ///
+    /// The matching notes, in no particular order
+    pub fn query_notes_containing(&self, filter: NoteFilter, text: &str) -> Result<Vec<Note>> {
+        if self.backend.indexed_queries() {
+            return self.backend.query(&filter, Some(text));
+        }
+        Ok(self
+            .query_notes(filter)?
+            .into_iter()
+            .filter(|note| note.title.contains(text) || note.content.contains(text))
+            .collect())
+    }
+
+    /// Lists one page of the notes matching a filter, in a stable order
+    ///
+    /// Only the notes of the page are cloned out of the cache. Notes saved or
+    /// deleted between two calls are picked up or dropped according to where
+    /// they sort relative to the cursor; the others are never skipped nor
+    /// repeated.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - Conditions the notes must meet
+    /// * `sort` - Order of the listing; ties are broken by ID
+    /// * `cursor` - `next_cursor` of the previous page, or `None` for the first
+    /// * `page_size` - Maximum number of notes in the page
+    ///
+    /// # Returns
+    ///
+    /// The page and the cursor of the next one, or `KbError::InvalidArgument`
+    /// for a zero page size or a cursor from a listing with another order
+    pub fn list_notes_page(
+        &self,
+        filter: &ListFilter,
+        sort: &NoteSort,
+        cursor: Option<String>,
+        page_size: usize,
+    ) -> Result<NotePage> {
+        debug!("Listing a page of {} notes with {:?}", page_size, filter);
+
+        let cache = self
+            .notes_cache
+            .read()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on notes cache".to_string(),
+            })?;
+        let mut candidates: Vec<&Note> =
+            cache.values().filter(|note| filter.matches(note)).collect();
+        if !filter.structure.is_empty() {
+            let summaries = self.summarize_structures(&cache, candidates.iter().copied())?;
+            candidates.retain(|note| {
+                summaries.get(&note.id).is_some_and(|summary| {
+                    filter
+                        .structure
+                        .iter()
+                        .all(|structure| structure.matches(summary))
+                })
+            });
+        }
+
+        let page = paginate_notes(candidates, sort, cursor.as_deref(), page_size)?;
+        info!(
+            "Listed {} notes{}",
+            page.notes.len(),
+            if page.next_cursor.is_some() {
+                ", more to follow"
+            } else {
+                ""
+            }
+        );
+        Ok(page)
+    }
+
+    /// Retrieves all notes associated with a project directory
+    ///
+    /// # Returns
+    ///
+    /// Pairs of note and recorded project path, sorted by path
+    pub fn get_project_notes(&self) -> Result<Vec<(Note, PathBuf)>> {
+        let cache = self
+            .notes_cache
+            .read()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on notes cache".to_string(),
+            })?;
+
+        let mut project_notes: Vec<(Note, PathBuf)> = cache
+            .values()
+            .filter_map(|note| {
+                note.metadata
+                    .get(PROJECT_PATH_KEY)
+                    .map(|path| (note.clone(), PathBuf::from(path)))
+            })
+            .collect();
+
+        project_notes.sort_by(|a, b| a.1.cmp(&b.1));
+        Ok(project_notes)
+    }
+
+    /// Finds the note associated with a directory or its nearest parent
+    ///
+    /// At every level a `.kbnote` marker file naming a note ID wins over notes
+    /// whose recorded project path matches the directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory to start searching from
+    ///
+    /// # Returns
+    ///
+    /// The associated note and the directory it is associated with, if any
+    pub fn find_project_note(&self, dir: &Path) -> Result<Option<(Note, PathBuf)>> {
+        let start = canonical_project_path(dir);
+
+        // Resolve recorded paths once, so symlinked checkouts compare equal
+        let project_notes: Vec<(Note, PathBuf)> = self
+            .get_project_notes()?
+            .into_iter()
+            .map(|(note, path)| (note, canonical_project_path(&path)))
+            .collect();
+
+        for ancestor in start.ancestors() {
+            let marker = ancestor.join(PROJECT_MARKER_FILE);
+            if marker.is_file() {
+                let note_id = fs::read_to_string(&marker)?.trim().to_string();
+                match self.get_note(&note_id) {
+                    Some(note) => return Ok(Some((note, ancestor.to_path_buf()))),
+                    None => warn!(
+                        "Marker {} refers to missing note {}",
+                        marker.display(),
+                        note_id
+                    ),
+                }
+            }
+
+            if let Some((note, _)) = project_notes
+                .iter()
+                .find(|(_, path)| same_project_path(path, ancestor))
+            {
+                return Ok(Some((note.clone(), ancestor.to_path_buf())));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Searches notes by title and content using fuzzy matching, leaving out
+    /// archived notes unless `include_archived` is set
+    ///
+    /// Queries using `AND`, `OR`, `NOT`, parentheses or quoted phrases are
+    /// evaluated as boolean expressions (see [`parse_search_query`]).
+    ///
+    /// # Returns
+    ///
+    /// All matching notes with their relevance scores (title and content
+    /// matches weighted by [`Config::search_weights`]), best first, or
+    /// `KbError::InvalidFormat` when the query can't be parsed
+    pub fn search_notes(&self, query: &str, include_archived: bool) -> Result<SearchResults> {
+        self.search_notes_filtered(
+            query,
+            &SearchRequest {
+                include_archived,
+                ..SearchRequest::default()
+            },
+        )
+    }
+
+    /// Searches like [`NoteStorage::search_notes`], only among the notes
+    /// meeting the conditions of a request, and returns the requested page
+    ///
+    /// Only the notes of the page are cloned out of the cache; the others
+    /// are just counted in [`SearchResults::total`].
+    pub fn search_notes_filtered(
+        &self,
+        query: &str,
+        request: &SearchRequest,
+    ) -> Result<SearchResults> {
+        use fuzzy_matcher::skim::SkimMatcherV2;
+
+        info!("Searching notes with query: '{}'", query);
+        let expr = match request.mode {
+            SearchMode::Fuzzy => parse_search_query(query)?,
+            // Literal queries are looked up in the index like a quoted phrase
+            SearchMode::Exact { .. } => Some(SearchExpr::Phrase(query.to_string())),
+        };
+
+        // Create a fuzzy matcher with default options
+        let matcher = SkimMatcherV2::default();
+        let weights = self.config.search_weights();
+
+        // The search index (when enabled) narrows down the notes to score
+        let candidates = match self.search_index.lock() {
+            Ok(index) => index
+                .as_ref()
+                .and_then(|index| index.candidates(query, expr.as_ref())),
+            Err(e) => {
+                warn!(
+                    "Failed to acquire lock on search index, scanning all notes: {}",
+                    e
+                );
+                None
+            }
+        };
+
+        let cache = self.notes_cache.read().map_err(|err| {
+            error!(
+                "Failed to acquire lock on notes cache during search: {}",
+                err
+            );
+            KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock during search".to_string(),
+            }
+        })?;
+        let notes: Box<dyn Iterator<Item = &Note>> = match &candidates {
+            Some(ids) => {
+                debug!(
+                    "Searching through {} notes from the search index",
+                    ids.len()
+                );
+                Box::new(ids.iter().filter_map(|id| cache.get(id)))
+            }
+            None => {
+                debug!("Searching through {} notes in cache", cache.len());
+                Box::new(cache.values())
+            }
+        };
+
+        // Score the notes by reference, cloning only the page returned
+        let mut matched_notes: Vec<(SearchScore, &Note)> = Vec::new();
+        for note in notes.filter(|note| {
+            (request.include_archived || !note.archived) && request.filter.matches(note)
+        }) {
+            trace!("Checking note: {}", note.id);
+
+            // Title and content matches are weighted as configured
+            let score = match (request.mode, &expr) {
+                (SearchMode::Exact { case_sensitive }, _) => Some(exact_score(
+                    note,
+                    query,
+                    case_sensitive,
+                    request.scope,
+                    weights,
+                ))
+                .filter(|s| s.score > 0),
+                (SearchMode::Fuzzy, Some(expr)) => {
+                    expr.score(note, request.scope, weights, &matcher)
+                }
+                (SearchMode::Fuzzy, None) => {
+                    Some(fuzzy_score(note, query, request.scope, weights, &matcher))
+                        .filter(|s| s.score > 0)
+                }
+            };
+
+            // If we have any match at all, include this note
+            if let Some(score) = score {
+                trace!("Note matched with score {}: {}", score.score, note.id);
+                matched_notes.push((score, note));
+            }
+        }
+
+        if !request.structure.is_empty() {
+            let summaries =
+                self.summarize_structures(&cache, matched_notes.iter().map(|(_, note)| *note))?;
+            matched_notes.retain(|(_, note)| {
+                summaries.get(&note.id).is_some_and(|summary| {
+                    request
+                        .structure
+                        .iter()
+                        .all(|filter| filter.matches(summary))
+                })
+            });
+        }
+
+        let mut hidden = 0;
+        if request.hide_excluded_by_policy {
+            let before = matched_notes.len();
+            matched_notes.retain(|(_, note)| !self.tag_policy(note).exclude_from_search_default);
+            hidden = before - matched_notes.len();
+        }
+
+        debug!(
+            "Found {} matching notes before sorting",
+            matched_notes.len()
+        );
+
+        // Sort matched notes by score (highest first), breaking ties by ID so
+        // pages don't overlap
+        matched_notes.sort_by(|a, b| b.0.score.cmp(&a.0.score).then_with(|| a.1.id.cmp(&b.1.id)));
+
+        let total = matched_notes.len();
+        let hits: Vec<SearchHit> = matched_notes
+            .into_iter()
+            .skip(request.offset)
+            .take(request.limit.unwrap_or(usize::MAX))
+            .map(|(score, note)| SearchHit {
+                note: note.clone(),
+                score: score.score,
+                matched_fields: score.fields,
+            })
+            .collect();
+
+        info!(
+            "Returning {} of {} sorted search results",
+            hits.len(),
+            total
+        );
+        Ok(SearchResults {
+            total,
+            hidden,
+            hits,
+        })
+    }
+
+    /// Suggests existing notes that the given note should probably link to
+    ///
+    /// Candidates already linked from the note (by ID or title) are skipped, and
+    /// at most `MAX_RELATED_CANDIDATES` of the most recently updated notes are
+    /// evaluated to keep the call cheap on large vaults.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note to find related notes for
+    /// * `limit` - Maximum number of suggestions to return
+    ///
+    /// # Returns
+    ///
+    /// Related notes sorted by similarity, or an error if the note doesn't exist
+    pub fn suggest_links(&self, note_id: &str, limit: usize) -> Result<Vec<RelatedNote>> {
+        let cache = self
+            .notes_cache
+            .read()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on notes cache".to_string(),
+            })?;
+
+        let target = cache.get(note_id).ok_or_else(|| KbError::NoteNotFound {
+            id: note_id.to_string(),
+        })?;
+
+        let linked: HashSet<String> = extract_wiki_links(&target.content)
+            .into_iter()
+            .map(|link| link.to_lowercase())
+            .collect();
+
+        let mut candidates: Vec<&Note> = cache
+            .values()
+            .filter(|note| note.id != target.id)
+            .filter(|note| {
+                !linked.contains(&note.id.to_lowercase())
+                    && !linked.contains(&note.title.to_lowercase())
+                    && !note
+                        .aliases
+                        .iter()
+                        .any(|alias| linked.contains(&normalize_alias(alias)))
+            })
+            .collect();
+
+        // Prefer recently touched notes when the vault exceeds the candidate cap
+        candidates.sort_by_key(|note| Reverse(note.updated_at));
+        candidates.truncate(MAX_RELATED_CANDIDATES);
+
+        let related = rank_related(target, &candidates, limit);
+        debug!(
+            "Found {} link suggestions for note {}",
+            related.len(),
+            note_id
+        );
+        Ok(related)
+    }
+
+    /// Helper method to get the directory holding a note's snapshots
+    fn get_snapshot_dir(&self, note_id: &str) -> PathBuf {
+        self.config
+            .notes_dir
+            .join(SNAPSHOTS_DIR)
+            .join(self.id_policy.sanitize(note_id))
+    }
+
+    /// Helper method to get the directory holding a note's revision history
+    fn get_history_dir(&self, note_id: &str) -> PathBuf {
+        self.config
+            .notes_dir
+            .join(HISTORY_DIR)
+            .join(self.id_policy.sanitize(note_id))
+    }
+
+    /// Helper method to get the directory holding a note's attached files
+    fn get_attachment_dir(&self, note_id: &str) -> PathBuf {
+        self.config
+            .notes_dir
+            .join(ATTACHMENTS_DIR)
+            .join(self.id_policy.sanitize(note_id))
+    }
+
+    /// Copies a file into the note's attachment directory and records it in
+    /// the note
+    ///
+    /// A file whose name is already attached to the note is stored under a
+    /// numbered name (`scan-2.pdf`) instead of replacing the existing one.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note to attach the file to
+    /// * `path` - The file to attach
+    ///
+    /// # Returns
+    ///
+    /// The new attachment, or an error if the note or the file doesn't exist
+    pub fn add_attachment(&self, note_id: &str, path: &Path) -> Result<Attachment> {
+        let mut note = self
+            .get_note(note_id)
+            .ok_or_else(|| KbError::NoteNotFound {
+                id: note_id.to_string(),
+            })?;
+
+        if !path.is_file() {
+            return Err(KbError::FileNotFound {
+                file_path: path.display().to_string(),
+            });
+        }
+        let source_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| KbError::InvalidArgument {
+                message: format!("{} has no file name", path.display()),
+            })?;
+        let filename = unique_attachment_name(&note.attachments, &source_name);
+
+        // Copy through a temporary file so a failed copy leaves nothing behind
+        let dir = self.get_attachment_dir(&note.id);
+        fs::create_dir_all(&dir)?;
+        let target = dir.join(&filename);
+        let mut temp_file = NamedTempFile::new_in(&dir)?;
+        let size = std::io::copy(&mut File::open(path)?, &mut temp_file)?;
+        temp_file.flush()?;
+        self.files.sync_file_before_persist(temp_file.as_file())?;
+        temp_file
+            .persist(&target)
+            .map_err(|e| KbError::Io(e.error))?;
+        self.files.sync_dir_after_persist(&target)?;
+
+        let attachment = Attachment {
+            path: format!(
+                "{}/{}/{}",
+                ATTACHMENTS_DIR,
+                self.id_policy.sanitize(&note.id),
+                filename
+            ),
+            filename,
+            mime_type: mime_type_for_path(path).to_string(),
+            size,
+            added_at: Utc::now(),
+        };
+        note.attachments.push(attachment.clone());
+        note.updated_at = attachment.added_at;
+        if let Err(e) = self.update_note(note) {
+            // Don't leave an unreferenced copy behind
+            let _ = fs::remove_file(&target);
+            return Err(e);
+        }
+
+        info!(
+            "Attached {} to note {} as {}",
+            path.display(),
+            note_id,
+            attachment.filename
+        );
+        Ok(attachment)
+    }
+
+    /// Removes an attachment from a note and deletes its stored file
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note the file is attached to
+    /// * `filename` - File name of the attachment
+    ///
+    /// # Returns
+    ///
+    /// The removed attachment, or an error if the note has no such attachment
+    pub fn remove_attachment(&self, note_id: &str, filename: &str) -> Result<Attachment> {
+        let mut note = self
+            .get_note(note_id)
+            .ok_or_else(|| KbError::NoteNotFound {
+                id: note_id.to_string(),
+            })?;
+
+        let position = note
+            .attachments
+            .iter()
+            .position(|a| a.filename == filename)
+            .ok_or_else(|| KbError::AttachmentNotFound {
+                note_id: note_id.to_string(),
+                filename: filename.to_string(),
+            })?;
+        let attachment = note.attachments.remove(position);
+        note.updated_at = Utc::now();
+        self.update_note(note)?;
+
+        let dir = self.get_attachment_dir(note_id);
+        match fs::remove_file(dir.join(&attachment.filename)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(KbError::Io(e)),
+            _ => {}
+        }
+        self.files.cleanup_empty_directory(&dir);
+
+        info!(
+            "Removed attachment {} from note {}",
+            attachment.filename, note_id
+        );
+        Ok(attachment)
+    }
+
+    /// Returns where an attachment of a note is stored
+    pub fn attachment_file_path(&self, attachment: &Attachment) -> PathBuf {
+        self.config.notes_dir.join(&attachment.path)
+    }
+
+    /// Stores an immutable named snapshot of a note
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note to snapshot
+    /// * `name` - Snapshot name, restricted to a safe file name character set
+    ///
+    /// # Returns
+    ///
+    /// The created snapshot, or an error if the note doesn't exist, the name is
+    /// invalid, or a snapshot with the same name already exists
+    pub fn create_snapshot(&self, note_id: &str, name: &str) -> Result<NoteSnapshot> {
+        validate_snapshot_name(name)?;
+
+        let note = self
+            .get_note(note_id)
+            .ok_or_else(|| KbError::NoteNotFound {
+                id: note_id.to_string(),
+            })?;
+
+        let snapshot = NoteSnapshot {
+            name: name.to_string(),
+            created_at: Utc::now(),
+            note,
+        };
+        self.write_snapshot(note_id, &snapshot)?;
+
+        info!("Created snapshot '{}' of note {}", name, note_id);
+        Ok(snapshot)
+    }
+
+    /// Writes a snapshot file, failing if the note already has one with that name
+    fn write_snapshot(&self, note_id: &str, snapshot: &NoteSnapshot) -> Result<()> {
+        validate_snapshot_name(&snapshot.name)?;
+
+        let snapshot_dir = self.get_snapshot_dir(note_id);
+        fs::create_dir_all(&snapshot_dir).map_err(|e| {
+            error!(
+                "Failed to create snapshot directory {}: {}",
+                snapshot_dir.display(),
+                e
+            );
+            KbError::Io(e)
+        })?;
+
+        let json = serde_json::to_string_pretty(snapshot)?;
+
+        // create_new makes the existence check and the write a single step
+        let snapshot_path = snapshot_dir.join(format!("{}.json", snapshot.name));
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&snapshot_path)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::AlreadyExists => KbError::ApplicationError {
+                    message: format!(
+                        "Snapshot '{}' already exists for note {}",
+                        snapshot.name, note_id
+                    ),
+                },
+                _ => KbError::Io(e),
+            })?;
+        file.write_all(json.as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Lists the snapshots of a note, oldest first
+    pub fn list_snapshots(&self, note_id: &str) -> Result<Vec<NoteSnapshot>> {
+        let snapshot_dir = self.get_snapshot_dir(note_id);
+        if !snapshot_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut snapshots = Vec::new();
+        for entry in fs::read_dir(&snapshot_dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                match fs::read_to_string(&path)
+                    .map_err(KbError::Io)
+                    .and_then(|json| serde_json::from_str(&json).map_err(KbError::from))
+                {
+                    Ok(snapshot) => snapshots.push(snapshot),
+                    Err(e) => warn!("Skipping unreadable snapshot {}: {}", path.display(), e),
+                }
+            }
+        }
+
+        snapshots.sort_by_key(|snapshot: &NoteSnapshot| snapshot.created_at);
+        Ok(snapshots)
+    }
+
+    /// Loads a single named snapshot of a note
+    pub fn get_snapshot(&self, note_id: &str, name: &str) -> Result<NoteSnapshot> {
+        validate_snapshot_name(name)?;
+
+        let snapshot_path = self
+            .get_snapshot_dir(note_id)
+            .join(format!("{}.json", name));
+        if !snapshot_path.exists() {
+            return Err(KbError::ApplicationError {
+                message: format!("Snapshot '{}' not found for note {}", name, note_id),
+            });
+        }
+
+        let json = fs::read_to_string(&snapshot_path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// Deletes every snapshot of a note
+    ///
+    /// Snapshots outlive their note, even a purged one, so they are only
+    /// removed on request.
+    ///
+    /// # Returns
+    ///
+    /// The number of snapshots deleted
+    pub fn delete_snapshots(&self, note_id: &str) -> Result<usize> {
+        let snapshot_dir = self.get_snapshot_dir(note_id);
+        if !snapshot_dir.exists() {
+            return Ok(0);
+        }
+
+        let count = fs::read_dir(&snapshot_dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+            .count();
+        fs::remove_dir_all(&snapshot_dir)?;
+        self.files
+            .cleanup_empty_directory(&self.config.notes_dir.join(SNAPSHOTS_DIR));
+
+        info!("Deleted {} snapshots of note {}", count, note_id);
+        Ok(count)
+    }
+
+    /// Restores a snapshot into the live note, or into a brand-new note
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the snapshotted note
+    /// * `name` - The snapshot name
+    /// * `as_copy` - Create a new note instead of overwriting the live one
+    ///
+    /// # Returns
+    ///
+    /// The note that now holds the snapshot content
+    pub fn restore_snapshot(&self, note_id: &str, name: &str, as_copy: bool) -> Result<Note> {
+        let snapshot = self.get_snapshot(note_id, name)?;
+
+        if as_copy {
+            let mut copy = Note::new(
+                format!("{} ({})", snapshot.note.title, snapshot.name),
+                snapshot.note.content,
+                snapshot.note.tags,
+            );
+            copy.aliases.clear();
+            self.save_note(&copy)?;
+            info!(
+                "Restored snapshot '{}' of note {} as new note {}",
+                name, note_id, copy.id
+            );
+            return Ok(copy);
+        }
+
+        let restored = match self.get_note(note_id) {
+            Some(mut live) => {
+                live.title = snapshot.note.title;
+                live.content = snapshot.note.content;
+                live.tags = snapshot.note.tags;
+                live.updated_at = Utc::now();
+                self.update_note(live.clone())?;
+                live
+            }
+            None => {
+                // The live note is gone; bring it back as it was
+                self.save_note(&snapshot.note)?;
+                snapshot.note
+            }
+        };
+
+        info!("Restored snapshot '{}' into note {}", name, note_id);
+        Ok(restored)
+    }
+
+    /// Keeps the current version of a note in its revision history, then
+    /// drops the oldest revisions beyond `max_revisions`
+    fn record_revision(&self, note: &Note) -> Result<()> {
+        let history_dir = self.get_history_dir(&note.id);
+        fs::create_dir_all(&history_dir).map_err(|e| {
+            error!(
+                "Failed to create history directory {}: {}",
+                history_dir.display(),
+                e
+            );
+            KbError::Io(e)
+        })?;
+
+        let timestamp = note.updated_at.timestamp_millis();
+        let mut temp_file = NamedTempFile::new_in(&history_dir)?;
+        temp_file.write_all(serde_json::to_string_pretty(note)?.as_bytes())?;
+        self.files.sync_file_before_persist(temp_file.as_file())?;
+        temp_file
+            .persist(history_dir.join(format!("{}.json", timestamp)))
+            .map_err(|e| KbError::Io(e.error))?;
+        debug!("Recorded revision {} of note {}", timestamp, note.id);
+
+        let keep = self.config.max_revisions as usize;
+        if keep > 0 {
+            let timestamps = self.revision_timestamps(&note.id)?;
+            for old in &timestamps[..timestamps.len().saturating_sub(keep)] {
+                let path = history_dir.join(format!("{}.json", old));
+                if let Err(e) = fs::remove_file(&path) {
+                    warn!("Failed to remove old revision {}: {}", path.display(), e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the keys of a note's revisions, oldest first
+    fn revision_timestamps(&self, note_id: &str) -> Result<Vec<i64>> {
+        let history_dir = self.get_history_dir(note_id);
+        if !history_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut timestamps: Vec<i64> = fs::read_dir(&history_dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|path| path.file_stem()?.to_str()?.parse().ok())
+            .collect();
+        timestamps.sort_unstable();
+        Ok(timestamps)
+    }
+
+    /// Lists the previous versions of a note, oldest first
+    ///
+    /// A version is kept every time the note is updated, up to
+    /// `max_revisions` per note. The history outlives the note itself.
+    pub fn list_revisions(&self, note_id: &str) -> Result<Vec<NoteRevision>> {
+        let mut revisions = Vec::new();
+        for timestamp in self.revision_timestamps(note_id)? {
+            match self.get_revision(note_id, timestamp) {
+                Ok(revision) => revisions.push(revision),
+                Err(e) => warn!(
+                    "Skipping unreadable revision {} of note {}: {}",
+                    timestamp, note_id, e
+                ),
+            }
+        }
+        Ok(revisions)
+    }
+
+    /// Loads a single revision of a note
+    pub fn get_revision(&self, note_id: &str, timestamp: i64) -> Result<NoteRevision> {
+        let path = self
+            .get_history_dir(note_id)
+            .join(format!("{}.json", timestamp));
+        if !path.exists() {
+            return Err(KbError::ApplicationError {
+                message: format!("Revision {} not found for note {}", timestamp, note_id),
+            });
+        }
+
+        let json = fs::read_to_string(&path)?;
+        Ok(NoteRevision {
+            timestamp,
+            note: serde_json::from_str(&json)?,
+        })
+    }
+
+    /// Brings back the title, content and tags of a previous version
+    ///
+    /// The current version is kept as a new revision first, so a restore can
+    /// itself be undone. A deleted note is recreated as it was.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note
+    /// * `timestamp` - Key of the revision, as listed by [`Self::list_revisions`]
+    ///
+    /// # Returns
+    ///
+    /// The note as it is after the restore
+    pub fn restore_revision(&self, note_id: &str, timestamp: i64) -> Result<Note> {
+        let revision = self.get_revision(note_id, timestamp)?;
+
+        let restored = match self.get_note(note_id) {
+            Some(mut live) => {
+                live.title = revision.note.title;
+                live.content = revision.note.content;
+                live.tags = revision.note.tags;
+                live.updated_at = Utc::now();
+                self.update_note(live.clone())?;
+                live
+            }
+            None => {
+                self.save_note(&revision.note)?;
+                revision.note
+            }
+        };
+
+        info!("Restored revision {} of note {}", timestamp, note_id);
+        Ok(restored)
+    }
+
+    /// Imports the per-note backup files written before snapshots existed
+    /// into the snapshot history of their notes
+    ///
+    /// Backups are imported oldest first, named after their kind and
+    /// timestamp. A backup whose title, content and tags match a version
+    /// already in the history is counted as a duplicate, which makes
+    /// re-running the migration safe.
+    ///
+    /// # Arguments
+    ///
+    /// * `disposition` - What to do with imported and duplicate files
+    /// * `dry_run` - Only report what would be imported, changing nothing
+    ///
+    /// # Returns
+    ///
+    /// A report of imported, duplicate and unparseable files
+    pub fn migrate_legacy_history(
+        &self,
+        disposition: LegacyDisposition,
+        dry_run: bool,
+    ) -> Result<HistoryMigrationReport> {
+        let mut report = HistoryMigrationReport::default();
+        if !self.config.backup_dir.exists() {
+            return Ok(report);
+        }
+
+        let mut paths = self
+            .note_backup_files()?
+            .into_iter()
+            .filter(|file| !file.record)
+            .map(|file| file.path)
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        // Parse every recognized file, grouped by the note it belongs to
+        let mut backups: BTreeMap<String, Vec<(LegacyBackupName, PathBuf, Note)>> = BTreeMap::new();
+        for path in paths {
+            let Some(name) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| parse_legacy_backup_name(self.id_policy.as_ref(), name))
+            else {
+                continue;
+            };
+
+            match fs::read_to_string(&path)
+                .map_err(KbError::Io)
+                .and_then(|json| salvage_legacy_note(&json, &name))
+            {
+                Ok(note) => backups
+                    .entry(note.id.clone())
+                    .or_default()
+                    .push((name, path, note)),
+                Err(e) => {
+                    warn!("Skipping unparseable backup {}: {}", path.display(), e);
+                    report.unparseable.push(SkippedLegacyBackup {
+                        path,
+                        reason: e.to_string(),
+                    });
+                }
+            }
+        }
+
+        let mut migrated = Vec::new();
+        for (note_id, mut versions) in backups {
+            versions.sort_by(|a, b| (a.0.timestamp, &a.1).cmp(&(b.0.timestamp, &b.1)));
+
+            let existing = self.list_snapshots(&note_id)?;
+            let mut seen: HashSet<u64> = existing
+                .iter()
+                .map(|snapshot| note_content_hash(&snapshot.note))
+                .collect();
+            let mut names: HashSet<String> =
+                existing.into_iter().map(|snapshot| snapshot.name).collect();
+
+            for (name, path, note) in versions {
+                if !seen.insert(note_content_hash(&note)) {
+                    debug!("Backup {} duplicates an existing version", path.display());
+                    report.duplicates.push(path.clone());
+                    migrated.push(path);
+                    continue;
+                }
+
+                // Backups written within the same second share a base name
+                let base = format!(
+                    "legacy-{}-{}",
+                    name.kind.label(),
+                    name.timestamp.timestamp()
+                );
+                let snapshot_name = (1..)
+                    .map(|n| match n {
+                        1 => base.clone(),
+                        n => format!("{}-{}", base, n),
+                    })
+                    .find(|candidate| !names.contains(candidate))
+                    .unwrap_or(base);
+                names.insert(snapshot_name.clone());
+
+                if !dry_run {
+                    self.write_snapshot(
+                        &note_id,
+                        &NoteSnapshot {
+                            name: snapshot_name.clone(),
+                            created_at: name.timestamp,
+                            note,
+                        },
+                    )?;
+                }
+
+                report.imported.push(ImportedLegacyBackup {
+                    source: path.clone(),
+                    note_id: note_id.clone(),
+                    snapshot: snapshot_name,
+                });
+                migrated.push(path);
+            }
+        }
+
+        if dry_run {
+            return Ok(report);
+        }
+
+        match disposition {
+            LegacyDisposition::Keep => {}
+            LegacyDisposition::Delete => {
+                for path in &migrated {
+                    fs::remove_file(path)?;
+                }
+            }
+            LegacyDisposition::Archive => {
+                let archive_dir = self.config.backup_dir.join(LEGACY_ARCHIVE_DIR);
+                fs::create_dir_all(&archive_dir)?;
+                for path in &migrated {
+                    if let Some(file_name) = path.file_name() {
+                        fs::rename(path, archive_dir.join(file_name))?;
+                    }
+                }
+            }
+        }
+
+        info!(
+            "Migrated legacy history: {} imported, {} duplicates, {} unparseable",
+            report.imported.len(),
+            report.duplicates.len(),
+            report.unparseable.len()
+        );
+        Ok(report)
+    }
+
+    /// Creates a full backup of all notes in a ZIP archive
+    ///
+    /// # Returns
+    ///
+    /// The path to the created backup file in case of success or an error
+    pub fn create_full_backup(&self) -> Result<PathBuf> {
+        // Generate timestamped filename for the backup
+        let timestamp = Utc::now().format(FULL_BACKUP_TIMESTAMP_FORMAT);
+        let backup_filename = format!("{}{}.zip", FULL_BACKUP_PREFIX, timestamp);
+        let backup_path = self.config.backup_dir.join(&backup_filename);
+
+        let manifest = self.create_full_backup_to(&backup_path)?;
+
+        // Later incremental backups build on this one
+        self.write_backup_state(&BackupChainState {
+            base: backup_filename,
+            last_backup_at: manifest.created_at,
+            incrementals: 0,
+        });
+
+        // Clean up old backups if exceeding max_backups
+        self.cleanup_old_backups()?;
+
+        Ok(backup_path)
+    }
+
+    /// Creates an incremental backup in the backup directory, holding the
+    /// notes written since the last backup on top of the latest full backup
+    ///
+    /// Snapshots and attachments written since the last backup are included
+    /// too. The database of the SQLite backend is only part of full backups.
+    ///
+    /// # Returns
+    ///
+    /// The path to the created backup file in case of success or an error,
+    /// also when there is no full backup to build on
+    pub fn create_incremental_backup(&self) -> Result<PathBuf> {
+        let state = self
+            .read_backup_state()
+            .filter(|state| self.config.backup_dir.join(&state.base).is_file())
+            .ok_or_else(|| KbError::BackupFailed {
+                message: "No full backup to build an incremental backup on".to_string(),
+            })?;
+
+        let timestamp = Utc::now().format(FULL_BACKUP_TIMESTAMP_FORMAT);
+        let backup_filename = format!("{}{}.zip", INCREMENTAL_BACKUP_PREFIX, timestamp);
+        let backup_path = self.config.backup_dir.join(backup_filename);
+
+        let manifest = self.write_backup_archive(&backup_path, Some(&state))?;
+        self.write_backup_state(&BackupChainState {
+            base: state.base,
+            last_backup_at: manifest.created_at,
+            incrementals: state.incrementals + 1,
+        });
+
+        Ok(backup_path)
+    }
+
+    /// Creates the backup a scheduled run is due for: a full backup, or with
+    /// `incremental_backups` on, an incremental one until the chain holds
+    /// `full_backup_every` backups
+    ///
+    /// A full backup is taken instead when there is none to build on.
+    ///
+    /// # Returns
+    ///
+    /// The path to the created backup file in case of success or an error
+    pub fn create_scheduled_backup(&self) -> Result<PathBuf> {
+        if self.config.incremental_backups {
+            let chain_open = self.read_backup_state().is_some_and(|state| {
+                state.incrementals + 1 < self.config.full_backup_every
+                    && self.config.backup_dir.join(&state.base).is_file()
+            });
+            if chain_open {
+                return self.create_incremental_backup();
+            }
+        }
+        self.create_full_backup()
+    }
+
+    /// Reads the incremental backup chain state, if a full backup was taken
+    fn read_backup_state(&self) -> Option<BackupChainState> {
+        let path = self.config.backup_dir.join(BACKUP_STATE_FILE);
+        let text = fs::read_to_string(&path).ok()?;
+        match serde_json::from_str(&text) {
+            Ok(state) => Some(state),
+            Err(e) => {
+                warn!("Ignoring unreadable {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Records the incremental backup chain state, logging rather than
+    /// failing the backup that was just written
+    fn write_backup_state(&self, state: &BackupChainState) {
+        let path = self.config.backup_dir.join(BACKUP_STATE_FILE);
+        let written = (|| -> Result<()> {
+            let mut temp_file = NamedTempFile::new_in(&self.config.backup_dir)?;
+            temp_file.write_all(serde_json::to_string_pretty(state)?.as_bytes())?;
+            temp_file.flush()?;
+            self.files.sync_file_before_persist(temp_file.as_file())?;
+            temp_file.persist(&path).map_err(|e| KbError::Io(e.error))?;
+            self.files.sync_dir_after_persist(&path)
+        })();
+        if let Err(e) = written {
+            warn!(
+                "Failed to record the backup chain in {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+
+    /// Creates a full backup of all notes in a ZIP archive at the given path
+    ///
+    /// Backups written outside the backup directory are not subject to the
+    /// `max_backups` limit. Notes whose tag policy excludes them from backups
+    /// are left out, together with their snapshots, and listed in the
+    /// archive's manifest.
+    ///
+    /// # Arguments
+    ///
+    /// * `backup_path` - Where to write the archive; missing parent directories are created
+    ///
+    /// # Returns
+    ///
+    /// The manifest written into the backup in case of success or an error
+    pub fn create_full_backup_to(&self, backup_path: &Path) -> Result<BackupManifest> {
+        self.write_backup_archive(backup_path, None)
+    }
+
+    /// Writes a full backup, or with `chain`, an incremental backup holding
+    /// only what changed since the chain's last backup
+    fn write_backup_archive(
+        &self,
+        backup_path: &Path,
+        chain: Option<&BackupChainState>,
+    ) -> Result<BackupManifest> {
+        // Ensure the target directory exists
+        if let Some(parent) = backup_path.parent() {
+            if !parent.as_os_str().is_empty() && !parent.exists() {
+                fs::create_dir_all(parent).map_err(|e| KbError::BackupFailed {
+                    message: e.to_string(),
+                })?;
+            }
+        }
+
+        // Write the ZIP file next to its final place and rename it there once
+        // complete, so an interrupted backup never leaves a truncated archive
+        let dir = backup_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty());
+        let dir = dir.unwrap_or_else(|| Path::new("."));
+        let file = NamedTempFile::new_in(dir).map_err(|e| KbError::BackupFailed {
+            message: e.to_string(),
+        })?;
+
+        let mut zip = ZipWriter::new(file);
+
+        // Lock the notes cache for reading
+        let notes_cache = self
+            .notes_cache
+            .read()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on notes cache".to_string(),
+            })?;
+
+        let mut manifest = BackupManifest {
+            created_at: Utc::now(),
+            format_version: BACKUP_FORMAT_VERSION,
+            app_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            notes: 0,
+            excluded_notes: Vec::new(),
+            incremental: chain.map(|chain| IncrementalManifest {
+                base: chain.base.clone(),
+                since: chain.last_backup_at,
+                note_ids: Vec::new(),
+            }),
+        };
+        let since = chain.map(|chain| chain.last_backup_at);
+        // Snapshots and attachments go in when written since the last backup
+        let changed_since = |path: &Path| {
+            since.is_none_or(|since| {
+                fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .is_ok_and(|modified| DateTime::<Utc>::from(modified) > since)
+            })
+        };
+
+        // Backups of a database hold the database too, with the notes as JSON;
+        // split notes are archived as JSON too, one entry per note
+        let files_backend = self.backend.kind() == BackendKind::Files;
+        let entry_format = match self.config.storage_format {
+            format @ (StorageFormat::Json | StorageFormat::Markdown) if files_backend => format,
+            _ => StorageFormat::Json,
+        };
+
+        // Notes go into an incremental when their files were written since
+        // the last backup, whatever their updated_at says: restores, imports
+        // and syncs write notes with their original timestamps. A database
+        // has no per-note files, so its incrementals hold every note
+        let note_written_since = |id: &str| {
+            !files_backend
+                || note_file_paths(&self.files.find_note_path(id))
+                    .iter()
+                    .any(|path| !path.exists() || changed_since(path))
+        };
+
+        // Iterate through notes and add each to the ZIP file
+        for (id, note) in notes_cache.iter() {
+            if self.tag_policy(note).exclude_from_backup {
+                manifest.excluded_notes.push(id.clone());
+                continue;
+            }
+            if let Some(incremental) = manifest.incremental.as_mut() {
+                incremental.note_ids.push(id.clone());
+                if !note_written_since(id) {
+                    continue;
+                }
+            }
+            manifest.notes += 1;
+
+            let options = self.backup_entry_options(0o644);
+
+            // Store compressed note files as they are instead of deflating them again
+            let compressed = files_backend
+                .then(|| fs::read(self.files.note_path(id)).ok())
+                .flatten()
+                .filter(|bytes| is_compressed_note(bytes));
+            let (note_bytes, options) = match compressed {
+                Some(bytes) => (
+                    bytes,
+                    options
+                        .compression_method(zip::CompressionMethod::Stored)
+                        .compression_level(None),
+                ),
+                // Serialize the note in the configured storage format
+                None => match entry_format {
+                    StorageFormat::Markdown => (note_to_markdown(note)?.into_bytes(), options),
+                    _ => (serde_json::to_string_pretty(&note)?.into_bytes(), options),
+                },
+            };
+
+            // Add note to the ZIP with folder structure matching the storage organization
+            let note_path = self.get_note_entry_name(id, entry_format);
+
+            // Start a file in the ZIP archive - using the existing ZipError from #[from] trait
+            zip.start_file(note_path, options)?;
+
+            // Write note data to the ZIP file
+            zip.write_all(&note_bytes)
+                .map_err(|e| KbError::BackupFailed {
+                    message: format!("Failed to write note {} content to backup: {}", id, e),
+                })?;
+        }
+
+        // Release the cache before touching the snapshot files
+        drop(notes_cache);
+        manifest.excluded_notes.sort();
+        if let Some(incremental) = manifest.incremental.as_mut() {
+            incremental.note_ids.sort();
+        }
+
+        // Copy the database, then drop the notes the backup leaves out
+        let database = NamedTempFile::new_in(dir)?;
+        if chain.is_none() && self.backend.snapshot(database.path())? {
+            let copy = SqliteBackend::new(database.path().to_path_buf(), FsyncMode::Never);
+            for id in &manifest.excluded_notes {
+                copy.delete(id)?;
+            }
+            copy.release();
+
+            let options = self.backup_entry_options(0o644);
+            zip.start_file(SQLITE_DB_FILE, options)?;
+            let mut input = File::open(database.path())?;
+            std::io::copy(&mut input, &mut zip).map_err(|e| KbError::BackupFailed {
+                message: format!("Failed to write the note database to backup: {}", e),
+            })?;
+        }
+
+        let excluded_snapshot_dirs: HashSet<PathBuf> = manifest
+            .excluded_notes
+            .iter()
+            .map(|id| self.get_snapshot_dir(id))
+            .collect();
+
+        // Include note snapshots, keeping their layout under .snapshots/
+        let snapshots_root = self.config.notes_dir.join(SNAPSHOTS_DIR);
+        for entry in WalkDir::new(&snapshots_root)
+            .min_depth(2)
+            .max_depth(2)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_file() && changed_since(e.path()))
+            .filter(|e| {
+                e.path()
+                    .parent()
+                    .is_none_or(|dir| !excluded_snapshot_dirs.contains(dir))
+            })
+        {
+            let relative = entry
+                .path()
+                .strip_prefix(&self.config.notes_dir)
+                .unwrap_or(entry.path());
+            let entry_name = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            let options = self.backup_entry_options(0o444);
+
+            zip.start_file(entry_name, options)?;
+            let bytes = fs::read(entry.path())?;
+            zip.write_all(&bytes).map_err(|e| KbError::BackupFailed {
+                message: format!(
+                    "Failed to write snapshot {} to backup: {}",
+                    entry.path().display(),
+                    e
+                ),
+            })?;
+        }
+
+        // Include attached files, keeping their layout under attachments/
+        let excluded_attachment_dirs: HashSet<PathBuf> = manifest
+            .excluded_notes
+            .iter()
+            .map(|id| self.get_attachment_dir(id))
+            .collect();
+        let attachments_root = self.config.notes_dir.join(ATTACHMENTS_DIR);
+        for entry in WalkDir::new(&attachments_root)
+            .min_depth(2)
+            .max_depth(2)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_file() && changed_since(e.path()))
+            .filter(|e| {
+                e.path()
+                    .parent()
+                    .is_none_or(|dir| !excluded_attachment_dirs.contains(dir))
+            })
+        {
+            let entry_name = format!(
+                "{}/{}/{}",
+                ATTACHMENTS_DIR,
+                entry
+                    .path()
+                    .parent()
+                    .and_then(Path::file_name)
+                    .unwrap_or_default()
+                    .to_string_lossy(),
+                entry.file_name().to_string_lossy()
+            );
+
+            let options = self.backup_entry_options(0o644);
+
+            zip.start_file(entry_name, options)?;
+            let mut input = File::open(entry.path())?;
+            std::io::copy(&mut input, &mut zip).map_err(|e| KbError::BackupFailed {
+                message: format!(
+                    "Failed to write attachment {} to backup: {}",
+                    entry.path().display(),
+                    e
+                ),
+            })?;
+        }
+
+        // Keep the configuration the backup was taken with
+        let options = self.backup_entry_options(0o644);
+        zip.start_file(BACKUP_CONFIG_ENTRY, options.clone())?;
+        zip.write_all(serde_json::to_string_pretty(&self.config.without_secrets())?.as_bytes())
+            .map_err(|e| KbError::BackupFailed {
+                message: format!("Failed to write configuration to backup: {}", e),
+            })?;
+
+        // Record what the backup contains and which notes were left out
+        zip.start_file(BACKUP_MANIFEST_ENTRY, options)?;
+        zip.write_all(serde_json::to_string_pretty(&manifest)?.as_bytes())
+            .map_err(|e| KbError::BackupFailed {
+                message: format!("Failed to write backup manifest: {}", e),
+            })?;
+
+        // Finalize the ZIP file and move it into place
+        let file = zip.finish()?;
+        file.persist(backup_path)
+            .map_err(|e| KbError::BackupFailed {
+                message: format!(
+                    "Failed to move backup into place at {}: {}",
+                    backup_path.display(),
+                    e.error
+                ),
+            })?;
+
+        info!(
+            "{} backup created successfully with {} notes ({} excluded by tag policy) at {}",
+            if chain.is_some() {
+                "Incremental"
+            } else {
+                "Full"
+            },
+            manifest.notes,
+            manifest.excluded_notes.len(),
+            backup_path.display()
+        );
+
+        Ok(manifest)
+    }
+
+    /// Reads the manifest of a full backup
+    ///
+    /// # Arguments
+    ///
+    /// * `backup_path` - Path to the backup ZIP file
+    ///
+    /// # Returns
+    ///
+    /// The manifest, or `None` for backups written before manifests were added
+    pub fn read_backup_manifest(&self, backup_path: &Path) -> Result<Option<BackupManifest>> {
+        let mut archive = self.open_backup_archive(backup_path)?;
+        let manifest = match archive.by_name(BACKUP_MANIFEST_ENTRY) {
+            Ok(entry) => entry,
+            Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(serde_json::from_reader(manifest)?))
+    }
+
+    /// Reads the manifest of a backup about to be restored, refusing backups
+    /// written in a newer layout than this version understands
+    ///
+    /// Backups without a manifest predate it and are restored as legacy
+    /// backups, holding nothing but notes, snapshots and attachments.
+    pub fn read_compatible_manifest(&self, backup_path: &Path) -> Result<Option<BackupManifest>> {
+        let Some(manifest) = self.read_backup_manifest(backup_path)? else {
+            info!(
+                "{} has no manifest; restoring it as a legacy backup",
+                backup_path.display()
+            );
+            return Ok(None);
+        };
+        if manifest.format_version > BACKUP_FORMAT_VERSION {
+            return Err(KbError::RestoreFailed {
+                message: format!(
+                    "{} was written by kbnotes {} in backup format {}, newer than the supported format {}",
+                    backup_path.display(),
+                    manifest.app_version.as_deref().unwrap_or("(unknown version)"),
+                    manifest.format_version,
+                    BACKUP_FORMAT_VERSION
+                ),
+            });
+        }
+        Ok(Some(manifest))
+    }
+
+    /// Reads the configuration a backup was taken with, or `None` for
+    /// backups written before it was included
+    pub fn read_backup_config(&self, backup_path: &Path) -> Result<Option<Config>> {
+        let mut archive = self.open_backup_archive(backup_path)?;
+        let config = match archive.by_name(BACKUP_CONFIG_ENTRY) {
+            Ok(entry) => entry,
+            Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(serde_json::from_reader(config)?))
+    }
+
+    /// Resolves the tag policy applying to a note
+    pub fn tag_policy(&self, note: &Note) -> EffectiveTagPolicy {
+        self.config.tags.policy_for(&note.tags)
+    }
+
+    /// Archives every note whose tag policy asks for it and that has not been
+    /// updated for the configured number of days
+    ///
+    /// Archiving marks the note archived, adds the [`ARCHIVED_TAG`] tag and
+    /// records the time under [`ARCHIVED_AT_KEY`]; notes that are already
+    /// archived, and locked notes, are left alone.
+    ///
+    /// # Arguments
+    ///
+    /// * `dry_run` - Only report the notes that would be archived
+    ///
+    /// # Returns
+    ///
+    /// The notes archived (or due for archiving), oldest update first
+    pub fn apply_tag_policies(&self, dry_run: bool) -> Result<Vec<Note>> {
+        let now = Utc::now();
+        // Only the notes due are cloned out of the cache
+        let mut due: Vec<Note> = {
+            let cache = self
+                .notes_cache
+                .read()
+                .map_err(|_| KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock on notes cache".to_string(),
+                })?;
+            cache
+                .values()
+                .filter(|note| {
+                    !note.archived && !note.locked && !note.metadata.contains_key(ARCHIVED_AT_KEY)
+                })
+                .filter(|note| {
+                    self.tag_policy(note)
+                        .auto_archive_after_days
+                        .is_some_and(|days| {
+                            now - note.updated_at >= chrono::Duration::days(days.into())
+                        })
+                })
+                .cloned()
+                .collect()
+        };
+        due.sort_by_key(|note| note.updated_at);
+
+        if dry_run {
+            return Ok(due);
+        }
+
+        for note in &due {
+            let mut archived = note.clone();
+            if !archived
+                .tags
+                .iter()
+                .any(|tag| tag.eq_ignore_ascii_case(ARCHIVED_TAG))
+            {
+                archived.tags.push(ARCHIVED_TAG.to_string());
+            }
+            archived.archived = true;
+            archived
+                .metadata
+                .insert(ARCHIVED_AT_KEY.to_string(), now.to_rfc3339());
+            self.update_note(archived)?;
+            info!("Archived note {} by tag policy", note.id);
+        }
+        Ok(due)
+    }
+
+    /// Locks a note against changes and deletion, or unlocks it
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note
+    /// * `locked` - Whether the note should be locked
+    ///
+    /// # Returns
+    ///
+    /// The note as saved, or `None` when it already was in the requested state
+    pub fn set_locked(&self, note_id: &str, locked: bool) -> Result<Option<Note>> {
+        let mut note = self
+            .get_note(note_id)
+            .ok_or_else(|| KbError::NoteNotFound {
+                id: note_id.to_string(),
+            })?;
+        if note.locked == locked {
+            return Ok(None);
+        }
+
+        note.locked = locked;
+        note.updated_at = Utc::now();
+        self.force_update_note(note.clone())?;
+
+        info!(
+            "{} note {}",
+            if locked { "Locked" } else { "Unlocked" },
+            note_id
+        );
+        Ok(Some(note))
+    }
+
+    /// Archives a note, or brings an archived note back
+    ///
+    /// Archiving records the time under [`ARCHIVED_AT_KEY`]. Unarchiving
+    /// removes that entry and the [`ARCHIVED_TAG`] tag a tag policy may have
+    /// added, so the note is treated like any other again.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note
+    /// * `archived` - Whether the note should be archived
+    ///
+    /// # Returns
+    ///
+    /// The note as saved, or `None` when it already was in the requested state
+    pub fn set_archived(&self, note_id: &str, archived: bool) -> Result<Option<Note>> {
+        let mut note = self
+            .get_note(note_id)
+            .ok_or_else(|| KbError::NoteNotFound {
+                id: note_id.to_string(),
+            })?;
+        if note.archived == archived {
+            return Ok(None);
+        }
+
+        let now = Utc::now();
+        note.archived = archived;
+        if archived {
+            note.metadata
+                .insert(ARCHIVED_AT_KEY.to_string(), now.to_rfc3339());
+        } else {
+            note.metadata.remove(ARCHIVED_AT_KEY);
+            note.tags
+                .retain(|tag| !tag.eq_ignore_ascii_case(ARCHIVED_TAG));
+        }
+        note.updated_at = now;
+        self.update_note(note.clone())?;
+
+        info!(
+            "{} note {}",
+            if archived { "Archived" } else { "Unarchived" },
+            note_id
+        );
+        Ok(Some(note))
+    }
+
+    /// Checks or unchecks the task on a line of a note's content
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - ID of the note
+    /// * `line` - Line of the task (1-based)
+    ///
+    /// # Returns
+    ///
+    /// The task with its new state, `KbError::NoteNotFound` for an unknown
+    /// note or `KbError::InvalidArgument` when there is no task on that line
+    pub fn toggle_task(&self, note_id: &str, line: usize) -> Result<Task> {
+        let mut note = self
+            .get_note(note_id)
+            .ok_or_else(|| KbError::NoteNotFound {
+                id: note_id.to_string(),
+            })?;
+
+        let (content, task) = toggle_task(&note.content, line)?;
+        note.content = content;
+        note.updated_at = Utc::now();
+        self.update_note(note)?;
+
+        info!(
+            "{} task on line {} of note {}",
+            if task.checked { "Checked" } else { "Unchecked" },
+            line,
+            note_id
+        );
+        Ok(task)
+    }
+
+    /// Lists the backups in the backup directory: the full backup archives
+    /// and the per-note backups and deletion records, grouped by note
+    ///
+    /// # Arguments
+    ///
+    /// * `count_notes` - Open every archive to count the notes it holds
+    ///
+    /// # Returns
+    ///
+    /// The archives newest first, and the notes ordered by their newest backup
+    pub fn list_backups(&self, count_notes: bool) -> Result<BackupInventory> {
+        let mut inventory = BackupInventory {
+            archives: self.backup_archives(count_notes)?,
+            notes: Vec::new(),
+        };
+
+        let mut by_note: HashMap<String, Vec<(NoteBackupFile, u64)>> = HashMap::new();
+        for file in self.note_backup_files()? {
+            let size = fs::metadata(&file.path).map(|m| m.len()).unwrap_or(0);
+            by_note
+                .entry(file.note_id.clone())
+                .or_default()
+                .push((file, size));
+        }
+        // Files written within the same second are ordered by modification time
+        let mut notes: Vec<((DateTime<Utc>, SystemTime), NoteBackupsInfo)> = by_note
+            .into_iter()
+            .map(|(note_id, mut files)| {
+                files.sort_by_key(|(file, _)| Reverse((file.timestamp, file.modified)));
+                let newest = (files[0].0.timestamp, files[0].0.modified);
+                let backups = files
+                    .into_iter()
+                    .map(|(file, size)| NoteBackupInfo {
+                        path: file.path,
+                        created_at: file.timestamp,
+                        size,
+                        deletion_record: file.record,
+                    })
+                    .collect();
+                (newest, NoteBackupsInfo { note_id, backups })
+            })
+            .collect();
+        notes.sort_by(|(a_newest, a), (b_newest, b)| {
+            b_newest
+                .cmp(a_newest)
+                .then_with(|| a.note_id.cmp(&b.note_id))
+        });
+        inventory.notes = notes.into_iter().map(|(_, note)| note).collect();
+        Ok(inventory)
+    }
+
+    /// Lists the full and incremental backup archives in the backup
+    /// directory, newest first
+    fn backup_archives(&self, count_notes: bool) -> Result<Vec<ArchiveBackupInfo>> {
+        let mut archives = Vec::new();
+        if !self.config.backup_dir.exists() {
+            return Ok(archives);
+        }
+
+        for entry in fs::read_dir(&self.config.backup_dir)?.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            let Some((stamp, incremental)) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(parse_backup_archive_name)
+            else {
+                continue;
+            };
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            let created_at =
+                match NaiveDateTime::parse_from_str(stamp, FULL_BACKUP_TIMESTAMP_FORMAT) {
+                    Ok(time) => time.and_utc(),
+                    Err(_) => metadata
+                        .modified()
+                        .map(DateTime::<Utc>::from)
+                        .unwrap_or_default(),
+                };
+            // Incremental backups are always opened, to show their chain
+            let mut base = None;
+            let mut notes = None;
+            if incremental {
+                match self.read_backup_manifest(&path) {
+                    Ok(Some(manifest)) => {
+                        notes = Some(manifest.notes);
+                        base = manifest
+                            .incremental
+                            .map(|chain| self.config.backup_dir.join(chain.base));
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!("Failed to read the manifest of {}: {}", path.display(), e),
+                }
+            } else if count_notes {
+                match self.count_backup_notes(&path) {
+                    Ok(count) => notes = Some(count),
+                    Err(e) => warn!("Failed to count the notes in {}: {}", path.display(), e),
+                }
+            }
+            archives.push(ArchiveBackupInfo {
+                path,
+                created_at,
+                size: metadata.len(),
+                notes,
+                incremental,
+                base,
+            });
+        }
+        archives.sort_by_key(|archive| Reverse((archive.created_at, archive.path.clone())));
+        Ok(archives)
+    }
+
+    /// Counts the notes in a full backup, by its manifest when it has one
+    fn count_backup_notes(&self, backup_path: &Path) -> Result<usize> {
+        if let Some(manifest) = self.read_backup_manifest(backup_path)? {
+            return Ok(manifest.notes);
+        }
+        let mut archive = self.open_backup_archive(backup_path)?;
+        Ok(self.backup_note_entries(&mut archive)?.len())
+    }
+
+    /// Sweeps the backup directory: removes the full backups beyond
+    /// `max_backups`, each note's per-note backups beyond the newest
+    /// `max_note_backups`, and the per-note backups and deletion records of
+    /// deleted notes written at least `orphans_older_than` ago
+    ///
+    /// Notes in the trash can still be restored, so their backups count as
+    /// those of an existing note.
+    ///
+    /// # Arguments
+    ///
+    /// * `orphans_older_than` - Age from which the files of deleted notes are
+    ///   removed (they are kept when `None`)
+    ///
+    /// # Returns
+    ///
+    /// The per-note files that were removed
+    pub fn prune_backups(
+        &self,
+        orphans_older_than: Option<chrono::Duration>,
+    ) -> Result<BackupPruneReport> {
+        self.cleanup_old_backups()?;
 
-        // Log the restoration
-        let backup_time = fs::metadata(backup_files[0].path())
-            .and_then(|meta| meta.modified())
-            .map(|time| {
-                DateTime::<chrono::Local>::from(time)
-                    .format("%Y-%m-%d %H:%M:%S")
-                    .to_string()
-            })
-            .unwrap_or_else(|_| "unknown time".to_string());
+        let mut existing: HashSet<String> = self.backend.list_ids()?.into_iter().collect();
+        existing.extend(self.cached_note_ids()?);
+        existing.extend(
+            self.list_trash()?
+                .into_iter()
+                .map(|trashed| trashed.note.id),
+        );
+
+        let mut files = self.note_backup_files()?;
+        let mut report = BackupPruneReport {
+            moved: self.move_flat_backups(&mut files),
+            ..Default::default()
+        };
+        let mut by_note: BTreeMap<String, Vec<NoteBackupFile>> = BTreeMap::new();
+        for file in files {
+            by_note.entry(file.note_id.clone()).or_default().push(file);
+        }
+
+        let cutoff = orphans_older_than.map(|age| Utc::now() - age);
+        for (note_id, files) in by_note {
+            let (expired, files): (Vec<_>, Vec<_>) = files.into_iter().partition(|file| {
+                !existing.contains(&note_id)
+                    && cutoff.is_some_and(|cutoff| file.timestamp <= cutoff)
+            });
+            report.orphaned.extend(remove_backup_files(
+                expired.into_iter().map(|file| file.path),
+            ));
+            report.pruned.extend(
+                self.remove_excess_backups(files.into_iter().filter(|file| !file.record).collect()),
+            );
+        }
 
         info!(
-            "Note {} successfully restored from backup created at {}",
-            note_id, backup_time
+            "Pruned {} per-note backups beyond max_note_backups and {} files of deleted notes",
+            report.pruned.len(),
+            report.orphaned.len()
         );
+        Ok(report)
+    }
 
-        Ok(restored_note)
+    /// Removes the oldest backups of a note beyond `max_note_backups`,
+    /// logging rather than failing the write that triggered it
+    fn prune_note_backups_logged(&self, note_id: &str) {
+        if self.config.max_note_backups == 0 {
+            return;
+        }
+        match self.note_backup_files() {
+            Ok(files) => {
+                let mut files: Vec<NoteBackupFile> = files
+                    .into_iter()
+                    .filter(|file| file.note_id == note_id)
+                    .collect();
+                self.move_flat_backups(&mut files);
+                files.retain(|file| !file.record);
+                self.remove_excess_backups(files);
+            }
+            Err(e) => warn!("Failed to prune backups of note {}: {}", note_id, e),
+        }
     }
 
-    /// Retrieves a note by its ID from the storage
-    /// Returns Some(Note) if found, or None if not found
-    pub fn get_note(&self, note_id: &str) -> Option<Note> {
-        debug!("Retrieving note by ID: {}", note_id);
+    /// Removes all but the newest `max_note_backups` of one note's backups
+    ///
+    /// # Returns
+    ///
+    /// The removed files
+    fn remove_excess_backups(&self, mut files: Vec<NoteBackupFile>) -> Vec<PathBuf> {
+        let keep = self.config.max_note_backups as usize;
+        if keep == 0 || files.len() <= keep {
+            return Vec::new();
+        }
+        files.sort_by_key(|file| Reverse((file.timestamp, file.modified)));
+        remove_backup_files(files.into_iter().skip(keep).map(|file| file.path))
+    }
 
-        // First, try to get from cache
-        match self.notes_cache.lock() {
-            Ok(cache) => {
-                // If found in cache, clone and return it
-                if let Some(note) = cache.get(note_id) {
-                    trace!("Note found in cache: {}", note_id);
-                    return Some(note.clone());
+    /// Directory in the backup directory holding a note's per-note backups
+    fn note_backup_dir(&self, note_id: &str) -> PathBuf {
+        self.config
+            .backup_dir
+            .join(self.id_policy.sanitize(note_id))
+    }
+
+    /// Lists the per-note files in the backup directory: the JSON backups
+    /// written on saves, updates and deletions, and the deletion records
+    ///
+    /// Files are found in their note's directory, and also directly in the
+    /// backup directory where they were written before.
+    fn note_backup_files(&self) -> Result<Vec<NoteBackupFile>> {
+        if !self.config.backup_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.config.backup_dir)?.filter_map(|entry| entry.ok()) {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_file() {
+                files.extend(self.note_backup_file(entry, None));
+            } else if file_type.is_dir() {
+                // Only a note's own files count, so other directories such
+                // as the legacy archive are left alone
+                let dir_name = entry.file_name().to_string_lossy().into_owned();
+                for entry in fs::read_dir(entry.path())?.filter_map(|entry| entry.ok()) {
+                    files.extend(self.note_backup_file(entry, Some(&dir_name)));
                 }
             }
-            Err(e) => {
-                error!("Failed to acquire lock on cache: {}", e);
-                // Fall through to file system check
+        }
+        Ok(files)
+    }
+
+    /// Reads what the name of a file in the backup directory says, if it is
+    /// a per-note file (of the note a directory is named after, when in one)
+    fn note_backup_file(
+        &self,
+        entry: fs::DirEntry,
+        dir_name: Option<&str>,
+    ) -> Option<NoteBackupFile> {
+        let path = entry.path();
+        let name = path.file_name()?.to_str()?;
+        let (note_id, timestamp, record) =
+            match parse_legacy_backup_name(self.id_policy.as_ref(), name) {
+                Some(name) => (name.note_id, name.timestamp, false),
+                None => parse_deletion_record_name(self.id_policy.as_ref(), name)
+                    .map(|(note_id, timestamp)| (note_id, timestamp, true))?,
+            };
+        if dir_name.is_some_and(|dir_name| self.id_policy.sanitize(&note_id) != dir_name) {
+            return None;
+        }
+        let metadata = entry.metadata().ok()?;
+        if !metadata.is_file() {
+            return None;
+        }
+        Some(NoteBackupFile {
+            path,
+            note_id,
+            timestamp,
+            modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            record,
+        })
+    }
+
+    /// Moves per-note files still lying directly in the backup directory into
+    /// their note's directory
+    ///
+    /// # Returns
+    ///
+    /// The number of files moved
+    fn move_flat_backups(&self, files: &mut [NoteBackupFile]) -> usize {
+        let mut moved = 0;
+        for file in files
+            .iter_mut()
+            .filter(|file| file.path.parent() == Some(self.config.backup_dir.as_path()))
+        {
+            let Some(name) = file.path.file_name() else {
+                continue;
+            };
+            let target = self.note_backup_dir(&file.note_id).join(name);
+            match fs::create_dir_all(self.note_backup_dir(&file.note_id))
+                .and_then(|()| fs::rename(&file.path, &target))
+            {
+                Ok(()) => {
+                    debug!("Moved {} to {}", file.path.display(), target.display());
+                    file.path = target;
+                    moved += 1;
+                }
+                Err(e) => warn!("Failed to move backup {}: {}", file.path.display(), e),
             }
         }
+        moved
+    }
 
-        // Not found in cache or couldn't access cache, try to load from disk
-        debug!("Note not found in cache, checking file system: {}", note_id);
-        let file_path = self.get_note_path(note_id);
+    /// Removes the full backups the retention settings let go: those beyond
+    /// the newest `max_backups`, or with `backup_retention` set, those its
+    /// policy doesn't keep
+    ///
+    /// Backups are ranked by the timestamp in their file names, which,
+    /// unlike modification times, survives copying backups between machines.
+    /// Incremental backups whose full backup is gone are removed too.
+    fn cleanup_old_backups(&self) -> Result<()> {
+        let full_backups: Vec<ArchiveBackupInfo> = self
+            .backup_archives(false)?
+            .into_iter()
+            .filter(|archive| !archive.incremental)
+            .collect();
+        let expired: Vec<usize> = match &self.config.backup_retention {
+            Some(retention) => {
+                let times: Vec<DateTime<Utc>> = full_backups
+                    .iter()
+                    .map(|archive| archive.created_at)
+                    .collect();
+                retention.expired(&times, Utc::now())
+            }
+            // If max_backups is 0, keep all backups
+            None if self.config.max_backups == 0 => Vec::new(),
+            // Archives are listed newest first
+            None => (self.config.max_backups as usize..full_backups.len()).collect(),
+        };
 
-        if file_path.exists() {
-            debug!("Note file exists at: {}", file_path.display());
-            match load_note_from_file(&file_path) {
-                Ok(note) => {
-                    // Update cache with the found note
-                    if let Ok(mut cache) = self.notes_cache.lock() {
-                        trace!("Updating cache with note loaded from disk");
-                        cache.insert(note_id.to_string(), note.clone());
+        let mut removed = 0;
+        for &i in &expired {
+            let path = &full_backups[i].path;
+            match fs::remove_file(path) {
+                Ok(()) => {
+                    debug!("Removed old backup: {}", path.display());
+                    removed += 1;
+                }
+                // Continue processing even if we couldn't delete this file
+                Err(e) => warn!("Failed to remove old backup {}: {}", path.display(), e),
+            }
+        }
+        if removed > 0 {
+            debug!(
+                "Cleanup complete: kept {} backups, removed {} old backups",
+                full_backups.len() - removed,
+                removed
+            );
+        }
+
+        // Incremental backups can't be restored without their full backup
+        remove_backup_files(
+            self.backup_archives(false)?
+                .into_iter()
+                .filter(|archive| archive.base.as_ref().is_some_and(|base| !base.exists()))
+                .map(|archive| archive.path),
+        );
+
+        Ok(())
+    }
+
+    /// Get the current backup scheduler status
+    pub async fn get_backup_status(&self) -> BackupSchedulerStatus {
+        let scheduler = self.backup_scheduler.lock().await;
+        scheduler.get_status()
+    }
+
+    /// Manually trigger a backup
+    ///
+    /// A running scheduler takes the backup in its task. Otherwise the full
+    /// backup is taken right away and recorded in the scheduler's status.
+    pub async fn create_backup_now(&self) -> Result<()> {
+        let scheduler = self.backup_scheduler.lock().await;
+        if scheduler.get_status().is_running {
+            return scheduler.create_backup_now().await;
+        }
+
+        let outcome = self.create_full_backup();
+        scheduler.record_run(&outcome);
+        let path = outcome?;
+        info!("Manual backup completed at {}", path.display());
+        scheduler.push_backup(path).await;
+        Ok(())
+    }
+
+    /// Uploads a backup archive to every configured backup target and prunes
+    /// them, recording the outcome in the backup scheduler's status
+    ///
+    /// # Arguments
+    ///
+    /// * `backup_path` - Path to the backup ZIP file
+    ///
+    /// # Returns
+    ///
+    /// The outcome for each target, or an error when no target is configured
+    /// or the archive doesn't exist
+    pub async fn push_backup(&self, backup_path: &Path) -> Result<Vec<BackupPushResult>> {
+        if self.config.backup_targets.is_empty() {
+            return Err(KbError::ConfigError {
+                message: "No backup targets are configured (see `backup_targets`)".to_string(),
+            });
+        }
+        if !backup_path.is_file() {
+            return Err(KbError::BackupFailed {
+                message: format!("Backup file not found: {}", backup_path.display()),
+            });
+        }
+        let scheduler = self.backup_scheduler.lock().await;
+        Ok(scheduler.push_backup(backup_path.to_path_buf()).await)
+    }
+
+    /// Stop the backup scheduler
+    pub async fn stop_backup_scheduler(&self) -> Result<()> {
+        let mut scheduler = self.backup_scheduler.lock().await;
+        scheduler.stop().await
+    }
+
+    /// Reads a full backup ZIP archive without restoring anything
+    ///
+    /// An incremental backup is read together with the full and incremental
+    /// backups it builds on, as [`restore_full_backup`](Self::restore_full_backup)
+    /// would restore it.
+    ///
+    /// # Arguments
+    ///
+    /// * `backup_path` - Path to the backup ZIP file
+    ///
+    /// # Returns
+    ///
+    /// How many notes the backup holds and which of them already exist
+    pub fn preview_full_backup(&self, backup_path: &Path) -> Result<BackupPreview> {
+        let chain = self.backup_chain(backup_path)?;
+        let (_, note_ids) = self.chain_note_entries(&chain)?;
+        let current_notes = self.cached_note_ids()?;
+
+        let mut existing_notes: Vec<String> = note_ids
+            .keys()
+            .filter(|id| current_notes.contains(*id))
+            .cloned()
+            .collect();
+        existing_notes.sort();
+
+        Ok(BackupPreview {
+            backup_file: backup_path.to_path_buf(),
+            total_notes: note_ids.len(),
+            existing_notes,
+        })
+    }
+
+    /// Compares a backup with the notes in the vault
+    ///
+    /// Every note of the backup is read, along the chain of an incremental
+    /// backup. A note in both is changed when its `updated_at` or its
+    /// [`note_content_hash`] differs. Nothing is written.
+    ///
+    /// # Arguments
+    ///
+    /// * `backup_path` - Path to the backup ZIP file
+    ///
+    /// # Returns
+    ///
+    /// The notes only in the backup, only in the vault, and changed, each
+    /// sorted by note ID
+    pub fn diff_backup(&self, backup_path: &Path) -> Result<BackupDiff> {
+        let chain = self.backup_chain(backup_path)?;
+        let (mut archives, note_ids) = self.chain_note_entries(&chain)?;
+        let mut vault: HashMap<String, Note> = self
+            .get_all_notes()?
+            .into_iter()
+            .map(|note| (note.id.clone(), note))
+            .collect();
+
+        let mut diff = BackupDiff {
+            backup_file: backup_path.to_path_buf(),
+            only_in_backup: Vec::new(),
+            only_in_vault: Vec::new(),
+            changed: Vec::new(),
+            unchanged: 0,
+            failed_notes: Vec::new(),
+        };
+        for (note_id, (archive, file_path)) in &note_ids {
+            let backed_up =
+                match self.read_note_from_zip(&mut archives[*archive], file_path, note_id) {
+                    Ok(note) => note,
+                    Err(e) => {
+                        diff.failed_notes.push((note_id.clone(), e.to_string()));
+                        // Still in the backup, so not only in the vault
+                        vault.remove(note_id);
+                        continue;
+                    }
+                };
+            match vault.remove(note_id) {
+                None => diff.only_in_backup.push(BackupDiffEntry {
+                    note_id: backed_up.id,
+                    title: backed_up.title,
+                    updated_at: backed_up.updated_at,
+                }),
+                Some(current) => {
+                    let content_changed =
+                        note_content_hash(&backed_up) != note_content_hash(&current);
+                    if content_changed || backed_up.updated_at != current.updated_at {
+                        diff.changed.push(ChangedNote {
+                            note_id: current.id,
+                            title: current.title,
+                            backup_updated_at: backed_up.updated_at,
+                            vault_updated_at: current.updated_at,
+                            content_changed,
+                        });
                     } else {
-                        warn!("Failed to acquire lock to update cache");
+                        diff.unchanged += 1;
                     }
-                    return Some(note);
                 }
-                Err(e) => {
-                    error!("Error loading note from file: {}", e);
-                    return None;
+            }
+        }
+        diff.only_in_vault = vault
+            .into_values()
+            .map(|note| BackupDiffEntry {
+                note_id: note.id,
+                title: note.title,
+                updated_at: note.updated_at,
+            })
+            .collect();
+
+        diff.only_in_backup
+            .sort_by(|a, b| a.note_id.cmp(&b.note_id));
+        diff.only_in_vault.sort_by(|a, b| a.note_id.cmp(&b.note_id));
+        diff.changed.sort_by(|a, b| a.note_id.cmp(&b.note_id));
+        diff.failed_notes.sort();
+        Ok(diff)
+    }
+
+    /// Reads one note from a backup, along the chain of an incremental
+    /// backup; `None` if the backup doesn't hold it
+    pub fn read_backup_note(&self, backup_path: &Path, note_id: &str) -> Result<Option<Note>> {
+        let chain = self.backup_chain(backup_path)?;
+        let (mut archives, note_ids) = self.chain_note_entries(&chain)?;
+        note_ids
+            .get(note_id)
+            .map(|(archive, file_path)| {
+                self.read_note_from_zip(&mut archives[*archive], file_path, note_id)
+            })
+            .transpose()
+    }
+
+    /// Opens a full backup after checking that it is an existing ZIP file
+    fn open_backup_archive(&self, backup_path: &Path) -> Result<ZipArchive<File>> {
+        if !backup_path.exists() || !backup_path.is_file() {
+            return Err(KbError::BackupFailed {
+                message: format!("Backup file not found: {}", backup_path.display()),
+            });
+        }
+
+        if backup_path.extension().is_none_or(|ext| ext != "zip") {
+            return Err(KbError::ApplicationError {
+                message: format!("Not a valid ZIP file: {}", backup_path.display()),
+            });
+        }
+
+        let backup_file = File::open(backup_path).map_err(|e| KbError::BackupFailed {
+            message: format!("Failed to open backup file: {}", e),
+        })?;
+
+        Ok(ZipArchive::new(backup_file)?)
+    }
+
+    /// Maps the ID of every note in a full backup to its ZIP entry name
+    fn backup_note_entries(
+        &self,
+        archive: &mut ZipArchive<File>,
+    ) -> Result<HashMap<String, String>> {
+        use std::io::Read;
+
+        let mut note_ids = HashMap::new();
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).map_err(|e| KbError::BackupFailed {
+                message: format!("Failed to read ZIP entry: {}", e),
+            })?;
+
+            let file_name = file.name().to_string();
+
+            // Expected format: "xx/xxxxxxxxxxxx.json" (or ".md"), named by the ID policy
+            if let Some(format) = StorageFormat::of_path(Path::new(&file_name)) {
+                let path_parts: Vec<&str> = file_name.split('/').collect();
+                if path_parts.len() == 2 {
+                    let suffix = format!(".{}", format.extension());
+                    if let Some(name) = path_parts[1].strip_suffix(suffix.as_str()) {
+                        let note_id = match self.id_policy.restore(name) {
+                            Some(note_id) => note_id,
+                            // Shortened names only say which note they hold inside
+                            None => {
+                                let mut bytes = Vec::new();
+                                file.read_to_end(&mut bytes)?;
+                                match decode_note_bytes(bytes)
+                                    .and_then(|text| parse_note(&text, format))
+                                {
+                                    Ok(note) => note.id,
+                                    Err(_) => name.to_string(),
+                                }
+                            }
+                        };
+                        note_ids.insert(note_id, file_name.clone());
+                    }
                 }
             }
         }
+        Ok(note_ids)
+    }
 
-        // Not found
-        debug!("Note not found: {}", note_id);
-        None
+    /// Returns the IDs of all cached notes
+    fn cached_note_ids(&self) -> Result<HashSet<String>> {
+        let cache = self
+            .notes_cache
+            .read()
+            .map_err(|_| KbError::LockAcquisitionFailed {
+                message: "Failed to acquire lock on notes cache".to_string(),
+            })?;
+
+        Ok(cache.keys().cloned().collect())
     }
 
-    /// Retrieves all notes with a specific tag
+    /// Restores all notes from a full backup ZIP archive
+    ///
+    /// An incremental backup is restored by applying the full backup it
+    /// builds on and then each incremental backup of the chain in order, up
+    /// to and including this one. Notes deleted along the chain are not
+    /// restored.
     ///
     /// # Arguments
     ///
-    /// * `tag` - The tag to search for
+    /// * `backup_path` - Path to the backup ZIP file to restore from
+    /// * `options` - Whether existing notes are overwritten or replaced,
+    ///   which notes are restored and whether anything is written at all
+    ///
+    /// After writing, the restored notes are read back into the cache, so
+    /// that the cache agrees with disk even where updating it failed.
     ///
     /// # Returns
     ///
-    /// A vector of notes that have the specified tag
-    pub fn get_notes_by_tag(&self, tag: &str) -> Result<Vec<Note>> {
-        info!("Retrieving notes by tag: {}", tag);
+    /// A summary of the restoration process in case of success or an error
+    pub fn restore_full_backup(
+        &self,
+        backup_path: &Path,
+        options: &RestoreOptions,
+    ) -> Result<RestoreBackupSummary> {
+        let RestoreOptions {
+            overwrite: overwrite_existing,
+            replace,
+            ref filter,
+            dry_run,
+        } = *options;
+        if replace && (filter.ids.is_some() || !filter.tags.is_empty()) {
+            return Err(KbError::RestoreFailed {
+                message: "A restore replacing the vault can't be limited to some notes".to_string(),
+            });
+        }
+        let overwrite_existing = overwrite_existing || replace;
+        let started = Instant::now();
+        let chain = self.backup_chain(backup_path)?;
+        for path in &chain {
+            self.read_compatible_manifest(path)?;
+        }
 
-        // Create a normalized version of the tag for comparison
-        let search_tag = tag.trim().to_lowercase();
+        // Track restoration results
+        let mut notes_skipped = 0;
+        let mut notes_filtered = 0;
+        let mut failed_notes = Vec::new();
 
-        // Acquire the lock only to clone the required data
-        let notes_snapshot = {
-            // Scope the lock to this block
-            let cache = self
-                .notes_cache
-                .lock()
-                .map_err(|_| KbError::LockAcquisitionFailed {
-                    message: "Failed to acquire lock on notes cache".to_string(),
-                })?;
+        // Get current notes from cache
+        let current_notes = self.cached_note_ids()?;
+
+        // First pass: Collect all note IDs from the ZIP, or from the newest
+        // archive of the chain holding each note
+        let (mut archives, note_ids) = self.chain_note_entries(&chain)?;
+
+        // Notes only in the vault, which a replacing restore removes
+        let mut local_only: Vec<NoteRestoreAction> = if replace {
+            current_notes
+                .iter()
+                .filter(|id| !note_ids.contains_key(*id))
+                .map(|id| NoteRestoreAction {
+                    note_id: id.clone(),
+                    title: self.get_note(id).map(|note| note.title),
+                    action: RestoreAction::Remove,
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        // Second pass: Read each note, then save them all as one batch
+        let mut notes = Vec::new();
+        let mut actions = Vec::new();
+        for (note_id, (archive, file_path)) in &note_ids {
+            if !filter.matches_id(note_id) {
+                notes_filtered += 1;
+                continue;
+            }
+
+            // Skip existing notes if not overwriting
+            let existing = !overwrite_existing && current_notes.contains(note_id);
+            if existing && filter.tags.is_empty() {
+                notes_skipped += 1;
+                // A dry run reads them too, to check the whole backup
+                let title = dry_run
+                    .then(|| self.read_note_from_zip(&mut archives[*archive], file_path, note_id))
+                    .and_then(|note| note.ok())
+                    .map(|note| note.title);
+                actions.push(NoteRestoreAction {
+                    note_id: note_id.clone(),
+                    title,
+                    action: RestoreAction::Skip,
+                });
+                continue;
+            }
+
+            // Tag filters need the note itself to decide
+            let note = match self.read_note_from_zip(&mut archives[*archive], file_path, note_id) {
+                Ok(note) => note,
+                Err(e) => {
+                    warn!("Failed to restore note {}: {}", note_id, e);
+                    failed_notes.push((note_id.clone(), e.to_string()));
+                    continue;
+                }
+            };
+            if !filter.matches(&note) {
+                notes_filtered += 1;
+            } else if existing {
+                notes_skipped += 1;
+                actions.push(NoteRestoreAction {
+                    note_id: note.id,
+                    title: Some(note.title),
+                    action: RestoreAction::Skip,
+                });
+            } else {
+                notes.push(note);
+            }
+        }
+        let restore_action = |note: &Note| NoteRestoreAction {
+            note_id: note.id.clone(),
+            title: Some(note.title.clone()),
+            action: if current_notes.contains(&note.id) {
+                RestoreAction::Overwrite
+            } else {
+                RestoreAction::Create
+            },
+        };
 
-            debug!("Searching through {} notes in cache", cache.len());
+        if dry_run {
+            let notes_removed = local_only.len();
+            actions.extend(notes.iter().map(restore_action));
+            actions.append(&mut local_only);
+            actions.sort_by(|a, b| a.note_id.cmp(&b.note_id));
+            info!(
+                "Restore dry run of {}: would restore {}, skip {}, filter out {}, remove {}, fail on {} notes",
+                backup_path.display(),
+                notes.len(),
+                notes_skipped,
+                notes_filtered,
+                notes_removed,
+                failed_notes.len()
+            );
+            return Ok(RestoreBackupSummary {
+                backup_file: backup_path.to_path_buf(),
+                dry_run,
+                total_notes: note_ids.len(),
+                notes_restored: notes.len(),
+                notes_skipped,
+                notes_filtered,
+                notes_removed,
+                failed_notes,
+                actions,
+                elapsed_ms: started.elapsed().as_millis() as u64,
+            });
+        }
 
-            // Clone all notes to process outside the lock
-            cache.values().cloned().collect::<Vec<Note>>()
-        }; // Lock is automatically released here when 'cache' goes out of scope
+        let report = self.save_notes(&notes)?;
+        let notes_restored = report.succeeded.len();
+        failed_notes.extend(report.failed);
+        let restored_ids: HashSet<String> = report.succeeded.into_iter().collect();
+        actions.extend(
+            notes
+                .iter()
+                .filter(|note| restored_ids.contains(&note.id))
+                .map(restore_action),
+        );
 
-        // Process the data without holding the lock
-        let matching_notes: Vec<Note> = notes_snapshot
-            .into_iter()
-            .filter(|note| {
-                note.tags
-                    .iter()
-                    .any(|t| t.trim().to_lowercase() == search_tag)
-            })
+        // Local-only notes go to the trash, locked ones included, so that
+        // the vault holds exactly the backup's notes
+        let local_only_ids: Vec<String> = local_only
+            .iter()
+            .map(|action| action.note_id.clone())
             .collect();
+        let report = self.delete_notes(&local_only_ids, true, false)?;
+        let notes_removed = report.succeeded.len();
+        failed_notes.extend(report.failed);
+        let removed_ids: HashSet<&String> = report.succeeded.iter().collect();
+        actions.extend(
+            local_only
+                .into_iter()
+                .filter(|action| removed_ids.contains(&action.note_id)),
+        );
+        actions.sort_by(|a, b| a.note_id.cmp(&b.note_id));
 
-        info!("Found {} notes with tag: {}", matching_notes.len(), tag);
-        Ok(matching_notes)
-    }
-
-    /// Searches notes by title and content using fuzzy matching
-    /// Returns a Vec of Notes sorted by relevance score
-    pub fn search_notes(&self, query: &str) -> Vec<Note> {
-        use fuzzy_matcher::skim::SkimMatcherV2;
-        use fuzzy_matcher::FuzzyMatcher;
-
-        info!("Searching notes with query: '{}'", query);
+        // Saving tolerates cache updates that fail, so the restored notes
+        // are read back to make the cache agree with disk
+        let refreshed = self.refresh_cached_notes(&restored_ids)?;
+        if refreshed > 0 {
+            info!("Refreshed {} cached note(s) after the restore", refreshed);
+        }
 
-        // Create a fuzzy matcher with default options
-        let matcher = SkimMatcherV2::default();
+        for archive in &mut archives {
+            // Bring back snapshots that are missing locally (snapshots are immutable,
+            // so existing ones are never overwritten)
+            self.restore_snapshots_from_zip(archive)?;
 
-        // Structure to hold note and its relevance score
-        struct ScoredNote {
-            note: Note,
-            score: i64,
+            // Attached files come back with their notes, later archives
+            // replacing the files of earlier ones
+            if !filter.skip_attachments {
+                self.restore_attachments_from_zip(archive, &restored_ids)?;
+            }
         }
 
-        match self.notes_cache.lock() {
-            Ok(cache) => {
-                debug!("Searching through {} notes in cache", cache.len());
-                let mut matched_notes: Vec<ScoredNote> = Vec::new();
+        // Restored notes are live again, so they leave the trash
+        self.discard_trash_entries(&restored_ids);
 
-                // Iterate through all notes in the cache
-                for note in cache.values() {
-                    trace!("Checking note: {}", note.id);
+        // Build and return the restoration summary
+        let summary = RestoreBackupSummary {
+            backup_file: backup_path.to_path_buf(),
+            dry_run,
+            total_notes: note_ids.len(),
+            notes_restored,
+            notes_skipped,
+            notes_filtered,
+            notes_removed,
+            failed_notes: failed_notes.clone(),
+            actions,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+        };
 
-                    // Try to match against title first (higher priority)
-                    let title_score = matcher.fuzzy_match(&note.title, query).unwrap_or(0);
+        info!(
+            "Backup restoration complete: restored {}, skipped {}, filtered out {}, removed {}, failed {} notes from {}",
+            notes_restored,
+            notes_skipped,
+            notes_filtered,
+            notes_removed,
+            failed_notes.len(),
+            backup_path.display()
+        );
 
-                    // Try to match against content
-                    let content_score = matcher.fuzzy_match(&note.content, query).unwrap_or(0);
+        Ok(summary)
+    }
 
-                    // Calculate final score - title matches are weighted more heavily
-                    let final_score = title_score * 2 + content_score;
+    /// Lists the archives restoring a backup takes: the backup itself, or for
+    /// an incremental backup, its full backup followed by the incremental
+    /// backups of its chain up to and including it, oldest first
+    fn backup_chain(&self, backup_path: &Path) -> Result<Vec<PathBuf>> {
+        let Some(chain) = self
+            .read_backup_manifest(backup_path)?
+            .and_then(|manifest| {
+                manifest
+                    .incremental
+                    .map(|chain| (manifest.created_at, chain))
+            })
+        else {
+            return Ok(vec![backup_path.to_path_buf()]);
+        };
+        let (created_at, chain) = chain;
+
+        // The chain lives next to the incremental backup
+        let dir = backup_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let base = dir.join(&chain.base);
+        if !base.is_file() {
+            return Err(KbError::RestoreFailed {
+                message: format!(
+                    "The full backup {} that {} builds on is missing",
+                    base.display(),
+                    backup_path.display()
+                ),
+            });
+        }
 
-                    // If we have any match at all, include this note
-                    if final_score > 0 {
-                        trace!("Note matched with score {}: {}", final_score, note.id);
-                        matched_notes.push(ScoredNote {
-                            note: note.clone(),
-                            score: final_score,
-                        });
-                    }
+        let mut incrementals = Vec::new();
+        for entry in fs::read_dir(dir)?.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            let is_incremental = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(parse_backup_archive_name)
+                .is_some_and(|(_, incremental)| incremental);
+            if !is_incremental {
+                continue;
+            }
+            // Archives of other chains, or later in this one, are left out
+            if let Some(manifest) = self.read_backup_manifest(&path)? {
+                let same_chain = manifest
+                    .incremental
+                    .is_some_and(|other| other.base == chain.base);
+                if same_chain && manifest.created_at <= created_at {
+                    incrementals.push((manifest.created_at, path));
                 }
+            }
+        }
+        incrementals.sort();
 
-                debug!(
-                    "Found {} matching notes before sorting",
-                    matched_notes.len()
-                );
-
-                // Sort matched notes by score (highest first)
-                matched_notes.sort_by(|a, b| {
-                    // Reverse ordering to get highest scores first
-                    b.score.cmp(&a.score)
-                });
-
-                // Extract just the notes in sorted order
-                let result: Vec<Note> = matched_notes
-                    .into_iter()
-                    .map(|scored| scored.note)
-                    .collect();
+        let mut archives = vec![base];
+        archives.extend(incrementals.into_iter().map(|(_, path)| path));
+        Ok(archives)
+    }
 
-                info!("Returning {} sorted search results", result.len());
-                result
+    /// Opens the archives of a backup chain and finds the latest version of
+    /// every note it restores
+    fn chain_note_entries(
+        &self,
+        chain: &[PathBuf],
+    ) -> Result<(Vec<ZipArchive<File>>, ChainNoteEntries)> {
+        let mut archives = Vec::with_capacity(chain.len());
+        let mut note_entries = ChainNoteEntries::new();
+        for (i, path) in chain.iter().enumerate() {
+            // Notes deleted before an incremental backup don't come back
+            if let Some(incremental) = self
+                .read_backup_manifest(path)?
+                .and_then(|manifest| manifest.incremental)
+            {
+                let live: HashSet<String> = incremental.note_ids.into_iter().collect();
+                note_entries.retain(|id, _| live.contains(id));
             }
-            Err(err) => {
-                error!(
-                    "Failed to acquire lock on notes cache during search: {}",
-                    err
-                );
-                Vec::new()
+
+            let mut archive = self.open_backup_archive(path)?;
+            for (id, entry) in self.backup_note_entries(&mut archive)? {
+                note_entries.insert(id, (i, entry));
             }
+            archives.push(archive);
         }
+        Ok((archives, note_entries))
     }
 
-    /// Creates a full backup of all notes in a ZIP archive
-    ///
-    /// # Returns
-    ///
-    /// The path to the created backup file in case of success or an error
-    pub fn create_full_backup(&self) -> Result<PathBuf> {
-        // Ensure backup directory exists
-        if !self.config.backup_dir.exists() {
-            fs::create_dir_all(&self.config.backup_dir).map_err(|e| KbError::BackupFailed {
-                message: e.to_string(),
-            })?;
-        }
-
-        // Generate timestamped filename for the backup
-        let timestamp = Utc::now().format("%Y%m%d_%H%M%S");
-        let backup_filename = format!("kbnotes_backup_{}.zip", timestamp);
-        let backup_path = self.config.backup_dir.join(backup_filename);
-
-        // Create a new ZIP file
-        let file = File::create(&backup_path).map_err(|e| KbError::BackupFailed {
-            message: e.to_string(),
-        })?;
+    /// Options of a backup archive entry, compressed as configured
+    fn backup_entry_options(
+        &self,
+        permissions: u32,
+    ) -> FileOptions<'static, zip::write::ExtendedFileOptions> {
+        FileOptions::default()
+            .compression_method(self.config.backup_compression.method())
+            .compression_level(self.config.backup_compression_level)
+            .unix_permissions(permissions)
+    }
 
-        let mut zip = ZipWriter::new(file);
+    /// Helper method to read a single note from the ZIP archive
+    fn read_note_from_zip(
+        &self,
+        archive: &mut ZipArchive<File>,
+        file_path: &str,
+        note_id: &str,
+    ) -> Result<Note> {
+        use std::io::Read;
 
-        // Lock the notes cache for reading
-        let notes_cache = self
-            .notes_cache
-            .lock()
-            .map_err(|_| KbError::LockAcquisitionFailed {
-                message: "Failed to acquire lock on notes cache".to_string(),
+        // Read the note JSON from the ZIP
+        let mut note_file = archive
+            .by_name(file_path)
+            .map_err(|e| KbError::BackupFailed {
+                message: format!("Failed to find note {} in backup: {}", note_id, e),
             })?;
 
-        let notes_count = notes_cache.len();
-
-        // Iterate through notes and add each to the ZIP file
-        for (id, note) in notes_cache.iter() {
-            let options = FileOptions::<zip::write::ExtendedFileOptions>::default()
-                .compression_method(zip::CompressionMethod::Deflated)
-                .unix_permissions(0o644);
-
-            // Serialize note to JSON - using the existing Serialization error via From trait
-            let note_json = serde_json::to_string_pretty(&note)?;
-
-            // Add note to the ZIP with folder structure matching the storage organization
-            let folder_name = &id[..2]; // First 2 chars for subdirectory
-            let note_path = format!("{}/{}.json", folder_name, id);
+        let mut note_bytes = Vec::new();
+        note_file
+            .read_to_end(&mut note_bytes)
+            .map_err(|e| KbError::BackupFailed {
+                message: format!("Failed to read note {} content: {}", note_id, e),
+            })?;
 
-            // Start a file in the ZIP archive - using the existing ZipError from #[from] trait
-            zip.start_file(note_path, options)?;
+        // Deserialize the note, which may have been stored compressed
+        let format = StorageFormat::of_path(Path::new(file_path)).unwrap_or_default();
+        let note = parse_note(&decode_note_bytes(note_bytes)?, format)?;
 
-            // Write note data to the ZIP file
-            zip.write_all(note_json.as_bytes())
-                .map_err(|e| KbError::BackupFailed {
-                    message: format!("Failed to write note {} content to backup: {}", id, e),
-                })?;
+        // Verify note ID matches the expected ID
+        if note.id != note_id {
+            return Err(KbError::ApplicationError {
+                message: format!("Note ID mismatch: expected {}, found {}", note_id, note.id),
+            });
         }
 
-        // Finalize the ZIP file
-        zip.finish()?;
-
-        // Clean up old backups if exceeding max_backups
-        self.cleanup_old_backups()?;
+        Ok(note)
+    }
 
-        info!(
-            "Full backup created successfully with {} notes at {}",
-            notes_count,
-            backup_path.display()
-        );
+    /// Extracts `.snapshots/<id>/<name>.json` entries that don't exist on disk yet
+    fn restore_snapshots_from_zip(&self, archive: &mut ZipArchive<File>) -> Result<usize> {
+        let mut restored = 0;
 
-        Ok(backup_path)
-    }
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            let name = file.name().to_string();
 
-    /// Removes old backup files if the number of backups exceeds the configured limit
-    /// Uses a BinaryHeap for efficient identification of oldest files
-    fn cleanup_old_backups(&self) -> Result<()> {
-        // If max_backups is 0, keep all backups
-        if self.config.max_backups == 0 {
-            return Ok(());
-        }
+            let parts: Vec<&str> = name.split('/').collect();
+            if parts.len() != 3 || parts[0] != SNAPSHOTS_DIR || !parts[2].ends_with(".json") {
+                continue;
+            }
 
-        // Custom wrapper to compare backup files by modification time
-        #[derive(Debug, Eq)]
-        struct BackupFile {
-            path: PathBuf,
-            modified_time: SystemTime,
-        }
+            // Reject entries that would escape the snapshot directory
+            if parts[1..]
+                .iter()
+                .any(|p| p.is_empty() || *p == ".." || *p == ".")
+            {
+                warn!("Skipping suspicious snapshot entry in backup: {}", name);
+                continue;
+            }
 
-        impl PartialEq for BackupFile {
-            fn eq(&self, other: &Self) -> bool {
-                self.modified_time.eq(&other.modified_time)
+            let target = self
+                .config
+                .notes_dir
+                .join(SNAPSHOTS_DIR)
+                .join(parts[1])
+                .join(parts[2]);
+            if target.exists() {
+                continue;
             }
-        }
 
-        impl PartialOrd for BackupFile {
-            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-                Some(self.cmp(other))
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
             }
+            let mut output = File::create(&target)?;
+            std::io::copy(&mut file, &mut output)?;
+            restored += 1;
         }
 
-        impl Ord for BackupFile {
-            // Compare by modified time (newer files are "greater" than older files)
-            fn cmp(&self, other: &Self) -> Ordering {
-                self.modified_time.cmp(&other.modified_time)
-            }
+        if restored > 0 {
+            info!("Restored {} note snapshots from backup", restored);
         }
+        Ok(restored)
+    }
 
-        // Use a min-heap to keep track of the newest backups
-        // By using Reverse, we make this a min-heap where the oldest files are at the top
-        let mut newest_backups: BinaryHeap<Reverse<BackupFile>> =
-            BinaryHeap::with_capacity((self.config.max_backups + 1) as usize);
+    /// Extracts `attachments/<id>/<file>` entries of the restored notes,
+    /// replacing the local copies, and those of other notes that are missing
+    /// on disk
+    fn restore_attachments_from_zip(
+        &self,
+        archive: &mut ZipArchive<File>,
+        restored_ids: &HashSet<String>,
+    ) -> Result<usize> {
+        let restored_dirs: HashSet<String> = restored_ids
+            .iter()
+            .map(|id| self.id_policy.sanitize(id))
+            .collect();
+        let mut restored = 0;
 
-        // Find and process all zip backup files in the backup directory
-        let mut total_backups = 0;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            let name = file.name().to_string();
 
-        for entry in WalkDir::new(&self.config.backup_dir)
-            .max_depth(1) // Only look in the immediate backup directory
-            .into_iter()
-            .filter_map(|entry| entry.ok())
-        {
-            let path = entry.path();
+            let parts: Vec<&str> = name.split('/').collect();
+            if parts.len() != 3 || parts[0] != ATTACHMENTS_DIR {
+                continue;
+            }
 
-            // Only consider zip files that match our backup naming pattern
-            if path.is_file()
-                && path.extension().is_some_and(|ext| ext == "zip")
-                && path
-                    .file_name()
-                    .is_some_and(|name| name.to_string_lossy().starts_with("kbnotes_backup_"))
+            // Reject entries that would escape the attachments directory
+            if parts[1..]
+                .iter()
+                .any(|p| p.is_empty() || *p == ".." || *p == ".")
             {
-                // Get file modification time
-                if let Ok(metadata) = entry.metadata() {
-                    if let Ok(modified_time) = metadata.modified() {
-                        total_backups += 1;
-
-                        // Create a BackupFile entry
-                        let backup_file = BackupFile {
-                            path: path.to_path_buf(),
-                            modified_time,
-                        };
-
-                        // Add to our min-heap
-                        newest_backups.push(Reverse(backup_file));
-
-                        // If we have more than max_backups, remove the oldest one (the top of min-heap)
-                        if newest_backups.len() > self.config.max_backups as usize {
-                            if let Some(Reverse(oldest)) = newest_backups.pop() {
-                                match fs::remove_file(&oldest.path) {
-                                    Ok(_) => {
-                                        debug!("Removed old backup: {}", oldest.path.display());
-                                    }
-                                    Err(e) => {
-                                        warn!(
-                                            "Failed to remove old backup {}: {}",
-                                            oldest.path.display(),
-                                            e
-                                        );
-                                        // Continue processing even if we couldn't delete this file
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+                warn!("Skipping suspicious attachment entry in backup: {}", name);
+                continue;
             }
-        }
 
-        let kept = newest_backups.len();
-        let removed = total_backups - kept;
+            let target = self
+                .config
+                .notes_dir
+                .join(ATTACHMENTS_DIR)
+                .join(parts[1])
+                .join(parts[2]);
+            if target.exists() && !restored_dirs.contains(parts[1]) {
+                continue;
+            }
 
-        if removed > 0 {
-            debug!(
-                "Cleanup complete: kept {} backups, removed {} old backups",
-                kept, removed
-            );
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut output = File::create(&target)?;
+            std::io::copy(&mut file, &mut output)?;
+            restored += 1;
         }
 
-        Ok(())
-    }
-
-    /// Get the current backup scheduler status
-    pub async fn get_backup_status(&self) -> BackupSchedulerStatus {
-        let scheduler = self.backup_scheduler.lock().await;
-        scheduler.get_status()
-    }
-
-    /// Manually trigger a backup
-    pub async fn create_backup_now(&self) -> Result<()> {
-        let scheduler = self.backup_scheduler.lock().await;
-        scheduler.create_backup_now().await
-    }
-
-    /// Stop the backup scheduler
-    pub async fn stop_backup_scheduler(&self) -> Result<()> {
-        let mut scheduler = self.backup_scheduler.lock().await;
-        scheduler.stop().await
+        if restored > 0 {
+            info!("Restored {} attachment files from backup", restored);
+        }
+        Ok(restored)
     }
 
-    /// Restores all notes from a full backup ZIP archive
+    /// Restores a full backup by swapping in a freshly extracted notes directory
+    ///
+    /// The backup is extracted next to the notes directory into
+    /// `<notes_dir>.restore-<timestamp>` and validated against its manifest
+    /// and a sample of its notes before anything is moved. The current notes
+    /// directory is then renamed to `<notes_dir>.pre-restore-<timestamp>` and
+    /// the staged one takes its place, so [`NoteStorage::rollback_restore`]
+    /// can switch back instantly. Afterwards the cache is reloaded and the
+    /// file watcher, if it was running, is restarted on the new directory.
     ///
     /// # Arguments
     ///
     /// * `backup_path` - Path to the backup ZIP file to restore from
-    /// * `overwrite_existing` - Whether to overwrite existing notes or preserve them
     ///
     /// # Returns
     ///
-    /// A summary of the restoration process in case of success or an error
-    pub fn restore_full_backup(
-        &self,
+    /// A summary of the swap in case of success or an error; the notes
+    /// directory is left as it was when validation or the swap fails
+    pub async fn restore_full_backup_staged(
+        &mut self,
         backup_path: &Path,
-        overwrite_existing: bool,
-    ) -> Result<RestoreBackupSummary> {
-        // Ensure the backup file exists and is a ZIP file
-        if !backup_path.exists() || !backup_path.is_file() {
-            return Err(KbError::BackupFailed {
-                message: format!("Backup file not found: {}", backup_path.display()),
+    ) -> Result<StagedRestoreSummary> {
+        let manifest = self.read_compatible_manifest(backup_path)?;
+        if manifest.as_ref().is_some_and(|m| m.incremental.is_some()) {
+            return Err(KbError::RestoreFailed {
+                message: format!(
+                    "{} is an incremental backup; a staged restore needs a full backup",
+                    backup_path.display()
+                ),
             });
         }
-
-        if backup_path.extension().map_or(true, |ext| ext != "zip") {
-            return Err(KbError::ApplicationError {
-                message: format!("Not a valid ZIP file: {}", backup_path.display()),
+        let mut archive = self.open_backup_archive(backup_path)?;
+        let note_entries = self.backup_note_entries(&mut archive)?;
+
+        let timestamp = Utc::now().format("%Y%m%d_%H%M%S").to_string();
+        let staged_dir =
+            self.restore_sibling_dir(&format!("{}{}", STAGED_RESTORE_SUFFIX, timestamp))?;
+        let previous_dir =
+            self.restore_sibling_dir(&format!("{}{}", PRE_RESTORE_SUFFIX, timestamp))?;
+        if staged_dir.exists() || previous_dir.exists() {
+            return Err(KbError::RestoreFailed {
+                message: format!(
+                    "{} already exists, another restore is in progress",
+                    staged_dir.display()
+                ),
             });
         }
 
-        // Open the ZIP archive
-        let backup_file = File::open(backup_path).map_err(|e| KbError::BackupFailed {
-            message: format!("Failed to open backup file: {}", e),
+        fs::create_dir_all(&self.config.notes_dir)?;
+        fs::create_dir_all(&staged_dir).map_err(|_| KbError::DirectoryError {
+            path: staged_dir.clone(),
         })?;
 
-        let mut archive = ZipArchive::new(backup_file)?;
+        // Nothing has been moved yet, so any failure only needs the staged
+        // directory cleaned up
+        let staged = ensure_same_filesystem(&self.config.notes_dir, &staged_dir)
+            .and_then(|_| self.extract_backup_to(&mut archive, &staged_dir))
+            .and_then(|_| validate_staged_restore(&staged_dir, &note_entries, manifest.as_ref()))
+            .and_then(|_| self.prepare_staged_backend(&staged_dir));
+        if let Err(e) = staged {
+            if let Err(cleanup) = fs::remove_dir_all(&staged_dir) {
+                warn!(
+                    "Failed to remove staged restore directory {}: {}",
+                    staged_dir.display(),
+                    cleanup
+                );
+            }
+            return Err(e);
+        }
 
-        // Track restoration results
-        let mut note_ids = HashSet::new();
-        let mut notes_restored = 0;
-        let mut notes_skipped = 0;
-        let mut failed_notes = Vec::new();
+        let notes_restored = self.swap_notes_dir(&staged_dir, &previous_dir).await?;
+        let pruned_dirs = self.prune_restore_dirs();
 
-        // Get current notes from cache
-        let current_notes = {
-            let cache = self
-                .notes_cache
-                .lock()
-                .map_err(|_| KbError::LockAcquisitionFailed {
-                    message: "Failed to acquire lock on notes cache".to_string(),
+        info!(
+            "Staged restore of {} complete: {} notes, previous notes kept in {}",
+            backup_path.display(),
+            notes_restored,
+            previous_dir.display()
+        );
+
+        Ok(StagedRestoreSummary {
+            backup_file: backup_path.to_path_buf(),
+            notes_restored,
+            previous_dir,
+            excluded_notes: manifest.map(|m| m.excluded_notes).unwrap_or_default(),
+            pruned_dirs,
+        })
+    }
+
+    /// Switches back to the notes directory kept aside by the latest staged restore
+    ///
+    /// The current notes directory is not deleted but moved to
+    /// `<notes_dir>.rolled-back-<timestamp>`.
+    ///
+    /// # Returns
+    ///
+    /// A summary of the swap in case of success or an error
+    pub async fn rollback_restore(&mut self) -> Result<RestoreRollbackSummary> {
+        let restored_dir =
+            self.restore_dirs(PRE_RESTORE_SUFFIX)
+                .pop()
+                .ok_or_else(|| KbError::RestoreFailed {
+                    message: "No notes directory from a staged restore to roll back to".to_string(),
                 })?;
 
-            cache.keys().cloned().collect::<HashSet<String>>()
-        };
+        let timestamp = Utc::now().format("%Y%m%d_%H%M%S").to_string();
+        let replaced_dir =
+            self.restore_sibling_dir(&format!("{}{}", ROLLED_BACK_SUFFIX, timestamp))?;
+        if replaced_dir.exists() {
+            return Err(KbError::RestoreFailed {
+                message: format!("{} already exists", replaced_dir.display()),
+            });
+        }
 
-        // First pass: Collect all note IDs from the ZIP
-        for i in 0..archive.len() {
-            let file = archive.by_index(i).map_err(|e| KbError::BackupFailed {
-                message: format!("Failed to read ZIP entry: {}", e),
+        fs::create_dir_all(&self.config.notes_dir)?;
+        ensure_same_filesystem(&self.config.notes_dir, &restored_dir)?;
+
+        let notes = self.swap_notes_dir(&restored_dir, &replaced_dir).await?;
+        let pruned_dirs = self.prune_restore_dirs();
+
+        info!(
+            "Rolled back to {}, replaced notes kept in {}",
+            restored_dir.display(),
+            replaced_dir.display()
+        );
+
+        Ok(RestoreRollbackSummary {
+            restored_dir,
+            replaced_dir,
+            notes,
+            pruned_dirs,
+        })
+    }
+
+    /// Returns `<notes_dir>.<suffix>`, a directory next to the notes directory
+    fn restore_sibling_dir(&self, suffix: &str) -> Result<PathBuf> {
+        let notes_dir = &self.config.notes_dir;
+        let name = notes_dir
+            .file_name()
+            .ok_or_else(|| KbError::RestoreFailed {
+                message: format!(
+                    "Cannot stage a restore next to notes directory {}",
+                    notes_dir.display()
+                ),
             })?;
+        Ok(notes_dir.with_file_name(format!("{}.{}", name.to_string_lossy(), suffix)))
+    }
+
+    /// Lists the directories kept next to the notes directory whose suffix
+    /// starts with `prefix`, oldest first
+    fn restore_dirs(&self, prefix: &str) -> Vec<PathBuf> {
+        let Ok(base) = self.restore_sibling_dir(prefix) else {
+            return Vec::new();
+        };
+        let (Some(parent), Some(base_name)) = (base.parent(), base.file_name()) else {
+            return Vec::new();
+        };
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        let base_name = base_name.to_string_lossy();
+
+        let mut dirs: Vec<PathBuf> = fs::read_dir(parent)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.path().is_dir())
+                    .filter(|entry| entry.file_name().to_string_lossy().starts_with(&*base_name))
+                    .map(|entry| base.with_file_name(entry.file_name()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        // Timestamps sort chronologically by name
+        dirs.sort();
+        dirs
+    }
 
-            let file_name = file.name().to_string();
+    /// Removes the oldest kept directories beyond `max_pre_restore_dirs`
+    fn prune_restore_dirs(&self) -> Vec<PathBuf> {
+        let keep = self.config.max_pre_restore_dirs as usize;
+        if keep == 0 {
+            return Vec::new();
+        }
 
-            // Expected format: "xx/xxxxxxxxxxxx.json"
-            if file_name.ends_with(".json") {
-                let path_parts: Vec<&str> = file_name.split('/').collect();
-                if path_parts.len() == 2 {
-                    if let Some(note_id) = path_parts[1].strip_suffix(".json") {
-                        note_ids.insert(note_id.to_string());
+        let mut pruned = Vec::new();
+        for prefix in [PRE_RESTORE_SUFFIX, ROLLED_BACK_SUFFIX] {
+            let dirs = self.restore_dirs(prefix);
+            let excess = dirs.len().saturating_sub(keep);
+            for dir in dirs.into_iter().take(excess) {
+                match fs::remove_dir_all(&dir) {
+                    Ok(()) => {
+                        debug!("Removed old restore directory {}", dir.display());
+                        pruned.push(dir);
                     }
+                    Err(e) => warn!("Failed to remove {}: {}", dir.display(), e),
                 }
             }
         }
+        pruned
+    }
 
-        // Second pass: Restore each note
-        for note_id in &note_ids {
-            let folder_name = &note_id[..2];
-            let file_path = format!("{}/{}.json", folder_name, note_id);
-
-            // Skip existing notes if not overwriting
-            if !overwrite_existing && current_notes.contains(note_id) {
-                notes_skipped += 1;
+    /// Extracts every note and snapshot of a backup below `target`, then
+    /// carries over the local state directory (read markers and the like)
+    fn extract_backup_to(&self, archive: &mut ZipArchive<File>, target: &Path) -> Result<()> {
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+            if entry.is_dir()
+                || [BACKUP_MANIFEST_ENTRY, BACKUP_CONFIG_ENTRY].contains(&entry.name())
+            {
                 continue;
             }
 
-            // Try to extract and restore the note
-            match self.restore_note_from_zip(&mut archive, &file_path, note_id) {
-                Ok(_) => {
-                    notes_restored += 1;
-                }
-                Err(e) => {
-                    warn!("Failed to restore note {}: {}", note_id, e);
-                    failed_notes.push((note_id.clone(), e.to_string()));
-                }
+            // Reject entries that would escape the staged directory
+            let Some(relative) = entry.enclosed_name() else {
+                warn!("Skipping suspicious entry in backup: {}", entry.name());
+                continue;
+            };
+
+            let path = target.join(relative);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
             }
+            let mut output = File::create(&path)?;
+            std::io::copy(&mut entry, &mut output)?;
         }
 
-        // Build and return the restoration summary
-        let summary = RestoreBackupSummary {
-            backup_file: backup_path.to_path_buf(),
-            total_notes: note_ids.len(),
-            notes_restored,
-            notes_skipped,
-            failed_notes: failed_notes.clone(),
-        };
-
-        info!(
-            "Backup restoration complete: restored {}, skipped {}, failed {} notes from {}",
-            notes_restored,
-            notes_skipped,
-            failed_notes.len(),
-            backup_path.display()
-        );
-
-        Ok(summary)
+        let state_dir = self.config.notes_dir.join(STATE_DIR);
+        if state_dir.is_dir() {
+            copy_dir_recursive(&state_dir, &target.join(STATE_DIR))?;
+        }
+        Ok(())
     }
 
-    /// Helper method to restore a single note from the ZIP archive
-    fn restore_note_from_zip(
-        &self,
-        archive: &mut ZipArchive<File>,
-        file_path: &str,
-        note_id: &str,
-    ) -> Result<()> {
-        use std::io::Read;
-
-        // Read the note JSON from the ZIP
-        let mut note_file = archive
-            .by_name(file_path)
-            .map_err(|e| KbError::BackupFailed {
-                message: format!("Failed to find note {} in backup: {}", note_id, e),
-            })?;
-
-        let mut note_content = String::new();
-        note_file
-            .read_to_string(&mut note_content)
-            .map_err(|e| KbError::BackupFailed {
-                message: format!("Failed to read note {} content: {}", note_id, e),
-            })?;
-
-        // Deserialize the note
-        let note: Note = serde_json::from_str(&note_content)?;
+    /// Leaves the notes of a staged restore in the form the configured backend
+    /// reads: the backup's note files, or its database
+    ///
+    /// Backups hold both. A backup of a vault that used note files has no
+    /// database, which is then built from the note files.
+    fn prepare_staged_backend(&self, staged_dir: &Path) -> Result<()> {
+        let database = staged_dir.join(SQLITE_DB_FILE);
+        let mut staged_config = self.config.clone();
+        staged_config.notes_dir = staged_dir.to_path_buf();
+        let staged_files = FileBackend::new(staged_config.clone());
+
+        if self.backend.kind() == BackendKind::Files {
+            if database.exists() {
+                fs::remove_file(&database)?;
+            }
+            return Ok(());
+        }
 
-        // Verify note ID matches the expected ID
-        if note.id != note_id {
-            return Err(KbError::ApplicationError {
-                message: format!("Note ID mismatch: expected {}, found {}", note_id, note.id),
+        let note_files = staged_files.load_all()?;
+        if let Some((path, e)) = note_files.failed.first() {
+            return Err(KbError::RestoreFailed {
+                message: format!(
+                    "Note file {} in the backup cannot be read: {}",
+                    path.display(),
+                    e
+                ),
             });
         }
+        if !database.exists() {
+            let staged = open_backend(self.backend.kind(), &staged_config, &staged_files);
+            for note in &note_files.notes {
+                staged.save(note)?;
+            }
+            staged.release();
+            debug!(
+                "Built {} from {} staged note files",
+                database.display(),
+                note_files.notes.len()
+            );
+        }
+        for note in &note_files.notes {
+            staged_files.delete(&note.id)?;
+        }
+        Ok(())
+    }
 
-        // Save the note to storage
-        self.save_note(&note)?;
+    /// Moves the notes directory to `aside` and `replacement` into its place,
+    /// then reloads the cache
+    ///
+    /// The file watcher is stopped during the swap and restarted afterwards.
+    /// If the second rename fails, the original directory is put back.
+    ///
+    /// # Returns
+    ///
+    /// The number of notes loaded from the new notes directory
+    async fn swap_notes_dir(&mut self, replacement: &Path, aside: &Path) -> Result<usize> {
+        let notes_dir = self.config.notes_dir.clone();
+        let watching = self.watcher.is_some();
+        self.stop_watcher().await?;
+        self.backend.release();
+
+        let swapped = fs::rename(&notes_dir, aside)
+            .map_err(|e| KbError::RestoreFailed {
+                message: format!(
+                    "Failed to move {} to {}: {}",
+                    notes_dir.display(),
+                    aside.display(),
+                    e
+                ),
+            })
+            .and_then(|_| {
+                fs::rename(replacement, &notes_dir).map_err(|e| {
+                    if let Err(undo) = fs::rename(aside, &notes_dir) {
+                        error!(
+                            "Failed to move {} back to {}: {}",
+                            aside.display(),
+                            notes_dir.display(),
+                            undo
+                        );
+                    }
+                    KbError::RestoreFailed {
+                        message: format!(
+                            "Failed to move {} to {}: {}",
+                            replacement.display(),
+                            notes_dir.display(),
+                            e
+                        ),
+                    }
+                })
+            });
 
-        Ok(())
+        // The cache and the watcher follow whichever directory is in place now
+        let loaded = swapped.and_then(|_| self.load_notes().map(|report| report.loaded));
+        if watching {
+            if let Err(e) = self.init_watcher_with_background_task().await {
+                error!("Failed to restart file watcher: {}", e);
+            }
+        }
+        loaded
     }
 
     /// Initializes the watcher and starts the event handling in the background
@@ -980,6 +5932,12 @@ impl NoteStorage {
             return Ok(());
         }
 
+        // Notes in a database are not edited behind our back
+        if self.backend.kind() != BackendKind::Files {
+            debug!("No file watcher for the {} backend", self.backend.kind());
+            return Ok(());
+        }
+
         // Create a standard mpsc channel for notify crate
         let (std_tx, std_rx) = std_mpsc::channel();
 
@@ -992,20 +5950,20 @@ impl NoteStorage {
             notify::Config::default().with_poll_interval(Duration::from_secs(2)),
         )
         .map_err(|e| {
-            KbError::Io(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Failed to create file watcher: {}", e),
-            ))
+            KbError::Io(std::io::Error::other(format!(
+                "Failed to create file watcher: {}",
+                e
+            )))
         })?;
 
         // Start watching the notes directory
         watcher
             .watch(self.config.notes_dir.as_ref(), RecursiveMode::Recursive)
             .map_err(|e| {
-                KbError::Io(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("Failed to watch directory: {}", e),
-                ))
+                KbError::Io(std::io::Error::other(format!(
+                    "Failed to watch directory: {}",
+                    e
+                )))
             })?;
 
         // Store the watcher in the struct field
@@ -1013,14 +5971,24 @@ impl NoteStorage {
 
         // Set up references for the event handler
         let notes_cache = Arc::clone(&self.notes_cache);
-        // let notes_dir = self.config.notes_dir.clone();
-
-        // Spawn a background task to bridge the standard channel to tokio channel
-        tokio::spawn(async move {
+        let alias_index = Arc::clone(&self.alias_index);
+        let title_index = Arc::clone(&self.title_index);
+        let tag_index = Arc::clone(&self.tag_index);
+        let structure_index = Arc::clone(&self.structure_index);
+        let link_index = Arc::clone(&self.link_index);
+        let search_index = Arc::clone(&self.search_index);
+        let notes_dir = self.config.notes_dir.clone();
+        let events = self.events.clone();
+        let id_policy = Arc::clone(&self.id_policy);
+        let own_changes = self.files.own_changes().clone();
+
+        // Bridge the standard channel to the tokio channel on a blocking thread,
+        // so waiting for file system events never occupies a runtime worker
+        tokio::task::spawn_blocking(move || {
             // This task will run until the std_rx channel is closed
             // (which happens when the watcher is dropped)
             while let Ok(event) = std_rx.recv() {
-                match tx.send(event).await {
+                match tx.blocking_send(event) {
                     Ok(_) => {}
                     Err(e) => {
                         error!("Failed to forward file system event: {}", e);
@@ -1036,12 +6004,39 @@ impl NoteStorage {
             debug!("File system watcher event handler task started");
 
             while let Some(event) = rx.recv().await {
-                match event {
-                    Ok(event) => {
-                        debug!("File system event: {:?}", event.kind);
-                        handle_fs_event(event, &notes_cache).await;
+                // Wait for the burst of events a write causes to settle and
+                // handle it in one go
+                let deadline = tokio::time::Instant::now() + WATCH_DEBOUNCE;
+                let mut batch = Vec::new();
+                let mut next = Some(event);
+                while let Some(event) = next {
+                    match event {
+                        Ok(event) => batch.push(event),
+                        Err(e) => error!("File system watcher error: {}", e),
                     }
-                    Err(e) => error!("File system watcher error: {}", e),
+                    next = tokio::time::timeout_at(deadline, rx.recv())
+                        .await
+                        .ok()
+                        .flatten();
+                }
+
+                for event in coalesce_fs_events(batch) {
+                    debug!("File system event: {:?}", event.kind);
+                    handle_fs_event(
+                        event,
+                        &notes_cache,
+                        &alias_index,
+                        &title_index,
+                        &tag_index,
+                        &structure_index,
+                        &link_index,
+                        &search_index,
+                        &events,
+                        id_policy.as_ref(),
+                        &notes_dir,
+                        &own_changes,
+                    )
+                    .await;
                 }
             }
 
@@ -1055,7 +6050,8 @@ impl NoteStorage {
         Ok(())
     }
 
-    /// Deletes a note from both the file system and the in-memory cache
+    /// Deletes a note from both the file system and the in-memory cache,
+    /// moving it and its attachments to the trash
     ///
     /// # Arguments
     ///
@@ -1063,8 +6059,59 @@ impl NoteStorage {
     ///
     /// # Returns
     ///
-    /// A Result indicating success or an error (e.g., if the note doesn't exist)
+    /// A Result indicating success or an error (e.g., if the note doesn't
+    /// exist, or `KbError::NoteLocked` if it is locked)
     pub fn delete_note(&self, note_id: &str) -> Result<()> {
+        self.delete_note_checked(note_id, false, false)
+    }
+
+    /// Deletes a note like [`NoteStorage::delete_note`], even when it is locked
+    pub fn force_delete_note(&self, note_id: &str) -> Result<()> {
+        self.delete_note_checked(note_id, true, false)
+    }
+
+    /// Deletes a note for good, even when it is locked, without keeping it
+    /// in the trash
+    pub fn delete_note_permanently(&self, note_id: &str) -> Result<()> {
+        self.delete_note_checked(note_id, true, true)
+    }
+
+    /// Deletes several notes, moving them to the trash unless `permanent`
+    ///
+    /// A note that can't be deleted is reported and the others are still
+    /// deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_ids` - The IDs of the notes to delete
+    /// * `force` - Delete locked notes too
+    /// * `permanent` - Delete for good instead of keeping the notes in the trash
+    ///
+    /// # Returns
+    ///
+    /// Which notes were deleted and why the others weren't
+    pub fn delete_notes(
+        &self,
+        note_ids: &[String],
+        force: bool,
+        permanent: bool,
+    ) -> Result<BatchReport> {
+        let mut report = BatchReport::default();
+        for note_id in note_ids {
+            match self.delete_note_checked(note_id, force, permanent) {
+                Ok(()) => report.succeeded.push(note_id.clone()),
+                Err(e) => report.failed.push((note_id.clone(), e.to_string())),
+            }
+        }
+        info!(
+            "Deleted {} of {} notes",
+            report.succeeded.len(),
+            note_ids.len()
+        );
+        Ok(report)
+    }
+
+    fn delete_note_checked(&self, note_id: &str, force: bool, permanent: bool) -> Result<()> {
         info!("Deleting note: {}", note_id);
 
         // First, retrieve the note to make a backup before deletion
@@ -1079,14 +6126,36 @@ impl NoteStorage {
             }
         };
 
+        if note_to_delete.locked && !force {
+            warn!("Refusing to delete locked note {}", note_id);
+            return Err(KbError::NoteLocked {
+                id: note_id.to_string(),
+            });
+        }
+
+        // Links to the note break once it is gone
+        let backlinks = self.get_backlinks(note_id)?;
+        if !backlinks.is_empty() {
+            warn!(
+                "Deleting note {} still linked from: {}",
+                note_id,
+                backlinks
+                    .iter()
+                    .map(|note| note.id.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
         // Create pre-deletion backup if auto_backup is enabled
         if self.config.auto_backup {
             debug!("Creating pre-deletion backup for note: {}", note_id);
 
-            // Ensure backup directory exists
-            if !self.config.backup_dir.exists() {
+            // Ensure the note's backup directory exists
+            let note_backup_dir = self.note_backup_dir(note_id);
+            if !note_backup_dir.exists() {
                 debug!("Creating backup directory for pre-deletion backup");
-                if let Err(e) = fs::create_dir_all(&self.config.backup_dir) {
+                if let Err(e) = fs::create_dir_all(&note_backup_dir) {
                     warn!(
                         "Failed to create backup directory for pre-deletion backup: {}",
                         e
@@ -1097,8 +6166,12 @@ impl NoteStorage {
 
             // Create a timestamped pre-deletion backup
             let timestamp = Utc::now().timestamp();
-            let backup_filename = format!("{}_predeletion_{}.json", note_id, timestamp);
-            let backup_path = self.config.backup_dir.join(backup_filename);
+            let backup_filename = format!(
+                "{}_predeletion_{}.json",
+                self.id_policy.sanitize(note_id),
+                timestamp
+            );
+            let backup_path = note_backup_dir.join(backup_filename);
 
             // Serialize and save the backup
             match serde_json::to_string_pretty(&note_to_delete) {
@@ -1108,6 +6181,7 @@ impl NoteStorage {
                         // Continue with deletion even if backup creation fails
                     } else {
                         debug!("Pre-deletion backup created at: {}", backup_path.display());
+                        self.prune_note_backups_logged(note_id);
                     }
                 }
                 Err(e) => {
@@ -1117,45 +6191,70 @@ impl NoteStorage {
             }
         }
 
-        // Get the file path for the note
-        let file_path = self.get_note_path(note_id);
-
-        // Delete from filesystem
-        if file_path.exists() {
-            debug!("Deleting note file: {}", file_path.display());
-            match fs::remove_file(&file_path) {
-                Ok(_) => {
-                    debug!("Note file deleted successfully");
-                    // Track directories to check for cleanup
-                    let mut dirs_to_check = Vec::new();
+        // Keep a copy in the trash before the file goes, or remember the
+        // note is gone for good
+        if permanent {
+            self.record_purges(&[DeletedNote {
+                id: note_id.to_string(),
+                deleted_at: Utc::now(),
+                purged: true,
+            }])?;
+        } else {
+            self.write_trash_entry(&note_to_delete)?;
+        }
 
-                    // Add parent directory to cleanup list if it's not the root notes directory
-                    if let Some(parent) = file_path.parent() {
-                        if parent != self.config.notes_dir {
-                            dirs_to_check.push(parent.to_path_buf());
-                        }
-                    }
+        if !self.backend.delete(note_id)? {
+            debug!(
+                "Note {} is not stored, only removing it from the cache",
+                note_id
+            );
+        }
 
-                    // Recursively clean up empty parent directories
-                    for dir_path in dirs_to_check {
-                        self.cleanup_empty_directory(&dir_path);
-                    }
-                }
-                Err(e) => {
-                    let error_msg =
-                        format!("Failed to delete note file {}: {}", file_path.display(), e);
-                    error!("{}", error_msg);
-                    return Err(KbError::Io(e));
-                }
+        // Attached files go with the note, into the trash unless deleted for good
+        let attachment_dir = self.get_attachment_dir(note_id);
+        if attachment_dir.exists() {
+            debug!("Deleting attachments in {}", attachment_dir.display());
+            let removed = if permanent {
+                fs::remove_dir_all(&attachment_dir)
+            } else {
+                fs::rename(&attachment_dir, self.get_trashed_attachment_dir(note_id))
+            };
+            if let Err(e) = removed {
+                warn!(
+                    "Failed to delete attachments of note {} in {}: {}",
+                    note_id,
+                    attachment_dir.display(),
+                    e
+                );
+            } else {
+                self.files
+                    .cleanup_empty_directory(&self.config.notes_dir.join(ATTACHMENTS_DIR));
             }
-        } else {
-            debug!("Note file doesn't exist on disk, only removing from cache");
         }
 
         // Remove from cache
-        match self.notes_cache.lock() {
+        if let Ok(mut index) = self.alias_index.lock() {
+            unindex_note_aliases(&mut index, note_id);
+        }
+        if let Ok(mut index) = self.title_index.lock() {
+            unindex_note_title(&mut index, note_id);
+        }
+        if let Ok(mut index) = self.tag_index.lock() {
+            unindex_note_tags(&mut index, note_id);
+        }
+        if let Ok(mut index) = self.structure_index.lock() {
+            index.remove(note_id);
+        }
+        if let Ok(mut index) = self.link_index.lock() {
+            index.remove_note(note_id);
+        }
+        self.unindex_search(note_id);
+        match self.notes_cache.write() {
             Ok(mut cache) => {
                 cache.remove(note_id);
+                if let Ok(mut dirty) = self.dirty_notes.lock() {
+                    dirty.remove(note_id);
+                }
                 debug!("Note removed from cache");
             }
             Err(e) => {
@@ -1167,82 +6266,344 @@ impl NoteStorage {
             }
         }
 
-        // Create a deletion record in the backup directory if auto_backup is enabled
-        if self.config.auto_backup {
-            debug!("Creating deletion record in backup directory");
-            let timestamp = Utc::now().timestamp();
-            let deletion_record_path = self
-                .config
-                .backup_dir
-                .join(format!("{}_deletion_record_{}.txt", note_id, timestamp));
+        // Create a deletion record in the backup directory if auto_backup is enabled
+        if self.config.auto_backup {
+            debug!("Creating deletion record in backup directory");
+            let timestamp = Utc::now().timestamp();
+            let note_backup_dir = self.note_backup_dir(note_id);
+            let deletion_record_path = note_backup_dir.join(format!(
+                "{}_deletion_record_{}.txt",
+                self.id_policy.sanitize(note_id),
+                timestamp
+            ));
+            if let Err(e) = fs::create_dir_all(&note_backup_dir) {
+                warn!(
+                    "Failed to create backup directory for deletion record: {}",
+                    e
+                );
+            }
+
+            // Create a detailed deletion record with metadata
+            let record = format!(
+                "Deletion Record\n\
+                Note ID: {}\n\
+                Title: {}\n\
+                Tags: {}\n\
+                Created at: {}\n\
+                Last updated: {}\n\
+                Deleted at: {}\n\
+                Content length: {} characters",
+                note_to_delete.id,
+                note_to_delete.title,
+                note_to_delete.tags.join(", "),
+                note_to_delete.created_at.to_rfc3339(),
+                note_to_delete.updated_at.to_rfc3339(),
+                Utc::now().to_rfc3339(),
+                note_to_delete.content.len()
+            );
+
+            if let Err(e) = fs::write(&deletion_record_path, record) {
+                warn!("Failed to create deletion record: {}", e);
+                // Continue since this is just a record
+            } else {
+                debug!(
+                    "Deletion record created at: {}",
+                    deletion_record_path.display()
+                );
+            }
+        }
+
+        if let Err(e) = self.update_access_state(|state| {
+            let removed = state.unread.remove(note_id);
+            state.last_viewed.remove(note_id).is_some() || removed
+        }) {
+            warn!("Failed to drop access state of note {}: {}", note_id, e);
+        }
+
+        self.publish_event(NoteEventKind::Deleted, &note_to_delete);
+
+        info!("Note {} successfully deleted", note_id);
+        Ok(())
+    }
+
+    /// Path of a deleted note's file in the trash
+    fn get_trash_path(&self, note_id: &str) -> PathBuf {
+        self.config
+            .notes_dir
+            .join(TRASH_DIR)
+            .join(format!("{}.json", self.id_policy.sanitize(note_id)))
+    }
+
+    /// Directory in the trash keeping a deleted note's attachments
+    fn get_trashed_attachment_dir(&self, note_id: &str) -> PathBuf {
+        self.config.notes_dir.join(TRASH_DIR).join(format!(
+            "{}{}",
+            self.id_policy.sanitize(note_id),
+            TRASHED_ATTACHMENTS_SUFFIX
+        ))
+    }
+
+    /// Writes a note about to be deleted to the trash, recording the time
+    /// of deletion under [`DELETED_AT_KEY`]
+    fn write_trash_entry(&self, note: &Note) -> Result<()> {
+        let trash_path = self.get_trash_path(&note.id);
+        if let Some(trash_dir) = trash_path.parent() {
+            fs::create_dir_all(trash_dir).map_err(|e| {
+                error!(
+                    "Failed to create trash directory {}: {}",
+                    trash_dir.display(),
+                    e
+                );
+                KbError::Io(e)
+            })?;
+        }
+
+        let mut trashed = note.clone();
+        trashed
+            .metadata
+            .insert(DELETED_AT_KEY.to_string(), Utc::now().to_rfc3339());
+        fs::write(&trash_path, serde_json::to_string_pretty(&trashed)?)?;
+        debug!("Moved note {} to {}", note.id, trash_path.display());
+        Ok(())
+    }
+
+    /// Removes the trash entries of notes brought back some other way than
+    /// [`NoteStorage::restore_from_trash`], such as a backup restore
+    ///
+    /// Trashed attachments move back to a note that has none, as when the
+    /// backup was restored without attachments, and are dropped otherwise.
+    /// Entries that can't be removed are logged and left in place.
+    fn discard_trash_entries(&self, note_ids: &HashSet<String>) {
+        for note_id in note_ids {
+            let trash_path = self.get_trash_path(note_id);
+            if !trash_path.exists() {
+                continue;
+            }
+            if let Err(e) = fs::remove_file(&trash_path) {
+                warn!(
+                    "Failed to remove trash entry {}: {}",
+                    trash_path.display(),
+                    e
+                );
+                continue;
+            }
+            let attachments = self.get_trashed_attachment_dir(note_id);
+            let attachment_dir = self.get_attachment_dir(note_id);
+            let result = if !attachments.exists() {
+                Ok(())
+            } else if attachment_dir.exists() {
+                fs::remove_dir_all(&attachments)
+            } else {
+                attachment_dir
+                    .parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|()| fs::rename(&attachments, &attachment_dir))
+            };
+            if let Err(e) = result {
+                warn!(
+                    "Failed to clear trashed attachments in {}: {}",
+                    attachments.display(),
+                    e
+                );
+            }
+            debug!("Note {} is restored, removed it from the trash", note_id);
+        }
+    }
+
+    /// Appends notes deleted for good to the purge journal
+    fn record_purges(&self, purged: &[DeletedNote]) -> Result<()> {
+        if purged.is_empty() {
+            return Ok(());
+        }
+
+        let journal_path = self
+            .config
+            .notes_dir
+            .join(TRASH_DIR)
+            .join(PURGE_JOURNAL_FILE);
+        if let Some(trash_dir) = journal_path.parent() {
+            fs::create_dir_all(trash_dir)?;
+        }
+        let mut lines = String::new();
+        for deleted in purged {
+            lines.push_str(&serde_json::to_string(deleted)?);
+            lines.push('\n');
+        }
+        let mut journal = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&journal_path)?;
+        journal.write_all(lines.as_bytes())?;
+        Ok(())
+    }
+
+    /// Reads the purge journal, skipping lines that can't be parsed
+    fn read_purge_journal(&self) -> Result<Vec<DeletedNote>> {
+        let journal_path = self
+            .config
+            .notes_dir
+            .join(TRASH_DIR)
+            .join(PURGE_JOURNAL_FILE);
+        if !journal_path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut purged = Vec::new();
+        for line in fs::read_to_string(&journal_path)?.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(deleted) => purged.push(deleted),
+                Err(e) => warn!(
+                    "Skipping unreadable line in {}: {}",
+                    journal_path.display(),
+                    e
+                ),
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Lists the notes in the trash, most recently deleted first
+    ///
+    /// Entries that can't be read are skipped with a warning.
+    pub fn list_trash(&self) -> Result<Vec<TrashedNote>> {
+        let trash_dir = self.config.notes_dir.join(TRASH_DIR);
+        if !trash_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut trashed = Vec::new();
+        for entry in fs::read_dir(&trash_dir)? {
+            let path = entry?.path();
+            if !path.is_file() || path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let note: Note = match fs::read_to_string(&path)
+                .map_err(KbError::Io)
+                .and_then(|json| serde_json::from_str(&json).map_err(KbError::from))
+            {
+                Ok(note) => note,
+                Err(e) => {
+                    warn!("Skipping unreadable trash entry {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            // Entries without a readable deletion time count from the file's
+            let deleted_at = note
+                .metadata
+                .get(DELETED_AT_KEY)
+                .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+                .map(|time| time.with_timezone(&Utc))
+                .or_else(|| {
+                    let modified = fs::metadata(&path).and_then(|meta| meta.modified());
+                    modified.ok().map(DateTime::<Utc>::from)
+                })
+                .unwrap_or_else(Utc::now);
+            trashed.push(TrashedNote { note, deleted_at });
+        }
+
+        trashed.sort_by(|a, b| {
+            b.deleted_at
+                .cmp(&a.deleted_at)
+                .then_with(|| a.note.id.cmp(&b.note.id))
+        });
+        Ok(trashed)
+    }
 
-            // Create a detailed deletion record with metadata
-            let record = format!(
-                "Deletion Record\n\
-                Note ID: {}\n\
-                Title: {}\n\
-                Tags: {}\n\
-                Created at: {}\n\
-                Last updated: {}\n\
-                Deleted at: {}\n\
-                Content length: {} characters",
-                note_to_delete.id,
-                note_to_delete.title,
-                note_to_delete.tags.join(", "),
-                note_to_delete.created_at.to_rfc3339(),
-                note_to_delete.updated_at.to_rfc3339(),
-                Utc::now().to_rfc3339(),
-                note_to_delete.content.len()
-            );
+    /// Brings a note back from the trash, with its attachments
+    ///
+    /// # Returns
+    ///
+    /// The restored note, `KbError::NoteNotFound` when the trash has no note
+    /// with that ID, or `KbError::ApplicationError` when a live note already
+    /// uses the ID
+    pub fn restore_from_trash(&self, note_id: &str) -> Result<Note> {
+        let trash_path = self.get_trash_path(note_id);
+        if !trash_path.exists() {
+            return Err(KbError::NoteNotFound {
+                id: note_id.to_string(),
+            });
+        }
 
-            if let Err(e) = fs::write(&deletion_record_path, record) {
-                warn!("Failed to create deletion record: {}", e);
-                // Continue since this is just a record
-            } else {
-                debug!(
-                    "Deletion record created at: {}",
-                    deletion_record_path.display()
-                );
+        let mut note: Note = serde_json::from_str(&fs::read_to_string(&trash_path)?)?;
+        note.metadata.remove(DELETED_AT_KEY);
+        if self.get_note(&note.id).is_some() {
+            return Err(KbError::ApplicationError {
+                message: format!(
+                    "Cannot restore note {}: a note with that ID already exists",
+                    note.id
+                ),
+            });
+        }
+
+        let trashed_attachments = self.get_trashed_attachment_dir(&note.id);
+        if trashed_attachments.exists() {
+            let attachment_dir = self.get_attachment_dir(&note.id);
+            if let Some(parent) = attachment_dir.parent() {
+                fs::create_dir_all(parent)?;
             }
+            fs::rename(&trashed_attachments, &attachment_dir)?;
         }
+        self.save_note(&note)?;
+        fs::remove_file(&trash_path)?;
 
-        info!("Note {} successfully deleted", note_id);
-        Ok(())
+        info!("Restored note {} from the trash", note.id);
+        Ok(note)
     }
 
-    /// Helper method to recursively clean up empty directories
+    /// Deletes notes in the trash for good, with their attachments
     ///
-    /// Checks if a directory is empty and removes it if it is.
-    /// Then checks its parent directory and does the same recursively.
-    fn cleanup_empty_directory(&self, dir_path: &Path) {
-        // Skip if this is the root notes directory or doesn't exist
-        if !dir_path.exists() || dir_path == self.config.notes_dir {
-            return;
-        }
+    /// # Arguments
+    ///
+    /// * `older_than` - Only purge notes deleted at least this long ago (all
+    ///   of them when `None`)
+    ///
+    /// # Returns
+    ///
+    /// The purged notes, most recently deleted first
+    pub fn empty_trash(&self, older_than: Option<chrono::Duration>) -> Result<Vec<TrashedNote>> {
+        let cutoff = older_than.map(|age| Utc::now() - age);
+        let purged: Vec<TrashedNote> = self
+            .list_trash()?
+            .into_iter()
+            .filter(|trashed| cutoff.is_none_or(|cutoff| trashed.deleted_at <= cutoff))
+            .collect();
 
-        // Check if the directory is empty
-        match fs::read_dir(dir_path) {
-            Ok(entries) => {
-                if entries.count() == 0 {
-                    debug!("Removing empty directory: {}", dir_path.display());
-                    match fs::remove_dir(dir_path) {
-                        Ok(_) => {
-                            // Recursively check parent directory
-                            if let Some(parent) = dir_path.parent() {
-                                if parent != self.config.notes_dir {
-                                    self.cleanup_empty_directory(parent);
-                                }
-                            }
-                        }
-                        Err(e) => warn!(
-                            "Failed to remove empty directory {}: {}",
-                            dir_path.display(),
-                            e
-                        ),
-                    }
+        self.record_purges(
+            &purged
+                .iter()
+                .map(|trashed| DeletedNote {
+                    id: trashed.note.id.clone(),
+                    deleted_at: trashed.deleted_at,
+                    purged: true,
+                })
+                .collect::<Vec<_>>(),
+        )?;
+        for trashed in &purged {
+            fs::remove_file(self.get_trash_path(&trashed.note.id))?;
+            let attachments = self.get_trashed_attachment_dir(&trashed.note.id);
+            if attachments.exists() {
+                if let Err(e) = fs::remove_dir_all(&attachments) {
+                    warn!(
+                        "Failed to delete trashed attachments in {}: {}",
+                        attachments.display(),
+                        e
+                    );
                 }
             }
-            Err(e) => warn!("Failed to read directory {}: {}", dir_path.display(), e),
+            debug!("Purged note {} from the trash", trashed.note.id);
+        }
+        Ok(purged)
+    }
+
+    /// Purges the notes that have been in the trash for longer than
+    /// `trash_retention_days` (nothing when it is unset)
+    pub fn purge_expired_trash(&self) -> Result<Vec<TrashedNote>> {
+        match self.config.trash_retention_days {
+            Some(days) => self.empty_trash(Some(chrono::Duration::days(days.into()))),
+            None => Ok(Vec::new()),
         }
     }
 
@@ -1257,8 +6618,68 @@ impl NoteStorage {
     ///
     /// # Returns
     ///
-    /// A Result indicating success or an error (e.g., if the note doesn't exist)
+    /// A Result indicating success or an error (e.g., if the note doesn't
+    /// exist, or `KbError::NoteLocked` if it is locked)
     pub fn update_note(&self, updated_note: Note) -> Result<()> {
+        self.update_note_checked(updated_note, false)
+    }
+
+    /// Updates a note like [`NoteStorage::update_note`], even when it is locked
+    pub fn force_update_note(&self, updated_note: Note) -> Result<()> {
+        self.update_note_checked(updated_note, true)
+    }
+
+    /// Adds and removes tags on every note matching a filter
+    ///
+    /// The matching notes are read under a single cache lock, then each
+    /// changed note is saved like [`NoteStorage::update_note`] does. A note
+    /// that can't be saved is reported and the others are still changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - Selects the notes, archived ones included
+    /// * `add` - Tags to add, compared like [`normalize_tag`] does
+    /// * `remove` - Tags to remove
+    /// * `force` - Change locked notes too
+    ///
+    /// # Returns
+    ///
+    /// Which notes were changed, which already had the requested tags, and
+    /// why the others failed
+    pub fn retag_notes(
+        &self,
+        filter: NoteFilter,
+        add: &[String],
+        remove: &[String],
+        force: bool,
+    ) -> Result<BatchReport> {
+        let mut notes = self.query_notes(filter)?;
+        notes.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let mut report = BatchReport::default();
+        for mut note in notes {
+            let (added, removed) = apply_tag_changes(&mut note, add, remove);
+            if added == 0 && removed == 0 {
+                report.unchanged.push(note.id);
+                continue;
+            }
+            note.updated_at = Utc::now();
+            let id = note.id.clone();
+            match self.update_note_checked(note, force) {
+                Ok(()) => report.succeeded.push(id),
+                Err(e) => report.failed.push((id, e.to_string())),
+            }
+        }
+        info!(
+            "Retagged {} notes ({} unchanged, {} failed)",
+            report.succeeded.len(),
+            report.unchanged.len(),
+            report.failed.len()
+        );
+        Ok(report)
+    }
+
+    fn update_note_checked(&self, updated_note: Note, force: bool) -> Result<()> {
         let note_id = updated_note.id.clone();
         info!("Updating note: {}", note_id);
 
@@ -1272,6 +6693,11 @@ impl NoteStorage {
             }
         };
 
+        if original_note.locked && !force {
+            warn!("Refusing to update locked note {}", note_id);
+            return Err(KbError::NoteLocked { id: note_id });
+        }
+
         // Validate update integrity - ensure we're not changing immutable fields
         if updated_note.id != original_note.id {
             let error_msg = "Cannot change note ID during update".to_string();
@@ -1285,67 +6711,27 @@ impl NoteStorage {
             return Err(KbError::ApplicationError { message: error_msg });
         }
 
+        // Keep the version being replaced in the note's history
+        if updated_note != original_note {
+            self.record_revision(&original_note)?;
+        }
+
         // Create pre-update backup if auto_backup is enabled
         if self.config.auto_backup {
             debug!("Creating pre-update backup for note: {}", note_id);
             self.create_update_backup(&original_note, "pre_update")?;
         }
 
-        // Generate the file path for the note
-        let file_path = self.get_note_path(&note_id);
-        debug!("File path for note update: {}", file_path.display());
-
-        // Ensure the parent directory exists
-        if let Some(parent) = file_path.parent() {
-            if !parent.exists() {
-                debug!("Creating parent directory: {}", parent.display());
-                fs::create_dir_all(parent).map_err(|e| {
-                    error!("Failed to create directory {}: {}", parent.display(), e);
-                    KbError::Io(e)
-                })?;
-            }
-        }
-
-        // Create a temporary file for atomic update
-        let dir = file_path.parent().unwrap_or_else(|| Path::new("."));
-        debug!("Creating temporary file in directory: {}", dir.display());
-        let mut temp_file = NamedTempFile::new_in(dir).map_err(|e| {
-            error!("Failed to create temporary file for update: {}", e);
-            KbError::Io(e)
-        })?;
-
-        // Serialize the updated note to JSON
-        trace!("Serializing updated note to JSON");
-        let json = serde_json::to_string_pretty(&updated_note).map_err(|e| {
-            error!("Failed to serialize updated note: {}", e);
-            KbError::Serialization(e)
-        })?;
-
-        // Write to the temporary file
-        trace!("Writing updated note to temporary file");
-        temp_file.write_all(json.as_bytes()).map_err(|e| {
-            error!("Failed to write to temporary file for update: {}", e);
-            KbError::Io(e)
-        })?;
-
-        temp_file.flush().map_err(|e| {
-            error!("Failed to flush temporary file for update: {}", e);
-            KbError::Io(e)
-        })?;
-
-        // Atomically replace the existing file with the updated content
-        debug!("Performing atomic replace of note file");
-        temp_file.persist(&file_path).map_err(|e| {
-            error!(
-                "Failed to replace file {}: {}",
-                file_path.display(),
-                e.error
-            );
-            KbError::Io(e.error)
-        })?;
+        self.persist_note(&updated_note)?;
 
         // Update the in-memory cache
-        match self.notes_cache.lock() {
+        self.index_aliases(&updated_note);
+        self.index_title(&updated_note);
+        self.index_tags(&updated_note);
+        self.index_structure(&updated_note);
+        self.index_links(&updated_note);
+        self.index_search(&updated_note);
+        match self.notes_cache.write() {
             Ok(mut cache) => {
                 debug!("Updating note in cache");
                 cache.insert(note_id.clone(), updated_note.clone());
@@ -1364,6 +6750,8 @@ impl NoteStorage {
             self.create_update_backup(&updated_note, "post_update")?;
         }
 
+        self.publish_event(NoteEventKind::Updated, &updated_note);
+
         info!("Note {} updated successfully", note_id);
         Ok(())
     }
@@ -1381,10 +6769,11 @@ impl NoteStorage {
     fn create_update_backup(&self, note: &Note, stage: &str) -> Result<PathBuf> {
         debug!("Creating {} backup for note: {}", stage, note.id);
 
-        // Ensure backup directory exists
-        if !self.config.backup_dir.exists() {
+        // Ensure the note's backup directory exists
+        let note_backup_dir = self.note_backup_dir(&note.id);
+        if !note_backup_dir.exists() {
             debug!("Creating backup directory for update backup");
-            fs::create_dir_all(&self.config.backup_dir).map_err(|e| {
+            fs::create_dir_all(&note_backup_dir).map_err(|e| {
                 warn!("Failed to create backup directory for update backup: {}", e);
                 KbError::Io(e)
             })?;
@@ -1394,12 +6783,12 @@ impl NoteStorage {
         let timestamp = Utc::now().timestamp();
         let backup_filename = format!(
             "{}_{}_{}_{}.json",
-            note.id,
+            self.id_policy.sanitize(&note.id),
             stage,
             timestamp,
             note.updated_at.timestamp()
         );
-        let backup_path = self.config.backup_dir.join(backup_filename);
+        let backup_path = note_backup_dir.join(backup_filename);
 
         // Serialize and save the backup
         let json = serde_json::to_string_pretty(&note).map_err(|e| {
@@ -1413,6 +6802,7 @@ impl NoteStorage {
         })?;
 
         debug!("Update backup created at: {}", backup_path.display());
+        self.prune_note_backups_logged(&note.id);
         Ok(backup_path)
     }
 
@@ -1428,11 +6818,31 @@ impl NoteStorage {
     ///
     /// # Returns
     ///
-    /// A Result indicating success or an error (e.g., if the note doesn't exist or was modified)
+    /// A Result indicating success or an error (e.g., if the note doesn't exist, was
+    /// modified, or is locked)
     pub fn update_note_with_version(
         &self,
         updated_note: Note,
         expected_version: NoteVersion,
+    ) -> Result<()> {
+        self.update_note_with_version_checked(updated_note, expected_version, false)
+    }
+
+    /// Updates a note like [`NoteStorage::update_note_with_version`], even when
+    /// it is locked
+    pub fn force_update_note_with_version(
+        &self,
+        updated_note: Note,
+        expected_version: NoteVersion,
+    ) -> Result<()> {
+        self.update_note_with_version_checked(updated_note, expected_version, true)
+    }
+
+    fn update_note_with_version_checked(
+        &self,
+        updated_note: Note,
+        expected_version: NoteVersion,
+        force: bool,
     ) -> Result<()> {
         let note_id = updated_note.id.clone();
         info!("Updating note with version check: {}", note_id);
@@ -1469,6 +6879,11 @@ impl NoteStorage {
             });
         }
 
+        if current_note.locked && !force {
+            warn!("Refusing to update locked note {}", note_id);
+            return Err(KbError::NoteLocked { id: note_id });
+        }
+
         // Validate update integrity - ensure we're not changing immutable fields
         if updated_note.id != current_note.id {
             return Err(KbError::ApplicationError {
@@ -1482,6 +6897,11 @@ impl NoteStorage {
             });
         }
 
+        // Keep the version being replaced in the note's history
+        if updated_note != current_note {
+            self.record_revision(&current_note)?;
+        }
+
         // Create pre-update backup if auto_backup is enabled
         if self.config.auto_backup {
             debug!("Creating pre-update backup for note: {}", note_id);
@@ -1492,62 +6912,16 @@ impl NoteStorage {
             }
         }
 
-        // Generate the file path for the note
-        let file_path = self.get_note_path(&note_id);
-        debug!("File path for note update: {}", file_path.display());
-
-        // Ensure the parent directory exists
-        if let Some(parent) = file_path.parent() {
-            if !parent.exists() {
-                debug!("Creating parent directory: {}", parent.display());
-                fs::create_dir_all(parent).map_err(|e| {
-                    error!("Failed to create directory {}: {}", parent.display(), e);
-                    KbError::Io(e)
-                })?;
-            }
-        }
-
-        // Create a temporary file for atomic update
-        let dir = file_path.parent().unwrap_or_else(|| Path::new("."));
-        debug!("Creating temporary file in directory: {}", dir.display());
-        let mut temp_file = NamedTempFile::new_in(dir).map_err(|e| {
-            error!("Failed to create temporary file for update: {}", e);
-            KbError::Io(e)
-        })?;
-
-        // Serialize the updated note to JSON
-        trace!("Serializing updated note to JSON");
-        let json = serde_json::to_string_pretty(&updated_note).map_err(|e| {
-            error!("Failed to serialize updated note: {}", e);
-            KbError::Serialization(e)
-        })?;
-
-        // Write to the temporary file
-        trace!("Writing updated note to temporary file");
-        temp_file.write_all(json.as_bytes()).map_err(|e| {
-            error!("Failed to write to temporary file for update: {}", e);
-            KbError::Io(e)
-        })?;
-
-        temp_file.flush().map_err(|e| {
-            error!("Failed to flush temporary file for update: {}", e);
-            KbError::Io(e)
-        })?;
-
-        // Start critical section - update both storage mechanisms atomically
-        // First, update the file system
-        debug!("Performing atomic replace of note file");
-        temp_file.persist(&file_path).map_err(|e| {
-            error!(
-                "Failed to replace file {}: {}",
-                file_path.display(),
-                e.error
-            );
-            KbError::Io(e.error)
-        })?;
+        self.persist_note(&updated_note)?;
 
         // Then update the in-memory cache
-        match self.notes_cache.lock() {
+        self.index_aliases(&updated_note);
+        self.index_title(&updated_note);
+        self.index_tags(&updated_note);
+        self.index_structure(&updated_note);
+        self.index_links(&updated_note);
+        self.index_search(&updated_note);
+        match self.notes_cache.write() {
             Ok(mut cache) => {
                 debug!("Updating note in cache");
                 // Double-check version before updating cache
@@ -1583,6 +6957,8 @@ impl NoteStorage {
             }
         }
 
+        self.publish_event(NoteEventKind::Updated, &updated_note);
+
         info!("Note {} updated successfully with version check", note_id);
         Ok(())
     }
@@ -1630,7 +7006,7 @@ impl NoteStorage {
             );
             merged_note.updated_at = Utc::now();
 
-            return Ok(ConflictResolution::UseMergedVersion(merged_note));
+            return Ok(ConflictResolution::UseMergedVersion(Box::new(merged_note)));
         }
 
         // If everything but the timestamp is identical, use the server version
@@ -1682,11 +7058,10 @@ impl NoteStorage {
     /// Performs a complete shutdown of the storage system, including
     /// stopping the file watcher and backup scheduler
     ///
-    /// # Returns
-    ///
-    /// A Result indicating success or an error
-    /// Performs a complete shutdown of the storage system, including
-    /// stopping the file watcher and backup scheduler
+    /// With `backup_on_shutdown` on, a final full backup is taken first,
+    /// giving up after [`SHUTDOWN_BACKUP_TIMEOUT`]. Queued webhook
+    /// deliveries get up to [`WEBHOOK_DRAIN_TIMEOUT`] before the ones left
+    /// are dead-lettered.
     ///
     /// # Returns
     ///
@@ -1700,6 +7075,35 @@ impl NoteStorage {
         // Track any errors during shutdown
         let mut shutdown_errors = Vec::new();
 
+        // Take the final backup while the watcher and scheduler still run; it
+        // reads the cache, so it includes changes not flushed yet
+        if self.config.backup_on_shutdown {
+            let storage = self.clone();
+            match tokio::time::timeout(
+                SHUTDOWN_BACKUP_TIMEOUT,
+                tokio::task::spawn_blocking(move || storage.create_full_backup()),
+            )
+            .await
+            {
+                Ok(Ok(Ok(path))) => info!("Shutdown backup created at {}", path.display()),
+                Ok(Ok(Err(e))) => {
+                    let error_msg = format!("Error creating shutdown backup: {}", e);
+                    warn!("{}", error_msg);
+                    shutdown_errors.push(error_msg);
+                }
+                Ok(Err(e)) => {
+                    let error_msg = format!("Shutdown backup task failed: {}", e);
+                    warn!("{}", error_msg);
+                    shutdown_errors.push(error_msg);
+                }
+                Err(_) => {
+                    let error_msg = "Timed out while creating shutdown backup";
+                    warn!("{}", error_msg);
+                    shutdown_errors.push(error_msg.to_string());
+                }
+            }
+        }
+
         // First, stop the backup scheduler to prevent new backup operations
         match self.stop_backup_scheduler().await {
             Ok(_) => debug!("Backup scheduler stopped successfully"),
@@ -1747,6 +7151,35 @@ impl NoteStorage {
             }
         }
 
+        if let Err(e) = self.save_link_index() {
+            let error_msg = format!("Error saving link index: {}", e);
+            warn!("{}", error_msg);
+            shutdown_errors.push(error_msg);
+        }
+
+        // Give queued webhook deliveries and their retries a bounded time,
+        // dead-lettering the ones left, since the delivery thread dies with
+        // the process
+        if let Some(webhooks) = self.webhooks.clone() {
+            let drained =
+                tokio::task::spawn_blocking(move || webhooks.drain(WEBHOOK_DRAIN_TIMEOUT)).await;
+            if !matches!(drained, Ok(true)) {
+                let error_msg = "Timed out while delivering webhooks";
+                warn!("{}", error_msg);
+                shutdown_errors.push(error_msg.to_string());
+            }
+        }
+
+        // Make deferred writes durable
+        if self.config.fsync == FsyncMode::OnClose {
+            if let Err(e) = self.files.sync_pending_writes() {
+                let error_msg = format!("Error syncing notes to disk: {}", e);
+                warn!("{}", error_msg);
+                shutdown_errors.push(error_msg);
+            }
+        }
+        self.backend.release();
+
         // Final shutdown status report
         if shutdown_errors.is_empty() {
             info!("NoteStorage shutdown complete - all components shut down cleanly");
@@ -1771,11 +7204,28 @@ impl NoteStorage {
     async fn flush_cache_to_disk(&self) -> Result<()> {
         debug!("Flushing cache to disk...");
 
+        let dirty: Vec<String> = match self.dirty_notes.lock() {
+            Ok(dirty) => dirty.iter().cloned().collect(),
+            Err(e) => {
+                warn!("Failed to acquire dirty notes lock during flush: {}", e);
+                return Err(KbError::LockAcquisitionFailed {
+                    message: "Failed to acquire lock during flush operation".to_string(),
+                });
+            }
+        };
+        if dirty.is_empty() {
+            debug!("No unsaved notes to flush");
+            return Ok(());
+        }
+
         let notes = {
-            match self.notes_cache.lock() {
+            match self.notes_cache.read() {
                 Ok(cache) => {
                     // Clone notes for processing outside of lock
-                    cache.values().cloned().collect::<Vec<Note>>()
+                    dirty
+                        .iter()
+                        .filter_map(|id| cache.get(id).cloned())
+                        .collect::<Vec<Note>>()
                 }
                 Err(e) => {
                     warn!("Failed to acquire cache lock during flush: {}", e);
@@ -1789,9 +7239,17 @@ impl NoteStorage {
         // Track any errors during flush
         let mut error_count = 0;
 
-        // Save each note to ensure it's on disk
+        // Write back each dirty note, backing up only content that changed
         for note in notes {
-            if let Err(e) = self.save_note(&note) {
+            if self.config.auto_backup {
+                let stored = self.backend.load(&note.id).ok().flatten();
+                if stored.is_none_or(|stored| stored.content != note.content) {
+                    if let Err(e) = self.backup_note(&note) {
+                        warn!("Failed to create backup of note {}: {}", note.id, e);
+                    }
+                }
+            }
+            if let Err(e) = self.persist_note(&note) {
                 error_count += 1;
                 warn!("Failed to flush note {}: {}", note.id, e);
                 // Continue with other notes despite this error
@@ -1808,6 +7266,40 @@ impl NoteStorage {
             Ok(())
         }
     }
+
+    /// Writes a note through the backend and keeps the dirty set in step: a
+    /// failed write may leave the note's files half-written, so the cached
+    /// copy is written back on shutdown
+    fn persist_note(&self, note: &Note) -> Result<()> {
+        let result = self.backend.save(note);
+        if let Ok(mut dirty) = self.dirty_notes.lock() {
+            if result.is_ok() {
+                dirty.remove(&note.id);
+            } else {
+                dirty.insert(note.id.clone());
+            }
+        }
+        result
+    }
+
+    /// Writes several notes as [`NoteStorage::persist_note`] does one
+    ///
+    /// # Returns
+    ///
+    /// The outcome of each note, in the order given
+    fn persist_notes(&self, notes: &[&Note]) -> Vec<Result<()>> {
+        let results = self.backend.save_batch(notes);
+        if let Ok(mut dirty) = self.dirty_notes.lock() {
+            for (note, result) in notes.iter().zip(&results) {
+                if result.is_ok() {
+                    dirty.remove(&note.id);
+                } else {
+                    dirty.insert(note.id.clone());
+                }
+            }
+        }
+        results
+    }
 }
 
 // Implement Clone for NoteStorage to use in closures
@@ -1816,9 +7308,162 @@ impl Clone for NoteStorage {
         Self {
             config: self.config.clone(),
             notes_cache: Arc::clone(&self.notes_cache),
+            alias_index: Arc::clone(&self.alias_index),
+            title_index: Arc::clone(&self.title_index),
+            tag_index: Arc::clone(&self.tag_index),
+            structure_index: Arc::clone(&self.structure_index),
+            link_index: Arc::clone(&self.link_index),
+            search_index: Arc::clone(&self.search_index),
             watcher: None,
             initialized: self.initialized,
             backup_scheduler: Arc::clone(&self.backup_scheduler),
+            files: self.files.clone(),
+            backend: Arc::clone(&self.backend),
+            events: self.events.clone(),
+            access_state: Arc::clone(&self.access_state),
+            id_policy: Arc::clone(&self.id_policy),
+            webhooks: self.webhooks.clone(),
+            cache_counters: Arc::clone(&self.cache_counters),
+            dirty_notes: Arc::clone(&self.dirty_notes),
+            foreground: self.foreground.clone(),
+            reindex_progress: Arc::clone(&self.reindex_progress),
+        }
+    }
+}
+
+/// Removes backup files, logging the ones that can't be removed
+///
+/// # Returns
+///
+/// The removed files
+fn remove_backup_files(paths: impl Iterator<Item = PathBuf>) -> Vec<PathBuf> {
+    paths
+        .filter(|path| match fs::remove_file(path) {
+            Ok(()) => {
+                debug!("Removed old backup: {}", path.display());
+                true
+            }
+            Err(e) => {
+                warn!("Failed to remove old backup {}: {}", path.display(), e);
+                false
+            }
+        })
+        .collect()
+}
+
+/// Reads the name of a full or incremental backup archive
+///
+/// # Returns
+///
+/// The timestamp part of the name and whether the archive is incremental, or
+/// `None` for any other file name
+pub(crate) fn parse_backup_archive_name(file_name: &str) -> Option<(&str, bool)> {
+    let stem = file_name.strip_suffix(".zip")?;
+    match stem.strip_prefix(FULL_BACKUP_PREFIX) {
+        Some(stamp) => Some((stamp, false)),
+        None => stem
+            .strip_prefix(INCREMENTAL_BACKUP_PREFIX)
+            .map(|stamp| (stamp, true)),
+    }
+}
+
+/// Applies a change to the search index, if there is one, and saves it when
+/// something changed
+pub(crate) fn update_search_index(
+    index: &mut Option<SearchIndex>,
+    change: impl FnOnce(&mut SearchIndex) -> bool,
+) {
+    if let Some(index) = index.as_mut() {
+        if change(index) {
+            if let Err(e) = index.save() {
+                warn!("Failed to save search index: {}", e);
+            }
+        }
+    }
+}
+
+/// Finds the note files that are not where their ID places them, or whose
+/// location is shared with another note
+fn layout_issues(files: Vec<NoteFile>) -> Vec<LayoutIssue> {
+    // Group by expected location, ignoring case so collisions that only
+    // show up on case-insensitive filesystems are reported everywhere
+    let mut claims: HashMap<String, Vec<String>> = HashMap::new();
+    for (note_id, _, expected) in &files {
+        let ids = claims
+            .entry(expected.to_string_lossy().to_lowercase())
+            .or_default();
+        if !ids.contains(note_id) {
+            ids.push(note_id.clone());
+        }
+    }
+
+    files
+        .into_iter()
+        .filter_map(|(note_id, path, expected)| {
+            let collides_with = claims[&expected.to_string_lossy().to_lowercase()]
+                .iter()
+                .find(|id| **id != note_id)
+                .cloned();
+
+            (path != expected || collides_with.is_some()).then_some(LayoutIssue {
+                note_id,
+                path,
+                expected,
+                collides_with,
+            })
+        })
+        .collect()
+}
+
+/// Moves the files of the note stored at `from` to `to`, a split note's
+/// content file before its sidecar
+fn move_note_files(from: &Path, to: &Path) -> Result<()> {
+    let targets = note_file_paths(to);
+    for (source, target) in note_file_paths(from).iter().zip(&targets).rev() {
+        if source.exists() {
+            fs::rename(source, target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks a staged restore before it is swapped in: the note count must match
+/// the backup manifest and a sample of notes spread over the backup must load
+fn validate_staged_restore(
+    staged_dir: &Path,
+    note_entries: &HashMap<String, String>,
+    manifest: Option<&BackupManifest>,
+) -> Result<()> {
+    match manifest {
+        Some(manifest) if manifest.notes != note_entries.len() => {
+            return Err(KbError::RestoreFailed {
+                message: format!(
+                    "Backup manifest lists {} notes but the archive holds {}",
+                    manifest.notes,
+                    note_entries.len()
+                ),
+            });
+        }
+        Some(_) => {}
+        None => warn!("Backup has no manifest, only checking a sample of its notes"),
+    }
+
+    let mut entries: Vec<(&String, &String)> = note_entries.iter().collect();
+    entries.sort();
+    let step = (entries.len() / RESTORE_VALIDATION_SAMPLE).max(1);
+    for (note_id, entry) in entries.into_iter().step_by(step) {
+        let note =
+            load_note_from_file(&staged_dir.join(entry)).map_err(|e| KbError::RestoreFailed {
+                message: format!("Note {} in the backup cannot be read: {}", note_id, e),
+            })?;
+        if note.id != *note_id {
+            return Err(KbError::RestoreFailed {
+                message: format!(
+                    "Note ID mismatch in the backup: expected {}, found {}",
+                    note_id, note.id
+                ),
+            });
         }
     }
+    Ok(())
 }
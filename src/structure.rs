@@ -0,0 +1,164 @@
+//! Structural summaries of note content for `--has` filters.
+//!
+//! Each note's Markdown is parsed once when it is loaded or saved, and the
+//! resulting counts (tasks, code blocks, links, attachments) are kept in an
+//! index next to the notes cache. Queries such as "notes with unchecked tasks"
+//! then only look at the counts instead of parsing every note again.
+//!
+//! Broken links depend on which other notes exist, so only the `[[wiki-link]]`
+//! targets are cached and resolved when a query asks for them.
+use std::collections::HashMap;
+
+use pulldown_cmark::{Event, LinkType, Options, Parser, Tag};
+use serde::{Deserialize, Serialize};
+
+use crate::{extract_wiki_links, KbError, Note, Result};
+
+/// Index from note ID to the structure of its content
+pub type StructureIndex = HashMap<String, NoteStructure>;
+
+/// Counts of the structural elements found in a note's content
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NoteStructure {
+    /// Task list items (`- [ ]` and `- [x]`)
+    pub tasks: usize,
+
+    /// Task list items that are not checked yet
+    pub open_tasks: usize,
+
+    /// Fenced and indented code blocks
+    pub code_blocks: usize,
+
+    /// Links to other notes (`[[wiki-links]]`) and Markdown links
+    pub links: usize,
+
+    /// Images and links pointing at local files rather than URLs or notes
+    pub attachments: usize,
+
+    /// Targets of the note's `[[wiki-links]]`, checked for broken links
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wiki_links: Vec<String>,
+}
+
+/// The structure of a note together with its unresolved links
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct StructureSummary {
+    #[serde(flatten)]
+    pub structure: NoteStructure,
+
+    /// `[[wiki-link]]` targets that match no note ID, title or alias
+    pub broken_links: Vec<String>,
+}
+
+/// Structural predicates accepted by `--has`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructureFilter {
+    /// At least one unchecked task
+    Tasks,
+    /// At least one code block
+    Code,
+    /// At least one attachment
+    Attachments,
+    /// At least one link
+    Links,
+    /// At least one link to a note that does not exist
+    BrokenLinks,
+}
+
+impl StructureFilter {
+    /// Names accepted on the command line
+    pub const NAMES: [&'static str; 5] = ["tasks", "code", "attachments", "links", "broken-links"];
+
+    /// Checks whether a note with the given structure satisfies this filter
+    pub fn matches(&self, summary: &StructureSummary) -> bool {
+        let structure = &summary.structure;
+        match self {
+            StructureFilter::Tasks => structure.open_tasks > 0,
+            StructureFilter::Code => structure.code_blocks > 0,
+            StructureFilter::Attachments => structure.attachments > 0,
+            StructureFilter::Links => structure.links > 0,
+            StructureFilter::BrokenLinks => !summary.broken_links.is_empty(),
+        }
+    }
+}
+
+impl std::str::FromStr for StructureFilter {
+    type Err = KbError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tasks" => Ok(StructureFilter::Tasks),
+            "code" => Ok(StructureFilter::Code),
+            "attachments" => Ok(StructureFilter::Attachments),
+            "links" => Ok(StructureFilter::Links),
+            "broken-links" => Ok(StructureFilter::BrokenLinks),
+            other => Err(KbError::InvalidArgument {
+                message: format!(
+                    "Unknown structure filter '{}': use {}",
+                    other,
+                    StructureFilter::NAMES.join(", ")
+                ),
+            }),
+        }
+    }
+}
+
+/// Parses a note's Markdown content and counts its structural elements
+pub fn summarize_structure(content: &str) -> NoteStructure {
+    let wiki_links = extract_wiki_links(content);
+    let mut structure = NoteStructure {
+        links: wiki_links.len(),
+        wiki_links,
+        ..NoteStructure::default()
+    };
+
+    let options = Options::ENABLE_TASKLISTS | Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES;
+    for event in Parser::new_ext(content, options) {
+        match event {
+            Event::TaskListMarker(checked) => {
+                structure.tasks += 1;
+                if !checked {
+                    structure.open_tasks += 1;
+                }
+            }
+            Event::Start(Tag::CodeBlock(_)) => structure.code_blocks += 1,
+            Event::Start(Tag::Image { dest_url, .. }) if is_local_target(&dest_url) => {
+                structure.attachments += 1;
+            }
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                ..
+            }) => {
+                // Autolinks and e-mail links are plain URLs, never attachments
+                structure.links += 1;
+                if !matches!(link_type, LinkType::Autolink | LinkType::Email)
+                    && is_local_target(&dest_url)
+                {
+                    structure.attachments += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    structure
+}
+
+/// Checks whether a link destination names a local file
+fn is_local_target(dest: &str) -> bool {
+    let dest = dest.trim();
+    let has_scheme = dest.split_once(':').is_some_and(|(scheme, _)| {
+        // A single letter before the colon is a Windows drive, not a scheme
+        scheme.len() > 1
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    });
+    !dest.is_empty() && !dest.starts_with('#') && !has_scheme
+}
+
+/// Replaces a note's entry in the structure index with its current structure
+pub fn index_note_structure(index: &mut StructureIndex, note: &Note) {
+    index.insert(note.id.clone(), summarize_structure(&note.content));
+}
@@ -0,0 +1,104 @@
+//! Per-tag policies overriding the default handling of notes.
+//!
+//! Policies are declared in the `tags.policies` section of the configuration,
+//! keyed by tag name (matched case-insensitively):
+//!
+//! ```json
+//! "tags": {
+//!   "policies": {
+//!     "scratch": { "auto_archive_after_days": 30, "exclude_from_backup": true },
+//!     "archived": { "exclude_from_search_default": true },
+//!     "journal": { "protect_from_delete": true }
+//!   }
+//! }
+//! ```
+//!
+//! A note with several tags carrying a policy gets the most protective
+//! combination (see [`TagsConfig::policy_for`]). Tags without a policy, and
+//! settings a policy leaves out, do not take part in the resolution.
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Tag given to notes archived by a policy
+pub const ARCHIVED_TAG: &str = "archived";
+
+/// Metadata key recording when a note was archived (RFC 3339)
+pub const ARCHIVED_AT_KEY: &str = "archived_at";
+
+/// How notes carrying a tag are treated; unset fields keep the default behavior
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct TagPolicy {
+    /// Archive notes that have not been updated for this many days
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_archive_after_days: Option<u32>,
+
+    /// Leave notes out of full backups
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude_from_backup: Option<bool>,
+
+    /// Hide notes from search results unless `search --all` is used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude_from_search_default: Option<bool>,
+
+    /// Refuse to delete notes unless `delete --force` is used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protect_from_delete: Option<bool>,
+}
+
+/// The policy resulting from all tags of a note
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EffectiveTagPolicy {
+    pub auto_archive_after_days: Option<u32>,
+    pub exclude_from_backup: bool,
+    pub exclude_from_search_default: bool,
+    pub protect_from_delete: bool,
+}
+
+/// The `tags` configuration section
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct TagsConfig {
+    /// Policies keyed by tag name
+    #[serde(default)]
+    pub policies: BTreeMap<String, TagPolicy>,
+}
+
+impl TagsConfig {
+    /// Returns the policy configured for a tag, ignoring case
+    pub fn policy(&self, tag: &str) -> Option<&TagPolicy> {
+        self.policies
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(tag))
+            .map(|(_, policy)| policy)
+    }
+
+    /// Resolves the policy applying to a note with the given tags
+    ///
+    /// Only the settings present in the note's tag policies are considered.
+    /// When several of them disagree, the most protective option wins:
+    /// * a note is auto-archived after the longest period any policy sets
+    /// * it is excluded from backups and hidden from search only if no policy
+    ///   sets the option to `false`
+    /// * it is protected from deletion if any policy protects it
+    pub fn policy_for(&self, tags: &[String]) -> EffectiveTagPolicy {
+        let policies: Vec<&TagPolicy> = tags.iter().filter_map(|tag| self.policy(tag)).collect();
+
+        // True when at least one policy sets the option and all that do agree
+        let unanimous = |option: fn(&TagPolicy) -> Option<bool>| {
+            let values: Vec<bool> = policies.iter().filter_map(|p| option(p)).collect();
+            !values.is_empty() && values.iter().all(|value| *value)
+        };
+
+        EffectiveTagPolicy {
+            auto_archive_after_days: policies
+                .iter()
+                .filter_map(|policy| policy.auto_archive_after_days)
+                .max(),
+            exclude_from_backup: unanimous(|policy| policy.exclude_from_backup),
+            exclude_from_search_default: unanimous(|policy| policy.exclude_from_search_default),
+            protect_from_delete: policies
+                .iter()
+                .any(|policy| policy.protect_from_delete == Some(true)),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Markdown task lists inside notes.
+//!
+//! Tasks are list items starting with `[ ]` (open) or `[x]` (done), at any
+//! nesting depth. They are found with the Markdown parser, so checkboxes in
+//! code blocks or in plain paragraphs are not tasks.
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+
+use crate::{KbError, Result};
+
+/// A task list item found in note content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Task {
+    /// Line the checkbox is on (1-based)
+    pub line: usize,
+    /// Whether the task is checked
+    pub checked: bool,
+    /// Text after the checkbox on the same line
+    pub text: String,
+    /// Nesting depth, 0 for a task in a top-level list
+    pub depth: usize,
+    /// Byte offset of the checkbox (`[ ]` or `[x]`)
+    pub start: usize,
+    /// Byte offset just past the checkbox
+    pub end: usize,
+}
+
+/// Lists the tasks of Markdown content in document order
+pub fn extract_tasks(content: &str) -> Vec<Task> {
+    let mut tasks = Vec::new();
+    let mut lists: usize = 0;
+    // Line numbers are counted incrementally so huge notes are scanned once
+    let (mut counted_to, mut line) = (0, 1);
+
+    for (event, range) in Parser::new_ext(content, Options::ENABLE_TASKLISTS).into_offset_iter() {
+        match event {
+            Event::Start(Tag::List(_)) => lists += 1,
+            Event::End(TagEnd::List(_)) => lists = lists.saturating_sub(1),
+            Event::TaskListMarker(checked) => {
+                line += content[counted_to..range.start].matches('\n').count();
+                counted_to = range.start;
+                let rest = &content[range.end..];
+                let text = rest.lines().next().unwrap_or("").trim().to_string();
+                tasks.push(Task {
+                    line,
+                    checked,
+                    text,
+                    depth: lists.saturating_sub(1),
+                    start: range.start,
+                    end: range.end,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    tasks
+}
+
+/// Checks an open task or unchecks a done one
+///
+/// # Arguments
+///
+/// * `content` - Markdown content of a note
+/// * `line` - Line of the task, as reported by [`extract_tasks`]
+///
+/// # Returns
+///
+/// The updated content and the task with its new state, or
+/// `KbError::InvalidArgument` when there is no task on that line
+pub fn toggle_task(content: &str, line: usize) -> Result<(String, Task)> {
+    let mut task = extract_tasks(content)
+        .into_iter()
+        .find(|task| task.line == line)
+        .ok_or_else(|| KbError::InvalidArgument {
+            message: format!("No task on line {}", line),
+        })?;
+
+    task.checked = !task.checked;
+    let checkbox = if task.checked { "[x]" } else { "[ ]" };
+    let updated = format!(
+        "{}{}{}",
+        &content[..task.start],
+        checkbox,
+        &content[task.end..]
+    );
+    task.end = task.start + checkbox.len();
+    Ok((updated, task))
+}
@@ -0,0 +1,128 @@
+//! Templates for new notes.
+//!
+//! A template is a Markdown file `<templates_dir>/<name>.md`. Creating a note
+//! from it replaces these placeholders:
+//!
+//! * `{{title}}` - the note's title
+//! * `{{date}}` - the current local date (`YYYY-MM-DD`)
+//! * `{{time}}` - the current local time (`HH:MM`)
+//! * `{{content}}` - content given on the command line (empty otherwise)
+//!
+//! Other `{{...}}` sequences are left as they are.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Local};
+
+use crate::{validate_snapshot_name, KbError, Result};
+
+/// Extension of template files
+pub const TEMPLATE_EXTENSION: &str = "md";
+
+/// Content of a template created with `template new`
+const STARTER_TEMPLATE: &str = "# {{title}}\n\nCreated {{date}} at {{time}}\n\n{{content}}\n";
+
+/// Values substituted for the placeholders of a template
+#[derive(Debug, Clone)]
+pub struct TemplateContext {
+    /// Title of the new note
+    pub title: String,
+    /// When the note is created
+    pub now: DateTime<Local>,
+    /// Content given with `--content` or `--file`, if any
+    pub content: Option<String>,
+}
+
+/// Returns the names of the templates in a directory, sorted
+///
+/// A missing directory holds no templates.
+pub fn list_templates(dir: &Path) -> Result<Vec<String>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file()
+            && path
+                .extension()
+                .is_some_and(|ext| ext == TEMPLATE_EXTENSION)
+        {
+            if let Some(stem) = path.file_stem() {
+                names.push(stem.to_string_lossy().to_string());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Returns the path of a template, checking that its name is a plain file name
+pub fn template_path(dir: &Path, name: &str) -> Result<PathBuf> {
+    validate_snapshot_name(name).map_err(|_| KbError::InvalidArgument {
+        message: format!(
+            "Invalid template name '{}': use letters, digits, '-', '_' or '.'",
+            name
+        ),
+    })?;
+    Ok(dir.join(format!("{}.{}", name, TEMPLATE_EXTENSION)))
+}
+
+/// Reads a template
+///
+/// # Returns
+///
+/// The template text, or `KbError::TemplateNotFound` listing the available
+/// templates
+pub fn load_template(dir: &Path, name: &str) -> Result<String> {
+    let path = template_path(dir, name)?;
+    if !path.is_file() {
+        return Err(KbError::TemplateNotFound {
+            name: name.to_string(),
+            dir: dir.to_path_buf(),
+            available: list_templates(dir)?,
+        });
+    }
+    Ok(fs::read_to_string(path)?)
+}
+
+/// Creates a template holding a starter text with every placeholder
+///
+/// # Returns
+///
+/// The path of the new template, or `KbError::InvalidArgument` when a
+/// template with that name exists
+pub fn create_template(dir: &Path, name: &str) -> Result<PathBuf> {
+    let path = template_path(dir, name)?;
+    if path.exists() {
+        return Err(KbError::InvalidArgument {
+            message: format!("Template '{}' already exists: {}", name, path.display()),
+        });
+    }
+
+    fs::create_dir_all(dir)?;
+    fs::write(&path, STARTER_TEMPLATE)?;
+    Ok(path)
+}
+
+/// Substitutes the placeholders of a template
+///
+/// Content given on the command line takes precedence over the template: it
+/// fills `{{content}}` when the template has that placeholder and replaces
+/// the template text entirely otherwise.
+pub fn render_template(template: &str, context: &TemplateContext) -> String {
+    if let Some(content) = &context.content {
+        if !template.contains("{{content}}") {
+            return content.clone();
+        }
+    }
+
+    template
+        .replace("{{title}}", &context.title)
+        .replace("{{date}}", &context.now.format("%Y-%m-%d").to_string())
+        .replace("{{time}}", &context.now.format("%H:%M").to_string())
+        .replace("{{content}}", context.content.as_deref().unwrap_or(""))
+}
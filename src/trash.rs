@@ -0,0 +1,78 @@
+//! Trash for deleted notes.
+//!
+//! Deleting a note moves it to `notes_dir/.trash/<id>.json`, with the time of
+//! deletion recorded under [`DELETED_AT_KEY`] in its metadata, and its
+//! attachments to `notes_dir/.trash/<id>.attachments/`. From there it can be
+//! restored, or purged for good by `trash empty` or once it is older than
+//! `trash_retention_days`. Purges and permanent deletions are appended to
+//! [`PURGE_JOURNAL_FILE`], so incremental mirrors still learn about notes
+//! that left the trash.
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{KbError, Note, Result};
+
+/// Directory (inside `notes_dir`) holding deleted notes
+pub const TRASH_DIR: &str = ".trash";
+
+/// Metadata key recording when a note was moved to the trash (RFC 3339)
+pub const DELETED_AT_KEY: &str = "deleted_at";
+
+/// Suffix of the directory (inside [`TRASH_DIR`]) keeping a deleted note's attachments
+pub const TRASHED_ATTACHMENTS_SUFFIX: &str = ".attachments";
+
+/// File (inside [`TRASH_DIR`]) listing purged notes, one JSON object per line
+pub const PURGE_JOURNAL_FILE: &str = "purged.jsonl";
+
+/// A note deleted from the vault, as reported by
+/// [`NoteStorage::get_notes_modified_since`](crate::NoteStorage::get_notes_modified_since)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeletedNote {
+    /// ID of the deleted note
+    pub id: String,
+    /// When the note left the vault (moved to the trash, or deleted for good)
+    pub deleted_at: DateTime<Utc>,
+    /// Whether the note is gone for good rather than waiting in the trash
+    pub purged: bool,
+}
+
+/// A note waiting in the trash
+#[derive(Debug, Clone, Serialize)]
+pub struct TrashedNote {
+    /// The note as it was when deleted
+    pub note: Note,
+    /// When the note was moved to the trash
+    pub deleted_at: DateTime<Utc>,
+}
+
+/// Parses an age such as `--older-than 30d`
+///
+/// # Arguments
+///
+/// * `value` - A number of hours, days or weeks (`12h`, `30d`, `2w`)
+///
+/// # Returns
+///
+/// The age, or `KbError::InvalidFormat` naming the accepted forms
+pub fn parse_age(value: &str) -> Result<Duration> {
+    let value = value.trim().to_lowercase();
+    let invalid = || KbError::InvalidFormat {
+        message: format!(
+            "Invalid age '{}': expected a number followed by h, d or w (e.g. 30d)",
+            value
+        ),
+    };
+
+    let (count, unit) = value.split_at(value.len().saturating_sub(1));
+    let count: i64 = count.parse().map_err(|_| invalid())?;
+    if count < 0 {
+        return Err(invalid());
+    }
+    let age = match unit {
+        "h" => Duration::try_hours(count),
+        "d" => Duration::try_days(count),
+        "w" => Duration::try_weeks(count),
+        _ => None,
+    };
+    age.ok_or_else(invalid)
+}
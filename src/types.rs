@@ -2,27 +2,102 @@
 //!
 //! This module contains the primary types used throughout the application,
 //! including Note and Config structures.
-use std::path::PathBuf;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::PathBuf,
+};
 
 use chrono::{DateTime, Utc};
 use clap::{Args, Subcommand};
+use serde::{Deserialize, Serialize};
 
-use crate::{KbError, Note};
+use crate::{DeletedNote, FsyncMode, KbError, Note, SortField, StructureFilter};
+
+#[derive(Debug, Clone, Args)]
+pub struct CreateNoteOptions {
+    /// Title of the note
+    #[clap(short = 'T', long)]
+    pub title: String,
+
+    /// Content of the note, can be markdown formatted
+    #[clap(short, long)]
+    pub content: Option<String>,
+
+    /// Open content in editor before saving
+    #[clap(short, long)]
+    pub edit: bool,
+
+    /// Tags to associate with the note (comma-separated)
+    #[clap(short = 't', long)]
+    pub tags: Option<String>,
+
+    /// Path to a file containing the note's content
+    #[clap(short, long)]
+    pub file: Option<PathBuf>,
+
+    /// Start from this template in the templates directory (content from
+    /// --content or --file fills its {{content}} placeholder)
+    #[clap(long)]
+    pub template: Option<String>,
+
+    /// Due date (YYYY-MM-DD, RFC 3339, today, tomorrow, +3d or +2w)
+    #[clap(long)]
+    pub due: Option<String>,
+
+    /// Notebook to file the note in, such as work/project-x
+    #[clap(long)]
+    pub notebook: Option<String>,
+}
 
 #[derive(Debug, Clone, Args)]
 pub struct ListNotesOptions {
-    /// Filter notes by tag
+    /// Filter notes by tag; repeat to list notes carrying any of the tags
     #[clap(short = 't', long = "tag")]
-    pub tag: Option<String>,
+    pub tags: Vec<String>,
+
+    /// Only list notes carrying every --tag
+    #[clap(long = "all-tags")]
+    pub all_tags: bool,
+
+    /// Hide notes carrying this tag, even if they match --tag (repeatable)
+    #[clap(long = "exclude-tag")]
+    pub exclude_tags: Vec<String>,
 
     /// Search term to filter notes by title or content
     #[clap(short = 's', long = "search")]
     pub search: Option<String>,
 
+    /// Only show notes containing these elements (comma-separated: tasks, code, attachments, links, broken-links)
+    #[clap(long = "has", value_delimiter = ',', value_parser = clap::builder::PossibleValuesParser::new(StructureFilter::NAMES))]
+    pub has: Vec<String>,
+
+    /// Only show pinned notes
+    #[clap(long = "pinned")]
+    pub pinned: bool,
+
+    /// Only show notes in this notebook or the notebooks below it
+    #[clap(long = "notebook")]
+    pub notebook: Option<String>,
+
+    /// Include archived notes
+    #[clap(long = "include-archived")]
+    pub include_archived: bool,
+
     /// Maximum number of notes to display
     #[clap(short = 'n', long = "limit", default_value = "20")]
     pub limit: usize,
 
+    /// Number of notes per page, printing a cursor for the next page when
+    /// there are more (replaces --limit)
+    #[clap(long = "page-size", conflicts_with = "limit")]
+    pub page_size: Option<usize>,
+
+    /// Continue the listing after the page that printed this cursor; the
+    /// filters and sort order must be the same
+    #[clap(long = "cursor")]
+    pub cursor: Option<String>,
+
     /// Show detailed information including content
     #[clap(short = 'd', long = "detailed")]
     pub detailed: bool,
@@ -31,19 +106,118 @@ pub struct ListNotesOptions {
     #[clap(short = 'f', long = "format", default_value = "text", value_parser = clap::builder::PossibleValuesParser::new(["text", "json"]))]
     pub format: String,
 
-    /// Sort notes by field (default is date)
-    #[clap(long = "sort-by", default_value = "date", value_parser = clap::builder::PossibleValuesParser::new(["date", "title", "id"]))]
+    /// Sort notes by field (default is date; notes without a due date sort last by due)
+    #[clap(long = "sort-by", default_value = "date", value_parser = clap::builder::PossibleValuesParser::new(SortField::NAMES))]
     pub sort_by: String,
 
     /// Sort in descending order
     #[clap(long = "desc")]
     pub descending: bool,
+
+    #[clap(flatten)]
+    pub dates: DateRangeOptions,
+}
+
+/// Creation and update date bounds shared by `list` and `search`
+#[derive(Debug, Clone, Default, Args)]
+pub struct DateRangeOptions {
+    /// Only notes created on or after this date (YYYY-MM-DD in local time, or RFC 3339)
+    #[clap(long = "created-after")]
+    pub created_after: Option<String>,
+
+    /// Only notes created on or before this date (YYYY-MM-DD in local time, or RFC 3339)
+    #[clap(long = "created-before")]
+    pub created_before: Option<String>,
+
+    /// Only notes last updated on or after this date (YYYY-MM-DD in local time, or RFC 3339)
+    #[clap(long = "updated-after", visible_alias = "since")]
+    pub updated_after: Option<String>,
+
+    /// Only notes last updated on or before this date (YYYY-MM-DD in local time, or RFC 3339)
+    #[clap(long = "updated-before")]
+    pub updated_before: Option<String>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct SearchOptions {
+    /// Search query; may combine terms with AND, OR, NOT, parentheses and "quoted phrases"
+    pub query: String,
+
+    /// Match the query literally as a substring instead of fuzzily, ignoring case
+    #[clap(long = "exact")]
+    pub exact: bool,
+
+    /// Match the query literally as a substring, respecting case (implies --exact)
+    #[clap(long = "case-sensitive")]
+    pub case_sensitive: bool,
+
+    /// Only match the query against note titles
+    #[clap(long = "title-only", conflicts_with = "content_only")]
+    pub title_only: bool,
+
+    /// Only match the query against note content
+    #[clap(long = "content-only")]
+    pub content_only: bool,
+
+    /// Maximum number of results to return
+    #[clap(short = 'l', long = "limit", default_value = "0")]
+    pub limit: usize,
+
+    /// Number of best matches to skip, for paging through results with --limit
+    #[clap(long = "offset", default_value = "0")]
+    pub offset: usize,
+
+    /// Output format (text, json)
+    #[clap(short = 'f', long = "format", default_value = "text", value_parser = clap::builder::PossibleValuesParser::new(["text", "json"]))]
+    pub format: String,
+
+    /// Include note content in results
+    #[clap(short = 'c', long = "include-content")]
+    pub include_content: bool,
+
+    /// Show each result's relevance score and the fields it matched (JSON
+    /// output always includes them)
+    #[clap(long = "show-scores")]
+    pub show_scores: bool,
+
+    /// Pick a result from a list with the arrow keys instead of printing them
+    /// all (falls back to printing when not run on a terminal)
+    #[clap(short = 'i', long = "interactive", conflicts_with = "format")]
+    pub interactive: bool,
+
+    /// What to do with the result picked with --interactive (view, edit)
+    #[clap(long = "then", default_value = "view", requires = "interactive", value_parser = clap::builder::PossibleValuesParser::new(["view", "edit"]))]
+    pub then: String,
+
+    /// Include notes that tag policies hide from search
+    #[clap(short = 'a', long)]
+    pub all: bool,
+
+    /// Leave out notes carrying this tag (repeatable)
+    #[clap(long = "exclude-tag")]
+    pub exclude_tags: Vec<String>,
+
+    /// Only return notes containing these elements (comma-separated: tasks, code, attachments, links, broken-links)
+    #[clap(long = "has", value_delimiter = ',', value_parser = clap::builder::PossibleValuesParser::new(StructureFilter::NAMES))]
+    pub has: Vec<String>,
+
+    /// Include archived notes
+    #[clap(long = "include-archived")]
+    pub include_archived: bool,
+
+    #[clap(flatten)]
+    pub dates: DateRangeOptions,
 }
 
 #[derive(Debug, Clone, Args)]
 pub struct EditNoteOptions {
     /// ID of the note to edit
-    pub id: String,
+    #[clap(required_unless_present = "by_title")]
+    pub id: Option<String>,
+
+    /// Select the note by its title instead of its ID
+    #[clap(long = "by-title", conflicts_with = "id")]
+    pub by_title: Option<String>,
 
     /// New title for the note
     #[clap(short = 't', long = "title")]
@@ -68,69 +242,351 @@ pub struct EditNoteOptions {
     /// Tags to remove (comma separated)
     #[clap(short = 'r', long = "remove-tags")]
     pub remove_tags: Option<String>,
+
+    /// Set a metadata entry, written KEY=VALUE (repeatable)
+    #[clap(long = "set-meta", value_name = "KEY=VALUE")]
+    pub set_meta: Vec<String>,
+
+    /// Remove a metadata entry (repeatable)
+    #[clap(long = "unset-meta", value_name = "KEY")]
+    pub unset_meta: Vec<String>,
+
+    /// Set the due date (YYYY-MM-DD, RFC 3339, today, tomorrow, +3d or +2w)
+    #[clap(long = "due", conflicts_with = "clear_due")]
+    pub due: Option<String>,
+
+    /// Remove the due date
+    #[clap(long = "clear-due")]
+    pub clear_due: bool,
+
+    /// Move the note to this notebook, such as work/project-x
+    #[clap(long = "notebook", conflicts_with = "clear_notebook")]
+    pub notebook: Option<String>,
+
+    /// Take the note out of its notebook
+    #[clap(long = "clear-notebook")]
+    pub clear_notebook: bool,
+
+    /// Edit the note even if it is locked
+    #[clap(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ExportOptions {
+    /// Path where exported files will be saved
+    #[clap(short, long)]
+    pub output: PathBuf,
+
+    /// Format to export to
+    #[clap(short, long, value_parser = ["markdown", "json", "html", "pdf"], default_value = "markdown")]
+    pub format: String,
+
+    /// Only export notes with this tag (repeatable)
+    #[clap(short, long = "tag")]
+    pub tags: Vec<String>,
+
+    /// Export notes carrying any of the given tags (default)
+    #[clap(long, conflicts_with = "all_tags")]
+    pub any_tag: bool,
+
+    /// Export only notes carrying every given tag
+    #[clap(long)]
+    pub all_tags: bool,
+
+    /// Only export notes created on or after this date (YYYY-MM-DD or RFC 3339)
+    #[clap(long)]
+    pub since: Option<String>,
+
+    /// Only export notes created on or before this date (YYYY-MM-DD or RFC 3339)
+    #[clap(long)]
+    pub until: Option<String>,
+
+    /// Export as a single file instead of multiple files
+    #[clap(short = 's', long)]
+    pub single_file: bool,
+
+    /// Order of the notes in a single-file export (default is creation date)
+    #[clap(long = "sort-by", default_value = "date", value_parser = clap::builder::PossibleValuesParser::new(["date", "title", "id"]))]
+    pub sort_by: String,
+
+    /// Sort in descending order
+    #[clap(long = "desc")]
+    pub descending: bool,
+
+    /// Apply the configured redaction rules (strict refuses notes that still leak)
+    #[clap(long, num_args = 0..=1, default_missing_value = "standard", value_parser = ["standard", "strict"])]
+    pub redact: Option<String>,
+
+    /// Print the export summary, including per-note redaction counts, as JSON
+    #[clap(long)]
+    pub json: bool,
 }
 
 #[derive(Debug, Clone, Args)]
 pub struct ImportOptions {
     /// Path to file or directory to import from
     #[clap(short = 'p', long = "path", required = true)]
-    path: String,
+    pub path: String,
 
     /// Format of the notes (markdown, json, text)
     #[clap(short = 'f', long = "format", default_value = "markdown", value_parser = clap::builder::PossibleValuesParser::new(["markdown", "md", "json", "text", "txt"]))]
-    format: String,
+    pub format: String,
 
     /// Tags to apply to all imported notes (comma separated)
     #[clap(short = 'g', long = "tags")]
-    tags: Option<String>,
+    pub tags: Option<String>,
 
     /// Use filenames as note titles when importing
     #[clap(long = "title-from-filename")]
-    title_from_filename: bool,
+    pub title_from_filename: bool,
 
     /// Recursive import (for directories)
     #[clap(short = 'r', long = "recursive")]
-    recursive: bool,
+    pub recursive: bool,
 
     /// Pattern to match files (glob syntax, e.g. "*.md")
     #[clap(long = "pattern")]
-    pattern: Option<String>,
+    pub pattern: Option<String>,
 
     /// Show detailed progress during import
     #[clap(short = 'v', long = "verbose")]
-    verbose: bool,
+    pub verbose: bool,
+
+    /// Continue an interrupted directory import (the default when one exists)
+    #[clap(long, conflicts_with = "force_reimport")]
+    pub resume: bool,
+
+    /// Import every file again, ignoring the progress journal and existing duplicates
+    #[clap(long)]
+    pub force_reimport: bool,
 }
 
-/// Available subcommands for the kbnotes application
+/// Alias management operations
 #[derive(Subcommand)]
-pub enum Commands {
-    /// Create a new note
+pub enum AliasCommand {
+    /// Add an alias to a note
+    Add {
+        /// ID, title, or alias of the note
+        id: String,
+
+        /// Alias to add
+        alias: String,
+    },
+
+    /// Remove an alias from a note
+    Remove {
+        /// ID, title, or alias of the note
+        id: String,
+
+        /// Alias to remove
+        alias: String,
+    },
+
+    /// List the aliases of a note
+    List {
+        /// ID, title, or alias of the note
+        id: String,
+    },
+}
+
+/// Note template operations
+#[derive(Subcommand)]
+pub enum TemplateCommand {
+    /// List the available templates
+    List,
+
+    /// Create a template and open it in the editor
+    New {
+        /// Template name (letters, digits, '-', '_' and '.')
+        name: String,
+
+        /// Only create the template file, without opening the editor
+        #[clap(long)]
+        no_edit: bool,
+    },
+}
+
+/// Task list operations
+#[derive(Subcommand)]
+pub enum TaskCommand {
+    /// Check an open task or uncheck a done one
+    Check {
+        /// ID, title, or alias of the note
+        id: String,
+
+        /// Line of the task, as shown by `kbnotes tasks`
+        line: usize,
+    },
+}
+
+/// Tag policy operations
+#[derive(Subcommand)]
+pub enum PolicyCommand {
+    /// List the configured tag policies and how many notes each applies to
+    Show,
+
+    /// Archive the notes whose tag policy asks for it
+    Apply {
+        /// Only list the notes that would be archived
+        #[clap(long)]
+        dry_run: bool,
+    },
+}
+
+/// Snapshot operations
+#[derive(Subcommand)]
+pub enum SnapshotCommand {
+    /// Store an immutable named snapshot of a note
     Create {
-        /// Title of the note
-        #[clap(short = 'T', long)]
-        title: String,
+        /// ID, title, or alias of the note
+        id: String,
 
-        /// Content of the note, can be markdown formatted
+        /// Snapshot name (letters, digits, '-', '_' and '.')
         #[clap(short, long)]
-        content: Option<String>,
+        name: String,
+    },
 
-        /// Open content in editor before saving
-        #[clap(short, long)]
-        edit: bool,
+    /// List the snapshots of a note
+    List {
+        /// ID, title, or alias of the note
+        id: String,
+    },
 
-        /// Tags to associate with the note (comma-separated)
-        #[clap(short = 't', long)]
-        tags: Option<String>,
+    /// Show the differences between a snapshot and the live note
+    Diff {
+        /// ID, title, or alias of the note
+        id: String,
 
-        /// Path to a file containing the note's content
-        #[clap(short, long)]
-        file: Option<PathBuf>,
+        /// Snapshot name
+        name: String,
+    },
+
+    /// Restore a snapshot into the live note or into a new note
+    Restore {
+        /// ID, title, or alias of the note
+        id: String,
+
+        /// Snapshot name
+        name: String,
+
+        /// Create a new note from the snapshot instead of overwriting the live note
+        #[clap(long)]
+        as_copy: bool,
+    },
+}
+
+/// Revision history operations
+#[derive(Subcommand)]
+pub enum HistoryCommand {
+    /// Bring back a previous version, keeping the current one in the history
+    Restore {
+        /// ID, title, or alias of the note
+        id: String,
+
+        /// Timestamp of the revision, as listed by `kbnotes history <id>`
+        timestamp: i64,
+    },
+}
+
+/// Trash operations
+#[derive(Subcommand)]
+pub enum TrashCommand {
+    /// List deleted notes, most recently deleted first
+    List,
+
+    /// Bring a deleted note back, with its attachments
+    Restore {
+        /// ID of the deleted note, as listed by `kbnotes trash list`
+        id: String,
+    },
+
+    /// Delete the notes in the trash for good
+    Empty {
+        /// Only purge notes deleted at least this long ago (e.g. 12h, 30d, 2w)
+        #[clap(long)]
+        older_than: Option<String>,
+
+        /// Also delete the snapshots of the purged notes, which are kept otherwise
+        #[clap(long)]
+        include_snapshots: bool,
+    },
+}
+
+/// Backup maintenance
+#[derive(Subcommand)]
+pub enum BackupAction {
+    /// List full backup archives and per-note backups, newest first
+    List {
+        /// Output format (text, json)
+        #[clap(short = 'f', long = "format", default_value = "text", value_parser = clap::builder::PossibleValuesParser::new(["text", "json"]))]
+        format: String,
+
+        /// Don't open the archives to count their notes
+        #[clap(long)]
+        no_count: bool,
+    },
+
+    /// Show the backup scheduler's state: its last run, the last backup it
+    /// wrote, its last error and when the next backup is due
+    Status {
+        /// Output format (text, json)
+        #[clap(short = 'f', long = "format", default_value = "text", value_parser = clap::builder::PossibleValuesParser::new(["text", "json"]))]
+        format: String,
     },
 
+    /// Compare a backup with the current vault: notes only in the backup,
+    /// only in the vault, and in both but changed
+    Diff {
+        /// Path to the backup file
+        backup_file: PathBuf,
+
+        /// Print a unified diff of this note's content, from the backup to the vault
+        #[clap(long, value_name = "ID", conflicts_with = "format")]
+        show_content_diff: Option<String>,
+
+        /// Output format (text, json)
+        #[clap(short = 'f', long = "format", default_value = "text", value_parser = clap::builder::PossibleValuesParser::new(["text", "json"]))]
+        format: String,
+    },
+
+    /// Upload a backup archive to the configured backup targets and prune
+    /// them to their retention
+    Push {
+        /// Path to the backup file
+        #[clap(required_unless_present = "latest")]
+        backup_file: Option<PathBuf>,
+
+        /// Push the newest archive in the backup directory
+        #[clap(long, conflicts_with = "backup_file")]
+        latest: bool,
+    },
+
+    /// Remove old per-note backups: all but the newest `max_note_backups` of
+    /// each note, and those of deleted notes past `orphan_backup_retention_days`
+    Prune {
+        /// Remove backups of deleted notes from this age on, instead of after
+        /// `orphan_backup_retention_days` (e.g. 12h, 30d, 2w)
+        #[clap(long)]
+        older_than: Option<String>,
+    },
+}
+
+/// Available subcommands for the kbnotes application
+#[derive(Subcommand)]
+pub enum Commands {
+    /// Create a new note
+    Create(CreateNoteOptions),
+
     /// View a note by ID
     View {
         /// ID of the note to view
-        id: String,
+        #[clap(required_unless_present = "title")]
+        id: Option<String>,
+
+        /// Select the note by its title instead of its ID
+        #[clap(long, conflicts_with = "id")]
+        title: Option<String>,
 
         /// Format output as raw JSON
         #[clap(short, long)]
@@ -139,6 +595,32 @@ pub enum Commands {
         /// Open in the default editor
         #[clap(short, long)]
         edit: bool,
+
+        /// Only print the section under this heading ("Parent > Heading" when the name is ambiguous)
+        #[clap(short, long, conflicts_with_all = ["json", "lines"])]
+        section: Option<String>,
+
+        /// Only print these lines of the content (A:B, A:, :B or A, starting at 1)
+        #[clap(short, long, conflicts_with = "json")]
+        lines: Option<String>,
+
+        /// Print directly instead of paging content longer than the terminal
+        #[clap(long)]
+        no_pager: bool,
+    },
+
+    /// Show word, character and line counts and the reading time of a note
+    Stats {
+        /// ID, title, or alias of the note (statistics of the whole vault when omitted)
+        id: Option<String>,
+
+        /// Format output as JSON
+        #[clap(short, long, conflicts_with = "format")]
+        json: bool,
+
+        /// Output format (text, json)
+        #[clap(short = 'f', long = "format", default_value = "text", value_parser = clap::builder::PossibleValuesParser::new(["text", "json"]))]
+        format: String,
     },
 
     /// List all notes, optionally filtering by tag
@@ -153,47 +635,215 @@ pub enum Commands {
     #[clap(
         name = "search",
         about = "Search for notes containing specific text",
-        long_about = "Search for notes containing specific text in either title, content, or both.\n\nExamples:\n  kbnotes search \"project ideas\"\n  kbnotes search \"meeting\" --title-only\n  kbnotes search \"todo\" --limit 5 --format json"
+        long_about = "Search for notes containing specific text in either title, content, or both.\n\nUse --title-only or --content-only to search a single field."
     )]
-    Search {
-        /// Search query
-        query: String,
-
-        /// Maximum number of results to return
-        #[clap(short = 'l', long = "limit", default_value = "0")]
-        limit: usize,
-
-        /// Output format (text, json)
-        #[clap(short = 'f', long = "format", default_value = "text", value_parser = clap::builder::PossibleValuesParser::new(["text", "json"]))]
-        format: String,
-
-        /// Include note content in results
-        #[clap(short = 'c', long = "include-content")]
-        include_content: bool,
-    },
+    Search(SearchOptions),
 
     /// Edit an existing note
     #[clap(
         name = "edit",
         about = "Edit an existing note",
-        long_about = "Edit a note's title, content, or tags. Content can be provided directly, read from a file, or entered using your default editor.\n\nExamples:\n  kbnotes edit abc123 --title \"Updated Title\"\n  kbnotes edit abc123 --content \"New content\"\n  kbnotes edit abc123 --file updates.md\n  kbnotes edit abc123 --edit\n  kbnotes edit abc123 --add-tags \"important,follow-up\""
+        long_about = "Edit a note's title, content, or tags. Content can be provided directly, read from a file, or entered using your default editor."
     )]
     Edit(EditNoteOptions),
 
+    /// Show or create the note associated with the current project directory
+    #[clap(
+        name = "here",
+        about = "Show or create the note for the current project directory",
+        long_about = "Find the note associated with the current directory or one of its parents, either through a .kbnote marker file or the note's recorded project path."
+    )]
+    Here {
+        /// Create a note for this directory if none is associated yet
+        #[clap(short, long, conflicts_with = "list")]
+        create: bool,
+
+        /// List every note associated with a project path
+        #[clap(short, long)]
+        list: bool,
+    },
+
+    /// List unread notes and mark notes read or unread
+    Inbox {
+        /// Only consider notes with this tag
+        #[clap(short, long)]
+        tag: Option<String>,
+
+        /// Mark notes as read (comma-separated IDs; all listed unread notes when omitted)
+        #[clap(long, num_args = 0..=1, value_delimiter = ',', conflicts_with = "mark_unread")]
+        mark_read: Option<Vec<String>>,
+
+        /// Mark notes as unread again (comma-separated IDs)
+        #[clap(long, value_delimiter = ',')]
+        mark_unread: Option<Vec<String>>,
+    },
+
+    /// Link a note to another one from its "Related" section
+    Link {
+        /// ID of the note to add the link to
+        id: String,
+
+        /// ID of the note to link to
+        target: String,
+    },
+
+    /// Attach a file (PDF, image, ...) to a note, or remove an attachment
+    Attach {
+        /// ID of the note
+        id: String,
+
+        /// File to attach
+        #[clap(required_unless_present = "remove")]
+        file: Option<PathBuf>,
+
+        /// Remove the attachment with this file name instead
+        #[clap(long, conflicts_with = "file")]
+        remove: Option<String>,
+    },
+
+    /// Manage alternative names a note can be referenced by
+    Alias {
+        #[clap(subcommand)]
+        action: AliasCommand,
+    },
+
+    /// Manage the templates notes can be created from
+    Template {
+        #[clap(subcommand)]
+        action: TemplateCommand,
+    },
+
+    /// List the previous versions of a note kept on every update
+    #[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+    History {
+        /// ID, title, or alias of the note
+        #[clap(required = true)]
+        id: Option<String>,
+
+        #[clap(subcommand)]
+        action: Option<HistoryCommand>,
+    },
+
+    /// Named, immutable snapshots of individual notes
+    Snapshot {
+        #[clap(subcommand)]
+        action: SnapshotCommand,
+    },
+
+    /// Per-tag policies (auto-archiving, backup and search exclusion, delete protection)
+    Policies {
+        #[clap(subcommand)]
+        action: PolicyCommand,
+    },
+
+    /// Pin a note so it is listed first
+    Pin {
+        /// ID of the note to pin
+        id: String,
+    },
+
+    /// Unpin a pinned note
+    Unpin {
+        /// ID of the note to unpin
+        id: String,
+    },
+
+    /// Lock a note, making it read-only until it is unlocked
+    Lock {
+        /// ID of the note to lock
+        id: String,
+    },
+
+    /// Unlock a locked note
+    Unlock {
+        /// ID of the note to unlock
+        id: String,
+    },
+
+    /// List notes with a due date: overdue, due today and upcoming
+    Due,
+
+    /// List notebooks as a tree, with the number of notes in each
+    Notebooks {
+        /// Only show this notebook and the notebooks below it
+        notebook: Option<String>,
+    },
+
+    /// List the Markdown tasks (`- [ ]`) of all notes
+    #[clap(args_conflicts_with_subcommands = true)]
+    Tasks {
+        /// Show done tasks instead of open ones
+        #[clap(long)]
+        done: bool,
+
+        /// Only show tasks of notes with this tag
+        #[clap(long)]
+        tag: Option<String>,
+
+        #[clap(subcommand)]
+        action: Option<TaskCommand>,
+    },
+
+    /// Archive a note, hiding it from list and search without deleting it
+    Archive {
+        /// ID of the note to archive
+        id: String,
+    },
+
+    /// Bring an archived note back
+    Unarchive {
+        /// ID of the note to unarchive
+        id: String,
+    },
+
     /// Delete a note by ID
     Delete {
         /// ID of the note to delete
-        id: String,
+        #[clap(required_unless_present_any = ["title", "tags"])]
+        id: Option<String>,
 
-        /// Skip confirmation prompt
+        /// Select the note by its title instead of its ID
+        #[clap(long, conflicts_with = "id")]
+        title: Option<String>,
+
+        /// Delete every note carrying this tag, archived ones included
+        /// (repeatable: notes carrying any of the tags)
+        #[clap(long = "tag", conflicts_with_all = ["id", "title"])]
+        tags: Vec<String>,
+
+        /// Skip confirmation prompt, and delete protected or locked notes
         #[clap(short, long)]
         force: bool,
+
+        /// Delete the note for good instead of moving it to the trash (needs --force)
+        #[clap(long, requires = "force")]
+        permanent: bool,
+
+        /// Also delete the note's snapshots, which a permanent deletion keeps otherwise
+        #[clap(long, requires = "permanent")]
+        include_snapshots: bool,
+    },
+
+    /// Deleted notes: list, restore, or purge them for good
+    Trash {
+        #[clap(subcommand)]
+        action: TrashCommand,
     },
 
     /// Tag operations (add, remove, list)
     Tag {
         /// ID of the note to modify
-        id: String,
+        #[clap(required_unless_present_any = ["title", "filter_tags"])]
+        id: Option<String>,
+
+        /// Select the note by its title instead of its ID
+        #[clap(long, conflicts_with = "id")]
+        title: Option<String>,
+
+        /// Change every note carrying this tag, archived ones included
+        /// (repeatable: notes carrying any of the tags)
+        #[clap(long = "filter-tag", conflicts_with_all = ["id", "title", "list"])]
+        filter_tags: Vec<String>,
 
         /// Tags to add (comma-separated)
         #[clap(short, long)]
@@ -206,32 +856,92 @@ pub enum Commands {
         /// List all tags for the note
         #[clap(short, long)]
         list: bool,
+
+        /// Change the tags even if the note is locked, and skip the
+        /// confirmation of --filter-tag changes
+        #[clap(long)]
+        force: bool,
     },
 
     /// Create a backup of all notes
+    #[clap(args_conflicts_with_subcommands = true)]
     Backup {
         /// Path for the backup file (default uses config setting)
         #[clap(short, long)]
         output: Option<PathBuf>,
+
+        /// Only back up the notes updated since the last backup, on top of
+        /// the latest full backup in the backup directory
+        #[clap(long, conflicts_with = "output")]
+        incremental: bool,
+
+        #[clap(subcommand)]
+        action: Option<BackupAction>,
     },
 
     /// Restore notes from a backup
     Restore {
         /// Path to the backup file
-        backup_file: PathBuf,
+        #[clap(required_unless_present_any = ["rollback", "latest"])]
+        backup_file: Option<PathBuf>,
+
+        /// Restore the newest archive in the backup directory, as listed by
+        /// `kbnotes backup list`
+        #[clap(long, conflicts_with = "backup_file")]
+        latest: bool,
 
         /// Skip confirmation prompt
         #[clap(short, long)]
         force: bool,
+
+        /// Replace notes that already exist instead of keeping them
+        #[clap(long, conflicts_with = "staged")]
+        overwrite: bool,
+
+        /// Make the vault match the backup: overwrite existing notes and move
+        /// notes that aren't in the backup to the trash
+        #[clap(long, conflicts_with_all = ["staged", "rollback", "ids", "tags"])]
+        replace: bool,
+
+        /// Extract the backup into a new directory and swap it in, keeping the
+        /// current notes directory for `--rollback`
+        #[clap(long)]
+        staged: bool,
+
+        /// Switch back to the notes directory kept by the last staged restore
+        #[clap(long, conflicts_with_all = ["backup_file", "latest", "staged", "overwrite"])]
+        rollback: bool,
+
+        /// Only restore these notes, given as comma-separated IDs
+        #[clap(long, value_delimiter = ',', conflicts_with_all = ["staged", "rollback"])]
+        ids: Vec<String>,
+
+        /// Only restore notes carrying this tag (repeatable, any tag matches)
+        #[clap(short, long = "tag", conflicts_with_all = ["staged", "rollback"])]
+        tags: Vec<String>,
+
+        /// Restore the notes without the files attached to them in the backup
+        #[clap(long, conflicts_with_all = ["staged", "rollback"])]
+        no_attachments: bool,
+
+        /// Show what the restore would create, overwrite or skip without
+        /// writing anything
+        #[clap(long, conflicts_with_all = ["staged", "rollback"])]
+        dry_run: bool,
+
+        /// Output format of the restore summary or dry run (text, json); JSON
+        /// output needs --force, as it skips the preview and prompt
+        #[clap(long = "format", default_value = "text", conflicts_with_all = ["staged", "rollback"], value_parser = clap::builder::PossibleValuesParser::new(["text", "json"]))]
+        format: String,
     },
 
     /// Configuration management
     Config {
         /// Show current configuration
-        #[clap(short = 'S', long)]
+        #[clap(short = 'S', long, conflicts_with_all = ["set", "reset"])]
         show: bool,
 
-        /// Update a configuration setting
+        /// Update a configuration setting, given as key=value
         #[clap(short, long)]
         set: Option<String>,
 
@@ -244,27 +954,100 @@ pub enum Commands {
     #[clap(
         name = "import",
         about = "Import notes from external files or directories",
-        long_about = "Import one or more notes from external files or directories with various format options.\n\nExamples:\n  kbnotes import -p ~/Documents/notes/ -f markdown\n  kbnotes import -p exported_notes.json -f json -g \"imported,archive\"\n  kbnotes import -p meeting_notes.md -f markdown --title-from-filename"
+        long_about = "Import one or more notes from external files or directories with various format options."
     )]
     Import(ImportOptions),
 
     /// Export notes to various formats
-    Export {
-        /// Path where exported files will be saved
-        #[clap(short, long)]
-        output: PathBuf,
+    Export(ExportOptions),
 
-        /// Format to export to
-        #[clap(short, long, value_parser = ["markdown", "json", "html", "pdf"], default_value = "markdown")]
-        format: String,
+    /// Import legacy per-note backup files from the backup directory into note snapshots
+    MigrateHistory {
+        /// Delete the migrated backup files afterwards
+        #[clap(long, conflicts_with = "archive")]
+        delete: bool,
 
-        /// Filter notes by tag for export
-        #[clap(short, long)]
-        tag: Option<String>,
+        /// Move the migrated backup files into the backup directory's legacy-archive folder
+        #[clap(long)]
+        archive: bool,
+
+        /// Only report what would be imported
+        #[clap(long, conflicts_with_all = ["delete", "archive"])]
+        dry_run: bool,
+    },
+
+    /// Show note storage sizes, compress or decompress every note file, or
+    /// convert the vault to another storage format
+    MigrateFormat {
+        /// Compress notes larger than the threshold
+        #[clap(long, conflicts_with = "decompress")]
+        compress: bool,
+
+        /// Store every note as plain JSON
+        #[clap(long)]
+        decompress: bool,
+
+        /// Size in bytes above which notes are compressed (defaults to compress_threshold_bytes)
+        #[clap(long, requires = "compress")]
+        threshold: Option<u64>,
+
+        /// Rewrite every note file in this format and save it as storage_format
+        #[clap(long, value_parser = ["json", "markdown", "split"], conflicts_with_all = ["compress", "decompress"])]
+        to: Option<String>,
+    },
+
+    /// Move every note to another storage backend and save it as the backend
+    MigrateBackend {
+        /// The backend to move the notes to
+        #[clap(value_parser = ["files", "sqlite"])]
+        to: String,
+    },
+
+    /// Show where the vault lives, how many notes it holds and cache statistics
+    Status {
+        /// Print the status as JSON
+        #[clap(long)]
+        json: bool,
+    },
 
-        /// Export as a single file instead of multiple files
-        #[clap(short = 's', long)]
-        single_file: bool,
+    /// Check the vault for unreadable, misplaced and missing note files
+    Doctor {
+        /// Move misplaced note files to their expected location, quarantine
+        /// unreadable ones in `.corrupt/` and reload the cache; with
+        /// `--corrupt`, restore the quarantined notes that can be recovered
+        #[clap(long)]
+        fix: bool,
+
+        /// List the quarantined note files and try to recover them
+        #[clap(long)]
+        corrupt: bool,
+    },
+
+    /// Rebuild the full-text search index from scratch, in the background
+    /// behind foreground commands
+    Reindex {
+        /// Rebuild in one go, for scripts that need the complete index before
+        /// they go on
+        #[clap(long)]
+        foreground: bool,
+    },
+
+    /// List the IDs and update times of notes created, modified or deleted
+    /// since a point in time, for tools that sync the vault elsewhere
+    Changed {
+        /// Point in time (YYYY-MM-DD in local time, or RFC 3339)
+        #[clap(long)]
+        since: String,
+
+        /// Output format (text, json)
+        #[clap(short = 'f', long = "format", default_value = "text", value_parser = clap::builder::PossibleValuesParser::new(["text", "json"]))]
+        format: String,
+    },
+
+    /// Show example invocations of a command
+    Examples {
+        /// Command to show examples for (all commands when omitted)
+        command: Option<String>,
     },
 }
 
@@ -279,19 +1062,429 @@ pub struct NoteVersion {
     pub updated_at: DateTime<Utc>,
 }
 
-/// Summary of a backup restoration operation
+/// Counters and size of the notes cache, as reported by `kbnotes status`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    /// Number of notes currently cached
+    pub entries: usize,
+    /// Approximate memory taken by the cached notes' text, in bytes
+    pub bytes_estimate: u64,
+    /// Lookups answered from the cache
+    pub hits: u64,
+    /// Lookups that had to go to disk
+    pub misses: u64,
+    /// Notes dropped from the cache by `evict` or `clear_cache`
+    pub evictions: u64,
+}
+
+/// Notes changed since a point in time, as reported by
+/// [`NoteStorage::get_notes_modified_since`](crate::NoteStorage::get_notes_modified_since)
+#[derive(Debug, Clone, Default)]
+pub struct NoteChanges {
+    /// Notes created or modified, least recently updated first
+    pub modified: Vec<Note>,
+    /// Notes trashed or purged, least recently deleted first
+    pub deleted: Vec<DeletedNote>,
+}
+
+/// Durability mode and sync counters of the note files, as reported by
+/// `kbnotes status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DurabilityStats {
+    /// The configured `fsync` mode
+    pub mode: FsyncMode,
+    /// Files synced since the storage was created
+    pub synced_files: u64,
+    /// Directories synced since the storage was created
+    pub synced_directories: u64,
+    /// Written files waiting for the sync at shutdown (`on_close` only)
+    pub pending_syncs: usize,
+}
+
+/// Space taken by note files, as reported by `migrate-format`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StorageSizes {
+    /// Number of note files measured
+    pub notes: usize,
+    /// Number of note files stored compressed
+    pub compressed_notes: usize,
+    /// Total size of the notes serialized as JSON
+    pub logical_bytes: u64,
+    /// Total size of the note files on disk
+    pub disk_bytes: u64,
+}
+
+/// Aggregate figures about the whole vault, as shown by `kbnotes stats`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VaultStatistics {
+    /// Number of notes, archived ones included
+    pub total_notes: usize,
+    /// Total size of the notes' content, in bytes
+    pub content_bytes: u64,
+    /// Number of notes without any tag
+    pub untagged_notes: usize,
+    /// Number of notes not viewed since they were created or marked unread
+    pub unread_notes: usize,
+    /// Number of notes carrying each tag, most used first
+    pub notes_per_tag: Vec<TagCount>,
+    /// Number of notes created in each month (local time), oldest first
+    pub notes_per_month: Vec<MonthCount>,
+    /// The notes with the most content, largest first
+    pub largest_notes: Vec<NoteSize>,
+    /// Total size of the files in the backup directory, in bytes
+    pub backup_bytes: u64,
+}
+
+/// Number of notes carrying a tag
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TagCount {
+    /// The tag, normalized like tag comparisons do
+    pub tag: String,
+    /// Number of notes carrying it
+    pub notes: usize,
+}
+
+/// Number of notes created in a month
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MonthCount {
+    /// The month, as `YYYY-MM`
+    pub month: String,
+    /// Number of notes created in it
+    pub notes: usize,
+}
+
+/// Size of a note's content
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct NoteSize {
+    /// ID of the note
+    pub id: String,
+    /// Title of the note
+    pub title: String,
+    /// Size of its content, in bytes
+    pub bytes: u64,
+}
+
+/// Contents of a full backup, stored in the archive as its manifest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    /// When the backup was created
+    pub created_at: DateTime<Utc>,
+    /// Layout version of the archive; 0 for backups written before it was recorded
+    #[serde(default)]
+    pub format_version: u32,
+    /// Version of kbnotes that wrote the backup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_version: Option<String>,
+    /// Number of notes in the backup
+    pub notes: usize,
+    /// IDs of notes left out because their tag policy excludes them from backups
+    #[serde(default)]
+    pub excluded_notes: Vec<String>,
+    /// What an incremental backup builds on; `None` for full backups
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub incremental: Option<IncrementalManifest>,
+}
+
+/// Where an incremental backup sits in its chain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IncrementalManifest {
+    /// File name of the full backup the chain starts from
+    pub base: String,
+    /// Only notes updated after this time are in the archive
+    pub since: DateTime<Utc>,
+    /// IDs of every note backed up at the time, so that notes deleted since
+    /// the previous backup of the chain are left out on restore
+    pub note_ids: Vec<String>,
+}
+
+/// Progress of the incremental backup chain, kept in the backup directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupChainState {
+    /// File name of the latest full backup, which the next incremental builds on
+    pub base: String,
+    /// When the latest successful backup, full or incremental, was taken
+    pub last_backup_at: DateTime<Utc>,
+    /// Number of incremental backups written since the full one
+    pub incrementals: u32,
+}
+
+/// What restoring a full backup would do, read before restoring it
 #[derive(Debug, Clone)]
+pub struct BackupPreview {
+    /// Path to the backup file
+    pub backup_file: PathBuf,
+    /// Total number of notes found in the backup
+    pub total_notes: usize,
+    /// IDs of notes in the backup that already exist
+    pub existing_notes: Vec<String>,
+}
+
+/// How a backup differs from the current vault, as found by
+/// `NoteStorage::diff_backup`
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupDiff {
+    /// Path to the backup file
+    pub backup_file: PathBuf,
+    /// Notes the backup holds but the vault doesn't, e.g. deleted since
+    pub only_in_backup: Vec<BackupDiffEntry>,
+    /// Notes the vault holds but the backup doesn't, e.g. created since
+    pub only_in_vault: Vec<BackupDiffEntry>,
+    /// Notes in both whose versions differ
+    pub changed: Vec<ChangedNote>,
+    /// Number of notes identical in both
+    pub unchanged: usize,
+    /// Notes of the backup that couldn't be read: (note_id, error_message)
+    pub failed_notes: Vec<(String, String)>,
+}
+
+/// A note found on only one side of a [`BackupDiff`]
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupDiffEntry {
+    /// ID of the note
+    pub note_id: String,
+    /// Title of the note
+    pub title: String,
+    /// When the note was last updated
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A note whose backed-up version differs from the one in the vault
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangedNote {
+    /// ID of the note
+    pub note_id: String,
+    /// Title of the note in the vault
+    pub title: String,
+    /// When the backed-up version was last updated
+    pub backup_updated_at: DateTime<Utc>,
+    /// When the vault's version was last updated
+    pub vault_updated_at: DateTime<Utc>,
+    /// Whether the title, content or tags differ, not only the timestamps
+    pub content_changed: bool,
+}
+
+/// Summary of a backup restoration operation
+#[derive(Debug, Clone, Serialize)]
 pub struct RestoreBackupSummary {
     /// Path to the backup file that was restored
     pub backup_file: PathBuf,
+    /// Whether this was a dry run, which only reports what a restore would do
+    pub dry_run: bool,
     /// Total number of notes found in the backup
     pub total_notes: usize,
-    /// Number of notes successfully restored
+    /// Number of notes successfully restored (or that would be, in a dry run)
     pub notes_restored: usize,
     /// Number of notes skipped (e.g., due to existing notes with overwrite disabled)
     pub notes_skipped: usize,
+    /// Number of notes left out by the restore's filter
+    pub notes_filtered: usize,
+    /// Number of local notes missing from the backup that a replacing
+    /// restore moved to the trash (or would, in a dry run)
+    pub notes_removed: usize,
     /// Details about notes that failed to restore
     pub failed_notes: Vec<(String, String)>, // (note_id, error_message)
+    /// What the restore did (or would do) with each note it didn't filter
+    /// out or fail on, sorted by note ID
+    pub actions: Vec<NoteRestoreAction>,
+    /// How long the restore took, in milliseconds
+    pub elapsed_ms: u64,
+}
+
+/// Renders the counts of a restore, then its failed notes with the reasons
+impl fmt::Display for RestoreBackupSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} {} of {} notes from {} in {:.2}s",
+            if self.dry_run {
+                "Would restore"
+            } else {
+                "Restored"
+            },
+            self.notes_restored,
+            self.total_notes,
+            self.backup_file.display(),
+            self.elapsed_ms as f64 / 1000.0
+        )?;
+        let counts = [
+            ("Restored", self.notes_restored),
+            ("Skipped", self.notes_skipped),
+            ("Filtered out", self.notes_filtered),
+            ("Removed", self.notes_removed),
+            ("Failed", self.failed_notes.len()),
+        ];
+        for (label, count) in counts {
+            // Counts of modes the restore didn't use stay out of the way
+            if count > 0 || matches!(label, "Restored" | "Skipped" | "Failed") {
+                writeln!(f, "  {:<13} {}", format!("{}:", label), count)?;
+            }
+        }
+
+        if !self.failed_notes.is_empty() {
+            let width = self
+                .failed_notes
+                .iter()
+                .map(|(note_id, _)| note_id.len())
+                .max()
+                .unwrap_or(0)
+                .max("ID".len());
+            writeln!(f, "\nFailed notes:")?;
+            writeln!(f, "  {:<width$}  Reason", "ID")?;
+            for (note_id, error) in &self.failed_notes {
+                writeln!(f, "  {:<width$}  {}", note_id, error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What restoring a backup does with one of its notes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreAction {
+    /// The note doesn't exist and is created
+    Create,
+    /// The note exists and is replaced by the backup's version
+    Overwrite,
+    /// The note exists and is kept
+    Skip,
+    /// The note is only in the vault and a replacing restore moves it to
+    /// the trash
+    Remove,
+}
+
+impl fmt::Display for RestoreAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            RestoreAction::Create => "create",
+            RestoreAction::Overwrite => "overwrite",
+            RestoreAction::Skip => "skip",
+            RestoreAction::Remove => "remove",
+        })
+    }
+}
+
+/// A note of a restored backup and what the restore does with it
+#[derive(Debug, Clone, Serialize)]
+pub struct NoteRestoreAction {
+    /// ID of the note
+    pub note_id: String,
+    /// Title of the note in the backup, when it was read (skipped notes are
+    /// only read in a dry run)
+    pub title: Option<String>,
+    /// What happens to the note
+    pub action: RestoreAction,
+}
+
+/// Outcome of [`NoteStorage::load_notes`](crate::NoteStorage::load_notes)
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    /// Number of notes loaded into the cache
+    pub loaded: usize,
+    /// Note files that could not be read, with why; they have been moved to
+    /// the quarantine unless that failed too
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Outcome of an operation applied to several notes at once
+#[derive(Debug, Clone, Default)]
+pub struct BatchReport {
+    /// IDs of the notes the operation changed
+    pub succeeded: Vec<String>,
+    /// IDs of the notes that needed no change
+    pub unchanged: Vec<String>,
+    /// Notes the operation failed on
+    pub failed: Vec<(String, String)>, // (note_id, error_message)
+}
+
+/// Outcome of a staged restore, which swaps in a freshly extracted notes directory
+#[derive(Debug, Clone)]
+pub struct StagedRestoreSummary {
+    /// Path to the backup file that was restored
+    pub backup_file: PathBuf,
+    /// Number of notes loaded from the restored directory
+    pub notes_restored: usize,
+    /// Where the previous notes directory was moved to
+    pub previous_dir: PathBuf,
+    /// Notes left out of the backup by tag policy, which only remain in `previous_dir`
+    pub excluded_notes: Vec<String>,
+    /// Old directories removed to stay within `max_pre_restore_dirs`
+    pub pruned_dirs: Vec<PathBuf>,
+}
+
+/// Outcome of [`NoteStorage::prune_backups`](crate::NoteStorage::prune_backups)
+#[derive(Debug, Clone, Default)]
+pub struct BackupPruneReport {
+    /// Backups of existing notes beyond the newest `max_note_backups`
+    pub pruned: Vec<PathBuf>,
+    /// Backups and deletion records of deleted notes past the age limit
+    pub orphaned: Vec<PathBuf>,
+    /// Per-note files moved from the top of the backup directory into their
+    /// note's directory
+    pub moved: usize,
+}
+
+/// Backups in the backup directory, as listed by
+/// [`NoteStorage::list_backups`](crate::NoteStorage::list_backups)
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BackupInventory {
+    /// Full and incremental backup archives, newest first
+    pub archives: Vec<ArchiveBackupInfo>,
+    /// Per-note backups grouped by note, the note with the newest backup first
+    pub notes: Vec<NoteBackupsInfo>,
+}
+
+/// A full (`kbnotes_backup_*.zip`) or incremental (`kbnotes_incremental_*.zip`)
+/// backup archive
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveBackupInfo {
+    /// Path to the archive
+    pub path: PathBuf,
+    /// When the archive was written, by its name or else its modification time
+    pub created_at: DateTime<Utc>,
+    /// Size of the archive in bytes
+    pub size: u64,
+    /// Number of notes in the archive, when it was opened to count them
+    pub notes: Option<usize>,
+    /// Whether the archive is an incremental backup (`kbnotes_incremental_*.zip`)
+    pub incremental: bool,
+    /// Full backup an incremental backup builds on, as its manifest says
+    pub base: Option<PathBuf>,
+}
+
+/// The per-note backups and deletion records of one note
+#[derive(Debug, Clone, Serialize)]
+pub struct NoteBackupsInfo {
+    /// ID of the note, decoded from the file names
+    pub note_id: String,
+    /// Newest first
+    pub backups: Vec<NoteBackupInfo>,
+}
+
+/// A per-note backup file or deletion record
+#[derive(Debug, Clone, Serialize)]
+pub struct NoteBackupInfo {
+    /// Path to the file
+    pub path: PathBuf,
+    /// When the file was written, by its name
+    pub created_at: DateTime<Utc>,
+    /// Size of the file in bytes
+    pub size: u64,
+    /// Whether this is a deletion record rather than a JSON backup
+    pub deletion_record: bool,
+}
+
+/// Outcome of rolling back a staged restore
+#[derive(Debug, Clone)]
+pub struct RestoreRollbackSummary {
+    /// The preserved directory that became the notes directory again
+    pub restored_dir: PathBuf,
+    /// Where the notes directory that was rolled back was moved to
+    pub replaced_dir: PathBuf,
+    /// Number of notes loaded after the rollback
+    pub notes: usize,
+    /// Old directories removed to stay within `max_pre_restore_dirs`
+    pub pruned_dirs: Vec<PathBuf>,
 }
 
 /// Represents the result of an attempt to resolve a concurrent modification conflict
@@ -301,7 +1494,202 @@ pub enum ConflictResolution {
     /// The update should use the server's version (discard changes)
     UseServerVersion,
     /// The update should use a merged version
-    UseMergedVersion(Note),
+    UseMergedVersion(Box<Note>),
     /// The conflict was not resolved
     Unresolved,
 }
+
+/// An immutable, named copy of a note taken at a point in time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoteSnapshot {
+    /// Name given to the snapshot
+    pub name: String,
+    /// When the snapshot was taken
+    pub created_at: DateTime<Utc>,
+    /// The note as it was when the snapshot was taken
+    pub note: Note,
+}
+
+/// A previous version of a note, kept in its revision history when the note
+/// was updated
+#[derive(Debug, Clone, Serialize)]
+pub struct NoteRevision {
+    /// Key of the revision: the version's `updated_at` in Unix milliseconds
+    pub timestamp: i64,
+    /// The note as it was before the update
+    pub note: Note,
+}
+
+/// Naming scheme of a per-note backup file written before snapshots existed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LegacyBackupKind {
+    /// `{id}_{ts}.json`, written when a note was saved
+    Backup,
+    /// `{id}_pre_update_{ts}_{updated_ts}.json`, written before an update
+    PreUpdate,
+    /// `{id}_post_update_{ts}_{updated_ts}.json`, written after an update
+    PostUpdate,
+    /// `{id}_predeletion_{ts}.json`, written before a deletion
+    PreDeletion,
+}
+
+impl LegacyBackupKind {
+    /// Label used in the names of snapshots imported from this kind of file
+    pub fn label(&self) -> &'static str {
+        match self {
+            LegacyBackupKind::Backup => "backup",
+            LegacyBackupKind::PreUpdate => "pre-update",
+            LegacyBackupKind::PostUpdate => "post-update",
+            LegacyBackupKind::PreDeletion => "predeletion",
+        }
+    }
+}
+
+/// What a legacy backup file name says about its contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LegacyBackupName {
+    /// ID of the backed-up note, decoded from the file name
+    pub note_id: String,
+    /// Naming scheme the file follows
+    pub kind: LegacyBackupKind,
+    /// When the backup was written
+    pub timestamp: DateTime<Utc>,
+}
+
+/// What happens to legacy backup files once their content is in the history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LegacyDisposition {
+    /// Leave the files where they are
+    Keep,
+    /// Remove the files
+    Delete,
+    /// Move the files into the archive directory inside the backup directory
+    Archive,
+}
+
+/// A legacy backup file imported as a snapshot
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportedLegacyBackup {
+    /// The legacy backup file
+    pub source: PathBuf,
+    /// ID of the note the snapshot belongs to
+    pub note_id: String,
+    /// Name of the created snapshot
+    pub snapshot: String,
+}
+
+/// A legacy backup file that could not be imported
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedLegacyBackup {
+    /// The legacy backup file
+    pub path: PathBuf,
+    /// Why it was skipped
+    pub reason: String,
+}
+
+/// Outcome of migrating legacy backup files into note snapshots
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HistoryMigrationReport {
+    /// Files imported as new snapshots, oldest first per note
+    pub imported: Vec<ImportedLegacyBackup>,
+    /// Files whose content already exists in the note's history
+    pub duplicates: Vec<PathBuf>,
+    /// Files that matched a legacy name but could not be read as a note
+    pub unparseable: Vec<SkippedLegacyBackup>,
+}
+
+/// Kind of change reported by a [`NoteEvent`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoteEventKind {
+    /// A new note was saved
+    Created,
+    /// An existing note was changed
+    Updated,
+    /// A note was removed
+    Deleted,
+}
+
+/// A change to a note, broadcast to every subscriber of the storage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoteEvent {
+    /// What happened to the note
+    #[serde(rename = "type")]
+    pub kind: NoteEventKind,
+    /// ID of the affected note
+    pub note_id: String,
+    /// Last update timestamp of the note (absent for deletions)
+    pub updated_at: Option<DateTime<Utc>>,
+    /// True when the change was made outside this process and picked up by the file watcher
+    pub external: bool,
+}
+
+/// Per-note access state kept in a sidecar file, so reading a note never
+/// rewrites the note file itself
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccessState {
+    /// IDs of notes that have not been viewed since they were created or
+    /// explicitly marked unread
+    #[serde(default)]
+    pub unread: BTreeSet<String>,
+    /// When each note was last viewed
+    #[serde(default)]
+    pub last_viewed: BTreeMap<String, DateTime<Utc>>,
+}
+
+/// A note file that is not where the configured ID policy expects it
+#[derive(Debug, Clone)]
+pub struct LayoutIssue {
+    /// ID of the note stored in the file
+    pub note_id: String,
+    /// Where the note file currently is
+    pub path: PathBuf,
+    /// Where the ID policy places the note
+    pub expected: PathBuf,
+    /// Another note already occupying the expected location, if any
+    pub collides_with: Option<String>,
+}
+
+impl LayoutIssue {
+    /// Whether the file's name doesn't match the note ID stored inside it,
+    /// as opposed to a correctly named file in the wrong prefix directory
+    pub fn is_name_mismatch(&self) -> bool {
+        self.path.file_name() != self.expected.file_name()
+    }
+}
+
+/// Result of [`NoteStorage::verify_integrity`](crate::NoteStorage::verify_integrity)
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityReport {
+    /// Note files that could not be parsed, with why
+    pub unparsable: Vec<(PathBuf, String)>,
+    /// Note files that are not where their ID places them, or that share
+    /// their location with another note
+    pub layout: Vec<LayoutIssue>,
+    /// IDs of cached notes whose file no longer exists
+    pub missing_files: Vec<String>,
+    /// Aliases claimed by several notes or shadowed by a note ID
+    pub alias_collisions: Vec<AliasCollision>,
+}
+
+impl IntegrityReport {
+    /// Whether no inconsistency was found
+    pub fn is_clean(&self) -> bool {
+        self.unparsable.is_empty()
+            && self.layout.is_empty()
+            && self.missing_files.is_empty()
+            && self.alias_collisions.is_empty()
+    }
+}
+
+/// An alias that doesn't resolve to a single note
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AliasCollision {
+    /// The alias, normalized like alias lookups do
+    pub alias: String,
+    /// IDs of the notes carrying the alias, sorted
+    pub note_ids: Vec<String>,
+    /// ID of another note that the alias equals, which wins every lookup
+    pub shadowed_by: Option<String>,
+}
@@ -0,0 +1,234 @@
+//! Audience-scoped, read-only access to notes for server mode.
+//!
+//! The `server.visibility` section of the configuration marks tags as public
+//! or private (matched case-insensitively):
+//!
+//! ```json
+//! "server": {
+//!   "visibility": { "public": "public", "draft": "private" }
+//! }
+//! ```
+//!
+//! A note is public when at least one of its tags is public and none is
+//! private; every other note is private. Unauthenticated visitors only ever
+//! see public notes, authenticated clients see everything.
+//!
+//! Request handlers are expected to read notes exclusively through a
+//! [`NoteScope`] obtained from [`NoteStorage::scope`], so the visibility rule
+//! is applied in one place instead of by each endpoint.
+use std::collections::{BTreeMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{render_html, KbError, Note, NoteEvent, NoteEventKind, NoteStorage, Result};
+
+/// Whether notes carrying a tag may be shown to unauthenticated visitors
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    /// Shown to everyone, unless another tag of the note is private
+    Public,
+    /// Only shown to authenticated clients
+    #[default]
+    Private,
+}
+
+/// The `server` configuration section
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ServerConfig {
+    /// Visibility keyed by tag name; tags left out are private
+    #[serde(default)]
+    pub visibility: BTreeMap<String, Visibility>,
+}
+
+impl ServerConfig {
+    /// Returns the visibility configured for a tag, ignoring case
+    pub fn visibility(&self, tag: &str) -> Option<Visibility> {
+        self.visibility
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(tag))
+            .map(|(_, visibility)| *visibility)
+    }
+
+    /// Checks whether a note with the given tags is public
+    pub fn is_public(&self, tags: &[String]) -> bool {
+        let mut public = false;
+        for tag in tags {
+            match self.visibility(tag) {
+                Some(Visibility::Private) => return false,
+                Some(Visibility::Public) => public = true,
+                None => {}
+            }
+        }
+        public
+    }
+}
+
+/// Who a request is made on behalf of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Audience {
+    /// An unauthenticated visitor, limited to public notes
+    Public,
+    /// A client that presented a valid token
+    Authenticated,
+}
+
+/// Read-only view of the notes an audience is allowed to see
+///
+/// Notes outside the scope behave as if they did not exist: they are left
+/// out of listings and searches, and looking them up fails with
+/// `KbError::NoteNotFound`.
+#[derive(Clone)]
+pub struct NoteScope {
+    storage: NoteStorage,
+    server: ServerConfig,
+    audience: Audience,
+}
+
+impl NoteScope {
+    /// Creates a view of `storage` for `audience`
+    pub(crate) fn new(storage: NoteStorage, server: ServerConfig, audience: Audience) -> Self {
+        Self {
+            storage,
+            server,
+            audience,
+        }
+    }
+
+    /// The audience this view was created for
+    pub fn audience(&self) -> Audience {
+        self.audience
+    }
+
+    /// Checks whether a note belongs to this view
+    pub fn can_see(&self, note: &Note) -> bool {
+        match self.audience {
+            Audience::Authenticated => true,
+            Audience::Public => self.server.is_public(&note.tags),
+        }
+    }
+
+    /// Keeps the notes belonging to this view
+    fn retain_visible(&self, mut notes: Vec<Note>) -> Vec<Note> {
+        notes.retain(|note| self.can_see(note));
+        notes
+    }
+
+    /// Returns every note in this view
+    pub fn list_notes(&self) -> Result<Vec<Note>> {
+        Ok(self.retain_visible(self.storage.get_all_notes()?))
+    }
+
+    /// Returns the notes in this view carrying a tag
+    pub fn notes_by_tag(&self, tag: &str) -> Result<Vec<Note>> {
+        Ok(self.retain_visible(self.storage.get_notes_by_tag(tag, true)?))
+    }
+
+    /// Searches the notes in this view, best match first
+    pub fn search_notes(&self, query: &str) -> Result<Vec<Note>> {
+        Ok(self.retain_visible(self.storage.search_notes(query, true)?.into_notes()))
+    }
+
+    /// Looks up a note by ID, title or alias
+    ///
+    /// # Returns
+    ///
+    /// The note, or `KbError::NoteNotFound` when it doesn't exist or is
+    /// outside this view
+    pub fn get_note(&self, reference: &str) -> Result<Note> {
+        let not_found = || KbError::NoteNotFound {
+            id: reference.to_string(),
+        };
+
+        // Resolution errors could tell a visitor that a hidden note exists
+        let id = self
+            .storage
+            .resolve_note_id(reference)
+            .map_err(|_| not_found())?;
+        self.storage
+            .get_note(&id)
+            .filter(|note| self.can_see(note))
+            .ok_or_else(not_found)
+    }
+
+    /// Renders a note in this view as a standalone HTML document
+    pub fn render_note_html(&self, reference: &str) -> Result<String> {
+        Ok(render_html(&self.get_note(reference)?))
+    }
+
+    /// Subscribes to changes of the notes in this view
+    pub fn subscribe_events(&self) -> Result<ScopedEvents> {
+        // Subscribe first so no change slips in between the two steps
+        let receiver = self.storage.subscribe_events();
+        let visible = self.list_notes()?.into_iter().map(|note| note.id).collect();
+
+        Ok(ScopedEvents {
+            scope: self.clone(),
+            receiver,
+            visible,
+        })
+    }
+}
+
+/// Note events restricted to the notes of a [`NoteScope`]
+///
+/// A note entering the view is announced as created, and a note leaving it
+/// (e.g. after losing its public tag) as deleted, so subscribers can keep a
+/// list of visible notes up to date from the events alone.
+pub struct ScopedEvents {
+    scope: NoteScope,
+    receiver: broadcast::Receiver<NoteEvent>,
+    /// Notes this subscriber currently knows about
+    visible: HashSet<String>,
+}
+
+impl ScopedEvents {
+    /// Waits for the next event concerning a note in the view
+    ///
+    /// # Returns
+    ///
+    /// The event, or the error of the underlying broadcast receiver
+    /// (`RecvError::Lagged` after falling behind, `RecvError::Closed` when the
+    /// storage is gone)
+    pub async fn recv(&mut self) -> std::result::Result<NoteEvent, RecvError> {
+        loop {
+            let event = self.receiver.recv().await?;
+            if let Some(event) = self.scoped(event) {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Translates an event into what this subscriber may see of it
+    fn scoped(&mut self, mut event: NoteEvent) -> Option<NoteEvent> {
+        if self.scope.audience == Audience::Authenticated {
+            return Some(event);
+        }
+
+        if event.kind == NoteEventKind::Deleted {
+            return self.visible.remove(&event.note_id).then_some(event);
+        }
+
+        // Visibility is decided on the note as it is now, which may already
+        // be newer than the event
+        let now_visible = self
+            .scope
+            .storage
+            .get_note(&event.note_id)
+            .is_some_and(|note| self.scope.can_see(&note));
+
+        if now_visible {
+            if self.visible.insert(event.note_id.clone()) {
+                event.kind = NoteEventKind::Created;
+            }
+            Some(event)
+        } else if self.visible.remove(&event.note_id) {
+            event.kind = NoteEventKind::Deleted;
+            event.updated_at = None;
+            Some(event)
+        } else {
+            None
+        }
+    }
+}
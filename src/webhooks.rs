@@ -0,0 +1,475 @@
+//! Webhooks notifying external services about note changes.
+//!
+//! Endpoints are declared in the `webhooks` section of the configuration.
+//! The storage layer hands every matching change to a [`WebhookDispatcher`],
+//! which posts a [`WebhookPayload`] from a background thread so a slow or
+//! unreachable endpoint never delays or fails the note operation itself.
+//! Deliveries are retried with exponential backoff; those that still fail are
+//! appended to a dead-letter file (one JSON record per line). Before the
+//! process exits, [`WebhookDispatcher::drain`] gives queued deliveries and
+//! their retries up to [`WEBHOOK_DRAIN_TIMEOUT`] and dead-letters the rest.
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Condvar, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{KbError, Note, NoteEventKind, Result};
+
+/// Version of the [`WebhookPayload`] schema, bumped on incompatible changes
+pub const WEBHOOK_SCHEMA_VERSION: u32 = 1;
+
+/// Header carrying the shared secret of a webhook
+pub const WEBHOOK_SECRET_HEADER: &str = "X-Kbnotes-Secret";
+
+/// File (inside the notes state directory) recording permanently failed deliveries
+pub const WEBHOOK_DEAD_LETTER_FILE: &str = "webhook-dead-letters.jsonl";
+
+/// How long [`WebhookDispatcher::drain`] lets queued deliveries and their
+/// retries run before dead-lettering the ones left
+pub const WEBHOOK_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Error recorded for deliveries the drain deadline left no time to attempt
+const DRAIN_DEADLINE_ERROR: &str = "not delivered before kbnotes exited";
+
+/// A single webhook endpoint as it appears in the configuration.
+///
+/// Example (JSON config):
+///
+/// ```json
+/// "webhooks": [
+///   {
+///     "url": "https://tracker.example.com/hooks/kbnotes",
+///     "events": ["created"],
+///     "tag": "todo",
+///     "secret": "s3cr3t",
+///     "timeout_secs": 5
+///   }
+/// ]
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookConfig {
+    /// Endpoint receiving the JSON payload (http or https)
+    pub url: String,
+
+    /// Events to deliver; every event when empty
+    #[serde(default)]
+    pub events: Vec<NoteEventKind>,
+
+    /// Only deliver changes to notes carrying this tag (case-insensitive)
+    #[serde(default)]
+    pub tag: Option<String>,
+
+    /// Shared secret sent in the [`WEBHOOK_SECRET_HEADER`] header
+    #[serde(default)]
+    pub secret: Option<String>,
+
+    /// Timeout of a single delivery attempt, in seconds
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+
+    /// Number of retries after a failed attempt before giving up
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+
+    /// Delay before the first retry in milliseconds, doubled for each further retry
+    #[serde(default = "default_retry_delay_ms")]
+    pub retry_delay_ms: u64,
+}
+
+fn default_timeout_secs() -> u64 {
+    10
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_retry_delay_ms() -> u64 {
+    1000
+}
+
+impl WebhookConfig {
+    /// Returns true when a change of `kind` to `note` should be delivered
+    pub fn matches(&self, kind: NoteEventKind, note: &Note) -> bool {
+        let event_matches = self.events.is_empty() || self.events.contains(&kind);
+        let tag_matches = self.tag.as_ref().is_none_or(|tag| {
+            note.tags
+                .iter()
+                .any(|note_tag| note_tag.eq_ignore_ascii_case(tag))
+        });
+        event_matches && tag_matches
+    }
+}
+
+/// Checks that every configured webhook has a usable URL and timeout
+pub fn validate_webhooks(webhooks: &[WebhookConfig]) -> Result<()> {
+    for webhook in webhooks {
+        if !(webhook.url.starts_with("http://") || webhook.url.starts_with("https://")) {
+            return Err(KbError::ConfigError {
+                message: format!(
+                    "Webhook URL '{}' must start with http:// or https://",
+                    webhook.url
+                ),
+            });
+        }
+        if webhook.timeout_secs == 0 {
+            return Err(KbError::ConfigError {
+                message: format!("Webhook timeout for '{}' cannot be zero", webhook.url),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Summary of the changed note included in a payload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookNoteSummary {
+    pub id: String,
+    pub title: String,
+    pub tags: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// JSON body posted to a webhook endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    /// See [`WEBHOOK_SCHEMA_VERSION`]
+    pub schema_version: u32,
+    /// What happened to the note
+    pub event: NoteEventKind,
+    /// When the change was made
+    pub occurred_at: DateTime<Utc>,
+    /// The note as it was after the change (before it, for deletions)
+    pub note: WebhookNoteSummary,
+}
+
+impl WebhookPayload {
+    /// Builds the payload announcing a change of `kind` to `note`
+    pub fn new(kind: NoteEventKind, note: &Note) -> Self {
+        Self {
+            schema_version: WEBHOOK_SCHEMA_VERSION,
+            event: kind,
+            occurred_at: Utc::now(),
+            note: WebhookNoteSummary {
+                id: note.id.clone(),
+                title: note.title.clone(),
+                tags: note.tags.clone(),
+                created_at: note.created_at,
+                updated_at: note.updated_at,
+            },
+        }
+    }
+}
+
+/// A delivery that failed on every attempt, as written to the dead-letter file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// When the last attempt failed
+    pub failed_at: DateTime<Utc>,
+    /// Endpoint the payload was meant for
+    pub url: String,
+    /// Number of attempts made
+    pub attempts: u32,
+    /// Error of the last attempt
+    pub error: String,
+    /// The undelivered payload
+    pub payload: WebhookPayload,
+}
+
+/// A payload queued for one endpoint
+struct Delivery {
+    webhook: WebhookConfig,
+    payload: WebhookPayload,
+}
+
+/// Outcome of a single failed delivery attempt
+enum AttemptError {
+    /// Worth retrying (network errors, timeouts, 429 and 5xx responses)
+    Transient(String),
+    /// Retrying cannot help (other 4xx responses)
+    Permanent(String),
+}
+
+/// Deliveries in progress, shared between the dispatcher and its thread
+#[derive(Default)]
+struct DeliveryState {
+    /// Deliveries queued or being attempted
+    pending: Mutex<usize>,
+    /// Signaled when `pending` drops
+    delivered: Condvar,
+    /// Set by [`WebhookDispatcher::drain`]; no attempt or retry outlasts it
+    deadline: Mutex<Option<Instant>>,
+    /// Signaled when `deadline` is set, waking retries waiting for their turn
+    draining: Condvar,
+}
+
+impl DeliveryState {
+    fn pending(&self) -> MutexGuard<'_, usize> {
+        self.pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        *self
+            .deadline
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Time left before the drain deadline, `None` while not draining
+    fn remaining(&self) -> Option<Duration> {
+        self.deadline()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Waits `delay` before a retry, or until the drain deadline if that comes
+    /// first
+    fn pause(&self, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut deadline = self
+            .deadline
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        loop {
+            let end = deadline.map_or(until, |deadline| deadline.min(until));
+            let now = Instant::now();
+            if now >= end {
+                return;
+            }
+            deadline = match self.draining.wait_timeout(deadline, end - now) {
+                Ok((deadline, _)) => deadline,
+                Err(poisoned) => poisoned.into_inner().0,
+            };
+        }
+    }
+
+    /// Counts a delivery as done
+    fn finish(&self) {
+        *self.pending() -= 1;
+        self.delivered.notify_all();
+    }
+}
+
+/// Queues matching note changes and delivers them from a background thread
+pub struct WebhookDispatcher {
+    webhooks: Vec<WebhookConfig>,
+    queue: mpsc::Sender<Delivery>,
+    state: Arc<DeliveryState>,
+}
+
+impl WebhookDispatcher {
+    /// Starts the delivery thread for the configured webhooks
+    ///
+    /// # Arguments
+    /// * `webhooks` - Configured endpoints
+    /// * `dead_letter_path` - File receiving deliveries that failed permanently
+    ///
+    /// # Returns
+    /// * `Option<Self>` - `None` when no webhook is configured
+    pub fn start(webhooks: Vec<WebhookConfig>, dead_letter_path: PathBuf) -> Option<Self> {
+        if webhooks.is_empty() {
+            return None;
+        }
+
+        let (queue, deliveries) = mpsc::channel::<Delivery>();
+        let state = Arc::new(DeliveryState::default());
+        let thread_state = Arc::clone(&state);
+        let spawned = thread::Builder::new()
+            .name("kbnotes-webhooks".to_string())
+            .spawn(move || {
+                // Runs until the dispatcher (and with it the sender) is dropped
+                for delivery in deliveries {
+                    deliver(&delivery, &dead_letter_path, &thread_state);
+                    thread_state.finish();
+                }
+            });
+
+        match spawned {
+            Ok(_) => Some(Self {
+                webhooks,
+                queue,
+                state,
+            }),
+            Err(e) => {
+                error!("Failed to start webhook delivery thread: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Queues a change for every webhook it matches, without waiting for delivery
+    pub fn dispatch(&self, kind: NoteEventKind, note: &Note) {
+        let matching = self.webhooks.iter().filter(|w| w.matches(kind, note));
+        for webhook in matching {
+            let delivery = Delivery {
+                webhook: webhook.clone(),
+                payload: WebhookPayload::new(kind, note),
+            };
+            *self.state.pending() += 1;
+            if self.queue.send(delivery).is_err() {
+                *self.state.pending() -= 1;
+                warn!("Webhook delivery thread has stopped, dropping event");
+                return;
+            }
+        }
+    }
+
+    /// Waits for the queued deliveries before the process exits
+    ///
+    /// Deliveries and retries go on for up to `timeout`. After that, no
+    /// further attempt starts: the deliveries still queued or retrying are
+    /// written to the dead-letter file instead. A single attempt never runs
+    /// past the deadline either, so the wait is bounded by `timeout`.
+    ///
+    /// # Arguments
+    /// * `timeout` - How long deliveries may still take
+    ///
+    /// # Returns
+    /// * `bool` - Whether every queued delivery was handled in time
+    pub fn drain(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        *self
+            .state
+            .deadline
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(deadline);
+        self.state.draining.notify_all();
+
+        // Dead-lettering what is left takes a moment past the deadline
+        let wait = timeout + Duration::from_secs(1);
+        let pending = self.state.pending();
+        let (pending, _) = match self
+            .state
+            .delivered
+            .wait_timeout_while(pending, wait, |pending| *pending > 0)
+        {
+            Ok(result) => result,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if *pending > 0 {
+            warn!(
+                "{} webhook deliveries still pending after {:?}",
+                *pending, wait
+            );
+            return false;
+        }
+        true
+    }
+}
+
+/// Posts a payload, retrying transient failures, and records it as a dead
+/// letter when every attempt failed
+fn deliver(delivery: &Delivery, dead_letter_path: &Path, state: &DeliveryState) {
+    let webhook = &delivery.webhook;
+    let body = match serde_json::to_string(&delivery.payload) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to serialize webhook payload: {}", e);
+            return;
+        }
+    };
+
+    let mut delay = Duration::from_millis(webhook.retry_delay_ms);
+    let mut attempts = 0;
+    let mut last_error = DRAIN_DEADLINE_ERROR.to_string();
+
+    let error = loop {
+        // While draining, attempts are cut short at the deadline
+        let mut timeout = Duration::from_secs(webhook.timeout_secs);
+        if let Some(remaining) = state.remaining() {
+            if remaining.is_zero() {
+                break last_error;
+            }
+            timeout = timeout.min(remaining);
+        }
+        let agent = ureq::AgentBuilder::new().timeout(timeout).build();
+
+        attempts += 1;
+        match post(&agent, webhook, &body) {
+            Ok(()) => {
+                debug!(
+                    "Delivered {:?} event for note {} to {}",
+                    delivery.payload.event, delivery.payload.note.id, webhook.url
+                );
+                return;
+            }
+            Err(AttemptError::Permanent(message)) => break message,
+            Err(AttemptError::Transient(message)) => {
+                if attempts > webhook.max_retries {
+                    break message;
+                }
+                warn!(
+                    "Webhook delivery to {} failed (attempt {}): {}, retrying in {:?}",
+                    webhook.url, attempts, message, delay
+                );
+                last_error = message;
+                state.pause(delay);
+                delay *= 2;
+            }
+        }
+    };
+
+    error!(
+        "Giving up on webhook delivery to {} after {} attempt(s): {}",
+        webhook.url, attempts, error
+    );
+    let dead_letter = DeadLetter {
+        failed_at: Utc::now(),
+        url: webhook.url.clone(),
+        attempts,
+        error,
+        payload: delivery.payload.clone(),
+    };
+    if let Err(e) = append_dead_letter(dead_letter_path, &dead_letter) {
+        error!(
+            "Failed to record dead letter in {}: {}",
+            dead_letter_path.display(),
+            e
+        );
+    }
+}
+
+/// Makes a single delivery attempt
+fn post(
+    agent: &ureq::Agent,
+    webhook: &WebhookConfig,
+    body: &str,
+) -> std::result::Result<(), AttemptError> {
+    let mut request = agent
+        .post(&webhook.url)
+        .set("Content-Type", "application/json");
+    if let Some(secret) = &webhook.secret {
+        request = request.set(WEBHOOK_SECRET_HEADER, secret);
+    }
+
+    match request.send_string(body) {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(status, _)) if status == 429 || status >= 500 => {
+            Err(AttemptError::Transient(format!("HTTP status {}", status)))
+        }
+        Err(ureq::Error::Status(status, _)) => {
+            Err(AttemptError::Permanent(format!("HTTP status {}", status)))
+        }
+        Err(e) => Err(AttemptError::Transient(e.to_string())),
+    }
+}
+
+/// Appends a record to the dead-letter file
+fn append_dead_letter(path: &Path, dead_letter: &DeadLetter) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(dead_letter)?)?;
+    Ok(())
+}
@@ -0,0 +1,82 @@
+//! Checks that archived notes drop out of searches and tag lookups unless
+//! asked for, come back when unarchived, and survive a full backup restore.
+//!
+//! Run with `cargo test --test archive`.
+mod common;
+
+use kbnotes::*;
+
+fn titles(notes: Vec<Note>) -> Vec<String> {
+    let mut titles: Vec<String> = notes.into_iter().map(|note| note.title).collect();
+    titles.sort();
+    titles
+}
+
+#[test]
+fn archive() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let storage = common::vault(&dir.path().join("source"));
+
+    let old = Note::new(
+        "Old recipe".to_string(),
+        "flour and water".to_string(),
+        vec!["cooking".to_string()],
+    );
+    let new = Note::new(
+        "New recipe".to_string(),
+        "flour and eggs".to_string(),
+        vec!["cooking".to_string()],
+    );
+    storage.save_note(&old).unwrap();
+    storage.save_note(&new).unwrap();
+
+    let archived = storage.set_archived(&old.id, true).unwrap().unwrap();
+    assert!(archived.archived);
+    assert!(archived.metadata.contains_key(ARCHIVED_AT_KEY));
+    assert!(storage.set_archived(&old.id, true).unwrap().is_none());
+    assert_eq!(
+        titles(storage.search_notes("flour", false).unwrap().into_notes()),
+        ["New recipe"]
+    );
+    assert_eq!(
+        titles(storage.search_notes("flour", true).unwrap().into_notes()),
+        ["New recipe", "Old recipe"]
+    );
+    assert_eq!(
+        titles(storage.get_notes_by_tag("cooking", false).unwrap()),
+        ["New recipe"]
+    );
+    assert_eq!(storage.get_notes_by_tag("cooking", true).unwrap().len(), 2);
+    // Archived notes can still be read directly
+    assert!(storage.get_note(&old.id).unwrap().archived);
+
+    // The flag is stored in the note file
+    let reloaded = common::vault(&dir.path().join("source"));
+    assert_eq!(
+        titles(reloaded.search_notes("flour", false).unwrap().into_notes()),
+        ["New recipe"]
+    );
+
+    // Restored from a full backup, still archived
+    let backup = dir.path().join("backup.zip");
+    storage.create_full_backup_to(&backup).unwrap();
+    let target = common::vault(&dir.path().join("target"));
+    let summary = target
+        .restore_full_backup(&backup, &RestoreOptions::default())
+        .unwrap();
+    assert_eq!(summary.notes_restored, 2);
+    assert!(target.get_note(&old.id).unwrap().archived);
+    assert_eq!(
+        titles(target.search_notes("flour", false).unwrap().into_notes()),
+        ["New recipe"]
+    );
+
+    let unarchived = storage.set_archived(&old.id, false).unwrap().unwrap();
+    assert!(!unarchived.archived);
+    assert!(!unarchived.metadata.contains_key(ARCHIVED_AT_KEY));
+    assert_eq!(storage.search_notes("flour", false).unwrap().hits.len(), 2);
+    assert!(matches!(
+        storage.set_archived("missing", true),
+        Err(KbError::NoteNotFound { .. })
+    ));
+}
@@ -0,0 +1,80 @@
+//! Checks that files attached to notes are stored under
+//! `attachments/<id>/`, survive reloading and a full backup restore, and are
+//! deleted together with their note.
+//!
+//! Run with `cargo test --test attachments`.
+mod common;
+
+use std::fs;
+
+use kbnotes::*;
+
+#[test]
+fn attachments() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let storage = common::vault(&dir.path().join("source"));
+
+    let note = Note::new("Receipts".to_string(), "March".to_string(), vec![]);
+    storage.save_note(&note).unwrap();
+
+    let files = dir.path().join("files");
+    fs::create_dir_all(&files).unwrap();
+    fs::write(files.join("scan.pdf"), b"%PDF-1.4 receipt").unwrap();
+    // A JSON attachment must not be mistaken for a note
+    fs::write(files.join("data.json"), br#"{"id": "not-a-note"}"#).unwrap();
+
+    let pdf = storage
+        .add_attachment(&note.id, &files.join("scan.pdf"))
+        .unwrap();
+    assert_eq!(pdf.mime_type, "application/pdf");
+    assert_eq!(pdf.size, 16);
+    let again = storage
+        .add_attachment(&note.id, &files.join("scan.pdf"))
+        .unwrap();
+    assert_eq!(again.filename, "scan-2.pdf");
+    storage
+        .add_attachment(&note.id, &files.join("data.json"))
+        .unwrap();
+    assert!(storage.attachment_file_path(&pdf).is_file());
+    assert!(matches!(
+        storage.add_attachment(&note.id, &files.join("missing.png")),
+        Err(KbError::FileNotFound { .. })
+    ));
+
+    // Reloaded from disk: still one note, with its three attachments
+    let reloaded = common::vault(&dir.path().join("source"));
+    assert_eq!(reloaded.get_all_notes().unwrap().len(), 1);
+    assert_eq!(reloaded.get_note(&note.id).unwrap().attachments.len(), 3);
+    assert!(reloaded.check_layout().unwrap().is_empty());
+
+    storage.remove_attachment(&note.id, "scan-2.pdf").unwrap();
+    assert!(!storage.attachment_file_path(&again).exists());
+    assert!(matches!(
+        storage.remove_attachment(&note.id, "scan-2.pdf"),
+        Err(KbError::AttachmentNotFound { .. })
+    ));
+
+    // Restored from a full backup into another vault
+    let backup = dir.path().join("backup.zip");
+    storage.create_full_backup_to(&backup).unwrap();
+    let target = common::vault(&dir.path().join("target"));
+    let summary = target
+        .restore_full_backup(&backup, &RestoreOptions::default())
+        .unwrap();
+    assert_eq!(summary.notes_restored, 1);
+    let restored = target.get_note(&note.id).unwrap();
+    assert_eq!(restored.attachments.len(), 2);
+    for attachment in &restored.attachments {
+        let copy = fs::read(target.attachment_file_path(attachment)).unwrap();
+        assert_eq!(copy.len() as u64, attachment.size);
+    }
+
+    // Deleting the note deletes its attachments
+    let attachment_dir = storage
+        .attachment_file_path(&pdf)
+        .parent()
+        .unwrap()
+        .to_path_buf();
+    storage.delete_note(&note.id).unwrap();
+    assert!(!attachment_dir.exists());
+}
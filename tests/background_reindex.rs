@@ -0,0 +1,163 @@
+//! Checks background reindexing: the index is rebuilt in batches that pause
+//! while a foreground command runs, progress is reported until the rebuild
+//! is done, and searches during it find the notes indexed so far together
+//! with the ones still pending. Foreground commands and progress are also
+//! shared with other processes on the vault. Also runs `kbnotes reindex`
+//! with and without `--foreground`.
+//!
+//! Run with `cargo test --test background_reindex`.
+mod common;
+
+use std::{collections::HashSet, path::Path, sync::Arc, time::Duration};
+
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+const NOTE_COUNT: usize = 1000;
+
+fn vault(root: &Path) -> NoteStorage {
+    let mut config = common::config(root);
+    config.fsync = FsyncMode::Never;
+    config.use_search_index = true;
+    common::open(config)
+}
+
+fn found(storage: &NoteStorage, query: &str) -> usize {
+    storage
+        .search_notes(query, false)
+        .unwrap()
+        .into_notes()
+        .len()
+}
+
+/// Lets the reindex task run until it has indexed at least `indexed` notes
+async fn run_until(storage: &NoteStorage, indexed: usize) {
+    while storage
+        .reindex_progress()
+        .is_some_and(|progress| progress.indexed < indexed)
+    {
+        tokio::task::yield_now().await;
+    }
+}
+
+// A single thread runs the reindex task only while this one waits, which
+// makes the batches it gets through predictable
+#[tokio::test]
+async fn background_reindex() {
+    // Pending notes are candidates of every search until they are indexed
+    let path = Path::new("search.json").to_path_buf();
+    let ids = ["a", "b", "c"].map(str::to_string);
+    let mut index = SearchIndex::pending(path, ids.clone());
+    index.index_note(&common::note("a", "docker compose"));
+    index.index_note(&common::note("b", "podman"));
+    assert_eq!(index.pending_len(), 1);
+    let candidates = index.candidates("docker", None).unwrap();
+    let expected: HashSet<String> = ["a", "c"].map(str::to_string).into();
+    assert_eq!(candidates, expected);
+
+    // Foreground guards nest, and other processes, stood in for by a second
+    // tracker of the same directory, see them through the lock file
+    let shared = tempfile::tempdir().expect("failed to create temporary directory");
+    let activity = ForegroundActivity::in_dir(shared.path().to_path_buf());
+    let other_process = ForegroundActivity::in_dir(shared.path().to_path_buf());
+    let outer = activity.enter();
+    let inner = activity.enter();
+    drop(inner);
+    assert!(activity.is_active());
+    assert!(other_process.is_active());
+    drop(outer);
+    assert!(!activity.is_active());
+    assert!(!other_process.is_active());
+
+    // A rebuild's progress can be read while it holds the lock, and only then
+    let lock = ReindexLock::acquire(shared.path()).unwrap().unwrap();
+    assert!(ReindexLock::acquire(shared.path()).unwrap().is_none());
+    let progress = ReindexProgress {
+        indexed: 4200,
+        total: 9000,
+        paused: false,
+    };
+    lock.report(&progress).unwrap();
+    assert_eq!(ReindexProgress::read(shared.path()), Some(progress));
+    assert_eq!(progress.to_string(), "indexing 4,200/9,000");
+    drop(lock);
+    assert!(!shared.path().join(REINDEX_PROGRESS_FILE).exists());
+    assert_eq!(ReindexProgress::read(shared.path()), None);
+    // A progress file left behind by a rebuild that died isn't reported
+    std::fs::write(
+        shared.path().join(REINDEX_PROGRESS_FILE),
+        r#"{"indexed":1,"total":2,"paused":false}"#,
+    )
+    .unwrap();
+    assert_eq!(ReindexProgress::read(shared.path()), None);
+
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let storage = vault(dir.path());
+    let notes: Vec<Note> = (0..NOTE_COUNT)
+        .map(|i| {
+            let content = if i % 10 == 0 { "needle" } else { "hay" };
+            common::note(&format!("n{:04}", i), &format!("{} {}", content, i))
+        })
+        .collect();
+    storage.save_notes(&notes).unwrap();
+    assert_eq!(found(&storage, "needle"), NOTE_COUNT / 10);
+
+    // While a foreground command runs, the reindex doesn't start
+    let guard = storage.enter_foreground();
+    let job = storage.start_reindex().unwrap();
+    tokio::time::sleep(REINDEX_PAUSE * 2).await;
+    let progress = storage.reindex_progress().unwrap();
+    assert_eq!((progress.indexed, progress.total), (0, NOTE_COUNT));
+    assert!(progress.paused);
+    assert_eq!(progress.to_string(), "indexing 0/1,000 (paused)");
+    assert!(storage.start_reindex().is_err());
+    // Nothing is indexed yet, so every note is scanned
+    assert_eq!(found(&storage, "needle"), NOTE_COUNT / 10);
+
+    // Let one batch through, then run a command in what stands for another
+    // process: a second storage on the same vault, sharing only its files
+    drop(guard);
+    run_until(&storage, REINDEX_BATCH_SIZE).await;
+    let other = vault(dir.path());
+    let guard = other.enter_foreground();
+    tokio::time::sleep(REINDEX_PAUSE * 2).await;
+    let progress = storage.reindex_progress().unwrap();
+    assert_eq!(progress.indexed, REINDEX_BATCH_SIZE);
+    assert!(progress.paused);
+    // The other process sees the progress and can't start its own reindex
+    assert_eq!(other.reindex_progress(), Some(progress));
+    assert!(other.start_reindex().is_err());
+    // Indexed and pending notes are found alike, deleted ones are not
+    assert_eq!(found(&storage, "needle"), NOTE_COUNT / 10);
+    storage.delete_note("n0990").unwrap();
+    assert_eq!(found(&storage, "needle"), NOTE_COUNT / 10 - 1);
+
+    drop(guard);
+    assert_eq!(job.await.unwrap().unwrap(), NOTE_COUNT - 1);
+    assert!(storage.reindex_progress().is_none());
+    assert!(other.reindex_progress().is_none());
+    assert_eq!(found(&storage, "needle"), NOTE_COUNT / 10 - 1);
+    let saved = SearchIndex::load(SearchIndex::path_in(&dir.path().join("notes")))
+        .unwrap()
+        .unwrap();
+    assert_eq!((saved.len(), saved.pending_len()), (NOTE_COUNT - 1, 0));
+
+    // The command line runs it in the background or, with --foreground, in
+    // one go; status shows no reindex once it is done
+    let config = {
+        let mut config = common::config(dir.path());
+        config.use_search_index = true;
+        config
+    };
+    let app = common::app(Arc::new(Mutex::new(storage)), config);
+    for args in [
+        &["reindex"][..],
+        &["reindex", "--foreground"],
+        &["status", "--json"],
+    ] {
+        tokio::time::timeout(Duration::from_secs(30), common::run(&app, args))
+            .await
+            .expect("command hung")
+            .expect("command failed");
+    }
+}
@@ -0,0 +1,153 @@
+//! Checks catch-up backups: a scheduler starting after a periodic backup
+//! fell due takes it right away and flags the catch-up in its status, one
+//! starting before it resumes the persisted schedule, and the schedule is
+//! written to `kbnotes_scheduler_state.json` in the backup directory.
+//!
+//! Run with `cargo test --test backup_catch_up`.
+mod common;
+
+use std::{fs, path::Path, sync::Arc, time::Duration};
+
+use chrono::Utc;
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+fn config(root: &Path) -> Config {
+    let mut config = common::config(root);
+    config.fsync = FsyncMode::Never;
+    config.auto_backup = true;
+    config
+}
+
+fn write_state(root: &Path, state: &BackupSchedulerState) {
+    let backups = root.join("backups");
+    fs::create_dir_all(&backups).unwrap();
+    fs::write(
+        backups.join(SCHEDULER_STATE_FILE),
+        serde_json::to_string(state).unwrap(),
+    )
+    .unwrap();
+}
+
+fn read_state(root: &Path) -> BackupSchedulerState {
+    let text = fs::read_to_string(root.join("backups").join(SCHEDULER_STATE_FILE)).unwrap();
+    serde_json::from_str(&text).unwrap()
+}
+
+/// Waits for the scheduler to record a backup, or a second at most
+async fn settled_status(storage: &Arc<Mutex<NoteStorage>>) -> BackupSchedulerStatus {
+    for _ in 0..20 {
+        let status = storage.lock().await.get_backup_status().await;
+        if status.last_run_time.is_some() {
+            return status;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    storage.lock().await.get_backup_status().await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn missed_backup() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let last = Utc::now() - chrono::Duration::hours(30);
+    write_state(
+        dir.path(),
+        &BackupSchedulerState {
+            last_backup_at: Some(last),
+            next_backup_at: Some(last + chrono::Duration::hours(24)),
+        },
+    );
+    let storage = common::start(config(dir.path())).await;
+
+    let status = settled_status(&storage).await;
+    assert!(status.last_backup_path.is_some(), "{:?}", status);
+    assert!(status.last_catch_up_time.is_some());
+    assert_eq!(
+        status.missed_backup_time,
+        Some(last + chrono::Duration::hours(24))
+    );
+    let next = status.next_backup_time.unwrap();
+    assert!(next > Utc::now() + chrono::Duration::hours(23));
+
+    let state = read_state(dir.path());
+    assert_eq!(state.last_backup_at, status.last_backup_time);
+    assert_eq!(state.next_backup_at, Some(next));
+    storage.lock().await.shutdown().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resumed_schedule() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let next = Utc::now() + chrono::Duration::hours(5);
+    write_state(
+        dir.path(),
+        &BackupSchedulerState {
+            last_backup_at: Some(Utc::now() - chrono::Duration::hours(19)),
+            next_backup_at: Some(next),
+        },
+    );
+    let storage = common::start(config(dir.path())).await;
+
+    let status = settled_status(&storage).await;
+    assert!(status.last_run_time.is_none(), "{:?}", status);
+    assert!(status.last_catch_up_time.is_none());
+    assert_eq!(status.next_backup_time, Some(next));
+    storage.lock().await.shutdown().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn chain_state_and_cron() {
+    // Without a scheduler state file, the incremental chain state tells
+    // when the last backup was taken
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let backups = dir.path().join("backups");
+    fs::create_dir_all(&backups).unwrap();
+    let chain = BackupChainState {
+        base: "kbnotes_backup_20000101_000000.zip".to_string(),
+        last_backup_at: Utc::now() - chrono::Duration::days(2),
+        incrementals: 0,
+    };
+    fs::write(
+        backups.join(BACKUP_STATE_FILE),
+        serde_json::to_string(&chain).unwrap(),
+    )
+    .unwrap();
+    let mut config = config(dir.path());
+    config.backup_schedule = Some("0 3 * * *".to_string());
+    let storage = common::start(config).await;
+
+    let status = settled_status(&storage).await;
+    assert!(status.last_catch_up_time.is_some(), "{:?}", status);
+    let missed = status.missed_backup_time.unwrap();
+    assert!(missed > chain.last_backup_at && missed < Utc::now());
+    storage.lock().await.shutdown().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn manual_backups_only() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    write_state(
+        dir.path(),
+        &BackupSchedulerState {
+            last_backup_at: Some(Utc::now() - chrono::Duration::days(3)),
+            next_backup_at: None,
+        },
+    );
+    let mut config = config(dir.path());
+    config.auto_backup = false;
+    let storage = common::start(config).await;
+
+    let status = settled_status(&storage).await;
+    assert!(status.last_run_time.is_none(), "{:?}", status);
+    assert!(status.next_backup_time.is_none());
+
+    // A manual backup still records when the last one was taken
+    storage.lock().await.create_backup_now().await.unwrap();
+    let status = settled_status(&storage).await;
+    assert!(status.last_catch_up_time.is_none());
+    assert_eq!(
+        read_state(dir.path()).last_backup_at,
+        status.last_backup_time
+    );
+    storage.lock().await.shutdown().await.unwrap();
+}
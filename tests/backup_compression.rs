@@ -0,0 +1,119 @@
+//! Checks `backup_compression` and `backup_compression_level`: every entry
+//! of a backup archive is written with the configured method, archives of
+//! each method restore, and levels that don't suit the method are rejected
+//! by `config --set` and when the configuration file is loaded.
+//!
+//! Run with `cargo test --test backup_compression`.
+mod common;
+
+use std::{fs, fs::File, path::Path};
+
+use kbnotes::*;
+use zip::{CompressionMethod, ZipArchive};
+
+fn open_storage(root: &Path, compression: BackupCompression, level: Option<i64>) -> NoteStorage {
+    let mut config = common::config(root);
+    config.fsync = FsyncMode::Never;
+    config.backup_compression = compression;
+    config.backup_compression_level = level;
+    common::open(config)
+}
+
+/// Compression method of every entry of an archive
+fn entry_methods(path: &Path) -> Vec<(String, CompressionMethod)> {
+    let mut archive = ZipArchive::new(File::open(path).unwrap()).unwrap();
+    (0..archive.len())
+        .map(|i| {
+            let entry = archive.by_index(i).unwrap();
+            (entry.name().to_string(), entry.compression())
+        })
+        .collect()
+}
+
+#[test]
+fn backup_compression() {
+    let content = "All work and no play makes a dull backup.\n".repeat(200);
+    let mut stored_size = 0;
+    for (compression, level, method) in [
+        (BackupCompression::None, None, CompressionMethod::Stored),
+        (
+            BackupCompression::Deflate,
+            Some(1),
+            CompressionMethod::Deflated,
+        ),
+        (BackupCompression::Bzip2, Some(9), CompressionMethod::Bzip2),
+        (BackupCompression::Zstd, Some(19), CompressionMethod::Zstd),
+    ] {
+        let dir = tempfile::tempdir().expect("failed to create temporary vault");
+        let storage = open_storage(dir.path(), compression, level);
+        for title in ["Alpha", "Beta"] {
+            let note = Note::new(title.to_string(), content.clone(), vec![]);
+            storage.save_note(&note).unwrap();
+            storage.create_snapshot(&note.id, "first").unwrap();
+        }
+        let backup = dir.path().join("backup.zip");
+        storage.create_full_backup_to(&backup).unwrap();
+
+        // Notes, snapshots and the manifest all use the configured method
+        let methods = entry_methods(&backup);
+        assert!(methods.len() >= 5, "{:?}", methods);
+        for (name, found) in &methods {
+            assert_eq!(*found, method, "{} in a {} backup", name, compression);
+        }
+        let size = fs::metadata(&backup).unwrap().len();
+        if method == CompressionMethod::Stored {
+            stored_size = size;
+        } else {
+            assert!(
+                size < stored_size,
+                "{} backup is {} bytes",
+                compression,
+                size
+            );
+        }
+
+        let target = tempfile::tempdir().expect("failed to create temporary vault");
+        let restored = open_storage(target.path(), BackupCompression::default(), None);
+        let summary = restored
+            .restore_full_backup(&backup, &RestoreOptions::default())
+            .unwrap();
+        assert_eq!(summary.notes_restored, 2);
+        assert!(restored
+            .get_all_notes()
+            .unwrap()
+            .iter()
+            .all(|note| note.content == content));
+    }
+
+    // Levels are checked against the method
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let mut config = common::config(dir.path());
+    config.set_value("backup_compression", "zstd").unwrap();
+    config.set_value("backup_compression_level", "22").unwrap();
+    assert!(config.set_value("backup_compression", "deflate").is_err());
+    assert!(config.set_value("backup_compression", "none").is_err());
+    assert!(config.set_value("backup_compression", "lzma").is_err());
+    config
+        .set_value("backup_compression_level", "none")
+        .unwrap();
+    config.set_value("backup_compression", "none").unwrap();
+    assert!(config.set_value("backup_compression_level", "3").is_err());
+    config.set_value("backup_compression", "bzip2").unwrap();
+    assert!(config.set_value("backup_compression_level", "0").is_err());
+    config.set_value("backup_compression_level", "9").unwrap();
+    assert_eq!(config.backup_compression, BackupCompression::Bzip2);
+
+    // So are the settings of a configuration file
+    let path = dir.path().join("config.json");
+    save_config_to_file(&config, &path).unwrap();
+    let (loaded, _) = load_config_from_file(&path).unwrap();
+    assert_eq!(loaded.backup_compression_level, Some(9));
+    fs::write(
+        &path,
+        r#"{"backup_compression": "deflate", "backup_compression_level": 12}"#,
+    )
+    .unwrap();
+    assert!(load_config_from_file(&path).is_err());
+    fs::write(&path, r#"{"backup_compression": "zip"}"#).unwrap();
+    assert!(load_config_from_file(&path).is_err());
+}
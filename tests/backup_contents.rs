@@ -0,0 +1,163 @@
+//! Checks what backups hold besides notes: a `manifest.json` with the app
+//! and format versions, a `config.json` without secrets, the refusal of
+//! backups in a newer format, the restore of legacy backups without a
+//! manifest, and restores leaving attachments out.
+//!
+//! Run with `cargo test --test backup_contents`.
+mod common;
+
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use clap::Parser;
+use kbnotes::*;
+use tokio::sync::Mutex;
+use zip::{write::SimpleFileOptions, ZipArchive, ZipWriter};
+
+fn open_storage(root: &Path, config: Option<Config>) -> (Config, NoteStorage) {
+    let mut config =
+        config.unwrap_or_else(|| Config::with_dirs(root.join("notes"), root.join("backups")));
+    config.notes_dir = root.join("notes");
+    config.backup_dir = root.join("backups");
+    config.auto_backup = false;
+    config.fsync = FsyncMode::Never;
+    let storage = common::open(config.clone());
+    (config, storage)
+}
+
+/// Copies a backup, dropping or rewriting its manifest
+fn rewrite_backup(from: &Path, to: &Path, manifest: Option<&BackupManifest>) {
+    let mut archive = ZipArchive::new(fs::File::open(from).unwrap()).unwrap();
+    let mut zip = ZipWriter::new(fs::File::create(to).unwrap());
+    for i in 0..archive.len() {
+        let entry = archive.by_index(i).unwrap();
+        if entry.name() != BACKUP_MANIFEST_ENTRY {
+            zip.raw_copy_file(entry).unwrap();
+        }
+    }
+    if let Some(manifest) = manifest {
+        zip.start_file(BACKUP_MANIFEST_ENTRY, SimpleFileOptions::default())
+            .unwrap();
+        serde_json::to_writer(&mut zip, manifest).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+fn read_entry(backup: &Path, name: &str) -> String {
+    let mut archive = ZipArchive::new(fs::File::open(backup).unwrap()).unwrap();
+    let mut text = String::new();
+    archive
+        .by_name(name)
+        .unwrap()
+        .read_to_string(&mut text)
+        .unwrap();
+    text
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn backup_contents() {
+    let source = tempfile::tempdir().expect("failed to create temporary vault");
+    let mut config = Config::with_dirs(PathBuf::new(), PathBuf::new());
+    config.webhooks =
+        serde_json::from_str(r#"[{ "url": "https://example.com/hook", "secret": "hook-secret" }]"#)
+            .unwrap();
+    config.backup_targets = serde_json::from_str(
+        r#"[{ "type": "s3", "endpoint": "https://s3.example.com", "bucket": "notes",
+              "access_key_id": "AKIDEXAMPLE", "secret_access_key": "s3-secret" }]"#,
+    )
+    .unwrap();
+    let (_, storage) = open_storage(source.path(), Some(config));
+    storage.save_note(&common::note("alpha", "a1")).unwrap();
+    storage.save_note(&common::note("beta", "b1")).unwrap();
+    let file = source.path().join("scan.txt");
+    fs::write(&file, "scanned").unwrap();
+    let attachment = storage.add_attachment("alpha", &file).unwrap();
+    let backup = source.path().join("backup.zip");
+    let manifest = storage.create_full_backup_to(&backup).unwrap();
+
+    // The manifest records who wrote the backup, in which layout
+    assert_eq!(manifest.format_version, BACKUP_FORMAT_VERSION);
+    assert_eq!(
+        manifest.app_version.as_deref(),
+        Some(env!("CARGO_PKG_VERSION"))
+    );
+    assert_eq!(manifest.notes, 2);
+    let stored = storage.read_backup_manifest(&backup).unwrap().unwrap();
+    assert_eq!(stored.app_version, manifest.app_version);
+
+    // The configuration comes along, without its secrets
+    let text = read_entry(&backup, BACKUP_CONFIG_ENTRY);
+    for secret in ["hook-secret", "s3-secret"] {
+        assert!(!text.contains(secret), "{}", text);
+    }
+    assert!(text.contains("AKIDEXAMPLE"), "{}", text);
+    let backed_up = storage.read_backup_config(&backup).unwrap().unwrap();
+    assert_eq!(backed_up.webhooks[0].url, "https://example.com/hook");
+    assert!(backed_up.webhooks[0].secret.is_none());
+
+    // Backups in a newer layout are refused, dry runs and staged restores too
+    let future = source.path().join("future.zip");
+    let mut newer = manifest.clone();
+    newer.format_version = BACKUP_FORMAT_VERSION + 1;
+    newer.app_version = Some("99.0.0".to_string());
+    rewrite_backup(&backup, &future, Some(&newer));
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let (_, mut target) = open_storage(dir.path(), None);
+    for dry_run in [false, true] {
+        let error = target
+            .restore_full_backup(
+                &future,
+                &RestoreOptions {
+                    dry_run,
+                    ..RestoreOptions::default()
+                },
+            )
+            .unwrap_err();
+        assert!(matches!(error, KbError::RestoreFailed { .. }), "{}", error);
+        assert!(error.to_string().contains("kbnotes 99.0.0"), "{}", error);
+    }
+    assert!(target.restore_full_backup_staged(&future).await.is_err());
+    assert!(target.get_all_notes().unwrap().is_empty());
+
+    // Archives from before manifests restore as legacy backups
+    let legacy = source.path().join("legacy.zip");
+    rewrite_backup(&backup, &legacy, None);
+    let summary = target
+        .restore_full_backup(&legacy, &RestoreOptions::default())
+        .unwrap();
+    assert_eq!(summary.notes_restored, 2);
+    assert!(target.attachment_file_path(&attachment).is_file());
+
+    // Notes can come back without their attachments
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let (_, target) = open_storage(dir.path(), None);
+    let options = RestoreOptions {
+        filter: RestoreFilter {
+            skip_attachments: true,
+            ..RestoreFilter::default()
+        },
+        ..RestoreOptions::default()
+    };
+    let summary = target.restore_full_backup(&backup, &options).unwrap();
+    assert_eq!(summary.notes_restored, 2);
+    assert!(!target.attachment_file_path(&attachment).exists());
+
+    // Also from the command line, where staged restores can't skip them
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let (config, storage) = open_storage(dir.path(), None);
+    let storage = Arc::new(Mutex::new(storage));
+    let app = common::app(Arc::clone(&storage), config);
+    let path = backup.display().to_string();
+    let args = ["restore", path.as_str(), "--force"];
+    common::run(&app, &[&args[..], &["--no-attachments"]].concat())
+        .await
+        .unwrap();
+    assert_eq!(storage.lock().await.get_all_notes().unwrap().len(), 2);
+    assert!(!dir.path().join("notes").join(&attachment.path).exists());
+    let staged = [&["kbnotes"][..], &args, &["--no-attachments", "--staged"]].concat();
+    assert!(Cli::try_parse_from(staged).is_err());
+}
@@ -0,0 +1,114 @@
+//! Checks `diff_backup`: notes only in the backup, only in the vault, and in
+//! both but changed (in content or only in `updated_at`) are told apart, and
+//! nothing is written. Also runs `kbnotes backup diff`, its JSON output and
+//! `--show-content-diff`.
+//!
+//! Run with `cargo test --test backup_diff`.
+mod common;
+
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+fn ids(entries: &[BackupDiffEntry]) -> Vec<&str> {
+    entries.iter().map(|entry| entry.note_id.as_str()).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn backup_diff() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let backups = dir.path().join("backups");
+    let mut config = common::config(dir.path());
+    config.fsync = FsyncMode::Never;
+    let storage = common::open(config.clone());
+
+    for id in ["alpha", "beta", "gamma", "delta"] {
+        storage
+            .save_note(&common::note(id, "line one\nline two\n"))
+            .unwrap();
+    }
+    let backup = backups.join("kbnotes_backup_20240101_120000.zip");
+    storage.create_full_backup_to(&backup).unwrap();
+
+    // After a bad sync: alpha is gone, epsilon is new, beta was edited and
+    // gamma only had its timestamp bumped
+    storage.delete_note("alpha").unwrap();
+    storage.save_note(&common::note("epsilon", "new")).unwrap();
+    let mut beta = storage.get_note("beta").unwrap();
+    beta.content = "line one\nline 2\n".to_string();
+    beta.updated_at = Utc::now() + Duration::seconds(5);
+    storage.update_note(beta).unwrap();
+    let mut gamma = storage.get_note("gamma").unwrap();
+    gamma.updated_at = Utc::now() + Duration::seconds(5);
+    storage.update_note(gamma).unwrap();
+
+    let diff = storage.diff_backup(&backup).unwrap();
+    assert_eq!(ids(&diff.only_in_backup), ["alpha"]);
+    assert_eq!(ids(&diff.only_in_vault), ["epsilon"]);
+    let changed: Vec<(&str, bool)> = diff
+        .changed
+        .iter()
+        .map(|note| (note.note_id.as_str(), note.content_changed))
+        .collect();
+    assert_eq!(changed, [("beta", true), ("gamma", false)]);
+    assert!(diff.changed[0].vault_updated_at > diff.changed[0].backup_updated_at);
+    assert_eq!(diff.unchanged, 1);
+    assert!(diff.failed_notes.is_empty());
+
+    // A single note can be read back from the backup for a drill-down
+    let old_beta = storage.read_backup_note(&backup, "beta").unwrap().unwrap();
+    assert_eq!(old_beta.content, "line one\nline two\n");
+    assert!(storage
+        .read_backup_note(&backup, "epsilon")
+        .unwrap()
+        .is_none());
+    assert!(storage.get_note("alpha").is_none());
+    assert_eq!(
+        std::fs::read_dir(&backups).unwrap().count(),
+        1,
+        "the diff wrote to the backup directory"
+    );
+
+    let json = serde_json::to_value(&diff).unwrap();
+    assert_eq!(json["only_in_backup"][0]["note_id"], "alpha");
+    assert_eq!(json["changed"][1]["content_changed"], false);
+
+    // The command prints the overview, as text and as JSON, and drills down
+    let storage = Arc::new(Mutex::new(storage));
+    let app = common::app(Arc::clone(&storage), config);
+    let path = backup.display().to_string();
+    for args in [
+        &["backup", "diff", path.as_str()][..],
+        &["backup", "diff", path.as_str(), "--format", "json"],
+        &[
+            "backup",
+            "diff",
+            path.as_str(),
+            "--show-content-diff",
+            "beta",
+        ],
+        &[
+            "backup",
+            "diff",
+            path.as_str(),
+            "--show-content-diff",
+            "alpha",
+        ],
+    ] {
+        common::run(&app, args).await.unwrap();
+    }
+    assert!(common::run(
+        &app,
+        &[
+            "backup",
+            "diff",
+            path.as_str(),
+            "--show-content-diff",
+            "nope",
+        ]
+    )
+    .await
+    .is_err());
+}
@@ -0,0 +1,97 @@
+//! Checks `list_backups`: full backup archives come newest first with their
+//! size and note count, per-note backups are grouped by note, and the
+//! inventory serializes for `kbnotes backup list --format json`. Also checks
+//! that `kbnotes restore --latest` picks the newest archive.
+//!
+//! Run with `cargo test --test backup_list`.
+mod common;
+
+use std::{fs, sync::Arc};
+
+use clap::Parser;
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn backup_list() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let backups = dir.path().join("backups");
+    let mut config = common::config(dir.path());
+    config.auto_backup = true;
+    config.fsync = FsyncMode::Never;
+    let storage = Arc::new(Mutex::new(common::open(config.clone())));
+
+    // An empty backup directory lists nothing
+    let inventory = storage.lock().await.list_backups(true).unwrap();
+    assert!(inventory.archives.is_empty() && inventory.notes.is_empty());
+
+    // Two archives named as `create_full_backup` names them, the newer one
+    // holding one more note
+    let older = backups.join("kbnotes_backup_20240101_120000.zip");
+    let newer = backups.join("kbnotes_backup_20240301_120000.zip");
+    {
+        let storage = storage.lock().await;
+        storage.save_note(&common::note("alpha", "a")).unwrap();
+        storage.save_note(&common::note("beta", "b")).unwrap();
+        storage.create_full_backup_to(&older).unwrap();
+        storage.save_note(&common::note("gamma", "c")).unwrap();
+        storage.create_full_backup_to(&newer).unwrap();
+        storage.delete_note("beta").unwrap();
+    }
+    fs::write(backups.join("unrelated.zip"), "not a backup").unwrap();
+
+    let inventory = storage.lock().await.list_backups(true).unwrap();
+    let paths: Vec<_> = inventory.archives.iter().map(|a| a.path.clone()).collect();
+    assert_eq!(paths, [newer.clone(), older.clone()]);
+    assert_eq!(
+        inventory.archives[0].created_at.to_rfc3339(),
+        "2024-03-01T12:00:00+00:00"
+    );
+    assert_eq!(inventory.archives[0].notes, Some(3));
+    assert_eq!(inventory.archives[1].notes, Some(2));
+    for archive in &inventory.archives {
+        assert_eq!(archive.size, fs::metadata(&archive.path).unwrap().len());
+    }
+
+    // Per-note backups are grouped by note, the latest deletion first
+    let ids: Vec<&str> = inventory
+        .notes
+        .iter()
+        .map(|note| note.note_id.as_str())
+        .collect();
+    assert_eq!(ids.len(), 3);
+    assert_eq!(ids[0], "beta");
+    let beta = &inventory.notes[0];
+    assert!(beta.backups.iter().any(|backup| backup.deletion_record));
+    assert!(beta
+        .backups
+        .windows(2)
+        .all(|pair| pair[0].created_at >= pair[1].created_at));
+    for backup in inventory.notes.iter().flat_map(|note| &note.backups) {
+        assert!(backup.path.starts_with(&backups));
+        assert_eq!(backup.size, fs::metadata(&backup.path).unwrap().len());
+    }
+
+    // Counting can be skipped, and the inventory serializes as JSON
+    let quick = storage.lock().await.list_backups(false).unwrap();
+    assert!(quick.archives.iter().all(|archive| archive.notes.is_none()));
+    let json = serde_json::to_value(&inventory).unwrap();
+    assert_eq!(json["archives"][0]["notes"], 3);
+    assert_eq!(json["notes"][0]["note_id"], "beta");
+
+    // The commands run, and `restore --latest` brings back beta from the newer archive
+    let app = common::app(Arc::clone(&storage), config);
+    for args in [
+        &["backup", "list"][..],
+        &["backup", "list", "--format", "json", "--no-count"],
+    ] {
+        common::run(&app, args).await.unwrap();
+    }
+    assert!(Cli::try_parse_from(["kbnotes", "restore"]).is_err());
+    assert!(Cli::try_parse_from(["kbnotes", "restore", "x.zip", "--latest"]).is_err());
+    assert!(storage.lock().await.get_note("beta").is_none());
+    common::run(&app, &["restore", "--latest", "--force"])
+        .await
+        .unwrap();
+    assert_eq!(storage.lock().await.get_note("beta").unwrap().content, "b");
+}
@@ -0,0 +1,188 @@
+//! Checks `backup_retention`: which synthetic backups an age limit and a
+//! tiered daily/weekly/monthly policy let go, the validation of policies,
+//! and that pruning the backup directory goes by the timestamps in the file
+//! names, not by modification times.
+//!
+//! Run with `cargo test --test backup_retention`.
+mod common;
+
+use std::{fs, path::Path, time::SystemTime};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use kbnotes::*;
+
+fn at(y: i32, mo: u32, d: u32, h: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(y, mo, d, h, 0, 0).unwrap()
+}
+
+/// The backups a policy keeps, newest first
+fn kept(
+    retention: &BackupRetention,
+    backups: &[DateTime<Utc>],
+    now: DateTime<Utc>,
+) -> Vec<DateTime<Utc>> {
+    let expired = retention.expired(backups, now);
+    let mut kept: Vec<DateTime<Utc>> = (0..backups.len())
+        .filter(|i| !expired.contains(i))
+        .map(|i| backups[i])
+        .collect();
+    kept.sort_by(|a, b| b.cmp(a));
+    kept
+}
+
+#[test]
+fn policies() {
+    // A backup at 02:00 every day for 120 days, up to Saturday 2026-10-17
+    let now = at(2026, 10, 17, 12);
+    let daily: Vec<DateTime<Utc>> = (0..120)
+        .map(|day| at(2026, 10, 17, 2) - Duration::days(day))
+        .collect();
+
+    let by_age = BackupRetention {
+        max_age_days: Some(30),
+        ..BackupRetention::default()
+    };
+    let kept_by_age = kept(&by_age, &daily, now);
+    assert_eq!(kept_by_age.len(), 30);
+    assert_eq!(kept_by_age.last(), Some(&at(2026, 9, 18, 2)));
+
+    // Everything too old still leaves the newest backup
+    let old = [now - Duration::days(90), now - Duration::days(45)];
+    assert_eq!(kept(&by_age, &old, now), [old[1]]);
+
+    let tiered = BackupRetention {
+        daily: Some(7),
+        weekly: Some(4),
+        monthly: Some(3),
+        ..BackupRetention::default()
+    };
+    let mut expected: Vec<DateTime<Utc>> = (11..=17).rev().map(|d| at(2026, 10, d, 2)).collect();
+    expected.extend([
+        // The newest of the ISO weeks before, then of the months before
+        at(2026, 10, 4, 2),
+        at(2026, 9, 30, 2),
+        at(2026, 9, 27, 2),
+        at(2026, 8, 31, 2),
+    ]);
+    assert_eq!(kept(&tiered, &daily, now), expected);
+
+    // Several backups a day: only the newest of each day counts
+    let hourly: Vec<DateTime<Utc>> = (0..72)
+        .map(|h| at(2026, 10, 17, 23) - Duration::hours(h))
+        .collect();
+    let two_days = BackupRetention {
+        daily: Some(2),
+        ..BackupRetention::default()
+    };
+    assert_eq!(
+        kept(&two_days, &hourly, now),
+        [at(2026, 10, 17, 23), at(2026, 10, 16, 23)]
+    );
+    assert!(two_days.expired(&[], now).is_empty());
+}
+
+#[test]
+fn validation() {
+    assert_eq!(
+        BackupRetention::parse("daily:14, weekly:13").unwrap(),
+        BackupRetention {
+            daily: Some(14),
+            weekly: Some(13),
+            ..BackupRetention::default()
+        }
+    );
+    for (value, reason) in [
+        ("max_age_days:30,daily:7", "can't be combined"),
+        ("max_age_days:0", "max_age_days cannot be zero"),
+        ("daily:0,weekly:0", "at least one of daily"),
+        ("hourly:3", "unknown 'hourly'"),
+        ("daily", "expected name:count"),
+        ("daily:many", "'many' is not a number"),
+    ] {
+        let error = BackupRetention::parse(value).unwrap_err().to_string();
+        assert!(error.contains(reason), "{}: {}", value, error);
+    }
+
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let mut config = common::config(dir.path());
+    config
+        .set_value("backup_retention", "max_age_days:30")
+        .unwrap();
+    assert_eq!(config.backup_retention.unwrap().max_age_days, Some(30));
+    assert!(config.set_value("backup_retention", "weekly:0").is_err());
+    assert!(config.backup_retention.is_some());
+    config.set_value("backup_retention", "none").unwrap();
+    assert!(config.backup_retention.is_none());
+
+    let path = dir.path().join("config.json");
+    fs::write(
+        &path,
+        r#"{ "backup_retention": { "max_age_days": 7, "monthly": 6 } }"#,
+    )
+    .unwrap();
+    let error = load_config_from_file(&path).unwrap_err().to_string();
+    assert!(error.contains("can't be combined"), "{}", error);
+    fs::write(
+        &path,
+        r#"{ "backup_retention": { "daily": 14, "weekly": 13 } }"#,
+    )
+    .unwrap();
+    let (config, _) = load_config_from_file(&path).unwrap();
+    assert_eq!(config.backup_retention.unwrap().weekly, Some(13));
+}
+
+/// Writes empty backups named after the given ages in days, with
+/// modification times in the opposite order, oldest name newest
+fn synthetic_backups(dir: &Path, ages: &[i64]) -> Vec<String> {
+    fs::create_dir_all(dir).unwrap();
+    ages.iter()
+        .enumerate()
+        .map(|(i, days)| {
+            let stamp = (Utc::now() - Duration::days(*days)).format("%Y%m%d_%H%M%S");
+            let name = format!("kbnotes_backup_{}.zip", stamp);
+            let file = fs::File::create(dir.join(&name)).unwrap();
+            let modified =
+                SystemTime::now() - std::time::Duration::from_secs(3600 * (i as u64 + 1));
+            file.set_modified(modified).unwrap();
+            name
+        })
+        .collect()
+}
+
+fn backups_left(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("kbnotes_backup_") && name.ends_with(".zip"))
+        .collect();
+    names.sort();
+    names
+}
+
+fn check_pruning(retention: Option<BackupRetention>, ages: &[i64], kept: &[usize]) {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let mut config = common::config(dir.path());
+    config.fsync = FsyncMode::Never;
+    config.max_backups = 3;
+    config.backup_retention = retention;
+    let names = synthetic_backups(&config.backup_dir, ages);
+    let storage = common::open(config.clone());
+
+    let new = storage.create_full_backup().unwrap();
+    let mut expected: Vec<String> = kept.iter().map(|&i| names[i].clone()).collect();
+    expected.push(new.file_name().unwrap().to_string_lossy().into_owned());
+    expected.sort();
+    assert_eq!(backups_left(&config.backup_dir), expected);
+}
+
+#[test]
+fn pruning() {
+    // The newest names survive max_backups, though their files look oldest
+    check_pruning(None, &[40, 20, 5, 1], &[2, 3]);
+
+    let by_age = BackupRetention {
+        max_age_days: Some(10),
+        ..BackupRetention::default()
+    };
+    check_pruning(Some(by_age), &[40, 20, 9, 5, 1], &[2, 3, 4]);
+}
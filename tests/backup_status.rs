@@ -0,0 +1,82 @@
+//! Checks that the backup scheduler's status follows the backups its task
+//! runs: the last run, last backup and last error are recorded, the next
+//! scheduled run is reported while the scheduler runs, and `kbnotes backup
+//! status` prints it.
+//!
+//! Run with `cargo test --test backup_status`.
+mod common;
+
+use std::{fs, sync::Arc, time::Duration};
+
+use chrono::Utc;
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+/// Waits for the scheduler task to record a run after `previous`
+async fn next_run(
+    storage: &Arc<Mutex<NoteStorage>>,
+    previous: Option<chrono::DateTime<Utc>>,
+) -> BackupSchedulerStatus {
+    for _ in 0..100 {
+        let status = storage.lock().await.get_backup_status().await;
+        if status.last_run_time.is_some() && status.last_run_time != previous {
+            return status;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("the scheduler recorded no backup run");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn backup_status() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let backups = dir.path().join("backups");
+    let mut config = common::config(dir.path());
+    config.auto_backup = true;
+    config.fsync = FsyncMode::Never;
+    config.backup_frequency = 6;
+    let storage = common::start(config.clone()).await;
+
+    // Running, with the next backup one period away and nothing run yet
+    let status = storage.lock().await.get_backup_status().await;
+    assert!(status.is_running);
+    let next = status.next_backup_time.expect("no next backup time");
+    let expected = Utc::now() + chrono::Duration::hours(6);
+    assert!((expected - next).num_seconds().abs() < 60, "{}", next);
+    assert!(status.last_run_time.is_none() && status.last_backup_time.is_none());
+
+    // A backup run by the task shows up in the status
+    let note = Note::new("Alpha".to_string(), "alpha".to_string(), vec![]);
+    storage.lock().await.save_note(&note).unwrap();
+    storage.lock().await.create_backup_now().await.unwrap();
+    let status = next_run(&storage, None).await;
+    let path = status.last_backup_path.clone().expect("no backup path");
+    assert!(path.exists() && path.starts_with(&backups));
+    assert_eq!(status.last_backup_time, status.last_run_time);
+    assert!(status.last_error.is_none());
+
+    // A failed run records its error and keeps the last backup
+    fs::remove_dir_all(&backups).unwrap();
+    fs::write(&backups, "not a directory").unwrap();
+    storage.lock().await.create_backup_now().await.unwrap();
+    let failed = next_run(&storage, status.last_run_time).await;
+    assert!(failed.last_error.is_some());
+    assert_eq!(failed.last_backup_path, Some(path));
+    assert_eq!(failed.last_backup_time, status.last_backup_time);
+    fs::remove_file(&backups).unwrap();
+
+    // The command prints the status, as text and as JSON
+    let app = common::app(Arc::clone(&storage), config);
+    for args in [
+        &["backup", "status"][..],
+        &["backup", "status", "--format", "json"],
+    ] {
+        common::run(&app, args).await.unwrap();
+    }
+
+    // Stopped, nothing is scheduled
+    storage.lock().await.stop_backup_scheduler().await.unwrap();
+    let status = storage.lock().await.get_backup_status().await;
+    assert!(!status.is_running && status.next_backup_time.is_none());
+    assert!(status.last_error.is_some());
+}
@@ -0,0 +1,262 @@
+//! Checks backup targets: archives are uploaded to an S3-compatible bucket
+//! (a small in-process stand-in that checks requests are signed) and through
+//! a command target, both are pruned to their retention, backups taken by
+//! the scheduler are pushed with failures recorded in its status, and
+//! `kbnotes backup push --latest` pushes on demand.
+//!
+//! Run with `cargo test --test backup_targets`.
+mod common;
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    path::Path,
+    sync::{Arc, Mutex as StdMutex},
+    thread,
+    time::Duration,
+};
+
+use clap::Parser;
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+type Bucket = Arc<StdMutex<BTreeMap<String, Vec<u8>>>>;
+
+/// Serves PUT, DELETE and ListObjectsV2 requests for one bucket, rejecting
+/// requests that aren't signed with SigV4 for the test key
+fn serve_bucket(bucket: Bucket) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).is_err() {
+                continue;
+            }
+            let mut headers = BTreeMap::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let Some((name, value)) = line.trim_end().split_once(": ") else {
+                    break;
+                };
+                headers.insert(name.to_lowercase(), value.to_string());
+            }
+            let length = headers
+                .get("content-length")
+                .map_or(0, |length| length.parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let mut parts = request_line.split_whitespace();
+            let (method, target) = (parts.next().unwrap(), parts.next().unwrap());
+            let (path, query) = target.split_once('?').unwrap_or((target, ""));
+            let key = path.trim_start_matches("/notes").trim_start_matches('/');
+            let signed = headers.get("authorization").is_some_and(|auth| {
+                auth.starts_with("AWS4-HMAC-SHA256 Credential=test-key/")
+                    && auth.contains("/us-east-1/s3/aws4_request")
+                    && auth.contains("Signature=")
+            }) && headers.contains_key("x-amz-date");
+
+            let (status, response) = if !signed {
+                ("403 Forbidden", "<Error>unsigned</Error>".to_string())
+            } else {
+                let mut bucket = bucket.lock().unwrap();
+                match method {
+                    "PUT" => {
+                        bucket.insert(key.to_string(), body);
+                        ("200 OK", String::new())
+                    }
+                    "DELETE" => {
+                        bucket.remove(key);
+                        ("204 No Content", String::new())
+                    }
+                    _ => {
+                        assert!(query.contains("list-type=2"), "{}", query);
+                        let prefix = query
+                            .split('&')
+                            .find_map(|param| param.strip_prefix("prefix="))
+                            .unwrap_or_default()
+                            .replace("%2F", "/");
+                        let keys: String = bucket
+                            .keys()
+                            .filter(|key| key.starts_with(&prefix))
+                            .map(|key| format!("<Contents><Key>{}</Key></Contents>", key))
+                            .collect();
+                        (
+                            "200 OK",
+                            format!("<ListBucketResult>{}</ListBucketResult>", keys),
+                        )
+                    }
+                }
+            };
+            let mut stream = stream;
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                response.len(),
+                response
+            )
+            .unwrap();
+        }
+    });
+    endpoint
+}
+
+fn s3_target(endpoint: &str, secret: Option<&str>) -> BackupTargetConfig {
+    BackupTargetConfig::S3(S3TargetConfig {
+        endpoint: endpoint.to_string(),
+        bucket: "notes".to_string(),
+        region: "us-east-1".to_string(),
+        prefix: "laptop/".to_string(),
+        access_key_id: secret.map(|_| "test-key".to_string()),
+        secret_access_key: secret.map(str::to_string),
+        timeout_secs: 10,
+        max_backups: Some(2),
+    })
+}
+
+fn command_target(remote: &Path) -> BackupTargetConfig {
+    let remote = remote.display();
+    BackupTargetConfig::Command(CommandTargetConfig {
+        upload: format!("cp {{path}} {}/{{name}}", remote),
+        list: Some(format!("ls {}", remote)),
+        remove: Some(format!("rm {}/{{name}}", remote)),
+        max_backups: None,
+    })
+}
+
+fn names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn backup_targets() {
+    // Pruning keeps the newest full backups and the incrementals built on them
+    let listed: Vec<String> = [
+        "kbnotes_backup_20240101_000000.zip",
+        "kbnotes_incremental_20240102_000000.zip",
+        "kbnotes_backup_20240103_000000.zip",
+        "kbnotes_incremental_20240104_000000.zip",
+        "kbnotes_backup_20240105_000000.zip",
+        "notes.txt",
+    ]
+    .map(str::to_string)
+    .into();
+    assert_eq!(
+        archives_to_prune(&listed, 2),
+        [
+            "kbnotes_incremental_20240102_000000.zip",
+            "kbnotes_backup_20240101_000000.zip"
+        ]
+    );
+    assert!(archives_to_prune(&listed, 0).is_empty());
+    assert!(archives_to_prune(&listed, 3).is_empty());
+
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let remote = dir.path().join("remote");
+    fs::create_dir_all(&remote).unwrap();
+    let bucket = Bucket::default();
+    let endpoint = serve_bucket(Arc::clone(&bucket));
+
+    let backups = dir.path().join("backups");
+    let mut config = common::config(dir.path());
+    config.fsync = FsyncMode::Never;
+    config.max_backups = 3;
+    config.backup_targets = vec![
+        s3_target(&endpoint, Some("test-secret")),
+        command_target(&remote),
+    ];
+    let storage = Arc::new(Mutex::new(common::open(config.clone())));
+    let note = Note::new("Alpha".to_string(), "alpha".to_string(), vec![]);
+    storage.lock().await.save_note(&note).unwrap();
+
+    // Three pushes: the bucket keeps two full backups, the command target
+    // falls back to max_backups and keeps all three
+    for day in 1..=3 {
+        let path = backups.join(format!("kbnotes_backup_2024010{}_120000.zip", day));
+        let storage = storage.lock().await;
+        storage.create_full_backup_to(&path).unwrap();
+        let results = storage.push_backup(&path).await.unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(
+            results.iter().all(|result| result.error.is_none()),
+            "{:?}",
+            results
+        );
+    }
+    let keys: Vec<String> = bucket.lock().unwrap().keys().cloned().collect();
+    assert_eq!(
+        keys,
+        [
+            "laptop/kbnotes_backup_20240102_120000.zip",
+            "laptop/kbnotes_backup_20240103_120000.zip"
+        ]
+    );
+    let uploaded = bucket.lock().unwrap()[&keys[1]].clone();
+    assert_eq!(
+        &uploaded,
+        &fs::read(backups.join("kbnotes_backup_20240103_120000.zip")).unwrap()
+    );
+    assert_eq!(names(&remote).len(), 3);
+
+    // The scheduler pushes the backups it takes, and records failed targets
+    storage
+        .lock()
+        .await
+        .initialize(Arc::clone(&storage))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    storage.lock().await.create_backup_now().await.unwrap();
+    let mut status = storage.lock().await.get_backup_status().await;
+    for _ in 0..100 {
+        if status.last_push_time.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        status = storage.lock().await.get_backup_status().await;
+    }
+    assert!(status.last_push_time.is_some(), "nothing was pushed");
+    assert!(
+        status.last_push_errors.is_empty(),
+        "{:?}",
+        status.last_push_errors
+    );
+    let name = status.last_backup_path.unwrap();
+    let name = name.file_name().unwrap().to_string_lossy();
+    assert!(names(&remote).contains(&name.to_string()));
+    assert!(bucket
+        .lock()
+        .unwrap()
+        .contains_key(&format!("laptop/{}", name)));
+    storage.lock().await.stop_backup_scheduler().await.unwrap();
+
+    // Missing credentials fail that target only, and the command reports it
+    config.backup_targets = vec![s3_target(&endpoint, None), command_target(&remote)];
+    std::env::remove_var("AWS_ACCESS_KEY_ID");
+    std::env::remove_var("AWS_SECRET_ACCESS_KEY");
+    let failing = Arc::new(Mutex::new(common::open(config.clone())));
+    let app = common::app(Arc::clone(&failing), config);
+    assert!(common::run(&app, &["backup", "push", "--latest"])
+        .await
+        .is_err());
+    let status = failing.lock().await.get_backup_status().await;
+    assert_eq!(
+        status.last_push_errors.len(),
+        1,
+        "{:?}",
+        status.last_push_errors
+    );
+    assert!(status.last_push_errors[0].starts_with("s3://notes/laptop/"));
+    assert!(Cli::try_parse_from(["kbnotes", "backup", "push"]).is_err());
+}
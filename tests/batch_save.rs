@@ -0,0 +1,162 @@
+//! Checks `save_notes`: a batch is cached and announced without per-note
+//! backups, a note that fails doesn't stop the others, and saving 1,000
+//! notes at once beats saving them one by one. Also checks that `kbnotes
+//! import` and `restore_full_backup` go through it.
+//!
+//! Run with `cargo test --test batch_save`.
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+const NOTE_COUNT: usize = 1000;
+
+fn config(root: &Path) -> Config {
+    let mut config = common::config(root);
+    config.auto_backup = true;
+    config.fsync = FsyncMode::Never;
+    config
+}
+
+fn open_storage(root: &Path) -> NoteStorage {
+    common::open(config(root))
+}
+
+fn notes(count: usize) -> Vec<Note> {
+    (0..count)
+        .map(|i| {
+            Note::new(
+                format!("Note {}", i),
+                format!("Body of note {}", i),
+                vec!["batch".to_string()],
+            )
+        })
+        .collect()
+}
+
+fn backups(root: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(root.join("backups"))
+        .map(|entries| entries.map(|entry| entry.unwrap().path()).collect())
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+/// How long saving `notes` takes, one by one or as one batch
+fn time_saves(notes: &[Note], batched: bool) -> Duration {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let storage = open_storage(dir.path());
+    let started = Instant::now();
+    if batched {
+        let report = storage.save_notes(notes).unwrap();
+        assert_eq!(report.succeeded.len(), notes.len());
+    } else {
+        for note in notes {
+            storage.save_note(note).unwrap();
+        }
+    }
+    started.elapsed()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn batch_save() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let root = dir.path();
+    let storage = open_storage(root);
+    let mut events = storage.subscribe_events();
+
+    // A batch is cached and announced, but not backed up note by note; the
+    // blocked note fails alone
+    let mut batch = notes(3);
+    let mut blocked = Note::new("Blocked".to_string(), "x".to_string(), vec![]);
+    blocked.id = "blocked".to_string();
+    fs::create_dir_all(root.join("notes/bl/blocked.json/inside")).unwrap();
+    batch.insert(1, blocked);
+    let report = storage.save_notes(&batch).unwrap();
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].0, "blocked");
+    assert_eq!(report.succeeded.len(), 3);
+    for note in [&batch[0], &batch[2], &batch[3]] {
+        assert_eq!(storage.get_note(&note.id).as_ref(), Some(note));
+        assert!(storage.is_note_unread(&note.id));
+        let event = events.try_recv().unwrap();
+        assert_eq!(
+            (event.kind, event.note_id.as_str()),
+            (NoteEventKind::Created, note.id.as_str())
+        );
+    }
+    assert!(events.try_recv().is_err());
+    assert!(storage.get_note("blocked").is_none());
+    let files = backups(root);
+    assert!(files.is_empty(), "{:?}", files);
+
+    // Saving a saved note again is an update
+    let mut changed = batch[0].clone();
+    changed.content = "changed".to_string();
+    storage.save_notes(&[changed.clone()]).unwrap();
+    let event = events.try_recv().unwrap();
+    assert_eq!(event.kind, NoteEventKind::Updated);
+    assert_eq!(storage.get_note(&changed.id), Some(changed));
+
+    // 1,000 notes at once beat 1,000 separate saves
+    let many = notes(NOTE_COUNT);
+    let looped = time_saves(&many, false);
+    let batched = time_saves(&many, true);
+    println!(
+        "{} notes: one by one {:?}, batched {:?} ({:.1}x)",
+        NOTE_COUNT,
+        looped,
+        batched,
+        looped.as_secs_f64() / batched.as_secs_f64()
+    );
+    assert!(batched < looped);
+
+    // The import command saves a JSON file in batches
+    let import_dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let source = import_dir.path().join("notes.jsonl");
+    let lines: Vec<String> = many
+        .iter()
+        .map(|note| serde_json::to_string(note).unwrap())
+        .collect();
+    fs::write(&source, lines.join("\n")).unwrap();
+    let imported = Arc::new(Mutex::new(open_storage(import_dir.path())));
+    let app = common::app(Arc::clone(&imported), config(import_dir.path()));
+    let started = Instant::now();
+    common::run(
+        &app,
+        &[
+            "import",
+            "--path",
+            source.to_str().unwrap(),
+            "--format",
+            "json",
+        ],
+    )
+    .await
+    .unwrap();
+    println!("imported {} notes in {:?}", NOTE_COUNT, started.elapsed());
+    assert_eq!(
+        imported.lock().await.get_all_notes().unwrap().len(),
+        NOTE_COUNT
+    );
+    assert!(backups(import_dir.path()).is_empty());
+
+    // Restoring a full backup saves its notes as one batch
+    let backup = imported.lock().await.create_full_backup().unwrap();
+    let restore_dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let restored = open_storage(restore_dir.path());
+    let summary = restored
+        .restore_full_backup(&backup, &RestoreOptions::default())
+        .unwrap();
+    assert_eq!(summary.notes_restored, NOTE_COUNT);
+    assert!(summary.failed_notes.is_empty());
+    assert_eq!(restored.get_all_notes().unwrap().len(), NOTE_COUNT);
+    assert!(backups(restore_dir.path()).is_empty());
+}
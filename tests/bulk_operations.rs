@@ -0,0 +1,138 @@
+//! Checks bulk operations: `retag_notes` and `delete_notes` change every
+//! selected note, report failures (locked or missing notes) without stopping
+//! the batch, and `delete --tag` and `tag --filter-tag` parse and run.
+//!
+//! Run with `cargo test --test bulk_operations`.
+mod common;
+
+use std::sync::Arc;
+
+use clap::Parser;
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+fn tags(tags: &[&str]) -> Vec<String> {
+    tags.iter().map(|tag| tag.to_string()).collect()
+}
+
+fn tagged(storage: &NoteStorage, tag: &str) -> Vec<String> {
+    let mut titles: Vec<String> = storage
+        .get_notes_by_tag(tag, true)
+        .unwrap()
+        .into_iter()
+        .map(|note| note.title)
+        .collect();
+    titles.sort();
+    titles
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bulk_operations() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let config = common::config(dir.path());
+    let storage = common::open(config.clone());
+    let mut ids = Vec::new();
+    for (title, note_tags) in [
+        ("Scratch", vec!["temp"]),
+        ("Draft", vec!["TEMP", "archive-2023"]),
+        ("Locked", vec!["temp"]),
+        ("Old", vec!["temp"]),
+        ("Keep", vec!["work"]),
+    ] {
+        let note = Note::new(title.to_string(), String::new(), tags(&note_tags));
+        storage.save_note(&note).unwrap();
+        ids.push(note.id);
+    }
+    storage.set_locked(&ids[2], true).unwrap();
+    let mut old = storage.get_note(&ids[3]).unwrap();
+    old.archived = true;
+    storage.update_note(old).unwrap();
+
+    // Retagging goes on past the locked note and reports it
+    let filter = NoteFilter {
+        tags: tags(&["temp"]),
+        ..NoteFilter::default()
+    };
+    let report = storage
+        .retag_notes(
+            filter.clone(),
+            &tags(&["archive-2023"]),
+            &tags(&["temp"]),
+            false,
+        )
+        .unwrap();
+    assert_eq!(report.succeeded.len(), 3);
+    assert!(report.unchanged.is_empty());
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].0, ids[2]);
+    assert_eq!(tagged(&storage, "temp"), ["Locked"]);
+    assert_eq!(
+        tagged(&storage, "archive-2023"),
+        ["Draft", "Old", "Scratch"]
+    );
+    // Forcing changes the locked note too; notes with the tags are left alone
+    let report = storage
+        .retag_notes(
+            NoteFilter {
+                tags: tags(&["temp", "archive-2023"]),
+                ..NoteFilter::default()
+            },
+            &tags(&["Archive-2023"]),
+            &tags(&["temp"]),
+            true,
+        )
+        .unwrap();
+    assert_eq!(report.succeeded, [ids[2].clone()]);
+    assert_eq!(report.unchanged.len(), 3);
+    assert!(report.failed.is_empty());
+    assert!(tagged(&storage, "temp").is_empty());
+
+    // Deleting goes on past missing and locked notes and reports them
+    let batch = vec![
+        ids[0].clone(),
+        "missing".to_string(),
+        ids[2].clone(),
+        ids[3].clone(),
+    ];
+    let report = storage.delete_notes(&batch, false, false).unwrap();
+    assert_eq!(report.succeeded, [ids[0].clone(), ids[3].clone()]);
+    let failed: Vec<&str> = report.failed.iter().map(|(id, _)| id.as_str()).collect();
+    assert_eq!(failed, ["missing", ids[2].as_str()]);
+    assert!(storage.get_note(&ids[0]).is_none());
+    assert!(storage.get_note(&ids[2]).is_some());
+    assert_eq!(storage.list_trash().unwrap().len(), 2);
+
+    // Flags
+    let parse = |args: &[&str]| Cli::try_parse_from(["kbnotes"].iter().chain(args));
+    assert!(parse(&["delete", "--tag", "temp", "--tag", "old"]).is_ok());
+    assert!(parse(&["delete", "abc", "--tag", "temp"]).is_err());
+    assert!(parse(&["tag", "--filter-tag", "temp", "--add", "x"]).is_ok());
+    assert!(parse(&["tag", "--filter-tag", "temp", "--list"]).is_err());
+    assert!(parse(&["tag", "abc", "--filter-tag", "temp"]).is_err());
+
+    // Commands; --force skips the confirmation
+    let storage = Arc::new(Mutex::new(storage));
+    let app = common::app(Arc::clone(&storage), config);
+    assert!(matches!(
+        common::run(&app, &["tag", "--filter-tag", "work"]).await,
+        Err(KbError::InvalidArgument { .. })
+    ));
+    common::run(
+        &app,
+        &["tag", "--filter-tag", "work", "--add", "temp", "--force"],
+    )
+    .await
+    .unwrap();
+    assert_eq!(tagged(&*storage.lock().await, "temp"), ["Keep"]);
+    common::run(
+        &app,
+        &["delete", "--tag", "archive-2023", "--force", "--permanent"],
+    )
+    .await
+    .unwrap();
+    assert!(tagged(&*storage.lock().await, "archive-2023").is_empty());
+    assert_eq!(storage.lock().await.list_trash().unwrap().len(), 2);
+    common::run(&app, &["delete", "--tag", "nothing-has-this", "--force"])
+        .await
+        .unwrap();
+}
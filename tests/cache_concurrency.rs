@@ -0,0 +1,119 @@
+//! Checks that searches from several threads share the notes cache while
+//! another thread keeps saving notes, and times the searches alone and in
+//! parallel.
+//!
+//! Run with `cargo test --release --test cache_concurrency -- --nocapture` to time it.
+mod common;
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use kbnotes::*;
+
+const NOTES: usize = 2000;
+const READERS: usize = 4;
+const SEARCHES_PER_READER: usize = 5;
+
+fn generated(i: usize, revision: usize) -> Note {
+    let mut note = Note::new(
+        format!("Note {}", i),
+        format!(
+            "Revision {} of note {}\n\n{}",
+            revision,
+            i,
+            "lorem ipsum dolor sit amet ".repeat(20)
+        ),
+        vec![format!("group-{}", i % 7)],
+    );
+    note.id = format!("note-{:05}", i);
+    note
+}
+
+/// Runs the searches of one reader
+fn search(storage: &NoteStorage, reader: usize) {
+    for i in 0..SEARCHES_PER_READER {
+        let query = format!("note {}", (reader * SEARCHES_PER_READER + i) % 10);
+        let results = storage.search_notes(&query, false).unwrap();
+        assert!(!results.hits.is_empty());
+        assert!(results
+            .hits
+            .iter()
+            .all(|hit| hit.note.id.starts_with("note-")));
+    }
+}
+
+fn rate(searches: usize, elapsed: Duration) -> f64 {
+    searches as f64 / elapsed.as_secs_f64()
+}
+
+#[test]
+fn cache_concurrency() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let mut config = common::config(dir.path());
+    config.fsync = FsyncMode::Never;
+    let storage = common::open(config);
+    for i in 0..NOTES {
+        storage.save_note(&generated(i, 0)).unwrap();
+    }
+    let searches = READERS * SEARCHES_PER_READER;
+
+    // One thread doing every search
+    let started = Instant::now();
+    for reader in 0..READERS {
+        search(&storage, reader);
+    }
+    let sequential = started.elapsed();
+    println!(
+        "{} searches on one thread: {:?} ({:.1}/s)",
+        searches,
+        sequential,
+        rate(searches, sequential)
+    );
+
+    // Readers in parallel, with a writer updating notes meanwhile
+    let started = Instant::now();
+    let writes = thread::scope(|scope| {
+        let readers: Vec<_> = (0..READERS)
+            .map(|reader| {
+                let storage = storage.clone();
+                scope.spawn(move || search(&storage, reader))
+            })
+            .collect();
+        let writer = {
+            let storage = storage.clone();
+            scope.spawn(move || {
+                let mut writes = 0;
+                while writes < NOTES || !readers.iter().all(|r| r.is_finished()) {
+                    storage
+                        .save_note(&generated(writes % NOTES, writes / NOTES + 1))
+                        .unwrap();
+                    writes += 1;
+                }
+                for reader in readers {
+                    reader.join().unwrap();
+                }
+                writes
+            })
+        };
+        writer.join().unwrap()
+    });
+    let parallel = started.elapsed();
+    println!(
+        "{} searches on {} threads with {} concurrent saves: {:?} ({:.1}/s)",
+        searches,
+        READERS,
+        writes,
+        parallel,
+        rate(searches, parallel)
+    );
+
+    // Every note holds its last saved revision
+    for i in 0..NOTES {
+        let last = (0..writes).rev().find(|w| w % NOTES == i).unwrap() / NOTES + 1;
+        let expected = generated(i, last);
+        let note = storage.get_note(&expected.id).unwrap();
+        assert_eq!(note.content, expected.content);
+    }
+}
@@ -0,0 +1,49 @@
+//! Checks that the note cache counts hits, misses and evictions, and that
+//! priming, evicting and clearing it behave as documented.
+//!
+//! Run with `cargo test --test cache_stats`.
+mod common;
+
+use kbnotes::*;
+
+#[test]
+fn cache_stats() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let config = common::config(dir.path());
+    let storage = common::open(config.clone());
+
+    let first = Note::new("First".to_string(), "One".to_string(), vec![]);
+    let second = Note::new("Second".to_string(), "Two".to_string(), vec![]);
+    storage.save_note(&first).unwrap();
+    storage.save_note(&second).unwrap();
+
+    // A fresh instance loads every note into the cache
+    let storage = common::open(config);
+    let stats = storage.cache_stats().unwrap();
+    assert_eq!(stats.entries, 2);
+    assert_eq!((stats.hits, stats.misses, stats.evictions), (0, 0, 0));
+    assert!(stats.bytes_estimate > 0);
+
+    // Evicting and priming
+    assert_eq!(storage.evict(std::slice::from_ref(&first.id)).unwrap(), 1);
+    assert_eq!(storage.cache_stats().unwrap().entries, 1);
+    let ids = [first.id.clone(), second.id.clone(), "0-missing".to_string()];
+    assert_eq!(storage.prime_cache(&ids).unwrap(), 1);
+    let stats = storage.cache_stats().unwrap();
+    assert_eq!(stats.entries, 2);
+    assert_eq!((stats.hits, stats.misses, stats.evictions), (0, 0, 1));
+
+    // A cached read is a hit; a read after eviction is a miss that reloads
+    storage.get_note(&first.id).unwrap();
+    storage.evict(std::slice::from_ref(&first.id)).unwrap();
+    assert_eq!(storage.get_note(&first.id).unwrap().content, "One");
+    let stats = storage.cache_stats().unwrap();
+    assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 1, 2));
+    assert_eq!(stats.entries, 2);
+
+    // Clearing empties the cache and counts every entry as evicted
+    assert_eq!(storage.clear_cache().unwrap(), 2);
+    let stats = storage.cache_stats().unwrap();
+    assert_eq!(stats.entries, 0);
+    assert_eq!(stats.evictions, 4);
+}
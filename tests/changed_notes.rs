@@ -0,0 +1,151 @@
+//! Checks incremental change queries: `get_notes_modified_since` reports
+//! created and modified notes (archived ones too) in update order and
+//! trashed and purged notes with their deletion time, `list --since` filters
+//! on the update time, and `kbnotes changed` runs.
+//!
+//! Run with `cargo test --test changed_notes`.
+mod common;
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+fn at(time: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(time)
+        .unwrap()
+        .with_timezone(&Utc)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn changed_notes() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let config = common::config(dir.path());
+    let storage = common::open(config.clone());
+    for (title, created, updated, archived) in [
+        (
+            "Untouched",
+            "2024-04-01T09:00:00Z",
+            "2024-04-02T09:00:00Z",
+            false,
+        ),
+        (
+            "Edited",
+            "2024-04-01T09:00:00Z",
+            "2024-05-03T09:00:00Z",
+            false,
+        ),
+        (
+            "Created",
+            "2024-05-02T09:00:00Z",
+            "2024-05-02T09:00:00Z",
+            false,
+        ),
+        (
+            "Archived",
+            "2024-03-01T09:00:00Z",
+            "2024-05-01T00:00:00Z",
+            true,
+        ),
+    ] {
+        let mut note = Note::new(title.to_string(), String::new(), vec![]);
+        note.created_at = at(created);
+        note.updated_at = at(updated);
+        note.archived = archived;
+        storage.save_note(&note).unwrap();
+    }
+
+    // Least recently updated first; the bound itself is included
+    let changed = |since: &str| -> Vec<String> {
+        storage
+            .get_notes_modified_since(at(since))
+            .unwrap()
+            .modified
+            .into_iter()
+            .map(|note| note.title)
+            .collect()
+    };
+    assert_eq!(
+        changed("2024-05-01T00:00:00Z"),
+        ["Archived", "Created", "Edited"]
+    );
+    assert_eq!(changed("2024-05-02T12:00:00Z"), ["Edited"]);
+    assert!(changed("2024-06-01T00:00:00Z").is_empty());
+    assert_eq!(changed("2000-01-01T00:00:00Z").len(), 4);
+
+    // Trashed notes are reported from their deletion time, purged ones are
+    // still reported once they left the trash, restored ones not at all
+    let start = Utc::now();
+    let id_of = |title: &str| storage.find_by_title(title).unwrap()[0].id.clone();
+    let (untouched, created, edited) = (id_of("Untouched"), id_of("Created"), id_of("Edited"));
+    let scratch = Note::new("Scratch".to_string(), String::new(), vec![]);
+    storage.save_note(&scratch).unwrap();
+    storage.delete_note(&untouched).unwrap();
+    storage.empty_trash(None).unwrap();
+    storage.delete_note(&created).unwrap();
+    storage.delete_note_permanently(&scratch.id).unwrap();
+    storage.delete_note(&edited).unwrap();
+    storage.restore_from_trash(&edited).unwrap();
+
+    let deleted = storage.get_notes_modified_since(start).unwrap().deleted;
+    let mut reported: Vec<(&str, bool)> = deleted
+        .iter()
+        .map(|deleted| (deleted.id.as_str(), deleted.purged))
+        .collect();
+    reported.sort();
+    let mut expected = vec![
+        (untouched.as_str(), true),
+        (created.as_str(), false),
+        (scratch.id.as_str(), true),
+    ];
+    expected.sort();
+    assert_eq!(reported, expected);
+    assert!(deleted.iter().all(|deleted| deleted.deleted_at >= start));
+    assert!(deleted
+        .windows(2)
+        .all(|pair| pair[0].deleted_at <= pair[1].deleted_at));
+    let later = Utc::now() + chrono::Duration::hours(1);
+    assert!(storage
+        .get_notes_modified_since(later)
+        .unwrap()
+        .deleted
+        .is_empty());
+
+    // `list --since` is `--updated-after`
+    let Commands::List(options) =
+        Cli::try_parse_from(["kbnotes", "list", "--since", "2024-05-01T00:00:00Z"])
+            .unwrap()
+            .command
+    else {
+        panic!("expected the list command");
+    };
+    assert_eq!(
+        options.dates.updated_after.as_deref(),
+        Some("2024-05-01T00:00:00Z")
+    );
+
+    // `kbnotes changed` accepts both date forms and rejects others
+    let app = common::app(Arc::new(Mutex::new(storage)), config);
+    common::run(
+        &app,
+        &[
+            "changed",
+            "--since",
+            "2024-05-01T00:00:00Z",
+            "--format",
+            "json",
+        ],
+    )
+    .await
+    .unwrap();
+    common::run(&app, &["changed", "--since", "2024-05-01"])
+        .await
+        .unwrap();
+    assert!(matches!(
+        common::run(&app, &["changed", "--since", "last week"]).await,
+        Err(KbError::InvalidFormat { .. })
+    ));
+    assert!(Cli::try_parse_from(["kbnotes", "changed"]).is_err());
+}
@@ -0,0 +1,73 @@
+//! Checks that shutting down after no edits writes nothing: the flush on
+//! shutdown only writes notes whose cached copy wasn't stored and the link
+//! index only when its links changed, so no file in the vault or the backups
+//! is touched even with `auto_backup` on and the watcher running.
+//!
+//! Run with `cargo test --test clean_shutdown`.
+mod common;
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use kbnotes::*;
+use walkdir::WalkDir;
+
+/// Every file below `dir` with its modification time, storage state such as
+/// indexes included
+fn file_times(dir: &Path) -> BTreeMap<PathBuf, SystemTime> {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| {
+            let modified = entry.metadata().unwrap().modified().unwrap();
+            (entry.path().to_path_buf(), modified)
+        })
+        .collect()
+}
+
+/// Note files, state files and backups
+fn written_files(root: &Path) -> BTreeMap<PathBuf, SystemTime> {
+    let mut files = file_times(&root.join("notes"));
+    files.extend(file_times(&root.join("backups")));
+    files
+}
+
+async fn run(root: &Path, format: StorageFormat) {
+    let mut config = common::config(root);
+    config.auto_backup = true;
+    config.fsync = FsyncMode::Never;
+    config.storage_format = format;
+
+    let storage = common::start(config).await;
+    {
+        let storage = storage.lock().await;
+        for title in ["alpha", "beta", "gamma"] {
+            let note = Note::new(title.to_string(), format!("{} body", title), vec![]);
+            storage.save_note(&note).unwrap();
+        }
+        let mut note = storage.get_all_notes().unwrap().remove(0);
+        note.content.push_str("\nmore");
+        storage.update_note(note).unwrap();
+    }
+
+    // Let the watcher see the writes above before taking stock
+    tokio::time::sleep(Duration::from_millis(800)).await;
+    let before = written_files(root);
+    assert!(before.len() > 3, "expected notes and backups: {:?}", before);
+
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    storage.lock().await.shutdown().await.unwrap();
+    assert_eq!(written_files(root), before);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clean_shutdown() {
+    for format in [StorageFormat::Json, StorageFormat::Split] {
+        let dir = tempfile::tempdir().expect("failed to create temporary vault");
+        run(dir.path(), format).await;
+    }
+}
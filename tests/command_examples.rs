@@ -0,0 +1,25 @@
+//! Checks that every registered command example parses through the CLI
+//! definition, that every subcommand has examples, and that the generated
+//! help shows them.
+//!
+//! Run with `cargo test --test command_examples`.
+use kbnotes::*;
+
+#[test]
+fn command_examples() {
+    validate_examples().expect("registered examples are stale");
+
+    for name in ["pin", "tasks", "template", "history"] {
+        let examples = examples_for(name).expect(name);
+        assert!(!examples.is_empty(), "{}", name);
+        let mut command = command_with_examples();
+        let help = command
+            .find_subcommand_mut(name)
+            .expect(name)
+            .render_long_help()
+            .to_string();
+        for example in examples {
+            assert!(help.contains(example.description), "{}", help);
+        }
+    }
+}
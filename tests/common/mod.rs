@@ -0,0 +1,79 @@
+//! Fixtures shared by the integration tests: temporary vaults, notes with
+//! fixed ids, and an `App` to run CLI commands against.
+#![allow(dead_code)]
+
+use std::{path::Path, sync::Arc};
+
+use clap::Parser;
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+/// Creates the configuration of a vault under `root`
+///
+/// Notes live in `root/notes` and backups in `root/backups`. Automatic
+/// backups are off so that tests only see the backups they create.
+///
+/// # Arguments
+///
+/// * `root` - Directory to create the vault in, usually a temporary one
+///
+/// # Returns
+///
+/// A configuration that tests can tweak before opening the vault
+pub fn config(root: &Path) -> Config {
+    let mut config = Config::with_dirs(root.join("notes"), root.join("backups"));
+    config.auto_backup = false;
+    config
+}
+
+/// Opens the vault described by `config` and loads its notes
+pub fn open(config: Config) -> NoteStorage {
+    let mut storage = NoteStorage::new(config);
+    storage.load_notes().unwrap();
+    storage
+}
+
+/// Opens a vault under `root` with the default test configuration
+pub fn vault(root: &Path) -> NoteStorage {
+    open(config(root))
+}
+
+/// Creates an untagged note whose id is `id`
+pub fn note(id: &str, content: &str) -> Note {
+    let mut note = Note::new(id.to_string(), content.to_string(), vec![]);
+    note.id = id.to_string();
+    note
+}
+
+/// Creates a quiet `App` for running CLI commands against `storage`
+pub fn app(storage: Arc<Mutex<NoteStorage>>, config: Config) -> App {
+    App::new(storage, config, ConfigOrigin::default(), false, true)
+}
+
+/// Runs a CLI command, given without the program name, through `app`
+///
+/// # Arguments
+///
+/// * `app` - Application to run the command with
+/// * `args` - Command line arguments after `kbnotes`
+///
+/// # Returns
+///
+/// The result of the command
+pub async fn run(app: &App, args: &[&str]) -> Result<()> {
+    let cli = Cli::parse_from(["kbnotes"].iter().chain(args));
+    app.run(cli.command).await
+}
+
+/// Opens and initializes the vault described by `config` as the CLI does,
+/// starting its backup scheduler
+pub async fn start(config: Config) -> Arc<Mutex<NoteStorage>> {
+    let storage = Arc::new(Mutex::new(NoteStorage::new(config)));
+    storage
+        .lock()
+        .await
+        .initialize(Arc::clone(&storage))
+        .await
+        .unwrap();
+    storage
+}
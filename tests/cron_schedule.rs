@@ -0,0 +1,164 @@
+//! Checks `backup_schedule`: cron expressions yield the expected UTC
+//! occurrences, invalid ones are rejected by `config --set` and when the
+//! configuration file is loaded, and the scheduler follows the schedule.
+//!
+//! Run with `cargo test --test cron_schedule`.
+mod common;
+
+use std::{fs, time::Duration};
+
+use chrono::{DateTime, TimeZone, Utc};
+use kbnotes::*;
+
+fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(y, mo, d, h, mi, s).unwrap()
+}
+
+fn next(expression: &str, after: DateTime<Utc>) -> DateTime<Utc> {
+    CronSchedule::parse(expression)
+        .unwrap()
+        .next_after(after)
+        .unwrap()
+}
+
+#[test]
+fn occurrences() {
+    // Friday 2026-10-16 02:30 runs once: the next run is Monday's
+    let weekdays = "30 2 * * MON-FRI";
+    assert_eq!(
+        next(weekdays, at(2026, 10, 16, 1, 0, 0)),
+        at(2026, 10, 16, 2, 30, 0)
+    );
+    assert_eq!(
+        next(weekdays, at(2026, 10, 16, 2, 30, 0)),
+        at(2026, 10, 19, 2, 30, 0)
+    );
+    // Numbered as in the `cron` crate, Monday to Friday are 2-6
+    assert_eq!(
+        next("30 2 * * 2-6", at(2026, 10, 17, 0, 0, 0)),
+        at(2026, 10, 19, 2, 30, 0)
+    );
+
+    // Steps, lists and the seconds field
+    assert_eq!(
+        next("*/15 * * * *", at(2026, 1, 1, 10, 16, 0)),
+        at(2026, 1, 1, 10, 30, 0)
+    );
+    assert_eq!(
+        next("0 0 9,18 * * *", at(2026, 1, 1, 9, 0, 0)),
+        at(2026, 1, 1, 18, 0, 0)
+    );
+    assert_eq!(
+        next("*/20 * * * * *", at(2026, 1, 1, 0, 0, 41)),
+        at(2026, 1, 1, 0, 1, 0)
+    );
+
+    // Month ends, leap days, and Sunday as 1
+    assert_eq!(
+        next("0 0 31 * *", at(2026, 2, 1, 0, 0, 0)),
+        at(2026, 3, 31, 0, 0, 0)
+    );
+    assert_eq!(
+        next("0 0 29 FEB *", at(2026, 3, 1, 0, 0, 0)),
+        at(2028, 2, 29, 0, 0, 0)
+    );
+    assert_eq!(
+        next("0 12 * * 1", at(2026, 10, 17, 0, 0, 0)),
+        at(2026, 10, 18, 12, 0, 0)
+    );
+    assert!(CronSchedule::parse("0 0 31 2 *")
+        .unwrap()
+        .next_after(at(2026, 1, 1, 0, 0, 0))
+        .is_none());
+
+    // Day of month and day of week both restricted: a day matches both
+    assert_eq!(
+        next("0 0 1 * MON", at(2026, 10, 17, 0, 0, 0)),
+        at(2027, 2, 1, 0, 0, 0)
+    );
+
+    // Seven fields end with the year, and shorthands work too
+    assert_eq!(
+        next("0 0 12 1 1 * 2030", at(2026, 10, 17, 0, 0, 0)),
+        at(2030, 1, 1, 12, 0, 0)
+    );
+    assert_eq!(
+        next("@daily", at(2026, 10, 17, 9, 0, 0)),
+        at(2026, 10, 18, 0, 0, 0)
+    );
+
+    // Across the European DST change on 2026-10-25 the hourly run still
+    // happens once per UTC hour
+    let hourly = "0 * * * *";
+    let mut time = at(2026, 10, 24, 23, 30, 0);
+    for hour in 0..4 {
+        time = next(hourly, time);
+        assert_eq!(time, at(2026, 10, 25, hour, 0, 0));
+    }
+}
+
+#[test]
+fn validation() {
+    for (expression, reason) in [
+        ("* * * *", "expected 5, 6 or 7 fields"),
+        ("61 * * * *", "Minutes must be less than 59"),
+        ("* * * * FUNDAY", "'FUNDAY' is not a valid day of the week"),
+        ("*/0 * * * *", "range step cannot be zero"),
+        ("0 5-1 * * *", "Invalid range for Hours: 5-1"),
+        // Vixie cron's 0 for Sunday is out of range
+        (
+            "0 0 * * 0",
+            "Days of Week must be greater than or equal to 1",
+        ),
+    ] {
+        let error = CronSchedule::parse(expression).unwrap_err().to_string();
+        assert!(error.contains(reason), "{}: {}", expression, error);
+    }
+
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let mut config = common::config(dir.path());
+    config.set_value("backup_schedule", "0 3 * * *").unwrap();
+    assert_eq!(config.backup_schedule.as_deref(), Some("0 3 * * *"));
+    let error = config
+        .set_value("backup_schedule", "0 25 * * *")
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("Hours must be less than 23"), "{}", error);
+    assert_eq!(config.backup_schedule.as_deref(), Some("0 3 * * *"));
+    config.set_value("backup_schedule", "none").unwrap();
+    assert!(config.backup_schedule.is_none());
+
+    let path = dir.path().join("config.json");
+    fs::write(&path, r#"{ "backup_schedule": "0 0 * * * * * *" }"#).unwrap();
+    let error = load_config_from_file(&path).unwrap_err().to_string();
+    assert!(error.contains("expected 5, 6 or 7 fields"), "{}", error);
+    fs::write(&path, r#"{ "backup_schedule": "0 0 * * SUN" }"#).unwrap();
+    let (config, _) = load_config_from_file(&path).unwrap();
+    assert_eq!(config.backup_schedule.as_deref(), Some("0 0 * * SUN"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scheduler() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let mut config = common::config(dir.path());
+    config.fsync = FsyncMode::Never;
+    config.auto_backup = true;
+    // Every two seconds, far more often than the hourly frequency
+    config.backup_frequency = 1;
+    config.backup_schedule = Some("*/2 * * * * *".to_string());
+    let storage = common::start(config).await;
+
+    let status = storage.lock().await.get_backup_status().await;
+    let first = status.next_backup_time.unwrap();
+    assert!(first > Utc::now() - chrono::Duration::seconds(1));
+    assert!(first <= Utc::now() + chrono::Duration::seconds(2));
+
+    tokio::time::sleep(Duration::from_millis(4500)).await;
+    let status = storage.lock().await.get_backup_status().await;
+    assert!(status.last_backup_time.is_some(), "{:?}", status);
+    assert!(status.last_error.is_none(), "{:?}", status);
+    let next = status.next_backup_time.unwrap();
+    assert!(next > status.last_run_time.unwrap());
+    assert!(next <= Utc::now() + chrono::Duration::seconds(2));
+    storage.lock().await.shutdown().await.unwrap();
+}
@@ -0,0 +1,92 @@
+//! Checks the accepted forms of due dates, grouping notes into overdue, today
+//! and upcoming, and that due dates are kept in the note file.
+//!
+//! Run with `cargo test --test due_dates`.
+mod common;
+
+use chrono::{Local, NaiveDate};
+use kbnotes::*;
+
+fn local_day(value: &str, today: NaiveDate) -> NaiveDate {
+    parse_due_date(value, today)
+        .unwrap()
+        .with_timezone(&Local)
+        .date_naive()
+}
+
+#[test]
+fn due_dates() {
+    let today = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+    let day = |d| NaiveDate::from_ymd_opt(2024, 6, d).unwrap();
+
+    assert_eq!(local_day("2024-06-10", today), day(10));
+    assert_eq!(local_day("today", today), today);
+    assert_eq!(local_day("Tomorrow", today), day(2));
+    assert_eq!(local_day("+3d", today), day(4));
+    assert_eq!(local_day("+2w", today), day(15));
+    assert_eq!(
+        parse_due_date("2024-06-03T14:30:00Z", today)
+            .unwrap()
+            .to_rfc3339(),
+        "2024-06-03T14:30:00+00:00"
+    );
+    for bad in ["soon", "+", "+3m", "+xd", "2024-13-01", ""] {
+        assert!(
+            matches!(
+                parse_due_date(bad, today),
+                Err(KbError::InvalidFormat { .. })
+            ),
+            "{} should be rejected",
+            bad
+        );
+    }
+    // Bare dates are due at the start of the day
+    assert_eq!(
+        format_due_date(parse_due_date("2024-06-10", today).unwrap()),
+        "2024-06-10"
+    );
+
+    let note = |title: &str, due: Option<&str>| {
+        let mut note = Note::new(title.to_string(), String::new(), vec![]);
+        note.due_at = due.map(|value| parse_due_date(value, today).unwrap());
+        note
+    };
+    let notes = vec![
+        note("Later", Some("+2w")),
+        note("Late", Some("2024-05-20")),
+        note("Now", Some("today")),
+        note("Soon", Some("tomorrow")),
+        note("Someday", None),
+    ];
+    let groups: Vec<(DueGroup, Vec<String>)> = group_by_due_date(notes, today)
+        .into_iter()
+        .map(|(group, notes)| (group, notes.into_iter().map(|n| n.title).collect()))
+        .collect();
+    assert_eq!(
+        groups,
+        [
+            (DueGroup::Overdue, vec!["Late".to_string()]),
+            (DueGroup::Today, vec!["Now".to_string()]),
+            (
+                DueGroup::Upcoming,
+                vec!["Soon".to_string(), "Later".to_string()]
+            ),
+        ]
+    );
+    assert!(group_by_due_date(vec![note("Someday", None)], today).is_empty());
+
+    // Stored with the note, and absent in files written before due dates
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let config = common::config(dir.path());
+    let storage = common::open(config.clone());
+    let due = note("Report", Some("2024-06-10"));
+    storage.save_note(&due).unwrap();
+    let reloaded = common::open(config);
+    assert_eq!(reloaded.get_note(&due.id).unwrap().due_at, due.due_at);
+    let legacy: Note = serde_json::from_str(
+        r#"{"id": "a", "title": "A", "content": "", "tags": [],
+            "created_at": "2024-01-01T00:00:00Z", "updated_at": "2024-01-01T00:00:00Z"}"#,
+    )
+    .unwrap();
+    assert_eq!(legacy.due_at, None);
+}
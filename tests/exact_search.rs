@@ -0,0 +1,114 @@
+//! Checks exact search: literal substring matching where fuzzy search finds
+//! scattered letters, case sensitivity, ranking by occurrences and snippets.
+//!
+//! Run with `cargo test --test exact_search`.
+mod common;
+
+use kbnotes::*;
+
+fn titles(results: SearchResults) -> Vec<String> {
+    results
+        .into_notes()
+        .into_iter()
+        .map(|note| note.title)
+        .collect()
+}
+
+#[test]
+fn exact_search() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let config = common::config(dir.path());
+    let storage = common::open(config);
+    for (title, content, tags) in [
+        (
+            "Borrow errors",
+            "E0502 again: e0502 everywhere, E0502",
+            vec![],
+        ),
+        ("Build log", "Error 05 in step 02", vec![]),
+        ("E0502 cheatsheet", "see the book", vec!["rust"]),
+        ("Todo", "TODO: (a AND b)", vec!["scratch"]),
+    ] {
+        let tags = tags.into_iter().map(str::to_string).collect();
+        storage
+            .save_note(&Note::new(title.to_string(), content.to_string(), tags))
+            .unwrap();
+    }
+    let search = |query: &str, mode: SearchMode| {
+        titles(
+            storage
+                .search_notes_filtered(
+                    query,
+                    &SearchRequest {
+                        mode,
+                        ..SearchRequest::default()
+                    },
+                )
+                .unwrap(),
+        )
+    };
+    let exact = SearchMode::Exact {
+        case_sensitive: false,
+    };
+    let case_sensitive = SearchMode::Exact {
+        case_sensitive: true,
+    };
+
+    // Fuzzy search finds the scattered letters, exact search doesn't
+    assert!(search("E0502", SearchMode::Fuzzy).contains(&"Build log".to_string()));
+    assert!(!search("E0502", exact).contains(&"Build log".to_string()));
+    assert!(search("Er052", SearchMode::Fuzzy).contains(&"Build log".to_string()));
+    assert!(search("Er052", exact).is_empty());
+
+    // Ranked by occurrences, title ones counting double
+    assert_eq!(
+        search("e0502", exact),
+        ["Borrow errors", "E0502 cheatsheet"]
+    );
+    assert_eq!(
+        exact_score(
+            &Note::new("E0502".into(), "E0502".into(), vec![]),
+            "e0502",
+            false,
+            SearchScope::All,
+            SearchWeights::default()
+        )
+        .score,
+        3
+    );
+    // Case sensitivity
+    assert_eq!(search("e0502", case_sensitive), ["Borrow errors"]);
+    assert!(search("todo:", case_sensitive).is_empty());
+    assert_eq!(search("TODO:", case_sensitive), ["Todo"]);
+
+    // Operators and quotes are matched as written
+    assert_eq!(search("(a AND b)", exact), ["Todo"]);
+    assert!(search("b AND a", SearchMode::Fuzzy).contains(&"Todo".to_string()));
+    assert!(search("b AND a", exact).is_empty());
+
+    // Exact mode combines with filters and paging
+    let results = storage
+        .search_notes_filtered(
+            "E0502",
+            &SearchRequest {
+                mode: exact,
+                filter: NoteFilter {
+                    exclude_tags: vec!["rust".to_string()],
+                    ..NoteFilter::default()
+                },
+                limit: Some(1),
+                ..SearchRequest::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        (results.total, titles(results)),
+        (1, vec!["Borrow errors".to_string()])
+    );
+
+    // Snippets highlight the literal occurrence
+    let note = storage.find_by_title("Borrow errors").unwrap().remove(0);
+    let snippet =
+        search_snippet_with_mode(&note, "e0502", case_sensitive, SearchScope::All).unwrap();
+    assert_eq!(snippet.match_positions, (13..18).collect::<Vec<_>>());
+}
@@ -0,0 +1,129 @@
+//! Checks `kbnotes export --single-file` for Markdown, JSON and HTML, the
+//! order of the notes, and that a JSON export imports back into the same notes.
+//!
+//! Run with `cargo test --test export_single_file`.
+mod common;
+
+use std::{fs, path::Path, sync::Arc};
+
+use chrono::{Duration, TimeZone, Utc};
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+async fn vault(root: &Path) -> (App, Arc<Mutex<NoteStorage>>) {
+    let config = common::config(root);
+    let storage = Arc::new(Mutex::new(common::open(config.clone())));
+    let app = common::app(Arc::clone(&storage), config);
+    (app, storage)
+}
+
+/// Positions of the given titles in `text`, which must all be present
+fn positions(text: &str, needles: &[&str]) -> Vec<usize> {
+    needles
+        .iter()
+        .map(|needle| text.find(needle).expect(needle))
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn export_single_file() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let (app, storage) = vault(&dir.path().join("source")).await;
+
+    // Created out of title order, two of them sharing a title
+    let base = Utc.with_ymd_and_hms(2024, 5, 1, 9, 0, 0).unwrap();
+    let mut notes = Vec::new();
+    for (offset, id, title, content) in [
+        (2, "n-banana", "Banana", "Yellow\n\n- [ ] peel"),
+        (0, "n-cherry", "Cherry", "Red"),
+        (1, "n-apple", "Apple", "Green"),
+        (3, "n-apple-2", "Apple", "Another apple"),
+    ] {
+        let mut note = Note::new(title.to_string(), content.to_string(), vec!["fruit".into()]);
+        note.id = id.to_string();
+        note.created_at = base + Duration::hours(offset);
+        note.updated_at = note.created_at + Duration::minutes(5);
+        note.aliases.push(format!("{}-alias", id));
+        note.metadata
+            .insert("origin".to_string(), "orchard".to_string());
+        storage.lock().await.save_note(&note).unwrap();
+        notes.push(note);
+    }
+    notes.sort_by_key(|note| note.created_at);
+
+    // Markdown: oldest first, an H1 per note and rules between them
+    let markdown = dir.path().join("out").join("notes.md");
+    let markdown_arg = markdown.to_str().unwrap();
+    common::run(&app, &["export", "-o", markdown_arg, "--single-file"])
+        .await
+        .expect("command failed");
+    let text = fs::read_to_string(&markdown).unwrap();
+    let found = positions(&text, &["# Cherry", "# Apple", "# Banana", "Another apple"]);
+    assert!(found.windows(2).all(|pair| pair[0] < pair[1]), "{}", text);
+    assert_eq!(text.matches("\n---\n").count(), 3);
+
+    // --sort-by follows the list command
+    common::run(
+        &app,
+        &[
+            "export",
+            "-o",
+            markdown_arg,
+            "-s",
+            "--sort-by",
+            "title",
+            "--desc",
+        ],
+    )
+    .await
+    .expect("command failed");
+    let text = fs::read_to_string(&markdown).unwrap();
+    let found = positions(&text, &["# Cherry", "# Banana", "# Apple"]);
+    assert!(found.windows(2).all(|pair| pair[0] < pair[1]), "{}", text);
+
+    // HTML: one document with a table of contents and unique anchors
+    let html = dir.path().join("out").join("notes.html");
+    common::run(
+        &app,
+        &["export", "-o", html.to_str().unwrap(), "-f", "html", "-s"],
+    )
+    .await
+    .expect("command failed");
+    let text = fs::read_to_string(&html).unwrap();
+    assert_eq!(text.matches("<article id=").count(), 4);
+    assert!(text.contains("<a href=\"#apple\">Apple</a>"));
+    assert!(text.contains("<a href=\"#apple-n-apple-2\">Apple</a>"));
+
+    // JSON: an array of the notes, identical to the stored ones
+    let json = dir.path().join("out").join("notes.json");
+    let json_arg = json.to_str().unwrap();
+    common::run(&app, &["export", "-o", json_arg, "-f", "json", "-s"])
+        .await
+        .expect("command failed");
+    let exported: Vec<Note> = serde_json::from_str(&fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(exported, notes);
+
+    // Importing the JSON export into another vault gives back the same notes,
+    // under new IDs that keep the original ones as aliases
+    let (target, target_storage) = vault(&dir.path().join("target")).await;
+    common::run(&target, &["import", "-p", json_arg, "-f", "json"])
+        .await
+        .expect("command failed");
+    let mut imported = target_storage.lock().await.get_all_notes().unwrap();
+    imported.sort_by_key(|note| note.created_at);
+    assert_eq!(imported.len(), notes.len());
+    for (original, copy) in notes.iter().zip(&imported) {
+        assert_eq!(copy.title, original.title);
+        assert_eq!(copy.content, original.content);
+        assert_eq!(copy.tags, original.tags);
+        assert_eq!(copy.created_at, original.created_at);
+        assert_eq!(copy.updated_at, original.updated_at);
+        assert!(copy.aliases.contains(&original.id));
+        assert!(original
+            .aliases
+            .iter()
+            .all(|alias| copy.aliases.contains(alias)));
+        assert_eq!(copy.metadata["origin"], "orchard");
+        assert_eq!(copy.metadata["import_format"], "json");
+    }
+}
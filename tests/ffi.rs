@@ -0,0 +1,107 @@
+//! Exercises the C interface the way an embedding application would.
+//!
+//! Run with `cargo test --test ffi --features ffi`.
+use std::{
+    ffi::{c_char, c_void, CStr, CString},
+    ptr,
+    sync::mpsc,
+    time::Duration,
+};
+
+use kbnotes::*;
+
+/// Panics with the last FFI error unless `status` is `Ok`
+fn check(status: KbnotesStatus, call: &str) {
+    if status != KbnotesStatus::Ok {
+        let message = unsafe { CStr::from_ptr(kbnotes_last_error()) };
+        panic!(
+            "{} failed with {:?}: {}",
+            call,
+            status,
+            message.to_string_lossy()
+        );
+    }
+}
+
+/// Takes ownership of a string returned by the library
+fn take_json(value: *mut c_char) -> serde_json::Value {
+    let json = unsafe { CStr::from_ptr(value) }
+        .to_string_lossy()
+        .into_owned();
+    unsafe { kbnotes_string_free(value) };
+    serde_json::from_str(&json).expect("library returned invalid JSON")
+}
+
+extern "C" fn on_event(event_json: *const c_char, user_data: *mut c_void) {
+    let sender = unsafe { &*(user_data as *const mpsc::Sender<String>) };
+    let event = unsafe { CStr::from_ptr(event_json) };
+    let _ = sender.send(event.to_string_lossy().into_owned());
+}
+
+#[test]
+fn ffi() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let notes_dir = CString::new(dir.path().join("notes").to_string_lossy().as_ref()).unwrap();
+    let backup_dir = CString::new(dir.path().join("backups").to_string_lossy().as_ref()).unwrap();
+
+    unsafe {
+        let mut vault = ptr::null_mut();
+        check(
+            kbnotes_vault_open(notes_dir.as_ptr(), backup_dir.as_ptr(), &mut vault),
+            "open",
+        );
+
+        let (sender, events) = mpsc::channel::<String>();
+        let mut subscription = ptr::null_mut();
+        check(
+            kbnotes_subscribe(
+                vault,
+                Some(on_event),
+                &sender as *const _ as *mut c_void,
+                &mut subscription,
+            ),
+            "subscribe",
+        );
+
+        let input =
+            CString::new(r#"{"title": "FFI note", "content": "hello", "tags": ["ffi"]}"#).unwrap();
+        let mut out = ptr::null_mut();
+        check(kbnotes_save_note(vault, input.as_ptr(), &mut out), "save");
+        let note = take_json(out);
+        let id = note["id"].as_str().unwrap().to_string();
+
+        let event: serde_json::Value = serde_json::from_str(
+            &events
+                .recv_timeout(Duration::from_secs(5))
+                .expect("no change event"),
+        )
+        .unwrap();
+        assert_eq!(event["type"], "created");
+        assert_eq!(event["note_id"], id.as_str());
+
+        let tag = CString::new("ffi").unwrap();
+        check(kbnotes_list_notes(vault, tag.as_ptr(), &mut out), "list");
+        assert_eq!(take_json(out).as_array().unwrap().len(), 1);
+
+        let query = CString::new("FFI").unwrap();
+        check(
+            kbnotes_search_notes(vault, query.as_ptr(), &mut out),
+            "search",
+        );
+        assert_eq!(take_json(out)[0]["id"], id.as_str());
+
+        let c_id = CString::new(id.as_str()).unwrap();
+        check(kbnotes_get_note(vault, c_id.as_ptr(), &mut out), "get");
+        assert_eq!(take_json(out)["content"], "hello");
+
+        check(kbnotes_delete_note(vault, c_id.as_ptr()), "delete");
+        let status = kbnotes_get_note(vault, c_id.as_ptr(), &mut out);
+        assert_eq!(status, KbnotesStatus::NotFound);
+        assert!(!CStr::from_ptr(kbnotes_last_error())
+            .to_string_lossy()
+            .is_empty());
+
+        check(kbnotes_unsubscribe(subscription), "unsubscribe");
+        check(kbnotes_vault_close(vault), "close");
+    }
+}
@@ -0,0 +1,97 @@
+//! Checks that each `fsync` mode syncs note writes when documented: `always`
+//! on every save (and each directory once per batch save), `on_close` once
+//! at shutdown and `never` not at all, and that `kbnotes status` reports the
+//! mode.
+//!
+//! Run with `cargo test --test fsync_durability`.
+mod common;
+
+use std::{path::Path, sync::Arc};
+
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+fn storage(root: &Path, mode: FsyncMode) -> NoteStorage {
+    let mut config = common::config(root);
+    config.fsync = mode;
+    common::open(config)
+}
+
+fn save_two(storage: &NoteStorage) {
+    let first = Note::new("First".to_string(), "One".to_string(), vec![]);
+    let second = Note::new("Second".to_string(), "Two".to_string(), vec![]);
+    storage.save_note(&first).unwrap();
+    storage.save_note(&second).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fsync_durability() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+
+    // Always syncs each file before the rename and its directory after it
+    let always = storage(&dir.path().join("always"), FsyncMode::Always);
+    save_two(&always);
+    let stats = always.durability_stats();
+    assert_eq!(stats.mode, FsyncMode::Always);
+    // At least the two note files; sidecar indexes are written the same way
+    assert!(stats.synced_files >= 2, "{:?}", stats);
+    assert_eq!(stats.synced_files, stats.synced_directories);
+    assert_eq!(stats.pending_syncs, 0);
+
+    // A batch syncs each prefix directory once, not once per note
+    let before = always.durability_stats();
+    let notes: Vec<Note> = (0..20)
+        .map(|i| {
+            let mut note = Note::new(format!("Batch {}", i), "Body".to_string(), vec![]);
+            note.id = format!("{}{}", if i % 2 == 0 { "aa" } else { "bb" }, i);
+            note
+        })
+        .collect();
+    let report = always.save_notes(&notes).unwrap();
+    assert_eq!(report.succeeded.len(), notes.len());
+    let stats = always.durability_stats();
+    let files = stats.synced_files - before.synced_files;
+    let directories = stats.synced_directories - before.synced_directories;
+    // 20 note files in 2 directories; sidecar indexes sync one each
+    assert!(files >= 20, "{:?}", stats);
+    assert_eq!(files - directories, 18, "{:?}", stats);
+
+    // OnClose only records the writes until shutdown syncs them
+    let mut on_close = storage(&dir.path().join("on_close"), FsyncMode::OnClose);
+    save_two(&on_close);
+    let stats = on_close.durability_stats();
+    assert_eq!((stats.synced_files, stats.synced_directories), (0, 0));
+    assert!(stats.pending_syncs >= 2, "{:?}", stats);
+    on_close.shutdown().await.unwrap();
+    let stats = on_close.durability_stats();
+    assert_eq!(stats.pending_syncs, 0);
+    assert!(stats.synced_files >= 2, "{:?}", stats);
+    assert!(stats.synced_directories >= 1, "{:?}", stats);
+
+    // Never leaves syncing to the operating system
+    let never = storage(&dir.path().join("never"), FsyncMode::Never);
+    save_two(&never);
+    let stats = never.durability_stats();
+    assert_eq!(
+        (
+            stats.synced_files,
+            stats.synced_directories,
+            stats.pending_syncs
+        ),
+        (0, 0, 0)
+    );
+
+    // status prints the mode in text and JSON
+    let config = {
+        let mut config = Config::with_dirs(
+            dir.path().join("always").join("notes"),
+            dir.path().join("always").join("backups"),
+        );
+        config.auto_backup = false;
+        config
+    };
+    let app = common::app(Arc::new(Mutex::new(always)), config);
+    for args in [&["status"][..], &["status", "--json"]] {
+        common::run(&app, args).await.expect("status failed");
+    }
+}
@@ -0,0 +1,154 @@
+//! Checks that note IDs with slashes, dots, unicode or great length map to
+//! safe, stable file names, and that storage, the file watcher and full
+//! backups find those notes again.
+//!
+//! Run with `cargo test --test id_sanitization`.
+mod common;
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use kbnotes::*;
+use tokio::sync::broadcast;
+use walkdir::WalkDir;
+
+fn vault(root: &Path) -> NoteStorage {
+    let mut config = common::config(root);
+    config.id_policy = IdPolicy::Standard;
+    common::open(config)
+}
+
+/// Files below a directory, leaving out storage state such as `.state/` and
+/// revision history
+fn files_in(dir: &Path) -> Vec<std::path::PathBuf> {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|entry| {
+            ![STATE_DIR, HISTORY_DIR, TRASH_DIR].contains(&&*entry.file_name().to_string_lossy())
+        })
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.path().to_path_buf())
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn id_sanitization() {
+    let long = "x".repeat(1000);
+    let long_unicode = "日本語のメモ/".repeat(40);
+    let ids = [
+        "foo/bar".to_string(),
+        "a\\b".to_string(),
+        "..".to_string(),
+        "../../etc/passwd".to_string(),
+        ".hidden".to_string(),
+        "ünïcødé 日本".to_string(),
+        "100%".to_string(),
+        long.clone(),
+        long_unicode.clone(),
+    ];
+
+    for policy in [
+        IdPolicy::Standard,
+        IdPolicy::StrictAscii,
+        IdPolicy::Permissive,
+    ] {
+        let policy = policy.policy();
+        for id in &ids {
+            let name = policy.sanitize(id);
+            assert!(!name.contains('/') && !name.contains('\\') && !name.starts_with('.'));
+            assert!(name.len() <= MAX_NAME_BYTES, "{} is too long", name);
+            assert_eq!(policy.sanitize(id), name, "names are stable");
+            if id.len() < 60 {
+                assert_eq!(policy.restore(&name).as_deref(), Some(id.as_str()));
+            }
+        }
+        // Long IDs are shortened, and still told apart by their hash
+        let shortened = policy.sanitize(&long);
+        assert!(policy.restore(&shortened).is_none());
+        assert_ne!(shortened, policy.sanitize(&format!("{}y", long)));
+    }
+
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let root = dir.path().join("source");
+    let storage = vault(&root);
+    let notes: Vec<Note> = ids
+        .iter()
+        .map(|id| {
+            let mut note = Note::new(id.chars().take(40).collect(), "body".to_string(), vec![]);
+            note.id = id.clone();
+            note
+        })
+        .collect();
+    for note in &notes {
+        storage.save_note(note).unwrap();
+        // Updates write backups named after the ID as well
+        let mut updated = note.clone();
+        updated.content = "updated".to_string();
+        updated.updated_at = chrono::Utc::now();
+        storage.update_note(updated).unwrap();
+    }
+
+    let notes_dir = root.join("notes");
+    let note_files = files_in(&notes_dir);
+    assert_eq!(note_files.len(), notes.len());
+    for file in note_files
+        .iter()
+        .chain(files_in(&root.join("backups")).iter())
+    {
+        assert!(file.starts_with(&root), "{} escaped", file.display());
+    }
+    let reloaded = vault(&root);
+    for note in &notes {
+        assert_eq!(reloaded.get_note(&note.id).unwrap().content, "updated");
+    }
+    assert!(reloaded.check_layout().unwrap().is_empty());
+
+    // A deleted file is mapped back to its note, even with a shortened name
+    let cache = Arc::new(RwLock::new(HashMap::from([(
+        long.clone(),
+        notes[7].clone(),
+    )])));
+    let (events, mut received) = broadcast::channel(4);
+    let long_file = note_files
+        .iter()
+        .find(|path| path.to_string_lossy().contains("%h"))
+        .unwrap()
+        .clone();
+    handle_fs_event(
+        notify::Event::new(notify::EventKind::Remove(notify::event::RemoveKind::File))
+            .add_path(long_file),
+        &cache,
+        &Arc::new(Mutex::new(AliasIndex::new())),
+        &Arc::new(Mutex::new(TitleIndex::new())),
+        &Arc::new(Mutex::new(TagIndex::new())),
+        &Arc::new(Mutex::new(StructureIndex::new())),
+        &Arc::new(Mutex::new(LinkIndex::default())),
+        &Arc::new(Mutex::new(None)),
+        &events,
+        &StandardPolicy,
+        &notes_dir,
+        &OwnChanges::new(notes_dir.clone()),
+    )
+    .await;
+    assert!(cache.read().unwrap().is_empty());
+    assert_eq!(received.try_recv().unwrap().note_id, long);
+
+    // Full backups name entries the same way and restore every note
+    let backup = dir.path().join("backup.zip");
+    storage.create_full_backup_to(&backup).unwrap();
+    let target = vault(&dir.path().join("target"));
+    let summary = target
+        .restore_full_backup(&backup, &RestoreOptions::default())
+        .unwrap();
+    assert_eq!(summary.notes_restored, notes.len());
+    assert!(summary.failed_notes.is_empty());
+    for note in &notes {
+        assert!(target.get_note(&note.id).is_some());
+    }
+    storage.delete_note(&long).unwrap();
+    assert_eq!(files_in(&notes_dir).len(), notes.len() - 1);
+}
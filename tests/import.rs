@@ -0,0 +1,131 @@
+//! Checks `kbnotes import` for a single file and for directories filtered by
+//! a glob pattern, with and without recursion, and for JSONL files whose
+//! first line is malformed.
+//!
+//! Run with `cargo test --test import`.
+mod common;
+
+use std::{fs, path::Path, sync::Arc};
+
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+fn write(path: &Path, content: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+async fn titles(storage: &Mutex<NoteStorage>) -> Vec<String> {
+    let mut titles: Vec<String> = storage
+        .lock()
+        .await
+        .get_all_notes()
+        .unwrap()
+        .into_iter()
+        .map(|note| note.title)
+        .collect();
+    titles.sort();
+    titles
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn import() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let source = dir.path().join("source");
+    write(&source.join("alpha.md"), "# Alpha\n\nTop-level note");
+    write(&source.join("readme.txt"), "Not Markdown");
+    write(&source.join("sub").join("beta.md"), "# Beta\n\nNested note");
+    write(
+        &source.join("sub").join("deeper").join("gamma.md"),
+        "No heading here",
+    );
+    write(&source.join("sub").join("skip.txt"), "Not Markdown either");
+
+    let config = common::config(dir.path());
+    let storage = Arc::new(Mutex::new(common::open(config.clone())));
+    let app = common::app(Arc::clone(&storage), config);
+
+    // A single file, titled after its H1 heading and tagged from the command line
+    let alpha = source.join("alpha.md");
+    common::run(
+        &app,
+        &[
+            "import",
+            "-p",
+            alpha.to_str().unwrap(),
+            "-g",
+            "imported,docs",
+        ],
+    )
+    .await
+    .expect("import failed");
+    let notes = storage.lock().await.get_all_notes().unwrap();
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0].title, "Alpha");
+    assert_eq!(notes[0].tags, ["imported", "docs"]);
+    assert_eq!(notes[0].metadata["import_format"], "markdown");
+
+    // Without --recursive only the top level is searched
+    let source_arg = source.to_str().unwrap();
+    common::run(
+        &app,
+        &["import", "-p", source_arg, "-f", "md", "--pattern", "*.md"],
+    )
+    .await
+    .expect("import failed");
+    assert_eq!(titles(&storage).await, ["Alpha", "Alpha"]);
+
+    // With --recursive the pattern applies below every subdirectory, and the
+    // untagged Alpha imported above is skipped as a duplicate
+    common::run(
+        &app,
+        &["import", "-p", source_arg, "-r", "--pattern", "*.MD"],
+    )
+    .await
+    .expect("import failed");
+    assert_eq!(
+        titles(&storage).await,
+        ["Alpha", "Alpha", "Beta", "gamma.md"]
+    );
+
+    // Text files are picked up by their own pattern and keep their file name
+    common::run(
+        &app,
+        &[
+            "import",
+            "-p",
+            source_arg,
+            "-r",
+            "-f",
+            "text",
+            "--pattern",
+            "sub/*.txt",
+        ],
+    )
+    .await
+    .expect("import failed");
+    assert!(titles(&storage).await.contains(&"skip.txt".to_string()));
+    assert!(!titles(&storage).await.contains(&"readme.txt".to_string()));
+
+    // A malformed first JSONL line fails on its own instead of sending the
+    // whole file down the pretty-printed object path
+    let jsonl = dir.path().join("lines.jsonl");
+    write(
+        &jsonl,
+        concat!(
+            "{\"title\": \"Broken\" \"content\": \"missing comma\"}\n",
+            "{\"title\": \"Line one\", \"content\": \"First\"}\n",
+            "{\"title\": \"Line two\", \"content\": \"Second\"}\n",
+        ),
+    );
+    common::run(
+        &app,
+        &["import", "-p", jsonl.to_str().unwrap(), "-f", "json"],
+    )
+    .await
+    .expect("import failed");
+    let imported = titles(&storage).await;
+    assert!(imported.contains(&"Line one".to_string()));
+    assert!(imported.contains(&"Line two".to_string()));
+    assert!(!imported.contains(&"Broken".to_string()));
+}
@@ -0,0 +1,108 @@
+//! Checks that an interrupted directory import resumes from its journal
+//! without duplicating notes, and that re-running a finished import adds
+//! nothing.
+//!
+//! The interruption is injected: one file is unreadable during the first run,
+//! and the journal is then cut back as if the process had been killed while
+//! writing it.
+//!
+//! Run with `cargo test --test import_resume`.
+mod common;
+
+use std::{fs, path::Path, sync::Arc};
+
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+const FILES: usize = 30;
+
+async fn note_count(storage: &Mutex<NoteStorage>) -> usize {
+    storage.lock().await.get_all_notes().unwrap().len()
+}
+
+fn journal_path(config: &Config, source: &Path) -> std::path::PathBuf {
+    ImportJournal::journal_path(
+        &config.notes_dir.join(STATE_DIR),
+        &source.canonicalize().unwrap(),
+        "markdown",
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn import_resume() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let source = dir.path().join("source");
+    fs::create_dir_all(&source).unwrap();
+    for i in 1..=FILES {
+        let content = format!("# Note {}\n\nBody of note {}", i, i);
+        fs::write(source.join(format!("note-{:02}.md", i)), content).unwrap();
+    }
+    // Injected failure: not valid UTF-8
+    fs::write(source.join("note-22.md"), [0xff, 0xfe, 0x00]).unwrap();
+
+    let config = common::config(dir.path());
+    let storage = Arc::new(Mutex::new(common::open(config.clone())));
+    let app = common::app(Arc::clone(&storage), config.clone());
+    let source_arg = source.to_str().unwrap();
+
+    // --resume needs an unfinished import
+    assert!(common::run(&app, &["import", "-p", source_arg, "--resume"])
+        .await
+        .is_err());
+
+    // First run: every file but the broken one, and the journal is kept
+    common::run(&app, &["import", "-p", source_arg])
+        .await
+        .unwrap();
+    assert_eq!(note_count(&storage).await, FILES - 1);
+    let journal = journal_path(&config, &source);
+    let lines: Vec<String> = fs::read_to_string(&journal)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect();
+    assert_eq!(lines.len(), FILES + 1, "header plus one entry per file");
+
+    // Pretend the process died after journaling ten files, halfway through
+    // writing the eleventh entry
+    let mut cut = lines[..11].join("\n");
+    cut.push('\n');
+    cut.push_str(&lines[11][..lines[11].len() / 2]);
+    fs::write(&journal, cut).unwrap();
+
+    // Resuming imports the fixed file; the notes created after the last
+    // journaled file are recognized as duplicates
+    fs::write(source.join("note-22.md"), "# Note 22\n\nBody of note 22").unwrap();
+    common::run(&app, &["import", "-p", source_arg])
+        .await
+        .unwrap();
+    assert_eq!(note_count(&storage).await, FILES);
+    assert!(!journal.exists(), "journal removed after a complete import");
+
+    // A finished import is a no-op
+    common::run(&app, &["import", "-p", source_arg])
+        .await
+        .unwrap();
+    assert_eq!(note_count(&storage).await, FILES);
+    assert!(!journal.exists());
+
+    // ...unless forced
+    common::run(&app, &["import", "-p", source_arg, "--force-reimport"])
+        .await
+        .unwrap();
+    assert_eq!(note_count(&storage).await, 2 * FILES);
+
+    // A journal left by a directory that is gone is purged
+    let gone = dir.path().join("gone");
+    fs::create_dir_all(&gone).unwrap();
+    fs::write(gone.join("broken.md"), [0xff]).unwrap();
+    common::run(&app, &["import", "-p", gone.to_str().unwrap()])
+        .await
+        .unwrap();
+    let gone_journal = journal_path(&config, &gone);
+    assert!(gone_journal.exists());
+    fs::remove_dir_all(&gone).unwrap();
+    let state_dir = config.notes_dir.join(STATE_DIR);
+    assert_eq!(purge_import_journals(&state_dir, false).unwrap(), 1);
+    assert!(!gone_journal.exists());
+}
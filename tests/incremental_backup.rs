@@ -0,0 +1,191 @@
+//! Checks incremental backups: scheduled runs write a full backup followed by
+//! incremental ones holding only the notes written since the last backup,
+//! whatever their `updated_at`, restoring an incremental backup applies its
+//! chain in order so a vault state that only exists as a full backup plus
+//! two incrementals comes back, `backup list` shows the chain, and removing a
+//! full backup removes the incremental backups built on it.
+//!
+//! Run with `cargo test --test incremental_backup`.
+mod common;
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+fn edit(storage: &NoteStorage, id: &str, content: &str) {
+    let mut note = storage.get_note(id).unwrap();
+    note.content = content.to_string();
+    note.updated_at = chrono::Utc::now();
+    storage.update_note(note).unwrap();
+}
+
+/// Every note of the vault by ID
+fn vault(storage: &NoteStorage) -> BTreeMap<String, Note> {
+    storage
+        .get_all_notes()
+        .unwrap()
+        .into_iter()
+        .map(|note| (note.id.clone(), note))
+        .collect()
+}
+
+fn open_storage(root: &Path, backups: &Path) -> NoteStorage {
+    let mut config = Config::with_dirs(root.join("notes"), backups.to_path_buf());
+    config.auto_backup = false;
+    config.fsync = FsyncMode::Never;
+    config.incremental_backups = true;
+    config.full_backup_every = 3;
+    common::open(config)
+}
+
+/// Restores `backup` into an empty vault and returns its notes
+fn restore_into_empty_vault(backup: &Path, backups: &Path) -> BTreeMap<String, Note> {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let storage = open_storage(dir.path(), backups);
+    let summary = storage
+        .restore_full_backup(backup, &RestoreOptions::default())
+        .unwrap();
+    assert!(
+        summary.failed_notes.is_empty(),
+        "{:?}",
+        summary.failed_notes
+    );
+    let preview = storage.preview_full_backup(backup).unwrap();
+    assert_eq!(preview.total_notes, summary.notes_restored);
+    vault(&storage)
+}
+
+/// Backup file names use whole seconds
+async fn next_second() {
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn incremental_backup() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let backups = dir.path().join("backups");
+    let storage = open_storage(dir.path(), &backups);
+
+    // Without a full backup to build on, an incremental one is refused and
+    // a scheduled run writes a full backup
+    assert!(storage.create_incremental_backup().is_err());
+    for id in ["alpha", "beta", "gamma"] {
+        storage.save_note(&common::note(id, "v1")).unwrap();
+    }
+    let full = storage.create_scheduled_backup().unwrap();
+    let full_name = full.file_name().unwrap().to_string_lossy().into_owned();
+    assert!(full_name.starts_with("kbnotes_backup_"), "{}", full_name);
+    assert!(backups.join(BACKUP_STATE_FILE).exists());
+
+    // First increment: an update, a new note and a deletion
+    next_second().await;
+    edit(&storage, "alpha", "v2");
+    storage.save_note(&common::note("delta", "v1")).unwrap();
+    storage.delete_note("beta").unwrap();
+    let first = storage.create_scheduled_backup().unwrap();
+    let after_first = vault(&storage);
+
+    // Second increment: two more updates
+    next_second().await;
+    edit(&storage, "alpha", "v3");
+    edit(&storage, "gamma", "v2");
+    // An imported note keeps its old updated_at, but was written since the
+    // last backup all the same
+    let mut imported = common::note("epsilon", "v1");
+    imported.updated_at = "2020-01-01T00:00:00Z".parse().unwrap();
+    imported.created_at = imported.updated_at;
+    storage.save_note(&imported).unwrap();
+    let second = storage.create_scheduled_backup().unwrap();
+    let after_second = vault(&storage);
+
+    for (path, changed) in [
+        (&first, &["alpha", "delta"][..]),
+        (&second, &["alpha", "epsilon", "gamma"]),
+    ] {
+        let name = path.file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with("kbnotes_incremental_"), "{}", name);
+        let manifest = storage.read_backup_manifest(path).unwrap().unwrap();
+        assert_eq!(manifest.notes, changed.len());
+        let chain = manifest.incremental.unwrap();
+        assert_eq!(chain.base, full_name);
+        assert!(!chain.note_ids.contains(&"beta".to_string()));
+    }
+
+    // The state after the second increment only exists as the full backup
+    // plus both incrementals
+    edit(&storage, "delta", "v2");
+    assert_eq!(restore_into_empty_vault(&second, &backups), after_second);
+    assert_eq!(restore_into_empty_vault(&first, &backups), after_first);
+    assert_eq!(
+        restore_into_empty_vault(&second, &backups)["alpha"].content,
+        "v3"
+    );
+    assert!(!restore_into_empty_vault(&second, &backups).contains_key("beta"));
+    assert_eq!(
+        restore_into_empty_vault(&second, &backups)["epsilon"].updated_at,
+        imported.updated_at
+    );
+    assert_eq!(restore_into_empty_vault(&full, &backups).len(), 3);
+
+    // A staged restore needs a full backup
+    let dir2 = tempfile::tempdir().expect("failed to create temporary vault");
+    let mut staged = open_storage(dir2.path(), &backups);
+    assert!(staged.restore_full_backup_staged(&second).await.is_err());
+
+    // The third run completes the chain with a new full backup
+    next_second().await;
+    let next_full = storage.create_scheduled_backup().unwrap();
+    assert!(next_full
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .starts_with("kbnotes_backup_"));
+
+    // The listing shows the chain: each incremental backup names its base
+    let archives = storage.list_backups(true).unwrap().archives;
+    let listed: Vec<(PathBuf, bool, Option<PathBuf>)> = archives
+        .iter()
+        .map(|archive| {
+            (
+                archive.path.clone(),
+                archive.incremental,
+                archive.base.clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        listed,
+        [
+            (next_full.clone(), false, None),
+            (second.clone(), true, Some(full.clone())),
+            (first.clone(), true, Some(full.clone())),
+            (full.clone(), false, None),
+        ]
+    );
+    assert_eq!(archives[1].notes, Some(3));
+    let storage = Arc::new(Mutex::new(storage));
+    let mut config = common::config(dir.path());
+    config.fsync = FsyncMode::Never;
+    let app = App::new(
+        Arc::clone(&storage),
+        config.clone(),
+        ConfigOrigin::default(),
+        true,
+        true,
+    );
+    common::run(&app, &["backup", "list"]).await.unwrap();
+
+    // Once its full backup is pruned, a chain's incremental backups go too
+    config.max_backups = 1;
+    let pruning = common::open(config);
+    next_second().await;
+    pruning.create_full_backup().unwrap();
+    assert!(!full.exists() && !first.exists() && !second.exists());
+    assert_eq!(pruning.list_backups(false).unwrap().archives.len(), 1);
+}
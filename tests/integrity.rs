@@ -0,0 +1,124 @@
+//! Checks `verify_integrity` against a vault seeded with an unreadable file,
+//! a note in the wrong prefix directory, a file named after another ID and a
+//! cached note whose file is gone, and that `kbnotes doctor --fix` repairs
+//! them, then checks that alias collisions are reported. Quarantining and
+//! recovery are covered by `tests/quarantine.rs`.
+//!
+//! Run with `cargo test --test integrity`.
+mod common;
+
+use std::{fs, sync::Arc};
+
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn integrity() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let notes = dir.path().join("notes");
+    let config = common::config(dir.path());
+    let storage = common::open(config.clone());
+    for id in ["alpha", "bravo", "charlie", "delta"] {
+        let mut note = Note::new(id.to_string(), String::new(), vec![]);
+        note.id = id.to_string();
+        storage.save_note(&note).unwrap();
+    }
+    assert!(storage.verify_integrity().unwrap().is_clean());
+
+    // One of each inconsistency
+    fs::create_dir_all(notes.join("zz")).unwrap();
+    fs::write(notes.join("zz/zzbroken.json"), "{ not a note").unwrap();
+    fs::create_dir_all(notes.join("xx")).unwrap();
+    fs::rename(notes.join("br/bravo.json"), notes.join("xx/bravo.json")).unwrap();
+    fs::rename(notes.join("ch/charlie.json"), notes.join("ch/wrong.json")).unwrap();
+    fs::remove_file(notes.join("de/delta.json")).unwrap();
+
+    let report = storage.verify_integrity().unwrap();
+    assert!(!report.is_clean());
+    assert_eq!(report.unparsable.len(), 1);
+    assert_eq!(report.unparsable[0].0, notes.join("zz/zzbroken.json"));
+    assert_eq!(report.missing_files, ["delta"]);
+    let mut layout: Vec<_> = report
+        .layout
+        .iter()
+        .map(|issue| {
+            (
+                issue.note_id.as_str(),
+                issue.is_name_mismatch(),
+                issue.collides_with.is_none(),
+            )
+        })
+        .collect();
+    layout.sort();
+    assert_eq!(layout, [("bravo", false, true), ("charlie", true, true)]);
+    let bravo = report.layout.iter().find(|i| i.note_id == "bravo").unwrap();
+    assert_eq!(bravo.path, notes.join("xx/bravo.json"));
+    assert_eq!(bravo.expected, notes.join("br/bravo.json"));
+
+    // Without --fix nothing changes
+    let storage = Arc::new(Mutex::new(storage));
+    let app = common::app(Arc::clone(&storage), config);
+    common::run(&app, &["doctor"]).await.unwrap();
+    assert!(notes.join("zz/zzbroken.json").exists());
+    assert_eq!(
+        storage
+            .lock()
+            .await
+            .verify_integrity()
+            .unwrap()
+            .layout
+            .len(),
+        2
+    );
+
+    // --fix re-files notes, quarantines the broken file and reloads the cache
+    common::run(&app, &["doctor", "--fix"]).await.unwrap();
+    let storage = storage.lock().await;
+    assert!(storage.verify_integrity().unwrap().is_clean());
+    assert!(!notes.join("zz/zzbroken.json").exists());
+    let quarantined = storage.list_quarantined().unwrap();
+    assert_eq!(quarantined.len(), 1);
+    assert_eq!(quarantined[0].original, notes.join("zz/zzbroken.json"));
+    assert!(quarantined[0].path.starts_with(notes.join(CORRUPT_DIR)));
+    assert_eq!(
+        fs::read_to_string(&quarantined[0].path).unwrap(),
+        "{ not a note"
+    );
+    assert!(notes.join("br/bravo.json").exists());
+    assert!(!notes.join("xx").exists());
+    assert!(notes.join("ch/charlie.json").exists());
+    assert_eq!(storage.get_note("charlie").unwrap().title, "charlie");
+    assert!(storage.get_note("delta").is_none());
+    assert!(storage.get_note("alpha").is_some());
+
+    // Two notes claiming one alias, and an alias equal to another note's ID
+    let set_aliases = |id: &str, aliases: &[&str]| {
+        let mut note = storage.get_note(id).unwrap();
+        note.aliases = aliases.iter().map(|alias| alias.to_string()).collect();
+        storage.update_note(note).unwrap();
+    };
+    set_aliases("alpha", &["Shared"]);
+    set_aliases("bravo", &["shared", "old-bravo"]);
+    set_aliases("charlie", &["ALPHA"]);
+    let report = storage.verify_integrity().unwrap();
+    assert!(!report.is_clean());
+    assert_eq!(
+        report.alias_collisions,
+        [
+            AliasCollision {
+                alias: "alpha".to_string(),
+                note_ids: vec!["charlie".to_string()],
+                shadowed_by: Some("alpha".to_string()),
+            },
+            AliasCollision {
+                alias: "shared".to_string(),
+                note_ids: vec!["alpha".to_string(), "bravo".to_string()],
+                shadowed_by: None,
+            },
+        ]
+    );
+
+    set_aliases("alpha", &[]);
+    set_aliases("charlie", &[]);
+    assert!(storage.verify_integrity().unwrap().is_clean());
+}
@@ -0,0 +1,143 @@
+//! Checks the `[[wiki-link]]` index behind backlinks and outgoing links, and
+//! that it follows notes across saves, deletes, watcher reloads and restarts
+//! without rewriting its file when nothing changed.
+//!
+//! Run with `cargo test --test links`.
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use kbnotes::*;
+use tokio::sync::broadcast;
+
+fn backlinks(storage: &NoteStorage, id: &str) -> Vec<String> {
+    storage
+        .get_backlinks(id)
+        .unwrap()
+        .into_iter()
+        .map(|note| note.title)
+        .collect()
+}
+
+fn note_path(notes_dir: &Path, id: &str) -> PathBuf {
+    walkdir::WalkDir::new(notes_dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.into_path())
+        .find(|path| path.file_name().unwrap() == format!("{}.json", id).as_str())
+        .expect("note file not found")
+}
+
+async fn wait_for(events: &mut broadcast::Receiver<NoteEvent>, kind: NoteEventKind) {
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(10), events.recv())
+            .await
+            .expect("watcher did not report the external change")
+            .unwrap();
+        if event.external && event.kind == kind {
+            return;
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn links() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let notes_dir = dir.path().join("notes");
+    let config = common::config(dir.path());
+
+    let storage = common::start(config).await;
+    let mut storage = storage.lock().await;
+
+    let mut hub = Note::new("Hub".to_string(), "The index".to_string(), vec![]);
+    hub.aliases.push("Home".to_string());
+    storage.save_note(&hub).unwrap();
+    let by_title = Note::new("Alpha".to_string(), "Back to [[hub]].".to_string(), vec![]);
+    let by_id = Note::new(
+        "Beta".to_string(),
+        format!("See [[{}]] and [[Nowhere]].", hub.id),
+        vec![],
+    );
+    let by_alias = Note::new("Gamma".to_string(), "Start at [[Home]]".to_string(), vec![]);
+    for note in [&by_title, &by_id, &by_alias] {
+        storage.save_note(note).unwrap();
+    }
+
+    assert_eq!(backlinks(&storage, &hub.id), ["Alpha", "Beta", "Gamma"]);
+    assert!(backlinks(&storage, &by_title.id).is_empty());
+    let outgoing = storage.get_outgoing_links(&by_id.id).unwrap();
+    assert_eq!(outgoing.len(), 2);
+    assert_eq!(outgoing[0].note_id.as_deref(), Some(hub.id.as_str()));
+    assert_eq!(outgoing[1].target, "Nowhere");
+    assert_eq!(outgoing[1].note_id, None);
+
+    // A dangling link resolves once its target exists
+    let nowhere = Note::new("Nowhere".to_string(), String::new(), vec![]);
+    storage.save_note(&nowhere).unwrap();
+    assert_eq!(backlinks(&storage, &nowhere.id), ["Beta"]);
+    assert_eq!(
+        storage.get_outgoing_links(&by_id.id).unwrap()[1]
+            .note_id
+            .as_deref(),
+        Some(nowhere.id.as_str())
+    );
+
+    // Removing a link or deleting the linking note drops the backlink
+    let mut edited = by_alias.clone();
+    edited.content = "No links here".to_string();
+    storage.save_note(&edited).unwrap();
+    storage.delete_note(&by_title.id).unwrap();
+    assert_eq!(backlinks(&storage, &hub.id), ["Beta"]);
+
+    // Changes made outside kbnotes are picked up by the watcher
+    let mut events = storage.subscribe_events();
+    let mut external = storage.get_note(&nowhere.id).unwrap();
+    external.content = "Part of [[Home]]".to_string();
+    external.updated_at = chrono::Utc::now();
+    fs::write(
+        note_path(&notes_dir, &nowhere.id),
+        serde_json::to_string_pretty(&external).unwrap(),
+    )
+    .unwrap();
+    wait_for(&mut events, NoteEventKind::Updated).await;
+    assert_eq!(backlinks(&storage, &hub.id), ["Beta", "Nowhere"]);
+
+    fs::remove_file(note_path(&notes_dir, &by_id.id)).unwrap();
+    wait_for(&mut events, NoteEventKind::Deleted).await;
+    assert_eq!(backlinks(&storage, &hub.id), ["Nowhere"]);
+    assert!(storage.get_outgoing_links(&by_id.id).unwrap().is_empty());
+
+    // Reloading rebuilds the index and writes it to the state directory
+    storage.stop_watcher().await.unwrap();
+    let mut offline = storage.get_note(&edited.id).unwrap();
+    offline.content = "Linked again: [[Hub]]".to_string();
+    offline.updated_at = chrono::Utc::now();
+    fs::write(
+        note_path(&notes_dir, &edited.id),
+        serde_json::to_string_pretty(&offline).unwrap(),
+    )
+    .unwrap();
+    storage.load_notes().unwrap();
+    assert_eq!(backlinks(&storage, &hub.id), ["Gamma", "Nowhere"]);
+    let saved: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(notes_dir.join(STATE_DIR).join(LINK_INDEX_FILE)).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(saved[&edited.id], serde_json::json!(["Hub"]));
+    assert!(saved.get(&hub.id).is_none());
+
+    // Loading or shutting down without link changes leaves the file alone
+    let links_file = notes_dir.join(STATE_DIR).join(LINK_INDEX_FILE);
+    let written = fs::metadata(&links_file).unwrap().modified().unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    storage.load_notes().unwrap();
+    storage.shutdown().await.unwrap();
+    assert_eq!(
+        fs::metadata(&links_file).unwrap().modified().unwrap(),
+        written
+    );
+}
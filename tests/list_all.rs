@@ -0,0 +1,87 @@
+//! Checks that `list` without filters selects every note: an empty filter
+//! matches all of them, `get_all_notes` returns the whole cache, and a bare
+//! `kbnotes list` prints every note, as text and as JSON.
+//!
+//! Run with `cargo test --test list_all`.
+mod common;
+
+use std::{path::Path, process::Command};
+
+use clap::Parser;
+use kbnotes::*;
+
+fn titles(notes: Vec<Note>) -> Vec<String> {
+    let mut titles: Vec<String> = notes.into_iter().map(|note| note.title).collect();
+    titles.sort();
+    titles
+}
+
+/// Runs the `kbnotes` binary against the vault under `root` and returns what
+/// it printed
+fn kbnotes(root: &Path, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_kbnotes"))
+        .env("HOME", root)
+        .arg("--notes-dir")
+        .arg(root.join("notes"))
+        .arg("--backup-dir")
+        .arg(root.join("backups"))
+        .args(args)
+        .output()
+        .expect("failed to run kbnotes");
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn list_all() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let storage = common::vault(dir.path());
+    for (title, tags) in [
+        ("Alpha", vec!["work"]),
+        ("Beta", vec![]),
+        ("Gamma", vec!["home"]),
+    ] {
+        let tags = tags.into_iter().map(str::to_string).collect();
+        storage
+            .save_note(&Note::new(title.to_string(), String::new(), tags))
+            .unwrap();
+    }
+
+    // The no-filter case selects everything, tagged or not
+    assert_eq!(
+        titles(storage.query_notes(NoteFilter::default()).unwrap()),
+        ["Alpha", "Beta", "Gamma"]
+    );
+    assert_eq!(
+        titles(storage.get_all_notes().unwrap()),
+        ["Alpha", "Beta", "Gamma"]
+    );
+
+    // A bare `list` carries no filters and lists them
+    let Commands::List(options) = Cli::try_parse_from(["kbnotes", "list"]).unwrap().command else {
+        panic!("expected the list command");
+    };
+    assert!(options.tags.is_empty() && options.search.is_none());
+    assert!(options.limit >= 3);
+    drop(storage);
+
+    let text = kbnotes(dir.path(), &["list"]);
+    for title in ["Alpha", "Beta", "Gamma"] {
+        assert!(text.contains(&format!("Title: {}\n", title)), "{}", text);
+    }
+    assert!(text.contains("Found 3 notes"), "{}", text);
+
+    let json = kbnotes(dir.path(), &["list", "--format", "json"]);
+    // The JSON array comes first, followed by the note count
+    let listed: Vec<serde_json::Value> = serde_json::Deserializer::from_str(&json)
+        .into_iter()
+        .next()
+        .unwrap()
+        .unwrap();
+    let mut titles: Vec<&str> = listed
+        .iter()
+        .map(|note| note["title"].as_str().unwrap())
+        .collect();
+    titles.sort();
+    assert_eq!(titles, ["Alpha", "Beta", "Gamma"]);
+}
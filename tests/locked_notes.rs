@@ -0,0 +1,123 @@
+//! Checks that locked notes survive edits, deletion and bulk operations
+//! untouched, that forced changes and unlocking still work, and that the file
+//! watcher keeps refreshing locked notes from disk.
+//!
+//! Run with `cargo test --test locked_notes`.
+mod common;
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use kbnotes::*;
+use tokio::sync::broadcast;
+use walkdir::WalkDir;
+
+fn vault(root: &Path) -> NoteStorage {
+    let mut config = common::config(root);
+    config.tags.policies.insert(
+        "scratch".to_string(),
+        TagPolicy {
+            auto_archive_after_days: Some(0),
+            ..TagPolicy::default()
+        },
+    );
+    common::open(config)
+}
+
+fn assert_locked<T: std::fmt::Debug>(result: Result<T>, id: &str) {
+    match result {
+        Err(KbError::NoteLocked { id: locked }) => assert_eq!(locked, id),
+        other => panic!("expected NoteLocked for {}, got {:?}", id, other),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn locked_notes() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let root = dir.path();
+
+    let storage = vault(root);
+    let locked = Note::new(
+        "Contract".to_string(),
+        "- [ ] sign\n".to_string(),
+        vec!["scratch".to_string()],
+    );
+    let open = Note::new(
+        "Draft".to_string(),
+        String::new(),
+        vec!["scratch".to_string()],
+    );
+    storage.save_note(&locked).unwrap();
+    storage.save_note(&open).unwrap();
+    let locked = storage.set_locked(&locked.id, true).unwrap().unwrap();
+    assert!(storage.set_locked(&locked.id, true).unwrap().is_none());
+    let id = locked.id.clone();
+
+    // Edits and deletion are refused, whichever way they come in
+    let mut edited = locked.clone();
+    edited.title = "Changed".to_string();
+    assert_locked(storage.update_note(edited.clone()), &id);
+    let (_, version) = storage.get_note_with_version(&id).unwrap();
+    assert_locked(
+        storage.update_note_with_version(edited.clone(), version),
+        &id,
+    );
+    assert_locked(storage.delete_note(&id), &id);
+    assert_locked(storage.set_archived(&id, true), &id);
+    assert_locked(storage.toggle_task(&id, 1), &id);
+
+    // Bulk operations leave the locked note alone but still do their work
+    let archived = storage.apply_tag_policies(false).unwrap();
+    assert_eq!(
+        archived.iter().map(|note| &note.id).collect::<Vec<_>>(),
+        [&open.id]
+    );
+    assert_eq!(storage.get_note(&id).as_ref(), Some(&locked));
+    assert_eq!(vault(root).get_note(&id).as_ref(), Some(&locked));
+
+    // Forced changes go through and keep the lock
+    storage.force_update_note(edited.clone()).unwrap();
+    assert_eq!(storage.get_note(&id).unwrap().title, "Changed");
+    assert!(vault(root).get_note(&id).unwrap().locked);
+
+    // The watcher refreshes locked notes changed on disk
+    let mut on_disk = edited.clone();
+    on_disk.content = "changed elsewhere".to_string();
+    let path = WalkDir::new(root.join("notes"))
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .find(|entry| is_note_file(entry.path()) && entry.path().file_stem() == Some(id.as_ref()))
+        .unwrap()
+        .into_path();
+    std::fs::write(&path, serde_json::to_string_pretty(&on_disk).unwrap()).unwrap();
+    let cache = Arc::new(RwLock::new(HashMap::from([(id.clone(), edited.clone())])));
+    let (events, _received) = broadcast::channel(4);
+    handle_fs_event(
+        notify::Event::new(notify::EventKind::Modify(notify::event::ModifyKind::Any))
+            .add_path(path),
+        &cache,
+        &Arc::new(Mutex::new(AliasIndex::new())),
+        &Arc::new(Mutex::new(TitleIndex::new())),
+        &Arc::new(Mutex::new(TagIndex::new())),
+        &Arc::new(Mutex::new(StructureIndex::new())),
+        &Arc::new(Mutex::new(LinkIndex::default())),
+        &Arc::new(Mutex::new(None)),
+        &events,
+        &StandardPolicy,
+        &root.join("notes"),
+        &OwnChanges::new(root.join("notes")),
+    )
+    .await;
+    assert_eq!(cache.read().unwrap()[&id].content, "changed elsewhere");
+
+    // Unlocking makes the note editable again, and forced deletion works too
+    let storage = vault(root);
+    assert!(!storage.set_locked(&id, false).unwrap().unwrap().locked);
+    storage.set_archived(&id, true).unwrap();
+    storage.set_locked(&id, true).unwrap();
+    storage.force_delete_note(&id).unwrap();
+    assert!(vault(root).get_note(&id).is_none());
+}
@@ -0,0 +1,70 @@
+//! Checks manual backups with `auto_backup` off: `create_backup_now` takes
+//! the backup directly when the scheduler isn't running, and a started
+//! scheduler takes manual backups without scheduling periodic ones.
+//!
+//! Run with `cargo test --test manual_backup`.
+mod common;
+
+use std::{path::Path, time::Duration};
+
+use kbnotes::*;
+
+fn config(root: &Path) -> Config {
+    let mut config = common::config(root);
+    config.fsync = FsyncMode::Never;
+    config
+}
+
+/// Number of backup archives in the backup directory
+fn archives(storage: &NoteStorage) -> usize {
+    storage.list_backups(false).unwrap().archives.len()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn manual_backup() {
+    // The scheduler never started: the backup is taken right away
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let storage = common::open(config(dir.path()));
+    let note = Note::new("Alpha".to_string(), "alpha".to_string(), vec![]);
+    storage.save_note(&note).unwrap();
+    storage.create_backup_now().await.unwrap();
+    assert_eq!(archives(&storage), 1);
+    let status = storage.get_backup_status().await;
+    assert!(!status.is_running);
+    let path = status
+        .last_backup_path
+        .expect("the backup was not recorded");
+    assert!(path.exists());
+    let manifest = storage.read_backup_manifest(&path).unwrap().unwrap();
+    assert_eq!(manifest.notes, 1);
+
+    // Started with auto_backup off: running, nothing scheduled, manual
+    // backups go through the scheduler task
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let storage = common::start(config(dir.path())).await;
+    let status = storage.lock().await.get_backup_status().await;
+    assert!(status.is_running);
+    assert!(status.next_backup_time.is_none());
+    assert_eq!(archives(&*storage.lock().await), 0);
+
+    storage.lock().await.create_backup_now().await.unwrap();
+    let mut status = storage.lock().await.get_backup_status().await;
+    for _ in 0..100 {
+        if status.last_backup_path.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        status = storage.lock().await.get_backup_status().await;
+    }
+    assert!(status
+        .last_backup_path
+        .expect("no backup was taken")
+        .exists());
+    assert_eq!(archives(&*storage.lock().await), 1);
+
+    // Stopped again, manual backups still work
+    storage.lock().await.stop_backup_scheduler().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    storage.lock().await.create_backup_now().await.unwrap();
+    assert_eq!(archives(&*storage.lock().await), 2);
+}
@@ -0,0 +1,160 @@
+//! Checks that notes survive the Markdown storage format unchanged, including
+//! content with `---` lines, and that vaults can be converted between formats
+//! and backed up and restored in either of them.
+//!
+//! Run with `cargo test --test markdown_storage`.
+mod common;
+
+use std::path::Path;
+
+use kbnotes::*;
+use walkdir::WalkDir;
+
+/// Note files below a directory, leaving out storage state and history
+fn note_files(dir: &Path) -> Vec<std::path::PathBuf> {
+    let mut files: Vec<_> = WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && is_note_file(entry.path()))
+        .map(|entry| entry.path().to_path_buf())
+        .collect();
+    files.sort();
+    files
+}
+
+fn vault(root: &Path, format: StorageFormat) -> NoteStorage {
+    let mut config = common::config(root);
+    config.storage_format = format;
+    common::open(config)
+}
+
+#[test]
+fn markdown_storage() {
+    let contents = [
+        "",
+        "---",
+        "---\n",
+        "---\ntitle: not front matter\n---\n",
+        "intro\n---\nafter a rule\n---",
+        "\n\n---\n\n",
+        "windows\r\n---\r\nline endings\r\n",
+        "# Heading\n\n```yaml\n---\nkey: value\n...\n```\n",
+    ];
+    let mut notes: Vec<Note> = contents
+        .iter()
+        .map(|content| {
+            Note::new(
+                "Rules".to_string(),
+                content.to_string(),
+                vec!["md".to_string()],
+            )
+        })
+        .collect();
+    let mut odd = Note::new(
+        "---\nnot: a key".to_string(),
+        "body".to_string(),
+        vec!["a: b".to_string(), "- c".to_string()],
+    );
+    odd.aliases = vec!["---".to_string()];
+    odd.metadata
+        .insert("multi".to_string(), "one\n---\ntwo".to_string());
+    odd.metadata.insert("empty".to_string(), String::new());
+    odd.pinned = true;
+    odd.due_at = Some(odd.created_at);
+    notes.push(odd);
+
+    // Every field, and the content byte for byte, comes back
+    for note in &notes {
+        let markdown = note_to_markdown(note).unwrap();
+        assert!(markdown.starts_with("---\n"), "{}", markdown);
+        assert!(markdown.ends_with(&note.content));
+        assert_eq!(&note_from_markdown(&markdown).unwrap(), note);
+    }
+    assert!(note_from_markdown("no front matter").is_err());
+    assert!(note_from_markdown("---\nid: x\n").is_err());
+    assert!(note_from_markdown("---\ntitle: no id\n---\n").is_err());
+
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let root = dir.path();
+
+    // Markdown vaults write .md files that load back as the same notes
+    let storage = vault(root, StorageFormat::Markdown);
+    for note in &notes {
+        storage.save_note(note).unwrap();
+    }
+    let files = note_files(&root.join("notes"));
+    assert_eq!(files.len(), notes.len());
+    assert!(files.iter().all(|path| path.extension().unwrap() == "md"));
+    let reloaded = vault(root, StorageFormat::Markdown);
+    for note in &notes {
+        assert_eq!(reloaded.get_note(&note.id).as_ref(), Some(note));
+    }
+
+    // A JSON vault still reads the .md files, and rewrites a note as JSON on update
+    let json = vault(root, StorageFormat::Json);
+    assert_eq!(json.get_all_notes().unwrap().len(), notes.len());
+    let mut updated = json.get_note(&notes[4].id).unwrap();
+    updated.content.push_str("\n---\nmore");
+    json.update_note(updated.clone()).unwrap();
+    let files = note_files(&root.join("notes"));
+    assert_eq!(files.len(), notes.len());
+    assert_eq!(
+        files
+            .iter()
+            .filter(|path| path.extension().unwrap() == "json")
+            .count(),
+        1
+    );
+    notes[4] = vault(root, StorageFormat::Json)
+        .get_note(&updated.id)
+        .unwrap();
+    assert_eq!(notes[4].content, updated.content);
+
+    // Converting in place keeps every note, both ways
+    for format in [StorageFormat::Json, StorageFormat::Markdown] {
+        let mut storage = vault(root, format);
+        storage.migrate_storage_format(format).unwrap();
+        let files = note_files(&root.join("notes"));
+        assert_eq!(files.len(), notes.len());
+        assert!(files
+            .iter()
+            .all(|path| StorageFormat::of_path(path) == Some(format)));
+        let reloaded = vault(root, format);
+        for note in &notes {
+            assert_eq!(reloaded.get_note(&note.id).as_ref(), Some(note));
+        }
+        assert_eq!(
+            vault(root, format).migrate_storage_format(format).unwrap(),
+            0
+        );
+    }
+
+    // Full backups hold the notes in the vault's format and restore into any
+    let backup = root.join("backup.zip");
+    vault(root, StorageFormat::Markdown)
+        .create_full_backup_to(&backup)
+        .unwrap();
+    for format in [StorageFormat::Json, StorageFormat::Markdown] {
+        let target_root = root.join(format.to_string());
+        let target = vault(&target_root, format);
+        let summary = target
+            .restore_full_backup(&backup, &RestoreOptions::default())
+            .unwrap();
+        assert_eq!(summary.notes_restored, notes.len());
+        assert!(note_files(&target_root.join("notes"))
+            .iter()
+            .all(|path| StorageFormat::of_path(path) == Some(format)));
+        for note in &notes {
+            assert_eq!(target.get_note(&note.id).as_ref(), Some(note));
+        }
+    }
+
+    // Deleting removes the note whichever format holds it
+    let storage = vault(root, StorageFormat::Json);
+    storage.delete_note(&notes[0].id).unwrap();
+    assert_eq!(note_files(&root.join("notes")).len(), notes.len() - 1);
+    assert!(vault(root, StorageFormat::Markdown)
+        .get_note(&notes[0].id)
+        .is_none());
+}
@@ -0,0 +1,112 @@
+//! Checks multi-tag queries: any or all of several tags, normalization,
+//! archived notes, `list --tag ... --all-tags` parsing, and how `--exclude-tag`
+//! combines with both modes.
+//!
+//! Run with `cargo test --test multi_tag`.
+mod common;
+
+use clap::Parser;
+use kbnotes::*;
+
+fn titles(notes: Vec<Note>) -> Vec<String> {
+    let mut titles: Vec<String> = notes.into_iter().map(|note| note.title).collect();
+    titles.sort();
+    titles
+}
+
+fn tags(tags: &[&str]) -> Vec<String> {
+    tags.iter().map(|tag| tag.to_string()).collect()
+}
+
+#[test]
+fn multi_tag() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let config = common::config(dir.path());
+    let storage = common::open(config);
+    for (title, note_tags) in [
+        ("Tokio runtime", vec!["Rust", "async"]),
+        ("Borrow checker", vec!["rust"]),
+        ("Asyncio", vec!["python", "async"]),
+        ("Old futures", vec!["rust", "async", "draft"]),
+        ("Groceries", vec![]),
+    ] {
+        let note = Note::new(title.to_string(), String::new(), tags(&note_tags));
+        storage.save_note(&note).unwrap();
+    }
+    let mut old = storage.find_by_title("Old futures").unwrap().remove(0);
+    old.archived = true;
+    storage.update_note(old).unwrap();
+
+    // Any needs one of the tags, All needs every one; case and spaces don't matter
+    let by_tags = |wanted: &[&str], mode, include_archived| {
+        titles(
+            storage
+                .get_notes_by_tags(&tags(wanted), mode, include_archived)
+                .unwrap(),
+        )
+    };
+    assert_eq!(
+        by_tags(&["rust", " ASYNC "], TagMatch::Any, false),
+        ["Asyncio", "Borrow checker", "Tokio runtime"]
+    );
+    assert_eq!(
+        by_tags(&["rust", " ASYNC "], TagMatch::All, false),
+        ["Tokio runtime"]
+    );
+    assert_eq!(
+        by_tags(&["rust", "async"], TagMatch::All, true),
+        ["Old futures", "Tokio runtime"]
+    );
+    assert!(by_tags(&["rust", "missing"], TagMatch::All, true).is_empty());
+    assert!(by_tags(&[], TagMatch::Any, true).is_empty());
+    assert!(by_tags(&[], TagMatch::All, true).is_empty());
+    // The single-tag method agrees with a one-tag query
+    assert_eq!(
+        titles(storage.get_notes_by_tag("Rust", false).unwrap()),
+        by_tags(&["rust"], TagMatch::All, false)
+    );
+
+    // Exclusion wins over both modes, as `list --exclude-tag` does
+    let query = |wanted: &[&str], tag_match, excluded: &[&str]| {
+        titles(
+            storage
+                .query_notes(NoteFilter {
+                    tags: tags(wanted),
+                    tag_match,
+                    exclude_tags: tags(excluded),
+                    ..NoteFilter::default()
+                })
+                .unwrap(),
+        )
+    };
+    assert_eq!(
+        query(&["rust", "async"], TagMatch::Any, &["Python"]),
+        ["Borrow checker", "Old futures", "Tokio runtime"]
+    );
+    assert_eq!(
+        query(&["rust", "async"], TagMatch::All, &["draft"]),
+        ["Tokio runtime"]
+    );
+    assert!(query(&["async"], TagMatch::All, &["async"]).is_empty());
+
+    // `--tag` repeats and `--all-tags` switches the mode
+    let Commands::List(options) = Cli::try_parse_from([
+        "kbnotes",
+        "list",
+        "--tag",
+        "rust",
+        "-t",
+        "async",
+        "--all-tags",
+        "--exclude-tag",
+        "draft",
+    ])
+    .unwrap()
+    .command
+    else {
+        panic!("expected the list command");
+    };
+    assert_eq!(options.tags, ["rust", "async"]);
+    assert!(options.all_tags);
+    assert_eq!(options.exclude_tags, ["draft"]);
+}
@@ -0,0 +1,68 @@
+//! Checks that a note restored with `restore_note_from_backup` is the one its
+//! latest per-note backup with another version holds: backups are written to
+//! the note's own directory in the backup directory, and backups written
+//! directly into the backup directory by earlier versions are still found
+//! and moved over.
+//!
+//! Run with `cargo test --test note_backup_restore`.
+mod common;
+
+use std::{fs, path::Path};
+
+use chrono::{Duration, Utc};
+use kbnotes::*;
+
+fn config(root: &Path) -> Config {
+    let mut config = common::config(root);
+    config.auto_backup = true;
+    config.fsync = FsyncMode::Never;
+    config
+}
+
+#[test]
+fn note_backup_restore() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let root = dir.path();
+    let backups = root.join("backups");
+    let storage = common::open(config(root));
+
+    // Save, modify, restore, compare: the backup of the modified note
+    // itself is passed over
+    let original = common::note("alpha", "first version");
+    storage.save_note(&original).unwrap();
+    assert_eq!(fs::read_dir(backups.join("alpha")).unwrap().count(), 1);
+    let mut modified = original.clone();
+    modified.content = "modified".to_string();
+    modified.updated_at = Utc::now();
+    storage.update_note(modified.clone()).unwrap();
+    assert_eq!(storage.get_note("alpha").unwrap().content, "modified");
+
+    let restored = storage.restore_note_from_backup("alpha").unwrap();
+    assert_eq!(restored, original);
+    assert_eq!(storage.get_note("alpha"), Some(original.clone()));
+
+    // Restoring again goes back to the modified version, which differs now
+    assert_eq!(storage.restore_note_from_backup("alpha").unwrap(), modified);
+    assert_eq!(storage.get_note("alpha").unwrap().content, "modified");
+
+    // A note no backup differs from can't be restored
+    let gamma = common::note("gamma", "only version");
+    storage.save_note(&gamma).unwrap();
+    assert!(storage.restore_note_from_backup("gamma").is_err());
+
+    // A backup written flat into the backup directory is still found, and
+    // moves into the note's directory with the next backup of the note
+    let beta = common::note("beta", "from a flat backup");
+    let written = Utc::now() - Duration::days(3);
+    let flat = backups.join(format!("beta_{}.json", written.timestamp()));
+    fs::write(&flat, serde_json::to_string_pretty(&beta).unwrap()).unwrap();
+    assert_eq!(storage.restore_note_from_backup("beta").unwrap(), beta);
+    assert!(!flat.exists());
+    assert!(backups
+        .join("beta")
+        .join(flat.file_name().unwrap())
+        .exists());
+
+    // A note without backups can't be restored
+    assert!(storage.restore_note_from_backup("missing").is_err());
+}
@@ -0,0 +1,70 @@
+//! Checks that new note IDs never collide and that notes with path-hostile
+//! titles or legacy IDs are stored inside the notes directory.
+//!
+//! Run with `cargo test --test note_ids`.
+mod common;
+
+use kbnotes::*;
+use walkdir::WalkDir;
+
+#[test]
+fn note_ids() {
+    // Same title, same millisecond: still distinct, and sorted by creation
+    let ids: Vec<String> = (0..1000)
+        .map(|_| Note::new("Same".to_string(), String::new(), vec![]).id)
+        .collect();
+    let mut sorted = ids.clone();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(sorted, ids);
+    assert!(ids.iter().all(|id| id.len() == 26
+        && id
+            .chars()
+            .all(|c| c.is_ascii_digit() || c.is_ascii_lowercase())));
+
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let root = dir.path().join("vault");
+    let storage = common::vault(&root);
+    let mut notes = vec![
+        Note::new("../../etc/passwd".to_string(), "x".to_string(), vec![]),
+        Note::new("a/b".to_string(), "y".to_string(), vec![]),
+    ];
+    // IDs in the old timestamp-plus-title format
+    for id in [
+        "1712345678901-../../etc/passwd",
+        "1712345678901-a/b",
+        "..",
+        "C:\\x",
+    ] {
+        let mut note = Note::new(id.to_string(), "z".to_string(), vec![]);
+        note.id = id.to_string();
+        notes.push(note);
+    }
+    for note in &notes {
+        storage.save_note(note).unwrap();
+    }
+
+    // Every file lands inside the notes directory, nothing next to it
+    let notes_dir = root.join("notes").canonicalize().unwrap();
+    let files: Vec<_> = WalkDir::new(dir.path())
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.path().canonicalize().unwrap())
+        .collect();
+    assert!(files.len() >= notes.len());
+    for file in &files {
+        assert!(file.starts_with(&notes_dir), "{} escaped", file.display());
+    }
+
+    let reloaded = common::vault(&root);
+    for note in &notes {
+        let stored = reloaded.get_note(&note.id).unwrap();
+        assert_eq!(
+            (stored.title, stored.content),
+            (note.title.clone(), note.content.clone())
+        );
+    }
+    reloaded.delete_note(&notes[2].id).unwrap();
+    assert!(reloaded.get_note(&notes[2].id).is_none());
+}
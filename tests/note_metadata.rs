@@ -0,0 +1,83 @@
+//! Checks that note metadata survives saving, reloading, compression and a
+//! full backup restore, that older note files without it still load, and
+//! that `kbnotes edit --set-meta/--unset-meta` change it.
+//!
+//! Run with `cargo test --test note_metadata`.
+mod common;
+
+use std::{path::Path, sync::Arc};
+
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+fn vault(root: &Path) -> (Config, NoteStorage) {
+    let mut config = common::config(root);
+    // Compress every note file, so both encodings are exercised
+    config.compress_threshold_bytes = Some(1);
+    let storage = common::open(config.clone());
+    (config, storage)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn note_metadata() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let (config, storage) = vault(&dir.path().join("source"));
+
+    let mut note = Note::new("Trip".to_string(), "Pack light".to_string(), vec![]);
+    note.metadata
+        .insert("status".to_string(), "draft".to_string());
+    note.metadata
+        .insert("source".to_string(), "https://example.com/?a=b".to_string());
+    storage.save_note(&note).unwrap();
+
+    // Reloaded from disk
+    let (_, reloaded) = vault(&dir.path().join("source"));
+    assert_eq!(reloaded.get_note(&note.id).unwrap().metadata, note.metadata);
+
+    // Restored from a full backup into another vault
+    let backup = dir.path().join("backup.zip");
+    storage.create_full_backup_to(&backup).unwrap();
+    let (_, target) = vault(&dir.path().join("target"));
+    target
+        .restore_full_backup(&backup, &RestoreOptions::default())
+        .unwrap();
+    assert_eq!(target.get_note(&note.id).unwrap().metadata, note.metadata);
+
+    // Files written before metadata existed
+    let legacy: Note = serde_json::from_str(
+        r#"{"id": "1-old", "title": "Old", "content": "", "tags": [],
+            "created_at": "2024-01-01T00:00:00Z", "updated_at": "2024-01-01T00:00:00Z"}"#,
+    )
+    .unwrap();
+    assert!(legacy.metadata.is_empty());
+
+    // edit --set-meta / --unset-meta
+    let storage = Arc::new(Mutex::new(storage));
+    let app = common::app(Arc::clone(&storage), config);
+    common::run(
+        &app,
+        &[
+            "edit",
+            "Trip",
+            "--set-meta",
+            "status=final",
+            "--set-meta",
+            "owner=sam",
+        ],
+    )
+    .await
+    .unwrap();
+    common::run(&app, &["edit", "Trip", "--unset-meta", "source"])
+        .await
+        .unwrap();
+    let metadata = storage.lock().await.get_note(&note.id).unwrap().metadata;
+    assert_eq!(metadata.len(), 2);
+    assert_eq!(metadata["status"], "final");
+    assert_eq!(metadata["owner"], "sam");
+    for invalid in ["status", "=final"] {
+        assert!(matches!(
+            common::run(&app, &["edit", "Trip", "--set-meta", invalid]).await,
+            Err(KbError::InvalidArgument { .. })
+        ));
+    }
+}
@@ -0,0 +1,66 @@
+//! Checks word, character, line and heading counts on Markdown edge cases,
+//! and that code blocks count as characters but not towards reading time.
+//!
+//! Run with `cargo test --test note_stats`.
+use kbnotes::*;
+
+fn words(content: &str) -> usize {
+    compute_stats(content).words
+}
+
+#[test]
+fn note_stats() {
+    assert_eq!(compute_stats(""), NoteStats::default());
+
+    let content = "# Title\n\nSome **bold**er text and `inline code`.\n";
+    let stats = compute_stats(content);
+    assert_eq!(
+        stats,
+        NoteStats {
+            words: 7,
+            characters: 49,
+            lines: 3,
+            headings: 1,
+            reading_minutes: 1,
+        }
+    );
+    assert_eq!(
+        stats.summary(),
+        "7 words, 49 characters, 3 lines, 1 heading, 1 min read"
+    );
+
+    // Code blocks count as characters and lines, not as words
+    let code = "Intro\n\n```rust\nfn main() { println!(\"many words in here\"); }\n```\n\n    indented code block\n";
+    let stats = compute_stats(code);
+    assert_eq!(stats.words, 1);
+    assert_eq!(stats.characters, code.chars().count());
+    assert_eq!(stats.lines, 7);
+    // An unclosed fence runs to the end of the note
+    assert_eq!(words("one two\n\n```\nthree four"), 2);
+
+    // Markup, link destinations and raw HTML are not words
+    assert_eq!(words("[link text](https://example.com/a-very-long-url)"), 2);
+    assert_eq!(words("<div class=\"x\">\n\n</div>\n\nplain"), 1);
+    assert_eq!(words("- [ ] buy milk\n- [x] eggs\n  - nested item"), 5);
+    assert_eq!(words("| a | b |\n|---|---|\n| c d | e |"), 5);
+    assert_eq!(words("> quoted\n>\n> twice"), 2);
+    assert_eq!(words("line one\nline two  \nline three"), 6);
+    assert_eq!(words("first\n\nsecond"), 2);
+    assert_eq!(words("[[Other note]] mentions"), 3);
+
+    // Characters are counted, not bytes; headings at every level
+    let stats = compute_stats("## Über\n\n### 日本語\n\nSetext\n======\n");
+    assert_eq!(stats.headings, 3);
+    assert_eq!(stats.characters, 32);
+    assert_eq!(stats.words, 3);
+
+    // Reading time rounds up at 200 words a minute
+    let long = "word ".repeat(WORDS_PER_MINUTE + 1);
+    assert_eq!(compute_stats(&long).reading_minutes, 2);
+    assert_eq!(
+        compute_stats(&"word ".repeat(WORDS_PER_MINUTE)).reading_minutes,
+        1
+    );
+    let note = Note::new("Long".to_string(), long, vec![]);
+    assert_eq!(note.stats().words, WORDS_PER_MINUTE + 1);
+}
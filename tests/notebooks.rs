@@ -0,0 +1,97 @@
+//! Checks notebook path validation, prefix matching, the notebook tree and
+//! that notebooks are stored with notes and included in exports.
+//!
+//! Run with `cargo test --test notebooks`.
+mod common;
+
+use kbnotes::*;
+
+fn note(title: &str, notebook: Option<&str>) -> Note {
+    let mut note = Note::new(title.to_string(), String::new(), vec![]);
+    note.notebook = notebook.map(str::to_string);
+    note
+}
+
+fn titles(notes: &[Note]) -> Vec<&str> {
+    notes.iter().map(|note| note.title.as_str()).collect()
+}
+
+#[test]
+fn notebooks() {
+    assert_eq!(
+        validate_notebook_path(" work/project-x ").unwrap(),
+        "work/project-x"
+    );
+    assert_eq!(validate_notebook_path("Ünïcode/ok").unwrap(), "Ünïcode/ok");
+    for bad in [
+        "", "/work", "work/", "work//x", "../x", "work/./x", "work/..", "a\\b", "a/ b",
+    ] {
+        assert!(
+            matches!(
+                validate_notebook_path(bad),
+                Err(KbError::InvalidArgument { .. })
+            ),
+            "{:?} should be rejected",
+            bad
+        );
+    }
+
+    let notes = vec![
+        note("Spec", Some("work/project-x")),
+        note("Plan", Some("work")),
+        note("Deep", Some("work/project-x/design")),
+        note("Workshop", Some("workshop")),
+        note("Dashed", Some("work-b")),
+        note("Loose", None),
+    ];
+    assert!(notes[2].in_notebook("work"));
+    assert!(notes[2].in_notebook("work/project-x"));
+    assert!(!notes[3].in_notebook("work"));
+    assert!(!notes[0].in_notebook("work/project"));
+    assert!(!notes[5].in_notebook("work"));
+
+    let tree: Vec<(String, usize, usize)> = notebook_tree(&notes)
+        .into_iter()
+        .map(|notebook| (notebook.path, notebook.notes, notebook.total))
+        .collect();
+    let expected = [
+        ("work", 1, 3),
+        ("work/project-x", 1, 2),
+        ("work/project-x/design", 1, 1),
+        ("work-b", 1, 1),
+        ("workshop", 1, 1),
+    ];
+    assert_eq!(
+        tree,
+        expected.map(|(path, notes, total)| (path.to_string(), notes, total))
+    );
+
+    // Stored with the note, and absent in files written before notebooks
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let config = common::config(dir.path());
+    let storage = common::open(config.clone());
+    for note in &notes {
+        storage.save_note(note).unwrap();
+    }
+    let reloaded = common::open(config);
+    assert_eq!(
+        titles(&reloaded.get_notes_by_notebook("work").unwrap()),
+        ["Spec", "Plan", "Deep"]
+    );
+    assert_eq!(
+        titles(&reloaded.get_notes_by_notebook("work/project-x").unwrap()),
+        ["Spec", "Deep"]
+    );
+    assert!(reloaded.get_notes_by_notebook("work/").is_err());
+    assert!(reloaded.get_notes_by_notebook("../work").is_err());
+    let legacy: Note = serde_json::from_str(
+        r#"{"id": "a", "title": "A", "content": "", "tags": [],
+            "created_at": "2024-01-01T00:00:00Z", "updated_at": "2024-01-01T00:00:00Z"}"#,
+    )
+    .unwrap();
+    assert_eq!(legacy.notebook, None);
+
+    assert!(render_markdown(&notes[0]).contains("- **Notebook:** work/project-x\n"));
+    let json = serde_json::to_value(&notes[0]).unwrap();
+    assert_eq!(json["notebook"], "work/project-x");
+}
@@ -0,0 +1,215 @@
+//! Checks cursor-based paging of `NoteStorage::list_notes_page`: walking the
+//! pages of every sort order yields each matching note exactly once and in
+//! the order of a full sort, even with tied sort keys and with notes deleted
+//! between pages, malformed or mismatched cursors are rejected, and
+//! `kbnotes list --page-size/--cursor` parses and runs.
+//!
+//! Run with `cargo test --test pagination`.
+mod common;
+
+use std::{collections::HashSet, sync::Arc};
+
+use chrono::{Duration, TimeZone, Utc};
+use clap::Parser;
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+const NOTES: usize = 250;
+
+/// Walks every page of a listing and returns the IDs in listing order
+fn walk(
+    storage: &NoteStorage,
+    filter: &ListFilter,
+    sort: &NoteSort,
+    page_size: usize,
+) -> Vec<String> {
+    let mut ids = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = storage
+            .list_notes_page(filter, sort, cursor, page_size)
+            .unwrap();
+        assert!(page.notes.len() <= page_size);
+        ids.extend(page.notes.into_iter().map(|note| note.id));
+        match page.next_cursor {
+            Some(next) => cursor = Some(next),
+            None => return ids,
+        }
+    }
+}
+
+/// IDs of the notes matching the filter, sorted in one go
+fn expected(storage: &NoteStorage, filter: &ListFilter, sort: &NoteSort) -> Vec<String> {
+    let mut notes: Vec<Note> = storage
+        .get_all_notes()
+        .unwrap()
+        .into_iter()
+        .filter(|note| filter.matches(note))
+        .collect();
+    notes.sort_by(|a, b| sort.compare(a, b));
+    notes.into_iter().map(|note| note.id).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pagination() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let mut config = common::config(dir.path());
+    config.fsync = FsyncMode::Never;
+    let storage = common::open(config.clone());
+
+    // Few distinct titles, creation times and due dates, so most sort keys tie
+    let base = Utc.with_ymd_and_hms(2024, 5, 1, 9, 0, 0).unwrap();
+    for i in 0..NOTES {
+        let mut note = Note::new(
+            format!("Note {}", i % 7),
+            if i % 5 == 0 {
+                "- [ ] open task".to_string()
+            } else {
+                String::new()
+            },
+            vec![if i % 2 == 0 { "even" } else { "odd" }.to_string()],
+        );
+        note.created_at = base + Duration::hours((i % 11) as i64);
+        note.due_at = (i % 3 == 0).then(|| base + Duration::days((i % 4) as i64));
+        note.pinned = i % 13 == 0;
+        note.archived = i % 17 == 0;
+        storage.save_note(&note).unwrap();
+    }
+
+    // Every order, paged in steps that do and don't divide the total
+    let filter = ListFilter::default();
+    for field in SortField::NAMES {
+        for descending in [false, true] {
+            for pinned_first in [false, true] {
+                let sort = NoteSort {
+                    field: field.parse().unwrap(),
+                    descending,
+                    pinned_first,
+                };
+                let all = expected(&storage, &filter, &sort);
+                for page_size in [1, 7, 50, NOTES] {
+                    let walked = walk(&storage, &filter, &sort, page_size);
+                    assert_eq!(walked, all, "{:?} in pages of {}", sort, page_size);
+                }
+            }
+        }
+    }
+    let unarchived = storage
+        .get_all_notes()
+        .unwrap()
+        .iter()
+        .filter(|note| !note.archived)
+        .count();
+    assert_eq!(
+        walk(&storage, &filter, &NoteSort::default(), 9).len(),
+        unarchived
+    );
+
+    // Filters narrow the listing before it is paged
+    let filter = ListFilter {
+        filter: NoteFilter {
+            tags: vec!["EVEN".to_string()],
+            ..NoteFilter::default()
+        },
+        include_archived: true,
+        structure: vec![StructureFilter::Tasks],
+        ..ListFilter::default()
+    };
+    let sort = NoteSort {
+        field: SortField::Title,
+        ..NoteSort::default()
+    };
+    let walked = walk(&storage, &filter, &sort, 4);
+    assert_eq!(walked.len(), (0..NOTES).filter(|i| i % 10 == 0).count());
+    let pinned = ListFilter {
+        pinned_only: true,
+        include_archived: true,
+        ..ListFilter::default()
+    };
+    assert_eq!(
+        walk(&storage, &pinned, &sort, 3).len(),
+        (0..NOTES).filter(|i| i % 13 == 0).count()
+    );
+
+    // Deleting notes between pages neither skips nor repeats the others
+    let sort = NoteSort {
+        field: SortField::Due,
+        descending: true,
+        pinned_first: true,
+    };
+    let filter = ListFilter::default();
+    let before = expected(&storage, &filter, &sort);
+    let first = storage.list_notes_page(&filter, &sort, None, 40).unwrap();
+    let deleted: Vec<String> = vec![
+        first.notes[5].id.clone(),
+        before[100].clone(),
+        before[41].clone(),
+    ];
+    for id in &deleted {
+        storage.delete_note(id).unwrap();
+    }
+    let mut seen: Vec<String> = first.notes.iter().map(|note| note.id.clone()).collect();
+    let mut cursor = first.next_cursor;
+    while let Some(current) = cursor {
+        let page = storage
+            .list_notes_page(&filter, &sort, Some(current), 40)
+            .unwrap();
+        seen.extend(page.notes.into_iter().map(|note| note.id));
+        cursor = page.next_cursor;
+    }
+    let wanted: Vec<String> = before
+        .iter()
+        .filter(|id| **id == deleted[0] || !deleted.contains(id))
+        .cloned()
+        .collect();
+    assert_eq!(seen, wanted);
+    assert_eq!(seen.iter().collect::<HashSet<_>>().len(), seen.len());
+
+    // Bad requests are refused
+    let page = storage.list_notes_page(&filter, &sort, None, 10).unwrap();
+    let cursor = page.next_cursor.unwrap();
+    let other = NoteSort {
+        descending: false,
+        ..sort
+    };
+    for (sort, cursor, page_size) in [
+        (sort, Some("zz".to_string()), 10),
+        (sort, Some("7b7d".to_string()), 10),
+        (other, Some(cursor.clone()), 10),
+        (sort, Some(cursor.clone()), 0),
+    ] {
+        let result = storage.list_notes_page(&filter, &sort, cursor, page_size);
+        assert!(
+            matches!(result, Err(KbError::InvalidArgument { .. })),
+            "{:?}",
+            result
+        );
+    }
+
+    // The command line takes a page size or a cursor instead of a limit
+    assert!(Cli::try_parse_from(["kbnotes", "list", "--page-size", "5", "--limit", "3"]).is_err());
+    let app = common::app(Arc::new(Mutex::new(storage)), config);
+    common::run(
+        &app,
+        &["list", "--page-size", "3", "--sort-by", "due", "--desc"],
+    )
+    .await
+    .unwrap();
+    common::run(
+        &app,
+        &["list", "--page-size", "3", "--format", "json", "--pinned"],
+    )
+    .await
+    .unwrap();
+    common::run(
+        &app,
+        &["list", "--sort-by", "due", "--desc", "--cursor", &cursor],
+    )
+    .await
+    .unwrap();
+    assert!(
+        common::run(&app, &["list", "--sort-by", "title", "--cursor", &cursor])
+            .await
+            .is_err()
+    );
+}
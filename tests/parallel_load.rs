@@ -0,0 +1,127 @@
+//! Checks that loading a vault of a few thousand notes in parallel fills the
+//! cache with exactly the notes on disk, whatever order they were written
+//! in, keeps the right copy of notes stored in both formats, and skips the
+//! trash, history and search index directories.
+//!
+//! Run with `cargo test --release --test parallel_load -- --nocapture` to time it.
+mod common;
+
+use std::{collections::HashMap, fs, path::Path, time::Instant};
+
+use chrono::{Duration, TimeZone, Utc};
+use kbnotes::*;
+
+const NOTES: usize = 3000;
+
+fn config(root: &Path) -> Config {
+    let mut config = common::config(root);
+    config.fsync = FsyncMode::Never;
+    config
+}
+
+fn generated(i: usize) -> Note {
+    let mut note = Note::new(
+        format!("Note {}", i),
+        format!("Content of note {}\n\n{}", i, "lorem ipsum ".repeat(i % 50)),
+        vec![format!("group-{}", i % 7)],
+    );
+    note.id = format!("note-{:05}", i);
+    note.created_at = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+    note.updated_at = note.created_at + Duration::minutes(i as i64);
+    note
+}
+
+/// Writes every generated note into a new vault, in the given order
+fn vault(root: &Path, order: impl Iterator<Item = usize>) -> NoteStorage {
+    let storage = common::open(config(root));
+    for i in order {
+        storage.save_note(&generated(i)).unwrap();
+    }
+    storage
+}
+
+/// Loads a vault from disk and returns its cache, by note ID
+fn reload(root: &Path) -> (LoadReport, HashMap<String, Note>) {
+    let mut storage = NoteStorage::new(config(root));
+    let started = Instant::now();
+    let report = storage.load_notes().unwrap();
+    println!("loaded {} notes in {:?}", report.loaded, started.elapsed());
+    let notes = storage
+        .query_notes(NoteFilter::default())
+        .unwrap()
+        .into_iter()
+        .map(|note| (note.id.clone(), note))
+        .collect();
+    (report, notes)
+}
+
+/// Writes a Markdown copy of `note` next to its JSON file, as an interrupted
+/// format conversion leaves behind
+fn markdown_copy(notes_dir: &Path, note: &Note) {
+    let json = notes_dir
+        .join(&note.id[..2])
+        .join(format!("{}.json", note.id));
+    assert!(json.exists());
+    fs::write(json.with_extension("md"), note_to_markdown(note).unwrap()).unwrap();
+}
+
+#[test]
+fn parallel_load() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vaults");
+    let forward = dir.path().join("forward");
+    let backward = dir.path().join("backward");
+    vault(&forward, 0..NOTES);
+    vault(&backward, (0..NOTES).rev());
+
+    // Every note comes back exactly as it was saved
+    let (report, notes) = reload(&forward);
+    assert_eq!(report.loaded, NOTES);
+    assert!(report.failed.is_empty());
+    assert_eq!(notes.len(), NOTES);
+    for i in 0..NOTES {
+        let expected = generated(i);
+        assert_eq!(notes[&expected.id], expected);
+    }
+
+    // The order the files were written in doesn't change the cache, and
+    // neither does loading again
+    assert_eq!(reload(&backward).1, notes);
+    assert_eq!(reload(&forward).1, notes);
+
+    // Copies in both formats: the newer wins, the configured format on a tie
+    for root in [&forward, &backward] {
+        let notes_dir = root.join("notes");
+        let mut newer = generated(1);
+        newer.content = "newer markdown copy".to_string();
+        newer.updated_at += Duration::hours(1);
+        markdown_copy(&notes_dir, &newer);
+        let mut older = generated(2);
+        older.content = "older markdown copy".to_string();
+        older.updated_at -= Duration::hours(1);
+        markdown_copy(&notes_dir, &older);
+        let mut tie = generated(3);
+        tie.content = "markdown copy as new as the JSON".to_string();
+        markdown_copy(&notes_dir, &tie);
+    }
+    let (report, notes) = reload(&forward);
+    assert_eq!(report.loaded, NOTES);
+    assert_eq!(notes["note-00001"].content, "newer markdown copy");
+    assert_eq!(notes["note-00002"], generated(2));
+    assert_eq!(notes["note-00003"], generated(3));
+    assert_eq!(reload(&backward).1, notes);
+
+    // Internal directories hold note-shaped files that are not live notes
+    let storage = NoteStorage::new(config(&forward));
+    storage.delete_note("note-00004").unwrap();
+    for internal in [TRASH_DIR, HISTORY_DIR, SEARCH_INDEX_DIR] {
+        let mut stray = generated(NOTES + 1);
+        stray.id = format!("stray{}", internal);
+        let path = forward.join("notes").join(internal).join("st/stray.json");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, serde_json::to_string(&stray).unwrap()).unwrap();
+    }
+    let (report, notes) = reload(&forward);
+    assert_eq!(report.loaded, NOTES - 1);
+    assert!(!notes.contains_key("note-00004"));
+    assert!(notes.keys().all(|id| id.starts_with("note-")));
+}
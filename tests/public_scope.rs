@@ -0,0 +1,138 @@
+//! Checks that an unauthenticated note scope only lists, finds, returns and
+//! streams public notes, including a note that turns private mid-session.
+//!
+//! Run with `cargo test --test public_scope`.
+mod common;
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+fn titles(notes: Vec<Note>) -> Vec<String> {
+    let mut titles: Vec<String> = notes.into_iter().map(|note| note.title).collect();
+    titles.sort();
+    titles
+}
+
+async fn next_event(events: &mut ScopedEvents) -> NoteEvent {
+    tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("no event received")
+        .unwrap()
+}
+
+async fn no_event(events: &mut ScopedEvents) {
+    let pending = tokio::time::timeout(Duration::from_millis(200), events.recv()).await;
+    assert!(pending.is_err(), "unexpected event: {:?}", pending);
+}
+
+fn tagged(title: &str, content: &str, tags: &[&str]) -> Note {
+    let tags = tags.iter().map(|tag| tag.to_string()).collect();
+    Note::new(title.to_string(), content.to_string(), tags)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn public_scope() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let mut config = common::config(dir.path());
+    config.server.visibility = BTreeMap::from([
+        ("public".to_string(), Visibility::Public),
+        ("draft".to_string(), Visibility::Private),
+    ]);
+
+    let storage = Arc::new(Mutex::new(common::open(config)));
+    let storage = storage.lock().await;
+
+    let mut recipe = tagged("Bread recipe", "Flour and water", &["Public", "food"]);
+    let draft = tagged(
+        "Bread draft",
+        "Unfinished flour notes",
+        &["public", "draft"],
+    );
+    let diary = tagged("Diary", "Private flour thoughts", &["personal"]);
+    for note in [&recipe, &draft, &diary] {
+        storage.save_note(note).unwrap();
+    }
+
+    let public = storage.scope(Audience::Public);
+    let authenticated = storage.scope(Audience::Authenticated);
+
+    // list
+    assert_eq!(titles(public.list_notes().unwrap()), ["Bread recipe"]);
+    assert_eq!(authenticated.list_notes().unwrap().len(), 3);
+    assert!(public.notes_by_tag("personal").unwrap().is_empty());
+
+    // search
+    assert_eq!(
+        titles(public.search_notes("flour").unwrap()),
+        ["Bread recipe"]
+    );
+    assert_eq!(authenticated.search_notes("flour").unwrap().len(), 3);
+
+    // get and html, by ID and by title, without revealing hidden notes
+    assert_eq!(public.get_note(&recipe.id).unwrap().title, "Bread recipe");
+    assert_eq!(public.get_note("bread recipe").unwrap().id, recipe.id);
+    for reference in [draft.id.as_str(), diary.id.as_str(), "Diary"] {
+        let error = public.get_note(reference).unwrap_err();
+        assert!(matches!(error, KbError::NoteNotFound { .. }), "{}", error);
+        assert!(public.render_note_html(reference).is_err());
+    }
+    assert!(public
+        .render_note_html(&recipe.id)
+        .unwrap()
+        .contains("Flour and water"));
+    assert_eq!(authenticated.get_note(&diary.id).unwrap().title, "Diary");
+
+    // events: changes to hidden notes are not streamed
+    let mut events = public.subscribe_events().unwrap();
+    let mut all_events = authenticated.subscribe_events().unwrap();
+
+    recipe.content = "Flour, water and salt".to_string();
+    recipe.updated_at = chrono::Utc::now();
+    storage.save_note(&recipe).unwrap();
+    let event = next_event(&mut events).await;
+    assert_eq!(
+        (event.kind, event.note_id.as_str()),
+        (NoteEventKind::Updated, recipe.id.as_str())
+    );
+
+    let mut diary_edit = diary.clone();
+    diary_edit.content = "More thoughts".to_string();
+    storage.save_note(&diary_edit).unwrap();
+    no_event(&mut events).await;
+
+    // The recipe turns private mid-session: it disappears everywhere and its
+    // subscribers are told it is gone
+    recipe.tags.push("draft".to_string());
+    recipe.updated_at = chrono::Utc::now();
+    storage.save_note(&recipe).unwrap();
+    let event = next_event(&mut events).await;
+    assert_eq!(
+        (event.kind, event.note_id.as_str()),
+        (NoteEventKind::Deleted, recipe.id.as_str())
+    );
+    assert!(public.list_notes().unwrap().is_empty());
+    assert!(public.search_notes("flour").unwrap().is_empty());
+    assert!(public.get_note(&recipe.id).is_err());
+
+    // Further changes to it stay hidden, until it becomes public again
+    recipe.content = "Secret ingredient".to_string();
+    storage.save_note(&recipe).unwrap();
+    no_event(&mut events).await;
+    recipe.tags.retain(|tag| tag != "draft");
+    storage.save_note(&recipe).unwrap();
+    let event = next_event(&mut events).await;
+    assert_eq!(
+        (event.kind, event.note_id.as_str()),
+        (NoteEventKind::Created, recipe.id.as_str())
+    );
+    assert_eq!(titles(public.list_notes().unwrap()), ["Bread recipe"]);
+
+    // Authenticated subscribers saw every change as it happened
+    let mut kinds = Vec::new();
+    for _ in 0..5 {
+        kinds.push(next_event(&mut all_events).await.kind);
+    }
+    assert!(kinds.iter().all(|kind| *kind == NoteEventKind::Updated));
+}
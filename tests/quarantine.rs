@@ -0,0 +1,133 @@
+//! Checks that `load_notes` moves unreadable note files into `.corrupt/`
+//! with the reason next to them and reports them, and that `kbnotes doctor
+//! --corrupt` lists them and restores the notes truncated JSON still holds.
+//!
+//! Run with `cargo test --test quarantine`.
+mod common;
+
+use std::{fs, path::Path, sync::Arc};
+
+use chrono::{TimeZone, Utc};
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+fn note(id: &str, content: &str) -> Note {
+    let mut note = Note::new(format!("{} title", id), content.to_string(), vec![]);
+    note.id = id.to_string();
+    note
+}
+
+/// Writes `note` as JSON cut short just before `marker`
+fn write_truncated(path: &Path, note: &Note, marker: &str) {
+    let json = serde_json::to_string_pretty(note).unwrap();
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, &json[..json.find(marker).unwrap()]).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn quarantine() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let notes = dir.path().join("notes");
+    let config = common::config(dir.path());
+    let storage = common::open(config.clone());
+    storage.save_note(&note("keep", "still fine")).unwrap();
+
+    // A note cut off mid-content, one that is not JSON at all, and a damaged
+    // copy of a note that is still in the vault
+    write_truncated(
+        &notes.join("cu/cut.json"),
+        &note("cut", "The quick brown fox jumps over the lazy dog"),
+        "lazy",
+    );
+    fs::create_dir_all(notes.join("ga")).unwrap();
+    fs::write(notes.join("ga/garbage.json"), "not json at all").unwrap();
+    write_truncated(&notes.join("zz/dup.json"), &note("keep", "old"), "\"tags\"");
+
+    let mut storage = NoteStorage::new(config.clone());
+    let report = storage.load_notes().unwrap();
+    assert_eq!(report.loaded, 1);
+    let mut failed: Vec<_> = report.failed.iter().map(|(path, _)| path.clone()).collect();
+    failed.sort();
+    assert_eq!(
+        failed,
+        [
+            notes.join("cu/cut.json"),
+            notes.join("ga/garbage.json"),
+            notes.join("zz/dup.json")
+        ]
+    );
+    assert!(report.failed.iter().all(|(_, reason)| !reason.is_empty()));
+    assert!(!notes.join("cu").exists() && !notes.join("ga/garbage.json").exists());
+
+    // The quarantine keeps each file with its original path, time and reason
+    let quarantined = storage.list_quarantined().unwrap();
+    let originals: Vec<_> = quarantined.iter().map(|f| f.original.clone()).collect();
+    assert_eq!(originals, failed);
+    for file in &quarantined {
+        assert!(file.path.starts_with(notes.join(CORRUPT_DIR)));
+        assert!(file.quarantined_at.is_some());
+        assert!(file.reason.as_deref().is_some_and(|r| !r.is_empty()));
+    }
+    // Reloading finds nothing left to quarantine
+    assert!(storage.load_notes().unwrap().failed.is_empty());
+    assert!(storage.verify_integrity().unwrap().is_clean());
+
+    // Truncated JSON keeps what was written before the cut
+    let cut = storage.salvage_quarantined(&quarantined[0]).unwrap();
+    assert_eq!(cut.id, "cut");
+    assert_eq!(cut.title, "cut title");
+    assert_eq!(cut.content, "The quick brown fox jumps over the ");
+    assert!(storage.salvage_quarantined(&quarantined[1]).is_err());
+    assert_eq!(
+        storage.salvage_quarantined(&quarantined[2]).unwrap().id,
+        "keep"
+    );
+
+    // `doctor --corrupt` only lists, `--fix` restores what doesn't clash
+    let storage = Arc::new(Mutex::new(storage));
+    let app = common::app(Arc::clone(&storage), config);
+    common::run(&app, &["doctor", "--corrupt"]).await.unwrap();
+    assert_eq!(storage.lock().await.list_quarantined().unwrap().len(), 3);
+    common::run(&app, &["doctor", "--corrupt", "--fix"])
+        .await
+        .unwrap();
+    let storage = storage.lock().await;
+    assert_eq!(storage.get_note("cut").unwrap().content, cut.content);
+    assert!(notes.join("cu/cut.json").exists());
+    assert_eq!(storage.get_note("keep").unwrap().content, "still fine");
+    let left: Vec<_> = storage
+        .list_quarantined()
+        .unwrap()
+        .into_iter()
+        .map(|f| f.original)
+        .collect();
+    assert_eq!(
+        left,
+        [notes.join("ga/garbage.json"), notes.join("zz/dup.json")]
+    );
+    assert!(matches!(
+        storage.restore_quarantined(&quarantined[2]),
+        Err(KbError::ApplicationError { .. })
+    ));
+
+    // Names carry the time, and files quarantined in the same second don't clash
+    let at = Utc.with_ymd_and_hms(2024, 5, 1, 9, 30, 0).unwrap();
+    let name = quarantine_name(Path::new("ab/abc.json"), at, 0);
+    assert_eq!(name, Path::new("ab/abc.json.20240501T093000Z"));
+    assert_eq!(
+        parse_quarantine_name(&quarantine_name(Path::new("ab/abc.json"), at, 2)),
+        (Path::new("ab/abc.json").to_path_buf(), Some(at))
+    );
+    let mut targets = Vec::new();
+    for _ in 0..2 {
+        fs::create_dir_all(notes.join("ab")).unwrap();
+        fs::write(notes.join("ab/abc.json"), "{").unwrap();
+        targets.push(
+            storage
+                .quarantine_file(&notes.join("ab/abc.json"), "broken")
+                .unwrap(),
+        );
+    }
+    assert_ne!(targets[0], targets[1]);
+    assert!(targets.iter().all(|target| target.exists()));
+}
@@ -0,0 +1,171 @@
+//! Checks `NoteStorage::query_notes` with tag, excluded tag and date
+//! filters, and the date formats of `export --since`/`--until` and the
+//! `--created-*` and `--updated-*` options of `list` and `search`.
+//!
+//! Run with `cargo test --test query_notes`.
+mod common;
+
+use std::sync::Arc;
+
+use chrono::{Duration, Local, TimeZone, Utc};
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+fn titles(notes: Vec<Note>) -> Vec<String> {
+    let mut titles: Vec<String> = notes.into_iter().map(|note| note.title).collect();
+    titles.sort();
+    titles
+}
+
+fn tags(tags: &[&str]) -> Vec<String> {
+    tags.iter().map(|tag| tag.to_string()).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn query_notes() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let config = common::config(dir.path());
+
+    let storage = Arc::new(Mutex::new(common::open(config)));
+    let storage = storage.lock().await;
+
+    for (title, note_tags, day) in [
+        ("Standup", &["Work", "meeting"][..], 10),
+        ("Roadmap", &["project"][..], 20),
+        ("Retro", &["work", "project"][..], 31),
+        ("Groceries", &["home"][..], 15),
+    ] {
+        let mut note = Note::new(title.to_string(), String::new(), tags(note_tags));
+        note.created_at = Local
+            .with_ymd_and_hms(2024, 1, day, 12, 0, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        note.updated_at = note.created_at + Duration::days(1);
+        storage.save_note(&note).unwrap();
+    }
+
+    // An empty filter selects everything
+    assert_eq!(storage.query_notes(NoteFilter::default()).unwrap().len(), 4);
+
+    // Tags, any or all of them, ignoring case
+    let any = NoteFilter {
+        tags: tags(&["work", "PROJECT"]),
+        ..NoteFilter::default()
+    };
+    assert_eq!(
+        titles(storage.query_notes(any.clone()).unwrap()),
+        ["Retro", "Roadmap", "Standup"]
+    );
+    let all = NoteFilter {
+        tag_match: TagMatch::All,
+        ..any.clone()
+    };
+    assert_eq!(titles(storage.query_notes(all).unwrap()), ["Retro"]);
+
+    // Excluded tags win over included ones, with the same normalization
+    let excluded = NoteFilter {
+        exclude_tags: tags(&[" Meeting ", "HOME"]),
+        ..NoteFilter::default()
+    };
+    assert_eq!(
+        titles(storage.query_notes(excluded.clone()).unwrap()),
+        ["Retro", "Roadmap"]
+    );
+    let excluded = NoteFilter {
+        exclude_tags: tags(&["project"]),
+        ..any.clone()
+    };
+    assert_eq!(
+        titles(storage.query_notes(excluded.clone()).unwrap()),
+        ["Standup"]
+    );
+    assert_eq!(
+        titles(
+            storage
+                .search_notes_filtered(
+                    "t",
+                    &SearchRequest {
+                        include_archived: true,
+                        filter: excluded,
+                        ..SearchRequest::default()
+                    }
+                )
+                .unwrap()
+                .into_notes()
+        ),
+        ["Standup"]
+    );
+
+    // Bare dates cover whole local days, at both ends
+    let since = parse_date_bound("2024-01-15", DateBound::Start).unwrap();
+    let until = parse_date_bound("2024-01-31", DateBound::End).unwrap();
+    assert_eq!(since, Local.with_ymd_and_hms(2024, 1, 15, 0, 0, 0).unwrap());
+    assert_eq!(
+        until + Duration::nanoseconds(1),
+        Local.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap()
+    );
+    let dated = NoteFilter {
+        since: Some(since),
+        until: Some(until),
+        ..any
+    };
+    assert_eq!(
+        titles(storage.query_notes(dated.clone()).unwrap()),
+        ["Retro", "Roadmap"]
+    );
+    let dated = NoteFilter {
+        tags: Vec::new(),
+        until: Some(parse_date_bound("2024-01-15", DateBound::End).unwrap()),
+        ..dated
+    };
+    assert_eq!(titles(storage.query_notes(dated).unwrap()), ["Groceries"]);
+
+    // Update dates are checked separately, and combine with the rest
+    let updated = NoteFilter {
+        updated_since: Some(parse_date_bound("2024-01-21", DateBound::Start).unwrap()),
+        ..NoteFilter::default()
+    };
+    assert_eq!(
+        titles(storage.query_notes(updated.clone()).unwrap()),
+        ["Retro", "Roadmap"]
+    );
+    let updated = NoteFilter {
+        updated_until: Some(parse_date_bound("2024-01-25", DateBound::End).unwrap()),
+        tags: tags(&["project"]),
+        ..updated
+    };
+    assert_eq!(titles(storage.query_notes(updated).unwrap()), ["Roadmap"]);
+
+    // A bound in the future matches nothing rather than failing
+    let future = NoteFilter {
+        since: Some(Utc::now() + Duration::days(365)),
+        ..NoteFilter::default()
+    };
+    assert!(storage.query_notes(future).unwrap().is_empty());
+    assert_eq!(
+        storage
+            .search_notes_filtered(
+                "o",
+                &SearchRequest {
+                    include_archived: true,
+                    filter: NoteFilter {
+                        since: Some(since),
+                        ..NoteFilter::default()
+                    },
+                    ..SearchRequest::default()
+                }
+            )
+            .unwrap()
+            .total,
+        3
+    );
+
+    // RFC 3339 timestamps are taken as they are
+    let time = parse_date_bound("2024-01-20T13:00:00+01:00", DateBound::End).unwrap();
+    assert_eq!(time, Utc.with_ymd_and_hms(2024, 1, 20, 12, 0, 0).unwrap());
+    for invalid in ["yesterday", "2024-02-30", "2024/01/01", "2024-01-01 10:00"] {
+        let error = parse_date_bound(invalid, DateBound::Start).unwrap_err();
+        assert!(matches!(error, KbError::InvalidFormat { .. }), "{}", error);
+        assert!(error.to_string().contains(invalid));
+    }
+}
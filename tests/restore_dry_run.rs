@@ -0,0 +1,163 @@
+//! Checks restore dry runs: `restore_full_backup` with `dry_run` plans a
+//! create, overwrite or skip for every note of the backup without touching
+//! the vault, its cache or the backup directory, a real restore then does
+//! what was planned, and `kbnotes restore --dry-run` prints the plan.
+//!
+//! Run with `cargo test --test restore_dry_run`.
+mod common;
+
+use std::{collections::BTreeMap, fs, path::Path, sync::Arc};
+
+use clap::Parser;
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+/// Every file below `dir` with its contents
+fn files(dir: &Path) -> BTreeMap<String, Vec<u8>> {
+    let mut files = BTreeMap::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return files;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            files.extend(self::files(&path));
+        } else {
+            files.insert(path.display().to_string(), fs::read(&path).unwrap());
+        }
+    }
+    files
+}
+
+/// Every note of the vault by ID
+fn vault(storage: &NoteStorage) -> BTreeMap<String, Note> {
+    storage
+        .get_all_notes()
+        .unwrap()
+        .into_iter()
+        .map(|note| (note.id.clone(), note))
+        .collect()
+}
+
+/// The planned action of each note
+fn plan(summary: &RestoreBackupSummary) -> Vec<(&str, RestoreAction)> {
+    summary
+        .actions
+        .iter()
+        .map(|action| (action.note_id.as_str(), action.action))
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restore_dry_run() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let backups = dir.path().join("backups");
+    let mut config = common::config(dir.path());
+    config.auto_backup = true;
+    config.fsync = FsyncMode::Never;
+    let storage = common::open(config.clone());
+
+    // A backup of alpha and beta, then a vault holding a changed beta and gamma
+    storage.save_note(&common::note("alpha", "a1")).unwrap();
+    storage.save_note(&common::note("beta", "b1")).unwrap();
+    let backup = backups.join("kbnotes_backup_20240101_120000.zip");
+    storage.create_full_backup_to(&backup).unwrap();
+    storage.delete_note("alpha").unwrap();
+    let mut beta = storage.get_note("beta").unwrap();
+    beta.content = "b2".to_string();
+    beta.updated_at = chrono::Utc::now();
+    storage.update_note(beta).unwrap();
+    storage.save_note(&common::note("gamma", "c1")).unwrap();
+
+    let vault_before = vault(&storage);
+    let files_before = files(dir.path());
+
+    let keep = RestoreOptions {
+        dry_run: true,
+        ..RestoreOptions::default()
+    };
+    let overwrite = RestoreOptions {
+        overwrite: true,
+        dry_run: true,
+        ..RestoreOptions::default()
+    };
+
+    // Keeping existing notes: alpha is created, beta is skipped
+    let summary = storage.restore_full_backup(&backup, &keep).unwrap();
+    assert!(summary.dry_run);
+    assert_eq!(
+        plan(&summary),
+        [
+            ("alpha", RestoreAction::Create),
+            ("beta", RestoreAction::Skip)
+        ]
+    );
+    assert_eq!((summary.notes_restored, summary.notes_skipped), (1, 1));
+    assert!(summary.actions.iter().all(|action| action.title.is_some()));
+
+    // Overwriting: beta would be replaced
+    let summary = storage.restore_full_backup(&backup, &overwrite).unwrap();
+    assert_eq!(
+        plan(&summary),
+        [
+            ("alpha", RestoreAction::Create),
+            ("beta", RestoreAction::Overwrite)
+        ]
+    );
+    let json = serde_json::to_value(&summary).unwrap();
+    assert_eq!(json["dry_run"], true);
+    assert_eq!(json["actions"][1]["action"], "overwrite");
+
+    // Nothing changed: not the cache, the note files or the backups
+    assert_eq!(vault(&storage), vault_before);
+    assert!(storage.get_note("alpha").is_none());
+    assert_eq!(files(dir.path()), files_before);
+
+    // The real restore does what the dry run planned
+    let summary = storage
+        .restore_full_backup(
+            &backup,
+            &RestoreOptions {
+                dry_run: false,
+                ..overwrite
+            },
+        )
+        .unwrap();
+    assert!(!summary.dry_run);
+    assert_eq!(
+        plan(&summary),
+        [
+            ("alpha", RestoreAction::Create),
+            ("beta", RestoreAction::Overwrite)
+        ]
+    );
+    assert_eq!(storage.get_note("beta").unwrap().content, "b1");
+    assert_eq!(storage.get_note("gamma").unwrap().content, "c1");
+
+    // The command prints the plan without asking and without writing
+    storage.delete_note("alpha").unwrap();
+    let files_before = files(dir.path());
+    let storage = Arc::new(Mutex::new(storage));
+    let app = common::app(Arc::clone(&storage), config);
+    let path = backup.display().to_string();
+    for args in [
+        &["restore", path.as_str(), "--dry-run"][..],
+        &[
+            "restore",
+            "--latest",
+            "--dry-run",
+            "--overwrite",
+            "--format",
+            "json",
+        ],
+    ] {
+        common::run(&app, args).await.unwrap();
+    }
+    assert_eq!(files(dir.path()), files_before);
+    assert!(storage.lock().await.get_note("alpha").is_none());
+    assert!(
+        Cli::try_parse_from(["kbnotes", "restore", "x.zip", "--staged", "--format", "json"])
+            .is_err()
+    );
+    assert!(Cli::try_parse_from(["kbnotes", "restore", "x.zip", "--staged", "--dry-run"]).is_err());
+}
@@ -0,0 +1,119 @@
+//! Checks selective restores: a `RestoreFilter` restores only the notes of a
+//! backup with the given IDs or tags, and the summary counts the notes it
+//! filtered out apart from the ones skipped because they exist. Also runs
+//! `kbnotes restore --ids` and `--tag`.
+//!
+//! Run with `cargo test --test restore_filter`.
+mod common;
+
+use std::sync::Arc;
+
+use clap::Parser;
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+fn note(id: &str, content: &str, tags: &[&str]) -> Note {
+    let tags = tags.iter().map(|tag| tag.to_string()).collect();
+    let mut note = Note::new(id.to_string(), content.to_string(), tags);
+    note.id = id.to_string();
+    note
+}
+
+fn open_storage(root: &std::path::Path) -> (Config, NoteStorage) {
+    let mut config = common::config(root);
+    config.fsync = FsyncMode::Never;
+    let storage = common::open(config.clone());
+    (config, storage)
+}
+
+/// IDs of the restored notes
+fn restored(summary: &RestoreBackupSummary) -> Vec<&str> {
+    summary
+        .actions
+        .iter()
+        .filter(|action| action.action != RestoreAction::Skip)
+        .map(|action| action.note_id.as_str())
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restore_filter() {
+    // A backup of four notes, two of them tagged project-x
+    let source = tempfile::tempdir().expect("failed to create temporary vault");
+    let (_, storage) = open_storage(source.path());
+    storage
+        .save_note(&note("alpha", "a1", &["project-x"]))
+        .unwrap();
+    storage
+        .save_note(&note("beta", "b1", &["Project-X", "draft"]))
+        .unwrap();
+    storage
+        .save_note(&note("gamma", "c1", &["project-y"]))
+        .unwrap();
+    storage.save_note(&note("delta", "d1", &[])).unwrap();
+    let backup = source.path().join("backup.zip");
+    storage.create_full_backup_to(&backup).unwrap();
+
+    // A vault that already has its own alpha
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let (config, storage) = open_storage(dir.path());
+    storage.save_note(&note("alpha", "local", &[])).unwrap();
+
+    // By tag, keeping existing notes: alpha exists, only beta comes back
+    let by_tag = RestoreOptions {
+        filter: RestoreFilter {
+            tags: vec!["project-x".to_string()],
+            ..RestoreFilter::default()
+        },
+        ..RestoreOptions::default()
+    };
+    let summary = storage.restore_full_backup(&backup, &by_tag).unwrap();
+    assert_eq!(summary.total_notes, 4);
+    assert_eq!(restored(&summary), ["beta"]);
+    assert_eq!((summary.notes_skipped, summary.notes_filtered), (1, 2));
+    assert!(summary.failed_notes.is_empty());
+    assert_eq!(storage.get_note("alpha").unwrap().content, "local");
+    assert!(storage.get_note("gamma").is_none() && storage.get_note("delta").is_none());
+
+    // By ID, overwriting: delta is created and nothing is skipped
+    let by_id = RestoreOptions {
+        overwrite: true,
+        filter: RestoreFilter {
+            ids: Some(["delta".to_string(), "missing".to_string()].into()),
+            ..RestoreFilter::default()
+        },
+        ..RestoreOptions::default()
+    };
+    let summary = storage.restore_full_backup(&backup, &by_id).unwrap();
+    assert_eq!(restored(&summary), ["delta"]);
+    assert_eq!((summary.notes_skipped, summary.notes_filtered), (0, 3));
+    assert_eq!(storage.get_note("alpha").unwrap().content, "local");
+
+    // The command takes both; a note must match the IDs and carry a tag
+    let storage = Arc::new(Mutex::new(storage));
+    let app = common::app(Arc::clone(&storage), config);
+    let path = backup.display().to_string();
+    common::run(
+        &app,
+        &[
+            "restore",
+            path.as_str(),
+            "--ids",
+            "alpha,gamma",
+            "--tag",
+            "project-x",
+            "--overwrite",
+            "--force",
+        ],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        storage.lock().await.get_note("alpha").unwrap().content,
+        "a1"
+    );
+    assert!(storage.lock().await.get_note("gamma").is_none());
+    assert!(
+        Cli::try_parse_from(["kbnotes", "restore", "x.zip", "--staged", "--tag", "x"]).is_err()
+    );
+}
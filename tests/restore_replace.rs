@@ -0,0 +1,155 @@
+//! Checks replacing restores: with `replace`, a restore overwrites existing
+//! notes and moves the notes missing from the backup to the trash, so the
+//! vault and its cache hold exactly the backup's notes. Trashed notes the
+//! restore brings back leave the trash. Also runs a dry run and
+//! `kbnotes restore --replace`.
+//!
+//! Run with `cargo test --test restore_replace`.
+mod common;
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use clap::Parser;
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+fn open_storage(root: &std::path::Path) -> (Config, NoteStorage) {
+    let mut config = common::config(root);
+    config.fsync = FsyncMode::Never;
+    let storage = common::open(config.clone());
+    (config, storage)
+}
+
+/// Note IDs and contents, as the storage sees them
+fn contents(storage: &NoteStorage) -> BTreeMap<String, String> {
+    storage
+        .get_all_notes()
+        .unwrap()
+        .into_iter()
+        .map(|note| (note.id, note.content))
+        .collect()
+}
+
+/// The vault after the backup was taken: alpha edited, beta deleted and a
+/// locked gamma added
+fn diverge(storage: &NoteStorage) {
+    let mut alpha = storage.get_note("alpha").unwrap();
+    alpha.content = "edited".to_string();
+    alpha.updated_at = chrono::Utc::now();
+    storage.update_note(alpha).unwrap();
+    storage.delete_note("beta").unwrap();
+    storage
+        .save_note(&common::note("gamma", "local only"))
+        .unwrap();
+    storage.set_locked("gamma", true).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restore_replace() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let (config, storage) = open_storage(dir.path());
+    storage.save_note(&common::note("alpha", "a1")).unwrap();
+    storage.save_note(&common::note("beta", "b1")).unwrap();
+    let backup = dir.path().join("backup.zip");
+    storage.create_full_backup_to(&backup).unwrap();
+    let expected = contents(&storage);
+    diverge(&storage);
+    let replace = RestoreOptions {
+        replace: true,
+        ..RestoreOptions::default()
+    };
+
+    // A dry run plans the removal without writing anything
+    let before = contents(&storage);
+    let summary = storage
+        .restore_full_backup(
+            &backup,
+            &RestoreOptions {
+                dry_run: true,
+                ..replace.clone()
+            },
+        )
+        .unwrap();
+    let plan: Vec<(&str, RestoreAction)> = summary
+        .actions
+        .iter()
+        .map(|action| (action.note_id.as_str(), action.action))
+        .collect();
+    assert_eq!(
+        plan,
+        [
+            ("alpha", RestoreAction::Overwrite),
+            ("beta", RestoreAction::Create),
+            ("gamma", RestoreAction::Remove),
+        ]
+    );
+    assert_eq!(summary.notes_removed, 1);
+    assert_eq!(contents(&storage), before);
+
+    // Filters can't be combined with replacing the vault
+    let by_id = RestoreOptions {
+        filter: RestoreFilter {
+            ids: Some(["alpha".to_string()].into_iter().collect()),
+            ..RestoreFilter::default()
+        },
+        ..replace.clone()
+    };
+    assert!(storage.restore_full_backup(&backup, &by_id).is_err());
+
+    // Without replace, the local-only note stays
+    let summary = storage
+        .restore_full_backup(
+            &backup,
+            &RestoreOptions {
+                overwrite: true,
+                ..RestoreOptions::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(summary.notes_removed, 0);
+    assert!(storage.get_note("gamma").is_some());
+
+    let summary = storage.restore_full_backup(&backup, &replace).unwrap();
+    assert_eq!((summary.notes_restored, summary.notes_removed), (2, 1));
+    assert!(
+        summary.failed_notes.is_empty(),
+        "{:?}",
+        summary.failed_notes
+    );
+    assert_eq!(contents(&storage), expected);
+    let trashed: Vec<String> = storage
+        .list_trash()
+        .unwrap()
+        .into_iter()
+        .map(|trashed| trashed.note.id)
+        .collect();
+    assert!(trashed.contains(&"gamma".to_string()), "{:?}", trashed);
+    // Beta, deleted to the trash before, is back and no longer trashed
+    assert!(!trashed.contains(&"beta".to_string()), "{:?}", trashed);
+    assert!(matches!(
+        storage.restore_from_trash("beta"),
+        Err(KbError::NoteNotFound { .. })
+    ));
+
+    // The cache agrees with a fresh load from disk
+    let (_, reloaded) = open_storage(dir.path());
+    assert_eq!(contents(&reloaded), expected);
+    for id in expected.keys() {
+        assert_eq!(storage.get_note(id), reloaded.get_note(id));
+    }
+
+    // The command, after the vault diverged again
+    diverge(&storage);
+    let storage = Arc::new(Mutex::new(storage));
+    let app = common::app(Arc::clone(&storage), config);
+    let path = backup.display().to_string();
+    common::run(&app, &["restore", path.as_str(), "--replace", "--force"])
+        .await
+        .unwrap();
+    assert_eq!(contents(&*storage.lock().await), expected);
+    for conflicting in ["--staged", "--tag=x", "--ids=alpha"] {
+        assert!(
+            Cli::try_parse_from(["kbnotes", "restore", "x.zip", "--replace", conflicting]).is_err()
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Checks restore summaries: a `RestoreBackupSummary` renders its counts,
+//! a table of failed notes and the elapsed time, serializes to JSON, and
+//! `kbnotes restore` fails when notes could not be restored, also with
+//! `--format json`.
+//!
+//! Run with `cargo test --test restore_summary`.
+mod common;
+
+use std::{fs, io::Write, path::Path, sync::Arc};
+
+use kbnotes::*;
+use tokio::sync::Mutex;
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+fn open_storage(root: &Path) -> (Config, NoteStorage) {
+    let mut config = common::config(root);
+    config.fsync = FsyncMode::Never;
+    let storage = common::open(config.clone());
+    (config, storage)
+}
+
+/// Runs `kbnotes restore` with the given flags against a fresh vault
+async fn run_restore(backup: &Path, flags: &[&str]) -> Result<()> {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let (config, storage) = open_storage(dir.path());
+    let app = common::app(Arc::new(Mutex::new(storage)), config);
+    let path = backup.display().to_string();
+    let mut args = vec!["restore", path.as_str()];
+    args.extend_from_slice(flags);
+    common::run(&app, &args).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restore_summary() {
+    let source = tempfile::tempdir().expect("failed to create temporary vault");
+    let (_, storage) = open_storage(source.path());
+    storage.save_note(&common::note("alpha", "a1")).unwrap();
+    storage.save_note(&common::note("beta", "b1")).unwrap();
+    let clean = source.path().join("clean.zip");
+    storage.create_full_backup_to(&clean).unwrap();
+
+    // The same backup with a note that can't be read
+    let broken = source.path().join("broken.zip");
+    fs::copy(&clean, &broken).unwrap();
+    let mut zip = ZipWriter::new_append(
+        fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&broken)
+            .unwrap(),
+    )
+    .unwrap();
+    zip.start_file("ga/gamma.json", SimpleFileOptions::default())
+        .unwrap();
+    zip.write_all(b"not a note").unwrap();
+    zip.finish().unwrap();
+
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let (_, target) = open_storage(dir.path());
+    let summary = target
+        .restore_full_backup(&broken, &RestoreOptions::default())
+        .unwrap();
+    assert_eq!((summary.notes_restored, summary.total_notes), (2, 3));
+    assert_eq!(summary.failed_notes.len(), 1);
+    assert_eq!(summary.failed_notes[0].0, "gamma");
+
+    let text = summary.to_string();
+    assert!(text.starts_with("Restored 2 of 3 notes from "), "{}", text);
+    assert!(text.contains(" in 0."), "{}", text);
+    for line in [
+        "  Restored:     2",
+        "  Skipped:      0",
+        "  Failed:       1",
+    ] {
+        assert!(text.contains(line), "{}", text);
+    }
+    // Counts of modes the restore didn't use are left out
+    assert!(!text.contains("Removed:"), "{}", text);
+    assert!(
+        text.contains("\nFailed notes:\n  ID     Reason\n  gamma  "),
+        "{}",
+        text
+    );
+
+    let json = serde_json::to_value(&summary).unwrap();
+    assert_eq!(json["notes_restored"], 2);
+    assert_eq!(json["failed_notes"][0][0], "gamma");
+    assert!(json["elapsed_ms"].is_u64());
+
+    // Partial restores exit with an error, in either format
+    for flags in [&["--force"][..], &["--force", "--format", "json"]] {
+        let error = run_restore(&broken, flags).await.unwrap_err();
+        assert!(matches!(error, KbError::RestoreFailed { .. }), "{}", error);
+    }
+    run_restore(&clean, &["--force", "--format", "json"])
+        .await
+        .unwrap();
+    let error = run_restore(&clean, &["--format", "json"])
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(error, KbError::InvalidArgument { .. }),
+        "{}",
+        error
+    );
+}
@@ -0,0 +1,89 @@
+//! Checks that updates keep the replaced version in the note's history, that
+//! the history is bounded by `max_revisions`, survives backup cleanup and
+//! deletion, and that restoring a revision can itself be undone.
+//!
+//! Run with `cargo test --test revision_history`.
+mod common;
+
+use chrono::{Duration, Utc};
+use kbnotes::*;
+
+fn edit(storage: &NoteStorage, id: &str, content: &str) {
+    let mut note = storage.get_note(id).unwrap();
+    note.content = content.to_string();
+    // Distinct timestamps, however fast the updates run
+    note.updated_at += Duration::milliseconds(1);
+    storage.update_note(note).unwrap();
+}
+
+fn contents(storage: &NoteStorage, id: &str) -> Vec<String> {
+    storage
+        .list_revisions(id)
+        .unwrap()
+        .into_iter()
+        .map(|revision| revision.note.content)
+        .collect()
+}
+
+#[test]
+fn revision_history() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let mut config = common::config(dir.path());
+    config.max_revisions = 3;
+    config.max_backups = 1;
+    let storage = common::open(config);
+
+    let note = Note::new("Draft".to_string(), "v1".to_string(), vec![]);
+    storage.save_note(&note).unwrap();
+    assert!(storage.list_revisions(&note.id).unwrap().is_empty());
+
+    edit(&storage, &note.id, "v2");
+    edit(&storage, &note.id, "v3");
+    assert_eq!(contents(&storage, &note.id), ["v1", "v2"]);
+    let first = storage.list_revisions(&note.id).unwrap()[0].clone();
+    assert_eq!(first.timestamp, note.updated_at.timestamp_millis());
+    assert!(dir
+        .path()
+        .join("notes")
+        .join(HISTORY_DIR)
+        .read_dir()
+        .unwrap()
+        .next()
+        .is_some());
+
+    // An update that changes nothing adds no revision
+    storage
+        .update_note(storage.get_note(&note.id).unwrap())
+        .unwrap();
+    assert_eq!(contents(&storage, &note.id).len(), 2);
+
+    // Only the newest max_revisions are kept
+    edit(&storage, &note.id, "v4");
+    edit(&storage, &note.id, "v5");
+    assert_eq!(contents(&storage, &note.id), ["v2", "v3", "v4"]);
+
+    // Backup cleanup leaves the history alone
+    for _ in 0..3 {
+        storage.create_full_backup().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+    }
+    assert_eq!(contents(&storage, &note.id).len(), 3);
+
+    // Restoring keeps the current version as a new revision
+    let v3 = storage.list_revisions(&note.id).unwrap()[1].timestamp;
+    let restored = storage.restore_revision(&note.id, v3).unwrap();
+    assert_eq!(restored.content, "v3");
+    assert!(restored.updated_at > Utc::now() - Duration::minutes(1));
+    assert_eq!(contents(&storage, &note.id), ["v3", "v4", "v5"]);
+    assert!(storage.restore_revision(&note.id, 42).is_err());
+
+    // The history outlives the note, which a revision brings back
+    storage.delete_note(&note.id).unwrap();
+    assert_eq!(contents(&storage, &note.id).len(), 3);
+    let v5 = storage.list_revisions(&note.id).unwrap()[2].timestamp;
+    assert_eq!(
+        storage.restore_revision(&note.id, v5).unwrap().content,
+        "v5"
+    );
+    assert_eq!(storage.get_note(&note.id).unwrap().content, "v5");
+}
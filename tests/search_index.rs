@@ -0,0 +1,161 @@
+//! Checks the on-disk search index: searches through it agree with the
+//! in-memory scan, it follows saves, updates, deletes and the file watcher,
+//! and stale or corrupt index files are refreshed or rebuilt on load.
+//!
+//! Run with `cargo test --test search_index`.
+mod common;
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use kbnotes::*;
+use tokio::sync::broadcast;
+use walkdir::WalkDir;
+
+fn vault(root: &Path, use_search_index: bool) -> NoteStorage {
+    let mut config = common::config(root);
+    config.use_search_index = use_search_index;
+    common::open(config)
+}
+
+fn titles(storage: &NoteStorage, query: &str) -> Vec<String> {
+    let mut titles: Vec<String> = storage
+        .search_notes(query, false)
+        .unwrap()
+        .into_notes()
+        .into_iter()
+        .map(|note| note.title)
+        .collect();
+    titles.sort();
+    titles
+}
+
+fn note_path(root: &Path, id: &str) -> PathBuf {
+    WalkDir::new(root.join("notes"))
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .find(|entry| is_note_file(entry.path()) && entry.path().file_stem() == Some(id.as_ref()))
+        .unwrap()
+        .into_path()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn search_index() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let root = dir.path();
+    let index_path = SearchIndex::path_in(&root.join("notes"));
+
+    let storage = vault(root, true);
+    let mut ids = HashMap::new();
+    for (title, content, tags) in [
+        ("Compose setup", "docker compose up -d", vec!["docker"]),
+        ("Cluster", "docker images deployed with kubernetes", vec![]),
+        ("Podman", "rootless containers, compose works too", vec![]),
+        ("Release checklist", "tag, build, deploy", vec!["ops"]),
+    ] {
+        let tags = tags.into_iter().map(str::to_string).collect();
+        let note = Note::new(title.to_string(), content.to_string(), tags);
+        storage.save_note(&note).unwrap();
+        ids.insert(title, note.id);
+    }
+    assert!(index_path.exists());
+
+    // The index only narrows the candidates: results match the plain scan
+    let scan = vault(root, false);
+    for query in [
+        "docker",
+        "dock",
+        "compose up",
+        "docker AND compose NOT kubernetes",
+        "kubernetes OR podman",
+        "NOT docker NOT compose",
+        "\"ops\"",
+        "\"ompose u\"",
+        "\"rootless compose\"",
+        "+++",
+    ] {
+        assert_eq!(titles(&storage, query), titles(&scan, query), "{}", query);
+    }
+    // ...except that query words must start a word of the note
+    assert_eq!(titles(&scan, "dkr"), ["Cluster", "Compose setup"]);
+    assert!(titles(&storage, "dkr").is_empty());
+
+    // Updates and deletes keep the index current, also across reloads
+    let mut podman = storage.get_note(&ids["Podman"]).unwrap();
+    podman.content = "rootless buildah images".to_string();
+    storage.update_note(podman).unwrap();
+    storage.delete_note(&ids["Cluster"]).unwrap();
+    for storage in [&storage, &vault(root, true)] {
+        assert_eq!(titles(storage, "buildah"), ["Podman"]);
+        assert_eq!(titles(storage, "images"), ["Podman"]);
+        assert!(titles(storage, "kubernetes").is_empty());
+    }
+
+    // Notes edited while kbnotes wasn't running are reindexed on load
+    let release = storage.get_note(&ids["Release checklist"]).unwrap();
+    let path = note_path(root, &release.id);
+    let mut edited = release.clone();
+    edited.content = "tag, build, deploy to staging".to_string();
+    fs::write(&path, serde_json::to_string_pretty(&edited).unwrap()).unwrap();
+    let storage = vault(root, true);
+    assert_eq!(titles(&storage, "staging"), ["Release checklist"]);
+
+    // The watcher updates the index too
+    let mut watched = edited.clone();
+    watched.content = "tag, build, deploy to production".to_string();
+    fs::write(&path, serde_json::to_string_pretty(&watched).unwrap()).unwrap();
+    let search_index = Arc::new(Mutex::new(SearchIndex::load(index_path.clone()).unwrap()));
+    let (events, _received) = broadcast::channel(4);
+    handle_fs_event(
+        notify::Event::new(notify::EventKind::Modify(notify::event::ModifyKind::Any))
+            .add_path(path),
+        &Arc::new(RwLock::new(HashMap::from([(edited.id.clone(), edited)]))),
+        &Arc::new(Mutex::new(AliasIndex::new())),
+        &Arc::new(Mutex::new(TitleIndex::new())),
+        &Arc::new(Mutex::new(TagIndex::new())),
+        &Arc::new(Mutex::new(StructureIndex::new())),
+        &Arc::new(Mutex::new(LinkIndex::default())),
+        &search_index,
+        &events,
+        &StandardPolicy,
+        &root.join("notes"),
+        &OwnChanges::new(root.join("notes")),
+    )
+    .await;
+    let index = SearchIndex::load(index_path.clone()).unwrap().unwrap();
+    assert_eq!(index.candidates("production", None).unwrap().len(), 1);
+    assert!(index.candidates("staging", None).unwrap().is_empty());
+
+    // Corrupt or tampered index files are rebuilt instead of failing searches
+    fs::write(&index_path, "{ not json").unwrap();
+    assert!(matches!(
+        SearchIndex::load(index_path.clone()),
+        Err(KbError::InvalidFormat { .. })
+    ));
+    let storage = vault(root, true);
+    assert_eq!(titles(&storage, "production"), ["Release checklist"]);
+    assert_eq!(
+        SearchIndex::load(index_path.clone())
+            .unwrap()
+            .unwrap()
+            .len(),
+        3
+    );
+    let tampered = fs::read_to_string(&index_path)
+        .unwrap()
+        .replace("\"production\"", "\"staging\"");
+    fs::write(&index_path, tampered).unwrap();
+    assert!(SearchIndex::load(index_path.clone()).is_err());
+    let storage = vault(root, true);
+    assert_eq!(titles(&storage, "production"), ["Release checklist"]);
+    assert!(titles(&storage, "staging").is_empty());
+
+    // Reindexing starts over from the notes
+    fs::remove_file(&index_path).unwrap();
+    assert_eq!(storage.rebuild_search_index().unwrap(), 3);
+    assert_eq!(SearchIndex::load(index_path).unwrap().unwrap().len(), 3);
+}
@@ -0,0 +1,92 @@
+//! Checks paged searches: pages follow the ranking, the total counts every
+//! match before paging, and structure filters and tag policies are applied
+//! before the page is cut.
+//!
+//! Run with `cargo test --test search_paging`.
+mod common;
+
+use kbnotes::*;
+
+#[test]
+fn search_paging() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let mut config = common::config(dir.path());
+    config.tags.policies.insert(
+        "private".to_string(),
+        TagPolicy {
+            exclude_from_search_default: Some(true),
+            ..TagPolicy::default()
+        },
+    );
+    let storage = common::open(config);
+
+    for i in 0..12 {
+        let title = format!("recipe{}", "s".repeat(i));
+        let content = if i % 2 == 0 { "- [ ] shop\n" } else { "" };
+        let tags = if i == 11 {
+            vec!["private".to_string()]
+        } else {
+            vec![]
+        };
+        storage
+            .save_note(&Note::new(title, content.to_string(), tags))
+            .unwrap();
+    }
+    let everything = storage.search_notes("recipe", false).unwrap();
+    assert_eq!(everything.total, 12);
+    assert_eq!(everything.hits.len(), 12);
+    assert_eq!(everything.hidden, 0);
+
+    // Pages are consecutive slices of the full ranking
+    let page = |offset, limit| {
+        storage
+            .search_notes_filtered(
+                "recipe",
+                &SearchRequest {
+                    offset,
+                    limit,
+                    ..SearchRequest::default()
+                },
+            )
+            .unwrap()
+    };
+    let mut paged = Vec::new();
+    for offset in [0, 5, 10] {
+        let results = page(offset, Some(5));
+        assert_eq!(results.total, 12);
+        paged.extend(results.hits);
+    }
+    assert_eq!(paged, everything.hits);
+    assert!(page(20, Some(5)).hits.is_empty());
+    assert_eq!(page(20, Some(5)).total, 12);
+    assert_eq!(page(3, None).hits, everything.hits[3..]);
+
+    // Structure filters and tag policies count towards the total
+    let results = storage
+        .search_notes_filtered(
+            "recipe",
+            &SearchRequest {
+                structure: vec![StructureFilter::Tasks],
+                hide_excluded_by_policy: true,
+                limit: Some(2),
+                ..SearchRequest::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(results.total, 6);
+    assert_eq!(results.hits.len(), 2);
+    assert!(results
+        .hits
+        .iter()
+        .all(|hit| hit.note.content.contains("[ ]")));
+    let results = storage
+        .search_notes_filtered(
+            "recipe",
+            &SearchRequest {
+                hide_excluded_by_policy: true,
+                ..SearchRequest::default()
+            },
+        )
+        .unwrap();
+    assert_eq!((results.total, results.hidden), (11, 1));
+}
@@ -0,0 +1,52 @@
+//! Checks the interactive search flags: `--then` needs `--interactive`, which
+//! doesn't combine with `--format`, and without a terminal the search falls
+//! back to printing the results.
+//!
+//! Run with `cargo test --test search_picker < /dev/null`.
+mod common;
+
+use std::sync::Arc;
+
+use clap::Parser;
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn search_picker() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let config = common::config(dir.path());
+    let storage = common::open(config.clone());
+    for title in ["Docker compose", "Docker swarm"] {
+        let note = Note::new(title.to_string(), "docker notes".to_string(), vec![]);
+        storage.save_note(&note).unwrap();
+    }
+
+    // Parsing
+    let parse =
+        |args: &[&str]| Cli::try_parse_from(["kbnotes", "search", "docker"].iter().chain(args));
+    let Commands::Search(options) = parse(&["-i", "--then", "edit"]).unwrap().command else {
+        panic!("expected the search command");
+    };
+    assert!(options.interactive);
+    assert_eq!(options.then, "edit");
+    let Commands::Search(options) = parse(&["--interactive"]).unwrap().command else {
+        panic!("expected the search command");
+    };
+    assert_eq!(options.then, "view");
+    assert!(parse(&["--then", "edit"]).is_err());
+    assert!(parse(&["-i", "--then", "delete"]).is_err());
+    assert!(parse(&["-i", "--format", "json"]).is_err());
+
+    // Without a terminal the results are printed instead of picked from
+    let app = common::app(Arc::new(Mutex::new(storage)), config);
+    if std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+        println!("stdin is a terminal, skipping the fallback check");
+    } else {
+        common::run(&app, &["search", "docker", "--interactive"])
+            .await
+            .unwrap();
+        common::run(&app, &["search", "nothing-matches", "-i"])
+            .await
+            .unwrap();
+    }
+}
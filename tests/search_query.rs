@@ -0,0 +1,101 @@
+//! Checks parsing of boolean search queries, their evaluation against title,
+//! content and tags, the fuzzy fallback and the positions reported for
+//! malformed queries.
+//!
+//! Run with `cargo test --test search_query`.
+mod common;
+
+use kbnotes::*;
+
+fn term(word: &str) -> Box<SearchExpr> {
+    Box::new(SearchExpr::Term(word.to_string()))
+}
+
+fn titles(notes: Vec<Note>) -> Vec<String> {
+    let mut titles: Vec<String> = notes.into_iter().map(|note| note.title).collect();
+    titles.sort();
+    titles
+}
+
+#[test]
+fn search_query() {
+    // Operators, precedence and implicit AND
+    assert_eq!(parse_search_query("docker compose").unwrap(), None);
+    assert_eq!(parse_search_query("rock and roll").unwrap(), None);
+    assert_eq!(
+        parse_search_query("docker AND compose NOT kubernetes").unwrap(),
+        Some(SearchExpr::And(
+            Box::new(SearchExpr::And(term("docker"), term("compose"))),
+            Box::new(SearchExpr::Not(term("kubernetes"))),
+        ))
+    );
+    assert_eq!(
+        parse_search_query("a OR b c").unwrap(),
+        Some(SearchExpr::Or(
+            term("a"),
+            Box::new(SearchExpr::And(term("b"), term("c")))
+        ))
+    );
+    assert_eq!(
+        parse_search_query("(a OR b) \"x y\"").unwrap(),
+        Some(SearchExpr::And(
+            Box::new(SearchExpr::Or(term("a"), term("b"))),
+            Box::new(SearchExpr::Phrase("x y".to_string())),
+        ))
+    );
+
+    // Malformed queries name the column of the problem
+    for (query, column) in [
+        ("docker AND", 11),
+        ("AND docker", 1),
+        ("docker OR OR compose", 11),
+        ("NOT", 4),
+        ("say \"hello", 5),
+        ("\"\" x", 1),
+        ("(docker OR compose", 1),
+        ("docker) x", 7),
+        ("() x", 2),
+    ] {
+        match parse_search_query(query) {
+            Err(KbError::InvalidFormat { message }) => assert!(
+                message.contains(&format!("at column {}:", column)),
+                "{:?}: {}",
+                query,
+                message
+            ),
+            other => panic!("{:?} should fail, got {:?}", query, other),
+        }
+    }
+
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let config = common::config(dir.path());
+    let storage = common::open(config);
+    for (title, content, tags) in [
+        ("Compose setup", "docker compose up -d", vec!["docker"]),
+        ("Cluster", "docker images deployed with kubernetes", vec![]),
+        ("Podman", "rootless containers, compose works too", vec![]),
+        ("Release checklist", "tag, build, deploy", vec!["ops"]),
+    ] {
+        let tags = tags.into_iter().map(str::to_string).collect();
+        storage
+            .save_note(&Note::new(title.to_string(), content.to_string(), tags))
+            .unwrap();
+    }
+
+    let search = |query: &str| titles(storage.search_notes(query, false).unwrap().into_notes());
+    assert_eq!(
+        search("docker AND compose NOT kubernetes"),
+        ["Compose setup"]
+    );
+    assert_eq!(search("kubernetes OR podman"), ["Cluster", "Podman"]);
+    assert_eq!(search("NOT docker NOT compose"), ["Release checklist"]);
+    assert_eq!(search("\"ops\""), ["Release checklist"]);
+    // Phrases must appear as written, ignoring case
+    assert_eq!(search("\"CHECKLIST\""), ["Release checklist"]);
+    assert!(search("\"rootless compose\"").is_empty());
+    assert_eq!(search("rootless compose"), ["Podman"]);
+    assert!(matches!(
+        storage.search_notes("docker AND", false),
+        Err(KbError::InvalidFormat { .. })
+    ));
+}
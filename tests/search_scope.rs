@@ -0,0 +1,127 @@
+//! Checks title-only and content-only searches: the other field adds
+//! nothing to the score, snippets come from the searched field, and the two
+//! flags can't be combined.
+//!
+//! Run with `cargo test --test search_scope`.
+mod common;
+
+use clap::Parser;
+use kbnotes::*;
+
+#[test]
+fn search_scope() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let config = common::config(dir.path());
+    let storage = common::open(config);
+    let mut ids = Vec::new();
+    for (title, content, tags) in [
+        ("Kubernetes", "kubernetes kubernetes kubernetes", vec![]),
+        ("Docker", "see the kubernetes note", vec![]),
+        ("Cluster setup", "nodes and pods", vec!["kubernetes"]),
+    ] {
+        let tags = tags.into_iter().map(str::to_string).collect();
+        let note = Note::new(title.to_string(), content.to_string(), tags);
+        storage.save_note(&note).unwrap();
+        ids.push(note);
+    }
+    let search = |query: &str, mode: SearchMode, scope: SearchScope| {
+        let mut titles: Vec<String> = storage
+            .search_notes_filtered(
+                query,
+                &SearchRequest {
+                    mode,
+                    scope,
+                    ..SearchRequest::default()
+                },
+            )
+            .unwrap()
+            .into_notes()
+            .into_iter()
+            .map(|note| note.title)
+            .collect();
+        titles.sort();
+        titles
+    };
+    let exact = SearchMode::Exact {
+        case_sensitive: false,
+    };
+
+    // A note whose content matches strongly is left out when only titles count
+    for mode in [SearchMode::Fuzzy, exact] {
+        assert!(search("kubernetes", mode, SearchScope::All).contains(&"Docker".to_string()));
+        assert_eq!(
+            search("kubernetes", mode, SearchScope::Content),
+            ["Docker", "Kubernetes"]
+        );
+        assert_eq!(search("docker", mode, SearchScope::Title), ["Docker"]);
+        assert!(search("pods", mode, SearchScope::Title).is_empty());
+        assert!(search("cluster", mode, SearchScope::Content).is_empty());
+    }
+    // Tags only count when searching everything
+    assert_eq!(
+        search("kubernetes", exact, SearchScope::All),
+        ["Cluster setup", "Docker", "Kubernetes"]
+    );
+    assert_eq!(
+        search("see AND note", SearchMode::Fuzzy, SearchScope::Content),
+        ["Docker"]
+    );
+    assert!(search("\"the kubernetes\"", SearchMode::Fuzzy, SearchScope::Title).is_empty());
+
+    // The other field contributes nothing to the score
+    let matcher = fuzzy_matcher::skim::SkimMatcherV2::default();
+    let weights = SearchWeights::default();
+    let kubernetes = &ids[0];
+    let all = fuzzy_score(
+        kubernetes,
+        "kubernetes",
+        SearchScope::All,
+        weights,
+        &matcher,
+    )
+    .score;
+    let title = fuzzy_score(
+        kubernetes,
+        "kubernetes",
+        SearchScope::Title,
+        weights,
+        &matcher,
+    )
+    .score;
+    let content = fuzzy_score(
+        kubernetes,
+        "kubernetes",
+        SearchScope::Content,
+        weights,
+        &matcher,
+    )
+    .score;
+    assert!(title > 0 && content > 0);
+    assert_eq!(all, title + content);
+    assert_eq!(
+        exact_score(
+            kubernetes,
+            "kubernetes",
+            false,
+            SearchScope::Content,
+            weights
+        )
+        .score,
+        3
+    );
+
+    // Snippets come from the searched field
+    let snippet = |scope| {
+        search_snippet_with_mode(kubernetes, "kubernetes", SearchMode::Fuzzy, scope)
+            .unwrap()
+            .field
+    };
+    assert_eq!(snippet(SearchScope::All), SnippetField::Title);
+    assert_eq!(snippet(SearchScope::Content), SnippetField::Content);
+
+    // The flags exclude each other
+    assert!(Cli::try_parse_from(["kbnotes", "search", "x", "--title-only"]).is_ok());
+    assert!(
+        Cli::try_parse_from(["kbnotes", "search", "x", "--title-only", "--content-only"]).is_err()
+    );
+}
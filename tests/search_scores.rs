@@ -0,0 +1,121 @@
+//! Checks search scores: results carry their score and matched fields, the
+//! title and content weights come from the configuration, and `search`
+//! accepts `--show-scores`.
+//!
+//! Run with `cargo test --test search_scores`.
+mod common;
+
+use std::path::Path;
+
+use clap::Parser;
+use kbnotes::*;
+
+fn vault(root: &Path, title_weight: Option<u32>, content_weight: Option<u32>) -> NoteStorage {
+    let mut config = common::config(root);
+    config.search_title_weight = title_weight;
+    config.search_content_weight = content_weight;
+    common::open(config)
+}
+
+fn exact(storage: &NoteStorage, query: &str) -> Vec<(String, i64)> {
+    storage
+        .search_notes_filtered(
+            query,
+            &SearchRequest {
+                mode: SearchMode::Exact {
+                    case_sensitive: false,
+                },
+                ..SearchRequest::default()
+            },
+        )
+        .unwrap()
+        .hits
+        .into_iter()
+        .map(|hit| (hit.note.title, hit.score))
+        .collect()
+}
+
+#[test]
+fn search_scores() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let root = dir.path();
+    let storage = vault(root, None, None);
+    for (title, content, tags) in [
+        ("Roadmap", "next quarter", vec![]),
+        ("Planning", "roadmap, roadmap and more roadmap", vec![]),
+        ("Ideas", "nothing here", vec!["roadmap"]),
+    ] {
+        let tags = tags.into_iter().map(str::to_string).collect();
+        storage
+            .save_note(&Note::new(title.to_string(), content.to_string(), tags))
+            .unwrap();
+    }
+
+    // Hits are sorted by score and say which fields matched
+    let results = storage.search_notes("roadmap", false).unwrap();
+    assert!(results
+        .hits
+        .windows(2)
+        .all(|pair| pair[0].score >= pair[1].score));
+    let hit = |title: &str| {
+        results
+            .hits
+            .iter()
+            .find(|hit| hit.note.title == title)
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(hit("Roadmap").matched_fields.names(), ["title"]);
+    assert_eq!(hit("Planning").matched_fields.names(), ["content"]);
+    let boolean = storage.search_notes("roadmap OR quarter", false).unwrap();
+    let roadmap = boolean
+        .hits
+        .iter()
+        .find(|hit| hit.note.title == "Roadmap")
+        .unwrap();
+    assert_eq!(roadmap.matched_fields.names(), ["title", "content"]);
+
+    // Default weights: title occurrences count double, tags once
+    assert_eq!(
+        exact(&storage, "roadmap"),
+        [
+            ("Planning".to_string(), 3),
+            ("Roadmap".to_string(), 2),
+            ("Ideas".to_string(), 1)
+        ]
+    );
+    // A title-heavy vault can make the title win
+    let title_heavy = vault(root, Some(5), None);
+    assert_eq!(
+        exact(&title_heavy, "roadmap")[0],
+        ("Roadmap".to_string(), 5)
+    );
+    let content_heavy = vault(root, Some(1), Some(4));
+    assert_eq!(
+        exact(&content_heavy, "roadmap")[..2],
+        [("Planning".to_string(), 12), ("Roadmap".to_string(), 1)]
+    );
+    let fuzzy = |storage: &NoteStorage| {
+        storage
+            .search_notes("roadmap", false)
+            .unwrap()
+            .hits
+            .into_iter()
+            .map(|hit| hit.note.title)
+            .next()
+            .unwrap()
+    };
+    assert_eq!(fuzzy(&title_heavy), "Roadmap");
+    assert_eq!(fuzzy(&vault(root, Some(1), Some(10))), "Planning");
+
+    // Weights are settable, can be reset, and can't be zero
+    let mut config = common::config(root);
+    assert_eq!(config.search_weights(), SearchWeights::default());
+    config.set_value("search_title_weight", "5").unwrap();
+    assert_eq!(config.search_weights().title, 5);
+    assert!(config.set_value("search_content_weight", "0").is_err());
+    config.set_value("search_title_weight", "none").unwrap();
+    assert_eq!(config.search_weights(), SearchWeights::default());
+
+    assert!(Cli::try_parse_from(["kbnotes", "search", "roadmap", "--show-scores"]).is_ok());
+}
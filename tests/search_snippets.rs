@@ -0,0 +1,68 @@
+//! Checks search result snippets: picking the best matching line, windowing
+//! long lines around the match, match positions and highlighting.
+//!
+//! Run with `cargo test --test search_snippets`.
+use kbnotes::*;
+
+fn note(title: &str, content: &str) -> Note {
+    Note::new(title.to_string(), content.to_string(), vec![])
+}
+
+/// The characters of a snippet at its match positions
+fn matched(snippet: &SearchSnippet) -> String {
+    let chars: Vec<char> = snippet.text.chars().collect();
+    snippet
+        .match_positions
+        .iter()
+        .map(|&position| chars[position])
+        .collect()
+}
+
+#[test]
+fn search_snippets() {
+    // The best line wins, titles counting double
+    let plain = note("Setup", "intro\n  run docker compose up\nthe end");
+    let snippet = search_snippet(&plain, "compose").unwrap();
+    assert_eq!(snippet.field, SnippetField::Content);
+    assert_eq!(snippet.text, "run docker compose up");
+    assert_eq!(matched(&snippet), "compose");
+    let titled = note("Compose notes", "compose");
+    assert_eq!(
+        search_snippet(&titled, "compose").unwrap().field,
+        SnippetField::Title
+    );
+
+    // Boolean queries highlight the terms that must be present
+    let snippet = search_snippet(&plain, "\"DOCKER compose\" NOT kubernetes").unwrap();
+    assert_eq!(matched(&snippet), "docker compose");
+    assert!(search_snippet(&plain, "NOT kubernetes").is_none());
+
+    // Long lines are cut around the match, keeping positions on the match
+    let long = format!("{}the needle here{}", "é".repeat(300), "ü".repeat(300));
+    let snippet = search_snippet(&note("Long", &long), "\"needle\"").unwrap();
+    assert!(snippet.text.starts_with('…') && snippet.text.ends_with('…'));
+    assert_eq!(snippet.text.chars().count(), SNIPPET_WIDTH + 2);
+    assert_eq!(matched(&snippet), "needle");
+    let center = snippet.match_positions[0];
+    assert!((SNIPPET_WIDTH / 2 - 10..SNIPPET_WIDTH / 2 + 10).contains(&center));
+    let start = search_snippet(
+        &note("Long", &format!("needle {}", "x".repeat(300))),
+        "needle",
+    )
+    .unwrap();
+    assert!(!start.text.starts_with('…') && start.text.ends_with('…'));
+    assert_eq!(start.match_positions, [0, 1, 2, 3, 4, 5]);
+
+    // Highlighting follows the color setting
+    let snippet = search_snippet(&plain, "compose").unwrap();
+    console::set_colors_enabled(false);
+    assert_eq!(snippet.highlighted(), snippet.text);
+    console::set_colors_enabled(true);
+    let highlighted = snippet.highlighted();
+    assert!(highlighted.contains('\u{1b}'));
+    assert_eq!(console::strip_ansi_codes(&highlighted), snippet.text);
+
+    let json = serde_json::to_value(&snippet).unwrap();
+    assert_eq!(json["field"], "content");
+    assert_eq!(json["match_positions"][0], 11);
+}
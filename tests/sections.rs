@@ -0,0 +1,116 @@
+//! Checks section lookup by heading name, line ranges and block-by-block
+//! splitting of note content, as used by `kbnotes view`.
+//!
+//! Run with `cargo test --test sections`.
+use kbnotes::*;
+
+const GUIDE: &str = "# Guide
+
+Intro
+
+## Setup
+
+Setup text
+
+### Install
+
+Install steps
+
+#### Troubleshooting
+
+Check the logs
+
+## Usage
+
+```sh
+# not a heading
+run it
+```
+
+### Install
+
+Plugin install
+
+# Appendix
+
+The end
+";
+
+#[test]
+fn sections() {
+    // A section includes everything nested below its heading
+    let setup = find_section(GUIDE, "setup").unwrap();
+    assert!(setup.starts_with("## Setup"));
+    assert!(setup.contains("### Install") && setup.contains("Check the logs"));
+    assert!(!setup.contains("## Usage"));
+
+    let guide = find_section(GUIDE, "Guide").unwrap();
+    assert!(guide.contains("Plugin install") && !guide.contains("Appendix"));
+    assert_eq!(
+        find_section(GUIDE, "appendix").unwrap(),
+        "# Appendix\n\nThe end"
+    );
+
+    // A name shared by several headings lists every candidate
+    match find_section(GUIDE, "Install") {
+        Err(KbError::AmbiguousSection { candidates, .. }) => assert_eq!(
+            candidates,
+            [
+                "Guide > Setup > Install (line 9)",
+                "Guide > Usage > Install (line 24)"
+            ]
+        ),
+        other => panic!("expected an ambiguous section, got {:?}", other),
+    }
+
+    // ...and a path picks one of them
+    let install = find_section(GUIDE, "Usage > Install").unwrap();
+    assert_eq!(install, "### Install\n\nPlugin install");
+    let install = find_section(GUIDE, "guide > setup > install").unwrap();
+    assert!(install.contains("Troubleshooting"));
+
+    // Code blocks don't contain headings, and unknown names are reported
+    assert!(matches!(
+        find_section(GUIDE, "not a heading"),
+        Err(KbError::SectionNotFound { .. })
+    ));
+    assert!(matches!(
+        find_section(GUIDE, "Appendix > Install"),
+        Err(KbError::SectionNotFound { .. })
+    ));
+
+    // Line ranges
+    let range: LineRange = "3:5".parse().unwrap();
+    assert_eq!(range.slice(GUIDE), "Intro\n\n## Setup\n");
+    assert_eq!("2".parse::<LineRange>().unwrap().slice(GUIDE), "\n");
+    assert_eq!(":1".parse::<LineRange>().unwrap().slice(GUIDE), "# Guide\n");
+    assert!("30:"
+        .parse::<LineRange>()
+        .unwrap()
+        .slice(GUIDE)
+        .starts_with("The end"));
+    assert_eq!("100:".parse::<LineRange>().unwrap().slice(GUIDE), "");
+    for invalid in ["0:3", "5:2", "a:b", ""] {
+        assert!(matches!(
+            invalid.parse::<LineRange>(),
+            Err(KbError::InvalidFormat { .. })
+        ));
+    }
+
+    // Blocks add up to the content, one top-level block at a time
+    let blocks: Vec<&str> = MarkdownBlocks::new(GUIDE).collect();
+    assert_eq!(blocks.concat(), GUIDE);
+    assert!(blocks
+        .iter()
+        .any(|block| block.trim_start().starts_with("```sh")));
+    assert_eq!(blocks.len(), 14);
+
+    let large: String = (0..20_000)
+        .map(|i| format!("## Entry {}\n\n- item\n- [ ] task\n\n", i))
+        .collect();
+    assert_eq!(
+        MarkdownBlocks::new(&large).map(str::len).sum::<usize>(),
+        large.len()
+    );
+    assert_eq!(MarkdownBlocks::new(&large).take(3).count(), 3);
+}
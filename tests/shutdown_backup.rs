@@ -0,0 +1,65 @@
+//! Checks `backup_on_shutdown`: with it on, shutting down storage writes one
+//! final full backup holding the latest changes and leaves no temporary
+//! file behind, and with it off, shutting down writes no backup.
+//!
+//! Run with `cargo test --test shutdown_backup`.
+mod common;
+
+use std::{fs, path::Path};
+
+use kbnotes::*;
+
+/// Full backups in the backup directory
+fn full_backups(backups: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(backups) else {
+        return Vec::new();
+    };
+    entries
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("kbnotes_backup_") && name.ends_with(".zip"))
+        .collect()
+}
+
+/// Starts storage, saves a note and shuts down, returning the backups left
+async fn run(backup_on_shutdown: bool) -> Vec<String> {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let backups = dir.path().join("backups");
+    let mut config = common::config(dir.path());
+    config.fsync = FsyncMode::Never;
+    config.backup_on_shutdown = backup_on_shutdown;
+    let storage = common::start(config).await;
+
+    let note = Note::new("Alpha".to_string(), "alpha".to_string(), vec![]);
+    storage.lock().await.save_note(&note).unwrap();
+    assert!(full_backups(&backups).is_empty());
+    storage.lock().await.shutdown().await.unwrap();
+
+    let names = full_backups(&backups);
+    // The archive is written to a temporary file, renamed into place
+    if let Ok(entries) = fs::read_dir(&backups) {
+        for entry in entries {
+            let name = entry.unwrap().file_name().to_string_lossy().into_owned();
+            assert!(!name.starts_with(".tmp"), "{}", name);
+        }
+    }
+    for name in &names {
+        let storage = storage.lock().await;
+        let manifest = storage
+            .read_backup_manifest(&backups.join(name))
+            .unwrap()
+            .unwrap();
+        assert_eq!(manifest.notes, 1);
+    }
+    names
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown_backup() {
+    assert_eq!(run(true).await.len(), 1);
+
+    assert!(run(false).await.is_empty());
+
+    let mut config = Config::with_dirs("notes".into(), "backups".into());
+    config.set_value("backup_on_shutdown", "true").unwrap();
+    assert!(config.backup_on_shutdown);
+}
@@ -0,0 +1,312 @@
+//! Checks the split storage format: notes round trip through a plain `.md`
+//! content file and a `.meta.json` sidecar, content edited by other tools is
+//! picked up with its metadata kept and `updated_at` bumped, half-written
+//! pairs are handled, the watcher reloads a note once per change, and vaults
+//! convert to and from the format and back up and restore.
+//!
+//! Run with `cargo test --test split_storage`.
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{TimeZone, Utc};
+use clap::Parser;
+use kbnotes::*;
+use tokio::sync::broadcast;
+use walkdir::WalkDir;
+
+/// Files below the notes directory, leaving out storage state and history
+fn vault_files(root: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<_> = WalkDir::new(root.join("notes"))
+        .into_iter()
+        .filter_entry(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.path().to_path_buf())
+        .collect();
+    files.sort();
+    files
+}
+
+fn config(root: &Path, format: StorageFormat) -> Config {
+    let mut config = common::config(root);
+    config.fsync = FsyncMode::Never;
+    config.storage_format = format;
+    config
+}
+
+fn vault(root: &Path, format: StorageFormat) -> NoteStorage {
+    common::open(config(root, format))
+}
+
+/// Where a note is stored in `format`, by the default ID policy
+fn note_file(root: &Path, id: &str, format: StorageFormat) -> PathBuf {
+    root.join("notes")
+        .join(&id[..2])
+        .join(format!("{}.{}", id, format.extension()))
+}
+
+fn content_path(root: &Path, id: &str) -> PathBuf {
+    note_file(root, id, StorageFormat::Markdown)
+}
+
+/// Replaces a file the way editors save, through a rename
+fn write_atomically(path: &Path, text: &str) {
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, text).unwrap();
+    fs::rename(&temp, path).unwrap();
+}
+
+/// Collects the watcher's events about `id` until none arrive for a while
+async fn events_for(events: &mut broadcast::Receiver<NoteEvent>, id: &str) -> Vec<NoteEvent> {
+    let mut seen = Vec::new();
+    while let Ok(event) = tokio::time::timeout(Duration::from_millis(1500), events.recv()).await {
+        let event = event.unwrap();
+        if event.note_id == id {
+            seen.push(event);
+        }
+    }
+    seen
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn split_storage() {
+    let old = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+    let mut notes: Vec<Note> = ["", "plain text", "---\nlooks: like front matter\n---\n"]
+        .iter()
+        .map(|content| {
+            let mut note = Note::new(
+                "Split".to_string(),
+                content.to_string(),
+                vec!["split".to_string()],
+            );
+            note.updated_at = old;
+            note
+        })
+        .collect();
+    notes[1]
+        .metadata
+        .insert("source".to_string(), "elsewhere".to_string());
+    notes[1].aliases = vec!["plain".to_string()];
+
+    // The sidecar holds everything but the content, and a hash of it
+    for note in &notes {
+        let (sidecar, content) = note_to_split(note).unwrap();
+        assert_eq!(content, note.content);
+        assert!(!sidecar.contains("\"content\""));
+        assert_eq!(
+            note_from_split(&sidecar, content).unwrap(),
+            (note.clone(), false)
+        );
+        let (edited, changed) = note_from_split(&sidecar, "changed".to_string()).unwrap();
+        assert!(changed);
+        assert_eq!(edited.content, "changed");
+        assert_eq!(edited.title, note.title);
+    }
+    assert_eq!(
+        StorageFormat::of_path(Path::new("ab/abc.meta.json")),
+        Some(StorageFormat::Split)
+    );
+    assert_eq!(
+        "split".parse::<StorageFormat>().unwrap(),
+        StorageFormat::Split
+    );
+
+    // A split vault writes both files, and only the sidecar counts as a note
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let root = dir.path();
+    let storage = vault(root, StorageFormat::Split);
+    for note in &notes {
+        storage.save_note(note).unwrap();
+    }
+    let files = vault_files(root);
+    assert_eq!(files.len(), 2 * notes.len());
+    assert_eq!(
+        files.iter().filter(|path| is_note_file(path)).count(),
+        notes.len()
+    );
+    for note in &notes {
+        let sidecar = note_file(root, &note.id, StorageFormat::Split);
+        assert!(sidecar.to_string_lossy().ends_with(".meta.json"));
+        assert!(is_note_file(&sidecar));
+        let content = content_path(root, &note.id);
+        assert_eq!(fs::read_to_string(&content).unwrap(), note.content);
+        assert!(is_split_content(&content) && !is_note_file(&content));
+    }
+    let reloaded = vault(root, StorageFormat::Split);
+    for note in &notes {
+        assert_eq!(reloaded.get_note(&note.id).as_ref(), Some(note));
+    }
+    assert!(reloaded.check_layout().unwrap().is_empty());
+
+    // Content edited elsewhere is taken with its metadata, and bumps updated_at
+    fs::write(content_path(root, &notes[1].id), "edited elsewhere").unwrap();
+    let edited = vault(root, StorageFormat::Split)
+        .get_note(&notes[1].id)
+        .unwrap();
+    assert_eq!(edited.content, "edited elsewhere");
+    assert!(edited.updated_at > old);
+    assert_eq!(edited.metadata, notes[1].metadata);
+    assert_eq!(edited.aliases, notes[1].aliases);
+    assert_eq!(edited.created_at, notes[1].created_at);
+    // Saving records the new content, after which nothing looks edited
+    storage.update_note(edited.clone()).unwrap();
+    let saved = vault(root, StorageFormat::Split)
+        .get_note(&notes[1].id)
+        .unwrap();
+    let (sidecar, content) = note_to_split(&saved).unwrap();
+    assert!(!note_from_split(&sidecar, content).unwrap().1);
+    notes[1] = saved;
+
+    // Half-written pairs: content without a sidecar is left alone, a sidecar
+    // without content is quarantined and salvaged with whatever is left
+    let orphan = root.join("notes/or/orphan.md");
+    fs::create_dir_all(orphan.parent().unwrap()).unwrap();
+    fs::write(&orphan, "no sidecar yet").unwrap();
+    assert!(is_orphaned_split_content(&orphan));
+    let lost = content_path(root, &notes[0].id);
+    fs::remove_file(&lost).unwrap();
+    let partial = vault(root, StorageFormat::Split);
+    assert_eq!(partial.get_all_notes().unwrap().len(), notes.len() - 1);
+    assert!(orphan.exists());
+    let quarantined = partial.list_quarantined().unwrap();
+    assert_eq!(quarantined.len(), 1);
+    let salvaged = partial.restore_quarantined(&quarantined[0]).unwrap();
+    assert_eq!(salvaged.id, notes[0].id);
+    assert_eq!(salvaged.title, notes[0].title);
+    assert!(lost.exists());
+    fs::remove_file(&orphan).unwrap();
+
+    // The watcher reloads a note once per change, whichever file changed
+    let shared = common::start(config(root, StorageFormat::Split)).await;
+    let mut events = shared.lock().await.subscribe_events();
+    let id = notes[2].id.clone();
+    let path = content_path(root, &id);
+
+    write_atomically(&path, "edited in another tool");
+    let seen = events_for(&mut events, &id).await;
+    assert_eq!(seen.len(), 1, "{:?}", seen);
+    assert!(seen[0].external && seen[0].kind == NoteEventKind::Updated);
+    let cached = shared.lock().await.get_note(&id).unwrap();
+    assert_eq!(cached.content, "edited in another tool");
+    assert_eq!(cached.tags, notes[2].tags);
+
+    // A content file alone is no note; its sidecar makes it one
+    let mut created = Note::new("Created".to_string(), "body".to_string(), vec![]);
+    created.id = "watched".to_string();
+    let sidecar = note_file(root, &created.id, StorageFormat::Split);
+    fs::create_dir_all(sidecar.parent().unwrap()).unwrap();
+    let (meta, content) = note_to_split(&created).unwrap();
+    fs::write(content_path(root, &created.id), content).unwrap();
+    assert!(events_for(&mut events, &created.id).await.is_empty());
+    assert!(shared.lock().await.get_note(&created.id).is_none());
+    write_atomically(&sidecar, &meta);
+    let seen = events_for(&mut events, &created.id).await;
+    assert_eq!(seen.len(), 1, "{:?}", seen);
+    assert_eq!(seen[0].kind, NoteEventKind::Created);
+    assert_eq!(
+        shared.lock().await.get_note(&created.id),
+        Some(created.clone())
+    );
+
+    // Removing the content file keeps the note until its sidecar goes too
+    fs::remove_file(content_path(root, &created.id)).unwrap();
+    assert!(events_for(&mut events, &created.id).await.is_empty());
+    assert!(shared.lock().await.get_note(&created.id).is_some());
+    fs::remove_file(&sidecar).unwrap();
+    let seen = events_for(&mut events, &created.id).await;
+    assert_eq!(seen.len(), 1, "{:?}", seen);
+    assert_eq!(seen[0].kind, NoteEventKind::Deleted);
+    shared.lock().await.stop_watcher().await.unwrap();
+
+    // Converting in place keeps every note, through every pair of formats
+    let expected: Vec<Note> = {
+        let storage = vault(root, StorageFormat::Split);
+        let mut notes = storage.get_all_notes().unwrap();
+        notes.sort_by(|a, b| a.id.cmp(&b.id));
+        notes
+    };
+    for format in [
+        StorageFormat::Markdown,
+        StorageFormat::Split,
+        StorageFormat::Json,
+        StorageFormat::Split,
+    ] {
+        let mut storage = vault(root, format);
+        storage.migrate_storage_format(format).unwrap();
+        let files = vault_files(root);
+        let per_note = if format == StorageFormat::Split { 2 } else { 1 };
+        assert_eq!(files.len(), per_note * expected.len(), "{}", format);
+        assert!(files
+            .iter()
+            .filter(|path| is_note_file(path))
+            .all(|path| StorageFormat::of_path(path) == Some(format)));
+        let reloaded = vault(root, format);
+        for note in &expected {
+            assert_eq!(reloaded.get_note(&note.id).as_ref(), Some(note));
+        }
+        assert_eq!(
+            vault(root, format).migrate_storage_format(format).unwrap(),
+            0
+        );
+    }
+
+    // A conversion from Markdown interrupted after the sidecar was written
+    let note = &expected[0];
+    let markdown = root.join("interrupted");
+    let storage = vault(&markdown, StorageFormat::Markdown);
+    storage.save_note(note).unwrap();
+    let (meta, _) = note_to_split(note).unwrap();
+    fs::write(note_file(&markdown, &note.id, StorageFormat::Split), meta).unwrap();
+    assert_eq!(
+        vault(&markdown, StorageFormat::Split)
+            .get_note(&note.id)
+            .as_ref(),
+        Some(note)
+    );
+    let mut resumed = vault(&markdown, StorageFormat::Split);
+    resumed
+        .migrate_storage_format(StorageFormat::Split)
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(content_path(&markdown, &note.id)).unwrap(),
+        note.content
+    );
+    assert_eq!(
+        vault(&markdown, StorageFormat::Split)
+            .get_note(&note.id)
+            .as_ref(),
+        Some(note)
+    );
+
+    // Backups of a split vault hold JSON notes and restore into any format
+    let backup = root.join("backup.zip");
+    vault(root, StorageFormat::Split)
+        .create_full_backup_to(&backup)
+        .unwrap();
+    for format in [StorageFormat::Split, StorageFormat::Markdown] {
+        let target_root = root.join(format!("restored-{}", format));
+        let target = vault(&target_root, format);
+        let summary = target
+            .restore_full_backup(&backup, &RestoreOptions::default())
+            .unwrap();
+        assert_eq!(summary.notes_restored, expected.len());
+        for note in &expected {
+            assert_eq!(target.get_note(&note.id).as_ref(), Some(note));
+        }
+    }
+
+    // Deleting removes both files
+    let storage = vault(root, StorageFormat::Split);
+    storage.delete_note(&expected[0].id).unwrap();
+    assert_eq!(vault_files(root).len(), 2 * (expected.len() - 1));
+
+    // The command line converts to the split format
+    let cli = Cli::try_parse_from(["kbnotes", "migrate-format", "--to", "split"]).unwrap();
+    assert!(matches!(cli.command, Commands::MigrateFormat { to: Some(to), .. } if to == "split"));
+    assert!(Cli::try_parse_from(["kbnotes", "migrate-format", "--to", "sidecar"]).is_err());
+}
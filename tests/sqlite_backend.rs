@@ -0,0 +1,298 @@
+//! Checks the SQLite storage backend: migrating a vault of note files into
+//! the database and back, reloading from it, tag and date queries answered
+//! by SQL matching the in-memory filter, backups holding the database
+//! (without excluded notes) next to JSON notes, plain and staged restores
+//! across backends, and `kbnotes migrate-backend`.
+//!
+//! Run with `cargo test --test sqlite_backend`.
+mod common;
+
+use std::{collections::BTreeMap, fs, io::Read, path::Path, sync::Arc};
+
+use chrono::{Duration, TimeZone, Utc};
+use clap::Parser;
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+/// Notes of a vault by ID, for comparing vaults
+fn snapshot(storage: &NoteStorage) -> BTreeMap<String, Note> {
+    storage
+        .get_all_notes()
+        .unwrap()
+        .into_iter()
+        .map(|note| (note.id.clone(), note))
+        .collect()
+}
+
+fn sorted_ids(notes: Vec<Note>) -> Vec<String> {
+    let mut ids: Vec<String> = notes.into_iter().map(|note| note.id).collect();
+    ids.sort();
+    ids
+}
+
+/// A storage over `root` with the given backend, loaded
+fn open(root: &Path, backend: BackendKind) -> (NoteStorage, Config) {
+    let mut config = common::config(root);
+    config.fsync = FsyncMode::Never;
+    config.backend = backend;
+    config.tags.policies.insert(
+        "secret".to_string(),
+        TagPolicy {
+            exclude_from_backup: Some(true),
+            ..TagPolicy::default()
+        },
+    );
+    let storage = common::open(config.clone());
+    (storage, config)
+}
+
+/// Names of the entries of a ZIP archive
+fn zip_entries(path: &Path) -> Vec<String> {
+    let archive = zip::ZipArchive::new(fs::File::open(path).unwrap()).unwrap();
+    archive.file_names().map(str::to_string).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sqlite_backend() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let root = dir.path().join("vault");
+    let (mut storage, _) = open(&root, BackendKind::Files);
+
+    let base = Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap();
+    for i in 0..60 {
+        let mut tags = vec![["Work", "home", "travel"][i % 3].to_string()];
+        if i % 4 == 0 {
+            tags.push("urgent".to_string());
+        }
+        let mut note = Note::new(
+            format!("Note {}", i),
+            format!(
+                "Body {} mentions {}",
+                i,
+                if i % 5 == 0 { "Rust" } else { "tea" }
+            ),
+            tags,
+        );
+        note.created_at = base + Duration::days(i as i64);
+        note.updated_at = note.created_at + Duration::hours(i as i64);
+        storage.save_note(&note).unwrap();
+    }
+    let secret = Note::new(
+        "Secret".to_string(),
+        "launch codes".to_string(),
+        vec!["secret".to_string()],
+    );
+    storage.save_note(&secret).unwrap();
+    let original = snapshot(&storage);
+
+    // Files to SQLite, once
+    assert_eq!(storage.migrate_backend(BackendKind::Sqlite).unwrap(), 61);
+    assert_eq!(storage.backend_kind(), BackendKind::Sqlite);
+    assert!(root.join("notes").join(SQLITE_DB_FILE).exists());
+    assert!(matches!(
+        storage.migrate_backend(BackendKind::Sqlite),
+        Err(KbError::InvalidArgument { .. })
+    ));
+    let (mut storage, config) = open(&root, BackendKind::Sqlite);
+    assert_eq!(snapshot(&storage), original);
+
+    // SQL answers tag and date filters like the in-memory filter does
+    let filters = [
+        NoteFilter::default(),
+        NoteFilter {
+            tags: vec!["WORK".to_string(), "urgent".to_string()],
+            ..NoteFilter::default()
+        },
+        NoteFilter {
+            tags: vec!["work".to_string(), "Urgent".to_string()],
+            tag_match: TagMatch::All,
+            ..NoteFilter::default()
+        },
+        NoteFilter {
+            exclude_tags: vec!["home".to_string()],
+            since: Some(base + Duration::days(10)),
+            until: Some(base + Duration::days(40)),
+            ..NoteFilter::default()
+        },
+        NoteFilter {
+            tags: vec!["travel".to_string()],
+            updated_since: Some(base + Duration::days(20)),
+            updated_until: Some(base + Duration::days(50) + Duration::hours(50)),
+            ..NoteFilter::default()
+        },
+    ];
+    for filter in filters {
+        let expected: Vec<Note> = original
+            .values()
+            .filter(|note| filter.matches(note))
+            .cloned()
+            .collect();
+        assert_eq!(
+            sorted_ids(storage.query_notes(filter.clone()).unwrap()),
+            sorted_ids(expected.clone()),
+            "{:?}",
+            filter
+        );
+        let containing: Vec<Note> = expected
+            .into_iter()
+            .filter(|note| note.content.contains("Rust"))
+            .collect();
+        assert_eq!(
+            sorted_ids(storage.query_notes_containing(filter, "Rust").unwrap()),
+            sorted_ids(containing)
+        );
+    }
+
+    // Writes go to the database; the excluded note stays for the backup below
+    let mut public: Vec<Note> = storage
+        .get_all_notes()
+        .unwrap()
+        .into_iter()
+        .filter(|note| note.id != secret.id)
+        .collect();
+    let mut edited = public.remove(0);
+    edited.content = "edited in the database".to_string();
+    edited.tags = vec!["edited".to_string()];
+    storage.update_note(edited.clone()).unwrap();
+    storage.delete_note(&edited.id).unwrap();
+    let kept = public.remove(0);
+    let mut changed = kept.clone();
+    changed.tags.push("changed".to_string());
+    storage.update_note(changed.clone()).unwrap();
+    let (reloaded, _) = open(&root, BackendKind::Sqlite);
+    assert!(reloaded.get_note(&edited.id).is_none());
+    assert_eq!(reloaded.get_note(&kept.id).unwrap().tags, changed.tags);
+    let tagged = NoteFilter {
+        tags: vec!["changed".to_string()],
+        ..NoteFilter::default()
+    };
+    assert_eq!(
+        sorted_ids(reloaded.query_notes(tagged).unwrap()),
+        [kept.id.as_str()]
+    );
+    assert!(storage.verify_integrity().unwrap().missing_files.is_empty());
+
+    // Backups hold the database and the notes as JSON, without excluded notes
+    let backup = dir.path().join("sqlite.zip");
+    let manifest = storage.create_full_backup_to(&backup).unwrap();
+    assert_eq!(manifest.excluded_notes, [secret.id.as_str()]);
+    let entries = zip_entries(&backup);
+    assert!(entries.contains(&SQLITE_DB_FILE.to_string()));
+    assert_eq!(
+        entries
+            .iter()
+            .filter(|name| name.ends_with(".json"))
+            .count(),
+        manifest.notes + 2 // and the manifest and configuration
+    );
+    let mut database = Vec::new();
+    zip::ZipArchive::new(fs::File::open(&backup).unwrap())
+        .unwrap()
+        .by_name(SQLITE_DB_FILE)
+        .unwrap()
+        .read_to_end(&mut database)
+        .unwrap();
+    assert!(!database.windows(12).any(|bytes| bytes == b"launch codes"));
+    let copy = dir.path().join("copy.db");
+    fs::write(&copy, &database).unwrap();
+    let copied = SqliteBackend::new(copy, FsyncMode::Never);
+    assert_eq!(copied.list_ids().unwrap().len(), manifest.notes);
+    assert!(copied.load(&secret.id).unwrap().is_none());
+
+    // Plain restores read the JSON notes into either backend
+    let expected = snapshot(&storage);
+    for backend in [BackendKind::Sqlite, BackendKind::Files] {
+        let (target, _) = open(&dir.path().join(format!("plain-{}", backend)), backend);
+        let summary = target
+            .restore_full_backup(&backup, &RestoreOptions::default())
+            .unwrap();
+        assert_eq!(summary.notes_restored, manifest.notes);
+        let mut restored = snapshot(&target);
+        restored.insert(secret.id.clone(), secret.clone());
+        assert_eq!(restored, expected);
+    }
+
+    // Staged restores use the backup's database, or build one from a
+    // backup of note files; a vault of note files drops the database
+    let (mut files_vault, _) = open(&dir.path().join("files-vault"), BackendKind::Files);
+    let files_backup = dir.path().join("files.zip");
+    let moved = Note::new(
+        "Moved".to_string(),
+        "over".to_string(),
+        vec!["x".to_string()],
+    );
+    files_vault.save_note(&moved).unwrap();
+    files_vault.create_full_backup_to(&files_backup).unwrap();
+    assert!(!zip_entries(&files_backup).contains(&SQLITE_DB_FILE.to_string()));
+
+    let summary = storage
+        .restore_full_backup_staged(&files_backup)
+        .await
+        .unwrap();
+    assert_eq!(summary.notes_restored, 1);
+    assert_eq!(
+        sorted_ids(storage.get_all_notes().unwrap()),
+        [moved.id.as_str()]
+    );
+    let (reloaded, _) = open(&root, BackendKind::Sqlite);
+    assert_eq!(snapshot(&reloaded), snapshot(&storage));
+    assert!(reloaded.check_layout().unwrap().is_empty());
+
+    // Kept directories are named by the second
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    let summary = storage.restore_full_backup_staged(&backup).await.unwrap();
+    assert_eq!(summary.notes_restored, manifest.notes);
+    assert_eq!(snapshot(&storage).len(), manifest.notes);
+
+    files_vault
+        .restore_full_backup_staged(&backup)
+        .await
+        .unwrap();
+    assert_eq!(snapshot(&files_vault), snapshot(&storage));
+    assert!(!dir
+        .path()
+        .join("files-vault/notes")
+        .join(SQLITE_DB_FILE)
+        .exists());
+
+    // And back to note files
+    let before = snapshot(&storage);
+    assert_eq!(
+        storage.migrate_backend(BackendKind::Files).unwrap(),
+        before.len()
+    );
+    let (storage, _) = open(&root, BackendKind::Files);
+    assert_eq!(snapshot(&storage), before);
+    assert!(storage.check_layout().unwrap().is_empty());
+
+    // The command moves the notes and records the backend in the config file
+    let config_path = dir.path().join("config.json");
+    let app = App::new(
+        Arc::new(Mutex::new(storage)),
+        Config {
+            backend: BackendKind::Files,
+            ..config
+        },
+        ConfigOrigin {
+            path: config_path.clone(),
+            ..ConfigOrigin::default()
+        },
+        false,
+        true,
+    );
+    assert!(Cli::try_parse_from(["kbnotes", "migrate-backend", "postgres"]).is_err());
+    common::run(&app, &["migrate-backend", "sqlite"])
+        .await
+        .unwrap();
+    assert_eq!(
+        load_config_from_file(&config_path).unwrap().0.backend,
+        BackendKind::Sqlite
+    );
+    common::run(&app, &["status"]).await.unwrap();
+    assert!(common::run(&app, &["migrate-backend", "sqlite"])
+        .await
+        .is_err());
+    assert!(common::run(&app, &["config", "--set", "backend=files"])
+        .await
+        .is_err());
+}
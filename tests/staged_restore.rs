@@ -0,0 +1,130 @@
+//! Checks staged restores: swapping in a backup, rolling back, pruning old
+//! directories and refusing a backup that fails validation.
+//!
+//! Run with `cargo test --test staged_restore`.
+mod common;
+
+use std::{fs, io::Write, path::Path, time::Duration};
+
+use kbnotes::*;
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+/// Directories next to `notes_dir` whose name contains `suffix`
+fn kept_dirs(notes_dir: &Path, suffix: &str) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(notes_dir.parent().unwrap())
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with(&format!("notes.{}", suffix)))
+        .collect();
+    names.sort();
+    names
+}
+
+fn titles(storage: &NoteStorage) -> Vec<String> {
+    let mut titles: Vec<String> = storage
+        .get_all_notes()
+        .unwrap()
+        .into_iter()
+        .map(|note| note.title)
+        .collect();
+    titles.sort();
+    titles
+}
+
+/// Timestamps in directory names have second precision
+async fn next_second() {
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn staged_restore() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let notes_dir = dir.path().join("notes");
+    let mut config = common::config(dir.path());
+    config.max_pre_restore_dirs = 1;
+
+    let storage = common::start(config).await;
+    let mut storage = storage.lock().await;
+
+    let alpha = Note::new("Alpha".to_string(), "first".to_string(), vec![]);
+    let beta = Note::new("Beta".to_string(), "second".to_string(), vec![]);
+    storage.save_note(&alpha).unwrap();
+    storage.save_note(&beta).unwrap();
+    let backup = dir.path().join("snapshot.zip");
+    storage.create_full_backup_to(&backup).unwrap();
+
+    // Changes made after the backup disappear with the swap...
+    storage.delete_note(&alpha.id).unwrap();
+    let gamma = Note::new("Gamma".to_string(), "third".to_string(), vec![]);
+    storage.save_note(&gamma).unwrap();
+
+    let summary = storage.restore_full_backup_staged(&backup).await.unwrap();
+    assert_eq!(summary.notes_restored, 2);
+    assert_eq!(titles(&storage), ["Alpha", "Beta"]);
+    assert!(summary.previous_dir.exists());
+    assert!(kept_dirs(&notes_dir, STAGED_RESTORE_SUFFIX).is_empty());
+
+    // ...and the watcher follows the new directory
+    let mut events = storage.subscribe_events();
+    let mut external = storage.get_note(&beta.id).unwrap();
+    external.content = "edited outside".to_string();
+    external.updated_at = chrono::Utc::now();
+    let beta_path = walkdir::WalkDir::new(&notes_dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.into_path())
+        .find(|path| path.file_name().unwrap() == format!("{}.json", beta.id).as_str())
+        .expect("restored note file not found");
+    fs::write(&beta_path, serde_json::to_string_pretty(&external).unwrap()).unwrap();
+    let event = tokio::time::timeout(Duration::from_secs(10), events.recv())
+        .await
+        .expect("watcher did not report the external edit")
+        .unwrap();
+    assert_eq!(event.note_id, beta.id);
+    assert!(event.external);
+
+    // Rolling back brings back the notes directory as it was before the restore
+    let rollback = storage.rollback_restore().await.unwrap();
+    assert_eq!(rollback.restored_dir, summary.previous_dir);
+    assert_eq!(titles(&storage), ["Beta", "Gamma"]);
+    assert!(!summary.previous_dir.exists());
+    assert!(rollback.replaced_dir.exists());
+
+    // Only `max_pre_restore_dirs` directories of each kind are kept
+    next_second().await;
+    let first = storage.restore_full_backup_staged(&backup).await.unwrap();
+    next_second().await;
+    let second = storage.restore_full_backup_staged(&backup).await.unwrap();
+    assert_eq!(second.pruned_dirs, vec![first.previous_dir]);
+    assert_eq!(kept_dirs(&notes_dir, PRE_RESTORE_SUFFIX).len(), 1);
+
+    // A backup whose manifest does not match its contents is refused before
+    // anything is moved
+    let corrupt = dir.path().join("corrupt.zip");
+    let mut zip = ZipWriter::new(fs::File::create(&corrupt).unwrap());
+    zip.start_file(BACKUP_MANIFEST_ENTRY, SimpleFileOptions::default())
+        .unwrap();
+    let manifest = BackupManifest {
+        created_at: chrono::Utc::now(),
+        format_version: BACKUP_FORMAT_VERSION,
+        app_version: None,
+        notes: 5,
+        excluded_notes: Vec::new(),
+        incremental: None,
+    };
+    zip.write_all(serde_json::to_string(&manifest).unwrap().as_bytes())
+        .unwrap();
+    zip.finish().unwrap();
+
+    let before = titles(&storage);
+    next_second().await;
+    let error = storage
+        .restore_full_backup_staged(&corrupt)
+        .await
+        .expect_err("corrupt backup was restored");
+    assert!(matches!(error, KbError::RestoreFailed { .. }), "{}", error);
+    assert_eq!(titles(&storage), before);
+    assert!(kept_dirs(&notes_dir, STAGED_RESTORE_SUFFIX).is_empty());
+    assert_eq!(kept_dirs(&notes_dir, PRE_RESTORE_SUFFIX).len(), 1);
+}
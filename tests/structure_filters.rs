@@ -0,0 +1,130 @@
+//! Checks the structural `--has` filters and that the cached structure of a
+//! note follows its content across saves, external edits and reloads.
+//!
+//! Run with `cargo test --test structure_filters`.
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use kbnotes::*;
+
+fn matching(storage: &NoteStorage, filters: &[StructureFilter]) -> Vec<String> {
+    let notes = storage.get_all_notes().unwrap();
+    let mut titles: Vec<String> = storage
+        .filter_by_structure(notes, filters)
+        .unwrap()
+        .into_iter()
+        .map(|note| note.title)
+        .collect();
+    titles.sort();
+    titles
+}
+
+fn note_path(notes_dir: &Path, id: &str) -> PathBuf {
+    walkdir::WalkDir::new(notes_dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.into_path())
+        .find(|path| path.file_name().unwrap() == format!("{}.json", id).as_str())
+        .expect("note file not found")
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn structure_filters() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let notes_dir = dir.path().join("notes");
+    let config = common::config(dir.path());
+
+    let storage = common::start(config).await;
+    let mut storage = storage.lock().await;
+
+    let mut todo = Note::new(
+        "Todo".to_string(),
+        "- [ ] write docs\n- [x] ship it\n".to_string(),
+        vec![],
+    );
+    let code = Note::new(
+        "Code".to_string(),
+        "```rust\nfn main() {}\n```\n\n    indented\n".to_string(),
+        vec![],
+    );
+    let files = Note::new(
+        "Files".to_string(),
+        "![diagram](diagram.png) and [spec](https://example.com/spec)".to_string(),
+        vec![],
+    );
+    let linked = Note::new(
+        "Linked".to_string(),
+        "See [[Todo]] and [[Missing]].".to_string(),
+        vec![],
+    );
+    for note in [&todo, &code, &files, &linked] {
+        storage.save_note(note).unwrap();
+    }
+
+    use StructureFilter::*;
+    assert_eq!(matching(&storage, &[Tasks]), ["Todo"]);
+    assert_eq!(matching(&storage, &[Code]), ["Code"]);
+    assert_eq!(matching(&storage, &[Attachments]), ["Files"]);
+    assert_eq!(matching(&storage, &[Links]), ["Files", "Linked"]);
+    assert_eq!(matching(&storage, &[BrokenLinks]), ["Linked"]);
+    assert!(matching(&storage, &[Tasks, Code]).is_empty());
+
+    let summaries = storage
+        .structure_summaries(std::slice::from_ref(&code))
+        .unwrap();
+    assert_eq!(summaries[&code.id].structure.code_blocks, 2);
+
+    // Checking off the last task takes the note out of the tasks filter
+    todo.content = "- [x] write docs\n- [x] ship it\n```sh\nmake\n```\n".to_string();
+    storage.save_note(&todo).unwrap();
+    assert!(matching(&storage, &[Tasks]).is_empty());
+    assert_eq!(matching(&storage, &[Code]), ["Code", "Todo"]);
+    let summaries = storage
+        .structure_summaries(std::slice::from_ref(&todo))
+        .unwrap();
+    assert_eq!(summaries[&todo.id].structure.tasks, 2);
+    assert_eq!(summaries[&todo.id].structure.open_tasks, 0);
+
+    // A link stops being broken once a note with that title exists
+    let missing = Note::new("Missing".to_string(), String::new(), vec![]);
+    storage.save_note(&missing).unwrap();
+    assert!(matching(&storage, &[BrokenLinks]).is_empty());
+    storage.delete_note(&missing.id).unwrap();
+    assert_eq!(matching(&storage, &[BrokenLinks]), ["Linked"]);
+
+    // Edits made outside kbnotes are picked up by the watcher
+    let mut events = storage.subscribe_events();
+    let mut external = storage.get_note(&files.id).unwrap();
+    external.content = "- [ ] attach the diagram again".to_string();
+    external.updated_at = chrono::Utc::now();
+    fs::write(
+        note_path(&notes_dir, &files.id),
+        serde_json::to_string_pretty(&external).unwrap(),
+    )
+    .unwrap();
+    tokio::time::timeout(Duration::from_secs(10), events.recv())
+        .await
+        .expect("watcher did not report the external edit")
+        .unwrap();
+    assert!(matching(&storage, &[Attachments]).is_empty());
+    assert_eq!(matching(&storage, &[Tasks]), ["Files"]);
+
+    // Reloading rebuilds the index from the files on disk
+    storage.stop_watcher().await.unwrap();
+    let mut offline = storage.get_note(&code.id).unwrap();
+    offline.content = "no code anymore".to_string();
+    offline.updated_at = chrono::Utc::now();
+    fs::write(
+        note_path(&notes_dir, &code.id),
+        serde_json::to_string_pretty(&offline).unwrap(),
+    )
+    .unwrap();
+    storage.load_notes().unwrap();
+    assert_eq!(matching(&storage, &[Code]), ["Todo"]);
+    assert_eq!(storage.reconcile_structure_index().unwrap(), 0);
+}
@@ -0,0 +1,219 @@
+//! Checks that the tag index agrees with the cached notes after saves,
+//! updates, deletions, file watcher events and reloads, and that tag queries
+//! and `list_tags` answer from it.
+//!
+//! Run with `cargo test --test tag_index`.
+mod common;
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use kbnotes::*;
+use tokio::sync::broadcast;
+
+fn note(id: &str, tags: &[&str]) -> Note {
+    let mut note = Note::new(
+        id.to_string(),
+        String::new(),
+        tags.iter().map(|tag| tag.to_string()).collect(),
+    );
+    note.id = id.to_string();
+    note
+}
+
+fn note_path(notes_dir: &Path, id: &str) -> PathBuf {
+    walkdir::WalkDir::new(notes_dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.into_path())
+        .find(|path| path.file_name().unwrap() == format!("{}.json", id).as_str())
+        .expect("note file not found")
+}
+
+fn ids(notes: Vec<Note>) -> Vec<String> {
+    let mut ids: Vec<String> = notes.into_iter().map(|note| note.id).collect();
+    ids.sort();
+    ids
+}
+
+/// Compares the index with what a scan of every cached note finds
+fn assert_consistent(storage: &NoteStorage) {
+    let mut expected: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for note in storage.get_all_notes().unwrap() {
+        let mut tags: Vec<String> = note.tags.iter().map(|tag| normalize_tag(tag)).collect();
+        tags.dedup();
+        for tag in tags {
+            expected.entry(tag).or_default().push(note.id.clone());
+        }
+    }
+
+    let listed: BTreeMap<String, usize> = storage.list_tags().unwrap().into_iter().collect();
+    assert_eq!(
+        listed,
+        expected
+            .iter()
+            .map(|(tag, ids)| (tag.clone(), ids.len()))
+            .collect::<BTreeMap<_, _>>()
+    );
+    for (tag, mut expected_ids) in expected {
+        expected_ids.sort();
+        assert_eq!(
+            ids(storage.get_notes_by_tag(&tag, true).unwrap()),
+            expected_ids
+        );
+    }
+}
+
+async fn wait_for(events: &mut broadcast::Receiver<NoteEvent>, id: &str) {
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(10), events.recv())
+            .await
+            .expect("watcher did not report the external change")
+            .unwrap();
+        if event.note_id == id {
+            return;
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tag_index() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let notes_dir = dir.path().join("notes");
+    let config = common::config(dir.path());
+
+    let storage = common::start(config).await;
+    let mut storage = storage.lock().await;
+
+    for (id, tags) in [
+        ("alpha", &["Rust", "cli"][..]),
+        ("bravo", &["rust"][..]),
+        ("charlie", &["Docs", "RUST"][..]),
+        ("delta", &[][..]),
+    ] {
+        storage.save_note(&note(id, tags)).unwrap();
+    }
+    let mut archived = storage.get_note("charlie").unwrap();
+    archived.archived = true;
+    storage.update_note(archived).unwrap();
+
+    // Queries come from the index, normalized like every tag comparison
+    assert_eq!(
+        storage.list_tags().unwrap(),
+        [
+            ("rust".to_string(), 3),
+            ("cli".to_string(), 1),
+            ("docs".to_string(), 1)
+        ]
+    );
+    assert_eq!(
+        ids(storage.get_notes_by_tag("RUST", false).unwrap()),
+        ["alpha", "bravo"]
+    );
+    assert_eq!(
+        ids(storage.get_notes_by_tag("rust", true).unwrap()),
+        ["alpha", "bravo", "charlie"]
+    );
+    let tags = |tags: &[&str]| tags.iter().map(|tag| tag.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        ids(storage
+            .get_notes_by_tags(&tags(&["rust", "CLI"]), TagMatch::All, false)
+            .unwrap()),
+        ["alpha"]
+    );
+    assert_eq!(
+        ids(storage
+            .get_notes_by_tags(&tags(&["cli", "docs"]), TagMatch::Any, true)
+            .unwrap()),
+        ["alpha", "charlie"]
+    );
+    assert!(storage
+        .get_notes_by_tag("missing", true)
+        .unwrap()
+        .is_empty());
+    assert_consistent(&storage);
+
+    // Updates and deletions through the storage
+    let mut bravo = storage.get_note("bravo").unwrap();
+    bravo.tags = tags(&["docs"]);
+    storage.update_note(bravo).unwrap();
+    storage.delete_note("alpha").unwrap();
+    assert_eq!(
+        storage.list_tags().unwrap(),
+        [("docs".to_string(), 2), ("rust".to_string(), 1)]
+    );
+    assert_consistent(&storage);
+    let stats = storage.get_statistics().unwrap();
+    assert_eq!(
+        stats
+            .notes_per_tag
+            .iter()
+            .map(|count| (count.tag.clone(), count.notes))
+            .collect::<Vec<_>>(),
+        storage.list_tags().unwrap()
+    );
+
+    // Changes made outside kbnotes reach the index through the watcher
+    let mut events = storage.subscribe_events();
+    let mut edited = storage.get_note("delta").unwrap();
+    edited.tags = tags(&["External", "docs"]);
+    edited.updated_at = chrono::Utc::now();
+    fs::write(
+        note_path(&notes_dir, "delta"),
+        serde_json::to_string_pretty(&edited).unwrap(),
+    )
+    .unwrap();
+    wait_for(&mut events, "delta").await;
+    assert_eq!(
+        ids(storage.get_notes_by_tag("external", false).unwrap()),
+        ["delta"]
+    );
+    assert_consistent(&storage);
+
+    // Into an existing prefix directory, which the watcher already covers
+    let created = note("deck", &["external"]);
+    fs::write(
+        notes_dir.join("de/deck.json"),
+        serde_json::to_string_pretty(&created).unwrap(),
+    )
+    .unwrap();
+    wait_for(&mut events, "deck").await;
+    assert_eq!(
+        ids(storage.get_notes_by_tag("external", false).unwrap()),
+        ["deck", "delta"]
+    );
+    assert_consistent(&storage);
+
+    fs::remove_file(note_path(&notes_dir, "delta")).unwrap();
+    wait_for(&mut events, "delta").await;
+    assert_eq!(
+        ids(storage.get_notes_by_tag("external", false).unwrap()),
+        ["deck"]
+    );
+    assert_consistent(&storage);
+
+    // Reloading rebuilds the index from the files on disk
+    storage.stop_watcher().await.unwrap();
+    let mut offline = storage.get_note("deck").unwrap();
+    offline.tags = tags(&["offline"]);
+    offline.updated_at = chrono::Utc::now();
+    fs::write(
+        note_path(&notes_dir, "deck"),
+        serde_json::to_string_pretty(&offline).unwrap(),
+    )
+    .unwrap();
+    storage.load_notes().unwrap();
+    assert!(storage
+        .get_notes_by_tag("external", true)
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        ids(storage.get_notes_by_tag("offline", true).unwrap()),
+        ["deck"]
+    );
+    assert_consistent(&storage);
+}
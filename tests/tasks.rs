@@ -0,0 +1,73 @@
+//! Checks that tasks are found at any nesting depth but not inside code
+//! blocks, and that toggling one rewrites only its checkbox.
+//!
+//! Run with `cargo test --test tasks`.
+mod common;
+
+use kbnotes::*;
+
+const CONTENT: &str = "# Groceries
+
+- [ ] milk
+- [x] bread
+  - [ ] whole grain
+    1. [X] from the bakery
+- not a task [ ]
+
+```markdown
+- [ ] in a code block
+```
+
+    - [ ] indented code
+
+* [ ] last
+";
+
+#[test]
+fn tasks() {
+    let tasks = extract_tasks(CONTENT);
+    let found: Vec<(usize, bool, &str, usize)> = tasks
+        .iter()
+        .map(|task| (task.line, task.checked, task.text.as_str(), task.depth))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            (3, false, "milk", 0),
+            (4, true, "bread", 0),
+            (5, false, "whole grain", 1),
+            (6, true, "from the bakery", 2),
+            (15, false, "last", 0),
+        ]
+    );
+
+    let (content, task) = toggle_task(CONTENT, 5).unwrap();
+    assert!(task.checked);
+    assert_eq!(content, CONTENT.replace("  - [ ] whole", "  - [x] whole"));
+    let (content, task) = toggle_task(&content, 6).unwrap();
+    assert!(!task.checked);
+    assert!(content.contains("    1. [ ] from the bakery"));
+    for line in [1, 7, 10, 13, 99] {
+        assert!(matches!(
+            toggle_task(CONTENT, line),
+            Err(KbError::InvalidArgument { .. })
+        ));
+    }
+
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let config = common::config(dir.path());
+    let storage = common::open(config);
+    let note = Note::new("Groceries".to_string(), CONTENT.to_string(), vec![]);
+    storage.save_note(&note).unwrap();
+
+    let task = storage.toggle_task(&note.id, 3).unwrap();
+    assert_eq!((task.checked, task.text.as_str()), (true, "milk"));
+    let stored = storage.get_note(&note.id).unwrap();
+    assert!(stored.content.contains("- [x] milk"));
+    assert!(stored.updated_at >= note.updated_at);
+    assert!(storage.toggle_task(&note.id, 1).is_err());
+    assert!(matches!(
+        storage.toggle_task("missing", 3),
+        Err(KbError::NoteNotFound { .. })
+    ));
+}
@@ -0,0 +1,59 @@
+//! Checks template placeholders, the precedence of given content over the
+//! template text, and the errors for missing templates.
+//!
+//! Run with `cargo test --test templates`.
+use chrono::{Local, TimeZone};
+use kbnotes::*;
+
+fn context(content: Option<&str>) -> TemplateContext {
+    TemplateContext {
+        title: "Weekly sync".to_string(),
+        now: Local.with_ymd_and_hms(2024, 6, 1, 9, 5, 0).unwrap(),
+        content: content.map(str::to_string),
+    }
+}
+
+#[test]
+fn templates() {
+    let dir = tempfile::tempdir().expect("failed to create temporary directory");
+    let templates = dir.path().join("templates");
+
+    // A missing directory holds no templates
+    assert!(list_templates(&templates).unwrap().is_empty());
+    match load_template(&templates, "meeting") {
+        Err(KbError::TemplateNotFound { available, .. }) => assert!(available.is_empty()),
+        other => panic!("expected TemplateNotFound, got {:?}", other),
+    }
+
+    let path = create_template(&templates, "meeting").unwrap();
+    assert!(path.ends_with("meeting.md"));
+    assert!(create_template(&templates, "meeting").is_err());
+    assert!(create_template(&templates, "../escape").is_err());
+    std::fs::write(templates.join("journal.md"), "## {{date}}\n").unwrap();
+    std::fs::write(templates.join("notes.txt"), "not a template").unwrap();
+    assert_eq!(list_templates(&templates).unwrap(), ["journal", "meeting"]);
+
+    // Placeholders are filled in, unknown ones are kept
+    let template = "# {{title}}\n{{date}} {{time}}\n{{content}}{{unknown}}";
+    assert_eq!(
+        render_template(template, &context(None)),
+        "# Weekly sync\n2024-06-01 09:05\n{{unknown}}"
+    );
+    let starter = load_template(&templates, "meeting").unwrap();
+    assert!(render_template(&starter, &context(None)).starts_with("# Weekly sync\n"));
+
+    // Given content fills {{content}}, or replaces a template without it
+    assert_eq!(
+        render_template(template, &context(Some("agenda {{date}}"))),
+        "# Weekly sync\n2024-06-01 09:05\nagenda {{date}}{{unknown}}"
+    );
+    assert_eq!(
+        render_template("## {{date}}\n", &context(Some("just this"))),
+        "just this"
+    );
+
+    let message = load_template(&templates, "standup")
+        .unwrap_err()
+        .to_string();
+    assert!(message.contains("journal, meeting"), "{}", message);
+}
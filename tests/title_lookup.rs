@@ -0,0 +1,69 @@
+//! Checks that notes can be found by title, case-insensitively, and that the
+//! title index follows creates, renames, deletes and reloads.
+//!
+//! Run with `cargo test --test title_lookup`.
+mod common;
+
+use kbnotes::*;
+
+fn ids(notes: &[Note]) -> Vec<String> {
+    notes.iter().map(|note| note.id.clone()).collect()
+}
+
+#[test]
+fn title_lookup() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let config = common::config(dir.path());
+    let storage = common::open(config.clone());
+
+    let first = Note::new("Meeting notes".to_string(), "a".to_string(), vec![]);
+    let second = Note::new("meeting NOTES".to_string(), "b".to_string(), vec![]);
+    let other = Note::new("Standup".to_string(), "c".to_string(), vec![]);
+    for note in [&first, &second, &other] {
+        storage.save_note(note).unwrap();
+    }
+
+    // Exact matches only, ignoring case and surrounding whitespace
+    let both = vec![first.id.clone(), second.id.clone()];
+    assert_eq!(
+        ids(&storage.find_by_title("  MEETING notes ").unwrap()),
+        both
+    );
+    assert_eq!(
+        ids(&storage.find_by_title("standup").unwrap()),
+        vec![other.id.clone()]
+    );
+    assert!(storage.find_by_title("Meeting").unwrap().is_empty());
+    match storage.resolve_note_id("meeting notes") {
+        Err(KbError::AmbiguousReference { candidates, .. }) => assert_eq!(candidates, both),
+        other => panic!("expected AmbiguousReference, got {:?}", other),
+    }
+
+    // Renames and deletes move the note in the index
+    let mut renamed = storage.get_note(&second.id).unwrap();
+    renamed.title = "Retro".to_string();
+    storage.update_note(renamed).unwrap();
+    assert_eq!(
+        ids(&storage.find_by_title("meeting notes").unwrap()),
+        vec![first.id.clone()]
+    );
+    assert_eq!(
+        ids(&storage.find_by_title("retro").unwrap()),
+        vec![second.id.clone()]
+    );
+    assert_eq!(storage.resolve_note_id("Meeting notes").unwrap(), first.id);
+    storage.delete_note(&other.id).unwrap();
+    assert!(storage.find_by_title("Standup").unwrap().is_empty());
+
+    // The index is rebuilt on load and survives cache eviction
+    let reloaded = common::open(config);
+    assert_eq!(
+        ids(&reloaded.find_by_title("retro").unwrap()),
+        vec![second.id.clone()]
+    );
+    reloaded.clear_cache().unwrap();
+    assert_eq!(
+        ids(&reloaded.find_by_title("meeting notes").unwrap()),
+        vec![first.id.clone()]
+    );
+}
@@ -0,0 +1,209 @@
+//! Checks the trash: deleting moves a note and its attachments to
+//! `.trash/`, `restore_from_trash` brings them back, `empty_trash` and the
+//! retention period purge them, `delete_note_permanently` skips the trash,
+//! and the `trash` and `delete --permanent` commands parse and run. Purged
+//! notes keep their snapshots unless `--include-snapshots` is passed.
+//!
+//! Run with `cargo test --test trash`.
+mod common;
+
+use std::{fs, path::Path, sync::Arc};
+
+use chrono::{Duration, Utc};
+use clap::Parser;
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+fn vault(root: &Path, retention_days: Option<u32>) -> (Config, NoteStorage) {
+    let mut config = common::config(root);
+    config.trash_retention_days = retention_days;
+    let storage = common::open(config.clone());
+    (config, storage)
+}
+
+fn trashed_ids(storage: &NoteStorage) -> Vec<String> {
+    let mut ids: Vec<String> = storage
+        .list_trash()
+        .unwrap()
+        .into_iter()
+        .map(|trashed| trashed.note.id)
+        .collect();
+    ids.sort();
+    ids
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn trash() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let root = dir.path();
+    let notes_dir = root.join("notes");
+    let (config, storage) = vault(root, None);
+    let receipts = Note::new("Receipts".to_string(), "March".to_string(), vec![]);
+    let draft = Note::new("Draft".to_string(), "scratch".to_string(), vec![]);
+    storage.save_note(&receipts).unwrap();
+    storage.save_note(&draft).unwrap();
+    fs::write(root.join("scan.pdf"), b"%PDF-1.4 receipt").unwrap();
+    let attachment = storage
+        .add_attachment(&receipts.id, &root.join("scan.pdf"))
+        .unwrap();
+
+    // Deleting moves the note and its attachments to the trash
+    storage.delete_note(&receipts.id).unwrap();
+    assert!(storage.get_note(&receipts.id).is_none());
+    assert!(!storage.attachment_file_path(&attachment).exists());
+    assert!(notes_dir
+        .join(TRASH_DIR)
+        .join(format!("{}.json", receipts.id))
+        .exists());
+    let trashed = storage.list_trash().unwrap();
+    assert_eq!(trashed.len(), 1);
+    assert_eq!(trashed[0].note.title, "Receipts");
+    assert!(Utc::now() - trashed[0].deleted_at < Duration::minutes(1));
+    // Trashed notes aren't loaded back as live notes
+    let (_, reloaded) = vault(root, None);
+    assert!(reloaded.get_note(&receipts.id).is_none());
+    assert_eq!(reloaded.get_all_notes().unwrap().len(), 1);
+
+    // Restoring brings back the note as it was, with its attachments
+    let restored = storage.restore_from_trash(&receipts.id).unwrap();
+    assert!(!restored.metadata.contains_key(DELETED_AT_KEY));
+    let live = storage.get_note(&receipts.id).unwrap();
+    assert_eq!(live.content, "March");
+    assert_eq!(live.attachments.len(), 1);
+    assert_eq!(
+        fs::read(storage.attachment_file_path(&attachment)).unwrap(),
+        b"%PDF-1.4 receipt"
+    );
+    assert!(storage.list_trash().unwrap().is_empty());
+    assert!(matches!(
+        storage.restore_from_trash(&receipts.id),
+        Err(KbError::NoteNotFound { .. })
+    ));
+
+    // Permanent deletion skips the trash
+    storage.delete_note_permanently(&draft.id).unwrap();
+    assert!(storage.get_note(&draft.id).is_none());
+    assert!(storage.list_trash().unwrap().is_empty());
+
+    // Emptying purges everything, or only what is old enough
+    storage.delete_note(&receipts.id).unwrap();
+    assert!(storage
+        .empty_trash(Some(Duration::days(1)))
+        .unwrap()
+        .is_empty());
+    assert_eq!(trashed_ids(&storage), [receipts.id.as_str()]);
+    let purged = storage.empty_trash(None).unwrap();
+    assert_eq!(purged.len(), 1);
+    assert!(storage.list_trash().unwrap().is_empty());
+    assert!(!notes_dir
+        .join(TRASH_DIR)
+        .join(format!("{}{}", receipts.id, TRASHED_ATTACHMENTS_SUFFIX))
+        .exists());
+
+    // The retention period purges notes deleted long enough ago
+    let old = Note::new("Old".to_string(), String::new(), vec![]);
+    let recent = Note::new("Recent".to_string(), String::new(), vec![]);
+    storage.save_note(&old).unwrap();
+    storage.save_note(&recent).unwrap();
+    storage.delete_note(&old.id).unwrap();
+    storage.delete_note(&recent.id).unwrap();
+    let old_entry = notes_dir.join(TRASH_DIR).join(format!("{}.json", old.id));
+    let mut entry: Note = serde_json::from_str(&fs::read_to_string(&old_entry).unwrap()).unwrap();
+    entry.metadata.insert(
+        DELETED_AT_KEY.to_string(),
+        (Utc::now() - Duration::days(40)).to_rfc3339(),
+    );
+    fs::write(&old_entry, serde_json::to_string(&entry).unwrap()).unwrap();
+    assert!(storage.purge_expired_trash().unwrap().is_empty());
+    let (_, keeping) = vault(root, Some(30));
+    let purged = keeping.purge_expired_trash().unwrap();
+    assert_eq!(purged.len(), 1);
+    assert_eq!(purged[0].note.id, old.id);
+    assert_eq!(trashed_ids(&keeping), [recent.id.as_str()]);
+
+    // Ages and the retention setting
+    assert_eq!(parse_age("30d").unwrap(), Duration::days(30));
+    assert_eq!(parse_age("2W").unwrap(), Duration::weeks(2));
+    assert_eq!(parse_age("12h").unwrap(), Duration::hours(12));
+    for bad in ["30", "d", "-1d", "3m", ""] {
+        assert!(parse_age(bad).is_err(), "{} should be rejected", bad);
+    }
+    let mut settings = config.clone();
+    assert_eq!(
+        Config::with_dirs(root.join("n"), root.join("b")).trash_retention_days,
+        Some(30)
+    );
+    settings.set_value("trash_retention_days", "7").unwrap();
+    assert_eq!(settings.trash_retention_days, Some(7));
+    settings.set_value("trash_retention_days", "none").unwrap();
+    assert_eq!(settings.trash_retention_days, None);
+    assert!(settings.set_value("trash_retention_days", "0").is_err());
+
+    // Commands
+    assert!(Cli::try_parse_from(["kbnotes", "delete", "abc", "--permanent"]).is_err());
+    assert!(Cli::try_parse_from(["kbnotes", "delete", "abc", "--force", "--permanent"]).is_ok());
+    let scratch = Note::new("Scratch".to_string(), String::new(), vec![]);
+    storage.save_note(&scratch).unwrap();
+    let storage = Arc::new(Mutex::new(storage));
+    let app = common::app(Arc::clone(&storage), config);
+    common::run(&app, &["delete", &scratch.id, "--force"])
+        .await
+        .unwrap();
+    assert!(storage.lock().await.get_note(&scratch.id).is_none());
+    common::run(&app, &["trash", "list"]).await.unwrap();
+    common::run(&app, &["trash", "restore", &scratch.id])
+        .await
+        .unwrap();
+    assert!(storage.lock().await.get_note(&scratch.id).is_some());
+    common::run(&app, &["trash", "empty", "--older-than", "1d"])
+        .await
+        .unwrap();
+    assert_eq!(trashed_ids(&*storage.lock().await), [recent.id.as_str()]);
+    assert!(matches!(
+        common::run(&app, &["trash", "empty", "--older-than", "soon"]).await,
+        Err(KbError::InvalidFormat { .. })
+    ));
+    common::run(&app, &["delete", &scratch.id, "--force", "--permanent"])
+        .await
+        .unwrap();
+    common::run(&app, &["trash", "empty"]).await.unwrap();
+    assert!(storage.lock().await.list_trash().unwrap().is_empty());
+
+    // Purging keeps snapshots unless asked to delete them too
+    let snapshots_of = |id: &str| notes_dir.join(".snapshots").join(id);
+    let mut snapshotted = Vec::new();
+    for title in ["Kept", "Dropped", "Emptied"] {
+        let note = Note::new(title.to_string(), String::new(), vec![]);
+        let storage = storage.lock().await;
+        storage.save_note(&note).unwrap();
+        storage.create_snapshot(&note.id, "v1").unwrap();
+        snapshotted.push(note.id);
+    }
+    let [kept, dropped, emptied] = [&snapshotted[0], &snapshotted[1], &snapshotted[2]];
+    common::run(&app, &["delete", kept, "--force", "--permanent"])
+        .await
+        .unwrap();
+    assert!(snapshots_of(kept).join("v1.json").exists());
+    assert!(Cli::try_parse_from(["kbnotes", "delete", kept, "--include-snapshots"]).is_err());
+    common::run(
+        &app,
+        &[
+            "delete",
+            dropped,
+            "--force",
+            "--permanent",
+            "--include-snapshots",
+        ],
+    )
+    .await
+    .unwrap();
+    assert!(!snapshots_of(dropped).exists());
+    common::run(&app, &["delete", emptied, "--force"])
+        .await
+        .unwrap();
+    common::run(&app, &["trash", "empty", "--include-snapshots"])
+        .await
+        .unwrap();
+    assert!(!snapshots_of(emptied).exists());
+    assert!(snapshots_of(kept).exists());
+}
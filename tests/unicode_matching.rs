@@ -0,0 +1,130 @@
+//! Checks that searches and tag comparisons ignore Unicode normalization:
+//! composed and decomposed accents, Turkish dotted and dotless I, and
+//! non-Latin scripts match the same way, with and without the search index,
+//! and `kbnotes tag --add/--remove` treat such tags as equal.
+//!
+//! Run with `cargo test --test unicode_matching`.
+mod common;
+
+use std::{path::Path, sync::Arc};
+
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+fn vault(root: &Path, use_search_index: bool) -> (Config, NoteStorage) {
+    let mut config = common::config(root);
+    config.use_search_index = use_search_index;
+    let storage = common::open(config.clone());
+    (config, storage)
+}
+
+fn titles(notes: Vec<Note>) -> Vec<String> {
+    let mut titles: Vec<String> = notes.into_iter().map(|note| note.title).collect();
+    titles.sort();
+    titles
+}
+
+fn search(storage: &NoteStorage, query: &str, mode: SearchMode) -> Vec<String> {
+    titles(
+        storage
+            .search_notes_filtered(
+                query,
+                &SearchRequest {
+                    mode,
+                    ..SearchRequest::default()
+                },
+            )
+            .unwrap()
+            .into_notes(),
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unicode_matching() {
+    // The folding itself
+    assert_eq!(fold_text("Cafe\u{301}"), fold_text("café"));
+    assert_eq!(fold_text("İZMİR"), "izmir");
+    assert_eq!(fold_text("ızmır"), "izmir");
+    assert_eq!(fold_text("STRASSE"), fold_text("Straße"));
+    assert_eq!(fold_text("ΟΔΟΣ"), fold_text("οδος"));
+    assert_eq!(fold_text("ﬁle"), "file");
+    assert_eq!(
+        normalize_tag("  Re\u{301}sume\u{301} "),
+        normalize_tag("résumé")
+    );
+    assert_ne!(fold_text("café"), fold_text("cafe"));
+
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let root = dir.path();
+    let (config, storage) = vault(root, true);
+    for (title, content, tags) in [
+        // Decomposed: e followed by a combining acute accent
+        (
+            "Cafe\u{301} list",
+            "Best cafe\u{301}s in town",
+            vec!["Re\u{301}sume\u{301}"],
+        ),
+        ("Trip", "Flights to İZMİR and ANKARA", vec!["İzmir"]),
+        ("Greetings", "Привет, мир! Καλημέρα κόσμε", vec!["Русский"]),
+        ("Plain", "cafe without an accent", vec!["resume"]),
+    ] {
+        let tags = tags.into_iter().map(str::to_string).collect();
+        storage
+            .save_note(&Note::new(title.to_string(), content.to_string(), tags))
+            .unwrap();
+    }
+    let exact = SearchMode::Exact {
+        case_sensitive: false,
+    };
+    let case_sensitive = SearchMode::Exact {
+        case_sensitive: true,
+    };
+
+    // Composed queries find decomposed text, with and without the index
+    let (_, scan) = vault(root, false);
+    for storage in [&storage, &scan] {
+        for mode in [SearchMode::Fuzzy, exact] {
+            assert_eq!(search(storage, "café", mode), ["Cafe\u{301} list"]);
+            assert_eq!(search(storage, "izmir", mode), ["Trip"]);
+            assert_eq!(search(storage, "ПРИВЕТ", mode), ["Greetings"]);
+            assert_eq!(search(storage, "ΚΑΛΗΜΈΡΑ", mode), ["Greetings"]);
+        }
+        assert_eq!(
+            search(storage, "\"cafés in\"", SearchMode::Fuzzy),
+            ["Cafe\u{301} list"]
+        );
+        assert_eq!(
+            search(storage, "Café", case_sensitive),
+            ["Cafe\u{301} list"]
+        );
+        assert!(search(storage, "CAFÉ", case_sensitive).is_empty());
+    }
+
+    // Tags compare the same way, in every lookup
+    let by_tag = |tag: &str| titles(storage.get_notes_by_tag(tag, false).unwrap());
+    assert_eq!(by_tag("résumé"), ["Cafe\u{301} list"]);
+    assert_eq!(by_tag("izmir"), ["Trip"]);
+    assert_eq!(by_tag("IZMIR"), ["Trip"]);
+    assert_eq!(by_tag("русский"), ["Greetings"]);
+    let filtered = storage
+        .query_notes(NoteFilter {
+            tags: vec!["RÉSUMÉ".to_string(), "resume".to_string()],
+            exclude_tags: vec!["Re\u{301}sume\u{301}".to_string()],
+            ..NoteFilter::default()
+        })
+        .unwrap();
+    assert_eq!(titles(filtered), ["Plain"]);
+
+    // Adding an equivalent tag is a no-op, removing one removes the original
+    let storage = Arc::new(Mutex::new(storage));
+    let app = common::app(Arc::clone(&storage), config);
+    common::run(&app, &["tag", "--title", "Trip", "--add", "IZMIR,Ankara"])
+        .await
+        .unwrap();
+    let tags = |storage: &NoteStorage| storage.find_by_title("Trip").unwrap()[0].tags.clone();
+    assert_eq!(tags(&*storage.lock().await), ["İzmir", "ankara"]);
+    common::run(&app, &["tag", "--title", "Trip", "--remove", "ızmır"])
+        .await
+        .unwrap();
+    assert_eq!(tags(&*storage.lock().await), ["ankara"]);
+}
@@ -0,0 +1,124 @@
+//! Checks vault statistics: note, tag and month counts, untagged notes, the
+//! largest notes, content and backup sizes, and the `stats` command with and
+//! without a note.
+//!
+//! Run with `cargo test --test vault_stats`.
+mod common;
+
+use std::{fs, sync::Arc};
+
+use chrono::{DateTime, Local, TimeZone, Utc};
+use clap::Parser;
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+fn local(year: i32, month: u32, day: u32) -> DateTime<Utc> {
+    Local
+        .with_ymd_and_hms(year, month, day, 12, 0, 0)
+        .unwrap()
+        .with_timezone(&Utc)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn vault_stats() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let config = common::config(dir.path());
+    let storage = common::open(config.clone());
+
+    // An empty vault has nothing to count
+    let empty = storage.get_statistics().unwrap();
+    assert_eq!(empty.total_notes, 0);
+    assert!(empty.notes_per_tag.is_empty() && empty.largest_notes.is_empty());
+
+    for (title, content, tags, created) in [
+        (
+            "Rust intro",
+            "a".repeat(300),
+            vec!["rust", "Learning"],
+            local(2024, 1, 5),
+        ),
+        ("Async", "b".repeat(200), vec!["RUST"], local(2024, 1, 20)),
+        ("Stray", "c".repeat(50), vec![], local(2024, 3, 2)),
+        ("Another stray", String::new(), vec![], local(2024, 3, 9)),
+    ] {
+        let tags = tags.into_iter().map(str::to_string).collect();
+        let mut note = Note::new(title.to_string(), content, tags);
+        note.created_at = created;
+        note.updated_at = created;
+        storage.save_note(&note).unwrap();
+    }
+    // Filler notes push the smallest ones out of the largest-notes list
+    for i in 0..LARGEST_NOTES_SHOWN {
+        let mut note = Note::new(
+            format!("Filler {}", i),
+            "d".repeat(100),
+            vec!["filler".to_string()],
+        );
+        note.created_at = local(2024, 2, 1);
+        storage.save_note(&note).unwrap();
+    }
+    fs::create_dir_all(&config.backup_dir).unwrap();
+    fs::write(config.backup_dir.join("backup.zip"), vec![0; 1234]).unwrap();
+
+    let stats = storage.get_statistics().unwrap();
+    assert_eq!(stats.total_notes, 4 + LARGEST_NOTES_SHOWN);
+    assert_eq!(stats.content_bytes, 550 + 100 * LARGEST_NOTES_SHOWN as u64);
+    assert_eq!(stats.untagged_notes, 2);
+    // New notes start out unread until viewed
+    assert_eq!(stats.unread_notes, 4 + LARGEST_NOTES_SHOWN);
+    assert_eq!(stats.backup_bytes, 1234);
+    // Tags are counted case-insensitively, most used first
+    let tags: Vec<(&str, usize)> = stats
+        .notes_per_tag
+        .iter()
+        .map(|count| (count.tag.as_str(), count.notes))
+        .collect();
+    assert_eq!(
+        tags,
+        [
+            ("filler", LARGEST_NOTES_SHOWN),
+            ("rust", 2),
+            ("learning", 1)
+        ]
+    );
+    let months: Vec<(&str, usize)> = stats
+        .notes_per_month
+        .iter()
+        .map(|count| (count.month.as_str(), count.notes))
+        .collect();
+    assert_eq!(
+        months,
+        [
+            ("2024-01", 2),
+            ("2024-02", LARGEST_NOTES_SHOWN),
+            ("2024-03", 2)
+        ]
+    );
+    assert_eq!(stats.largest_notes.len(), LARGEST_NOTES_SHOWN);
+    assert_eq!(stats.largest_notes[0].title, "Rust intro");
+    assert_eq!(stats.largest_notes[0].bytes, 300);
+    assert_eq!(stats.largest_notes[1].title, "Async");
+    assert!(stats.largest_notes.iter().all(|size| size.bytes >= 100));
+
+    let viewed = storage.find_by_title("Stray").unwrap()[0].id.clone();
+    storage.record_note_view(&viewed).unwrap();
+    assert_eq!(
+        storage.get_statistics().unwrap().unread_notes,
+        3 + LARGEST_NOTES_SHOWN
+    );
+
+    // `stats` takes an optional note and both output flags
+    let parse = |args: &[&str]| Cli::try_parse_from(["kbnotes", "stats"].iter().chain(args));
+    assert!(parse(&[]).is_ok());
+    assert!(parse(&["--format", "json"]).is_ok());
+    assert!(parse(&["--json", "--format", "text"]).is_err());
+    let note_id = storage.find_by_title("Async").unwrap()[0].id.clone();
+    let app = common::app(Arc::new(Mutex::new(storage)), config);
+    for args in [
+        &["stats"][..],
+        &["stats", "--format", "json"],
+        &["stats", note_id.as_str(), "--json"],
+    ] {
+        common::run(&app, args).await.unwrap();
+    }
+}
@@ -0,0 +1,48 @@
+//! Checks that `kbnotes view --edit` saves what the editor changed, and
+//! nothing else, using `sed` and `true` as stand-in editors.
+//!
+//! Run with `cargo test --test view_edit` (needs a Unix shell
+//! environment).
+#![cfg(unix)]
+
+mod common;
+
+use std::sync::Arc;
+
+use kbnotes::*;
+use tokio::sync::Mutex;
+
+async fn edit_with(storage: &Arc<Mutex<NoteStorage>>, config: &Config, editor: &str, id: &str) {
+    let mut config = config.clone();
+    config.editor_command = Some(editor.to_string());
+    let app = common::app(Arc::clone(storage), config);
+    common::run(&app, &["view", id, "--edit", "--no-pager"])
+        .await
+        .expect("view --edit failed");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn view_edit() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let config = common::config(dir.path());
+    let storage = Arc::new(Mutex::new(common::open(config.clone())));
+
+    let note = Note::new(
+        "Recipes".to_string(),
+        "- apple pie\n- apple crumble".to_string(),
+        vec!["food".to_string()],
+    );
+    storage.lock().await.save_note(&note).unwrap();
+
+    // An editor that changes nothing leaves the note alone
+    edit_with(&storage, &config, "true", &note.id).await;
+    let unchanged = storage.lock().await.get_note(&note.id).unwrap();
+    assert_eq!(unchanged.content, note.content);
+    assert_eq!(unchanged.updated_at, note.updated_at);
+
+    // Edits are saved without the heading and hint shown in the editor
+    edit_with(&storage, &config, "sed -i s/apple/pear/", "Recipes").await;
+    let edited = storage.lock().await.get_note(&note.id).unwrap();
+    assert_eq!(edited.content, "- pear pie\n- pear crumble");
+    assert!(edited.updated_at > note.updated_at);
+}
@@ -0,0 +1,132 @@
+//! Checks that the file watcher ignores the writes and deletions made through
+//! the storage, including their temporary files, that a burst of external
+//! writes to one file reloads the note once, and that an external edit right
+//! after a save of the same note still reaches the cache.
+//!
+//! Run with `cargo test --test watcher_debounce`.
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::Utc;
+use kbnotes::*;
+use tokio::sync::broadcast;
+
+fn note_path(notes_dir: &Path, id: &str) -> PathBuf {
+    notes_dir.join(&id[..2]).join(format!("{}.json", id))
+}
+
+/// Collects the watcher's events until none arrive for a while
+async fn settle(events: &mut broadcast::Receiver<NoteEvent>) -> Vec<NoteEvent> {
+    let mut seen = Vec::new();
+    while let Ok(event) = tokio::time::timeout(Duration::from_millis(1500), events.recv()).await {
+        seen.push(event.unwrap());
+    }
+    seen
+}
+
+fn external(events: &[NoteEvent]) -> Vec<&NoteEvent> {
+    events.iter().filter(|event| event.external).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn watcher_debounce() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let notes_dir = dir.path().join("notes");
+    let mut config = common::config(dir.path());
+    config.fsync = FsyncMode::Never;
+
+    // Own changes are remembered in the state they left the file in
+    let own = OwnChanges::new(notes_dir.clone());
+    let probe = notes_dir.join("pr/probe.json");
+    fs::create_dir_all(probe.parent().unwrap()).unwrap();
+    fs::write(&probe, "{}").unwrap();
+    assert!(!own.is_own(&probe));
+    own.record(&probe);
+    assert!(own.is_own(&probe));
+    fs::write(&probe, "{\"changed\": true}").unwrap();
+    assert!(!own.is_own(&probe));
+    fs::remove_file(&probe).unwrap();
+    own.record(&probe);
+    assert!(own.is_own(&probe));
+    assert!(is_temp_file(Path::new("ab/.tmpXyZ12")));
+    assert!(!is_temp_file(Path::new("ab/abc.json")));
+
+    let storage = common::start(config).await;
+    let mut events = storage.lock().await.subscribe_events();
+
+    // Saves, updates and deletes through the storage are not reported back
+    let mut notes = Vec::new();
+    for i in 0..20 {
+        let note = Note::new(format!("Note {}", i), "body".to_string(), vec![]);
+        storage.lock().await.save_note(&note).unwrap();
+        notes.push(note);
+    }
+    for note in &notes[..10] {
+        let mut updated = note.clone();
+        updated.content = "updated".to_string();
+        storage.lock().await.update_note(updated).unwrap();
+    }
+    storage.lock().await.delete_note(&notes[19].id).unwrap();
+    let seen = settle(&mut events).await;
+    assert!(external(&seen).is_empty(), "{:?}", seen);
+    assert!(!seen.is_empty());
+    assert_eq!(
+        storage.lock().await.get_note(&notes[0].id).unwrap().content,
+        "updated"
+    );
+
+    // A burst of writes to one file reloads the note once, at its last version
+    let id = notes[12].id.clone();
+    let mut edited = notes[12].clone();
+    for i in 0..5 {
+        edited.content = format!("burst {}", i);
+        edited.updated_at = Utc::now();
+        fs::write(
+            note_path(&notes_dir, &id),
+            serde_json::to_string_pretty(&edited).unwrap(),
+        )
+        .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let seen = settle(&mut events).await;
+    let reported = external(&seen);
+    assert_eq!(reported.len(), 1, "{:?}", seen);
+    assert_eq!(reported[0].note_id, id);
+    assert_eq!(
+        storage.lock().await.get_note(&id).unwrap().content,
+        "burst 4"
+    );
+
+    // An external edit right after a save is not mistaken for its echo
+    let id = notes[13].id.clone();
+    let mut saved = notes[13].clone();
+    saved.content = "saved".to_string();
+    storage.lock().await.update_note(saved.clone()).unwrap();
+    let mut outside = storage.lock().await.get_note(&id).unwrap();
+    outside.content = "edited outside".to_string();
+    outside.updated_at = Utc::now();
+    fs::write(
+        note_path(&notes_dir, &id),
+        serde_json::to_string_pretty(&outside).unwrap(),
+    )
+    .unwrap();
+    let seen = settle(&mut events).await;
+    assert_eq!(external(&seen).len(), 1, "{:?}", seen);
+    assert_eq!(
+        storage.lock().await.get_note(&id).unwrap().content,
+        "edited outside"
+    );
+
+    // Temporary files never count as notes
+    let temp = note_path(&notes_dir, &notes[14].id).with_file_name(".tmpAbC123");
+    fs::write(&temp, serde_json::to_string_pretty(&notes[14]).unwrap()).unwrap();
+    fs::remove_file(&temp).unwrap();
+    assert!(settle(&mut events).await.is_empty());
+
+    storage.lock().await.stop_watcher().await.unwrap();
+}
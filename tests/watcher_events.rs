@@ -0,0 +1,238 @@
+//! Checks how `handle_fs_event` maps create, modify, rename and delete
+//! sequences in a vault to cache updates: notes are keyed by the ID their
+//! file holds, copies of a note next to its own file are ignored, renames
+//! keep a note that is still on disk and drop one that left the vault, and
+//! every change is announced once.
+//!
+//! Run with `cargo test --test watcher_events`.
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use kbnotes::*;
+use notify::{
+    event::{CreateKind, ModifyKind, RemoveKind, RenameMode},
+    Event, EventKind,
+};
+use tokio::sync::broadcast;
+
+/// The cache and indexes a watcher keeps up to date
+struct Watched {
+    notes_dir: PathBuf,
+    cache: Arc<RwLock<HashMap<String, Note>>>,
+    tag_index: Arc<Mutex<TagIndex>>,
+    events: broadcast::Sender<NoteEvent>,
+    received: broadcast::Receiver<NoteEvent>,
+}
+
+impl Watched {
+    fn new(notes_dir: PathBuf) -> Self {
+        let (events, received) = broadcast::channel(64);
+        Self {
+            notes_dir,
+            cache: Arc::new(RwLock::new(HashMap::new())),
+            tag_index: Arc::new(Mutex::new(TagIndex::new())),
+            events,
+            received,
+        }
+    }
+
+    async fn handle(&self, event: Event) {
+        handle_fs_event(
+            event,
+            &self.cache,
+            &Arc::new(Mutex::new(AliasIndex::new())),
+            &Arc::new(Mutex::new(TitleIndex::new())),
+            &self.tag_index,
+            &Arc::new(Mutex::new(StructureIndex::new())),
+            &Arc::new(Mutex::new(LinkIndex::default())),
+            &Arc::new(Mutex::new(None)),
+            &self.events,
+            &StandardPolicy,
+            &self.notes_dir,
+            &OwnChanges::new(self.notes_dir.clone()),
+        )
+        .await;
+    }
+
+    /// The events announced since the last call, as (kind, note ID)
+    fn announced(&mut self) -> Vec<(NoteEventKind, String)> {
+        let mut announced = Vec::new();
+        while let Ok(event) = self.received.try_recv() {
+            assert!(event.external);
+            announced.push((event.kind, event.note_id));
+        }
+        announced
+    }
+
+    fn cached(&self, id: &str) -> Option<Note> {
+        self.cache.read().unwrap().get(id).cloned()
+    }
+
+    fn cached_ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.cache.read().unwrap().keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+}
+
+fn note(id: &str, content: &str) -> Note {
+    let mut note = Note::new(id.to_string(), content.to_string(), vec![id.to_string()]);
+    note.id = id.to_string();
+    note
+}
+
+fn write(path: &Path, note: &Note) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, serde_json::to_string_pretty(note).unwrap()).unwrap();
+}
+
+fn created(path: &Path) -> Event {
+    Event::new(EventKind::Create(CreateKind::File)).add_path(path.to_path_buf())
+}
+
+fn modified(path: &Path) -> Event {
+    Event::new(EventKind::Modify(ModifyKind::Any)).add_path(path.to_path_buf())
+}
+
+fn removed(path: &Path) -> Event {
+    Event::new(EventKind::Remove(RemoveKind::File)).add_path(path.to_path_buf())
+}
+
+fn renamed(from: &Path, to: &Path) -> Event {
+    Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+        .add_path(from.to_path_buf())
+        .add_path(to.to_path_buf())
+}
+
+fn renamed_half(mode: RenameMode, path: &Path) -> Event {
+    Event::new(EventKind::Modify(ModifyKind::Name(mode))).add_path(path.to_path_buf())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn watcher_events() {
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let notes_dir = dir.path().join("notes");
+    let mut watched = Watched::new(notes_dir.clone());
+    let alpha_path = notes_dir.join("al/alpha.json");
+
+    // Create, then modify
+    let mut alpha = note("alpha", "first");
+    write(&alpha_path, &alpha);
+    watched.handle(created(&alpha_path)).await;
+    assert_eq!(watched.cached("alpha"), Some(alpha.clone()));
+    assert_eq!(
+        watched.announced(),
+        [(NoteEventKind::Created, "alpha".to_string())]
+    );
+    alpha.content = "second".to_string();
+    alpha.updated_at = chrono::Utc::now();
+    write(&alpha_path, &alpha);
+    watched.handle(modified(&alpha_path)).await;
+    watched.handle(modified(&alpha_path)).await;
+    assert_eq!(watched.cached("alpha"), Some(alpha.clone()));
+    assert_eq!(
+        watched.announced(),
+        [(NoteEventKind::Updated, "alpha".to_string())]
+    );
+
+    // A copy next to the note's own file neither adds nor replaces a note
+    let copy_path = notes_dir.join("al/alpha-copy.json");
+    let mut copy = alpha.clone();
+    copy.content = "edited copy".to_string();
+    copy.updated_at = chrono::Utc::now();
+    write(&copy_path, &copy);
+    watched.handle(created(&copy_path)).await;
+    watched.handle(modified(&copy_path)).await;
+    assert_eq!(watched.cached_ids(), ["alpha"]);
+    assert_eq!(watched.cached("alpha"), Some(alpha.clone()));
+    fs::remove_file(&copy_path).unwrap();
+    watched.handle(removed(&copy_path)).await;
+    assert_eq!(watched.cached("alpha"), Some(alpha.clone()));
+    assert!(watched.announced().is_empty());
+
+    // A file named after another ID is keyed by the ID it holds
+    let beta_path = notes_dir.join("be/beta.json");
+    let gamma = note("gamma", "misfiled");
+    write(&beta_path, &gamma);
+    watched.handle(created(&beta_path)).await;
+    assert_eq!(watched.cached_ids(), ["alpha", "gamma"]);
+    assert_eq!(
+        watched.announced(),
+        [(NoteEventKind::Created, "gamma".to_string())]
+    );
+
+    // Renaming to a name that doesn't match keeps the note, which is still on disk
+    let renamed_path = notes_dir.join("al/alpha-renamed.json");
+    fs::rename(&alpha_path, &renamed_path).unwrap();
+    watched.handle(renamed(&alpha_path, &renamed_path)).await;
+    assert_eq!(watched.cached("alpha"), Some(alpha.clone()));
+    assert!(watched.announced().is_empty());
+
+    // Back again, reported as two halves
+    fs::rename(&renamed_path, &alpha_path).unwrap();
+    watched
+        .handle(renamed_half(RenameMode::From, &renamed_path))
+        .await;
+    watched
+        .handle(renamed_half(RenameMode::To, &alpha_path))
+        .await;
+    assert_eq!(watched.cached("alpha"), Some(alpha.clone()));
+    assert!(watched.announced().is_empty());
+
+    // Into its own place from a wrong one, both names in one event
+    let delta = note("delta", "moved");
+    let misplaced = notes_dir.join("zz/delta.json");
+    write(&misplaced, &delta);
+    watched.handle(created(&misplaced)).await;
+    let delta_path = notes_dir.join("de/delta.json");
+    fs::create_dir_all(delta_path.parent().unwrap()).unwrap();
+    fs::rename(&misplaced, &delta_path).unwrap();
+    watched.handle(renamed(&misplaced, &delta_path)).await;
+    assert_eq!(watched.cached("delta"), Some(delta.clone()));
+    assert_eq!(
+        watched.announced(),
+        [(NoteEventKind::Created, "delta".to_string())]
+    );
+
+    // Renaming to something that is no note file drops the note
+    let parked = alpha_path.with_extension("json.bak");
+    fs::rename(&alpha_path, &parked).unwrap();
+    watched.handle(renamed(&alpha_path, &parked)).await;
+    assert!(watched.cached("alpha").is_none());
+    assert_eq!(
+        watched.announced(),
+        [(NoteEventKind::Deleted, "alpha".to_string())]
+    );
+    assert!(watched.tag_index.lock().unwrap().get("alpha").is_none());
+
+    // So does moving it out of the vault, reported as the old name only
+    let outside = dir.path().join("delta.json");
+    fs::rename(&delta_path, &outside).unwrap();
+    watched
+        .handle(renamed_half(RenameMode::From, &delta_path))
+        .await;
+    assert!(watched.cached("delta").is_none());
+    assert_eq!(
+        watched.announced(),
+        [(NoteEventKind::Deleted, "delta".to_string())]
+    );
+
+    // And deleting it
+    let fresh = note("alpha", "again");
+    write(&alpha_path, &fresh);
+    watched.handle(created(&alpha_path)).await;
+    fs::remove_file(&alpha_path).unwrap();
+    watched.handle(removed(&alpha_path)).await;
+    assert_eq!(watched.cached_ids(), ["gamma"]);
+    assert_eq!(
+        watched.announced(),
+        [
+            (NoteEventKind::Created, "alpha".to_string()),
+            (NoteEventKind::Deleted, "alpha".to_string())
+        ]
+    );
+}
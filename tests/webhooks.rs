@@ -0,0 +1,220 @@
+//! Checks webhook delivery, retries, filters, dead letters and draining
+//! before exit against a local test server.
+//!
+//! Run with `cargo test --test webhooks`.
+mod common;
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use kbnotes::*;
+
+/// A request received by the test server
+struct Received {
+    path: String,
+    secret: Option<String>,
+    payload: WebhookPayload,
+}
+
+/// Serves every request on a local port, answering with the next status from
+/// `statuses` for its path (200 once they run out) and reporting it on the channel
+fn start_server(statuses: Vec<(&'static str, u16)>) -> (String, mpsc::Receiver<Received>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind test server");
+    let address = format!("http://{}", listener.local_addr().unwrap());
+    let statuses = Arc::new(Mutex::new(statuses));
+    let (sender, received) = mpsc::channel();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let path = request_line.split_whitespace().nth(1).unwrap().to_string();
+
+            let mut content_length = 0;
+            let mut secret = None;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                let header = header.trim_end();
+                if header.is_empty() {
+                    break;
+                }
+                let (name, value) = header.split_once(": ").unwrap();
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.parse().unwrap();
+                } else if name.eq_ignore_ascii_case(WEBHOOK_SECRET_HEADER) {
+                    secret = Some(value.to_string());
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            let status = {
+                let mut statuses = statuses.lock().unwrap();
+                match statuses.iter().position(|(p, _)| *p == path) {
+                    Some(index) => statuses.remove(index).1,
+                    None => 200,
+                }
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {} Test\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            )
+            .unwrap();
+
+            let payload = serde_json::from_slice(&body).expect("invalid webhook payload");
+            let _ = sender.send(Received {
+                path,
+                secret,
+                payload,
+            });
+        }
+    });
+
+    (address, received)
+}
+
+fn webhook(url: String, events: Vec<NoteEventKind>, tag: Option<&str>) -> WebhookConfig {
+    WebhookConfig {
+        url,
+        events,
+        tag: tag.map(str::to_string),
+        secret: None,
+        timeout_secs: 5,
+        max_retries: 2,
+        retry_delay_ms: 50,
+    }
+}
+
+fn next(received: &mpsc::Receiver<Received>) -> Received {
+    received
+        .recv_timeout(Duration::from_secs(5))
+        .expect("no webhook delivered")
+}
+
+#[test]
+fn webhooks() {
+    // `/flaky` fails twice before accepting, `/gone` always rejects the payload
+    let (address, received) = start_server(vec![("/flaky", 503), ("/flaky", 503), ("/gone", 404)]);
+
+    let dir = tempfile::tempdir().expect("failed to create temporary vault");
+    let mut config = common::config(dir.path());
+
+    let mut todo_hook = webhook(
+        format!("{}/todo", address),
+        vec![NoteEventKind::Created],
+        Some("todo"),
+    );
+    todo_hook.secret = Some("s3cr3t".to_string());
+    config.webhooks = vec![
+        todo_hook,
+        webhook(
+            format!("{}/flaky", address),
+            vec![NoteEventKind::Deleted],
+            None,
+        ),
+        webhook(
+            format!("{}/gone", address),
+            vec![NoteEventKind::Updated],
+            None,
+        ),
+    ];
+
+    let storage = NoteStorage::new(config);
+
+    // Only the tagged note reaches the `todo` hook
+    let plain = Note::new("Plain".to_string(), "no tags".to_string(), vec![]);
+    storage.save_note(&plain).unwrap();
+    let task = Note::new(
+        "Task".to_string(),
+        "ship it".to_string(),
+        vec!["TODO".to_string()],
+    );
+    storage.save_note(&task).unwrap();
+
+    let delivery = next(&received);
+    assert_eq!(delivery.path, "/todo");
+    assert_eq!(delivery.secret.as_deref(), Some("s3cr3t"));
+    assert_eq!(delivery.payload.schema_version, WEBHOOK_SCHEMA_VERSION);
+    assert_eq!(delivery.payload.event, NoteEventKind::Created);
+    assert_eq!(delivery.payload.note.id, task.id);
+
+    // The deletion is retried until `/flaky` accepts it
+    storage.delete_note(&plain.id).unwrap();
+    for expected in 0..3 {
+        let delivery = next(&received);
+        assert_eq!(delivery.path, "/flaky", "attempt {}", expected);
+        assert_eq!(delivery.payload.event, NoteEventKind::Deleted);
+        assert_eq!(delivery.payload.note.id, plain.id);
+    }
+
+    // A rejected update is not retried and ends up in the dead-letter file
+    let mut updated = task.clone();
+    updated.content = "shipped".to_string();
+    storage.update_note(updated).unwrap();
+    assert_eq!(next(&received).path, "/gone");
+
+    let dead_letters = dir
+        .path()
+        .join("notes")
+        .join(STATE_DIR)
+        .join(WEBHOOK_DEAD_LETTER_FILE);
+    let mut records = String::new();
+    for _ in 0..50 {
+        records = std::fs::read_to_string(&dead_letters).unwrap_or_default();
+        if !records.is_empty() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let dead_letter: DeadLetter =
+        serde_json::from_str(records.lines().next().expect("no dead letter")).unwrap();
+    assert_eq!(dead_letter.attempts, 1);
+    assert_eq!(dead_letter.payload.note.id, task.id);
+    assert!(received.recv_timeout(Duration::from_millis(300)).is_err());
+
+    // Draining before exit waits for the retries of a queued delivery
+    let (address, received) = start_server(vec![("/down", 500), ("/down", 500), ("/down", 500)]);
+    let drained_letters = dir.path().join("drained.jsonl");
+    let dispatcher = WebhookDispatcher::start(
+        vec![webhook(format!("{}/down", address), vec![], None)],
+        drained_letters.clone(),
+    )
+    .unwrap();
+    dispatcher.dispatch(NoteEventKind::Created, &task);
+    assert!(dispatcher.drain(Duration::from_secs(5)));
+    for _ in 0..3 {
+        assert_eq!(next(&received).path, "/down");
+    }
+    let records = std::fs::read_to_string(&drained_letters).unwrap();
+    let dead_letter: DeadLetter = serde_json::from_str(records.trim_end()).unwrap();
+    assert_eq!(dead_letter.attempts, 3);
+
+    // At the drain deadline, retrying and queued deliveries are dead-lettered
+    let (address, received) = start_server(vec![("/slow", 500)]);
+    let abandoned_letters = dir.path().join("abandoned.jsonl");
+    let mut slow_hook = webhook(format!("{}/slow", address), vec![], None);
+    slow_hook.retry_delay_ms = 60_000;
+    let dispatcher = WebhookDispatcher::start(vec![slow_hook], abandoned_letters.clone()).unwrap();
+    dispatcher.dispatch(NoteEventKind::Created, &task);
+    dispatcher.dispatch(NoteEventKind::Deleted, &task);
+    assert_eq!(next(&received).path, "/slow");
+    let started = std::time::Instant::now();
+    assert!(dispatcher.drain(Duration::from_millis(300)));
+    assert!(started.elapsed() < Duration::from_secs(2));
+    let records = std::fs::read_to_string(&abandoned_letters).unwrap();
+    let attempts: Vec<u32> = records
+        .lines()
+        .map(|line| serde_json::from_str::<DeadLetter>(line).unwrap().attempts)
+        .collect();
+    assert_eq!(attempts, [1, 0]);
+}